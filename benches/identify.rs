@@ -0,0 +1,77 @@
+//! Compares handing a received identify info to the lookups waiting for the peer by cloning the
+//! info for each of them with converting it once into a [`PeerInfo`].
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use libp2p::{identify::Info, identity::Keypair, Multiaddr, StreamProtocol};
+use rust_ipfs::p2p::PeerInfo;
+
+fn info() -> Info {
+    Info {
+        public_key: Keypair::generate_ed25519().public(),
+        protocol_version: "/ipfs/0.1.0".into(),
+        agent_version: "rust-ipfs/0.11".into(),
+        listen_addrs: (0..8)
+            .map(|i| format!("/ip4/10.0.0.{i}/tcp/4001").parse().unwrap())
+            .collect(),
+        protocols: [
+            "/ipfs/id/1.0.0",
+            "/ipfs/id/push/1.0.0",
+            "/ipfs/ping/1.0.0",
+            "/ipfs/kad/1.0.0",
+            "/ipfs/bitswap/1.2.0",
+            "/meshsub/1.1.0",
+            "/libp2p/autonat/1.0.0",
+            "/libp2p/circuit/relay/0.2.0/hop",
+        ]
+        .into_iter()
+        .map(StreamProtocol::new)
+        .collect(),
+        observed_addr: "/ip4/192.168.1.1/tcp/4001".parse::<Multiaddr>().unwrap(),
+    }
+}
+
+fn identify_fanout(c: &mut Criterion) {
+    let mut group = c.benchmark_group("identify_fanout");
+
+    for lookups in [1, 4, 16] {
+        group.bench_with_input(
+            BenchmarkId::new("clone_info", lookups),
+            &lookups,
+            |b, &lookups| {
+                b.iter_batched(
+                    info,
+                    |info| {
+                        for _ in 0..lookups {
+                            black_box(PeerInfo::from(info.clone()));
+                        }
+                        info
+                    },
+                    criterion::BatchSize::SmallInput,
+                )
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("convert_once", lookups),
+            &lookups,
+            |b, &lookups| {
+                b.iter_batched(
+                    info,
+                    |info| {
+                        let peer_info = PeerInfo::from(&info);
+                        for _ in 1..lookups {
+                            black_box(peer_info.clone());
+                        }
+                        black_box(peer_info);
+                        info
+                    },
+                    criterion::BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, identify_fanout);
+criterion_main!(benches);
@@ -0,0 +1,175 @@
+//! Compares writing and reading blocks one at a time with [`Repo::put_blocks`] and
+//! [`Repo::get_blocks_now`], printing the speedup of the batched calls for every store after the
+//! criterion results.
+//!
+//! Run with `cargo bench --bench put_blocks`, adding `--features redb_data_store` to include the
+//! redb store.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use libipld::{
+    multihash::{Code, MultihashDigest},
+    Cid, IpldCodec,
+};
+use rust_ipfs::repo::Repo;
+use rust_ipfs::Block;
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+use tempfile::TempDir;
+
+const BLOCKS: usize = 256;
+
+/// Same as the default batch size of the car import and unixfs add.
+const BATCH_SIZE: usize = 64;
+
+fn blocks() -> Vec<Block> {
+    (0..BLOCKS)
+        .map(|i| {
+            let data = format!("block {i}").repeat(64).into_bytes();
+            let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+            Block::new_unchecked(cid, data)
+        })
+        .collect()
+}
+
+fn repo(store: &str, tmp: &TempDir) -> Repo {
+    match store {
+        "memory" => Repo::new_memory(),
+        "flatfs" => Repo::new_fs(tmp.path()),
+        #[cfg(feature = "redb_data_store")]
+        "redb" => Repo::new_redb(tmp.path()),
+        _ => unreachable!("unknown store {store}"),
+    }
+}
+
+/// Total time and iterations measured for each benchmark, to compute the mean time per iteration.
+#[derive(Default)]
+struct Measurements(RefCell<BTreeMap<(&'static str, &'static str), (Duration, u64)>>);
+
+impl Measurements {
+    fn record(&self, name: &'static str, store: &'static str, elapsed: Duration, iters: u64) {
+        let mut inner = self.0.borrow_mut();
+        let (total, count) = inner.entry((name, store)).or_default();
+        *total += elapsed;
+        *count += iters;
+    }
+
+    fn mean(&self, name: &'static str, store: &'static str) -> Option<Duration> {
+        let inner = self.0.borrow();
+        let (total, count) = inner.get(&(name, store))?;
+        Some(total.checked_div(u32::try_from(*count).ok()?)?)
+    }
+
+    fn report(&self, stores: &[&'static str]) {
+        for store in stores {
+            for (single, batched) in [("put_single", "put_batched"), ("get_single", "get_batched")]
+            {
+                let (Some(single_mean), Some(batched_mean)) =
+                    (self.mean(single, store), self.mean(batched, store))
+                else {
+                    continue;
+                };
+
+                println!(
+                    "{store}: {batched} {batched_mean:?} vs {single} {single_mean:?} per {BLOCKS} blocks, {:.2}x speedup",
+                    single_mean.as_secs_f64() / batched_mean.as_secs_f64()
+                );
+            }
+        }
+    }
+}
+
+fn put_blocks(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let blocks = blocks();
+    let cids = blocks.iter().map(|block| *block.cid()).collect::<Vec<_>>();
+    let measurements = Measurements::default();
+
+    let mut group = c.benchmark_group("put_blocks");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(BLOCKS as u64));
+
+    let mut stores = vec!["memory", "flatfs"];
+    if cfg!(feature = "redb_data_store") {
+        stores.push("redb");
+    }
+
+    for &store in &stores {
+        let setup = |filled: bool| {
+            let tmp = TempDir::new().unwrap();
+            let repo = repo(store, &tmp);
+            rt.block_on(async {
+                repo.init().await.unwrap();
+                if filled {
+                    repo.put_blocks(blocks.clone()).await.unwrap();
+                }
+            });
+            (tmp, repo)
+        };
+
+        let put_single = |repo: &Repo| {
+            rt.block_on(async {
+                for block in blocks.clone() {
+                    repo.put_block(block).await.unwrap();
+                }
+            })
+        };
+
+        let put_batched = |repo: &Repo| {
+            rt.block_on(async {
+                for batch in blocks.chunks(BATCH_SIZE) {
+                    repo.put_blocks(batch.to_vec()).await.unwrap();
+                }
+            })
+        };
+
+        let get_single = |repo: &Repo| {
+            rt.block_on(async {
+                for cid in &cids {
+                    assert!(repo.get_block_now(cid).await.unwrap().is_some());
+                }
+            })
+        };
+
+        let get_batched = |repo: &Repo| {
+            rt.block_on(async {
+                for batch in cids.chunks(BATCH_SIZE) {
+                    let found = repo.get_blocks_now(batch).await.unwrap();
+                    assert!(found.iter().all(Option::is_some));
+                }
+            })
+        };
+
+        // name, whether the repo starts with the blocks, and the measured run
+        let runs: [(&'static str, bool, &dyn Fn(&Repo)); 4] = [
+            ("put_single", false, &put_single),
+            ("put_batched", false, &put_batched),
+            ("get_single", true, &get_single),
+            ("get_batched", true, &get_batched),
+        ];
+
+        for (name, filled, run) in runs {
+            group.bench_function(BenchmarkId::new(name, store), |b| {
+                b.iter_custom(|iters| {
+                    // the repos are created outside of the measured time
+                    let mut elapsed = Duration::ZERO;
+                    for _ in 0..iters {
+                        let (_tmp, repo) = setup(filled);
+                        let start = Instant::now();
+                        run(&repo);
+                        elapsed += start.elapsed();
+                    }
+                    measurements.record(name, store, elapsed, iters);
+                    elapsed
+                })
+            });
+        }
+    }
+
+    group.finish();
+    measurements.report(&stores);
+}
+
+criterion_group!(benches, put_blocks);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+//! Compares the time to cat a file from another node with growing read-ahead windows.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rust_ipfs::{testing::memory_nodes, IpfsPath};
+use std::time::{Duration, Instant};
+
+/// 64 leaves of the default 256 KiB chunker
+const FILE_SIZE: usize = 64 * 256 * 1024;
+
+fn unixfs_cat(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("unixfs_cat");
+    group.sample_size(10);
+
+    for window in [1, 4, 16] {
+        group.bench_function(BenchmarkId::new("window", window), |b| {
+            b.iter_custom(|iters| {
+                rt.block_on(async {
+                    let mut elapsed = Duration::ZERO;
+                    for i in 0..iters {
+                        // a fresh pair of nodes, so that the file is never already local
+                        let nodes = memory_nodes(2).await;
+                        let data = (0..FILE_SIZE)
+                            .map(|j| (j as u64 + i) as u8)
+                            .collect::<Vec<_>>();
+                        let path: IpfsPath = nodes[0].add_unixfs(data).await.unwrap();
+
+                        let start = Instant::now();
+                        nodes[1].cat_unixfs(path).window(window).await.unwrap();
+                        elapsed += start.elapsed();
+                    }
+                    elapsed
+                })
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, unixfs_cat);
+criterion_main!(benches);
@@ -51,8 +51,17 @@ async fn main() -> anyhow::Result<()> {
                     anyhow::bail!("Unknown error while writting to blockstore");
                 }
             }
-            UnixfsStatus::CompletedStatus { path, written, .. } => {
+            UnixfsStatus::CompletedStatus {
+                path,
+                written,
+                blocks,
+                ..
+            } => {
                 println!("{written} been stored with path {path}");
+                println!(
+                    "{} blocks added, {} deduplicated",
+                    blocks.new_blocks, blocks.existing_blocks
+                );
             }
         }
     }
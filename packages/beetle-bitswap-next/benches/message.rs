@@ -1,9 +1,29 @@
+use asynchronous_codec::{Decoder, Encoder};
 use beetle_bitswap_next::{
     create_block_v1 as create_test_block,
     message::{BitswapMessage, Priority, WantType},
+    BitswapCodec, ProtocolId,
 };
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use quick_protobuf::{MessageWrite, Writer};
+use unsigned_varint::codec::UviBytes;
+
+fn codec() -> BitswapCodec {
+    let mut length_codec = UviBytes::default();
+    length_codec.set_max_len(2 * 1024 * 1024);
+    BitswapCodec::new(length_codec, ProtocolId::Bitswap120)
+}
+
+/// Encodes the frame as the codec did before writing directly into the destination, through an
+/// intermediate buffer.
+fn encode_buffered(length_codec: &mut UviBytes, message: &BitswapMessage, dst: &mut BytesMut) {
+    let message = message.encode_as_proto_v1();
+    let mut buf = Vec::with_capacity(message.get_size());
+    let mut writer = Writer::new(&mut buf);
+    message.write_message(&mut writer).unwrap();
+    length_codec.encode(Bytes::from(buf), dst).unwrap();
+}
 
 pub fn criterion_benchmark(c: &mut Criterion) {
     {
@@ -111,5 +131,83 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     }
 }
 
-criterion_group!(benches, criterion_benchmark);
+/// Frames a message with 64 wants and one 256 KiB block, comparing the codec with the previous
+/// buffered encoding and copying decoding.
+pub fn codec_benchmark(c: &mut Criterion) {
+    let mut message = BitswapMessage::new(false);
+    for i in 0..64u32 {
+        let wanted = create_test_block(Bytes::from(i.to_be_bytes().to_vec()));
+        message.add_entry(*wanted.cid(), Priority::default(), WantType::Block, true);
+    }
+    message.add_block(create_test_block(Bytes::from(vec![3; 256 * 1024])));
+
+    let mut length_codec = UviBytes::default();
+    length_codec.set_max_len(2 * 1024 * 1024);
+
+    c.bench_function(
+        "BitswapCodec::encode - 64 wants, 256 KiB block - buffered",
+        |b| {
+            b.iter(|| {
+                let mut dst = BytesMut::new();
+                encode_buffered(&mut length_codec, &message, &mut dst);
+                black_box(dst);
+            })
+        },
+    );
+
+    c.bench_function(
+        "BitswapCodec::encode - 64 wants, 256 KiB block - direct",
+        |b| {
+            let mut codec = codec();
+            b.iter_batched(
+                || message.clone(),
+                |message| {
+                    let mut dst = BytesMut::new();
+                    codec.encode(message, &mut dst).unwrap();
+                    black_box(dst);
+                },
+                BatchSize::SmallInput,
+            )
+        },
+    );
+
+    let mut frame = BytesMut::new();
+    codec().encode(message.clone(), &mut frame).unwrap();
+
+    c.bench_function(
+        "BitswapCodec::decode - 64 wants, 256 KiB block - copying",
+        |b| {
+            let mut codec = codec();
+            b.iter_batched(
+                || frame.clone(),
+                |mut frame| {
+                    let (res, _) = codec.decode(&mut frame).unwrap().unwrap();
+                    // the payloads used to be copied out of the packet
+                    for block in res.blocks() {
+                        black_box(block.data().to_vec());
+                    }
+                    black_box(res);
+                },
+                BatchSize::SmallInput,
+            )
+        },
+    );
+
+    c.bench_function(
+        "BitswapCodec::decode - 64 wants, 256 KiB block - zero-copy",
+        |b| {
+            let mut codec = codec();
+            b.iter_batched(
+                || frame.clone(),
+                |mut frame| {
+                    let res = codec.decode(&mut frame).unwrap().unwrap();
+                    black_box(res);
+                },
+                BatchSize::SmallInput,
+            )
+        },
+    );
+}
+
+criterion_group!(benches, criterion_benchmark, codec_benchmark);
 criterion_main!(benches);
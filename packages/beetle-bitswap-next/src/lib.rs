@@ -50,7 +50,7 @@ pub mod message;
 pub mod peer_task_queue;
 
 pub use self::block::{tests::*, Block};
-pub use self::protocol::ProtocolId;
+pub use self::protocol::{BitswapCodec, ProtocolId};
 
 // const DIAL_BACK_OFF: Duration = Duration::from_secs(10 * 60);
 
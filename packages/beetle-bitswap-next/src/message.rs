@@ -1,4 +1,5 @@
 use core::convert::TryFrom;
+use std::borrow::Cow;
 use std::fmt::{self, Debug};
 
 use ahash::AHashMap;
@@ -11,6 +12,7 @@ use tracing::{trace, warn};
 use crate::block::Block;
 use crate::error::Error;
 use crate::prefix::Prefix;
+use crate::protocol::ProtocolId;
 
 mod pb {
     pub use super::super::pb::bitswap_pb::Message;
@@ -414,7 +416,7 @@ impl BitswapMessage {
 
         // blocks
         for block in self.blocks.values() {
-            message.blocks.push(block.data().clone().to_vec().into());
+            message.blocks.push(block.data()[..].into());
         }
 
         message
@@ -435,7 +437,7 @@ impl BitswapMessage {
         for block in self.blocks.values() {
             message.payload.push(pb::message::Block {
                 prefix: Prefix::from(block.cid()).to_bytes().into(),
-                data: block.data().to_vec().into(),
+                data: block.data()[..].into(),
             });
         }
 
@@ -451,12 +453,89 @@ impl BitswapMessage {
 
         message
     }
-}
 
-impl<'a> TryFrom<pb::Message<'a>> for BitswapMessage {
-    type Error = Error;
+    /// Same as [`BitswapMessage::encode_as_proto_v0`] or [`BitswapMessage::encode_as_proto_v1`]
+    /// depending on the protocol, writing the CIDs and prefixes of the message into `scratch`
+    /// instead of allocating each of them. The buffer is cleared first, keeping its capacity.
+    pub fn encode_as_proto_with<'a>(
+        &'a self,
+        protocol: ProtocolId,
+        scratch: &'a mut Vec<u8>,
+    ) -> pb::Message<'a> {
+        let v1 = matches!(protocol, ProtocolId::Bitswap110 | ProtocolId::Bitswap120);
 
-    fn try_from(pbm: pb::Message<'a>) -> Result<Self, Self::Error> {
+        scratch.clear();
+        for entry in self.wantlist.values() {
+            entry.cid.write_bytes(&mut *scratch).expect("vec");
+        }
+        if v1 {
+            for block in self.blocks.values() {
+                Prefix::from(block.cid()).write_bytes(scratch);
+            }
+            for cid in self.block_presences.keys() {
+                cid.write_bytes(&mut *scratch).expect("vec");
+            }
+        }
+
+        // the bytes are taken back in the order they were written
+        let mut rest: &'a [u8] = scratch;
+        let mut next = |len: usize| {
+            let (bytes, tail) = rest.split_at(len);
+            rest = tail;
+            Cow::Borrowed(bytes)
+        };
+
+        let mut message = pb::Message::default();
+
+        // wantlist
+        let mut wantlist = pb::message::Wantlist::default();
+        for entry in self.wantlist.values() {
+            wantlist.entries.push(pb::message::wantlist::Entry {
+                block: next(entry.cid.encoded_len()),
+                priority: entry.priority,
+                wantType: entry.want_type.into(),
+                cancel: entry.cancel,
+                sendDontHave: entry.send_dont_have,
+            });
+        }
+        wantlist.full = self.full;
+        message.wantlist = Some(wantlist);
+
+        if !v1 {
+            for block in self.blocks.values() {
+                message.blocks.push(block.data()[..].into());
+            }
+            return message;
+        }
+
+        // blocks
+        for block in self.blocks.values() {
+            message.payload.push(pb::message::Block {
+                prefix: next(Prefix::from(block.cid()).encoded_len()),
+                data: block.data()[..].into(),
+            });
+        }
+
+        // block presences
+        for (cid, typ) in &self.block_presences {
+            message.blockPresences.push(pb::message::BlockPresence {
+                cid: next(cid.encoded_len()),
+                type_pb: (*typ).into(),
+            });
+        }
+
+        message.pendingBytes = self.pending_bytes();
+
+        message
+    }
+}
+
+impl BitswapMessage {
+    /// Converts a decoded message, with `data` turning the block payloads into [`Bytes`].
+    fn from_proto<'a>(
+        pbm: pb::Message<'a>,
+        data: impl Fn(Cow<'a, [u8]>) -> Bytes,
+    ) -> Result<Self, Error> {
         let full = pbm.wantlist.as_ref().map(|w| w.full).unwrap_or_default();
         let mut message = BitswapMessage::new(full);
 
@@ -474,16 +553,16 @@ impl<'a> TryFrom<pb::Message<'a>> for BitswapMessage {
         }
 
         // deprecated
-        for data in pbm.blocks {
+        for block in pbm.blocks {
             // CID v0, SHA26
-            let block = Block::from_v0_data(data.to_vec())?;
+            let block = Block::from_v0_data(data(block))?;
             message.add_block(block);
         }
 
         for block in pbm.payload {
             let prefix = Prefix::new(&block.prefix)?;
             let cid = prefix.to_cid(&block.data)?;
-            let block = Block::new(block.data.to_vec(), cid);
+            let block = Block::new(data(block.data), cid);
             message.add_block(block);
         }
 
@@ -498,12 +577,24 @@ impl<'a> TryFrom<pb::Message<'a>> for BitswapMessage {
     }
 }
 
+impl<'a> TryFrom<pb::Message<'a>> for BitswapMessage {
+    type Error = Error;
+
+    fn try_from(pbm: pb::Message<'a>) -> Result<Self, Self::Error> {
+        BitswapMessage::from_proto(pbm, |data| data.into_owned().into())
+    }
+}
+
 impl TryFrom<Bytes> for BitswapMessage {
     type Error = Error;
 
     fn try_from(value: Bytes) -> Result<Self, Self::Error> {
         let mut reader = BytesReader::from_bytes(&value);
         let pbm = pb::Message::from_reader(&mut reader, &value)?;
-        pbm.try_into()
+        // the block payloads share the buffer of the packet instead of being copied out of it
+        BitswapMessage::from_proto(pbm, |data| match data {
+            Cow::Borrowed(data) => value.slice_ref(data),
+            Cow::Owned(data) => data.into(),
+        })
     }
 }
@@ -39,21 +39,33 @@ impl Prefix {
     /// Convert the prefix to encoded bytes.
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut res = Vec::with_capacity(4);
+        self.write_bytes(&mut res);
+        res
+    }
 
-        let mut buf = varint_encode::u64_buffer();
-        let version = varint_encode::u64(self.version.into(), &mut buf);
-        res.extend_from_slice(version);
-        let mut buf = varint_encode::u64_buffer();
-        let codec = varint_encode::u64(self.codec, &mut buf);
-        res.extend_from_slice(codec);
-        let mut buf = varint_encode::u64_buffer();
-        let mh_type = varint_encode::u64(self.mh_type.into(), &mut buf);
-        res.extend_from_slice(mh_type);
-        let mut buf = varint_encode::u64_buffer();
-        let mh_len = varint_encode::u64(self.mh_len as u64, &mut buf);
-        res.extend_from_slice(mh_len);
+    /// Appends the encoded bytes of the prefix to `dst`.
+    pub fn write_bytes(&self, dst: &mut Vec<u8>) {
+        for value in self.fields() {
+            let mut buf = varint_encode::u64_buffer();
+            dst.extend_from_slice(varint_encode::u64(value, &mut buf));
+        }
+    }
 
-        res
+    /// Length of the encoded bytes of the prefix.
+    pub fn encoded_len(&self) -> usize {
+        self.fields()
+            .into_iter()
+            .map(|value| varint_encode::u64(value, &mut varint_encode::u64_buffer()).len())
+            .sum()
+    }
+
+    fn fields(&self) -> [u64; 4] {
+        [
+            self.version.into(),
+            self.codec,
+            self.mh_type.into(),
+            self.mh_len as u64,
+        ]
     }
 
     /// Create a CID out of the prefix and some data that will be hashed
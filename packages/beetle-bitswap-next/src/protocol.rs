@@ -3,7 +3,7 @@ use std::future::Future;
 use std::pin::Pin;
 
 use asynchronous_codec::{Decoder, Encoder, Framed};
-use bytes::{Bytes, BytesMut};
+use bytes::{BufMut, BytesMut};
 use futures::future;
 use futures::io::{AsyncRead, AsyncWrite};
 use libp2p::core::{InboundUpgrade, OutboundUpgrade, UpgradeInfo};
@@ -150,6 +150,9 @@ pub struct BitswapCodec {
     /// Codec to encode/decode the Unsigned varint length prefix of the frames.
     pub length_codec: codec::UviBytes,
     pub protocol: ProtocolId,
+    /// Holds the CIDs and prefixes of the message being encoded, which are needed to compute its
+    /// size before it is written.
+    scratch: Vec<u8>,
 }
 
 impl fmt::Debug for BitswapCodec {
@@ -166,6 +169,7 @@ impl BitswapCodec {
         BitswapCodec {
             length_codec,
             protocol,
+            scratch: Vec::new(),
         }
     }
 }
@@ -177,19 +181,24 @@ impl Encoder for BitswapCodec {
     fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
         tracing::trace!("sending message protocol: {:?}\n{:?}", self.protocol, item);
 
-        let message = match self.protocol {
-            ProtocolId::Legacy | ProtocolId::Bitswap100 => item.encode_as_proto_v0(),
-            ProtocolId::Bitswap110 | ProtocolId::Bitswap120 => item.encode_as_proto_v1(),
-        };
-        let mut buf = Vec::with_capacity(message.get_size());
-        let mut writer = Writer::new(&mut buf);
+        let message = item.encode_as_proto_with(self.protocol, &mut self.scratch);
+
+        // length prefix the protobuf message, ensuring the max limit is not hit
+        let size = message.get_size();
+        if size > self.length_codec.max_len() {
+            return Err(BitswapHandlerError::MaxTransmissionSize);
+        }
+
+        let mut prefix = unsigned_varint::encode::usize_buffer();
+        let prefix = unsigned_varint::encode::usize(size, &mut prefix);
+        dst.reserve(prefix.len() + size);
+        dst.extend_from_slice(prefix);
 
+        // the message is written straight into the frame, without an intermediate buffer
+        let mut writer = Writer::new(dst.writer());
         message.write_message(&mut writer).expect("fixed target");
 
-        // length prefix the protobuf message, ensuring the max limit is not hit
-        self.length_codec
-            .encode(Bytes::from(buf), dst)
-            .map_err(|_| BitswapHandlerError::MaxTransmissionSize)
+        Ok(())
     }
 }
 
@@ -268,4 +277,92 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn codec_roundtrip_shares_block_buffer() {
+        use crate::{create_block_v1, message::Priority, message::WantType};
+
+        let block = create_block_v1(vec![7; 256 * 1024]);
+        let mut message = BitswapMessage::new(false);
+        message.add_entry(*block.cid(), Priority::default(), WantType::Block, true);
+        message.add_block(block.clone());
+
+        let mut length_codec = codec::UviBytes::default();
+        length_codec.set_max_len(MAX_BUF_SIZE);
+        let mut codec = BitswapCodec::new(length_codec, ProtocolId::Bitswap120);
+
+        let mut frame = BytesMut::new();
+        codec.encode(message.clone(), &mut frame).unwrap();
+        let start = frame.as_ptr() as usize;
+        let end = start + frame.len();
+
+        let (decoded, protocol) = codec.decode(&mut frame).unwrap().unwrap();
+        assert_eq!(protocol, ProtocolId::Bitswap120);
+        assert_eq!(decoded, message);
+
+        // the payload points into the received frame
+        let data = decoded.blocks().next().unwrap().data();
+        assert_eq!(data, block.data());
+        let data = data.as_ptr() as usize;
+        assert!(start <= data && data < end);
+    }
+
+    #[test]
+    fn codec_reuses_scratch_buffer() {
+        use crate::{create_block_v1, message::Priority, message::WantType};
+
+        for protocol in [ProtocolId::Bitswap100, ProtocolId::Bitswap120] {
+            let mut length_codec = codec::UviBytes::default();
+            length_codec.set_max_len(MAX_BUF_SIZE);
+            let mut codec = BitswapCodec::new(length_codec, protocol);
+
+            for round in 0..2u8 {
+                let mut message = BitswapMessage::new(false);
+                for i in 0..8u8 {
+                    let wanted = create_block_v1(vec![round, i]);
+                    message.add_entry(*wanted.cid(), Priority::default(), WantType::Have, true);
+                }
+                if protocol == ProtocolId::Bitswap120 {
+                    message.add_have(*create_block_v1(vec![round, 8]).cid());
+                    message.add_block(create_block_v1(vec![round, 9]));
+                }
+
+                let mut frame = BytesMut::new();
+                codec.encode(message.clone(), &mut frame).unwrap();
+                let (decoded, _) = codec.decode(&mut frame).unwrap().unwrap();
+                assert_eq!(decoded, message);
+            }
+
+            // the buffer grown for the first messages is reused for the next ones
+            let capacity = codec.scratch.capacity();
+            assert!(capacity > 0);
+            let mut message = BitswapMessage::new(false);
+            message.add_entry(
+                *create_block_v1(vec![2, 0]).cid(),
+                Priority::default(),
+                WantType::Have,
+                true,
+            );
+            codec.encode(message, &mut BytesMut::new()).unwrap();
+            assert_eq!(codec.scratch.capacity(), capacity);
+        }
+    }
+
+    #[test]
+    fn codec_rejects_oversized_messages() {
+        let block = crate::create_block_v1(vec![1; 1024]);
+        let mut message = BitswapMessage::new(false);
+        message.add_block(block);
+
+        let mut length_codec = codec::UviBytes::default();
+        length_codec.set_max_len(512);
+        let mut codec = BitswapCodec::new(length_codec, ProtocolId::Bitswap120);
+
+        let mut frame = BytesMut::new();
+        assert!(matches!(
+            codec.encode(message, &mut frame),
+            Err(BitswapHandlerError::MaxTransmissionSize)
+        ));
+        assert!(frame.is_empty());
+    }
 }
@@ -0,0 +1,751 @@
+//! Exporting and importing of DAGs as [CARv1] streams. Importing also supports [CARv2], of which
+//! only the inner CARv1 payload is read.
+//!
+//! [CARv1]: https://ipld.io/specs/transport/car/carv1/
+//! [CARv2]: https://ipld.io/specs/transport/car/carv2/
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use libipld::cbor::DagCborCodec;
+use libipld::codec::Codec;
+use libipld::{Cid, Ipld};
+use libp2p::PeerId;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::{Instrument, Span};
+use unsigned_varint::{decode as varint_decode, encode as varint_encode};
+
+use crate::dag::IpldDag;
+use crate::error::Error;
+use crate::repo::{BlockSource, Repo, DEFAULT_WANT_PRIORITY};
+use crate::{Block, IpfsPath};
+
+/// Largest block section accepted while importing.
+pub(crate) const MAX_SECTION_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Length of the fixed CARv2 header following the pragma.
+const V2_HEADER_SIZE: usize = 40;
+
+/// Options for [`crate::Ipfs::dag_import`].
+#[derive(Debug, Clone, Copy)]
+pub struct ImportOptions {
+    /// Recursively pin the roots listed in the header once all blocks are imported.
+    pub pin_roots: bool,
+    /// Number of blocks written to the repo at once, by default 64.
+    pub batch_size: usize,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            pin_roots: false,
+            batch_size: crate::repo::PUT_BATCH_SIZE,
+        }
+    }
+}
+
+/// Failures which can occur while parsing a CAR stream.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ImportError {
+    /// Reading from the stream failed.
+    #[error("failed to read car: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// The header could not be parsed.
+    #[error("invalid car header: {0}")]
+    InvalidHeader(String),
+
+    /// The header declares a version which is not supported.
+    #[error("unsupported car version {0}")]
+    UnsupportedVersion(i128),
+
+    /// The section starting at the offset is malformed or too large.
+    #[error("invalid section at offset {0}")]
+    InvalidSection(u64),
+
+    /// The data of the block starting at the offset does not match its cid.
+    #[error("corrupt block {cid} at offset {offset}")]
+    CorruptBlock { cid: Cid, offset: u64 },
+}
+
+/// Statistics of a completed CAR export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CarStats {
+    /// Number of blocks written.
+    pub blocks: usize,
+    /// Total number of bytes written, including the header and the framing of each block.
+    pub bytes: u64,
+}
+
+/// Writes the DAG rooted at a [`Cid`] as a CARv1 stream.
+///
+/// The DAG is walked breadth-first and each block is written at most once. Blocks missing from
+/// the local repo are fetched from the network unless [`DagExport::local`] is used.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct DagExport {
+    dag_ipld: IpldDag,
+    root: Cid,
+    writer: Box<dyn AsyncWrite + Send + Unpin + 'static>,
+    selective: Option<IpfsPath>,
+    providers: Vec<PeerId>,
+    local: bool,
+    timeout: Option<Duration>,
+    span: Span,
+}
+
+impl DagExport {
+    pub fn new(dag: IpldDag, root: Cid, writer: impl AsyncWrite + Send + Unpin + 'static) -> Self {
+        Self {
+            dag_ipld: dag,
+            root,
+            writer: Box::new(writer),
+            selective: None,
+            providers: vec![],
+            local: false,
+            timeout: None,
+            span: Span::current(),
+        }
+    }
+
+    /// Only export the blocks along the path, which must be rooted at the exported [`Cid`], and
+    /// the subtree of the document the path resolves to.
+    ///
+    /// Note that buckets of HAMT-sharded directories along the path are not exported.
+    pub fn selective(mut self, path: IpfsPath) -> Self {
+        self.selective = Some(path);
+        self
+    }
+
+    /// Peer that may contain the blocks
+    pub fn provider(mut self, peer_id: PeerId) -> Self {
+        if !self.providers.contains(&peer_id) {
+            self.providers.push(peer_id);
+        }
+        self
+    }
+
+    /// List of peers that may contain the blocks
+    pub fn providers(mut self, providers: &[PeerId]) -> Self {
+        self.providers = providers.to_vec();
+        self
+    }
+
+    /// Only export blocks found in the local repo
+    pub fn local(mut self) -> Self {
+        self.local = true;
+        self
+    }
+
+    /// Set flag to only export blocks found in the local repo
+    pub fn set_local(mut self, local: bool) -> Self {
+        self.local = local;
+        self
+    }
+
+    /// Timeout duration to fetch each block before returning an error
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set tracing span
+    pub fn span(mut self, span: Span) -> Self {
+        self.span = span;
+        self
+    }
+}
+
+impl std::future::IntoFuture for DagExport {
+    type Output = Result<CarStats, Error>;
+
+    type IntoFuture = BoxFuture<'static, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        let DagExport {
+            dag_ipld,
+            root,
+            mut writer,
+            selective,
+            providers,
+            local,
+            timeout,
+            span,
+        } = self;
+
+        async move {
+            let repo = dag_ipld.repo().clone();
+            let mut stats = CarStats::default();
+            let mut seen = HashSet::new();
+            let mut queue = VecDeque::new();
+
+            stats.bytes += write_header(&mut writer, &[root]).await?;
+
+            match selective {
+                Some(path) => {
+                    if path.root().cid() != Some(&root) {
+                        anyhow::bail!("selective path {path} is not rooted at {root}");
+                    }
+
+                    let (_, resolved) = dag_ipld
+                        .resolve_path_with_session(
+                            None,
+                            path,
+                            true,
+                            &providers,
+                            local,
+                            timeout,
+                            DEFAULT_WANT_PRIORITY,
+                        )
+                        .await?;
+
+                    // the blocks along the path, the last one being the start of the subtree
+                    let (last, along) = resolved
+                        .traversed()
+                        .split_last()
+                        .expect("resolving traverses at least the root");
+
+                    for cid in along {
+                        if !seen.insert(*cid) {
+                            continue;
+                        }
+                        let block = repo
+                            .get_block_with_session(None, cid, &providers, local, timeout)
+                            .await?;
+                        stats.bytes += write_block(&mut writer, &block).await?;
+                        stats.blocks += 1;
+                    }
+
+                    queue.push_back(*last);
+                }
+                None => queue.push_back(root),
+            }
+
+            while let Some(cid) = queue.pop_front() {
+                if !seen.insert(cid) {
+                    continue;
+                }
+
+                let block = repo
+                    .get_block_with_session(None, &cid, &providers, local, timeout)
+                    .await?;
+
+                let mut links = Vec::new();
+                block.references(&mut links)?;
+                queue.extend(links.into_iter().filter(|link| !seen.contains(link)));
+
+                stats.bytes += write_block(&mut writer, &block).await?;
+                stats.blocks += 1;
+            }
+
+            writer.flush().await?;
+
+            Ok(stats)
+        }
+        .instrument(span)
+        .boxed()
+    }
+}
+
+/// Reads a CAR stream, storing every block in the repo and returning the roots listed in the
+/// header.
+pub(crate) async fn import<R: AsyncRead + Unpin>(
+    repo: &Repo,
+    reader: R,
+    options: ImportOptions,
+) -> Result<Vec<Cid>, Error> {
+    let _g = repo.gc_guard().await;
+
+    let mut reader = CarReader { reader, offset: 0 };
+    let (roots, end) = reader.read_headers().await?;
+
+    let batch_size = options.batch_size.max(1);
+    let mut batch = Vec::with_capacity(batch_size);
+
+    while let Some(block) = reader.read_data_block(end).await? {
+        batch.push(block);
+        if batch.len() >= batch_size {
+            // blocks which already exist are not announced again
+            repo.put_blocks_from(std::mem::take(&mut batch), BlockSource::Import)
+                .await?;
+        }
+    }
+
+    repo.put_blocks_from(batch, BlockSource::Import).await?;
+
+    if options.pin_roots {
+        for root in &roots {
+            if !repo.is_pinned(root).await? {
+                repo.pin(root).recursive().await?;
+            }
+        }
+    }
+
+    Ok(roots)
+}
+
+/// Reads a CAR stream holding the DAG rooted at the cid, only storing the blocks reachable from
+/// it. Returns the number of blocks stored.
+///
+/// Every block is read before any is stored, so the size of the stream should be bounded by the
+/// caller.
+pub(crate) async fn import_dag<R: AsyncRead + Unpin>(
+    repo: &Repo,
+    reader: R,
+    root: &Cid,
+    source: BlockSource,
+) -> Result<usize, Error> {
+    let mut reader = CarReader { reader, offset: 0 };
+    let (_, end) = reader.read_headers().await?;
+
+    let mut blocks = HashMap::new();
+    while let Some(block) = reader.read_data_block(end).await? {
+        blocks.insert(*block.cid(), block);
+    }
+
+    let mut reachable: Vec<Block> = Vec::new();
+    let mut queue = VecDeque::from([*root]);
+    while let Some(cid) = queue.pop_front() {
+        // blocks missing from the stream are fetched once needed
+        let Some(block) = blocks.remove(&cid) else {
+            continue;
+        };
+        let mut links = Vec::new();
+        block.references(&mut links)?;
+        queue.extend(links);
+        reachable.push(block);
+    }
+
+    if !blocks.is_empty() {
+        debug!(%root, unreachable = blocks.len(), "skipping blocks not reachable from the root");
+    }
+
+    let stored = reachable.len();
+    let _g = repo.gc_guard().await;
+    while !reachable.is_empty() {
+        let len = reachable.len().min(crate::repo::PUT_BATCH_SIZE);
+        let batch = reachable.drain(..len).collect();
+        repo.put_blocks_from(batch, source).await?;
+    }
+
+    Ok(stored)
+}
+
+enum CarHeader {
+    V1 { roots: Vec<Cid> },
+    V2 { data_offset: u64, data_size: u64 },
+}
+
+struct CarReader<R> {
+    reader: R,
+    offset: u64,
+}
+
+impl<R: AsyncRead + Unpin> CarReader<R> {
+    /// Reads a varint, returning `None` if the stream ended before the first byte.
+    async fn read_varint(&mut self) -> Result<Option<u64>, ImportError> {
+        let start = self.offset;
+        let mut buf = varint_encode::u64_buffer();
+        let mut len = 0;
+        while len < buf.len() {
+            let byte = match self.reader.read_u8().await {
+                Ok(byte) => byte,
+                Err(e) if len == 0 && e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e.into()),
+            };
+            self.offset += 1;
+            buf[len] = byte;
+            len += 1;
+            if varint_decode::is_last(byte) {
+                let (value, _) = varint_decode::u64(&buf[..len])
+                    .map_err(|_| ImportError::InvalidSection(start))?;
+                return Ok(Some(value));
+            }
+        }
+        Err(ImportError::InvalidSection(start))
+    }
+
+    async fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), ImportError> {
+        self.reader.read_exact(buf).await?;
+        self.offset += buf.len() as u64;
+        Ok(())
+    }
+
+    async fn skip_to(&mut self, offset: u64) -> Result<(), ImportError> {
+        let Some(len) = offset.checked_sub(self.offset) else {
+            return Err(ImportError::InvalidHeader(format!(
+                "data offset {offset} overlaps the header"
+            )));
+        };
+        let skipped =
+            tokio::io::copy(&mut (&mut self.reader).take(len), &mut tokio::io::sink()).await?;
+        self.offset += skipped;
+        if skipped != len {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(())
+    }
+
+    async fn read_header(&mut self) -> Result<CarHeader, ImportError> {
+        let len = match self.read_varint().await? {
+            Some(len) if len > 0 && len <= MAX_SECTION_SIZE => len,
+            _ => return Err(ImportError::InvalidHeader("invalid header length".into())),
+        };
+        let mut buf = vec![0; len as usize];
+        self.read_exact(&mut buf).await?;
+
+        let header: Ipld = DagCborCodec
+            .decode(&buf)
+            .map_err(|e| ImportError::InvalidHeader(e.to_string()))?;
+
+        let Ipld::Map(mut header) = header else {
+            return Err(ImportError::InvalidHeader("header is not a map".into()));
+        };
+
+        match header.remove("version") {
+            Some(Ipld::Integer(1)) => {}
+            Some(Ipld::Integer(2)) => {
+                let mut buf = [0; V2_HEADER_SIZE];
+                self.read_exact(&mut buf).await?;
+                // the first 16 bytes are the characteristics bitfield which is not needed here
+                let data_offset = u64::from_le_bytes(buf[16..24].try_into().expect("8 bytes"));
+                let data_size = u64::from_le_bytes(buf[24..32].try_into().expect("8 bytes"));
+                return Ok(CarHeader::V2 {
+                    data_offset,
+                    data_size,
+                });
+            }
+            Some(Ipld::Integer(version)) => return Err(ImportError::UnsupportedVersion(version)),
+            _ => return Err(ImportError::InvalidHeader("missing version".into())),
+        }
+
+        let roots = match header.remove("roots") {
+            Some(Ipld::List(roots)) => roots
+                .into_iter()
+                .map(|root| match root {
+                    Ipld::Link(cid) => Ok(cid),
+                    _ => Err(ImportError::InvalidHeader("root is not a link".into())),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => return Err(ImportError::InvalidHeader("missing roots".into())),
+        };
+
+        Ok(CarHeader::V1 { roots })
+    }
+
+    /// Reads the header, and the header of the CARv1 payload of a CARv2, returning the roots and
+    /// the offset the data of a CARv2 ends at.
+    async fn read_headers(&mut self) -> Result<(Vec<Cid>, Option<u64>), ImportError> {
+        match self.read_header().await? {
+            CarHeader::V1 { roots } => Ok((roots, None)),
+            CarHeader::V2 {
+                data_offset,
+                data_size,
+            } => {
+                self.skip_to(data_offset).await?;
+                match self.read_header().await? {
+                    CarHeader::V1 { roots } => Ok((roots, Some(data_offset + data_size))),
+                    CarHeader::V2 { .. } => {
+                        Err(ImportError::InvalidHeader("nested CARv2 payload".into()))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads the next block section of the data ending at `end`, if any.
+    async fn read_data_block(&mut self, end: Option<u64>) -> Result<Option<Block>, ImportError> {
+        if end.map(|end| self.offset >= end).unwrap_or(false) {
+            return Ok(None);
+        }
+        self.read_block().await
+    }
+
+    /// Reads the next block section, returning `None` at the end of the stream.
+    async fn read_block(&mut self) -> Result<Option<Block>, ImportError> {
+        let offset = self.offset;
+        let len = match self.read_varint().await? {
+            // a zero length section is treated as the end of the data, as it can appear as
+            // padding in CARv2
+            None | Some(0) => return Ok(None),
+            Some(len) if len > MAX_SECTION_SIZE => return Err(ImportError::InvalidSection(offset)),
+            Some(len) => len,
+        };
+
+        let mut buf = vec![0; len as usize];
+        self.read_exact(&mut buf).await?;
+
+        let mut section = buf.as_slice();
+        let cid = Cid::read_bytes(&mut section).map_err(|_| ImportError::InvalidSection(offset))?;
+
+        Block::new(cid, section.to_vec())
+            .map(Some)
+            .map_err(|_| ImportError::CorruptBlock { cid, offset })
+    }
+}
+
+/// Writes the CARv1 header listing the given roots, returning the number of bytes written.
+async fn write_header<W: AsyncWrite + Unpin>(writer: &mut W, roots: &[Cid]) -> Result<u64, Error> {
+    let header = Ipld::Map(BTreeMap::from([
+        (
+            "roots".to_string(),
+            Ipld::List(roots.iter().copied().map(Ipld::Link).collect()),
+        ),
+        ("version".to_string(), Ipld::Integer(1)),
+    ]));
+
+    let bytes = DagCborCodec.encode(&header)?;
+    let written = write_frame(writer, &[bytes.as_slice()]).await?;
+    Ok(written)
+}
+
+/// Writes a single block section, returning the number of bytes written.
+async fn write_block<W: AsyncWrite + Unpin>(writer: &mut W, block: &Block) -> Result<u64, Error> {
+    let cid = block.cid().to_bytes();
+    let written = write_frame(writer, &[cid.as_slice(), block.data()]).await?;
+    Ok(written)
+}
+
+/// Writes the parts prefixed with the varint of their total length.
+async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    parts: &[&[u8]],
+) -> std::io::Result<u64> {
+    let len = parts.iter().map(|part| part.len()).sum::<usize>();
+
+    let mut buf = varint_encode::u64_buffer();
+    let prefix = varint_encode::u64(len as u64, &mut buf);
+
+    writer.write_all(prefix).await?;
+    for part in parts {
+        writer.write_all(part).await?;
+    }
+
+    Ok((prefix.len() + len) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CarStats, ImportError, ImportOptions};
+    use crate::{Block, IpfsPath, Node};
+    use libipld::cbor::DagCborCodec;
+    use libipld::codec::Codec;
+    use libipld::{ipld, Cid, Ipld};
+    use std::future::IntoFuture;
+    use tokio::io::{AsyncReadExt, DuplexStream};
+    use unsigned_varint::decode as varint_decode;
+
+    async fn export(fut: super::DagExport, mut reader: DuplexStream) -> (CarStats, Vec<u8>) {
+        let (stats, bytes) = tokio::join!(fut.into_future(), async move {
+            let mut bytes = vec![];
+            reader.read_to_end(&mut bytes).await.map(|_| bytes)
+        });
+        (stats.unwrap(), bytes.unwrap())
+    }
+
+    fn read_car(mut bytes: &[u8]) -> (Vec<Cid>, Vec<Block>) {
+        let (len, rest) = varint_decode::usize(bytes).unwrap();
+        let header: Ipld = DagCborCodec.decode(&rest[..len]).unwrap();
+        let Ipld::Map(mut header) = header else {
+            panic!("header is not a map");
+        };
+        let roots = match header.remove("roots").unwrap() {
+            Ipld::List(roots) => roots
+                .into_iter()
+                .map(|root| match root {
+                    Ipld::Link(cid) => cid,
+                    x => panic!("unexpected root {x:?}"),
+                })
+                .collect(),
+            x => panic!("unexpected roots {x:?}"),
+        };
+        assert_eq!(header.remove("version").unwrap(), Ipld::Integer(1));
+        bytes = &rest[len..];
+
+        let mut blocks = vec![];
+        while !bytes.is_empty() {
+            let (len, rest) = varint_decode::usize(bytes).unwrap();
+            let mut section = &rest[..len];
+            let cid = Cid::read_bytes(&mut section).unwrap();
+            blocks.push(Block::new(cid, section.to_vec()).unwrap());
+            bytes = &rest[len..];
+        }
+
+        (roots, blocks)
+    }
+
+    #[tokio::test]
+    async fn export_unixfs_tree() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        let data = b"hello, world\n".repeat(1024);
+
+        let path = ipfs
+            .add_unixfs(("hello.txt".to_string(), data.clone()))
+            .chunk(rust_unixfs::file::adder::Chunker::Size(1024))
+            .wrap()
+            .await
+            .unwrap();
+
+        let root = *path.root().cid().unwrap();
+
+        let (writer, reader) = tokio::io::duplex(1024);
+        let (stats, bytes) = export(ipfs.dag_export(root, writer), reader).await;
+
+        assert_eq!(stats.bytes, bytes.len() as u64);
+
+        let (roots, blocks) = read_car(&bytes);
+        assert_eq!(roots, [root]);
+        assert_eq!(stats.blocks, blocks.len());
+        // the wrapping directory, the file root and at least 13 leaves
+        assert!(blocks.len() > 14, "{}", blocks.len());
+
+        let Node { ipfs: other, .. } = Node::new("other_node").await;
+        for block in blocks {
+            other.put_block(block).await.unwrap();
+        }
+
+        let cat = other.cat_unixfs(path).local().await.unwrap();
+        assert_eq!(cat, data);
+    }
+
+    #[tokio::test]
+    async fn import_exported_car() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        let data = b"hello, world\n".repeat(1024);
+        let path = ipfs
+            .add_unixfs(("hello.txt".to_string(), data.clone()))
+            .chunk(rust_unixfs::file::adder::Chunker::Size(1024))
+            .wrap()
+            .await
+            .unwrap();
+        let root = *path.root().cid().unwrap();
+
+        let (writer, reader) = tokio::io::duplex(1024);
+        let (_, bytes) = export(ipfs.dag_export(root, writer), reader).await;
+
+        let Node { ipfs: other, .. } = Node::new("other_node").await;
+        let roots = other
+            .dag_import(
+                bytes.as_slice(),
+                ImportOptions {
+                    pin_roots: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(roots, [root]);
+        assert!(other.is_pinned(&root).await.unwrap());
+
+        let cat = other.cat_unixfs(path).local().await.unwrap();
+        assert_eq!(cat, data);
+
+        // importing again skips the existing blocks
+        let roots = other
+            .dag_import(bytes.as_slice(), Default::default())
+            .await
+            .unwrap();
+        assert_eq!(roots, [root]);
+    }
+
+    #[tokio::test]
+    async fn import_carv2() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+        let root = ipfs.put_dag(ipld!({ "hello": "world" })).await.unwrap();
+
+        let (writer, reader) = tokio::io::duplex(1024);
+        let (_, payload) = export(ipfs.dag_export(root, writer), reader).await;
+
+        let mut car = vec![];
+        // pragma
+        car.extend_from_slice(&[
+            0x0a, 0xa1, 0x67, 0x76, 0x65, 0x72, 0x73, 0x69, 0x6f, 0x6e, 0x02,
+        ]);
+        let data_offset = 11 + 40 + 9;
+        car.extend_from_slice(&[0; 16]);
+        car.extend_from_slice(&(data_offset as u64).to_le_bytes());
+        car.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        car.extend_from_slice(&0u64.to_le_bytes());
+        car.extend_from_slice(&[0; 9]);
+        car.extend_from_slice(&payload);
+        // trailing index, which is ignored
+        car.extend_from_slice(&[0xff; 32]);
+
+        let Node { ipfs: other, .. } = Node::new("other_node").await;
+        let roots = other
+            .dag_import(car.as_slice(), Default::default())
+            .await
+            .unwrap();
+        assert_eq!(roots, [root]);
+        assert_eq!(
+            other.get_dag(root).local().await.unwrap(),
+            ipld!({ "hello": "world" })
+        );
+    }
+
+    #[tokio::test]
+    async fn import_corrupt_block() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+        let root = ipfs.put_dag(ipld!("some data")).await.unwrap();
+
+        let (writer, reader) = tokio::io::duplex(1024);
+        let (_, mut bytes) = export(ipfs.dag_export(root, writer), reader).await;
+        let header_len = {
+            let (len, rest) = varint_decode::usize(&bytes).unwrap();
+            bytes.len() - rest.len() + len
+        };
+        *bytes.last_mut().unwrap() ^= 0xff;
+
+        let Node { ipfs: other, .. } = Node::new("other_node").await;
+        let e = other
+            .dag_import(bytes.as_slice(), Default::default())
+            .await
+            .unwrap_err();
+
+        match e.downcast_ref::<ImportError>() {
+            Some(ImportError::CorruptBlock { cid, offset }) => {
+                assert_eq!(cid, &root);
+                assert_eq!(*offset, header_len as u64);
+            }
+            x => panic!("unexpected error {x:?}"),
+        }
+        assert!(!other.repo().contains(&root).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn selective_export() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        let leaf = ipfs.put_dag(ipld!("leaf")).await.unwrap();
+        let a = ipfs.put_dag(ipld!({ "leaf": leaf })).await.unwrap();
+        let b = ipfs.put_dag(ipld!(["b"])).await.unwrap();
+        let root = ipfs.put_dag(ipld!({ "a": a, "b": b })).await.unwrap();
+
+        let (writer, reader) = tokio::io::duplex(1024);
+        let (stats, bytes) = export(ipfs.dag_export(root, writer), reader).await;
+        assert_eq!(stats.blocks, 4);
+        assert_eq!(read_car(&bytes).1.len(), 4);
+
+        let (writer, reader) = tokio::io::duplex(1024);
+        let path = IpfsPath::from(root).sub_path("a").unwrap();
+        let (stats, bytes) = export(ipfs.dag_export(root, writer).selective(path), reader).await;
+
+        let (roots, blocks) = read_car(&bytes);
+        assert_eq!(roots, [root]);
+        assert_eq!(stats.blocks, 3);
+        let cids = blocks.iter().map(|block| *block.cid()).collect::<Vec<_>>();
+        assert_eq!(cids, [root, a, leaf]);
+
+        let (writer, _reader) = tokio::io::duplex(1024);
+        ipfs.dag_export(root, writer)
+            .selective(IpfsPath::from(b))
+            .await
+            .unwrap_err();
+    }
+}
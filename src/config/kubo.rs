@@ -0,0 +1,448 @@
+//! Reading and writing the options of the node in the JSON `config` file of a kubo repository.
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use libp2p::identity::Keypair;
+use libp2p::{Multiaddr, PeerId};
+use serde_json::{json, Map, Value};
+
+use crate::error::Error;
+use crate::p2p::ConnectionLimits;
+use crate::repo::blockstore::flatfs::FsLayout;
+use crate::{DhtMode, IpfsOptions, StoragePath};
+
+/// Keys of the config mapped to the options, any other key which is set is reported as ignored.
+const SUPPORTED: &[&str] = &[
+    "Identity.PeerID",
+    "Identity.PrivKey",
+    "Addresses.Swarm",
+    "Bootstrap",
+    "Datastore.Spec",
+    "Datastore.StorageMax",
+    "Discovery.MDNS.Enabled",
+    "Routing.Type",
+    "Swarm.ConnMgr.Type",
+    "Swarm.ConnMgr.LowWater",
+    "Swarm.ConnMgr.HighWater",
+];
+
+/// Shard function of the kubo flatfs, matching [`FsLayout::Multihash`].
+const NEXT_TO_LAST_2: &str = "/repo/flatfs/shard/v1/next-to-last/2";
+
+/// The options and identity loaded with [`IpfsOptions::from_kubo_config`].
+pub struct KuboConfig {
+    pub options: IpfsOptions,
+    pub keypair: Keypair,
+    /// Keys which are set in the config but not supported, e.g. `Gateway` or `Datastore.GCPeriod`
+    pub ignored: Vec<String>,
+}
+
+impl IpfsOptions {
+    /// Loads the options from the `config` file of a kubo repository: the identity, the swarm
+    /// addresses listened on, the bootstrap nodes, the storage limit, mdns, the routing type and
+    /// the connection manager watermarks.
+    ///
+    /// The blocks are kept in the flatfs `blocks` directory of the repository while the rest of
+    /// the data goes to its `rust-ipfs` directory, as the leveldb datastore of kubo is not
+    /// supported. The other protocols are those of [`UninitializedIpfs::with_default`].
+    ///
+    /// [`UninitializedIpfs::with_default`]: crate::UninitializedIpfs::with_default
+    pub fn from_kubo_config(path: impl AsRef<Path>) -> Result<KuboConfig, Error> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        let config: Value = serde_json::from_slice(&bytes)?;
+        let repo = path.parent().unwrap_or(Path::new("."));
+
+        let mut ignored = vec![];
+        collect_ignored(&config, "", &mut ignored);
+
+        let keypair = decode_identity(&config["Identity"])?;
+
+        let mut options = IpfsOptions::default();
+        options.default_protocols();
+
+        for addr in strings(&config["Addresses"]["Swarm"]) {
+            match addr.parse::<Multiaddr>() {
+                Ok(addr) => options.listening_addrs.push(addr),
+                Err(_) => ignored.push(format!("Addresses.Swarm: {addr}")),
+            }
+        }
+
+        for addr in strings(&config["Bootstrap"]) {
+            match addr.parse::<Multiaddr>() {
+                Ok(addr) => options.bootstrap.push(addr),
+                Err(_) => ignored.push(format!("Bootstrap: {addr}")),
+            }
+        }
+
+        let flatfs = find_flatfs(&config["Datastore"]["Spec"]);
+        let field = |key: &str| flatfs.and_then(|flatfs| flatfs.get(key)?.as_str());
+        options.ipfs_path = match flatfs {
+            Some(_) if field("shardFunc") == Some(NEXT_TO_LAST_2) => StoragePath::Flatfs {
+                path: repo.join("rust-ipfs"),
+                blocks: repo.join(field("path").unwrap_or("blocks")),
+                layout: FsLayout::Multihash,
+                read_only: false,
+            },
+            found => {
+                if found.is_some() {
+                    ignored.push("Datastore.Spec".into());
+                }
+                StoragePath::Disk(repo.join("rust-ipfs"))
+            }
+        };
+
+        if let Some(max) = config["Datastore"]["StorageMax"].as_str() {
+            options.storage_max = Some(parse_bytes(max)?);
+        }
+
+        options.protocols.mdns = config["Discovery"]["MDNS"]["Enabled"]
+            .as_bool()
+            .unwrap_or_default();
+
+        match config["Routing"]["Type"].as_str().unwrap_or("auto") {
+            "" | "auto" | "dht" => {}
+            "autoclient" | "dhtclient" => options.dht_mode = DhtMode::Client,
+            "dhtserver" => options.dht_mode = DhtMode::Server,
+            "none" => options.protocols.kad = false,
+            other => ignored.push(format!("Routing.Type: {other}")),
+        }
+
+        let conn_mgr = &config["Swarm"]["ConnMgr"];
+        if conn_mgr["Type"] != "none" {
+            let water = |key: &str| conn_mgr[key].as_u64().map(|n| n as usize);
+            options.connection_limits = ConnectionLimits {
+                high_water: water("HighWater"),
+                low_water: water("LowWater"),
+                ..Default::default()
+            };
+        }
+
+        ignored.sort();
+
+        Ok(KuboConfig {
+            options,
+            keypair,
+            ignored,
+        })
+    }
+
+    /// Writes the options supported by [`IpfsOptions::from_kubo_config`] to the `config` file of
+    /// a kubo repository, keeping the other sections of an existing file. Fails with an RSA
+    /// keypair, which cannot be encoded.
+    pub fn save_kubo_config(&self, path: impl AsRef<Path>, keypair: &Keypair) -> Result<(), Error> {
+        let path = path.as_ref();
+        let mut config = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => json!({}),
+            Err(e) => return Err(e.into()),
+        };
+
+        let key = keypair.to_protobuf_encoding()?;
+        set(
+            &mut config,
+            &["Identity"],
+            json!({
+                "PeerID": keypair.public().to_peer_id().to_string(),
+                "PrivKey": STANDARD.encode(key),
+            }),
+        )?;
+
+        let swarm = self.listening_addrs.iter().map(ToString::to_string);
+        set(
+            &mut config,
+            &["Addresses", "Swarm"],
+            Value::from_iter(swarm),
+        )?;
+
+        let bootstrap = self.bootstrap.iter().map(ToString::to_string);
+        set(&mut config, &["Bootstrap"], Value::from_iter(bootstrap))?;
+
+        if let Some(max) = self.storage_max {
+            set(
+                &mut config,
+                &["Datastore", "StorageMax"],
+                format_bytes(max).into(),
+            )?;
+        }
+
+        set(
+            &mut config,
+            &["Discovery", "MDNS", "Enabled"],
+            self.protocols.mdns.into(),
+        )?;
+
+        let routing = match (self.protocols.kad, self.dht_mode) {
+            (false, _) => "none",
+            (true, DhtMode::Auto) => "dht",
+            (true, DhtMode::Client) => "dhtclient",
+            (true, DhtMode::Server) => "dhtserver",
+        };
+        set(&mut config, &["Routing", "Type"], routing.into())?;
+
+        let limits = &self.connection_limits;
+        match limits.high_water {
+            Some(high_water) => {
+                set(&mut config, &["Swarm", "ConnMgr", "Type"], "basic".into())?;
+                set(
+                    &mut config,
+                    &["Swarm", "ConnMgr", "HighWater"],
+                    high_water.into(),
+                )?;
+                set(
+                    &mut config,
+                    &["Swarm", "ConnMgr", "LowWater"],
+                    limits.low_water.unwrap_or(high_water).into(),
+                )?;
+            }
+            None => set(&mut config, &["Swarm", "ConnMgr", "Type"], "none".into())?,
+        }
+
+        let mut bytes = serde_json::to_vec_pretty(&config)?;
+        bytes.push(b'\n');
+
+        // the config holds the private key
+        let mut file = std::fs::OpenOptions::new();
+        file.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut file, 0o600);
+        file.open(path)?.write_all(&bytes)?;
+
+        Ok(())
+    }
+}
+
+fn decode_identity(identity: &Value) -> Result<Keypair, Error> {
+    let key = identity["PrivKey"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Identity.PrivKey is missing"))?;
+    let keypair = Keypair::from_protobuf_encoding(&STANDARD.decode(key)?)?;
+
+    if let Some(peer_id) = identity["PeerID"].as_str() {
+        let peer_id: PeerId = peer_id.parse()?;
+        if peer_id != keypair.public().to_peer_id() {
+            anyhow::bail!("Identity.PeerID {peer_id} does not match Identity.PrivKey");
+        }
+    }
+
+    Ok(keypair)
+}
+
+fn strings(value: &Value) -> impl Iterator<Item = &str> {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+}
+
+/// Finds the flatfs datastore within the mounts of the datastore spec.
+fn find_flatfs(spec: &Value) -> Option<&Map<String, Value>> {
+    match spec {
+        Value::Object(map) if map.get("type").and_then(Value::as_str) == Some("flatfs") => {
+            Some(map)
+        }
+        Value::Object(map) => map.values().find_map(find_flatfs),
+        Value::Array(values) => values.iter().find_map(find_flatfs),
+        _ => None,
+    }
+}
+
+fn collect_ignored(value: &Value, prefix: &str, ignored: &mut Vec<String>) {
+    let Value::Object(map) = value else {
+        return;
+    };
+
+    for (key, value) in map {
+        let path = match prefix {
+            "" => key.clone(),
+            prefix => format!("{prefix}.{key}"),
+        };
+
+        if SUPPORTED.contains(&path.as_str()) || !is_set(value) {
+            continue;
+        }
+
+        let section = format!("{path}.");
+        match SUPPORTED.iter().any(|key| key.starts_with(&section)) {
+            true => collect_ignored(value, &path, ignored),
+            false => ignored.push(path),
+        }
+    }
+}
+
+/// Whether the value differs from the zero value kubo writes for the unused keys.
+fn is_set(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(values) => !values.is_empty(),
+        Value::Object(map) => map.values().any(is_set),
+    }
+}
+
+/// Sets the value of the nested key, creating the missing sections.
+fn set(config: &mut Value, keys: &[&str], value: Value) -> Result<(), Error> {
+    let (last, sections) = keys.split_last().expect("at least one key");
+    let not_object = || anyhow!("cannot set {}, a section is not an object", keys.join("."));
+    let mut section = config;
+    for key in sections {
+        let map = section.as_object_mut().ok_or_else(not_object)?;
+        section = map.entry(*key).or_insert_with(|| json!({}));
+        if section.is_null() {
+            *section = json!({});
+        }
+    }
+    section
+        .as_object_mut()
+        .ok_or_else(not_object)?
+        .insert(last.to_string(), value);
+    Ok(())
+}
+
+/// The units of go-humanize, the decimal ones first so that `10GB` is not written in `KiB`.
+const UNITS: &[(&str, u64)] = &[
+    ("TB", 1_000_000_000_000),
+    ("GB", 1_000_000_000),
+    ("MB", 1_000_000),
+    ("KB", 1_000),
+    ("kB", 1_000),
+    ("TiB", 1 << 40),
+    ("GiB", 1 << 30),
+    ("MiB", 1 << 20),
+    ("KiB", 1 << 10),
+    ("B", 1),
+];
+
+/// Parses a size such as `10GB`, as kubo does with go-humanize.
+fn parse_bytes(size: &str) -> Result<u64, Error> {
+    let size = size.trim();
+    let (number, unit) = UNITS
+        .iter()
+        .find_map(|(suffix, unit)| Some((size.strip_suffix(suffix)?, *unit)))
+        .unwrap_or((size, 1));
+    let number: f64 = number
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid size {size}"))?;
+    Ok((number * unit as f64) as u64)
+}
+
+fn format_bytes(size: u64) -> String {
+    let (suffix, unit) = UNITS
+        .iter()
+        .filter(|(suffix, _)| *suffix != "kB")
+        .find(|(_, unit)| size != 0 && size % unit == 0)
+        .copied()
+        .unwrap_or(("B", 1));
+    format!("{}{suffix}", size / unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../../tests/fixtures/kubo_config.json");
+
+    fn load_fixture(dir: &Path) -> KuboConfig {
+        let path = dir.join("config");
+        std::fs::write(&path, FIXTURE).unwrap();
+        IpfsOptions::from_kubo_config(path).unwrap()
+    }
+
+    #[test]
+    fn kubo_config_is_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let KuboConfig {
+            options,
+            keypair,
+            ignored,
+        } = load_fixture(dir.path());
+
+        assert_eq!(
+            keypair.public().to_peer_id().to_string(),
+            "12D3KooWQdgcktX85LTXupqJUd39LgSvE8ScutFQrFYK2duuizDm"
+        );
+        assert_eq!(options.listening_addrs.len(), 4);
+        assert_eq!(
+            options.listening_addrs[2],
+            "/ip4/0.0.0.0/udp/4001/quic-v1".parse().unwrap()
+        );
+        assert_eq!(options.bootstrap.len(), 5);
+        assert_eq!(options.storage_max, Some(10_000_000_000));
+        assert!(options.protocols.mdns);
+        assert!(options.protocols.kad);
+        assert_eq!(options.dht_mode, DhtMode::Client);
+        assert_eq!(options.connection_limits.high_water, Some(96));
+        assert_eq!(options.connection_limits.low_water, Some(32));
+        assert_eq!(
+            options.ipfs_path,
+            StoragePath::Flatfs {
+                path: dir.path().join("rust-ipfs"),
+                blocks: dir.path().join("blocks"),
+                layout: FsLayout::Multihash,
+                read_only: false,
+            }
+        );
+        assert_eq!(
+            ignored,
+            [
+                "Addresses.API",
+                "Addresses.Gateway",
+                "Datastore.GCPeriod",
+                "Datastore.StorageGCWatermark",
+                "Ipns",
+                "Mounts",
+                "Swarm.ConnMgr.GracePeriod",
+            ]
+        );
+    }
+
+    #[test]
+    fn kubo_config_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let KuboConfig {
+            mut options,
+            keypair,
+            ..
+        } = load_fixture(dir.path());
+
+        options.dht_mode = DhtMode::Server;
+        options.storage_max = Some(5 << 30);
+        options.bootstrap.truncate(1);
+        options
+            .save_kubo_config(dir.path().join("config"), &keypair)
+            .unwrap();
+
+        let saved = IpfsOptions::from_kubo_config(dir.path().join("config")).unwrap();
+        assert_eq!(saved.keypair.public(), keypair.public());
+        assert_eq!(saved.options.listening_addrs, options.listening_addrs);
+        assert_eq!(saved.options.bootstrap, options.bootstrap);
+        assert_eq!(saved.options.storage_max, Some(5 << 30));
+        assert_eq!(saved.options.dht_mode, DhtMode::Server);
+        assert!(saved.options.protocols.mdns);
+        assert_eq!(saved.options.connection_limits.high_water, Some(96));
+
+        // the unsupported sections are kept
+        let config: Value =
+            serde_json::from_slice(&std::fs::read(dir.path().join("config")).unwrap()).unwrap();
+        assert_eq!(config["Addresses"]["API"], "/ip4/127.0.0.1/tcp/5001");
+        assert_eq!(config["Datastore"]["StorageMax"], "5GiB");
+        assert_eq!(config["Datastore"]["Spec"]["type"], "mount");
+    }
+
+    #[test]
+    fn sizes_are_parsed_as_kubo_does() {
+        assert_eq!(parse_bytes("10GB").unwrap(), 10_000_000_000);
+        assert_eq!(parse_bytes("1.5 MiB").unwrap(), 3 << 19);
+        assert_eq!(parse_bytes("512").unwrap(), 512);
+        parse_bytes("lots").unwrap_err();
+
+        assert_eq!(format_bytes(10_000_000_000), "10GB");
+        assert_eq!(format_bytes(512), "512B");
+    }
+}
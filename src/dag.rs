@@ -1,11 +1,13 @@
 //! `ipfs.dag` interface implementation around [`Ipfs`].
 
+use crate::car::{DagExport, ImportOptions};
 use crate::error::Error;
 use crate::path::{IpfsPath, PathRoot, SlashedPath};
-use crate::repo::Repo;
+use crate::repo::{BlockPut, FetchPolicy, Repo, DEFAULT_WANT_PRIORITY};
 use crate::{Block, Ipfs};
 use futures::future::BoxFuture;
-use futures::FutureExt;
+use futures::stream::BoxStream;
+use futures::{FutureExt, TryFutureExt};
 use libipld::serde::{from_ipld, to_ipld};
 use libipld::{
     cid::{
@@ -30,6 +32,14 @@ use std::time::Duration;
 use thiserror::Error;
 use tracing::{Instrument, Span};
 
+mod diff;
+mod stat;
+mod walk;
+
+pub use diff::DagDiffEntry;
+pub use stat::{DagStat, DagStatError};
+pub use walk::Selector;
+
 #[derive(Debug, Error)]
 pub enum ResolveError {
     /// Loading of the block on the path failed
@@ -72,8 +82,32 @@ pub enum ResolveError {
 
     #[error("path is not provided or is invalid")]
     PathNotProvided,
+
+    /// The lookup did not complete before its deadline
+    #[error(transparent)]
+    Deadline(crate::error::Timeout),
 }
 
+/// Fails with [`ResolveError::Deadline`] unless the lookup completes before the deadline, the
+/// blocks it wanted being no longer wanted once it is dropped.
+pub(crate) async fn with_deadline<T>(
+    deadline: Option<Duration>,
+    operation: &'static str,
+    lookup: impl std::future::Future<Output = Result<T, ResolveError>>,
+) -> Result<T, ResolveError> {
+    let Some(elapsed) = deadline else {
+        return lookup.await;
+    };
+    tokio::time::timeout(elapsed, lookup)
+        .await
+        .map_err(|_| ResolveError::Deadline(crate::error::Timeout { elapsed, operation }))?
+}
+
+/// Codec requested for [`DagPut`] which cannot be used to encode documents.
+#[derive(Debug, Error)]
+#[error("unsupported codec {0:#x}, supported codecs are dag-cbor (0x71), dag-json (0x0129), dag-pb (0x70) and raw (0x55)")]
+pub struct UnsupportedCodec(pub u64);
+
 #[derive(Debug, Error)]
 pub enum UnexpectedResolved {
     #[error("path resolved to unexpected type of document: {:?} or {}", .0, .1.source())]
@@ -205,6 +239,52 @@ impl IpldDag {
         self.put().ipld(ipld)
     }
 
+    /// Writes the DAG rooted at `root` as a CARv1 stream into `writer`.
+    ///
+    /// See [`DagExport`] for more information.
+    pub fn export(
+        &self,
+        root: Cid,
+        writer: impl tokio::io::AsyncWrite + Send + Unpin + 'static,
+    ) -> DagExport {
+        DagExport::new(self.clone(), root, writer)
+    }
+
+    /// Reads a CARv1 or CARv2 stream, storing the blocks in the repo. Returns the roots listed in
+    /// the header of the stream.
+    pub async fn import(
+        &self,
+        reader: impl tokio::io::AsyncRead + Unpin,
+        options: ImportOptions,
+    ) -> Result<Vec<Cid>, Error> {
+        crate::car::import(&self.repo, reader, options).await
+    }
+
+    /// Walks the DAG rooted at `root` and returns the statistics of its unique blocks. When
+    /// `offline` is set, only the local repo is used and the missing blocks are returned as an
+    /// error, otherwise the missing blocks are fetched from the network.
+    pub async fn stat(&self, root: Cid, offline: bool) -> Result<DagStat, DagStatError> {
+        stat::stat(&self.repo, root, offline).await
+    }
+
+    /// Compares the DAGs rooted at `a` and `b` by their links, returning the added, removed and
+    /// modified links ordered by their paths. Subtrees with the same [`Cid`] on both sides are
+    /// not loaded.
+    pub async fn diff(&self, a: Cid, b: Cid) -> Result<Vec<DagDiffEntry>, Error> {
+        diff::diff(&self.repo, a, b).await
+    }
+
+    /// Walks the DAG rooted at `root`, fetching only the blocks the [`Selector`] traverses into.
+    /// Each block is yielded once, with the path it was first reached at and its decoded
+    /// document. Links back to a block being traversed are not followed.
+    pub fn walk(
+        &self,
+        root: Cid,
+        selector: Selector,
+    ) -> BoxStream<'static, Result<(IpfsPath, Cid, Ipld), Error>> {
+        walk::walk(self.repo.clone(), root, selector)
+    }
+
     /// Gets an ipld node from the ipfs, fetching the block if necessary.
     ///
     /// See [`IpldDag::get`] for more information.
@@ -226,6 +306,10 @@ impl IpldDag {
         DagGet::new(self.clone())
     }
 
+    pub(crate) fn repo(&self) -> &Repo {
+        &self.repo
+    }
+
     pub(crate) async fn get_with_session(
         &self,
         session: Option<u64>,
@@ -233,41 +317,29 @@ impl IpldDag {
         providers: &[PeerId],
         local_only: bool,
         timeout: Option<Duration>,
+        priority: i32,
     ) -> Result<Ipld, ResolveError> {
-        let resolved_path = match &self.ipfs {
-            Some(ipfs) => ipfs
-                .resolve_ipns(&path, true)
-                .await
-                .map_err(|_| ResolveError::IpnsResolutionFailed(path))?,
-            None => {
-                if !matches!(path.root(), PathRoot::Ipld(_)) {
-                    return Err(ResolveError::IpnsResolutionFailed(path));
-                }
-                path
-            }
-        };
-
-        let cid = match resolved_path.root().cid() {
-            Some(cid) => cid,
-            None => return Err(ResolveError::NoCid(resolved_path)),
-        };
-
-        let mut iter = resolved_path.iter().peekable();
+        self.get_resolved_with_session(session, path, providers, local_only, timeout, priority)
+            .await
+            .map(|(ipld, _)| ipld)
+    }
 
-        let (node, _) = match self
-            .resolve0(
-                session, cid, &mut iter, true, providers, local_only, timeout,
+    pub(crate) async fn get_resolved_with_session(
+        &self,
+        session: Option<u64>,
+        path: IpfsPath,
+        providers: &[PeerId],
+        local_only: bool,
+        timeout: Option<Duration>,
+        priority: i32,
+    ) -> Result<(Ipld, ResolvedPath), ResolveError> {
+        let (node, resolved) = self
+            .resolve_path_with_session(
+                session, path, true, providers, local_only, timeout, priority,
             )
-            .await
-        {
-            Ok(t) => t,
-            Err(e) => {
-                drop(iter);
-                return Err(e.with_path(resolved_path));
-            }
-        };
+            .await?;
 
-        Ipld::try_from(node)
+        Ok((Ipld::try_from(node)?, resolved))
     }
 
     /// Resolves a `Cid`-rooted path to a document "node."
@@ -292,6 +364,28 @@ impl IpldDag {
             .await
     }
 
+    /// Resolves a `Cid`-rooted path to a document "node" in the same way as [`IpldDag::resolve`],
+    /// but instead of only the remaining path, returns a [`ResolvedPath`] which also records the
+    /// documents which were traversed while resolving.
+    pub async fn resolve_path(
+        &self,
+        path: IpfsPath,
+        follow_links: bool,
+        providers: &[PeerId],
+        local_only: bool,
+    ) -> Result<(ResolvedNode, ResolvedPath), ResolveError> {
+        self.resolve_path_with_session(
+            None,
+            path,
+            follow_links,
+            providers,
+            local_only,
+            None,
+            DEFAULT_WANT_PRIORITY,
+        )
+        .await
+    }
+
     pub(crate) async fn resolve_with_session(
         &self,
         session: Option<u64>,
@@ -301,6 +395,33 @@ impl IpldDag {
         local_only: bool,
         timeout: Option<Duration>,
     ) -> Result<(ResolvedNode, SlashedPath), ResolveError> {
+        self.resolve_path_with_session(
+            session,
+            path,
+            follow_links,
+            providers,
+            local_only,
+            timeout,
+            DEFAULT_WANT_PRIORITY,
+        )
+        .await
+        .map(|(node, resolved)| match node {
+            ResolvedNode::Link(..) => (node, resolved.remaining),
+            _ => (node, resolved.path_in_document),
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn resolve_path_with_session(
+        &self,
+        session: Option<u64>,
+        path: IpfsPath,
+        follow_links: bool,
+        providers: &[PeerId],
+        local_only: bool,
+        timeout: Option<Duration>,
+        priority: i32,
+    ) -> Result<(ResolvedNode, ResolvedPath), ResolveError> {
         let resolved_path = match &self.ipfs {
             Some(ipfs) => ipfs
                 .resolve_ipns(&path, true)
@@ -319,6 +440,8 @@ impl IpldDag {
             None => return Err(ResolveError::NoCid(resolved_path)),
         };
 
+        let mut traversed = Vec::new();
+
         let (node, matched_segments) = {
             let mut iter = resolved_path.iter().peekable();
             match self
@@ -330,6 +453,8 @@ impl IpldDag {
                     providers,
                     local_only,
                     timeout,
+                    priority,
+                    &mut traversed,
                 )
                 .await
             {
@@ -341,12 +466,22 @@ impl IpldDag {
             }
         };
 
-        // we only care about returning this remaining_path with segments up until the last
-        // document but it can and should contain all of the following segments (if any). there
-        // could be more segments when `!follow_links`.
-        let remaining_path = resolved_path.into_shifted(matched_segments);
+        // the segments after the last document are either projected inside of it, or left
+        // unresolved past a link which was not followed.
+        let shifted = resolved_path.into_shifted(matched_segments);
+        let (remaining, path_in_document) = match node {
+            ResolvedNode::Link(..) => (shifted, SlashedPath::default()),
+            _ => (SlashedPath::default(), shifted),
+        };
 
-        Ok((node, remaining_path))
+        let resolved = ResolvedPath {
+            cid: *node.source(),
+            remaining,
+            path_in_document,
+            traversed,
+        };
+
+        Ok((node, resolved))
     }
 
     /// Return the node where the resolving ended, and the **count** of segments matched.
@@ -360,6 +495,8 @@ impl IpldDag {
         providers: &[PeerId],
         local_only: bool,
         timeout: Option<Duration>,
+        priority: i32,
+        traversed: &mut Vec<Cid>,
     ) -> Result<(ResolvedNode, usize), RawResolveLocalError> {
         use LocallyResolved::*;
 
@@ -371,13 +508,17 @@ impl IpldDag {
         loop {
             let block = match self
                 .repo
-                .get_block_with_session(session, &current, providers, local_only, timeout)
+                .get_block_with_priority(
+                    session, &current, providers, local_only, timeout, priority,
+                )
                 .await
             {
                 Ok(block) => block,
                 Err(e) => return Err(RawResolveLocalError::Loading(current, e)),
             };
 
+            traversed.push(current);
+
             let start = total;
 
             let (resolution, matched) = match resolve_local(block, segments, &mut cache) {
@@ -447,6 +588,8 @@ pub struct DagGet {
     providers: Vec<PeerId>,
     local: bool,
     timeout: Option<Duration>,
+    deadline: Option<Duration>,
+    priority: i32,
     span: Option<Span>,
 }
 
@@ -459,6 +602,8 @@ impl DagGet {
             providers: vec![],
             local: false,
             timeout: None,
+            deadline: None,
+            priority: DEFAULT_WANT_PRIORITY,
             span: None,
         }
     }
@@ -501,12 +646,33 @@ impl DagGet {
         self
     }
 
+    /// Whether the missing blocks are fetched from the network, [`FetchPolicy::LocalOnly`] being
+    /// the same as [`DagGet::local`].
+    pub fn fetch_policy(mut self, policy: FetchPolicy) -> Self {
+        self.local = policy == FetchPolicy::LocalOnly;
+        self
+    }
+
     /// Timeout duration to resolve a block before returning an error
     pub fn timeout(mut self, timeout: Duration) -> Self {
         self.timeout = Some(timeout);
         self
     }
 
+    /// Time given to the whole lookup, rather than to each block like [`DagGet::timeout`], before
+    /// failing with [`ResolveError::Deadline`]
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Bitswap priority the blocks along the path are wanted at, higher being served first by the
+    /// providers. Defaults to [`DEFAULT_WANT_PRIORITY`].
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
     /// Deserialize to a serde-compatible object
     pub fn deserialized<D: DeserializeOwned>(self) -> DagGetDeserialize<D> {
         DagGetDeserialize {
@@ -515,6 +681,11 @@ impl DagGet {
         }
     }
 
+    /// Also return the [`ResolvedPath`] describing how the path was resolved
+    pub fn resolved(self) -> DagGetResolved {
+        DagGetResolved { dag_get: self }
+    }
+
     /// Set tracing span
     pub fn span(mut self, span: Span) -> Self {
         self.span = Some(span);
@@ -522,6 +693,65 @@ impl DagGet {
     }
 }
 
+/// Resolves a path through the DAG, see [`crate::Ipfs::resolve`]. Can be awaited right away, the
+/// lookup starting on the first poll.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct DagResolve {
+    dag_ipld: IpldDag,
+    path: Option<IpfsPath>,
+    deadline: Option<Duration>,
+    span: Span,
+    lookup: Option<BoxFuture<'static, Result<ResolvedPath, ResolveError>>>,
+}
+
+impl DagResolve {
+    pub(crate) fn new(dag_ipld: IpldDag, path: IpfsPath, span: Span) -> Self {
+        Self {
+            dag_ipld,
+            path: Some(path),
+            deadline: None,
+            span,
+            lookup: None,
+        }
+    }
+
+    /// Time given to the whole lookup before failing with [`ResolveError::Deadline`], after which
+    /// the blocks along the path are no longer wanted
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+}
+
+impl std::future::Future for DagResolve {
+    type Output = Result<ResolvedPath, ResolveError>;
+
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        if self.lookup.is_none() {
+            let dag = self.dag_ipld.clone();
+            let path = self.path.take().ok_or(ResolveError::PathNotProvided);
+            let deadline = self.deadline;
+            let lookup = async move {
+                let lookup = dag.resolve_path(path?, true, &[], false);
+                with_deadline(deadline, "resolve", lookup)
+                    .await
+                    .map(|(_, resolved)| resolved)
+            }
+            .instrument(self.span.clone())
+            .boxed();
+            self.lookup = Some(lookup);
+        }
+
+        self.lookup
+            .as_mut()
+            .expect("the lookup was started")
+            .poll_unpin(cx)
+    }
+}
+
 impl std::future::IntoFuture for DagGet {
     type Output = Result<Ipld, ResolveError>;
 
@@ -531,15 +761,49 @@ impl std::future::IntoFuture for DagGet {
         let span = self.span.unwrap_or(Span::current());
         async move {
             let path = self.path.ok_or(ResolveError::PathNotProvided)?;
-            self.dag_ipld
-                .get_with_session(
-                    self.session,
-                    path,
-                    &self.providers,
-                    self.local,
-                    self.timeout,
-                )
-                .await
+            let lookup = self.dag_ipld.get_with_session(
+                self.session,
+                path,
+                &self.providers,
+                self.local,
+                self.timeout,
+                self.priority,
+            );
+            with_deadline(self.deadline, "dag_get", lookup).await
+        }
+        .instrument(span)
+        .boxed()
+    }
+}
+
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct DagGetResolved {
+    dag_get: DagGet,
+}
+
+impl std::future::IntoFuture for DagGetResolved {
+    type Output = Result<(Ipld, ResolvedPath), ResolveError>;
+
+    type IntoFuture = BoxFuture<'static, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        let DagGet {
+            dag_ipld,
+            session,
+            path,
+            providers,
+            local,
+            timeout,
+            deadline,
+            priority,
+            span,
+        } = self.dag_get;
+        let span = span.unwrap_or(Span::current());
+        async move {
+            let path = path.ok_or(ResolveError::PathNotProvided)?;
+            let lookup = dag_ipld
+                .get_resolved_with_session(session, path, &providers, local, timeout, priority);
+            with_deadline(deadline, "dag_get", lookup).await
         }
         .instrument(span)
         .boxed()
@@ -574,9 +838,10 @@ where
 #[must_use = "futures do nothing unless you `.await` or poll them"]
 pub struct DagPut {
     dag_ipld: IpldDag,
-    codec: IpldCodec,
+    codec: u64,
     data: Box<dyn FnOnce() -> anyhow::Result<Ipld> + Send + 'static>,
     hash: Code,
+    version: Option<Version>,
     pinned: Option<bool>,
     span: Span,
     provide: bool,
@@ -586,9 +851,10 @@ impl DagPut {
     pub fn new(dag: IpldDag) -> Self {
         Self {
             dag_ipld: dag,
-            codec: IpldCodec::DagCbor,
+            codec: IpldCodec::DagCbor.into(),
             data: Box::new(|| anyhow::bail!("data not available")),
             hash: Code::Sha2_256,
+            version: None,
             pinned: None,
             span: Span::current(),
             provide: false,
@@ -626,9 +892,17 @@ impl DagPut {
         self
     }
 
-    /// Set codec for ipld
-    pub fn codec(mut self, codec: IpldCodec) -> Self {
-        self.codec = codec;
+    /// Set codec for ipld, either as [`IpldCodec`] or as a multicodec code.
+    ///
+    /// Using a codec other than one of [`IpldCodec`] will fail with [`UnsupportedCodec`].
+    pub fn codec(mut self, codec: impl Into<u64>) -> Self {
+        self.codec = codec.into();
+        self
+    }
+
+    /// Set cid version. By default, dag-pb uses version 0 and all other codecs use version 1.
+    pub fn cid_version(mut self, version: Version) -> Self {
+        self.version = Some(version);
         self
     }
 
@@ -639,12 +913,10 @@ impl DagPut {
     }
 }
 
-impl std::future::IntoFuture for DagPut {
-    type Output = Result<Cid, anyhow::Error>;
-
-    type IntoFuture = BoxFuture<'static, Self::Output>;
-
-    fn into_future(self) -> Self::IntoFuture {
+impl DagPut {
+    /// Puts the node like awaiting the [`DagPut`] does, also returning whether its block was
+    /// written or already stored.
+    pub fn with_outcome(self) -> BoxFuture<'static, Result<(Cid, BlockPut), anyhow::Error>> {
         let span = self.span;
         async move {
             if self.provide && self.dag_ipld.ipfs.is_none() {
@@ -653,19 +925,22 @@ impl std::future::IntoFuture for DagPut {
 
             let _g = self.dag_ipld.repo.gc_guard().await;
 
+            let codec =
+                IpldCodec::try_from(self.codec).map_err(|_| UnsupportedCodec(self.codec))?;
+
             let data = (self.data)()?;
 
-            let bytes = self.codec.encode(&data)?;
+            let bytes = codec.encode(&data)?;
             let code = self.hash;
             let hash = code.digest(&bytes);
-            let version = if self.codec == IpldCodec::DagPb {
+            let version = self.version.unwrap_or(if codec == IpldCodec::DagPb {
                 Version::V0
             } else {
                 Version::V1
-            };
-            let cid = Cid::new(version, self.codec.into(), hash)?;
+            });
+            let cid = Cid::new(version, codec.into(), hash)?;
             let block = Block::new(cid, bytes)?;
-            let cid = self.dag_ipld.repo.put_block(block).await?;
+            let (cid, put) = self.dag_ipld.repo.put_block(block).await?;
 
             if let Some(opt) = self.pinned {
                 if !self.dag_ipld.repo.is_pinned(&cid).await? {
@@ -681,13 +956,57 @@ impl std::future::IntoFuture for DagPut {
                 }
             }
 
-            Ok(cid)
+            Ok((cid, put))
         }
         .instrument(span)
         .boxed()
     }
 }
 
+impl std::future::IntoFuture for DagPut {
+    type Output = Result<Cid, anyhow::Error>;
+
+    type IntoFuture = BoxFuture<'static, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        self.with_outcome().map_ok(|(cid, _)| cid).boxed()
+    }
+}
+
+/// Describes how an [`IpfsPath`] was resolved through the DAG.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedPath {
+    cid: Cid,
+    remaining: SlashedPath,
+    path_in_document: SlashedPath,
+    traversed: Vec<Cid>,
+}
+
+impl ResolvedPath {
+    /// Returns the `Cid` of the last document the path resolved to.
+    pub fn cid(&self) -> &Cid {
+        &self.cid
+    }
+
+    /// Returns the segments left unresolved, past a link which was not followed. This is empty
+    /// when the path was fully resolved.
+    pub fn remaining(&self) -> &SlashedPath {
+        &self.remaining
+    }
+
+    /// Returns the segments projected inside the last document, e.g. `a/0` for a path ending in
+    /// the first element of the list `a` of a dag-cbor document.
+    pub fn path_in_document(&self) -> &SlashedPath {
+        &self.path_in_document
+    }
+
+    /// Returns the `Cid`s of the documents traversed while resolving, starting from the root of
+    /// the path. Buckets of HAMT-sharded directories are not included.
+    pub fn traversed(&self) -> &[Cid] {
+        &self.traversed
+    }
+}
+
 /// `IpfsPath`'s `Cid`-based variant can be resolved to the block, projections represented by this
 /// type.
 ///
@@ -717,10 +1036,12 @@ impl ResolvedNode {
         }
     }
 
-    /// Unwraps the dagpb block variant and turns others into UnexpectedResolved.
+    /// Unwraps the dagpb or raw block variant and turns others into UnexpectedResolved. Raw
+    /// blocks are accepted as they are used as file leaves.
     /// This is useful wherever unixfs operations are continued after resolving an IpfsPath.
     pub fn into_unixfs_block(self) -> Result<Block, UnexpectedResolved> {
-        if self.source().codec() != <IpldCodec as Into<u64>>::into(IpldCodec::DagPb) {
+        let codec = self.source().codec();
+        if codec != u64::from(IpldCodec::DagPb) && codec != u64::from(IpldCodec::Raw) {
             Err(UnexpectedResolved::UnexpectedCodec(
                 IpldCodec::DagPb.into(),
                 self,
@@ -965,6 +1286,78 @@ mod tests {
         assert_eq!(res, data);
     }
 
+    #[tokio::test]
+    async fn put_and_get_with_codecs() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+        let dag = IpldDag::new(ipfs);
+        let data = ipld!({
+            "list": [1, -2, "three"],
+            "nested": { "bool": true, "null": null },
+            "bytes": Ipld::Bytes(vec![1, 2, 3]),
+        });
+
+        let cbor = dag.put_dag(data.clone()).await.unwrap();
+        let json = dag
+            .put_dag(data.clone())
+            .codec(IpldCodec::DagJson)
+            .hash(Code::Sha2_512)
+            .await
+            .unwrap();
+
+        assert_eq!(cbor.codec(), u64::from(IpldCodec::DagCbor));
+        assert_eq!(json.codec(), u64::from(IpldCodec::DagJson));
+        assert_eq!(json.hash().code(), u64::from(Code::Sha2_512));
+
+        let from_cbor = dag.get_dag(IpfsPath::from(cbor)).await.unwrap();
+        let from_json = dag.get_dag(IpfsPath::from(json)).await.unwrap();
+        assert_eq!(from_cbor, data);
+        assert_eq!(from_json, from_cbor);
+
+        let raw = dag
+            .put_dag(Ipld::Bytes(b"foobar".to_vec()))
+            .codec(IpldCodec::Raw)
+            .await
+            .unwrap();
+        assert_eq!(raw.codec(), u64::from(IpldCodec::Raw));
+        assert_eq!(
+            dag.get_dag(IpfsPath::from(raw)).await.unwrap(),
+            Ipld::Bytes(b"foobar".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn put_with_cid_version() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+        let dag = IpldDag::new(ipfs);
+
+        let cid = dag
+            .put_dag(ipld!({ "Links": [] }))
+            .codec(IpldCodec::DagPb)
+            .cid_version(Version::V1)
+            .await
+            .unwrap();
+        assert_eq!(cid.version(), Version::V1);
+
+        dag.put_dag(ipld!([1]))
+            .cid_version(Version::V0)
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn put_with_unsupported_codec() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+        let dag = IpldDag::new(ipfs);
+
+        // dag-jose
+        let e = dag.put_dag(ipld!([1])).codec(0x85u64).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<UnsupportedCodec>(),
+            Some(UnsupportedCodec(0x85))
+        ));
+        assert!(e.to_string().contains("dag-json"));
+    }
+
     #[tokio::test]
     async fn test_resolve_array_elem() {
         let Node { ipfs, .. } = Node::new("test_node").await;
@@ -1113,19 +1506,17 @@ mod tests {
     fn resolve_cbor_locally_to_link_with_dot() {
         let (root, example_doc, cid) = example_doc_and_cid();
 
-        let p = IpfsPath::try_from(
-            "bafyreielwgy762ox5ndmhx6kpi6go6il3gzahz3ngagb7xw3bj3aazeita/nested/even/2/or/./foobar/trailer",
-            // counts:                                                    1      2   3 4  5
-        )
-        .unwrap();
+        // IpfsPath no longer accepts dot segments, but the local resolving still supports them
+        let p = ["nested", "even", "2", "or", ".", "foobar", "trailer"];
+        // counts:  1        2      3    4     5
 
         let (resolved, matched_segments) =
-            super::resolve_local_ipld(root, example_doc, &mut p.iter().peekable()).unwrap();
+            super::resolve_local_ipld(root, example_doc, &mut p.iter().copied().peekable())
+                .unwrap();
         assert_eq!(resolved.unwrap_complete(), ResolvedNode::Link(root, cid));
         assert_eq!(matched_segments, 5);
 
-        let remaining_path = p.iter().skip(matched_segments).collect::<Vec<&str>>();
-        assert_eq!(remaining_path, &["foobar", "trailer"]);
+        assert_eq!(&p[matched_segments..], &["foobar", "trailer"]);
     }
 
     #[test]
@@ -1205,24 +1596,69 @@ mod tests {
 
         let prefix = IpfsPath::from(cid2);
 
-        // the two should be equal, as dot can appear or not appear
-        // FIXME: validate that go-ipfs still does this
-        let equiv_paths = vec![
-            prefix.sub_path("0/0").unwrap(),
-            prefix.sub_path("0/./0").unwrap(),
-        ];
+        // dot segments are rejected when constructing the path
+        prefix.sub_path("0/./0").unwrap_err();
 
-        for p in equiv_paths {
-            let cloned = p.clone();
-            match dag.resolve(p, true, &[], false).await.unwrap() {
-                (ResolvedNode::Projection(_, Ipld::Integer(1)), remaining_path) => {
-                    assert_eq!(remaining_path, ["0"][..], "{cloned}");
-                }
-                x => unreachable!("{:?}", x),
+        let p = prefix.sub_path("0/0").unwrap();
+        let cloned = p.clone();
+        match dag.resolve(p, true, &[], false).await.unwrap() {
+            (ResolvedNode::Projection(_, Ipld::Integer(1)), remaining_path) => {
+                assert_eq!(remaining_path, ["0"][..], "{cloned}");
             }
+            x => unreachable!("{:?}", x),
         }
     }
 
+    #[tokio::test]
+    async fn resolve_path_records_traversed() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+        let dag = IpldDag::new(ipfs.clone());
+        let ipld = ipld!({ "a": [1] });
+        let cid1 = dag.put_dag(ipld).await.unwrap();
+        let ipld = ipld!([cid1]);
+        let cid2 = dag.put_dag(ipld).await.unwrap();
+
+        let path = IpfsPath::from(cid2).sub_path("0/a/0").unwrap();
+
+        let (node, resolved) = dag
+            .resolve_path(path.clone(), true, &[], false)
+            .await
+            .unwrap();
+        assert!(matches!(
+            node,
+            ResolvedNode::Projection(_, Ipld::Integer(1))
+        ));
+        assert_eq!(resolved.cid(), &cid1);
+        assert_eq!(resolved.traversed(), &[cid2, cid1]);
+        assert!(resolved.remaining().is_empty());
+        assert_eq!(resolved.path_in_document(), &["a", "0"][..]);
+
+        let (_, resolved) = dag
+            .resolve_path(
+                IpfsPath::from(cid2).sub_path("0").unwrap(),
+                false,
+                &[],
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(resolved.traversed(), &[cid2]);
+        assert!(resolved.remaining().is_empty());
+
+        // the link is not followed, leaving the rest of the path unresolved
+        let (node, resolved) = dag
+            .resolve_path(path.clone(), false, &[], false)
+            .await
+            .unwrap();
+        assert_eq!(node, ResolvedNode::Link(cid2, cid1));
+        assert_eq!(resolved.remaining(), &["a", "0"][..]);
+        assert!(resolved.path_in_document().is_empty());
+
+        let (ipld, resolved) = ipfs.get_dag(path.clone()).resolved().await.unwrap();
+        assert_eq!(ipld, Ipld::Integer(1));
+        assert_eq!(resolved, ipfs.resolve(path).await.unwrap());
+    }
+
     #[tokio::test]
     async fn fail_resolving_first_segment() {
         let Node { ipfs, .. } = Node::new("test_node").await;
@@ -0,0 +1,281 @@
+//! Structural comparison of two DAGs.
+
+use std::collections::BTreeMap;
+
+use libipld::{Cid, Ipld, IpldCodec};
+use rust_unixfs::ListedLink;
+
+use crate::{error::Error, repo::Repo};
+
+/// A difference between two DAGs found by [`super::IpldDag::diff`]. The path is relative to the
+/// compared roots and uses the link names for dag-pb, including the entries of HAMT sharded
+/// directories, and the keys and indices within the document for other codecs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DagDiffEntry {
+    /// The link only exists in the second DAG.
+    Added { path: String, cid: Cid },
+    /// The link only exists in the first DAG.
+    Removed { path: String, cid: Cid },
+    /// The link exists in both but the contents of its target differ, apart from any of the
+    /// differences found deeper in the DAG.
+    Modified {
+        path: String,
+        before: Cid,
+        after: Cid,
+    },
+}
+
+impl DagDiffEntry {
+    pub fn path(&self) -> &str {
+        match self {
+            Self::Added { path, .. } | Self::Removed { path, .. } | Self::Modified { path, .. } => {
+                path
+            }
+        }
+    }
+}
+
+pub(super) async fn diff(repo: &Repo, a: Cid, b: Cid) -> Result<Vec<DagDiffEntry>, Error> {
+    let mut found = Vec::new();
+    let mut pending = vec![(String::new(), a, b)];
+
+    while let Some((path, before, after)) = pending.pop() {
+        // identical subtrees need not be loaded
+        if before == after {
+            continue;
+        }
+
+        let (Some(before_links), Some(after_links)) =
+            (links(repo, &before).await?, links(repo, &after).await?)
+        else {
+            found.push(DagDiffEntry::Modified {
+                path,
+                before,
+                after,
+            });
+            continue;
+        };
+
+        let mut changed_links = false;
+
+        for (name, cid) in &before_links {
+            if !after_links.contains_key(name) {
+                changed_links = true;
+                found.push(DagDiffEntry::Removed {
+                    path: join(&path, name),
+                    cid: *cid,
+                });
+            }
+        }
+
+        for (name, cid) in after_links {
+            match before_links.get(&name) {
+                Some(previous) if *previous == cid => {}
+                Some(previous) => {
+                    changed_links = true;
+                    pending.push((join(&path, &name), *previous, cid));
+                }
+                None => {
+                    changed_links = true;
+                    found.push(DagDiffEntry::Added {
+                        path: join(&path, &name),
+                        cid,
+                    });
+                }
+            }
+        }
+
+        if !changed_links {
+            // only the data of the node itself differs
+            found.push(DagDiffEntry::Modified {
+                path,
+                before,
+                after,
+            });
+        }
+    }
+
+    found.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(found)
+}
+
+/// Returns the named links of the block, or `None` when the block is compared as a whole, such as
+/// for raw blocks and UnixFS files.
+async fn links(repo: &Repo, cid: &Cid) -> Result<Option<BTreeMap<String, Cid>>, Error> {
+    let block = repo.get_block(cid, &[], false).await?;
+
+    if cid.codec() == u64::from(IpldCodec::Raw) {
+        return Ok(None);
+    }
+
+    if cid.codec() != u64::from(IpldCodec::DagPb) {
+        let mut links = BTreeMap::new();
+        document_links(
+            String::new(),
+            block.decode::<IpldCodec, Ipld>()?,
+            &mut links,
+        );
+        return Ok((!links.is_empty()).then_some(links));
+    }
+
+    let mut links = BTreeMap::new();
+    let mut pending = vec![block];
+
+    while let Some(block) = pending.pop() {
+        // unixfs files and other nodes without link names are compared as a whole
+        let Ok(listed) = rust_unixfs::list(block.data()) else {
+            return Ok(None);
+        };
+
+        for link in listed {
+            match link {
+                ListedLink::Entry { name, cid, .. } => {
+                    if name.is_empty() || links.insert(name, cid).is_some() {
+                        return Ok(None);
+                    }
+                }
+                ListedLink::Bucket(cid) => pending.push(repo.get_block(&cid, &[], false).await?),
+            }
+        }
+    }
+
+    Ok(Some(links))
+}
+
+fn document_links(path: String, ipld: Ipld, links: &mut BTreeMap<String, Cid>) {
+    match ipld {
+        Ipld::Link(cid) => {
+            links.insert(path, cid);
+        }
+        Ipld::List(list) => {
+            for (i, ipld) in list.into_iter().enumerate() {
+                document_links(join(&path, &i.to_string()), ipld, links);
+            }
+        }
+        Ipld::Map(map) => {
+            for (key, ipld) in map {
+                document_links(join(&path, &key), ipld, links);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{path}/{name}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DagDiffEntry;
+    use crate::unixfs::WriteOptions;
+    use crate::Node;
+    use libipld::ipld;
+
+    #[tokio::test]
+    async fn diff_single_changed_leaf() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        let create = WriteOptions {
+            create: true,
+            ..Default::default()
+        };
+
+        ipfs.files_mkdir("/a/b/c/d", true).await.unwrap();
+        ipfs.files_write("/a/b/c/d/leaf", b"before", create)
+            .await
+            .unwrap();
+        ipfs.files_write("/a/b/c/other", b"same", create)
+            .await
+            .unwrap();
+        ipfs.files_write("/a/sibling", b"same", create)
+            .await
+            .unwrap();
+        let first = ipfs.files_flush().await.unwrap();
+
+        let truncate = WriteOptions {
+            truncate: true,
+            ..Default::default()
+        };
+        ipfs.files_write("/a/b/c/d/leaf", b"after", truncate)
+            .await
+            .unwrap();
+        let second = ipfs.files_flush().await.unwrap();
+
+        let changed = ipfs.files_stat("/a/b/c/d/leaf").await.unwrap().cid;
+
+        let diff = ipfs.dag_diff(first, second).await.unwrap();
+        assert_eq!(diff.len(), 1, "{diff:?}");
+        assert_eq!(diff[0].path(), "a/b/c/d/leaf");
+        assert!(matches!(diff[0], DagDiffEntry::Modified { after, .. } if after == changed));
+
+        assert!(ipfs.dag_diff(first, first).await.unwrap().is_empty());
+
+        ipfs.files_rm("/a/sibling", false).await.unwrap();
+        ipfs.files_write("/a/b/added", b"new", create)
+            .await
+            .unwrap();
+        let third = ipfs.files_flush().await.unwrap();
+
+        let paths = ipfs
+            .dag_diff(second, third)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| match entry {
+                DagDiffEntry::Added { path, .. } => format!("+{path}"),
+                DagDiffEntry::Removed { path, .. } => format!("-{path}"),
+                DagDiffEntry::Modified { path, .. } => format!("~{path}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["+a/b/added", "-a/sibling"]);
+    }
+
+    #[tokio::test]
+    async fn diff_documents() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        let leaf = ipfs.dag().put_dag(ipld!("leaf")).await.unwrap();
+        let changed = ipfs.dag().put_dag(ipld!("changed")).await.unwrap();
+
+        let a = ipfs
+            .dag()
+            .put_dag(ipld!({ "list": [leaf, leaf], "value": 1 }))
+            .await
+            .unwrap();
+        let b = ipfs
+            .dag()
+            .put_dag(ipld!({ "list": [leaf, changed], "value": 1 }))
+            .await
+            .unwrap();
+        let c = ipfs
+            .dag()
+            .put_dag(ipld!({ "list": [leaf, leaf], "value": 2 }))
+            .await
+            .unwrap();
+
+        let diff = ipfs.dag_diff(a, b).await.unwrap();
+        assert_eq!(
+            diff,
+            [DagDiffEntry::Modified {
+                path: "list/1".into(),
+                before: leaf,
+                after: changed
+            }]
+        );
+
+        let diff = ipfs.dag_diff(a, c).await.unwrap();
+        assert_eq!(
+            diff,
+            [DagDiffEntry::Modified {
+                path: String::new(),
+                before: a,
+                after: c
+            }]
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Size statistics of a DAG.
+
+use std::collections::{HashSet, VecDeque};
+
+use libipld::Cid;
+
+use crate::{error::Error, repo::Repo};
+
+/// Statistics of the unique blocks of a DAG, returned by [`super::IpldDag::stat`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DagStat {
+    /// Number of unique blocks, including the root.
+    pub blocks: usize,
+    /// Sum of the sizes of the unique blocks.
+    pub total_size: u64,
+    /// Size of the largest block.
+    pub largest_block: u64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DagStatError {
+    /// Loading of a block failed
+    #[error("loading of {0} failed")]
+    Loading(Cid, #[source] Error),
+
+    /// The links of a block could not be read
+    #[error("reading the links of {0} failed")]
+    References(Cid, #[source] Error),
+
+    /// The blocks which were not found in the local repo when walking offline. The walk continues
+    /// past the missing blocks, so all of the missing blocks reachable through the found ones are
+    /// listed.
+    #[error("{} blocks are missing from the local repo", .0.len())]
+    MissingBlocks(Vec<Cid>),
+}
+
+pub(super) async fn stat(repo: &Repo, root: Cid, offline: bool) -> Result<DagStat, DagStatError> {
+    let mut stat = DagStat::default();
+    let mut missing = Vec::new();
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([root]);
+
+    while let Some(cid) = queue.pop_front() {
+        if !seen.insert(cid) {
+            continue;
+        }
+
+        let block = if offline {
+            match repo.get_block_now(&cid).await {
+                Ok(Some(block)) => block,
+                Ok(None) => {
+                    missing.push(cid);
+                    continue;
+                }
+                Err(e) => return Err(DagStatError::Loading(cid, e)),
+            }
+        } else {
+            repo.get_block_with_session(None, &cid, &[], false, None)
+                .await
+                .map_err(|e| DagStatError::Loading(cid, e))?
+        };
+
+        let mut links = Vec::new();
+        block
+            .references(&mut links)
+            .map_err(|e| DagStatError::References(cid, e))?;
+        queue.extend(links.into_iter().filter(|link| !seen.contains(link)));
+
+        let size = block.data().len() as u64;
+        stat.blocks += 1;
+        stat.total_size += size;
+        stat.largest_block = stat.largest_block.max(size);
+    }
+
+    if !missing.is_empty() {
+        return Err(DagStatError::MissingBlocks(missing));
+    }
+
+    Ok(stat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DagStatError;
+    use crate::Node;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn stat_counts_unique_blocks() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        let data = b"hello, world\n".repeat(1024);
+        let path = ipfs
+            .add_unixfs(("hello.txt".to_string(), data))
+            .chunk(rust_unixfs::file::adder::Chunker::Size(1024))
+            .pin(false)
+            .wrap()
+            .await
+            .unwrap();
+        let root = *path.root().cid().unwrap();
+
+        let mut expected_size = 0;
+        let mut largest = 0;
+        let mut blocks = ipfs
+            .repo()
+            .list_blocks(Default::default())
+            .await
+            .map(|(cid, _)| cid)
+            .collect::<Vec<_>>()
+            .await;
+        for cid in &blocks {
+            let block = ipfs.repo().get_block_now(cid).await.unwrap().unwrap();
+            expected_size += block.data().len() as u64;
+            largest = largest.max(block.data().len() as u64);
+        }
+
+        let stat = ipfs.dag_stat(root, true).await.unwrap();
+        // the wrapping directory, the file root and 13 distinct leaves
+        assert_eq!(stat.blocks, 15);
+        assert_eq!(stat.blocks, blocks.len());
+        assert_eq!(stat.total_size, expected_size);
+        assert_eq!(stat.largest_block, largest);
+
+        blocks.retain(|cid| *cid != root);
+        let removed = blocks[0];
+        ipfs.repo().remove_block(&removed, false).await.unwrap();
+
+        match ipfs.dag_stat(root, true).await {
+            Err(DagStatError::MissingBlocks(missing)) => assert_eq!(missing, [removed]),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+}
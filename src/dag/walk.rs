@@ -0,0 +1,291 @@
+//! Partial traversal of a DAG guided by a [`Selector`].
+
+use std::collections::HashSet;
+
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use libipld::{Cid, Ipld, IpldCodec};
+
+use crate::{error::Error, path::IpfsPath, repo::Repo};
+
+/// Selects the parts of a DAG to traverse, after the IPLD selectors. The selector applies to the
+/// documents as if the links within them were replaced by the documents they point to, so
+/// traversing into a link loads the linked block.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Selector {
+    /// Selects the current node, ending the traversal there. Inside of
+    /// [`Selector::ExploreRecursive`] it marks where the recursion continues instead.
+    Matcher,
+    /// Applies the selector to the given fields of a map, or indices of a list, which exist.
+    ExploreFields(Vec<String>, Box<Selector>),
+    /// Applies the selector to every value of a map or element of a list.
+    ExploreAll(Box<Selector>),
+    /// Applies `inner` repeatedly, starting over at its [`Selector::Matcher`]s until `limit`
+    /// repetitions have been made. The matchers of a nested `ExploreRecursive` belong to it.
+    ExploreRecursive { limit: usize, inner: Box<Selector> },
+}
+
+impl Selector {
+    /// Selects the whole DAG up to the given depth of nested maps and lists.
+    pub fn explore_all_recursively(limit: usize) -> Self {
+        Selector::ExploreRecursive {
+            limit,
+            inner: Box::new(Selector::ExploreAll(Box::new(Selector::Matcher))),
+        }
+    }
+
+    /// Returns the selector which applies at the current node, unrolling a recursion by one
+    /// repetition, or [`Selector::Matcher`] once the limit has been reached.
+    fn unroll(self) -> Self {
+        match self {
+            Selector::ExploreRecursive { limit: 0, .. } => Selector::Matcher,
+            Selector::ExploreRecursive { limit, inner } => {
+                let next = Selector::ExploreRecursive {
+                    limit: limit - 1,
+                    inner: inner.clone(),
+                };
+                inner.continue_with(&next)
+            }
+            selector => selector,
+        }
+    }
+
+    fn continue_with(self, next: &Selector) -> Self {
+        match self {
+            Selector::Matcher => next.clone(),
+            Selector::ExploreFields(fields, inner) => {
+                Selector::ExploreFields(fields, Box::new(inner.continue_with(next)))
+            }
+            Selector::ExploreAll(inner) => {
+                Selector::ExploreAll(Box::new(inner.continue_with(next)))
+            }
+            recursive @ Selector::ExploreRecursive { .. } => recursive,
+        }
+    }
+}
+
+struct Visit {
+    node: Ipld,
+    path: IpfsPath,
+    selector: Selector,
+    /// The blocks linking to this node, used to break cycles.
+    ancestors: Vec<Cid>,
+}
+
+/// Walks the DAG from `root` depth-first, loading and yielding only the blocks the selector
+/// traverses into, each once along with the path it was first reached at.
+pub(super) fn walk(
+    repo: Repo,
+    root: Cid,
+    selector: Selector,
+) -> BoxStream<'static, Result<(IpfsPath, Cid, Ipld), Error>> {
+    async_stream::try_stream! {
+        let mut yielded = HashSet::new();
+        let mut explored = HashSet::new();
+        let mut pending = vec![Visit {
+            node: Ipld::Link(root),
+            path: IpfsPath::from(root),
+            selector,
+            ancestors: vec![],
+        }];
+
+        while let Some(Visit { node, path, selector, mut ancestors }) = pending.pop() {
+            let node = match node {
+                Ipld::Link(cid) => {
+                    if ancestors.contains(&cid) {
+                        debug!("link cycle through {} at {}", cid, path);
+                        continue;
+                    }
+
+                    // a block reached through several paths with the same selector is only
+                    // explored once
+                    if !explored.insert((cid, selector.clone())) {
+                        continue;
+                    }
+
+                    let block = repo.get_block(&cid, &[], false).await?;
+                    let document = block.decode::<IpldCodec, Ipld>()?;
+
+                    if yielded.insert(cid) {
+                        yield (path.clone(), cid, document.clone());
+                    }
+
+                    ancestors.push(cid);
+                    document
+                }
+                node => node,
+            };
+
+            let (fields, next) = match selector.unroll() {
+                Selector::Matcher => continue,
+                Selector::ExploreFields(fields, next) => (Some(fields), next),
+                Selector::ExploreAll(next) => (None, next),
+                Selector::ExploreRecursive { .. } => unreachable!("recursion is unrolled"),
+            };
+
+            let children = match node {
+                Ipld::Map(map) => map.into_iter().collect::<Vec<_>>(),
+                Ipld::List(list) => list
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, node)| (index.to_string(), node))
+                    .collect(),
+                _ => continue,
+            };
+
+            // pushed in reverse so that the children are visited in their order
+            for (name, node) in children.into_iter().rev() {
+                if matches!(&fields, Some(fields) if !fields.contains(&name)) {
+                    continue;
+                }
+
+                // names which cannot be path segments cannot be selected either
+                let Ok(path) = path.join(&name) else {
+                    continue;
+                };
+
+                pending.push(Visit {
+                    node,
+                    path,
+                    selector: (*next).clone(),
+                    ancestors: ancestors.clone(),
+                });
+            }
+        }
+    }
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use futures::TryStreamExt;
+    use libipld::cbor::DagCborCodec;
+    use libipld::codec::Codec;
+    use libipld::multihash::{Code, MultihashDigest};
+    use libipld::{ipld, Cid, IpldCodec};
+
+    use super::Selector;
+    use crate::{Block, Node};
+
+    fn fields(names: &[&str], next: Selector) -> Selector {
+        Selector::ExploreFields(
+            names.iter().map(|name| name.to_string()).collect(),
+            Box::new(next),
+        )
+    }
+
+    fn all(next: Selector) -> Selector {
+        Selector::ExploreAll(Box::new(next))
+    }
+
+    #[tokio::test]
+    async fn fields_skip_the_unselected_blocks() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        let huge = ipfs.put_dag(ipld!("huge data leaf")).await.unwrap();
+        let mut entries = vec![];
+        let mut payloads = vec![];
+        for i in 0..3 {
+            let payload = ipfs.put_dag(ipld!({ "n": i })).await.unwrap();
+            payloads.push(payload);
+            entries.push(ipld!({ "payload": payload, "data": huge }));
+        }
+        let root = ipfs.put_dag(ipld!({ "links": entries })).await.unwrap();
+
+        // /links/*/payload
+        let selector = fields(&["links"], all(fields(&["payload"], Selector::Matcher)));
+        let walked = ipfs
+            .dag_walk(root, selector)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        let cids = walked.iter().map(|(_, cid, _)| *cid).collect::<Vec<_>>();
+        assert_eq!(cids, [&[root][..], &payloads[..]].concat());
+        assert!(!cids.contains(&huge));
+
+        assert_eq!(
+            walked[2].0,
+            format!("/ipfs/{root}/links/1/payload").parse().unwrap()
+        );
+        assert_eq!(walked[2].2, ipld!({ "n": 1 }));
+    }
+
+    #[tokio::test]
+    async fn diamond_blocks_are_yielded_once() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        let bottom = ipfs.put_dag(ipld!("bottom")).await.unwrap();
+        let left = ipfs.put_dag(ipld!({ "next": bottom })).await.unwrap();
+        let right = ipfs
+            .put_dag(ipld!({ "next": bottom, "side": true }))
+            .await
+            .unwrap();
+        let top = ipfs.put_dag(ipld!([left, right])).await.unwrap();
+
+        let walked = ipfs
+            .dag_walk(top, Selector::explore_all_recursively(10))
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        let cids = walked.iter().map(|(_, cid, _)| *cid).collect::<Vec<_>>();
+        assert_eq!(cids, [top, left, bottom, right]);
+        assert_eq!(walked[2].0, format!("/ipfs/{top}/0/next").parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn recursion_is_depth_limited() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        let mut chain = vec![ipfs.put_dag(ipld!({ "end": true })).await.unwrap()];
+        for _ in 0..5 {
+            let next = *chain.last().unwrap();
+            chain.push(ipfs.put_dag(ipld!({ "next": next })).await.unwrap());
+        }
+        chain.reverse();
+
+        let selector = Selector::ExploreRecursive {
+            limit: 2,
+            inner: Box::new(fields(&["next"], Selector::Matcher)),
+        };
+        let walked = ipfs
+            .dag_walk(chain[0], selector)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        let cids = walked.iter().map(|(_, cid, _)| *cid).collect::<Vec<_>>();
+        assert_eq!(cids, chain[..3]);
+    }
+
+    #[tokio::test]
+    async fn link_cycles_are_broken() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        // no hash can link to itself, so the cids are made up for the blocks to form a loop
+        let a = Cid::new_v1(IpldCodec::DagCbor.into(), Code::Sha2_256.digest(b"a"));
+        let b = Cid::new_v1(IpldCodec::DagCbor.into(), Code::Sha2_256.digest(b"b"));
+        for (cid, next) in [(a, b), (b, a)] {
+            let data = DagCborCodec.encode(&ipld!({ "next": next })).unwrap();
+            ipfs.put_block(Block::new_unchecked(cid, data))
+                .await
+                .unwrap();
+        }
+
+        let walked = ipfs
+            .dag_walk(a, Selector::explore_all_recursively(100))
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        let cids = walked
+            .iter()
+            .map(|(_, cid, _)| *cid)
+            .collect::<BTreeSet<_>>();
+        assert_eq!(cids, BTreeSet::from([a, b]));
+        assert_eq!(walked.len(), 2);
+    }
+}
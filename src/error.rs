@@ -15,3 +15,64 @@ pub use anyhow::Error;
 ///
 /// Very likely to change in the future.
 pub struct TryError;
+
+/// A protocol of the node, which can be disabled through its options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ProtocolKind {
+    Kademlia,
+    Bitswap,
+    Pubsub,
+    Ping,
+    Stream,
+    Mdns,
+    Autonat,
+    Relay,
+    RelayServer,
+    Rendezvous,
+}
+
+impl std::fmt::Display for ProtocolKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ProtocolKind::Kademlia => "kad",
+            ProtocolKind::Bitswap => "bitswap",
+            ProtocolKind::Pubsub => "pubsub",
+            ProtocolKind::Ping => "ping",
+            ProtocolKind::Stream => "stream",
+            ProtocolKind::Mdns => "mdns",
+            ProtocolKind::Autonat => "autonat",
+            ProtocolKind::Relay => "relay",
+            ProtocolKind::RelayServer => "relay server",
+            ProtocolKind::Rendezvous => "rendezvous client",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The request needs a protocol which is disabled on the node. Returned within [`Error`], from
+/// which it can be recovered with `downcast_ref::<ProtocolDisabled>()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("{0} protocol is disabled")]
+pub struct ProtocolDisabled(pub ProtocolKind);
+
+/// The node did not meet the [`ReadyCriteria`](crate::ReadyCriteria) of
+/// [`Ipfs::wait_ready`](crate::Ipfs::wait_ready) before its timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "node not ready: {} listeners, {} connected peers, {} peers in the routing table",
+    .0.listeners,
+    .0.connected_peers,
+    .0.dht_routing_peers
+)]
+pub struct NotReady(pub crate::ReadyState);
+
+/// The operation did not complete before its timeout, what it started being cancelled: the
+/// blocks it wanted are no longer wanted, and the DHT queries it started are finished. Returned
+/// within [`Error`], from which it can be recovered with `downcast_ref::<Timeout>()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("{operation} timed out after {elapsed:?}")]
+pub struct Timeout {
+    pub elapsed: std::time::Duration,
+    pub operation: &'static str,
+}
@@ -0,0 +1,253 @@
+//! The bounded channel through which [`crate::Ipfs`] hands its requests over to the background
+//! task, and the handle on that task.
+
+use std::any::Any;
+use std::fmt;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, OnceLock};
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::watch;
+use tokio::task::AbortHandle;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+
+use crate::IpfsEvent;
+
+/// Failure to hand a request over to the background task of the node.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum FacadeError {
+    /// The queue of requests to the background task is full. Only returned by the `try_`
+    /// variants of the requests, the others wait for room in the queue.
+    #[error("the node is busy")]
+    Busy,
+    /// The background task has exited, for the reason given.
+    #[error("the node has shut down: {reason}")]
+    NodeShutdown { reason: ShutdownReason },
+}
+
+/// Why the background task of the node exited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// The node was shut down, or every [`Ipfs`](crate::Ipfs) was dropped
+    Exited,
+    /// The task panicked, with the message of the panic
+    Panicked(String),
+    /// The task was aborted with [`TaskHandle::abort`]
+    Aborted,
+}
+
+impl ShutdownReason {
+    pub(crate) fn from_panic(payload: Box<dyn Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&'static str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "unknown panic payload".into(),
+            },
+        };
+        ShutdownReason::Panicked(message)
+    }
+}
+
+impl fmt::Display for ShutdownReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShutdownReason::Exited => f.write_str("exited"),
+            ShutdownReason::Panicked(message) => write!(f, "panicked: {message}"),
+            ShutdownReason::Aborted => f.write_str("aborted"),
+        }
+    }
+}
+
+/// Handle on the background task of the node, to wait for it to exit, find out why it did, or
+/// abort it.
+#[derive(Debug, Clone)]
+pub struct TaskHandle {
+    inner: Arc<TaskInner>,
+}
+
+#[derive(Debug)]
+struct TaskInner {
+    reason: watch::Sender<Option<ShutdownReason>>,
+    abort: OnceLock<AbortHandle>,
+}
+
+impl TaskHandle {
+    /// Waits for the task to exit, returning why it did.
+    pub async fn join(&self) -> ShutdownReason {
+        let mut reason = self.inner.reason.subscribe();
+        let reason = reason
+            .wait_for(Option::is_some)
+            .await
+            .expect("the sender is held by the handle");
+        reason.clone().expect("waited for the reason")
+    }
+
+    /// Why the task exited, or `None` while it is running.
+    pub fn reason(&self) -> Option<ShutdownReason> {
+        self.inner.reason.borrow().clone()
+    }
+
+    /// Aborts the task, the requests to the node failing with [`FacadeError::NodeShutdown`]
+    /// afterwards. Unlike [`Ipfs::shutdown_graceful`](crate::Ipfs::shutdown_graceful), the pending
+    /// writes of the task are not completed.
+    pub fn abort(&self) {
+        if let Some(abort) = self.inner.abort.get() {
+            abort.abort();
+        }
+    }
+
+    /// Runs the task until it exits or the token is cancelled, recording why it exited.
+    pub(crate) async fn run(self, fut: BoxFuture<'static, ()>, token: CancellationToken) {
+        let mut task = Running {
+            fut,
+            handle: self,
+            reason: ShutdownReason::Aborted,
+        };
+        task.reason = tokio::select! {
+            result = AssertUnwindSafe(&mut task.fut).catch_unwind() => match result {
+                Ok(()) => ShutdownReason::Exited,
+                Err(panic) => ShutdownReason::from_panic(panic),
+            },
+            _ = token.cancelled() => ShutdownReason::Exited,
+        };
+        if let ShutdownReason::Panicked(message) = &task.reason {
+            tracing::error!("background task panicked: {message}");
+        }
+    }
+
+    /// Records why the task exited, unless it already was.
+    fn exited(&self, reason: ShutdownReason) {
+        self.inner.reason.send_if_modified(|current| {
+            if current.is_some() {
+                return false;
+            }
+            *current = Some(reason);
+            true
+        });
+    }
+
+    pub(crate) fn set_abort_handle(&self, abort: AbortHandle) {
+        let _ = self.inner.abort.set(abort);
+    }
+}
+
+/// The future of the task, recording why it exited when dropped, before dropping the future
+/// closes the channel of the requests. It was aborted if dropped before exiting.
+struct Running {
+    fut: BoxFuture<'static, ()>,
+    handle: TaskHandle,
+    reason: ShutdownReason,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.handle.exited(self.reason.clone());
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct FacadeSender {
+    sender: mpsc::Sender<IpfsEvent>,
+    reason: watch::Receiver<Option<ShutdownReason>>,
+}
+
+/// Creates the channel, holding up to `capacity` requests not yet handled by the task, along
+/// with the handle on the task.
+pub(crate) fn channel(capacity: usize) -> (FacadeSender, ReceiverStream<IpfsEvent>, TaskHandle) {
+    let (sender, receiver) = mpsc::channel(capacity.max(1));
+    let (reason_tx, reason) = watch::channel(None);
+    let handle = TaskHandle {
+        inner: Arc::new(TaskInner {
+            reason: reason_tx,
+            abort: OnceLock::new(),
+        }),
+    };
+    (
+        FacadeSender { sender, reason },
+        ReceiverStream::new(receiver),
+        handle,
+    )
+}
+
+impl FacadeSender {
+    /// Queues the event, waiting for room in the queue.
+    pub(crate) async fn send(&self, event: IpfsEvent) -> Result<(), FacadeError> {
+        self.sender.send(event).await.map_err(|_| self.shutdown())
+    }
+
+    /// Queues the event if there is room in the queue.
+    pub(crate) fn try_send(&self, event: IpfsEvent) -> Result<(), FacadeError> {
+        self.sender.try_send(event).map_err(|e| match e {
+            TrySendError::Full(_) => FacadeError::Busy,
+            TrySendError::Closed(_) => self.shutdown(),
+        })
+    }
+
+    /// The error of the requests made once the task exited.
+    pub(crate) fn shutdown(&self) -> FacadeError {
+        // recorded before the channel is closed, unless it was never run by a task
+        let reason = self.reason.borrow().clone();
+        FacadeError::NodeShutdown {
+            reason: reason.unwrap_or(ShutdownReason::Exited),
+        }
+    }
+
+    /// Number of events queued, not yet received by the task.
+    pub(crate) fn depth(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.sender.capacity() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::{channel, FacadeError, ShutdownReason};
+    use crate::IpfsEvent;
+
+    #[tokio::test]
+    async fn full_and_closed_channels() {
+        let (sender, mut receiver, handle) = channel(2);
+
+        sender.try_send(IpfsEvent::Exit).unwrap();
+        sender.clone().try_send(IpfsEvent::Exit).unwrap();
+        assert_eq!(sender.depth(), 2);
+        assert!(sender.is_full());
+        assert_eq!(
+            sender.clone().try_send(IpfsEvent::Exit),
+            Err(FacadeError::Busy)
+        );
+
+        receiver.next().await.unwrap();
+        assert_eq!(sender.depth(), 1);
+
+        drop(receiver);
+        let shutdown = FacadeError::NodeShutdown {
+            reason: ShutdownReason::Exited,
+        };
+        assert_eq!(sender.try_send(IpfsEvent::Exit), Err(shutdown.clone()));
+        assert_eq!(sender.send(IpfsEvent::Exit).await, Err(shutdown));
+
+        handle.exited(ShutdownReason::Panicked("poisoned".into()));
+        // only the first reason is kept
+        handle.exited(ShutdownReason::Exited);
+        assert_eq!(
+            handle.join().await,
+            ShutdownReason::Panicked("poisoned".into())
+        );
+        assert_eq!(
+            sender.send(IpfsEvent::Exit).await,
+            Err(FacadeError::NodeShutdown {
+                reason: ShutdownReason::Panicked("poisoned".into())
+            })
+        );
+    }
+}
@@ -1,9 +1,11 @@
 use std::{
     collections::{btree_map::Entry, BTreeMap},
+    io::Write,
+    path::Path,
     sync::Arc,
 };
 
-use anyhow::Error;
+use anyhow::{Context, Error};
 use futures::{stream::BoxStream, StreamExt};
 use libp2p::identity::{Keypair, PublicKey};
 use tokio::sync::Mutex;
@@ -119,7 +121,9 @@ impl Keystore {
     ) -> Result<PublicKey, Error> {
         let keypair = match key_type {
             KeyType::Ed25519 => Keypair::generate_ed25519(),
-            _ => anyhow::bail!("unimplemented"),
+            KeyType::Ecdsa => Keypair::generate_ecdsa(),
+            KeyType::Secp256k1 => Keypair::generate_secp256k1(),
+            KeyType::Rsa => anyhow::bail!("RSA keys cannot be generated, only imported"),
         };
         let public_key = keypair.public();
 
@@ -157,6 +161,36 @@ impl Keystore {
     }
 }
 
+/// Loads the [`Keypair`] from the protobuf encoded private key in the file, writing the given
+/// or a generated Ed25519 one to it if the file does not exist.
+pub(crate) fn load_or_create_keypair(
+    path: &Path,
+    keypair: Option<Keypair>,
+) -> Result<Keypair, Error> {
+    match std::fs::read(path) {
+        Ok(bytes) => {
+            let key = Key::from(bytes);
+            Keypair::from_protobuf_encoding(key.as_ref())
+                .with_context(|| format!("decoding the keypair in {}", path.display()))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let keypair = keypair.unwrap_or_else(Keypair::generate_ed25519);
+            let key = Key::from(keypair.to_protobuf_encoding()?);
+
+            let mut file = std::fs::OpenOptions::new();
+            file.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut file, 0o600);
+            file.open(path)
+                .and_then(|mut file| file.write_all(key.as_ref()))
+                .with_context(|| format!("writing the keypair to {}", path.display()))?;
+
+            Ok(keypair)
+        }
+        Err(e) => Err(Error::from(e).context(format!("reading {}", path.display()))),
+    }
+}
+
 #[async_trait::async_trait]
 pub trait KeyStorage: Sync + Send + 'static {
     async fn set(&self, name: &str, key: &[u8]) -> Result<(), Error>;
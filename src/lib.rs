@@ -22,16 +22,21 @@
 // the docs better.
 //#![allow(private_intra_doc_links)]
 
+pub mod car;
 pub mod config;
 pub mod dag;
 pub mod error;
+mod facade;
 pub mod ipns;
 mod keystore;
+#[cfg(feature = "metrics")]
+mod metrics;
 pub mod p2p;
 pub mod path;
 pub mod refs;
 pub mod repo;
 mod task;
+pub mod testing;
 pub mod unixfs;
 
 #[macro_use]
@@ -39,11 +44,13 @@ extern crate tracing;
 
 use anyhow::{anyhow, format_err};
 use bytes::Bytes;
-use dag::{DagGet, DagPut};
+use car::{DagExport, ImportOptions};
+use dag::{DagDiffEntry, DagGet, DagPut, DagResolve, DagStat, DagStatError, Selector};
 use either::Either;
+use facade::FacadeSender;
 use futures::{
     channel::{
-        mpsc::{channel, Sender, UnboundedReceiver},
+        mpsc::{unbounded, UnboundedReceiver},
         oneshot::{self, channel as oneshot_channel, Sender as OneshotSender},
     },
     future::BoxFuture,
@@ -58,26 +65,37 @@ use keystore::Keystore;
 use p2p::BitswapConfig;
 
 use p2p::{
-    IdentifyConfiguration, KadConfig, KadStoreConfig, PeerInfo, PubsubConfig, RelayConfig,
-    SwarmConfig, TransportConfig,
+    bandwidth::BandwidthCounters, dht_limit::DhtWriteLimiter, observed::ObservedAddrs,
+    AutonatStatus, Ban, BandwidthStats, BootstrapPolicy, BootstrapReport, ConnectionGate,
+    ConnectionLimits, ContentAnnouncerConfig, DhtStats, DhtStoreContents, DhtWriteLimit, DialError,
+    ExternalAddressInfo, GateHandle, InterfaceFilter, KadConfig, KadStoreConfig, ListenerInfo,
+    MultiaddrExt, ObservedAddrConfig, PeerInfo, PeerLedger, PeerMetaConfig, PeerProtectionStatus,
+    ProviderRanking, PubsubConfig, RelayClientConfig, RelayConfig, RelayServerStats, RelayStatus,
+    RendezvousConfig, ServeOrder, SwarmConfig, TransportConfig,
 };
+use repo::blockstore::flatfs::FsLayout;
 use repo::{
-    BlockStore, DataStore, GCConfig, GCTrigger, Lock, RepoFetch, RepoInsertPin, RepoRemovePin,
+    BlockStore, DataStore, Eviction, GCConfig, GCResult, GCTrigger, Lock, RepairMode, RepoFetch,
+    RepoInsertPin, RepoRemovePin, RepoStat, VerifyHandle,
 };
 use tokio::task::JoinHandle;
 use tokio_util::sync::{CancellationToken, DropGuard};
 use tracing::Span;
 use tracing_futures::Instrument;
-use unixfs::{AddOpt, IpfsUnixfs, UnixfsAdd, UnixfsCat, UnixfsGet, UnixfsLs};
+use unixfs::{
+    AddOpt, GatewayRequest, GatewayResponse, IpfsUnixfs, Mfs, MfsError, MfsStat, UnixfsAdd,
+    UnixfsCat, UnixfsGet, UnixfsLs, WriteOptions,
+};
 
 use std::{
+    any::Any,
     collections::{BTreeSet, HashMap, HashSet},
     fmt,
     ops::{Deref, DerefMut},
     path::{Path, PathBuf},
     sync::atomic::AtomicU64,
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
 
 use self::{
@@ -87,14 +105,15 @@ use self::{
     repo::Repo,
 };
 
-pub use self::p2p::gossipsub::SubscriptionStream;
+pub use self::p2p::gossipsub::{MeshEvent, MeshInfo, SubscriptionStream};
 
 pub use self::{
     error::Error,
+    facade::{FacadeError, ShutdownReason, TaskHandle},
     p2p::BehaviourEvent,
     p2p::KadResult,
     path::IpfsPath,
-    repo::{PinKind, PinMode},
+    repo::{BlockFilter, BlockPut, FetchPolicy, PinKind, PinLabel, PinMode, PinProgress, PutStats},
 };
 
 pub type Block = libipld::Block<libipld::DefaultParams>;
@@ -115,8 +134,8 @@ pub use libp2p::{
 };
 
 use libp2p::{
-    core::{muxing::StreamMuxerBox, transport::Boxed},
-    kad::{store::MemoryStoreConfig, Mode, Record},
+    core::{muxing::StreamMuxerBox, transport::Boxed, Endpoint},
+    kad::{Mode, Record},
     ping::Config as PingConfig,
     rendezvous::Namespace,
     swarm::dial_opts::DialOpts,
@@ -128,6 +147,20 @@ pub(crate) static BITSWAP_ID: AtomicU64 = AtomicU64::new(1);
 #[derive(Default, Debug)]
 pub enum StoragePath {
     Disk(PathBuf),
+    /// Like [`StoragePath::Disk`], except the blocks are kept in the `blocks` directory with the
+    /// given layout. With [`FsLayout::Multihash`], this can be the `blocks` directory of a go-ipfs
+    /// repository, which is best opened `read_only` unless go-ipfs is no longer used with it.
+    Flatfs {
+        path: PathBuf,
+        blocks: PathBuf,
+        layout: FsLayout,
+        /// The blocks are never written nor removed, so only existing blocks can be used.
+        read_only: bool,
+    },
+    /// Blocks, pins and other data are kept in a single redb database in the directory, so that a
+    /// block can be put and pinned atomically with [`Ipfs::put_pinned_block`].
+    #[cfg(feature = "redb_data_store")]
+    Redb(PathBuf),
     #[default]
     Memory,
     Custom {
@@ -143,6 +176,22 @@ impl PartialEq for StoragePath {
             (StoragePath::Disk(left_path), StoragePath::Disk(right_path)) => {
                 left_path.eq(right_path)
             }
+            (
+                StoragePath::Flatfs {
+                    path: left_path,
+                    blocks: left_blocks,
+                    ..
+                },
+                StoragePath::Flatfs {
+                    path: right_path,
+                    blocks: right_blocks,
+                    ..
+                },
+            ) => left_path.eq(right_path) && left_blocks.eq(right_blocks),
+            #[cfg(feature = "redb_data_store")]
+            (StoragePath::Redb(left_path), StoragePath::Redb(right_path)) => {
+                left_path.eq(right_path)
+            }
             (StoragePath::Memory, StoragePath::Memory) => true,
             (StoragePath::Custom { .. }, StoragePath::Custom { .. }) => {
                 //Do we really care if they equal?
@@ -173,6 +222,9 @@ pub struct IpfsOptions {
     /// Nodes used as bootstrap peers.
     pub bootstrap: Vec<Multiaddr>,
 
+    /// When the node bootstraps the DHT by itself. See [`BootstrapPolicy`]
+    pub bootstrap_policy: BootstrapPolicy,
+
     #[cfg(feature = "beetle_bitswap")]
     /// Bitswap configuration
     pub bitswap_config: BitswapConfig,
@@ -183,6 +235,10 @@ pub struct IpfsOptions {
     /// Bound listening addresses; by default the node will not listen on any address.
     pub listening_addrs: Vec<Multiaddr>,
 
+    /// Listen again on the requested addresses whose listener expired or failed, with a backoff
+    /// between the attempts, see [`ConnectionEvent::Relistened`].
+    pub relisten: bool,
+
     /// Transport configuration
     pub transport_configuration: crate::p2p::TransportConfig,
 
@@ -192,16 +248,22 @@ pub struct IpfsOptions {
     /// Identify configuration
     pub identify_configuration: crate::p2p::IdentifyConfiguration,
 
+    /// Agent version announced through identify and reported by [`Ipfs::identity`], replacing
+    /// the one of [`IpfsOptions::identify_configuration`] when set.
+    pub agent_version: Option<String>,
+
     /// Pubsub configuration
     pub pubsub_config: crate::p2p::PubsubConfig,
 
     /// Kad configuration
     pub kad_configuration: Either<KadConfig, libp2p::kad::Config>,
 
-    /// Kad Store Config
-    /// Note: Only supports MemoryStoreConfig at this time
+    /// Kad Store Config, keeping the records in memory by default
     pub kad_store_config: KadStoreConfig,
 
+    /// Mode of kademlia on startup, switched by the confirmed external addresses with `Auto`
+    pub dht_mode: DhtMode,
+
     /// Ping Configuration
     pub ping_configuration: PingConfig,
 
@@ -213,9 +275,105 @@ pub struct IpfsOptions {
     /// Connection idle
     pub connection_idle: Duration,
 
-    /// Repo Provider option
+    /// Limits on the established and pending connections, and the number of connected peers kept
+    /// when pruning.
+    pub connection_limits: ConnectionLimits,
+
+    /// Relay client configuration
+    pub relay: RelayClientConfig,
+
+    /// Dial the peers discovered by mdns. They are added to the addressbook either way.
+    pub mdns_auto_dial: bool,
+
+    /// Order in which the providers found for the blocks wanted from bitswap are dialed. Only
+    /// used by the default bitswap implementation.
+    pub provider_ranking: ProviderRanking,
+
+    /// Order in which the responses to the wants of the peers are sent when several are ready at
+    /// once. Only used by the default bitswap implementation.
+    pub bitswap_serve_order: ServeOrder,
+
+    /// Save the bytes exchanged with each peer through bitswap in the datastore, for the ledgers
+    /// to outlive the node. Only used by the default bitswap implementation.
+    pub persist_bitswap_ledgers: bool,
+
+    /// Limit on the records and provider announcements each peer may store on the node through
+    /// the DHT, or `None` to store all of them. Enforcing the limit overrides the store filtering
+    /// of the kad configuration, as the writes are handed over to the node to be stored.
+    pub dht_write_limit: Option<DhtWriteLimit>,
+
+    /// Offer to compress the bitswap messages with zstd, which is used with the peers offering it
+    /// too. Only used by the default bitswap implementation.
+    #[cfg(feature = "bitswap_compression")]
+    pub bitswap_compression: bool,
+
+    /// Bounds of the metadata attached to peers, and whether it is saved with the addressbook
+    pub peer_meta: PeerMetaConfig,
+
+    /// Interface addresses the unspecified listening addresses are reported and advertised as.
+    /// The interfaces are only detected with the `interface_addrs` feature.
+    pub interface_filter: InterfaceFilter,
+
+    /// How long [`Ipfs::connect`] and [`Ipfs::connect_addr`] wait for the dial to complete.
+    pub dial_timeout: Duration,
+
+    /// Number of requests queued for the background task before further requests wait for room
+    /// in the queue, or fail with [`FacadeError::Busy`] for the `try_` variants.
+    pub facade_channel_capacity: usize,
+
+    /// Fail [`Ipfs::connect`] and [`Ipfs::connect_addr`] with [`DialError::AlreadyConnected`]
+    /// when connected to the peer, rather than succeeding.
+    pub fail_if_connected: bool,
+
+    /// Number of the most recent disconnections kept for [`Ipfs::recent_disconnections`]
+    pub disconnection_history: usize,
+
+    /// Rendezvous client configuration
+    pub rendezvous: RendezvousConfig,
+
+    /// Announce the new pins and look for the wanted blocks among the content announced by the
+    /// peers over pubsub. See [`ContentAnnouncerConfig`]
+    pub content_announcer: Option<ContentAnnouncerConfig>,
+
+    /// When the addresses the peers observe us at are advertised as external addresses. See
+    /// [`ObservedAddrConfig`]
+    pub observed_addr_config: ObservedAddrConfig,
+
+    /// Which blocks are provided on the DHT, those stored on startup as well as the ones written
+    /// or pinned afterwards. Providing every chunk of large files is usually undesirable.
     pub provider: RepoProvider,
 
+    /// Maximum total size of the blocks in bytes. Writing a block beyond it fails with
+    /// [`repo::StorageFull`], unless `gc_auto` is set and collecting garbage makes room for it.
+    pub storage_max: Option<u64>,
+
+    /// Cipher the data of the blocks is encrypted with at rest, see
+    /// [`Repo::set_block_cipher`](repo::Repo::set_block_cipher).
+    pub block_cipher: Option<Arc<dyn repo::encryption::BlockCipher>>,
+
+    /// Policy the blocks are checked against before being written, see
+    /// [`Repo::set_block_policy`](repo::Repo::set_block_policy).
+    pub block_policy: Option<Arc<dyn repo::policy::BlockPolicy>>,
+
+    /// Collect garbage when writing a block would exceed `storage_max`.
+    pub gc_auto: bool,
+
+    /// Evict unpinned blocks when writing a block would exceed `storage_max`.
+    pub eviction: Eviction,
+
+    /// Trustless gateways the blocks bitswap did not find within `gateway_fallback_delay` are
+    /// fetched from, all of them being requested at once. The fallback is disabled without any.
+    #[cfg(feature = "gateway_fallback")]
+    pub gateway_fallback: Vec<reqwest::Url>,
+
+    /// Time given to bitswap to find a block before falling back to the gateways.
+    #[cfg(feature = "gateway_fallback")]
+    pub gateway_fallback_delay: Duration,
+
+    /// Persist the blocks wanted over bitswap so that the fetches interrupted by a restart resume,
+    /// see [`WantPersistence`](repo::WantPersistence).
+    pub want_persistence: Option<repo::WantPersistence>,
+
     /// The span for tracing purposes, `None` value is converted to `tracing::trace_span!("ipfs")`.
     ///
     /// All futures returned by `Ipfs`, background task actions and swarm actions are instrumented
@@ -251,13 +409,13 @@ pub enum RepoProvider {
     #[default]
     None,
 
-    /// Provide all blocks stored automatically
+    /// Provide all blocks stored automatically, including every block written
     All,
 
-    /// Provide pinned blocks
+    /// Provide pinned blocks, including the roots of new pins and the blocks they pin indirectly
     Pinned,
 
-    /// Provide root blocks only
+    /// Provide the roots of the direct and recursive pins only
     Roots,
 }
 
@@ -266,17 +424,49 @@ impl Default for IpfsOptions {
         Self {
             ipfs_path: StoragePath::Memory,
             bootstrap: Default::default(),
+            bootstrap_policy: Default::default(),
             #[cfg(feature = "beetle_bitswap")]
             bitswap_config: Default::default(),
             relay_server_config: Default::default(),
             kad_configuration: Either::Left(Default::default()),
             kad_store_config: Default::default(),
+            dht_mode: DhtMode::Auto,
             ping_configuration: Default::default(),
             identify_configuration: Default::default(),
+            agent_version: None,
             addr_config: Default::default(),
             provider: Default::default(),
+            storage_max: None,
+            block_cipher: None,
+            block_policy: None,
+            gc_auto: false,
+            eviction: Eviction::None,
+            #[cfg(feature = "gateway_fallback")]
+            gateway_fallback: vec![],
+            #[cfg(feature = "gateway_fallback")]
+            gateway_fallback_delay: Duration::from_secs(10),
+            want_persistence: None,
+            relisten: false,
             keystore: Keystore::in_memory(),
             connection_idle: Duration::from_secs(30),
+            connection_limits: Default::default(),
+            relay: Default::default(),
+            mdns_auto_dial: false,
+            interface_filter: Default::default(),
+            peer_meta: Default::default(),
+            provider_ranking: Default::default(),
+            #[cfg(feature = "bitswap_compression")]
+            bitswap_compression: false,
+            dht_write_limit: Some(Default::default()),
+            bitswap_serve_order: Default::default(),
+            persist_bitswap_ledgers: false,
+            dial_timeout: Duration::from_secs(60),
+            facade_channel_capacity: 256,
+            fail_if_connected: false,
+            disconnection_history: 128,
+            rendezvous: Default::default(),
+            content_announcer: None,
+            observed_addr_config: Default::default(),
             listening_addrs: vec![],
             transport_configuration: TransportConfig::default(),
             pubsub_config: PubsubConfig::default(),
@@ -287,6 +477,65 @@ impl Default for IpfsOptions {
     }
 }
 
+impl IpfsOptions {
+    /// Whether compressing the bitswap messages is offered, never without the
+    /// `bitswap_compression` feature.
+    pub(crate) fn bitswap_compression(&self) -> bool {
+        #[cfg(feature = "bitswap_compression")]
+        return self.bitswap_compression;
+        #[cfg(not(feature = "bitswap_compression"))]
+        false
+    }
+
+    fn minimal(&mut self) {
+        let protocols = &mut self.protocols;
+        protocols.identify = true;
+        protocols.ping = true;
+        protocols.bitswap = true;
+        protocols.kad = true;
+        self.dht_mode = DhtMode::Client;
+    }
+
+    fn desktop(&mut self) {
+        self.default_protocols();
+        let protocols = &mut self.protocols;
+        protocols.mdns = true;
+        protocols.relay_client = true;
+        protocols.dcutr = true;
+        self.relay = RelayClientConfig {
+            enabled: true,
+            static_relays: vec![],
+            auto: true,
+        };
+        self.connection_limits = ConnectionLimits {
+            high_water: Some(96),
+            low_water: Some(32),
+            ..Default::default()
+        };
+    }
+
+    fn server(&mut self) {
+        self.default_protocols();
+        self.dht_mode = DhtMode::Server;
+        self.connection_limits = ConnectionLimits {
+            high_water: Some(900),
+            low_water: Some(600),
+            ..Default::default()
+        };
+    }
+
+    /// The protocols of [`UninitializedIpfs::with_default`]
+    fn default_protocols(&mut self) {
+        let protocols = &mut self.protocols;
+        protocols.identify = true;
+        protocols.autonat = true;
+        protocols.bitswap = true;
+        protocols.kad = true;
+        protocols.ping = true;
+        protocols.pubsub = true;
+    }
+}
+
 impl fmt::Debug for IpfsOptions {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         // needed since libp2p::identity::Keypair does not have a Debug impl, and the IpfsOptions
@@ -312,11 +561,23 @@ impl fmt::Debug for IpfsOptions {
 pub struct Ipfs {
     span: Span,
     repo: Repo,
+    /// Attached to the repo of another node, which shuts it down
+    shared_repo: bool,
+    gate: GateHandle,
+    bandwidth: BandwidthCounters,
+    #[cfg(feature = "metrics")]
+    metrics: metrics::Metrics,
+    connection_events: tokio::sync::broadcast::Sender<ConnectionEvent>,
+    nat_events: tokio::sync::broadcast::Sender<AutonatStatus>,
     key: Keypair,
     keystore: Keystore,
-    identify_conf: IdentifyConfiguration,
-    to_task: Sender<IpfsEvent>,
+    mfs: Mfs,
+    to_task: FacadeSender,
+    dial_timeout: Duration,
     record_key_validator: HashMap<String, Arc<dyn Fn(&str) -> anyhow::Result<Key> + Sync + Send>>,
+    token: CancellationToken,
+    task: Arc<parking_lot::Mutex<Option<JoinHandle<()>>>>,
+    task_handle: TaskHandle,
     _guard: Arc<DropGuard>,
 }
 
@@ -334,25 +595,68 @@ type ReceiverChannel<T> = oneshot::Receiver<Result<T, Error>>;
 #[allow(clippy::type_complexity)]
 enum IpfsEvent {
     /// Connect
-    Connect(DialOpts, Channel<()>),
+    Connect(DialOpts, OneshotSender<Result<(), DialError>>),
+    /// Connect to exactly the address, returning the peer connected to
+    ConnectAddr(Multiaddr, OneshotSender<Result<PeerId, DialError>>),
+    PendingDials(Channel<Vec<(Option<PeerId>, Vec<Multiaddr>, Instant)>>),
+    RecentDisconnections(Channel<Vec<Disconnection>>),
+    AbortDial(PeerId, Channel<bool>),
     /// Node supported protocol
     Protocol(OneshotSender<Vec<String>>),
+    PeerProtocols(PeerId, Channel<Vec<String>>),
+    LocalIdentity(Channel<PeerInfo>),
     /// Addresses
     Addresses(Channel<Vec<(PeerId, Vec<Multiaddr>)>>),
     /// Local addresses
     Listeners(Channel<Vec<Multiaddr>>),
+    ListenerInfo(Channel<Vec<ListenerInfo>>),
     /// Local addresses
     ExternalAddresses(Channel<Vec<Multiaddr>>),
+    ExternalAddressInfo(Channel<Vec<ExternalAddressInfo>>),
+    AddExternalAddress(Multiaddr, Channel<()>),
+    RemoveExternalAddress(Multiaddr, Channel<()>),
     /// Connected peers
     Connected(Channel<Vec<PeerId>>),
     /// Is Connected
     IsConnected(PeerId, Channel<bool>),
     /// Disconnect
     Disconnect(PeerId, Channel<()>),
-    /// Ban Peer
-    Ban(PeerId, Channel<()>),
+    /// Ban Peer, until unbanned if no duration is given
+    Ban(PeerId, Option<Duration>, Channel<()>),
     /// Unban peer
     Unban(PeerId, Channel<()>),
+    /// Ban address, until unbanned if no duration is given
+    BanAddress(Multiaddr, Option<Duration>, Channel<()>),
+    /// Unban address
+    UnbanAddress(Multiaddr, Channel<()>),
+    /// Active bans
+    Bans(Channel<Vec<Ban>>),
+    /// Whitelist peer, never pruning its connections
+    WhitelistPeer(PeerId, Channel<()>),
+    /// Remove peer from the whitelist
+    RemoveWhitelistedPeer(PeerId, Channel<bool>),
+    /// Identified peers
+    PeersInfo(Channel<Vec<PeerInfo>>),
+    /// Our addresses as observed by peers
+    ObservedAddresses(Channel<Vec<(Multiaddr, usize)>>),
+    /// Subscribe to the ping round-trip times of a connected peer
+    Ping(
+        PeerId,
+        Option<usize>,
+        Channel<UnboundedReceiver<Result<Duration, Error>>>,
+    ),
+    /// Protect peer with a tag
+    ProtectPeer(PeerId, String, Channel<()>),
+    /// Remove a tag protecting the peer
+    UnprotectPeer(PeerId, String, Channel<bool>),
+    /// Set the metadata of a peer under a key
+    PeerSetMeta(PeerId, String, Vec<u8>, Channel<Option<Vec<u8>>>),
+    PeerGetMeta(PeerId, String, Channel<Option<Vec<u8>>>),
+    PeerDelMeta(PeerId, String, Channel<Option<Vec<u8>>>),
+    /// Protected peers and their reconnection state
+    PeerProtectionStatus(Channel<Vec<PeerProtectionStatus>>),
+    /// Prune connections above the high-water mark
+    PruneConnections(Channel<()>),
     PubsubSubscribe(String, Channel<Option<SubscriptionStream>>),
     PubsubUnsubscribe(String, Channel<Result<bool, Error>>),
     PubsubPublish(String, Bytes, Channel<Result<MessageId, PublishError>>),
@@ -360,21 +664,42 @@ enum IpfsEvent {
     GetBitswapPeers(Channel<BoxFuture<'static, Vec<PeerId>>>),
     WantList(Option<PeerId>, Channel<BoxFuture<'static, Vec<Cid>>>),
     PubsubSubscribed(Channel<Vec<String>>),
-    AddListeningAddress(Multiaddr, Channel<Multiaddr>),
+    PubsubMesh(String, Channel<MeshInfo>),
+    PubsubMeshEvents(Channel<futures::channel::mpsc::UnboundedReceiver<MeshEvent>>),
+    AnnouncedProviders(Cid, Channel<Vec<PeerId>>),
+    AddListeningAddress(Multiaddr, Channel<Vec<Multiaddr>>),
     RemoveListeningAddress(Multiaddr, Channel<()>),
     Bootstrap(Channel<ReceiverChannel<KadResult>>),
+    /// Bootstrap, reporting the bootstrap nodes connected to once complete
+    BootstrapReport(Channel<ReceiverChannel<BootstrapReport>>),
     AddPeer(PeerId, Multiaddr, Channel<()>),
     RemovePeer(PeerId, Option<Multiaddr>, Channel<bool>),
     GetClosestPeers(PeerId, Channel<ReceiverChannel<KadResult>>),
-    FindPeerIdentity(PeerId, Channel<ReceiverChannel<libp2p::identify::Info>>),
+    FindPeerIdentity(PeerId, Channel<ReceiverChannel<PeerInfo>>),
     FindPeer(
         PeerId,
         bool,
         Channel<Either<Vec<Multiaddr>, ReceiverChannel<KadResult>>>,
     ),
-    GetProviders(Cid, Channel<Option<BoxStream<'static, PeerId>>>),
+    GetProviders(
+        Cid,
+        Option<Duration>,
+        Channel<Option<BoxStream<'static, PeerId>>>,
+    ),
+    DhtQueries(Channel<usize>),
     Provide(Cid, Channel<ReceiverChannel<KadResult>>),
+    /// Start providing without waiting for the query
+    ProvideLazy(Cid, Channel<()>),
+    Provided(Channel<Vec<Cid>>),
     DhtMode(DhtMode, Channel<()>),
+    DhtStats(Channel<DhtStats>),
+    DhtStore(Channel<DhtStoreContents>),
+    DhtEvents(Channel<tokio::sync::broadcast::Receiver<DhtEvent>>),
+    WaitReady(ReadyCriteria, Channel<ReadyState>),
+    EnabledComponents(Channel<Components>),
+    ReadyState(Channel<ReadyState>),
+    BitswapLedger(PeerId, Channel<PeerLedger>),
+    BitswapLedgerReset(PeerId, Channel<()>),
     DhtGet(Key, Channel<BoxStream<'static, Record>>),
     DhtPut(Key, Vec<u8>, Quorum, Channel<ReceiverChannel<KadResult>>),
     GetBootstrappers(OneshotSender<Vec<Multiaddr>>),
@@ -382,6 +707,8 @@ enum IpfsEvent {
     RemoveBootstrapper(Multiaddr, Channel<Multiaddr>),
     ClearBootstrappers(Channel<Vec<Multiaddr>>),
     DefaultBootstrap(Channel<Vec<Multiaddr>>),
+    BootstrapPolicy(Channel<BootstrapPolicy>),
+    SetBootstrapPolicy(BootstrapPolicy, Channel<()>),
 
     AddRelay(PeerId, Multiaddr, Channel<()>),
     RemoveRelay(PeerId, Multiaddr, Channel<()>),
@@ -389,6 +716,12 @@ enum IpfsEvent {
     DisableRelay(PeerId, Channel<()>),
     ListRelays(Channel<Vec<(PeerId, Vec<Multiaddr>)>>),
     ListActiveRelays(Channel<Vec<(PeerId, Vec<Multiaddr>)>>),
+    RelayStatus(Channel<RelayStatus>),
+    RelayServerStats(Channel<RelayServerStats>),
+    NatStatus(Channel<AutonatStatus>),
+    MdnsPeers(Channel<Vec<(PeerId, Vec<Multiaddr>)>>),
+    SetMdnsEnabled(bool, Channel<()>),
+    AddAutonatServer(PeerId, Option<Multiaddr>, Channel<()>),
     //event streams
     PubsubEventStream(OneshotSender<UnboundedReceiver<InnerPubsubEvent>>),
 
@@ -401,14 +734,26 @@ enum IpfsEvent {
         PeerId,
         Channel<HashMap<PeerId, Vec<Multiaddr>>>,
     ),
+    RendezvousRegister(Namespace, Channel<Vec<ReceiverChannel<()>>>),
+    RendezvousDiscover(
+        Namespace,
+        Channel<Vec<ReceiverChannel<HashMap<PeerId, Vec<Multiaddr>>>>>,
+    ),
     #[cfg(feature = "experimental_stream")]
     StreamControlHandle(Channel<libp2p_stream::Control>),
     #[cfg(feature = "experimental_stream")]
     NewStream(StreamProtocol, Channel<libp2p_stream::IncomingStreams>),
     Exit,
+    Shutdown(Duration, Channel<()>),
+    /// An observer of the swarm events, type erased as `Ipfs` does not know the custom behaviour
+    AddSwarmObserver(Box<dyn Any + Send>, Channel<()>),
+    /// A sender of the custom behaviour events, type erased as for the observers
+    CustomEvents(Box<dyn Any + Send>, Channel<()>),
+    /// A closure ran with the custom behaviour, type erased as for the observers
+    WithCustomBehaviour(Box<dyn Any + Send>, Channel<()>),
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum DhtMode {
     Auto,
     Client,
@@ -434,6 +779,174 @@ pub enum PubsubEvent {
     Unsubscribe { peer_id: PeerId },
 }
 
+/// Connection events of the node, see [`Ipfs::connection_events`].
+#[derive(Debug, Clone)]
+pub enum ConnectionEvent {
+    /// A connection with the peer was established
+    Connected {
+        peer: PeerId,
+        addr: Multiaddr,
+        direction: Endpoint,
+    },
+
+    /// A connection with the peer was closed, `cause` being `None` when closed gracefully
+    Disconnected {
+        peer: PeerId,
+        remaining_connections: u32,
+        cause: Option<String>,
+    },
+
+    /// Dialing failed, `addr` being the first address attempted if any
+    DialFailure {
+        peer: Option<PeerId>,
+        addr: Option<Multiaddr>,
+        error: String,
+    },
+
+    /// The relay server opened a circuit from `src` to `dst`
+    RelayCircuitOpened { src: PeerId, dst: PeerId },
+
+    /// A relayed circuit was closed, `error` being set if it failed or exceeded the limits of
+    /// the [`RelayConfig`]
+    RelayCircuitClosed {
+        src: PeerId,
+        dst: PeerId,
+        error: Option<String>,
+    },
+
+    /// The relay server denied a circuit, e.g. above the limits or without a reservation of `dst`
+    RelayCircuitDenied { src: PeerId, dst: PeerId },
+
+    /// The peer does not speak a protocol the node uses with it, such as bitswap failing to be
+    /// negotiated, or kad missing from the protocols the peer identified with
+    ProtocolUnsupported { peer: PeerId, protocol: String },
+
+    /// The listening address expired, e.g. as its interface went away
+    ListenAddrExpired { address: Multiaddr },
+
+    /// Listening again on the requested address succeeded, see [`IpfsOptions::relisten`]
+    Relistened { address: Multiaddr },
+
+    /// Listening again on the requested address failed, `attempt` times so far; it is attempted
+    /// again after a backoff
+    RelistenFailed {
+        address: Multiaddr,
+        attempt: u32,
+        error: String,
+    },
+}
+
+/// Why a connection was closed, see [`Disconnection`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisconnectCause {
+    /// Closed with [`Ipfs::disconnect`]
+    Requested,
+    /// The peer was banned
+    Banned,
+    /// Closed to bring the connections back to the low water mark of the limits
+    Pruned,
+    /// Closed once none of the protocols used the connection anymore
+    Idle,
+    /// Closed by the peer
+    RemoteClosed,
+    /// The connection failed after the last ping over it timed out
+    PingTimeout,
+    /// The connection failed with the error
+    Transport(String),
+}
+
+/// A closed connection, see [`Ipfs::recent_disconnections`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disconnection {
+    pub peer: PeerId,
+    /// Remote address of the connection
+    pub addr: Multiaddr,
+    pub when: SystemTime,
+    pub cause: DisconnectCause,
+}
+
+/// Connection events kept for a subscriber which has not received them yet.
+const CONNECTION_EVENTS_CAPACITY: usize = 256;
+
+/// Changes of the kademlia routing table, see [`Ipfs::dht_events`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DhtEvent {
+    /// The peer was added to the routing table, in the bucket of the given index
+    PeerAdded {
+        peer: PeerId,
+        addresses: Vec<Multiaddr>,
+        bucket: u32,
+    },
+
+    /// The peer was evicted from the routing table to make room for another peer
+    PeerEvicted { peer: PeerId },
+
+    /// The peer connected but none of its addresses is known, so it cannot be added
+    UnroutablePeer { peer: PeerId },
+
+    /// The peer connected and can be added to the routing table with the address, which is not
+    /// done automatically per the kad configuration
+    RoutablePeer { peer: PeerId, address: Multiaddr },
+}
+
+/// Routing table changes kept for a subscriber which has not received them yet.
+const DHT_EVENTS_CAPACITY: usize = 256;
+
+/// The protocols enabled on the node, see [`Ipfs::enabled_components`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Components {
+    pub kad: bool,
+    pub mdns: bool,
+    pub bitswap: bool,
+    pub autonat: bool,
+    pub relay_client: bool,
+    pub relay_server: bool,
+    pub pubsub: bool,
+    pub upnp: bool,
+}
+
+/// Thresholds the node has to reach for [`Ipfs::wait_ready`] to return.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadyCriteria {
+    /// Listeners with at least one listening address
+    pub listeners: usize,
+    pub connected_peers: usize,
+    /// Peers in the kademlia routing table
+    pub dht_routing_peers: usize,
+    /// How long to wait before failing with [`NotReady`](error::NotReady)
+    pub timeout: Duration,
+}
+
+impl Default for ReadyCriteria {
+    fn default() -> Self {
+        Self {
+            listeners: 1,
+            connected_peers: 0,
+            dht_routing_peers: 0,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// The counters compared to the [`ReadyCriteria`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadyState {
+    pub listeners: usize,
+    pub connected_peers: usize,
+    pub dht_routing_peers: usize,
+}
+
+impl ReadyState {
+    pub(crate) fn meets(&self, criteria: &ReadyCriteria) -> bool {
+        self.listeners >= criteria.listeners
+            && self.connected_peers >= criteria.connected_peers
+            && self.dht_routing_peers >= criteria.dht_routing_peers
+    }
+}
+
+/// Transitions of the NAT status kept for a subscriber which has not received them yet.
+const NAT_EVENTS_CAPACITY: usize = 16;
+
 #[derive(Debug, Clone)]
 pub(crate) enum InnerPubsubEvent {
     /// Subscription event to a given topic
@@ -454,6 +967,9 @@ impl From<InnerPubsubEvent> for PubsubEvent {
 
 type TSwarmEvent<C> = <TSwarm<C> as Stream>::Item;
 type TSwarmEventFn<C> = Arc<dyn Fn(&mut TSwarm<C>, &TSwarmEvent<C>) + Sync + Send>;
+type TSwarmObserverFn<C> = Arc<dyn Fn(&TSwarmEvent<C>) + Sync + Send>;
+type TCustomBehaviourFn<C> = Box<dyn FnOnce(&mut C) + Send>;
+type TIntervalFn = Arc<dyn Fn(Duration) -> BoxStream<'static, ()> + Sync + Send>;
 type TTransportFn = Box<
     dyn Fn(
             &Keypair,
@@ -472,48 +988,97 @@ pub enum FDLimit {
 
 /// Configured Ipfs which can only be started.
 #[allow(clippy::type_complexity)]
-pub struct UninitializedIpfs<C: NetworkBehaviour<ToSwarm = void::Void> + Send> {
+pub struct UninitializedIpfs<C: NetworkBehaviour + Send>
+where
+    <C as NetworkBehaviour>::ToSwarm: fmt::Debug,
+{
     keys: Option<Keypair>,
+    keypair_path: Option<PathBuf>,
     options: IpfsOptions,
     fdlimit: Option<FDLimit>,
     repo_handle: Option<Repo>,
+    /// Attached to the repo of a started node, see [`UninitializedIpfs::attach_repo`]
+    shared_repo: bool,
     local_external_addr: bool,
     swarm_event: Option<TSwarmEventFn<C>>,
+    swarm_observers: Vec<TSwarmObserverFn<C>>,
     // record_validators: HashMap<String, Arc<dyn Fn(&str, &Record) -> bool + Sync + Send>>,
     record_key_validator: HashMap<String, Arc<dyn Fn(&str) -> anyhow::Result<Key> + Sync + Send>>,
     custom_behaviour: Option<C>,
     custom_transport: Option<TTransportFn>,
+    interval_factory: Option<TIntervalFn>,
     gc_config: Option<GCConfig>,
     gc_repo_duration: Option<Duration>,
+    offline: bool,
+    connection_gate: Option<Box<dyn ConnectionGate>>,
 }
 
 pub type UninitializedIpfsNoop = UninitializedIpfs<libp2p::swarm::dummy::Behaviour>;
 
-impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> Default for UninitializedIpfs<C> {
+impl<C: NetworkBehaviour + Send> Default for UninitializedIpfs<C>
+where
+    <C as NetworkBehaviour>::ToSwarm: fmt::Debug,
+{
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
+impl<C: NetworkBehaviour + Send> UninitializedIpfs<C>
+where
+    <C as NetworkBehaviour>::ToSwarm: fmt::Debug,
+{
     /// New uninitualized instance
     pub fn new() -> Self {
         UninitializedIpfs {
             keys: None,
+            keypair_path: None,
             options: Default::default(),
             fdlimit: None,
             repo_handle: None,
+            shared_repo: false,
             // record_validators: Default::default(),
             record_key_validator: Default::default(),
             local_external_addr: false,
             swarm_event: None,
+            swarm_observers: Vec::new(),
             custom_behaviour: None,
             custom_transport: None,
+            interval_factory: None,
             gc_config: None,
             gc_repo_duration: None,
+            offline: false,
+            connection_gate: None,
         }
     }
 
+    /// Node fetching and serving blocks only, with bitswap, identify, ping and kademlia as a
+    /// client. Mdns, the relays and autonat are disabled. Subsequent builder calls override the
+    /// preset.
+    pub fn minimal() -> Self {
+        let mut uninit = Self::new();
+        uninit.options.minimal();
+        uninit
+    }
+
+    /// Node behind a NAT in a local network: the default behaviours with mdns, and the relay
+    /// client requesting a reservation automatically once autonat reports the node as private.
+    /// Subsequent builder calls override the preset.
+    pub fn desktop() -> Self {
+        let mut uninit = Self::new();
+        uninit.options.desktop();
+        uninit
+    }
+
+    /// Publicly reachable node: the default behaviours with kademlia as a server and higher
+    /// connection limits. The relay server is left to [`UninitializedIpfs::with_relay_server`].
+    /// Subsequent builder calls override the preset.
+    pub fn server() -> Self {
+        let mut uninit = Self::new();
+        uninit.options.server();
+        uninit
+    }
+
     /// New uninitualized instance without any listener addresses
     #[deprecated(
         note = "UninitializedIpfs::empty will be removed in the future. Use UninitializedIpfs::new()"
@@ -565,6 +1130,13 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
+    /// Listens again on the requested addresses whose listener expired or failed, see
+    /// [`IpfsOptions::relisten`].
+    pub fn enable_relisten(mut self) -> Self {
+        self.options.relisten = true;
+        self
+    }
+
     /// Adds a bootstrap node
     pub fn add_bootstrap(mut self, addr: Multiaddr) -> Self {
         if !self.options.bootstrap.contains(&addr) {
@@ -641,6 +1213,49 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
+    /// Dial the peers discovered by mdns instead of only adding them to the addressbook
+    pub fn set_mdns_auto_dial(mut self, auto_dial: bool) -> Self {
+        self.options.mdns_auto_dial = auto_dial;
+        self
+    }
+
+    /// Set which interface addresses the unspecified listening addresses are expanded into
+    pub fn set_interface_filter(mut self, filter: InterfaceFilter) -> Self {
+        self.options.interface_filter = filter;
+        self
+    }
+
+    /// Set the agent version announced to other peers through identify
+    pub fn set_agent_version(mut self, agent_version: impl Into<String>) -> Self {
+        self.options.agent_version = Some(agent_version.into());
+        self
+    }
+
+    /// Set how long connecting to a peer waits for the dial to complete
+    pub fn set_dial_timeout(mut self, timeout: Duration) -> Self {
+        self.options.dial_timeout = timeout;
+        self
+    }
+
+    /// Set the number of requests queued for the background task
+    pub fn set_facade_channel_capacity(mut self, capacity: usize) -> Self {
+        self.options.facade_channel_capacity = capacity;
+        self
+    }
+
+    /// Fail connecting to a peer which is already connected with
+    /// [`DialError::AlreadyConnected`] rather than succeeding
+    pub fn set_fail_if_connected(mut self, fail: bool) -> Self {
+        self.options.fail_if_connected = fail;
+        self
+    }
+
+    /// Set the number of the most recent disconnections kept, none with zero
+    pub fn set_disconnection_history(mut self, len: usize) -> Self {
+        self.options.disconnection_history = len;
+        self
+    }
+
     /// Enable mdns
     pub fn with_mdns(mut self) -> Self {
         self.options.protocols.mdns = true;
@@ -654,7 +1269,8 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
-    /// Enable relay server
+    /// Enable relay server, relaying circuits for peers with a reservation within the limits of
+    /// the config. Circuits exceeding their duration or bytes are closed.
     pub fn with_relay_server(mut self, config: RelayConfig) -> Self {
         self.options.protocols.relay_server = true;
         self.options.relay_server_config = config;
@@ -679,6 +1295,14 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
+    /// Set the rendezvous client configuration, enabling the rendezvous client. See
+    /// [`RendezvousConfig`]
+    pub fn set_rendezvous_configuration(mut self, config: RendezvousConfig) -> Self {
+        self.options.protocols.rendezvous_client = true;
+        self.options.rendezvous = config;
+        self
+    }
+
     /// Enables identify
     pub fn with_identify(mut self, config: crate::p2p::IdentifyConfiguration) -> Self {
         self.options.protocols.identify = true;
@@ -699,6 +1323,14 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
+    /// Enables the content announcer, and pubsub it is announced over. See
+    /// [`ContentAnnouncerConfig`]
+    pub fn with_content_announcer(mut self, config: ContentAnnouncerConfig) -> Self {
+        self.options.protocols.pubsub = true;
+        self.options.content_announcer = Some(config);
+        self
+    }
+
     /// Enables autonat
     pub fn with_autonat(mut self) -> Self {
         self.options.protocols.autonat = true;
@@ -712,7 +1344,9 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
-    /// Set a custom behaviour
+    /// Set a custom behaviour, e.g. a request/response protocol of the application, running on
+    /// the swarm of the node. Its events are received with [`Ipfs::custom_events`] and it is
+    /// driven with [`Ipfs::with_custom_behaviour`].
     pub fn with_custom_behaviour(mut self, behaviour: C) -> Self {
         self.custom_behaviour = Some(behaviour);
         self
@@ -724,13 +1358,85 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
-    /// Set a duration for which blocks are not removed due to the garbage collector
-    /// Defaults: 2 mins
+    /// Set a duration for which newly written blocks are not removed due to the garbage collector,
+    /// giving time to pin them after they have been put.
+    /// Defaults: blocks are not spared.
     pub fn set_temp_pin_duration(mut self, duration: Duration) -> Self {
         self.gc_repo_duration = Some(duration);
         self
     }
 
+    /// Encrypts the data of the blocks at rest with XChaCha20-Poly1305 and the key
+    #[cfg(feature = "block_encryption")]
+    pub fn set_encryption_key(mut self, key: [u8; 32]) -> Self {
+        let cipher = repo::encryption::XChaCha20Poly1305Cipher::new(key);
+        self.options.block_cipher = Some(Arc::new(cipher));
+        self
+    }
+
+    /// Encrypts the data of the blocks at rest with the cipher
+    pub fn set_block_cipher(mut self, cipher: impl repo::encryption::BlockCipher) -> Self {
+        self.options.block_cipher = Some(Arc::new(cipher));
+        self
+    }
+
+    /// Checks the blocks against the policy before writing them, see [`IpfsOptions::block_policy`].
+    pub fn set_block_policy(mut self, policy: impl repo::policy::BlockPolicy) -> Self {
+        self.options.block_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Limits the total size of the blocks in bytes, see [`IpfsOptions::storage_max`].
+    pub fn set_storage_max(mut self, max: u64) -> Self {
+        self.options.storage_max = Some(max);
+        self
+    }
+
+    /// Collects garbage when writing a block would exceed the storage limit, instead of failing
+    /// right away.
+    pub fn with_gc_auto(mut self) -> Self {
+        self.options.gc_auto = true;
+        self
+    }
+
+    /// Evicts unpinned blocks when writing a block would exceed the storage limit, e.g.
+    /// [`Eviction::Lru`] to use the repo as a bounded cache.
+    pub fn set_eviction(mut self, eviction: Eviction) -> Self {
+        self.options.eviction = eviction;
+        self
+    }
+
+    /// Fetches the blocks bitswap did not find within the delay from the trustless gateways,
+    /// requesting all of them at once
+    #[cfg(feature = "gateway_fallback")]
+    pub fn set_gateway_fallback(mut self, gateways: Vec<reqwest::Url>, delay: Duration) -> Self {
+        self.options.gateway_fallback = gateways;
+        self.options.gateway_fallback_delay = delay;
+        self
+    }
+
+    /// Persists the blocks wanted over bitswap, see [`IpfsOptions::want_persistence`].
+    pub fn set_want_persistence(mut self, config: repo::WantPersistence) -> Self {
+        self.options.want_persistence = Some(config);
+        self
+    }
+
+    /// Starts the node without dialing any peer: bootstrap nodes are ignored, protocols which
+    /// discover or dial peers are disabled, every dial is refused by the connection gate, and
+    /// blocks are only read from the local repo, failing with [`repo::BlockNotLocal`] when
+    /// missing. All local repo operations keep working.
+    pub fn set_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Set a [`ConnectionGate`] deciding which connections are established, in addition to the
+    /// rules updated at runtime with [`Ipfs::gate`].
+    pub fn with_connection_gate<G: ConnectionGate>(mut self, gate: G) -> Self {
+        self.connection_gate = Some(Box::new(gate));
+        self
+    }
+
     /// Sets a path
     pub fn set_path<P: AsRef<Path>>(mut self, path: P) -> Self {
         let path = path.as_ref().to_path_buf();
@@ -750,6 +1456,35 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
+    /// Set the mode of kademlia on startup, see [`Ipfs::dht_mode`] to switch it afterwards
+    pub fn set_dht_mode(mut self, mode: DhtMode) -> Self {
+        self.options.dht_mode = mode;
+        self
+    }
+
+    /// Set limits on the connections, see [`ConnectionLimits`]
+    pub fn set_connection_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.options.connection_limits = limits;
+        self
+    }
+
+    /// Set when the addresses observed by the peers are advertised. See [`ObservedAddrConfig`]
+    pub fn set_observed_addr_config(mut self, config: ObservedAddrConfig) -> Self {
+        self.options.observed_addr_config = config;
+        self
+    }
+
+    /// Set the relay client configuration, enabling the relay client and dcutr if `enabled` is
+    /// set. See [`RelayClientConfig`]
+    pub fn set_relay_configuration(mut self, config: RelayClientConfig) -> Self {
+        if config.enabled {
+            self.options.protocols.relay_client = true;
+            self.options.protocols.dcutr = true;
+        }
+        self.options.relay = config;
+        self
+    }
+
     /// Set swarm configuration
     pub fn set_swarm_configuration(mut self, config: crate::p2p::SwarmConfig) -> Self {
         self.options.swarm_configuration = config;
@@ -782,24 +1517,94 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
+    /// Set the order in which the providers of wanted blocks are dialed
+    pub fn set_provider_ranking(mut self, ranking: ProviderRanking) -> Self {
+        self.options.provider_ranking = ranking;
+        self
+    }
+
+    /// Set the order in which the responses ready at once are sent to the peers
+    pub fn set_bitswap_serve_order(mut self, order: ServeOrder) -> Self {
+        self.options.bitswap_serve_order = order;
+        self
+    }
+
+    /// Save the bitswap ledgers of the peers in the datastore
+    pub fn persist_bitswap_ledgers(mut self) -> Self {
+        self.options.persist_bitswap_ledgers = true;
+        self
+    }
+
+    /// Set when the node bootstraps the DHT by itself. See [`BootstrapPolicy`]
+    pub fn set_bootstrap_policy(mut self, policy: BootstrapPolicy) -> Self {
+        self.options.bootstrap_policy = policy;
+        self
+    }
+
+    /// Set the limit on the DHT writes of each peer, or `None` to store all of them
+    pub fn set_dht_write_limit(mut self, limit: impl Into<Option<DhtWriteLimit>>) -> Self {
+        self.options.dht_write_limit = limit.into();
+        self
+    }
+
+    /// Offer to compress bitswap messages with the peers supporting it
+    #[cfg(feature = "bitswap_compression")]
+    pub fn enable_bitswap_compression(mut self) -> Self {
+        self.options.bitswap_compression = true;
+        self
+    }
+
+    /// Set the bounds of the metadata attached to peers, and whether it is persisted
+    pub fn set_peer_meta_configuration(mut self, config: PeerMetaConfig) -> Self {
+        self.options.peer_meta = config;
+        self
+    }
+
     /// Set RepoProvider option to provide blocks automatically
     pub fn set_provider(mut self, opt: RepoProvider) -> Self {
         self.options.provider = opt;
         self
     }
 
-    /// Set keypair
+    /// Set keypair, which can be an Ed25519, Secp256k1, Ecdsa or RSA one
     pub fn set_keypair(mut self, keypair: &Keypair) -> Self {
         self.keys = Some(keypair.clone());
         self
     }
 
+    /// Load the keypair from the protobuf encoded private key in the file when the node is
+    /// started, e.g. one exported from kubo. If the file does not exist, the keypair set with
+    /// [`UninitializedIpfs::set_keypair`], or a generated Ed25519 one, is written to it, only
+    /// readable by the owner.
+    ///
+    /// Note: RSA keypairs can be loaded but not written.
+    pub fn load_or_create_keypair(mut self, path: impl AsRef<Path>) -> Self {
+        self.keypair_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
     /// Set block and data repo
     pub fn set_repo(mut self, repo: &Repo) -> Self {
         self.repo_handle = Some(repo.clone());
         self
     }
 
+    /// Attaches the node to the repo of a started node, e.g. for several identities serving the
+    /// blocks of a single blockstore without duplicating them.
+    ///
+    /// The attached node serves the blocks of the repo over bitswap, sending the blocks written to
+    /// the repo to its peers wanting them, but the blocks wanted through it are fetched, and the
+    /// blocks written to it provided, by the node the repo was started with. The repo keeps the
+    /// configuration of that node: starting the attached node fails if it is given a block
+    /// cipher, a block policy, a storage limit, eviction, garbage collection, a provider option,
+    /// persisted wants or gateways. The attached node stops once the repo is shut down with that
+    /// node, and leaves the repo running when it stops.
+    pub fn attach_repo(mut self, repo: &Repo) -> Self {
+        self.repo_handle = Some(repo.clone());
+        self.shared_repo = true;
+        self
+    }
+
     /// Set a keystore
     pub fn set_keystore(mut self, keystore: &Keystore) -> Self {
         self.options.keystore = keystore.clone();
@@ -818,6 +1623,17 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
+    /// Set the factory of the periodic timers of the background task, such as the connection
+    /// pruning or the bitswap session cleanup, given their period. Defaults to tokio intervals;
+    /// see [`testing::ManualIntervals`] to drive them by hand.
+    pub fn with_interval_factory<F>(mut self, factory: F) -> Self
+    where
+        F: Fn(Duration) -> BoxStream<'static, ()> + Sync + Send + 'static,
+    {
+        self.interval_factory = Some(Arc::new(factory));
+        self
+    }
+
     /// Set file desc limit
     pub fn fd_limit(mut self, limit: FDLimit) -> Self {
         self.fdlimit = Some(limit);
@@ -830,7 +1646,8 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
-    /// Handle libp2p swarm events
+    /// Handle libp2p swarm events, with mutable access to the swarm. Only one handler is kept;
+    /// see [`UninitializedIpfs::register_swarm_observer`] for observing the events.
     pub fn swarm_events<F>(mut self, func: F) -> Self
     where
         F: Fn(&mut TSwarm<C>, &TSwarmEvent<C>) + Sync + Send + 'static,
@@ -839,23 +1656,43 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
+    /// Observe libp2p swarm events. Every observer is called for every event before the event is
+    /// handled; an observer which panics is removed.
+    pub fn register_swarm_observer<F>(mut self, func: F) -> Self
+    where
+        F: Fn(&TSwarmEvent<C>) + Sync + Send + 'static,
+    {
+        self.swarm_observers.push(Arc::new(func));
+        self
+    }
+
     /// Initialize the ipfs node. The returned `Ipfs` value is cloneable, send and sync.
     pub async fn start(self) -> Result<Ipfs, Error> {
         let UninitializedIpfs {
             keys,
+            keypair_path,
             fdlimit,
             mut options,
             swarm_event,
+            swarm_observers,
             custom_behaviour,
             custom_transport,
+            interval_factory,
             record_key_validator,
             local_external_addr,
             repo_handle,
+            shared_repo,
             gc_config,
+            gc_repo_duration,
+            offline,
+            connection_gate,
             ..
         } = self;
 
-        let keys = keys.unwrap_or(Keypair::generate_ed25519());
+        let keys = match keypair_path {
+            Some(path) => keystore::load_or_create_keypair(&path, keys)?,
+            None => keys.unwrap_or(Keypair::generate_ed25519()),
+        };
 
         let root_span = Option::take(&mut options.span)
             // not sure what would be the best practice with tracing and spans
@@ -874,7 +1711,57 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         // instruments the IpfsFuture, the background task.
         let swarm_span = tracing::trace_span!(parent: &root_span, "swarm");
 
+        let addressbook_path = match &options.ipfs_path {
+            _ if !options.addr_config.persist => None,
+            StoragePath::Disk(path) | StoragePath::Flatfs { path, .. } => Some(path),
+            #[cfg(feature = "redb_data_store")]
+            StoragePath::Redb(path) => Some(path),
+            _ => None,
+        }
+        .map(|path| path.join("addressbook.json"));
+
+        if shared_repo {
+            let mut conflicting = vec![];
+            if options.block_cipher.is_some() {
+                conflicting.push("block cipher");
+            }
+            if options.block_policy.is_some() {
+                conflicting.push("block policy");
+            }
+            if options.storage_max.is_some() {
+                conflicting.push("storage limit");
+            }
+            if options.eviction != Eviction::None {
+                conflicting.push("eviction");
+            }
+            if options.gc_auto || gc_config.is_some() || gc_repo_duration.is_some() {
+                conflicting.push("garbage collection");
+            }
+            if options.provider != RepoProvider::None {
+                conflicting.push("provider");
+            }
+            if options.want_persistence.is_some() {
+                conflicting.push("want persistence");
+            }
+            #[cfg(feature = "gateway_fallback")]
+            if !options.gateway_fallback.is_empty() {
+                conflicting.push("gateway fallback");
+            }
+            if !conflicting.is_empty() {
+                anyhow::bail!(
+                    "The {} of the repo cannot be set on a node attached to it",
+                    conflicting.join(", ")
+                );
+            }
+        }
+
         let repo = match repo_handle {
+            Some(repo) if shared_repo => {
+                if !repo.is_online() {
+                    anyhow::bail!("Repo is not started by a node to attach to");
+                }
+                repo
+            }
             Some(repo) => {
                 if repo.is_online() {
                     anyhow::bail!("Repo is already initialized");
@@ -882,7 +1769,13 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
                 repo
             }
             None => {
-                if let StoragePath::Disk(path) = &options.ipfs_path {
+                let path = match &options.ipfs_path {
+                    StoragePath::Disk(path) | StoragePath::Flatfs { path, .. } => Some(path),
+                    #[cfg(feature = "redb_data_store")]
+                    StoragePath::Redb(path) => Some(path),
+                    _ => None,
+                };
+                if let Some(path) = path {
                     if !path.is_dir() {
                         tokio::fs::create_dir_all(path).await?;
                     }
@@ -891,9 +1784,52 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
             }
         };
 
-        repo.init().instrument(init_span.clone()).await?;
+        // the repo is configured by the node it was started with
+        if !shared_repo {
+            if let Some(cipher) = options.block_cipher.take() {
+                repo.set_block_cipher(Some(cipher));
+            }
+
+            if let Some(policy) = options.block_policy.take() {
+                repo.set_block_policy(Some(policy));
+            }
+
+            repo.init().instrument(init_span.clone()).await?;
+
+            if let Some(duration) = gc_repo_duration {
+                repo.set_gc_grace_period(duration);
+            }
+
+            if let Some(max) = options.storage_max {
+                repo.set_max_storage_size(max as usize);
+            }
+            repo.set_gc_auto(options.gc_auto);
+            repo.set_eviction(options.eviction);
+            repo.set_local_only(offline);
+            repo.set_provider(options.provider);
+            repo.set_bitswap_enabled(options.protocols.bitswap);
+            #[cfg(feature = "gateway_fallback")]
+            repo.set_gateway_fallback(
+                std::mem::take(&mut options.gateway_fallback),
+                options.gateway_fallback_delay,
+            );
+        }
+
+        if offline {
+            options.bootstrap.clear();
+            let protocols = &mut options.protocols;
+            protocols.kad = false;
+            protocols.mdns = false;
+            protocols.relay_client = false;
+            protocols.dcutr = false;
+            protocols.autonat = false;
+            protocols.rendezvous_client = false;
+        }
 
-        let repo_events = repo.initialize_channel();
+        let repo_events = match shared_repo {
+            true => repo.attach_channel(),
+            false => repo.initialize_channel(),
+        };
 
         if let Some(limit) = fdlimit {
             #[cfg(unix)]
@@ -920,28 +1856,54 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         let token = CancellationToken::new();
         let _guard = Arc::new(token.clone().drop_guard());
 
-        let (to_task, receiver) = channel::<IpfsEvent>(1);
-        let id_conf = options.identify_configuration.clone();
+        let (to_task, receiver, task_handle) = facade::channel(options.facade_channel_capacity);
+        if let Some(agent_version) = options.agent_version.take() {
+            options.identify_configuration.agent_version = agent_version;
+        }
+        let identify_conf = options.identify_configuration.clone();
 
         let keystore = options.keystore.clone();
 
+        let mfs = Mfs::new(repo.clone());
+
         let ipfs = Ipfs {
             span: facade_span,
             repo,
-            identify_conf: id_conf,
+            shared_repo,
+            gate: GateHandle::new(connection_gate),
+            bandwidth: Default::default(),
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
+            connection_events: tokio::sync::broadcast::channel(CONNECTION_EVENTS_CAPACITY).0,
+            nat_events: tokio::sync::broadcast::channel(NAT_EVENTS_CAPACITY).0,
             key: keys.clone(),
             keystore,
+            mfs,
             to_task,
+            dial_timeout: options.dial_timeout,
             record_key_validator,
+            token: token.clone(),
+            task: Default::default(),
+            task_handle,
             _guard,
         };
 
+        // every dial is refused at the swarm level, whichever behaviour or facade call makes it
+        ipfs.gate.set_offline(offline);
+
         //Note: If `All` or `Pinned` are used, we would have to auto adjust the amount of
         //      provider records by adding the amount of blocks to the config.
         //TODO: Add persistent layer for kad store
-        let blocks = match options.provider {
+        let mut blocks = match options.provider {
             RepoProvider::None => vec![],
-            RepoProvider::All => ipfs.repo.list_blocks().await.collect::<Vec<_>>().await,
+            RepoProvider::All => {
+                ipfs.repo
+                    .list_blocks(BlockFilter::default())
+                    .await
+                    .map(|(cid, _)| cid)
+                    .collect::<Vec<_>>()
+                    .await
+            }
             RepoProvider::Pinned => {
                 ipfs.repo
                     .list_pins(None)
@@ -951,44 +1913,106 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
                     .await
             }
             RepoProvider::Roots => {
-                //TODO: Scan blockstore for root unixfs blocks
-                warn!("RepoProvider::Roots is not implemented... ignoring...");
-                vec![]
+                ipfs.repo
+                    .list_pins(None)
+                    .await
+                    .filter_map(|result| async move {
+                        result
+                            .ok()
+                            .filter(|(_, mode)| *mode != PinMode::Indirect)
+                            .map(|(cid, _)| cid)
+                    })
+                    .collect()
+                    .await
             }
         };
 
-        let count = blocks.len();
+        if matches!(options.provider, RepoProvider::None) {
+            blocks.extend(ipfs.repo.provided_pins().await);
+        }
 
-        let store_config = &mut options.kad_store_config;
+        let count = blocks.len();
 
-        match store_config.memory.as_mut() {
-            Some(memory_config) => {
-                memory_config.max_provided_keys += count;
-            }
-            None => {
-                store_config.memory = Some(MemoryStoreConfig {
-                    //Provide a buffer to the max amount of provided keys
-                    max_provided_keys: (50 * 1024) + count,
-                    ..Default::default()
-                })
-            }
+        // room for the keys provided on start on top of the configured ones
+        options.kad_store_config.limits_mut().max_provided_keys += count;
+
+        if options.protocols.relay_server {
+            ipfs.bandwidth.count_relayed();
         }
 
-        let swarm = create_swarm(
+        let mut swarm = create_swarm(
             &keys,
             &options,
             &ipfs.repo,
             exec_span,
+            ipfs.gate.clone(),
+            &ipfs.bandwidth,
             (custom_behaviour, custom_transport),
         )
         .instrument(tracing::trace_span!(parent: &init_span, "swarm"))
         .await?;
 
+        if let Some(kad) = swarm.behaviour_mut().kademlia.as_mut() {
+            kad.set_mode(options.dht_mode.into());
+        }
+
+        if let Some(path) = &addressbook_path {
+            let AddressBookConfig {
+                max_age, kad_seed, ..
+            } = options.addr_config;
+
+            match p2p::addressbook::load_records(path, max_age).await {
+                Ok(mut records) => {
+                    let behaviour = swarm.behaviour_mut();
+                    for record in &records {
+                        behaviour.addressbook.add_record(record);
+                        for (key, value) in &record.meta {
+                            if let Err(e) = behaviour.peerbook.set_peer_meta(
+                                record.peer_id,
+                                key.clone(),
+                                value.clone(),
+                            ) {
+                                tracing::warn!(error = %e, "dropping saved peer metadata");
+                            }
+                        }
+                    }
+
+                    if let Some(kad) = behaviour.kademlia.as_mut() {
+                        records.retain(|record| record.dht);
+                        records.sort_by_key(|record| std::cmp::Reverse(record.last_seen));
+                        for record in records.iter().take(kad_seed) {
+                            for addr in &record.addrs {
+                                kad.add_address(&record.peer_id, addr.clone());
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!(path = %path.display(), error = %e, "failed to load the addressbook")
+                }
+            }
+        }
+
         let IpfsOptions {
-            listening_addrs, ..
+            listening_addrs,
+            provider,
+            bootstrap,
+            bootstrap_policy,
+            connection_limits,
+            relay,
+            mdns_auto_dial,
+            interface_filter,
+            fail_if_connected,
+            disconnection_history,
+            rendezvous,
+            content_announcer,
+            observed_addr_config,
+            want_persistence,
+            relisten,
+            ..
         } = options;
 
-        if let Some(config) = gc_config {
+        if let Some(config) = gc_config.filter(|_| !shared_repo) {
             tokio::spawn({
                 let repo = ipfs.repo.clone();
                 let token = token.clone();
@@ -1052,8 +2076,14 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
 
                                 if cleanup {
                                     tracing::debug!("running cleanup of unpinned blocks");
-                                    let blocks = repo.cleanup().await.unwrap();
-                                    tracing::debug!(removed_blocks = blocks.len(), "blocks removed");
+                                    match repo.cleanup().await {
+                                        Ok(GCResult { removed, reclaimed }) => tracing::debug!(
+                                            removed_blocks = removed.len(),
+                                            reclaimed,
+                                            "blocks removed"
+                                        ),
+                                        Err(e) => tracing::error!(error = %e, "cleanup failed"),
+                                    }
                                     tracing::debug!("cleanup finished");
                                 }
                             }
@@ -1065,16 +2095,65 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
 
         let mut fut = task::IpfsTask::new(swarm, repo_events.fuse(), receiver.fuse(), &ipfs.repo);
         fut.swarm_event = swarm_event;
+        fut.swarm_observers = swarm_observers;
         fut.local_external_addr = local_external_addr;
+        fut.provider = provider;
+        fut.offline = offline;
+        fut.mdns_auto_dial = mdns_auto_dial;
+        fut.interface_filter = interface_filter;
+        fut.dht_write_limiter = options.dht_write_limit.map(DhtWriteLimiter::new);
+        #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+        {
+            fut.provider_ranking = options.provider_ranking;
+            fut.persist_bitswap_ledgers = options.persist_bitswap_ledgers;
+        }
+        fut.interfaces = p2p::addr::interface_addresses();
+        fut.fail_if_connected = fail_if_connected;
+        fut.disconnection_history = disconnection_history;
+        fut.identify_conf = identify_conf;
+        fut.public_key = Some(keys.public());
+        fut.interval_factory = interval_factory;
+        fut.connection_limits = connection_limits;
+        fut.connection_events = ipfs.connection_events.clone();
+        fut.nat_events = ipfs.nat_events.clone();
+        fut.addressbook_path = addressbook_path;
+        fut.gate = ipfs.gate.clone();
+        fut.bandwidth = ipfs.bandwidth.clone();
+        #[cfg(feature = "metrics")]
+        {
+            fut.metrics = ipfs.metrics.clone();
+        }
+        fut.auto_relay = relay.auto;
+        fut.observed_addrs = ObservedAddrs::new(observed_addr_config);
+        fut.relisten = relisten;
+
+        if let Some(manager) = fut.swarm.behaviour_mut().relay_manager.as_mut() {
+            for mut addr in relay.static_relays {
+                let Some(peer_id) = addr.peer_id() else {
+                    tracing::warn!(%addr, "static relay address without a peer id");
+                    continue;
+                };
+                addr.pop();
+                manager.add_address(peer_id, addr);
+                fut.static_relays.push(peer_id);
+            }
+        }
+        fut.bootstraps.extend(bootstrap);
+        if fut.swarm.behaviour().kademlia.is_enabled() {
+            fut.set_bootstrap_policy(bootstrap_policy);
+        }
+
+        if let Some(config) = content_announcer {
+            fut.set_content_announcer(config);
+        }
+
+        if fut.swarm.behaviour().rendezvous_client.is_enabled() {
+            fut.set_rendezvous(rendezvous);
+        }
 
         for addr in listening_addrs.into_iter() {
-            match fut.swarm.listen_on(addr) {
-                Ok(id) => {
-                    let (tx, _rx) = oneshot_channel();
-                    fut.pending_add_listener.insert(id, tx);
-                }
-                _ => continue,
-            };
+            let (tx, _rx) = oneshot_channel();
+            fut.listen_on(addr, tx);
         }
 
         for block in blocks {
@@ -1093,7 +2172,8 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
             }
         }
 
-        tokio::spawn({
+        let task_handle = ipfs.task_handle.clone();
+        let task = tokio::spawn({
             async move {
                 //Note: For now this is not configurable as its meant for internal testing purposes but may change in the future
                 let as_fut = false;
@@ -1104,13 +2184,19 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
                     fut.run().boxed()
                 };
 
-                tokio::select! {
-                    _ = fut => {}
-                    _ = token.cancelled() => {},
-                };
+                task_handle.run(fut, token).await;
             }
             .instrument(swarm_span)
         });
+        ipfs.task_handle.set_abort_handle(task.abort_handle());
+        *ipfs.task.lock() = Some(task);
+
+        // once the task is running, as the resumed wants are sent to it
+        if let Some(config) = want_persistence.filter(|_| !shared_repo) {
+            let resumed = ipfs.repo.persist_wants(config).await?;
+            ipfs.repo.resume_wants(resumed).await;
+        }
+
         Ok(ipfs)
     }
 }
@@ -1126,6 +2212,36 @@ impl Ipfs {
         &self.repo
     }
 
+    /// Returns a [`GateHandle`] to allow or deny peers and addresses at runtime, and to query the
+    /// connections denied so far.
+    pub fn gate(&self) -> GateHandle {
+        self.gate.clone()
+    }
+
+    /// Returns the bytes sent and received on every connection so far. The rates are averaged
+    /// over the samples taken every minute.
+    pub fn bandwidth_stats(&self) -> BandwidthStats {
+        self.bandwidth.stats()
+    }
+
+    /// Returns the metrics of the node in the prometheus text exposition format. The metrics are
+    /// updated periodically by the background task.
+    #[cfg(feature = "metrics")]
+    pub fn metrics_encoded(&self) -> String {
+        self.metrics.encode()
+    }
+
+    /// Returns the bandwidth used by each peer connected to so far.
+    pub fn bandwidth_by_peer(&self) -> HashMap<PeerId, BandwidthStats> {
+        self.bandwidth.by_peer()
+    }
+
+    /// Returns the bandwidth used by each stack of transport protocols, e.g. `/ip4/tcp` or
+    /// `/ip4/udp/quic-v1`.
+    pub fn bandwidth_by_protocol(&self) -> HashMap<String, BandwidthStats> {
+        self.bandwidth.by_protocol()
+    }
+
     /// Returns an [`IpfsUnixfs`] for files operations
     pub fn unixfs(&self) -> IpfsUnixfs {
         IpfsUnixfs::new(self.clone())
@@ -1136,19 +2252,78 @@ impl Ipfs {
         Ipns::new(self.clone())
     }
 
-    /// Puts a block into the ipfs repo.
-    pub async fn put_block(&self, block: Block) -> Result<Cid, Error> {
+    /// Returns the [`Mfs`] of the node for mutable file system operations
+    pub fn files(&self) -> &Mfs {
+        &self.mfs
+    }
+
+    /// Puts a block into the ipfs repo, returning whether it was written or already stored.
+    pub async fn put_block(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
         self.repo
             .put_block(block)
             .instrument(self.span.clone())
             .await
     }
 
+    /// Puts a block into the ipfs repo and pins it directly, see [`Repo::put_pinned_block`].
+    pub async fn put_pinned_block(&self, block: Block) -> Result<Cid, Error> {
+        self.repo
+            .put_pinned_block(block)
+            .instrument(self.span.clone())
+            .await
+    }
+
     /// Retrieves a block from the local blockstore, or starts fetching from the network or join an
-    /// already started fetch.
-    pub async fn get_block(&self, cid: &Cid) -> Result<Block, Error> {
+    /// already started fetch. See [`BlockGet::deadline`] to give up on the block.
+    pub fn get_block(&self, cid: &Cid) -> BlockGet {
+        BlockGet::new(self.repo.clone(), *cid, self.span.clone())
+    }
+
+    /// Retrieves a block like [`Ipfs::get_block`], but fails with [`FacadeError::Busy`] instead of
+    /// waiting for the background task to take the want of the missing block while it has yet to
+    /// take the previous ones, and with [`FacadeError::NodeShutdown`] if the node shuts down
+    /// before the block was fetched.
+    pub async fn try_get_block(&self, cid: &Cid) -> Result<Block, Error> {
+        self.repo
+            .try_get_block(cid)
+            .instrument(self.span.clone())
+            .await
+            .map_err(|e| match e.is::<futures::channel::oneshot::Canceled>() {
+                // the requests for the block are dropped once the node shut down
+                true => self.to_task.shutdown().into(),
+                false => e,
+            })
+    }
+
+    /// Retrieves a block from the local blockstore, or fetches it from the given providers. Content
+    /// discovery is skipped as long as any of the providers can be connected to.
+    pub async fn get_block_from(&self, cid: &Cid, providers: &[PeerId]) -> Result<Block, Error> {
+        self.repo
+            .get_block(cid, providers, false)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Retrieves a block like [`Ipfs::get_block_from`], wanting it at the given bitswap priority
+    /// so that providers send it ahead of the blocks wanted at a lower one. The default priority
+    /// is [`repo::DEFAULT_WANT_PRIORITY`].
+    pub async fn get_block_with_priority(
+        &self,
+        cid: &Cid,
+        providers: &[PeerId],
+        priority: i32,
+    ) -> Result<Block, Error> {
+        self.repo
+            .get_block_with_priority(None, cid, providers, false, None, priority)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Retrieves a block from the local blockstore only, failing with [`repo::BlockNotLocal`]
+    /// instead of fetching it from the network.
+    pub async fn get_block_local(&self, cid: &Cid) -> Result<Block, Error> {
         self.repo
-            .get_block(cid, &[], false)
+            .get_block(cid, &[], true)
             .instrument(self.span.clone())
             .await
     }
@@ -1161,10 +2336,87 @@ impl Ipfs {
             .await
     }
 
-    /// Cleans up of all unpinned blocks
+    /// Returns the number of blocks and their total size, along with the storage limit.
+    pub async fn repo_stat(&self) -> Result<RepoStat, Error> {
+        self.repo.stat().instrument(self.span.clone()).await
+    }
+
+    /// Checks the repo for corrupt blocks and for blocks missing from the pinned dags, as left
+    /// behind by an unclean shutdown, repairing them according to `mode`. See [`Repo::verify`].
+    pub fn repo_verify(&self, mode: RepairMode) -> VerifyHandle {
+        let _g = self.span.enter();
+        self.repo.verify(mode)
+    }
+
+    /// Puts the value under the key in the namespace of the datastore of the repo, for the
+    /// metadata of the application. The namespaces are kept apart from the entries of the node
+    /// and the values are limited to [`MAX_KV_VALUE_SIZE`](repo::MAX_KV_VALUE_SIZE) bytes.
+    pub async fn ds_put(
+        &self,
+        namespace: &str,
+        key: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+    ) -> Result<(), Error> {
+        self.repo
+            .kv_put(namespace, key.as_ref(), value.as_ref())
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Returns the value of the key in the namespace, see [`Ipfs::ds_put`].
+    pub async fn ds_get(
+        &self,
+        namespace: &str,
+        key: impl AsRef<[u8]>,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.repo
+            .kv_get(namespace, key.as_ref())
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Removes the key from the namespace, returning whether it was there.
+    pub async fn ds_delete(&self, namespace: &str, key: impl AsRef<[u8]>) -> Result<bool, Error> {
+        self.repo
+            .kv_delete(namespace, key.as_ref())
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Sets the value of the key in the namespace, or removes it if `None`, only if its current
+    /// value is `expected`, `None` meaning absent. Returns whether it was set.
+    pub async fn ds_compare_and_swap(
+        &self,
+        namespace: &str,
+        key: impl AsRef<[u8]>,
+        expected: Option<&[u8]>,
+        value: Option<&[u8]>,
+    ) -> Result<bool, Error> {
+        self.repo
+            .kv_compare_and_swap(namespace, key.as_ref(), expected, value)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Returns the entries of the namespace whose key starts with `prefix`, the keys without the
+    /// namespace.
+    pub async fn ds_query(
+        &self,
+        namespace: &str,
+        prefix: impl AsRef<[u8]>,
+    ) -> Result<BoxStream<'static, (Vec<u8>, Vec<u8>)>, Error> {
+        self.repo
+            .kv_query(namespace, prefix.as_ref())
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Cleans up of all blocks which are neither pinned nor reachable from the [`Mfs`] root,
+    /// returning the removed blocks and the number of bytes reclaimed. Blocks written within the
+    /// period set with [`UninitializedIpfs::set_temp_pin_duration`] are kept.
     /// Note: This will prevent writing operations in [`Repo`] until it finish clearing unpinned
     ///       blocks.
-    pub async fn gc(&self) -> Result<Vec<Cid>, Error> {
+    pub async fn gc(&self) -> Result<GCResult, Error> {
         let _g = self.repo.inner.gclock.write().await;
         self.repo.cleanup().instrument(self.span.clone()).await
     }
@@ -1182,6 +2434,11 @@ impl Ipfs {
     /// Recursively pinned Cids cannot be re-pinned non-recursively but non-recursively pinned Cids
     /// can be "upgraded to" being recursively pinned.
     ///
+    /// Pins can be given a name and metadata with [`RepoInsertPin::name`] and
+    /// [`RepoInsertPin::metadata`], which are kept until the pin is removed.
+    ///
+    /// Use [`RepoInsertPin::handle`] to follow the progress of a recursive pin or to cancel it.
+    ///
     /// # Crash unsafety
     ///
     /// If a recursive `insert_pin` operation is interrupted because of a crash or the crash
@@ -1201,6 +2458,27 @@ impl Ipfs {
         self.repo().remove_pin(cid).span(self.span.clone())
     }
 
+    /// Pins the roots recursively, loading the blocks shared between them once, and writes all
+    /// the pins as a single change of the data store. Roots already pinned recursively are
+    /// skipped.
+    pub async fn pin_many(&self, roots: impl IntoIterator<Item = Cid>) -> Result<(), Error> {
+        let roots = roots.into_iter().collect::<Vec<_>>();
+        let span = debug_span!(parent: &self.span, "pin_many", roots = roots.len());
+        self.repo.pin_many(&roots, false).instrument(span).await
+    }
+
+    /// Pins `new` recursively in place of the recursive pin of `old`, fetching only the blocks of
+    /// `new` not already in the repo. With `unpin_old`, the pin of `old` is removed in the same
+    /// change of the data store as the pin of `new` is written, so that one of them is pinned at
+    /// all times. See [`Repo::pin_update`].
+    pub async fn pin_update(&self, old: &Cid, new: &Cid, unpin_old: bool) -> Result<(), Error> {
+        let span = debug_span!(parent: &self.span, "pin_update", old = %old, new = %new);
+        self.repo
+            .pin_update(old, new, unpin_old, false)
+            .instrument(span)
+            .await
+    }
+
     /// Checks whether a given block is pinned.
     ///
     /// Returns true if the block is pinned, false if not. See Crash unsafety notes for the false
@@ -1219,7 +2497,7 @@ impl Ipfs {
         self.repo.is_pinned(cid).instrument(span).await
     }
 
-    /// Lists all pins, or the specific kind thereof.
+    /// Lists all pins, or the specific kind thereof, along with the name of the pin if it has one.
     ///
     /// # Crash unsafety
     ///
@@ -1227,9 +2505,25 @@ impl Ipfs {
     pub async fn list_pins(
         &self,
         filter: Option<PinMode>,
-    ) -> futures::stream::BoxStream<'static, Result<(Cid, PinMode), Error>> {
+    ) -> futures::stream::BoxStream<'static, Result<(Cid, PinMode, Option<String>), Error>> {
         let span = debug_span!(parent: &self.span, "list_pins", ?filter);
-        self.repo.list_pins(filter).instrument(span).await
+        self.repo
+            .list_labeled_pins(filter)
+            .instrument(span)
+            .await
+            .map_ok(|(cid, mode, label)| (cid, mode, label.name))
+            .boxed()
+    }
+
+    /// Lists the direct and recursive pins whose name starts with `prefix`, along with their
+    /// [`PinLabel`].
+    pub async fn pin_ls_by_name(
+        &self,
+        prefix: impl Into<String>,
+    ) -> futures::stream::BoxStream<'static, Result<(Cid, PinMode, PinLabel), Error>> {
+        let prefix = prefix.into();
+        let span = debug_span!(parent: &self.span, "pin_ls_by_name", prefix = %prefix);
+        self.repo.list_pins_by_name(prefix).instrument(span).await
     }
 
     /// Read specific pins. When `requirement` is `Some`, all pins are required to be of the given
@@ -1264,6 +2558,76 @@ impl Ipfs {
         self.dag().get_dag(path).span(self.span.clone())
     }
 
+    /// Gets an ipld node like [`Ipfs::get_dag`] from the local blocks only, failing with
+    /// [`repo::BlockNotLocal`] instead of fetching a missing block along the path.
+    pub fn dag_get_local<I: Into<IpfsPath>>(&self, path: I) -> DagGet {
+        self.get_dag(path).fetch_policy(FetchPolicy::LocalOnly)
+    }
+
+    /// Resolves a path through the DAG, returning a [`ResolvedPath`](dag::ResolvedPath) with the
+    /// final [`Cid`] and the documents traversed along the way.
+    ///
+    /// See [`IpldDag::resolve_path`] for more information, and [`DagResolve::deadline`] to give up
+    /// on the lookup.
+    pub fn resolve(&self, path: IpfsPath) -> DagResolve {
+        DagResolve::new(self.dag(), path, self.span.clone())
+    }
+
+    /// Writes the DAG rooted at `root` as a CARv1 stream into `writer`, fetching missing blocks
+    /// from the network.
+    pub fn dag_export(
+        &self,
+        root: Cid,
+        writer: impl tokio::io::AsyncWrite + Send + Unpin + 'static,
+    ) -> DagExport {
+        self.dag().export(root, writer).span(self.span.clone())
+    }
+
+    /// Imports the blocks of a CARv1 or CARv2 stream into the repo, returning the roots listed in
+    /// the header of the stream.
+    pub async fn dag_import(
+        &self,
+        reader: impl tokio::io::AsyncRead + Unpin,
+        options: ImportOptions,
+    ) -> Result<Vec<Cid>, Error> {
+        self.dag()
+            .import(reader, options)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Returns the number of unique blocks, their total size and the size of the largest block of
+    /// the DAG rooted at `root`.
+    ///
+    /// See [`IpldDag::stat`] for more information.
+    pub async fn dag_stat(&self, root: Cid, offline: bool) -> Result<DagStat, DagStatError> {
+        self.dag()
+            .stat(root, offline)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Compares two DAGs, returning the links which differ between them.
+    ///
+    /// See [`IpldDag::diff`] for more information.
+    pub async fn dag_diff(&self, a: Cid, b: Cid) -> Result<Vec<DagDiffEntry>, Error> {
+        self.dag().diff(a, b).instrument(self.span.clone()).await
+    }
+
+    /// Walks the DAG rooted at `root`, fetching only the blocks the selector traverses into.
+    ///
+    /// See [`IpldDag::walk`] for more information.
+    pub fn dag_walk(
+        &self,
+        root: Cid,
+        selector: Selector,
+    ) -> BoxStream<'static, Result<(IpfsPath, Cid, Ipld), Error>> {
+        self.dag()
+            .walk(root, selector)
+            .instrument(self.span.clone())
+            .boxed()
+    }
+
     /// Creates a stream which will yield the bytes of an UnixFS file from the root Cid, with the
     /// optional file byte range. If the range is specified and is outside of the file, the stream
     /// will end without producing any bytes.
@@ -1276,16 +2640,84 @@ impl Ipfs {
         self.unixfs().add(opt).span(self.span.clone())
     }
 
-    /// Retreive a file and saving it to a path.
+    /// Retreive a file or a directory and saving it to a path.
+    ///
+    /// Fails if the path already exists; see [`unixfs::GetOptions`] for overwriting, symlinks,
+    /// progress and the handling of partially written files.
     pub fn get_unixfs<P: AsRef<Path>>(&self, path: IpfsPath, dest: P) -> UnixfsGet {
         self.unixfs().get(path, dest).span(self.span.clone())
     }
 
-    /// List directory contents
+    /// List the entries of a directory, including the entries of HAMT sharded directories
     pub fn ls_unixfs(&self, path: IpfsPath) -> UnixfsLs {
         self.unixfs().ls(path).span(self.span.clone())
     }
 
+    /// Resolves the path into the response a path gateway would serve for it: the file, the
+    /// `index.html` of a directory or a listing of it, honoring the range, accept and
+    /// if-none-match headers of the request. Paths which do not exist result in a 404 response,
+    /// while failing to load the content is an error.
+    pub async fn fetch_unixfs_as_http_response(
+        &self,
+        path: IpfsPath,
+        request: GatewayRequest,
+    ) -> Result<GatewayResponse, Error> {
+        unixfs::gateway_respond(self, path, request)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Creates a directory in the mutable file system, optionally along with its parents.
+    pub async fn files_mkdir(&self, path: &str, parents: bool) -> Result<(), MfsError> {
+        self.mfs
+            .mkdir(path, parents)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Writes data into a file of the mutable file system.
+    ///
+    /// See [`Mfs::write`] for more information.
+    pub async fn files_write(
+        &self,
+        path: &str,
+        data: impl AsRef<[u8]>,
+        options: WriteOptions,
+    ) -> Result<(), MfsError> {
+        self.mfs
+            .write(path, data, options)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Reads the contents of a file of the mutable file system.
+    pub async fn files_read(&self, path: &str) -> Result<Bytes, MfsError> {
+        self.mfs.read(path).instrument(self.span.clone()).await
+    }
+
+    /// Moves a file or a directory within the mutable file system.
+    pub async fn files_mv(&self, from: &str, to: &str) -> Result<(), MfsError> {
+        self.mfs.mv(from, to).instrument(self.span.clone()).await
+    }
+
+    /// Removes a file or, if `recursive` is set, a directory from the mutable file system.
+    pub async fn files_rm(&self, path: &str, recursive: bool) -> Result<(), MfsError> {
+        self.mfs
+            .rm(path, recursive)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Returns information about an entry of the mutable file system.
+    pub async fn files_stat(&self, path: &str) -> Result<MfsStat, MfsError> {
+        self.mfs.stat(path).instrument(self.span.clone()).await
+    }
+
+    /// Returns the root [`Cid`] of the mutable file system, which is persisted in the repo.
+    pub async fn files_flush(&self) -> Result<Cid, MfsError> {
+        self.mfs.flush().instrument(self.span.clone()).await
+    }
+
     /// Resolves a ipns path to an ipld path; currently only supports dht and dnslink resolution.
     pub async fn resolve_ipns(&self, path: &IpfsPath, recursive: bool) -> Result<IpfsPath, Error> {
         async move {
@@ -1317,27 +2749,58 @@ impl Ipfs {
         .await
     }
 
-    /// Connects to the peer
-    pub async fn connect(&self, target: impl Into<DialOpts>) -> Result<(), Error> {
+    /// Connects to the peer, failing with [`DialError::Timeout`] if the dial does not complete
+    /// within [`IpfsOptions::dial_timeout`]. Succeeds if already connected, unless
+    /// [`IpfsOptions::fail_if_connected`] is set.
+    pub async fn connect(&self, target: impl Into<DialOpts>) -> Result<(), DialError> {
         async move {
             let target = target.into();
             let (tx, rx) = oneshot_channel();
             self.to_task
-                .clone()
                 .send(IpfsEvent::Connect(target, tx))
-                .await?;
+                .await
+                .map_err(|_| DialError::Aborted)?;
 
-            rx.await?
+            self.dial_result(rx).await
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Dials exactly the given address, returning the peer connected to. The address does not
+    /// need a `/p2p/` component, but when it has one the connected peer must match it. Times out
+    /// as [`Ipfs::connect`].
+    pub async fn connect_addr(&self, addr: Multiaddr) -> Result<PeerId, DialError> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .send(IpfsEvent::ConnectAddr(addr, tx))
+                .await
+                .map_err(|_| DialError::Aborted)?;
+
+            self.dial_result(rx).await
         }
         .instrument(self.span.clone())
         .await
     }
 
+    /// Waits up to the dial timeout for the result of a dial. The swarm keeps on dialing after
+    /// the timeout, and keeps the connection if it is established later on.
+    async fn dial_result<T>(
+        &self,
+        rx: oneshot::Receiver<Result<T, DialError>>,
+    ) -> Result<T, DialError> {
+        match tokio::time::timeout(self.dial_timeout, rx).await {
+            Ok(result) => result.map_err(|_| DialError::Aborted)?,
+            Err(_) => Err(DialError::Timeout(None)),
+        }
+    }
+
     /// Returns known peer addresses
     pub async fn addrs(&self) -> Result<Vec<(PeerId, Vec<Multiaddr>)>, Error> {
         async move {
             let (tx, rx) = oneshot_channel();
-            self.to_task.clone().send(IpfsEvent::Addresses(tx)).await?;
+            self.to_task.send(IpfsEvent::Addresses(tx)).await?;
             rx.await?
         }
         .instrument(self.span.clone())
@@ -1349,7 +2812,6 @@ impl Ipfs {
         async move {
             let (tx, rx) = oneshot_channel();
             self.to_task
-                .clone()
                 .send(IpfsEvent::IsConnected(peer_id, tx))
                 .await?;
             rx.await?
@@ -1358,11 +2820,51 @@ impl Ipfs {
         .await
     }
 
+    /// Returns the most recently closed connections, oldest first, with why they were closed. See
+    /// [`IpfsOptions::disconnection_history`].
+    pub async fn recent_disconnections(&self) -> Result<Vec<Disconnection>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .send(IpfsEvent::RecentDisconnections(tx))
+                .await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the outbound connection attempts in progress, oldest first, with the peer dialed
+    /// if known, the addresses known for it and when the attempt started.
+    pub async fn pending_dials(
+        &self,
+    ) -> Result<Vec<(Option<PeerId>, Vec<Multiaddr>, Instant)>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::PendingDials(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Aborts the pending dials to the peer, returning whether there were any. Nothing is aborted
+    /// while connected to the peer, as its connections would be closed too.
+    pub async fn abort_dial(&self, peer_id: PeerId) -> Result<bool, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::AbortDial(peer_id, tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
     /// Returns the connected peers
     pub async fn connected(&self) -> Result<Vec<PeerId>, Error> {
         async move {
             let (tx, rx) = oneshot_channel();
-            self.to_task.clone().send(IpfsEvent::Connected(tx)).await?;
+            self.to_task.send(IpfsEvent::Connected(tx)).await?;
             rx.await?
         }
         .instrument(self.span.clone())
@@ -1373,10 +2875,7 @@ impl Ipfs {
     pub async fn disconnect(&self, target: PeerId) -> Result<(), Error> {
         async move {
             let (tx, rx) = oneshot_channel();
-            self.to_task
-                .clone()
-                .send(IpfsEvent::Disconnect(target, tx))
-                .await?;
+            self.to_task.send(IpfsEvent::Disconnect(target, tx)).await?;
 
             rx.await?
         }
@@ -1386,11 +2885,26 @@ impl Ipfs {
 
     /// Bans a peer.
     pub async fn ban_peer(&self, target: PeerId) -> Result<(), Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::Ban(target, None, tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Bans a peer for the given duration, after which it is unbanned. Banning a peer again
+    /// replaces the previous duration.
+    pub async fn ban_peer_with_duration(
+        &self,
+        target: PeerId,
+        duration: Duration,
+    ) -> Result<(), Error> {
         async move {
             let (tx, rx) = oneshot_channel();
             self.to_task
-                .clone()
-                .send(IpfsEvent::Ban(target, tx))
+                .send(IpfsEvent::Ban(target, Some(duration), tx))
                 .await?;
             rx.await?
         }
@@ -1398,13 +2912,21 @@ impl Ipfs {
         .await
     }
 
-    /// Unbans a peer.
-    pub async fn unban_peer(&self, target: PeerId) -> Result<(), Error> {
+    /// Bans an address, refusing connections to and from addresses starting with it regardless
+    /// of the peer, e.g. `/ip4/1.2.3.4` bans every port of that ip. The existing connections on the
+    /// address are closed. The ban is permanent unless a duration is given.
+    ///
+    /// Bans are kept apart from the addresses denied through [`Ipfs::gate`], unbanning does not
+    /// lift a deny.
+    pub async fn ban_address(
+        &self,
+        target: Multiaddr,
+        duration: Option<Duration>,
+    ) -> Result<(), Error> {
         async move {
             let (tx, rx) = oneshot_channel();
             self.to_task
-                .clone()
-                .send(IpfsEvent::Unban(target, tx))
+                .send(IpfsEvent::BanAddress(target, duration, tx))
                 .await?;
             rx.await?
         }
@@ -1412,65 +2934,270 @@ impl Ipfs {
         .await
     }
 
-    /// Returns the peer identity information. If no peer id is supplied the local node identity is used.
-    pub async fn identity(&self, peer_id: Option<PeerId>) -> Result<PeerInfo, Error> {
+    /// Unbans an address banned with [`Ipfs::ban_address`].
+    pub async fn unban_address(&self, target: Multiaddr) -> Result<(), Error> {
         async move {
-            match peer_id {
-                Some(peer_id) => {
-                    let (tx, rx) = oneshot_channel();
-
-                    self.to_task
-                        .clone()
-                        .send(IpfsEvent::FindPeerIdentity(peer_id, tx))
-                        .await?;
-
-                    rx.await??.await?.map(PeerInfo::from)
-                }
-                None => {
-                    let mut addresses = HashSet::new();
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .send(IpfsEvent::UnbanAddress(target, tx))
+                .await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
 
-                    let (local_result, external_result) =
-                        futures::join!(self.listening_addresses(), self.external_addresses());
+    /// Returns the active bans of peers and addresses.
+    pub async fn list_bans(&self) -> Result<Vec<Ban>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::Bans(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
 
-                    let external: HashSet<Multiaddr> =
-                        HashSet::from_iter(external_result.unwrap_or_default());
-                    let local: HashSet<Multiaddr> =
-                        HashSet::from_iter(local_result.unwrap_or_default());
+    /// Unbans a peer.
+    pub async fn unban_peer(&self, target: PeerId) -> Result<(), Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::Unban(target, tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
 
-                    addresses.extend(external.iter().cloned());
-                    addresses.extend(local.iter().cloned());
+    /// Whitelists a peer, whose connections are never pruned when above the
+    /// [`ConnectionLimits::high_water`] mark.
+    pub async fn whitelist_peer(&self, target: PeerId) -> Result<(), Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .send(IpfsEvent::WhitelistPeer(target, tx))
+                .await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
 
-                    let mut addresses = Vec::from_iter(addresses);
+    /// Removes a peer from the whitelist, returning false if it was not whitelisted.
+    pub async fn remove_whitelisted_peer(&self, target: PeerId) -> Result<bool, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .send(IpfsEvent::RemoveWhitelistedPeer(target, tx))
+                .await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
 
-                    let (tx, rx) = oneshot_channel();
-                    self.to_task.clone().send(IpfsEvent::Protocol(tx)).await?;
+    /// Returns a stream of the connections established and closed, and of the failed dials, from
+    /// now on. A subscriber falling behind by more than 256 events skips the oldest ones.
+    pub fn connection_events(&self) -> BoxStream<'static, ConnectionEvent> {
+        let mut receiver = self.connection_events.subscribe();
+        let stream = async_stream::stream! {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => yield event,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                        debug!("connection event subscriber skipped {skipped} events");
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        };
 
-                    let protocols = rx
-                        .await?
-                        .iter()
-                        .filter_map(|s| StreamProtocol::try_from_owned(s.clone()).ok())
-                        .collect();
+        stream.boxed()
+    }
 
-                    let public_key = self.key.public();
-                    let peer_id = public_key.to_peer_id();
+    /// Protects the peer with the tag: its connections are kept alive and never pruned, and it
+    /// is redialed with an exponential backoff when disconnected. Tags are reference counted, so
+    /// the peer stays protected until every [`Ipfs::protect_peer`] is matched by an
+    /// [`Ipfs::unprotect_peer`] with the same tag.
+    pub async fn protect_peer(&self, peer_id: PeerId, tag: &str) -> Result<(), Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .send(IpfsEvent::ProtectPeer(peer_id, tag.to_string(), tx))
+                .await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
 
-                    for addr in &mut addresses {
-                        if !matches!(addr.iter().last(), Some(Protocol::P2p(_))) {
-                            addr.push(Protocol::P2p(peer_id))
-                        }
-                    }
+    /// Removes a reference of the tag protecting the peer, returning false if the peer was not
+    /// protected by it.
+    pub async fn unprotect_peer(&self, peer_id: PeerId, tag: &str) -> Result<bool, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .send(IpfsEvent::UnprotectPeer(peer_id, tag.to_string(), tx))
+                .await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
 
-                    let info = PeerInfo {
-                        peer_id,
-                        public_key,
-                        protocol_version: self.identify_conf.protocol_version.clone(),
-                        agent_version: self.identify_conf.agent_version.clone(),
-                        listen_addrs: addresses,
-                        protocols,
-                        observed_addr: None,
-                    };
+    /// Attaches the value to the peer under the key, returning the value it replaced. The
+    /// metadata is kept when the peer disconnects, and is reported by [`Ipfs::peers_info`].
+    /// Values over the limits of [`IpfsOptions::peer_meta`] fail with a
+    /// [`PeerMetaError`](p2p::PeerMetaError).
+    pub async fn peer_set_meta(
+        &self,
+        peer_id: PeerId,
+        key: impl Into<String>,
+        value: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let key = key.into();
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .send(IpfsEvent::PeerSetMeta(peer_id, key, value, tx))
+                .await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the value attached to the peer under the key.
+    pub async fn peer_get_meta(
+        &self,
+        peer_id: PeerId,
+        key: &str,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .send(IpfsEvent::PeerGetMeta(peer_id, key.to_string(), tx))
+                .await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Removes the value attached to the peer under the key, returning it.
+    pub async fn peer_del_meta(
+        &self,
+        peer_id: PeerId,
+        key: &str,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .send(IpfsEvent::PeerDelMeta(peer_id, key.to_string(), tx))
+                .await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the protected peers, with the attempts to reconnect to the disconnected ones.
+    pub async fn peer_protection_status(&self) -> Result<Vec<PeerProtectionStatus>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .send(IpfsEvent::PeerProtectionStatus(tx))
+                .await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Prunes the connected peers down to the low-water mark of the [`ConnectionLimits`] right
+    /// away, instead of waiting for the periodic pruning.
+    pub async fn prune_connections(&self) -> Result<(), Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::PruneConnections(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the information of every identified peer, along with the latest round-trip time
+    /// and the addresses of the connections with it.
+    pub async fn peers_info(&self) -> Result<Vec<PeerInfo>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::PeersInfo(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns our addresses as observed by the identified peers, along with the number of peers
+    /// having reported each, most reported first.
+    pub async fn observed_addresses(&self) -> Result<Vec<(Multiaddr, usize)>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::ObservedAddresses(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns a stream of the next `count` round-trip times measured by pinging the peer, or of
+    /// every one with `None`, connecting to the peer first if needed. The stream ends with an
+    /// error if the peer disconnects.
+    ///
+    /// The peer is pinged right away, then every second until the stream ends. The round-trip
+    /// times measured meanwhile by the keep-alive pings of the [`PingConfig`] are received as well,
+    /// every stream pinging the peer receiving each of them.
+    pub async fn ping(
+        &self,
+        peer_id: PeerId,
+        count: Option<usize>,
+    ) -> Result<BoxStream<'static, Result<Duration, Error>>, Error> {
+        async move {
+            if !self.is_connected(peer_id).await? {
+                self.connect(peer_id).await?;
+            }
+
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .send(IpfsEvent::Ping(peer_id, count, tx))
+                .await?;
+            let receiver = rx.await??;
+
+            Ok(receiver.boxed())
+        }
+        .instrument(self.span.clone())
+        .await
+    }
 
-                    Ok(info)
+    /// Returns the peer identity information. If no peer id is supplied the local node identity is
+    /// used, with the listening and external addresses including our peer id. Otherwise the
+    /// identity is taken from the peerbook, or looked up through the DHT.
+    pub async fn identity(&self, peer_id: Option<PeerId>) -> Result<PeerInfo, Error> {
+        async move {
+            match peer_id {
+                Some(peer_id) => {
+                    let (tx, rx) = oneshot_channel();
+
+                    self.to_task
+                        .send(IpfsEvent::FindPeerIdentity(peer_id, tx))
+                        .await?;
+
+                    rx.await??.await?
+                }
+                None => {
+                    let (tx, rx) = oneshot_channel();
+                    self.to_task.send(IpfsEvent::LocalIdentity(tx)).await?;
+                    rx.await?
                 }
             }
         }
@@ -1478,6 +3205,22 @@ impl Ipfs {
         .await
     }
 
+    /// Returns the protocols the connected peer supports, as it identified with or last pushed
+    /// them. Empty until the peer is identified.
+    pub async fn peer_protocols(&self, peer_id: PeerId) -> Result<Vec<String>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .send(IpfsEvent::PeerProtocols(peer_id, tx))
+                .await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
     /// Subscribes to a given topic. Can be done at most once without unsubscribing in the between.
     /// The subscription can be unsubscribed by dropping the stream or calling
     /// [`Ipfs::pubsub_unsubscribe`].
@@ -1490,7 +3233,6 @@ impl Ipfs {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
                 .send(IpfsEvent::PubsubSubscribe(topic.clone(), tx))
                 .await?;
 
@@ -1510,10 +3252,7 @@ impl Ipfs {
             let topic = topic.into();
             let (tx, rx) = oneshot_channel();
 
-            self.to_task
-                .clone()
-                .send(IpfsEvent::PubsubEventStream(tx))
-                .await?;
+            self.to_task.send(IpfsEvent::PubsubEventStream(tx)).await?;
 
             let mut receiver = rx
                 .await?;
@@ -1547,7 +3286,6 @@ impl Ipfs {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
                 .send(IpfsEvent::PubsubPublish(topic, data, tx))
                 .await?;
             rx.await??.map_err(anyhow::Error::from)
@@ -1565,7 +3303,6 @@ impl Ipfs {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
                 .send(IpfsEvent::PubsubUnsubscribe(topic.into(), tx))
                 .await?;
 
@@ -1584,10 +3321,7 @@ impl Ipfs {
             let topic = topic.into();
             let (tx, rx) = oneshot_channel();
 
-            self.to_task
-                .clone()
-                .send(IpfsEvent::PubsubPeers(topic, tx))
-                .await?;
+            self.to_task.send(IpfsEvent::PubsubPeers(topic, tx)).await?;
 
             rx.await?
         }
@@ -1597,12 +3331,66 @@ impl Ipfs {
 
     /// Returns all currently subscribed topics
     pub async fn pubsub_subscribed(&self) -> Result<Vec<String>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task.send(IpfsEvent::PubsubSubscribed(tx)).await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the peers of the gossipsub mesh of the topic and the other peers subscribed to it.
+    /// The mesh is empty unless subscribed to the topic, its fanout being listed instead if
+    /// published to recently.
+    pub async fn pubsub_mesh(&self, topic: impl Into<String>) -> Result<MeshInfo, Error> {
+        async move {
+            let topic = topic.into();
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task.send(IpfsEvent::PubsubMesh(topic, tx)).await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns a stream of the peers grafted to and pruned from the gossipsub meshes, of the topic
+    /// if any, starting with a graft for each peer currently in the meshes.
+    pub async fn pubsub_mesh_events(
+        &self,
+        topic: impl Into<Option<String>>,
+    ) -> Result<BoxStream<'static, MeshEvent>, Error> {
+        let topic = topic.into();
+        let receiver = async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::PubsubMeshEvents(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await?;
+
+        let stream = receiver.filter(move |event| {
+            futures::future::ready(
+                topic
+                    .as_deref()
+                    .map_or(true, |topic| event.topic() == topic),
+            )
+        });
+        Ok(stream.boxed())
+    }
+
+    /// Returns the peers which announced the cid over pubsub within the ttl of the content
+    /// announcer, none if it is not enabled.
+    pub async fn announced_providers(&self, cid: &Cid) -> Result<Vec<PeerId>, Error> {
         async move {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
-                .send(IpfsEvent::PubsubSubscribed(tx))
+                .send(IpfsEvent::AnnouncedProviders(*cid, tx))
                 .await?;
 
             rx.await?
@@ -1620,12 +3408,36 @@ impl Ipfs {
             let peer = peer.into();
             let (tx, rx) = oneshot_channel();
 
+            self.to_task.send(IpfsEvent::WantList(peer, tx)).await?;
+
+            Ok(rx.await??.await)
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the bytes and blocks exchanged with the peer through bitswap. Only supported by
+    /// the default bitswap implementation.
+    pub async fn bitswap_ledger(&self, peer_id: PeerId) -> Result<PeerLedger, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
             self.to_task
-                .clone()
-                .send(IpfsEvent::WantList(peer, tx))
+                .send(IpfsEvent::BitswapLedger(peer_id, tx))
                 .await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
 
-            Ok(rx.await??.await)
+    /// Forgets the bytes and blocks exchanged with the peer through bitswap.
+    pub async fn bitswap_ledger_reset(&self, peer_id: PeerId) -> Result<(), Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .send(IpfsEvent::BitswapLedgerReset(peer_id, tx))
+                .await?;
+            rx.await?
         }
         .instrument(self.span.clone())
         .await
@@ -1636,10 +3448,93 @@ impl Ipfs {
         async move {
             let (tx, rx) = oneshot_channel();
 
-            self.to_task
-                .clone()
-                .send(IpfsEvent::StreamControlHandle(tx))
-                .await?;
+            self.to_task
+                .send(IpfsEvent::StreamControlHandle(tx))
+                .await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    #[cfg(feature = "experimental_stream")]
+    pub async fn new_stream(
+        &self,
+        protocol: impl Into<StreamProtocolRef>,
+    ) -> Result<libp2p_stream::IncomingStreams, Error> {
+        let protocol: StreamProtocol = protocol.into().try_into()?;
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .send(IpfsEvent::NewStream(protocol, tx))
+                .await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    #[cfg(feature = "experimental_stream")]
+    pub async fn open_stream(
+        &self,
+        peer_id: PeerId,
+        protocol: impl Into<StreamProtocolRef>,
+    ) -> Result<libp2p::Stream, Error> {
+        let protocol: StreamProtocol = protocol.into().try_into()?;
+        async move {
+            let mut control = self.stream_control().await?;
+            let stream = control
+                .open_stream(peer_id, protocol)
+                .await
+                .map_err(|e| anyhow::anyhow!("{e}"))?;
+            Ok(stream)
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Lists the local blocks matching the filter, along with the size of their data. See
+    /// [`Repo::list_blocks`].
+    pub async fn refs_local(&self, filter: BlockFilter) -> BoxStream<'static, (Cid, usize)> {
+        self.repo
+            .list_blocks(filter)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Returns local listening addresses confirmed by the transports. See [`Ipfs::listeners`] for
+    /// the addresses they were requested with.
+    pub async fn listening_addresses(&self) -> Result<Vec<Multiaddr>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::Listeners(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the listeners, with the requested address and the addresses confirmed by the
+    /// transport, e.g. the assigned port or every interface of an unspecified ip.
+    pub async fn listeners(&self) -> Result<Vec<ListenerInfo>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::ListenerInfo(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns external addresses
+    pub async fn external_addresses(&self) -> Result<Vec<Multiaddr>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task.send(IpfsEvent::ExternalAddresses(tx)).await?;
 
             rx.await?
         }
@@ -1647,18 +3542,13 @@ impl Ipfs {
         .await
     }
 
-    #[cfg(feature = "experimental_stream")]
-    pub async fn new_stream(
-        &self,
-        protocol: impl Into<StreamProtocolRef>,
-    ) -> Result<libp2p_stream::IncomingStreams, Error> {
-        let protocol: StreamProtocol = protocol.into().try_into()?;
+    /// Returns the external addresses with where they were learned from
+    pub async fn external_address_info(&self) -> Result<Vec<ExternalAddressInfo>, Error> {
         async move {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
-                .send(IpfsEvent::NewStream(protocol, tx))
+                .send(IpfsEvent::ExternalAddressInfo(tx))
                 .await?;
 
             rx.await?
@@ -1667,54 +3557,29 @@ impl Ipfs {
         .await
     }
 
-    #[cfg(feature = "experimental_stream")]
-    pub async fn open_stream(
-        &self,
-        peer_id: PeerId,
-        protocol: impl Into<StreamProtocolRef>,
-    ) -> Result<libp2p::Stream, Error> {
-        let protocol: StreamProtocol = protocol.into().try_into()?;
+    /// Advertise the address as external, pushing it to the connected peers with identify
+    pub async fn add_external_address(&self, addr: Multiaddr) -> Result<(), Error> {
         async move {
-            let mut control = self.stream_control().await?;
-            let stream = control
-                .open_stream(peer_id, protocol)
-                .await
-                .map_err(|e| anyhow::anyhow!("{e}"))?;
-            Ok(stream)
-        }
-        .instrument(self.span.clone())
-        .await
-    }
+            let (tx, rx) = oneshot_channel();
 
-    /// Returns a list of local blocks
-    pub async fn refs_local(&self) -> Vec<Cid> {
-        self.repo
-            .list_blocks()
-            .instrument(self.span.clone())
-            .await
-            .collect::<Vec<_>>()
-            .await
-    }
+            self.to_task
+                .send(IpfsEvent::AddExternalAddress(addr, tx))
+                .await?;
 
-    /// Returns local listening addresses
-    pub async fn listening_addresses(&self) -> Result<Vec<Multiaddr>, Error> {
-        async move {
-            let (tx, rx) = oneshot_channel();
-            self.to_task.clone().send(IpfsEvent::Listeners(tx)).await?;
             rx.await?
         }
         .instrument(self.span.clone())
         .await
     }
 
-    /// Returns external addresses
-    pub async fn external_addresses(&self) -> Result<Vec<Multiaddr>, Error> {
+    /// Stop advertising the external address, pushing the change to the connected peers with
+    /// identify
+    pub async fn remove_external_address(&self, addr: Multiaddr) -> Result<(), Error> {
         async move {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
-                .send(IpfsEvent::ExternalAddresses(tx))
+                .send(IpfsEvent::RemoveExternalAddress(addr, tx))
                 .await?;
 
             rx.await?
@@ -1725,13 +3590,25 @@ impl Ipfs {
 
     /// Add a given multiaddr as a listening address. Will fail if the address is unsupported, or
     /// if it is already being listened on. Currently will invoke `Swarm::listen_on` internally,
-    /// returning the first `Multiaddr` that is being listened on.
+    /// returning the first `Multiaddr` that is being listened on, with the assigned port.
+    ///
+    /// An unspecified ip listens on every interface; use [`Ipfs::add_listening_addresses`] to
+    /// get all of their addresses.
     pub async fn add_listening_address(&self, addr: Multiaddr) -> Result<Multiaddr, Error> {
+        let addrs = self.add_listening_addresses(addr).await?;
+        addrs
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("listener did not report any address"))
+    }
+
+    /// Add a given multiaddr as a listening address, returning every `Multiaddr` it listens on
+    /// once confirmed by the transport, e.g. one per interface for an unspecified ip.
+    pub async fn add_listening_addresses(&self, addr: Multiaddr) -> Result<Vec<Multiaddr>, Error> {
         async move {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
                 .send(IpfsEvent::AddListeningAddress(addr, tx))
                 .await?;
 
@@ -1750,7 +3627,6 @@ impl Ipfs {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
                 .send(IpfsEvent::RemoveListeningAddress(addr, tx))
                 .await?;
 
@@ -1769,7 +3645,6 @@ impl Ipfs {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
                 .send(IpfsEvent::FindPeer(peer_id, false, tx))
                 .await?;
 
@@ -1782,7 +3657,6 @@ impl Ipfs {
                     let (tx, rx) = oneshot_channel();
 
                     self.to_task
-                        .clone()
                         .send(IpfsEvent::FindPeer(peer_id, true, tx))
                         .await?;
 
@@ -1799,17 +3673,20 @@ impl Ipfs {
 
     /// Performs a DHT lookup for providers of a value to the given key.
     ///
-    /// Returns a list of peers found providing the Cid.
-    pub async fn get_providers(&self, cid: Cid) -> Result<BoxStream<'static, PeerId>, Error> {
+    /// Returns a list of peers found providing the Cid. See [`ProvidersGet::deadline`] to end the
+    /// lookup early.
+    pub fn get_providers(&self, cid: Cid) -> ProvidersGet {
+        ProvidersGet::new(self.to_task.clone(), cid, self.span.clone())
+    }
+
+    /// Returns the number of DHT queries in progress
+    pub async fn dht_queries(&self) -> Result<usize, Error> {
         async move {
             let (tx, rx) = oneshot_channel();
 
-            self.to_task
-                .clone()
-                .send(IpfsEvent::GetProviders(cid, tx))
-                .await?;
+            self.to_task.send(IpfsEvent::DhtQueries(tx)).await?;
 
-            rx.await??.ok_or_else(|| anyhow!("Provider already exist"))
+            rx.await?
         }
         .instrument(self.span.clone())
         .await
@@ -1831,10 +3708,7 @@ impl Ipfs {
         let kad_result = async move {
             let (tx, rx) = oneshot_channel();
 
-            self.to_task
-                .clone()
-                .send(IpfsEvent::Provide(cid, tx))
-                .await?;
+            self.to_task.send(IpfsEvent::Provide(cid, tx)).await?;
 
             rx.await?
         }
@@ -1849,6 +3723,39 @@ impl Ipfs {
         }
     }
 
+    /// Starts providing the block like [`Ipfs::provide`], but returns as soon as the providing is
+    /// started instead of waiting for the provider record to be put to the closest peers. The
+    /// record is republished like the others, making this suited to providing many blocks.
+    pub async fn provide_lazy(&self, cid: Cid) -> Result<(), Error> {
+        // don't provide things we don't actually have
+        if !self.repo.contains(&cid).await? {
+            return Err(anyhow!(
+                "Error: block {} not found locally, cannot provide",
+                cid
+            ));
+        }
+
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::ProvideLazy(cid, tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the keys the node provides, as raw cids since the provider records are keyed by
+    /// multihash.
+    pub async fn provided(&self) -> Result<Vec<Cid>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::Provided(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
     /// Fetches the block, and, if set, recursively walk the graph loading all the blocks to the blockstore.
     pub fn fetch(&self, cid: &Cid) -> RepoFetch {
         self.repo.fetch(cid).span(self.span.clone())
@@ -1862,7 +3769,6 @@ impl Ipfs {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
                 .send(IpfsEvent::GetClosestPeers(peer_id, tx))
                 .await?;
 
@@ -1879,22 +3785,115 @@ impl Ipfs {
         }
     }
 
-    /// Change the DHT mode
-    pub async fn dht_mode(&self, mode: DhtMode) -> Result<(), Error> {
+    /// Returns the records and provider announcements stored for the peers through the DHT, and
+    /// the ones dropped as over the [`IpfsOptions::dht_write_limit`].
+    pub async fn dht_stats(&self) -> Result<DhtStats, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::DhtStats(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the records and provider records held by the kademlia store of the node, the
+    /// expired ones being removed periodically.
+    pub async fn dht_store(&self) -> Result<DhtStoreContents, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::DhtStore(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns which of the protocols are enabled on the node.
+    pub async fn enabled_components(&self) -> Result<Components, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::EnabledComponents(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Waits until the node meets the criteria, returning the counters then. Fails with
+    /// [`NotReady`](error::NotReady) holding the counters reached once the timeout elapses.
+    pub async fn wait_ready(&self, criteria: ReadyCriteria) -> Result<ReadyState, Error> {
         async move {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
-                .send(IpfsEvent::DhtMode(mode, tx))
+                .send(IpfsEvent::WaitReady(criteria, tx))
                 .await?;
 
+            if let Ok(result) = tokio::time::timeout(criteria.timeout, rx).await {
+                return result?;
+            }
+
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task.send(IpfsEvent::ReadyState(tx)).await?;
+
+            Err(error::NotReady(rx.await??).into())
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns a stream of the peers added to and evicted from the kademlia routing table from
+    /// now on. A subscriber falling behind by more than 256 events skips the oldest ones.
+    pub async fn dht_events(&self) -> Result<BoxStream<'static, DhtEvent>, Error> {
+        let mut receiver = async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::DhtEvents(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await?;
+
+        let stream = async_stream::stream! {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => yield event,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                        debug!("dht event subscriber skipped {skipped} events");
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        };
+
+        Ok(stream.boxed())
+    }
+
+    /// Change the DHT mode
+    pub async fn dht_mode(&self, mode: DhtMode) -> Result<(), Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task.send(IpfsEvent::DhtMode(mode, tx)).await?;
+
             rx.await?
         }
         .instrument(self.span.clone())
         .await
     }
 
+    fn dht_record_key(&self, key: &[u8]) -> Result<Key, Error> {
+        let key_str = String::from_utf8_lossy(key);
+
+        if let Ok((prefix, _)) = split_dht_key(&key_str) {
+            if let Some(key_fn) = self.record_key_validator.get(prefix) {
+                return key_fn(&key_str);
+            }
+        }
+        Ok(Key::from(key.to_vec()))
+    }
+
     /// Attempts to look a key up in the DHT and returns the values found in the records
     /// containing that key.
     pub async fn dht_get<T: AsRef<[u8]>>(
@@ -1902,26 +3901,30 @@ impl Ipfs {
         key: T,
     ) -> Result<BoxStream<'static, Record>, Error> {
         async move {
-            let key = key.as_ref();
+            let key = self.dht_record_key(key.as_ref())?;
 
-            let key_str = String::from_utf8_lossy(key);
+            let (tx, rx) = oneshot_channel();
 
-            let key = if let Ok((prefix, _)) = split_dht_key(&key_str) {
-                if let Some(key_fn) = self.record_key_validator.get(prefix) {
-                    key_fn(&key_str)?
-                } else {
-                    Key::from(key.to_vec())
-                }
-            } else {
-                Key::from(key.to_vec())
-            };
+            self.to_task.send(IpfsEvent::DhtGet(key, tx)).await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Looks a key up in the DHT like [`Ipfs::dht_get`], but fails with [`FacadeError::Busy`]
+    /// instead of waiting while the queue of requests to the background task is full.
+    pub async fn try_dht_get<T: AsRef<[u8]>>(
+        &self,
+        key: T,
+    ) -> Result<BoxStream<'static, Record>, Error> {
+        async move {
+            let key = self.dht_record_key(key.as_ref())?;
 
             let (tx, rx) = oneshot_channel();
 
-            self.to_task
-                .clone()
-                .send(IpfsEvent::DhtGet(key, tx))
-                .await?;
+            self.to_task.try_send(IpfsEvent::DhtGet(key, tx))?;
 
             rx.await?
         }
@@ -1929,6 +3932,11 @@ impl Ipfs {
         .await
     }
 
+    /// Number of requests queued for the background task, which it has not started handling yet.
+    pub fn queue_depth(&self) -> usize {
+        self.to_task.depth()
+    }
+
     /// Stores the given key + value record locally and replicates it in the DHT. It doesn't
     /// expire locally and is periodically replicated in the DHT, as per the `KademliaConfig`
     /// setup.
@@ -1956,7 +3964,6 @@ impl Ipfs {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
                 .send(IpfsEvent::DhtPut(key, value.into(), quorum, tx))
                 .await?;
 
@@ -1979,7 +3986,6 @@ impl Ipfs {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
                 .send(IpfsEvent::AddRelay(peer_id, addr, tx))
                 .await?;
 
@@ -1995,7 +4001,6 @@ impl Ipfs {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
                 .send(IpfsEvent::RemoveRelay(peer_id, addr, tx))
                 .await?;
 
@@ -2011,13 +4016,8 @@ impl Ipfs {
             let (tx, rx) = oneshot_channel();
 
             match active {
-                true => {
-                    self.to_task
-                        .clone()
-                        .send(IpfsEvent::ListActiveRelays(tx))
-                        .await?
-                }
-                false => self.to_task.clone().send(IpfsEvent::ListRelays(tx)).await?,
+                true => self.to_task.send(IpfsEvent::ListActiveRelays(tx)).await?,
+                false => self.to_task.send(IpfsEvent::ListRelays(tx)).await?,
             };
 
             rx.await?
@@ -2026,6 +4026,103 @@ impl Ipfs {
         .await
     }
 
+    /// Returns the peers discovered by mdns on the local network which have not expired, with
+    /// their addresses. Fails if mdns is disabled.
+    pub async fn mdns_peers(&self) -> Result<Vec<(PeerId, Vec<Multiaddr>)>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::MdnsPeers(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Enables or disables mdns, forgetting the discovered peers when disabled.
+    pub async fn set_mdns_enabled(&self, enabled: bool) -> Result<(), Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .send(IpfsEvent::SetMdnsEnabled(enabled, tx))
+                .await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the reachability of the node as reported by AutoNAT. Fails if AutoNAT is not
+    /// enabled.
+    pub async fn nat_status(&self) -> Result<AutonatStatus, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::NatStatus(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns a stream of the changes of the NAT status reported by AutoNAT.
+    pub fn nat_status_events(&self) -> BoxStream<'static, AutonatStatus> {
+        let mut receiver = self.nat_events.subscribe();
+        let stream = async_stream::stream! {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => yield event,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                        debug!("nat status subscriber skipped {skipped} events");
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        };
+
+        stream.boxed()
+    }
+
+    /// Adds a server AutoNAT probes the node with, in addition to the ones discovered through
+    /// identify. The address is needed if the peer is not in the addressbook.
+    pub async fn add_autonat_server(
+        &self,
+        peer_id: PeerId,
+        addr: Option<Multiaddr>,
+    ) -> Result<(), Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .send(IpfsEvent::AddAutonatServer(peer_id, addr, tx))
+                .await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the reservations and circuits of the relay server, see
+    /// [`UninitializedIpfs::with_relay_server`]. The circuits are reported in
+    /// [`Ipfs::connection_events`] as well.
+    pub async fn relay_server_stats(&self) -> Result<RelayServerStats, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::RelayServerStats(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the relay reservations and whether the node was reported private by AutoNAT.
+    pub async fn relay_status(&self) -> Result<RelayStatus, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::RelayStatus(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
     pub async fn enable_autorelay(&self) -> Result<(), Error> {
         Err(anyhow::anyhow!("Unimplemented"))
     }
@@ -2041,7 +4138,6 @@ impl Ipfs {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
                 .send(IpfsEvent::EnableRelay(peer_id, tx))
                 .await?;
 
@@ -2057,7 +4153,6 @@ impl Ipfs {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
                 .send(IpfsEvent::DisableRelay(peer_id, tx))
                 .await?;
 
@@ -2079,7 +4174,6 @@ impl Ipfs {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
                 .send(IpfsEvent::RegisterRendezvousNamespace(
                     namespace, peer_id, ttl, tx,
                 ))
@@ -2102,7 +4196,6 @@ impl Ipfs {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
                 .send(IpfsEvent::UnregisterRendezvousNamespace(
                     namespace, peer_id, tx,
                 ))
@@ -2127,7 +4220,6 @@ impl Ipfs {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
                 .send(IpfsEvent::RendezvousNamespaceDiscovery(
                     Some(namespace),
                     false,
@@ -2137,26 +4229,144 @@ impl Ipfs {
                 ))
                 .await?;
 
-            rx.await?
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Register our external addresses under the namespace on every configured rendezvous server,
+    /// renewing the registrations until the node stops. Succeeds once a server accepted it.
+    pub async fn rendezvous_register(&self, namespace: impl Into<String>) -> Result<(), Error> {
+        async move {
+            let namespace = Namespace::new(namespace.into())?;
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .send(IpfsEvent::RendezvousRegister(namespace, tx))
+                .await?;
+
+            let pending = rx.await??;
+            let mut last_error = anyhow::anyhow!("no rendezvous server configured");
+            for result in futures::future::join_all(pending).await {
+                match result {
+                    Ok(Ok(())) => return Ok(()),
+                    Ok(Err(e)) => last_error = e,
+                    Err(e) => last_error = e.into(),
+                }
+            }
+            Err(last_error)
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Discover the peers registered under the namespace on the configured rendezvous servers,
+    /// merging the results of the servers which replied.
+    pub async fn rendezvous_discover(
+        &self,
+        namespace: impl Into<String>,
+    ) -> Result<Vec<(PeerId, Vec<Multiaddr>)>, Error> {
+        async move {
+            let namespace = Namespace::new(namespace.into())?;
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .send(IpfsEvent::RendezvousDiscover(namespace, tx))
+                .await?;
+
+            let pending = rx.await??;
+            let local_peer_id = self.keypair().public().to_peer_id();
+            let mut last_error = anyhow::anyhow!("no rendezvous server configured");
+            let mut replied = false;
+            let mut peers: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
+            for result in futures::future::join_all(pending).await {
+                let registrations = match result {
+                    Ok(Ok(registrations)) => registrations,
+                    Ok(Err(e)) => {
+                        last_error = e;
+                        continue;
+                    }
+                    Err(e) => {
+                        last_error = e.into();
+                        continue;
+                    }
+                };
+                replied = true;
+                for (peer_id, addrs) in registrations {
+                    if peer_id == local_peer_id {
+                        continue;
+                    }
+                    let entry = peers.entry(peer_id).or_default();
+                    for addr in addrs {
+                        if !entry.contains(&addr) {
+                            entry.push(addr);
+                        }
+                    }
+                }
+            }
+
+            match replied {
+                true => Ok(Vec::from_iter(peers)),
+                false => Err(last_error),
+            }
         }
         .instrument(self.span.clone())
         .await
     }
 
-    /// Walk the given Iplds' links up to `max_depth` (or indefinitely for `None`). Will return
-    /// any duplicate trees unless `unique` is `true`.
+    /// Lists the links of the document at the path, like `ipfs refs`, breadth-first. The links of
+    /// dag-pb documents are listed with their names.
     ///
-    /// More information and a `'static` lifetime version available at [`refs::iplds_refs`].
-    pub fn refs<'a, Iter>(
-        &'a self,
-        iplds: Iter,
-        max_depth: Option<u64>,
-        unique: bool,
-    ) -> impl Stream<Item = Result<refs::Edge, libipld::error::Error>> + Send + 'a
-    where
-        Iter: IntoIterator<Item = (Cid, Ipld)> + Send + 'a,
-    {
-        refs::iplds_refs(self.repo(), iplds, max_depth, unique)
+    /// The blocks which cannot be loaded are listed as errors without ending the stream.
+    ///
+    /// To walk already resolved documents, see [`refs::iplds_refs`].
+    pub async fn refs(
+        &self,
+        path: IpfsPath,
+        options: refs::RefsOptions,
+    ) -> Result<BoxStream<'static, Result<refs::Ref, refs::IpldRefsError>>, Error> {
+        async move {
+            let (node, _) = self.dag().resolve(path, true, &[], options.offline).await?;
+
+            let (cid, ipld) = match node {
+                dag::ResolvedNode::Block(block) => {
+                    (*block.cid(), block.decode::<libipld::IpldCodec, Ipld>()?)
+                }
+                dag::ResolvedNode::Projection(cid, ipld) => (cid, ipld),
+                // the data of a dag-pb document has no links, and the links are followed
+                dag::ResolvedNode::DagPbData(..) | dag::ResolvedNode::Link(..) => {
+                    return Ok(futures::stream::empty().boxed())
+                }
+            };
+
+            let max_depth = match options.recursive {
+                true => options.max_depth,
+                false => Some(1),
+            };
+
+            let mut refs = refs::IpldRefs::default().with_inline_errors();
+            if let Some(depth) = max_depth {
+                refs = refs.with_max_depth(depth);
+            }
+            if options.unique {
+                refs = refs.with_only_unique();
+            }
+            if options.offline {
+                refs = refs.with_existing_blocks();
+            }
+
+            let edges = options.edges;
+            Ok(refs
+                .refs_of_resolved(self.repo.clone(), vec![(cid, ipld)])
+                .map_ok(move |edge| match edges {
+                    true => refs::Ref::Edge(edge),
+                    false => refs::Ref::Cid(edge.destination),
+                })
+                .boxed())
+        }
+        .instrument(self.span.clone())
+        .await
     }
 
     /// Obtain the list of addresses of bootstrapper nodes that are currently used.
@@ -2164,10 +4374,7 @@ impl Ipfs {
         async move {
             let (tx, rx) = oneshot_channel();
 
-            self.to_task
-                .clone()
-                .send(IpfsEvent::GetBootstrappers(tx))
-                .await?;
+            self.to_task.send(IpfsEvent::GetBootstrappers(tx)).await?;
 
             Ok(rx.await?)
         }
@@ -2183,7 +4390,6 @@ impl Ipfs {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
                 .send(IpfsEvent::AddBootstrapper(addr, tx))
                 .await?;
 
@@ -2201,7 +4407,6 @@ impl Ipfs {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
                 .send(IpfsEvent::RemoveBootstrapper(addr, tx))
                 .await?;
 
@@ -2216,10 +4421,7 @@ impl Ipfs {
         async move {
             let (tx, rx) = oneshot_channel();
 
-            self.to_task
-                .clone()
-                .send(IpfsEvent::ClearBootstrappers(tx))
-                .await?;
+            self.to_task.send(IpfsEvent::ClearBootstrappers(tx)).await?;
 
             rx.await?
         }
@@ -2230,12 +4432,37 @@ impl Ipfs {
     /// Restore the originally configured bootstrapper node list by adding them to the list of the
     /// currently used bootstrapper node address list; returns the restored addresses.
     pub async fn default_bootstrap(&self) -> Result<Vec<Multiaddr>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task.send(IpfsEvent::DefaultBootstrap(tx)).await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns when the node bootstraps the DHT by itself.
+    pub async fn bootstrap_policy(&self) -> Result<BootstrapPolicy, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task.send(IpfsEvent::BootstrapPolicy(tx)).await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Replaces when the node bootstraps the DHT by itself, restarting the interval.
+    pub async fn set_bootstrap_policy(&self, policy: BootstrapPolicy) -> Result<(), Error> {
         async move {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
-                .clone()
-                .send(IpfsEvent::DefaultBootstrap(tx))
+                .send(IpfsEvent::SetBootstrapPolicy(policy, tx))
                 .await?;
 
             rx.await?
@@ -2252,7 +4479,7 @@ impl Ipfs {
     pub async fn bootstrap(&self) -> Result<JoinHandle<Result<KadResult, Error>>, Error> {
         let (tx, rx) = oneshot_channel();
 
-        self.to_task.clone().send(IpfsEvent::Bootstrap(tx)).await?;
+        self.to_task.send(IpfsEvent::Bootstrap(tx)).await?;
         let fut = rx.await??;
 
         let bootstrap_task =
@@ -2261,6 +4488,19 @@ impl Ipfs {
         Ok(bootstrap_task)
     }
 
+    /// Bootstraps like [`Ipfs::bootstrap`], waiting for the bootstrap to complete and reporting
+    /// which of the configured bootstrap nodes were connected to. A bootstrap which timed out is
+    /// reported as well, with the nodes which did not respond as failed.
+    pub async fn bootstrap_report(&self) -> Result<BootstrapReport, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.send(IpfsEvent::BootstrapReport(tx)).await?;
+            rx.await??.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
     /// Add address of a peer to the address book
     pub async fn add_peer(&self, peer_id: PeerId, mut addr: Multiaddr) -> Result<(), Error> {
         if matches!(addr.iter().last(), Some(Protocol::P2p(_))) {
@@ -2270,7 +4510,6 @@ impl Ipfs {
         let (tx, rx) = oneshot::channel();
 
         self.to_task
-            .clone()
             .send(IpfsEvent::AddPeer(peer_id, addr, tx))
             .await?;
 
@@ -2284,7 +4523,6 @@ impl Ipfs {
         let (tx, rx) = oneshot::channel();
 
         self.to_task
-            .clone()
             .send(IpfsEvent::RemovePeer(peer_id, None, tx))
             .await?;
 
@@ -2300,7 +4538,6 @@ impl Ipfs {
         let (tx, rx) = oneshot::channel();
 
         self.to_task
-            .clone()
             .send(IpfsEvent::RemovePeer(peer_id, Some(addr), tx))
             .await?;
 
@@ -2311,10 +4548,7 @@ impl Ipfs {
     pub async fn get_bitswap_peers(&self) -> Result<Vec<PeerId>, Error> {
         let (tx, rx) = oneshot_channel();
 
-        self.to_task
-            .clone()
-            .send(IpfsEvent::GetBitswapPeers(tx))
-            .await?;
+        self.to_task.send(IpfsEvent::GetBitswapPeers(tx)).await?;
 
         Ok(rx.await??.await)
     }
@@ -2329,14 +4563,242 @@ impl Ipfs {
         &self.keystore
     }
 
+    /// Observe libp2p swarm events from now on, as with
+    /// [`UninitializedIpfs::register_swarm_observer`]. Fails if `C` is not the custom behaviour
+    /// the node was started with.
+    pub async fn add_swarm_observer<C, F>(&self, func: F) -> Result<(), Error>
+    where
+        C: NetworkBehaviour,
+        F: Fn(&TSwarmEvent<C>) + Sync + Send + 'static,
+    {
+        async move {
+            let observer: TSwarmObserverFn<C> = Arc::new(func);
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .send(IpfsEvent::AddSwarmObserver(Box::new(observer), tx))
+                .await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the events of the custom behaviour set with
+    /// [`UninitializedIpfs::with_custom_behaviour`]. The events are delivered to the latest
+    /// stream only, ending the previous one, and dropped while there is none. Fails if `C` is
+    /// not the custom behaviour the node was started with.
+    pub async fn custom_events<C>(&self) -> Result<BoxStream<'static, C::ToSwarm>, Error>
+    where
+        C: NetworkBehaviour,
+    {
+        async move {
+            let (tx, rx) = unbounded::<C::ToSwarm>();
+            let (ret, ret_rx) = oneshot_channel();
+            self.to_task
+                .send(IpfsEvent::CustomEvents(Box::new(tx), ret))
+                .await?;
+            ret_rx.await??;
+            Ok(rx.boxed())
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Runs the closure with the custom behaviour set with
+    /// [`UninitializedIpfs::with_custom_behaviour`] on the background task, e.g. to send a
+    /// request or to respond to one received from [`Ipfs::custom_events`], returning its result.
+    /// Fails if the node has no custom behaviour or if `C` is not its type.
+    pub async fn with_custom_behaviour<C, F, R>(&self, func: F) -> Result<R, Error>
+    where
+        C: NetworkBehaviour,
+        F: FnOnce(&mut C) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            let func: TCustomBehaviourFn<C> = Box::new(move |behaviour| {
+                let _ = tx.send(func(behaviour));
+            });
+            let (ret, ret_rx) = oneshot_channel();
+            self.to_task
+                .send(IpfsEvent::WithCustomBehaviour(Box::new(func), ret))
+                .await?;
+            ret_rx.await??;
+            Ok(rx.await?)
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the handle on the background task of the node, to wait for it to exit and find out
+    /// why, e.g. after a panic, or to abort it. The requests made once the task exited fail with
+    /// [`FacadeError::NodeShutdown`] giving the same reason.
+    pub fn task_handle(&self) -> TaskHandle {
+        self.task_handle.clone()
+    }
+
     /// Exit daemon.
     pub async fn exit_daemon(mut self) {
         // FIXME: this is a stopgap measure needed while repo is part of the struct Ipfs instead of
         // the background task or stream. After that this could be handled by dropping.
-        self.repo.shutdown();
+        if !self.shared_repo {
+            self.repo.shutdown();
+        }
 
         // ignoring the error because it'd mean that the background task had already been dropped
-        let _ = self.to_task.try_send(IpfsEvent::Exit);
+        let _ = self.to_task.send(IpfsEvent::Exit).await;
+    }
+
+    /// Shuts the node down, waiting up to `timeout` for the background task to close the
+    /// listeners, stop the bitswap sessions and complete the pending writes of the repo and of the
+    /// addressbook. The background task is aborted once the timeout elapses, as it is when every
+    /// `Ipfs` is dropped.
+    pub async fn shutdown_graceful(self, timeout: Duration) -> Result<(), Error> {
+        async move {
+            // the bitswap sessions stopped by the task keep their persisted wants
+            if !self.shared_repo {
+                self.repo.stop_persisting_wants();
+            }
+
+            let (tx, rx) = oneshot_channel();
+            let result = tokio::time::timeout(timeout, async {
+                self.to_task.send(IpfsEvent::Shutdown(timeout, tx)).await?;
+                rx.await?
+            })
+            .await;
+
+            if !self.shared_repo {
+                self.repo.shutdown();
+            }
+            self.token.cancel();
+            let task = self.task.lock().take();
+            if let Some(task) = task {
+                let _ = task.await;
+            }
+
+            result.map_err(|_| anyhow!("shutdown did not complete within {timeout:?}"))?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+}
+
+/// Retrieves a block, see [`Ipfs::get_block`]. Can be awaited right away, the block being wanted
+/// on the first poll.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct BlockGet {
+    repo: Repo,
+    cid: Cid,
+    deadline: Option<Duration>,
+    span: Span,
+    fetch: Option<BoxFuture<'static, Result<Block, Error>>>,
+}
+
+impl BlockGet {
+    fn new(repo: Repo, cid: Cid, span: Span) -> Self {
+        Self {
+            repo,
+            cid,
+            deadline: None,
+            span,
+            fetch: None,
+        }
+    }
+
+    /// Time given to fetch the block before failing with [`error::Timeout`], after which it is no
+    /// longer wanted
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+}
+
+impl std::future::Future for BlockGet {
+    type Output = Result<Block, Error>;
+
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        if self.fetch.is_none() {
+            let repo = self.repo.clone();
+            let cid = self.cid;
+            let deadline = self.deadline;
+            let fetch = async move {
+                repo.get_block_with_session(None, &cid, &[], false, deadline)
+                    .await
+            }
+            .instrument(self.span.clone())
+            .boxed();
+            self.fetch = Some(fetch);
+        }
+
+        self.fetch
+            .as_mut()
+            .expect("the fetch was started")
+            .poll_unpin(cx)
+    }
+}
+
+/// DHT lookup for the providers of a Cid, see [`Ipfs::get_providers`]. Can be awaited right away,
+/// the lookup starting on the first poll.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct ProvidersGet {
+    to_task: FacadeSender,
+    cid: Cid,
+    deadline: Option<Duration>,
+    span: Span,
+    lookup: Option<BoxFuture<'static, Result<BoxStream<'static, PeerId>, Error>>>,
+}
+
+impl ProvidersGet {
+    fn new(to_task: FacadeSender, cid: Cid, span: Span) -> Self {
+        Self {
+            to_task,
+            cid,
+            deadline: None,
+            span,
+            lookup: None,
+        }
+    }
+
+    /// Time given to the lookup, after which the stream of providers ends. The lookup is then
+    /// finished, unless it is shared with other lookups of the same Cid.
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+}
+
+impl std::future::Future for ProvidersGet {
+    type Output = Result<BoxStream<'static, PeerId>, Error>;
+
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        if self.lookup.is_none() {
+            let to_task = self.to_task.clone();
+            let cid = self.cid;
+            let deadline = self.deadline;
+            let lookup = async move {
+                let (tx, rx) = oneshot_channel();
+
+                to_task
+                    .send(IpfsEvent::GetProviders(cid, deadline, tx))
+                    .await?;
+
+                rx.await??.ok_or_else(|| anyhow!("Provider already exist"))
+            }
+            .instrument(self.span.clone())
+            .boxed();
+            self.lookup = Some(lookup);
+        }
+
+        self.lookup
+            .as_mut()
+            .expect("the lookup was started")
+            .poll_unpin(cx)
     }
 }
 
@@ -2482,7 +4944,7 @@ mod node {
                     return Ok(());
                 }
             }
-            self.ipfs.connect(opts).await
+            Ok(self.ipfs.connect(opts).await?)
         }
 
         /// Returns a new `Node` based on `IpfsOptions`.
@@ -2500,6 +4962,11 @@ mod node {
                 None => vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
             };
 
+            Self::with_builder(uninit, list).await
+        }
+
+        /// Starts a `Node` from the builder, listening on the given addresses.
+        pub async fn with_builder(uninit: UninitializedIpfsNoop, list: Vec<Multiaddr>) -> Self {
             let ipfs = uninit.start().await.unwrap();
 
             ipfs.dht_mode(DhtMode::Server).await.unwrap();
@@ -2575,6 +5042,7 @@ mod node {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use dag::ResolveError;
     use libipld::{
         ipld,
         multihash::{Code, MultihashDigest},
@@ -2589,9 +5057,225 @@ mod tests {
         let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
         let block = Block::new(cid, data).unwrap();
 
-        let cid: Cid = ipfs.put_block(block.clone()).await.unwrap();
+        let (cid, put) = ipfs.put_block(block.clone()).await.unwrap();
+        assert_eq!(put, BlockPut::NewBlock);
         let new_block = ipfs.get_block(&cid).await.unwrap();
         assert_eq!(block, new_block);
+
+        let (_, put) = ipfs.put_block(block).await.unwrap();
+        assert_eq!(put, BlockPut::Existed);
+    }
+
+    #[tokio::test]
+    async fn get_block_local_does_not_fetch() {
+        let ipfs = Node::new("test_node").await;
+
+        let data = b"not stored\n".to_vec();
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+
+        let e = ipfs.get_block_local(&cid).await.unwrap_err();
+        assert_eq!(e.downcast_ref(), Some(&repo::BlockNotLocal(cid)));
+    }
+
+    #[tokio::test]
+    async fn local_only_dag_and_unixfs_reads_do_not_fetch() {
+        let ipfs = Node::new("test_node").await;
+
+        let missing = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(b"missing"));
+        let root = ipfs.put_dag(ipld!({ "link": missing })).await.unwrap();
+
+        assert_eq!(
+            ipfs.dag_get_local(root).await.unwrap(),
+            ipld!({ "link": missing })
+        );
+        let path = IpfsPath::from(root).sub_path("link").unwrap();
+        match ipfs.dag_get_local(path).await.unwrap_err() {
+            ResolveError::Loading(cid, e) => {
+                assert_eq!(cid, missing);
+                assert_eq!(e.downcast_ref(), Some(&repo::BlockNotLocal(missing)));
+            }
+            e => panic!("unexpected error: {e}"),
+        }
+
+        let e = ipfs
+            .cat_unixfs(missing)
+            .fetch_policy(FetchPolicy::LocalOnly)
+            .next()
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert!(matches!(
+            e,
+            unixfs::TraversalFailed::Resolving(ResolveError::Loading(cid, _)) if cid == missing
+        ));
+    }
+
+    #[tokio::test]
+    async fn offline_node_keeps_local_operations() {
+        let ipfs = UninitializedIpfsNoop::new()
+            .with_default()
+            .set_offline(true)
+            .start()
+            .await
+            .unwrap();
+
+        let data = b"hello block\n".to_vec();
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let block = Block::new(cid, data).unwrap();
+        ipfs.put_block(block.clone()).await.unwrap();
+        assert_eq!(ipfs.get_block(&cid).await.unwrap(), block);
+
+        let missing = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(b"missing"));
+        let e = ipfs.get_block(&missing).await.unwrap_err();
+        assert_eq!(e.downcast_ref(), Some(&repo::BlockNotLocal(missing)));
+
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+        ipfs.connect(addr).await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn pruning_keeps_whitelisted_peers() {
+        let ipfs = UninitializedIpfsNoop::new()
+            .with_default()
+            .set_connection_limits(ConnectionLimits {
+                high_water: Some(1),
+                low_water: Some(1),
+                ..Default::default()
+            })
+            .start()
+            .await
+            .unwrap();
+
+        let kept = Node::new("kept").await;
+        let others = [Node::new("a").await, Node::new("b").await];
+
+        ipfs.connect(kept.addrs[0].clone()).await.unwrap();
+        for node in &others {
+            ipfs.connect(node.addrs[0].clone()).await.unwrap();
+        }
+        ipfs.whitelist_peer(kept.id).await.unwrap();
+
+        ipfs.prune_connections().await.unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while ipfs.connected().await.unwrap().len() > 1 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("peers were pruned");
+
+        assert_eq!(ipfs.connected().await.unwrap(), vec![kept.id]);
+
+        // nothing is pruned within the limit
+        ipfs.prune_connections().await.unwrap();
+        assert!(ipfs.is_connected(kept.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn ping_does_not_wait_for_the_next_interval() {
+        // pinged every 15 seconds by the keep-alive pings
+        let ipfs = UninitializedIpfsNoop::new()
+            .with_default()
+            .start()
+            .await
+            .unwrap();
+        let peer = Node::new("peer").await;
+        ipfs.add_peer(peer.id, peer.addrs[0].clone()).await.unwrap();
+
+        // connects to the peer, measuring the first round-trip time
+        let first = ipfs.ping(peer.id, Some(1)).await.unwrap();
+        let first = tokio::time::timeout(Duration::from_secs(5), first.collect::<Vec<_>>())
+            .await
+            .expect("first sample was not received");
+        assert!(matches!(first[..], [Ok(_)]));
+
+        // once connected, the peer is pinged on demand
+        let samples = ipfs.ping(peer.id, Some(3)).await.unwrap();
+        let samples = tokio::time::timeout(Duration::from_secs(5), samples.collect::<Vec<_>>())
+            .await
+            .expect("samples waited for the next interval");
+        assert_eq!(samples.len(), 3);
+        assert!(samples.iter().all(Result::is_ok));
+    }
+
+    #[tokio::test]
+    async fn ping_streams_receive_every_sample() {
+        let ipfs = UninitializedIpfsNoop::new()
+            .with_default()
+            .with_ping(PingConfig::new().with_interval(Duration::from_millis(100)))
+            .start()
+            .await
+            .unwrap();
+        let peer = Node::new("peer").await;
+        ipfs.add_peer(peer.id, peer.addrs[0].clone()).await.unwrap();
+
+        // connects to the peer
+        let first = ipfs.ping(peer.id, Some(2)).await.unwrap();
+        let second = ipfs.ping(peer.id, Some(2)).await.unwrap();
+
+        let (first, second) = tokio::time::timeout(
+            Duration::from_secs(5),
+            futures::future::join(first.collect::<Vec<_>>(), second.collect::<Vec<_>>()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(first.len(), 2);
+        assert_eq!(second.len(), 2);
+        assert!(first.iter().chain(&second).all(Result::is_ok));
+
+        let mut unbounded = ipfs.ping(peer.id, None).await.unwrap();
+        ipfs.disconnect(peer.id).await.unwrap();
+        let last = tokio::time::timeout(Duration::from_secs(5), async {
+            let mut last = None;
+            while let Some(result) = unbounded.next().await {
+                last = Some(result);
+            }
+            last
+        })
+        .await
+        .unwrap();
+        assert!(matches!(last, Some(Err(_))));
+    }
+
+    #[tokio::test]
+    async fn protected_peers_are_kept_alive_and_redialed() {
+        let ipfs = UninitializedIpfsNoop::new()
+            .with_default()
+            .set_idle_connection_timeout(1)
+            .start()
+            .await
+            .unwrap();
+        let peer = Node::new("peer").await;
+        ipfs.add_peer(peer.id, peer.addrs[0].clone()).await.unwrap();
+
+        ipfs.connect(peer.id).await.unwrap();
+        ipfs.protect_peer(peer.id, "cluster").await.unwrap();
+        ipfs.protect_peer(peer.id, "cluster").await.unwrap();
+
+        // outlives the idle timeout
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert!(ipfs.is_connected(peer.id).await.unwrap());
+
+        ipfs.disconnect(peer.id).await.unwrap();
+        let status = ipfs.peer_protection_status().await.unwrap();
+        assert_eq!(status.len(), 1);
+        assert_eq!(status[0].tags, vec![("cluster".to_string(), 2)]);
+        assert!(!status[0].connected);
+        assert!(status[0].next_attempt.is_some());
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !ipfs.is_connected(peer.id).await.unwrap() {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await
+        .expect("reconnected");
+
+        assert!(ipfs.unprotect_peer(peer.id, "cluster").await.unwrap());
+        assert!(ipfs.unprotect_peer(peer.id, "cluster").await.unwrap());
+        assert!(!ipfs.unprotect_peer(peer.id, "cluster").await.unwrap());
+        assert!(ipfs.peer_protection_status().await.unwrap().is_empty());
     }
 
     #[tokio::test]
@@ -2615,4 +5299,107 @@ mod tests {
         ipfs.remove_pin(&cid).await.unwrap();
         assert!(!ipfs.is_pinned(&cid).await.unwrap());
     }
+
+    #[tokio::test]
+    async fn test_cancel_recursive_pin() {
+        let ipfs = Node::new("test_node").await;
+
+        let child = ipfs.put_dag(ipld!("present")).await.unwrap();
+        let missing = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(b"missing"));
+        let root = ipfs.put_dag(ipld!([child, missing])).await.unwrap();
+
+        let handle = ipfs.insert_pin(&root).recursive().handle();
+
+        // the walk loads the child and then waits for the missing block
+        let mut progress = handle.progress();
+        while let Some(update) = progress.next().await {
+            if update.fetched_blocks == 2 {
+                assert_eq!(update.known_remaining, 1);
+                break;
+            }
+        }
+
+        // blocks of the pin in progress are kept
+        ipfs.gc().await.unwrap();
+        assert!(ipfs.repo().contains(&root).await.unwrap());
+        assert!(ipfs.repo().contains(&child).await.unwrap());
+
+        handle.cancel();
+        handle.await.unwrap_err();
+
+        let pins = ipfs
+            .list_pins(None)
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert!(pins.is_empty(), "{pins:?}");
+        assert!(!ipfs.is_pinned(&root).await.unwrap());
+        assert!(!ipfs.is_pinned(&child).await.unwrap());
+
+        // and no longer kept once rolled back
+        ipfs.gc().await.unwrap();
+        assert!(!ipfs.repo().contains(&child).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_named_pins() {
+        let ipfs = Node::new("test_node").await;
+
+        let first = ipfs.put_dag(ipld!("first")).await.unwrap();
+        let second = ipfs.put_dag(ipld!("second")).await.unwrap();
+        let other = ipfs.put_dag(ipld!("other")).await.unwrap();
+
+        ipfs.insert_pin(&first)
+            .name("backup/first")
+            .metadata([("origin", "test")])
+            .await
+            .unwrap();
+        ipfs.insert_pin(&second)
+            .recursive()
+            .name("backup/second")
+            .await
+            .unwrap();
+        ipfs.insert_pin(&other).await.unwrap();
+
+        let mut pins = ipfs
+            .list_pins(None)
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        pins.sort_by(|a, b| a.2.cmp(&b.2));
+        assert_eq!(
+            pins,
+            vec![
+                (other, PinMode::Direct, None),
+                (first, PinMode::Direct, Some("backup/first".into())),
+                (second, PinMode::Recursive, Some("backup/second".into())),
+            ]
+        );
+
+        let mut named = ipfs
+            .pin_ls_by_name("backup/")
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        named.sort_by(|a, b| a.2.name.cmp(&b.2.name));
+        assert_eq!(named.len(), 2);
+        assert_eq!(named[0].0, first);
+        assert_eq!(
+            named[0].2.metadata.get("origin").map(String::as_str),
+            Some("test")
+        );
+        assert_eq!(named[1].0, second);
+
+        ipfs.remove_pin(&first).await.unwrap();
+        let named = ipfs
+            .pin_ls_by_name("backup/f")
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert!(named.is_empty());
+    }
 }
@@ -0,0 +1,133 @@
+//! Metrics of the node in a prometheus registry, named after the metrics of kubo where it has an
+//! equivalent so that its dashboards can be reused.
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use prometheus_client::encoding::text::encode;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::registry::Registry;
+
+use crate::p2p::BandwidthStats;
+
+#[derive(Clone)]
+pub(crate) struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+    pub(crate) connected_peers: Gauge,
+    pub(crate) known_addresses: Gauge,
+    pub(crate) pending_kad_queries: Gauge,
+    pub(crate) wantlist_length: Gauge,
+    pub(crate) repo_blocks: Gauge,
+    sent_bytes: Counter,
+    received_bytes: Counter,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let mut registry = Registry::default();
+
+        let connected_peers = Gauge::default();
+        registry.register(
+            "ipfs_p2p_peers_total",
+            "Number of connected peers",
+            connected_peers.clone(),
+        );
+
+        let known_addresses = Gauge::default();
+        registry.register(
+            "ipfs_p2p_known_addresses",
+            "Number of addresses in the addressbook",
+            known_addresses.clone(),
+        );
+
+        let pending_kad_queries = Gauge::default();
+        registry.register(
+            "ipfs_dht_pending_queries",
+            "Number of kademlia queries in progress",
+            pending_kad_queries.clone(),
+        );
+
+        let wantlist_length = Gauge::default();
+        registry.register(
+            "ipfs_bitswap_wantlist_total",
+            "Number of blocks in the local wantlist",
+            wantlist_length.clone(),
+        );
+
+        let repo_blocks = Gauge::default();
+        registry.register(
+            "ipfs_repo_blocks",
+            "Number of blocks in the repo",
+            repo_blocks.clone(),
+        );
+
+        // counters are suffixed with `_total` when encoded
+        let sent_bytes = Counter::default();
+        registry.register(
+            "ipfs_p2p_sent_bytes",
+            "Bytes sent to the peers",
+            sent_bytes.clone(),
+        );
+
+        let received_bytes = Counter::default();
+        registry.register(
+            "ipfs_p2p_received_bytes",
+            "Bytes received from the peers",
+            received_bytes.clone(),
+        );
+
+        Self {
+            registry: Arc::new(Mutex::new(registry)),
+            connected_peers,
+            known_addresses,
+            pending_kad_queries,
+            wantlist_length,
+            repo_blocks,
+            sent_bytes,
+            received_bytes,
+        }
+    }
+}
+
+impl Metrics {
+    /// Sets the byte counters to the totals of the bandwidth counters.
+    pub(crate) fn set_bandwidth(&self, stats: BandwidthStats) {
+        self.sent_bytes
+            .inner()
+            .store(stats.total_outbound, Ordering::Relaxed);
+        self.received_bytes
+            .inner()
+            .store(stats.total_inbound, Ordering::Relaxed);
+    }
+
+    /// Returns the metrics in the text exposition format.
+    pub(crate) fn encode(&self) -> String {
+        let mut buffer = String::new();
+        // writing to a string does not fail
+        let _ = encode(&mut buffer, &self.registry.lock());
+        buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_are_encoded() {
+        let metrics = Metrics::default();
+        metrics.connected_peers.set(3);
+        metrics.set_bandwidth(BandwidthStats {
+            total_inbound: 10,
+            total_outbound: 20,
+            ..Default::default()
+        });
+
+        let encoded = metrics.encode();
+        assert!(encoded.contains("ipfs_p2p_peers_total 3"), "{encoded}");
+        assert!(encoded.contains("ipfs_p2p_received_bytes_total 10"));
+        assert!(encoded.contains("ipfs_p2p_sent_bytes_total 20"));
+        assert!(encoded.ends_with("# EOF\n"));
+    }
+}
@@ -1,3 +1,5 @@
+use std::net::IpAddr;
+
 use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
 
 pub trait MultiaddrExt {
@@ -23,6 +25,9 @@ pub trait MultiaddrExt {
 
     /// Determine if address is private address
     fn is_private(&self) -> bool;
+
+    /// Determine if the address has an unspecified ip, listening on every interface
+    fn is_unspecified(&self) -> bool;
 }
 
 impl MultiaddrExt for Multiaddr {
@@ -111,6 +116,103 @@ impl MultiaddrExt for Multiaddr {
             _ => false,
         })
     }
+
+    fn is_unspecified(&self) -> bool {
+        self.iter().any(|proto| match proto {
+            Protocol::Ip4(ip) => ip.is_unspecified(),
+            Protocol::Ip6(ip) => ip.is_unspecified(),
+            _ => false,
+        })
+    }
+}
+
+/// Which interface addresses the unspecified listening addresses are expanded into.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterfaceFilter {
+    /// Include the loopback addresses
+    pub loopback: bool,
+    /// Include the link-local addresses
+    pub link_local: bool,
+}
+
+impl InterfaceFilter {
+    fn allows(&self, ip: &IpAddr) -> bool {
+        let link_local = match ip {
+            IpAddr::V4(ip) => ip.is_link_local(),
+            IpAddr::V6(ip) => (ip.segments()[0] & 0xffc0) == 0xfe80,
+        };
+
+        (self.loopback || !ip.is_loopback()) && (self.link_local || !link_local)
+    }
+}
+
+/// Returns the addresses of the network interfaces of the machine, sorted, or none if they
+/// cannot be detected.
+#[cfg(feature = "interface_addrs")]
+pub(crate) fn interface_addresses() -> Vec<IpAddr> {
+    match if_addrs::get_if_addrs() {
+        Ok(interfaces) => {
+            let mut addrs = interfaces
+                .iter()
+                .map(|interface| interface.ip())
+                .collect::<Vec<_>>();
+            addrs.sort_unstable();
+            addrs.dedup();
+            addrs
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to detect the interface addresses");
+            vec![]
+        }
+    }
+}
+
+/// Interface detection is disabled without the `interface_addrs` feature.
+#[cfg(not(feature = "interface_addrs"))]
+pub(crate) fn interface_addresses() -> Vec<IpAddr> {
+    vec![]
+}
+
+/// Expands an address with an unspecified ip into one address per interface address of the same
+/// family allowed by the filter. Other addresses, and unspecified ones when no interface of their
+/// family is known, are returned as they are.
+pub(crate) fn expand_unspecified(
+    addr: &Multiaddr,
+    interfaces: &[IpAddr],
+    filter: InterfaceFilter,
+) -> Vec<Multiaddr> {
+    let Some(index) = addr.iter().position(|proto| match proto {
+        Protocol::Ip4(ip) => ip.is_unspecified(),
+        Protocol::Ip6(ip) => ip.is_unspecified(),
+        _ => false,
+    }) else {
+        return vec![addr.clone()];
+    };
+
+    let v4 = matches!(addr.iter().nth(index), Some(Protocol::Ip4(_)));
+    let family = interfaces
+        .iter()
+        .filter(|ip| ip.is_ipv4() == v4)
+        .collect::<Vec<_>>();
+
+    if family.is_empty() {
+        return vec![addr.clone()];
+    }
+
+    family
+        .into_iter()
+        .filter(|ip| filter.allows(ip))
+        .map(|ip| {
+            addr.iter()
+                .enumerate()
+                .map(|(i, proto)| match (i == index, ip) {
+                    (true, IpAddr::V4(ip)) => Protocol::Ip4(*ip),
+                    (true, IpAddr::V6(ip)) => Protocol::Ip6(*ip),
+                    (false, _) => proto,
+                })
+                .collect()
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -138,4 +240,69 @@ mod tests {
 
         assert_eq!(peer_id_target, peer_id);
     }
+
+    #[test]
+    fn unspecified_addresses() {
+        let any = Multiaddr::from_str("/ip4/0.0.0.0/tcp/0").expect("Valid multiaddr");
+        let any_v6 = Multiaddr::from_str("/ip6/::/udp/0/quic-v1").expect("Valid multiaddr");
+        let loopback = Multiaddr::from_str("/ip4/127.0.0.1/tcp/0").expect("Valid multiaddr");
+
+        assert!(any.is_unspecified());
+        assert!(any_v6.is_unspecified());
+        assert!(!loopback.is_unspecified());
+    }
+
+    #[test]
+    fn unspecified_addresses_are_expanded() {
+        let interfaces = [
+            "127.0.0.1".parse().unwrap(),
+            "169.254.10.1".parse().unwrap(),
+            "192.168.1.20".parse().unwrap(),
+            "::1".parse().unwrap(),
+            "fe80::1".parse().unwrap(),
+            "2001:db8::1".parse().unwrap(),
+        ];
+        let any = Multiaddr::from_str("/ip4/0.0.0.0/tcp/4001").expect("Valid multiaddr");
+        let any_v6 = Multiaddr::from_str("/ip6/::/udp/4001/quic-v1").expect("Valid multiaddr");
+
+        assert_eq!(
+            expand_unspecified(&any, &interfaces, InterfaceFilter::default()),
+            ["/ip4/192.168.1.20/tcp/4001".parse::<Multiaddr>().unwrap()]
+        );
+        assert_eq!(
+            expand_unspecified(&any_v6, &interfaces, InterfaceFilter::default()),
+            ["/ip6/2001:db8::1/udp/4001/quic-v1"
+                .parse::<Multiaddr>()
+                .unwrap()]
+        );
+
+        let all = InterfaceFilter {
+            loopback: true,
+            link_local: true,
+        };
+        assert_eq!(
+            expand_unspecified(&any, &interfaces, all),
+            [
+                "/ip4/127.0.0.1/tcp/4001".parse::<Multiaddr>().unwrap(),
+                "/ip4/169.254.10.1/tcp/4001".parse().unwrap(),
+                "/ip4/192.168.1.20/tcp/4001".parse().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_interfaces_keep_the_address() {
+        let any = Multiaddr::from_str("/ip6/::/tcp/4001").expect("Valid multiaddr");
+        let specific = Multiaddr::from_str("/ip4/10.0.0.1/tcp/4001").expect("Valid multiaddr");
+        let interfaces = ["10.0.0.1".parse().unwrap()];
+
+        assert_eq!(
+            expand_unspecified(&any, &interfaces, InterfaceFilter::default()),
+            [any.clone()]
+        );
+        assert_eq!(
+            expand_unspecified(&specific, &interfaces, InterfaceFilter::default()),
+            [specific.clone()]
+        );
+    }
 }
@@ -0,0 +1,210 @@
+//! Filtering of the local addresses advertised to the other peers, see [`AddressFilter`].
+
+use std::net::IpAddr;
+use std::ops::{Deref, DerefMut};
+use std::task::{Context, Poll};
+
+use libp2p::core::Endpoint;
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::{
+    ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+    THandlerOutEvent, ToSwarm,
+};
+use libp2p::{Multiaddr, PeerId};
+
+/// Which of the listening and external addresses of the node are advertised to the other peers
+/// through identify and kademlia.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum AddressFilter {
+    /// Every address
+    #[default]
+    All,
+    /// Only the addresses not on a loopback, private or link-local network
+    PublicOnly,
+    /// The addresses for which the function returns `true`
+    Custom(fn(&Multiaddr) -> bool),
+}
+
+impl AddressFilter {
+    pub fn allows(&self, addr: &Multiaddr) -> bool {
+        match self {
+            AddressFilter::All => true,
+            AddressFilter::PublicOnly => is_public(addr),
+            AddressFilter::Custom(filter) => filter(addr),
+        }
+    }
+}
+
+/// Returns whether the first ip of the address is a public one, the addresses without an ip
+/// (e.g. dns) being considered as public.
+fn is_public(addr: &Multiaddr) -> bool {
+    let ip = match addr.iter().next() {
+        Some(Protocol::Ip4(ip)) => IpAddr::V4(ip),
+        Some(Protocol::Ip6(ip)) => IpAddr::V6(ip),
+        _ => return true,
+    };
+
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified())
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            // unique local (fc00::/7) and link-local (fe80::/10)
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Wraps a behaviour, hiding the local addresses rejected by the filter from it so that it
+/// does not advertise them.
+pub struct Filtered<B> {
+    inner: B,
+    filter: AddressFilter,
+}
+
+impl<B> Filtered<B> {
+    pub fn new(inner: B, filter: AddressFilter) -> Self {
+        Self { inner, filter }
+    }
+}
+
+impl<B> Deref for Filtered<B> {
+    type Target = B;
+
+    fn deref(&self) -> &B {
+        &self.inner
+    }
+}
+
+impl<B> DerefMut for Filtered<B> {
+    fn deref_mut(&mut self) -> &mut B {
+        &mut self.inner
+    }
+}
+
+impl<B: NetworkBehaviour> NetworkBehaviour for Filtered<B> {
+    type ConnectionHandler = B::ConnectionHandler;
+    type ToSwarm = B::ToSwarm;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.inner
+            .handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner.handle_established_inbound_connection(
+            connection_id,
+            peer,
+            local_addr,
+            remote_addr,
+        )
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.inner.handle_pending_outbound_connection(
+            connection_id,
+            maybe_peer,
+            addresses,
+            effective_role,
+        )
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner
+            .handle_established_outbound_connection(connection_id, peer, addr, role_override)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        let local_addr = match &event {
+            FromSwarm::NewListenAddr(e) => Some(e.addr),
+            FromSwarm::ExpiredListenAddr(e) => Some(e.addr),
+            FromSwarm::NewExternalAddrCandidate(e) => Some(e.addr),
+            FromSwarm::ExternalAddrConfirmed(e) => Some(e.addr),
+            FromSwarm::ExternalAddrExpired(e) => Some(e.addr),
+            _ => None,
+        };
+
+        if matches!(local_addr, Some(addr) if !self.filter.allows(addr)) {
+            return;
+        }
+
+        self.inner.on_swarm_event(event)
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        self.inner
+            .on_connection_handler_event(peer_id, connection_id, event)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        self.inner.poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_only_rejects_the_local_networks() {
+        let filter = AddressFilter::PublicOnly;
+        for addr in [
+            "/ip4/127.0.0.1/tcp/4001",
+            "/ip4/10.0.0.1/tcp/4001",
+            "/ip4/172.16.5.4/tcp/4001",
+            "/ip4/192.168.1.10/udp/4001/quic-v1",
+            "/ip4/169.254.0.1/tcp/4001",
+            "/ip6/::1/tcp/4001",
+            "/ip6/fe80::1/tcp/4001",
+            "/ip6/fd00::1/tcp/4001",
+        ] {
+            assert!(!filter.allows(&addr.parse().unwrap()), "{addr}");
+        }
+        for addr in [
+            "/ip4/198.51.100.1/tcp/4001",
+            "/ip6/2001:db8::1/tcp/4001",
+            "/dns4/example.com/tcp/4001",
+        ] {
+            assert!(filter.allows(&addr.parse().unwrap()), "{addr}");
+        }
+
+        let filter = AddressFilter::Custom(|addr| addr.iter().any(|p| p == Protocol::Tcp(4001)));
+        assert!(filter.allows(&"/ip4/10.0.0.1/tcp/4001".parse().unwrap()));
+        assert!(!filter.allows(&"/ip4/10.0.0.1/tcp/4002".parse().unwrap()));
+    }
+}
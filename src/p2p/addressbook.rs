@@ -1,6 +1,8 @@
 use std::{
-    collections::{hash_map::Entry, HashMap, VecDeque},
+    collections::{hash_map::Entry, BTreeMap, HashMap, VecDeque},
+    path::Path,
     task::{Context, Poll},
+    time::{Duration, SystemTime},
 };
 
 use libp2p::{
@@ -8,21 +10,97 @@ use libp2p::{
     multiaddr::Protocol,
     swarm::{
         self, dummy::ConnectionHandler as DummyConnectionHandler, AddressChange, ConnectionDenied,
-        ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent, ToSwarm,
+        ConnectionEstablished, ConnectionId, FromSwarm, NetworkBehaviour, THandler,
+        THandlerInEvent, ToSwarm,
     },
     Multiaddr, PeerId,
 };
+use serde::{Deserialize, Serialize};
 
-#[derive(Default, Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone)]
 pub struct Config {
     /// Store peer address on an established connection
     pub store_on_connection: bool,
+
+    /// Save the addresses in the repo directory every minute and on exit, loading them into the
+    /// addressbook on startup. The addresses of the peers in the Kademlia routing table and the
+    /// listen addresses the connected peers identified with are saved as well. Ignored with an
+    /// in-memory repo.
+    pub persist: bool,
+
+    /// Saved peers not seen for longer are discarded on startup.
+    pub max_age: Option<Duration>,
+
+    /// Number of the most recently seen DHT peers added to Kademlia on startup.
+    pub kad_seed: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            store_on_connection: false,
+            persist: false,
+            max_age: Some(Duration::from_secs(7 * 24 * 60 * 60)),
+            kad_seed: 32,
+        }
+    }
+}
+
+/// A peer of the addressbook as saved in the repo.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PeerRecord {
+    pub peer_id: PeerId,
+    pub addrs: Vec<Multiaddr>,
+    /// Seconds since the unix epoch
+    pub last_seen: u64,
+    /// Whether the peer was in the Kademlia routing table
+    pub dht: bool,
+    /// Metadata attached to the peer, if persisted
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub meta: BTreeMap<String, Vec<u8>>,
+}
+
+/// Writes the records to the file, replacing it once written.
+pub(crate) fn save_records(path: &Path, records: &[PeerRecord]) -> std::io::Result<()> {
+    let bytes = serde_json::to_vec(records)?;
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(tmp, path)
+}
+
+/// Reads the records from the file, skipping the ones older than `max_age`. A missing file has no
+/// records.
+pub(crate) async fn load_records(
+    path: &Path,
+    max_age: Option<Duration>,
+) -> std::io::Result<Vec<PeerRecord>> {
+    let bytes = match tokio::fs::read(path).await {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+
+    let mut records: Vec<PeerRecord> = serde_json::from_slice(&bytes)?;
+
+    if let Some(max_age) = max_age {
+        let oldest = unix_secs(SystemTime::now()).saturating_sub(max_age.as_secs());
+        records.retain(|record| record.last_seen >= oldest);
+    }
+
+    Ok(records)
+}
+
+pub(crate) fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
 }
 
 #[derive(Default, Debug)]
 pub struct Behaviour {
     events: VecDeque<ToSwarm<<Self as NetworkBehaviour>::ToSwarm, THandlerInEvent<Self>>>,
     peer_addresses: HashMap<PeerId, Vec<Multiaddr>>,
+    last_seen: HashMap<PeerId, SystemTime>,
     config: Config,
 }
 
@@ -51,9 +129,36 @@ impl Behaviour {
                 e.insert(vec![addr]);
             }
         }
+        self.last_seen
+            .entry(peer_id)
+            .or_insert_with(SystemTime::now);
         true
     }
 
+    /// Adds a saved peer, keeping the time it was last seen.
+    pub(crate) fn add_record(&mut self, record: &PeerRecord) {
+        for addr in &record.addrs {
+            self.add_address(record.peer_id, addr.clone());
+        }
+        let last_seen = SystemTime::UNIX_EPOCH + Duration::from_secs(record.last_seen);
+        self.last_seen.insert(record.peer_id, last_seen);
+    }
+
+    /// Returns the peers with addresses, with the time they were last connected to or added.
+    pub(crate) fn records(&self) -> impl Iterator<Item = (&PeerId, &Vec<Multiaddr>, SystemTime)> {
+        self.peer_addresses
+            .iter()
+            .filter(|(_, addrs)| !addrs.is_empty())
+            .map(|(peer_id, addrs)| {
+                let last_seen = self
+                    .last_seen
+                    .get(peer_id)
+                    .copied()
+                    .unwrap_or(SystemTime::UNIX_EPOCH);
+                (peer_id, addrs, last_seen)
+            })
+    }
+
     pub fn remove_address(&mut self, peer_id: &PeerId, addr: &Multiaddr) -> bool {
         if let Entry::Occupied(mut e) = self.peer_addresses.entry(*peer_id) {
             let entry = e.get_mut();
@@ -66,6 +171,7 @@ impl Behaviour {
     }
 
     pub fn remove_peer(&mut self, peer_id: &PeerId) -> bool {
+        self.last_seen.remove(peer_id);
         self.peer_addresses.remove(peer_id).is_some()
     }
 
@@ -208,7 +314,11 @@ impl NetworkBehaviour for Behaviour {
                     }
                 }
             }
-            FromSwarm::ConnectionEstablished(_) => {}
+            FromSwarm::ConnectionEstablished(ConnectionEstablished { peer_id, .. }) => {
+                if self.peer_addresses.contains_key(&peer_id) {
+                    self.last_seen.insert(peer_id, SystemTime::now());
+                }
+            }
             FromSwarm::ConnectionClosed(_) => {}
             FromSwarm::DialFailure(_) => {}
             FromSwarm::ListenFailure(_) => {}
@@ -356,6 +466,7 @@ mod test {
             .with_behaviour(|_| {
                 super::Behaviour::with_config(super::Config {
                     store_on_connection,
+                    ..Default::default()
                 })
             })
             .expect("")
@@ -0,0 +1,187 @@
+//! Announcement of the newly pinned content over pubsub, see [`ContentAnnouncerConfig`].
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use libipld::Cid;
+use libp2p::gossipsub::Message as GossipsubMessage;
+use libp2p::PeerId;
+
+/// Configuration of the content announcer, which publishes the roots of the new pins on a pubsub
+/// topic and remembers the content announced by the other peers on it.
+///
+/// The peers which announced a wanted block are tried before looking for its providers on the
+/// DHT, or instead of it with `replace_dht`. This suits small swarms where every node is
+/// subscribed to the topic, in which the announcements reach the peers much faster than the
+/// provider records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentAnnouncerConfig {
+    pub topic: String,
+    /// How long an announcement is remembered
+    pub ttl: Duration,
+    /// Number of announcements remembered, the oldest ones being forgotten first
+    pub max_entries: usize,
+    /// Only look for providers on the DHT once the peers which announced the block failed to
+    /// send it
+    pub replace_dht: bool,
+}
+
+impl Default for ContentAnnouncerConfig {
+    fn default() -> Self {
+        Self {
+            topic: "/rust-ipfs/announce/1.0.0".into(),
+            ttl: Duration::from_secs(30 * 60),
+            max_entries: 4096,
+            replace_dht: false,
+        }
+    }
+}
+
+/// The content announced by the peers, with when it was last announced.
+#[derive(Debug)]
+pub(crate) struct ContentAnnouncer {
+    pub(crate) config: ContentAnnouncerConfig,
+    announced: HashMap<Cid, HashMap<PeerId, Instant>>,
+    len: usize,
+    /// Announcements in the order they were received, including the ones announced again since
+    order: VecDeque<(Instant, Cid, PeerId)>,
+}
+
+impl ContentAnnouncer {
+    pub(crate) fn new(config: ContentAnnouncerConfig) -> Self {
+        Self {
+            config,
+            announced: HashMap::new(),
+            len: 0,
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Records the cid announced in the message, returning it unless the message is not an
+    /// announcement from another peer.
+    pub(crate) fn receive(
+        &mut self,
+        local_peer_id: &PeerId,
+        message: &GossipsubMessage,
+        now: Instant,
+    ) -> Option<(PeerId, Cid)> {
+        let peer_id = message.source?;
+        if peer_id == *local_peer_id {
+            return None;
+        }
+        let cid = Cid::try_from(message.data.as_slice()).ok()?;
+        self.insert(cid, peer_id, now);
+        Some((peer_id, cid))
+    }
+
+    pub(crate) fn insert(&mut self, cid: Cid, peer_id: PeerId, now: Instant) {
+        if self.config.max_entries == 0 {
+            return;
+        }
+
+        if self
+            .announced
+            .entry(cid)
+            .or_default()
+            .insert(peer_id, now)
+            .is_none()
+        {
+            self.len += 1;
+        }
+        self.order.push_back((now, cid, peer_id));
+
+        while self.len > self.config.max_entries {
+            self.pop_oldest();
+        }
+
+        // the peers announcing the same content again leave their previous announcements behind
+        if self.order.len() > 2 * self.config.max_entries {
+            let announced = &self.announced;
+            self.order.retain(|(at, cid, peer_id)| {
+                announced.get(cid).and_then(|peers| peers.get(peer_id)) == Some(at)
+            });
+        }
+    }
+
+    /// Forgets the announcements older than the ttl.
+    pub(crate) fn sweep(&mut self, now: Instant) {
+        while let Some((at, _, _)) = self.order.front() {
+            if now.duration_since(*at) < self.config.ttl {
+                break;
+            }
+            self.pop_oldest();
+        }
+    }
+
+    /// Returns the peers which announced the cid within the ttl.
+    pub(crate) fn providers(&self, cid: &Cid, now: Instant) -> HashSet<PeerId> {
+        self.announced
+            .get(cid)
+            .map(|peers| {
+                peers
+                    .iter()
+                    .filter(|(_, at)| now.duration_since(**at) < self.config.ttl)
+                    .map(|(peer_id, _)| *peer_id)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn pop_oldest(&mut self) {
+        let Some((at, cid, peer_id)) = self.order.pop_front() else {
+            return;
+        };
+        let Some(peers) = self.announced.get_mut(&cid) else {
+            return;
+        };
+        // announced again since
+        if peers.get(&peer_id) != Some(&at) {
+            return;
+        }
+        peers.remove(&peer_id);
+        self.len -= 1;
+        if peers.is_empty() {
+            self.announced.remove(&cid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libipld::multihash::{Code, MultihashDigest};
+    use libipld::IpldCodec;
+
+    fn cid_of(data: &[u8]) -> Cid {
+        Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(data))
+    }
+
+    #[test]
+    fn announcements_expire_and_are_capped() {
+        let mut announcer = ContentAnnouncer::new(ContentAnnouncerConfig {
+            ttl: Duration::from_secs(10),
+            max_entries: 2,
+            ..Default::default()
+        });
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let (first, second) = (cid_of(b"first"), cid_of(b"second"));
+        let start = Instant::now();
+
+        announcer.insert(first, a, start);
+        announcer.insert(first, b, start + Duration::from_secs(1));
+        assert_eq!(announcer.providers(&first, start), HashSet::from([a, b]));
+
+        // announcing again keeps it from being the oldest one
+        announcer.insert(first, a, start + Duration::from_secs(2));
+        announcer.insert(second, a, start + Duration::from_secs(3));
+        assert_eq!(announcer.providers(&first, start), HashSet::from([a]));
+        assert_eq!(announcer.providers(&second, start), HashSet::from([a]));
+
+        let later = start + Duration::from_secs(12);
+        assert!(announcer.providers(&first, later).is_empty());
+        announcer.sweep(later);
+        assert!(!announcer.announced.contains_key(&first));
+        assert_eq!(announcer.providers(&second, later), HashSet::from([a]));
+        assert_eq!(announcer.len, 1);
+    }
+}
@@ -0,0 +1,469 @@
+//! Bandwidth used by the connections, counted on the substreams of every muxer.
+use futures::io::{IoSlice, IoSliceMut};
+use futures::{ready, AsyncRead, AsyncWrite};
+use libp2p::core::muxing::{StreamMuxer, StreamMuxerBox, StreamMuxerEvent};
+use libp2p::core::transport::Boxed;
+use libp2p::core::ConnectedPoint;
+use libp2p::{Multiaddr, PeerId, Transport};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+/// Weight of the latest sample in the moving averages.
+const SMOOTHING: f64 = 0.25;
+
+/// Bytes of an inbound substream searched for the relay hop protocol, enough for the
+/// multistream-select header and the protocol.
+const SNIFF_LEN: usize = 64;
+
+/// Bytes sent and received, with their rates as moving averages of the periodic samples.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BandwidthStats {
+    pub total_inbound: u64,
+    pub total_outbound: u64,
+    /// Bytes per second received
+    pub rate_inbound: f64,
+    /// Bytes per second sent
+    pub rate_outbound: f64,
+}
+
+#[derive(Debug, Default)]
+struct Counter {
+    inbound: AtomicU64,
+    outbound: AtomicU64,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Rate {
+    inbound: u64,
+    outbound: u64,
+    rate_inbound: f64,
+    rate_outbound: f64,
+}
+
+impl Rate {
+    fn sample(&mut self, counter: &Counter, elapsed: f64) {
+        let inbound = counter.inbound.load(Ordering::Relaxed);
+        let outbound = counter.outbound.load(Ordering::Relaxed);
+        let current_inbound = inbound.saturating_sub(self.inbound) as f64 / elapsed;
+        let current_outbound = outbound.saturating_sub(self.outbound) as f64 / elapsed;
+        self.rate_inbound = SMOOTHING * current_inbound + (1.0 - SMOOTHING) * self.rate_inbound;
+        self.rate_outbound = SMOOTHING * current_outbound + (1.0 - SMOOTHING) * self.rate_outbound;
+        self.inbound = inbound;
+        self.outbound = outbound;
+    }
+
+    fn stats(&self, counter: &Counter) -> BandwidthStats {
+        BandwidthStats {
+            total_inbound: counter.inbound.load(Ordering::Relaxed),
+            total_outbound: counter.outbound.load(Ordering::Relaxed),
+            rate_inbound: self.rate_inbound,
+            rate_outbound: self.rate_outbound,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Counters<K> {
+    counters: HashMap<K, (Arc<Counter>, Rate)>,
+}
+
+impl<K> Default for Counters<K> {
+    fn default() -> Self {
+        Self {
+            counters: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone> Counters<K> {
+    fn get_or_create(&mut self, key: K) -> Arc<Counter> {
+        self.counters.entry(key).or_default().0.clone()
+    }
+
+    fn sample(&mut self, elapsed: f64) {
+        for (counter, rate) in self.counters.values_mut() {
+            rate.sample(counter, elapsed);
+        }
+    }
+
+    fn stats(&self) -> HashMap<K, BandwidthStats> {
+        self.counters
+            .iter()
+            .map(|(key, (counter, rate))| (key.clone(), rate.stats(counter)))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    total: Arc<Counter>,
+    total_rate: Mutex<Rate>,
+    peers: Mutex<Counters<PeerId>>,
+    protocols: Mutex<Counters<String>>,
+    sampled_at: Mutex<Instant>,
+    count_relayed: AtomicBool,
+    relayed: Arc<AtomicU64>,
+}
+
+/// Handle to the bandwidth counters of the transport, shared with [`Ipfs`](crate::Ipfs).
+#[derive(Debug, Clone)]
+pub struct BandwidthCounters {
+    inner: Arc<Inner>,
+}
+
+impl Default for BandwidthCounters {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                total: Default::default(),
+                total_rate: Default::default(),
+                peers: Default::default(),
+                protocols: Default::default(),
+                sampled_at: Mutex::new(Instant::now()),
+                count_relayed: AtomicBool::new(false),
+                relayed: Default::default(),
+            }),
+        }
+    }
+}
+
+impl BandwidthCounters {
+    /// Wraps the transport, counting the bytes of the substreams of every connection.
+    pub(crate) fn wrap(
+        &self,
+        transport: Boxed<(PeerId, StreamMuxerBox)>,
+    ) -> Boxed<(PeerId, StreamMuxerBox)> {
+        let counters = self.clone();
+        transport
+            .map(move |(peer_id, muxer), endpoint| {
+                let counters = counters.connection(peer_id, &endpoint);
+                (
+                    peer_id,
+                    StreamMuxerBox::new(Muxer {
+                        inner: muxer,
+                        counters,
+                    }),
+                )
+            })
+            .boxed()
+    }
+
+    fn connection(&self, peer_id: PeerId, endpoint: &ConnectedPoint) -> ConnectionCounters {
+        let protocols = protocol_stack(endpoint.get_remote_address());
+        ConnectionCounters {
+            total: self.inner.total.clone(),
+            peer: self.inner.peers.lock().get_or_create(peer_id),
+            protocol: self.inner.protocols.lock().get_or_create(protocols),
+            relayed: self
+                .inner
+                .count_relayed
+                .load(Ordering::Relaxed)
+                .then(|| self.inner.relayed.clone()),
+        }
+    }
+
+    /// Counts the bytes of the inbound relay hop substreams of the connections established
+    /// afterwards, the circuits relayed when running a relay server.
+    pub(crate) fn count_relayed(&self) {
+        self.inner.count_relayed.store(true, Ordering::Relaxed);
+    }
+
+    /// Bytes relayed in both directions of the circuits.
+    pub(crate) fn relayed(&self) -> u64 {
+        self.inner.relayed.load(Ordering::Relaxed)
+    }
+
+    /// Updates the moving averages of the rates with the bytes counted since the previous
+    /// sample.
+    pub(crate) fn sample(&self) {
+        let now = Instant::now();
+        let elapsed = {
+            let mut sampled_at = self.inner.sampled_at.lock();
+            let elapsed = now.duration_since(*sampled_at).as_secs_f64();
+            *sampled_at = now;
+            elapsed
+        };
+
+        if elapsed <= 0.0 {
+            return;
+        }
+
+        self.inner
+            .total_rate
+            .lock()
+            .sample(&self.inner.total, elapsed);
+        self.inner.peers.lock().sample(elapsed);
+        self.inner.protocols.lock().sample(elapsed);
+    }
+
+    pub fn stats(&self) -> BandwidthStats {
+        self.inner.total_rate.lock().stats(&self.inner.total)
+    }
+
+    pub fn by_peer(&self) -> HashMap<PeerId, BandwidthStats> {
+        self.inner.peers.lock().stats()
+    }
+
+    pub fn by_protocol(&self) -> HashMap<String, BandwidthStats> {
+        self.inner.protocols.lock().stats()
+    }
+}
+
+/// The transport protocols of the address, e.g. `/ip4/tcp`.
+fn protocol_stack(addr: &Multiaddr) -> String {
+    addr.protocol_stack()
+        .filter(|tag| *tag != "p2p")
+        .map(|tag| format!("/{tag}"))
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+struct ConnectionCounters {
+    total: Arc<Counter>,
+    peer: Arc<Counter>,
+    protocol: Arc<Counter>,
+    relayed: Option<Arc<AtomicU64>>,
+}
+
+impl ConnectionCounters {
+    fn inbound(&self, bytes: usize) {
+        for counter in [&self.total, &self.peer, &self.protocol] {
+            counter.inbound.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+
+    fn outbound(&self, bytes: usize) {
+        for counter in [&self.total, &self.peer, &self.protocol] {
+            counter.outbound.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+struct Muxer {
+    inner: StreamMuxerBox,
+    counters: ConnectionCounters,
+}
+
+impl StreamMuxer for Muxer {
+    type Substream = Substream<<StreamMuxerBox as StreamMuxer>::Substream>;
+    type Error = <StreamMuxerBox as StreamMuxer>::Error;
+
+    fn poll_inbound(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::Substream, Self::Error>> {
+        let inner = ready!(Pin::new(&mut self.inner).poll_inbound(cx))?;
+        let relay = match self.counters.relayed {
+            Some(_) => Relay::Unknown(Vec::with_capacity(SNIFF_LEN)),
+            None => Relay::Other,
+        };
+        Poll::Ready(Ok(Substream {
+            inner,
+            counters: self.counters.clone(),
+            relay,
+        }))
+    }
+
+    fn poll_outbound(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::Substream, Self::Error>> {
+        let inner = ready!(Pin::new(&mut self.inner).poll_outbound(cx))?;
+        Poll::Ready(Ok(Substream {
+            inner,
+            counters: self.counters.clone(),
+            relay: Relay::Other,
+        }))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<StreamMuxerEvent, Self::Error>> {
+        Pin::new(&mut self.inner).poll(cx)
+    }
+}
+
+/// Whether an inbound substream is a relayed circuit, found from the protocol negotiated at its
+/// start.
+enum Relay {
+    /// The first bytes read so far
+    Unknown(Vec<u8>),
+    Hop,
+    Other,
+}
+
+impl Relay {
+    fn on_read(&mut self, read: &[u8], relayed: Option<&AtomicU64>) {
+        match self {
+            Relay::Unknown(buf) => {
+                let end = read.len().min(SNIFF_LEN - buf.len());
+                buf.extend_from_slice(&read[..end]);
+
+                let hop = libp2p::relay::HOP_PROTOCOL_NAME.as_ref().as_bytes();
+                if buf.windows(hop.len()).any(|window| window == hop) {
+                    if let Some(relayed) = relayed {
+                        // the bytes of the negotiation are counted as well
+                        let sniffed = buf.len() + read.len() - end;
+                        relayed.fetch_add(sniffed as u64, Ordering::Relaxed);
+                    }
+                    *self = Relay::Hop;
+                } else if buf.len() == SNIFF_LEN || read.is_empty() {
+                    *self = Relay::Other;
+                }
+            }
+            Relay::Hop => {
+                if let Some(relayed) = relayed {
+                    relayed.fetch_add(read.len() as u64, Ordering::Relaxed);
+                }
+            }
+            Relay::Other => {}
+        }
+    }
+
+    fn on_write(&self, bytes: usize, relayed: Option<&AtomicU64>) {
+        if let (Relay::Hop, Some(relayed)) = (self, relayed) {
+            relayed.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+struct Substream<S> {
+    inner: S,
+    counters: ConnectionCounters,
+    relay: Relay,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Substream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let bytes = ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        self.counters.inbound(bytes);
+        let this = &mut *self;
+        this.relay
+            .on_read(&buf[..bytes], this.counters.relayed.as_deref());
+        Poll::Ready(Ok(bytes))
+    }
+
+    fn poll_read_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        if let Relay::Unknown(_) = self.relay {
+            // read into a single buffer until the protocol is known
+            let buf = bufs
+                .iter_mut()
+                .find(|buf| !buf.is_empty())
+                .map_or(&mut [][..], |buf| &mut **buf);
+            return self.poll_read(cx, buf);
+        }
+
+        let bytes = ready!(Pin::new(&mut self.inner).poll_read_vectored(cx, bufs))?;
+        self.counters.inbound(bytes);
+        let this = &mut *self;
+        if let (Relay::Hop, Some(relayed)) = (&this.relay, &this.counters.relayed) {
+            relayed.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+        Poll::Ready(Ok(bytes))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Substream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let bytes = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.counters.outbound(bytes);
+        self.relay.on_write(bytes, self.counters.relayed.as_deref());
+        Poll::Ready(Ok(bytes))
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let bytes = ready!(Pin::new(&mut self.inner).poll_write_vectored(cx, bufs))?;
+        self.counters.outbound(bytes);
+        self.relay.on_write(bytes, self.counters.relayed.as_deref());
+        Poll::Ready(Ok(bytes))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_are_moving_averages() {
+        let counter = Counter::default();
+        let mut rate = Rate::default();
+
+        counter.inbound.fetch_add(400, Ordering::Relaxed);
+        rate.sample(&counter, 1.0);
+        assert_eq!(rate.rate_inbound, 100.0);
+
+        // nothing received, the average decays
+        rate.sample(&counter, 1.0);
+        assert_eq!(rate.rate_inbound, 75.0);
+
+        let stats = rate.stats(&counter);
+        assert_eq!(stats.total_inbound, 400);
+        assert_eq!(stats.total_outbound, 0);
+        assert_eq!(stats.rate_outbound, 0.0);
+    }
+
+    #[test]
+    fn protocol_stack_excludes_the_peer_id() {
+        let addr: Multiaddr = format!("/ip4/127.0.0.1/tcp/4001/p2p/{}", PeerId::random())
+            .parse()
+            .unwrap();
+        assert_eq!(protocol_stack(&addr), "/ip4/tcp");
+    }
+
+    #[test]
+    fn hop_substreams_are_counted_as_relayed() {
+        let relayed = AtomicU64::new(0);
+        let header = b"\x13/multistream/1.0.0\n\x20/libp2p/circuit/relay/0.2.0/hop\n";
+
+        let mut relay = Relay::Unknown(vec![]);
+        relay.on_read(&header[..10], Some(&relayed));
+        assert!(matches!(relay, Relay::Unknown(_)));
+        relay.on_read(&header[10..], Some(&relayed));
+        assert!(matches!(relay, Relay::Hop));
+        relay.on_write(5, Some(&relayed));
+        assert_eq!(relayed.load(Ordering::Relaxed), header.len() as u64 + 5);
+
+        let other = b"\x13/multistream/1.0.0\n\x0f/ipfs/id/1.0.0\n";
+        let mut relay = Relay::Unknown(vec![]);
+        relay.on_read(other, Some(&relayed));
+        relay.on_read(&[0; SNIFF_LEN], Some(&relayed));
+        assert!(matches!(relay, Relay::Other));
+        assert_eq!(relayed.load(Ordering::Relaxed), header.len() as u64 + 5);
+    }
+}
@@ -1,4 +1,7 @@
+use super::addr_filter::{AddressFilter, Filtered};
+use super::gate::{self, GateHandle};
 use super::gossipsub::GossipsubStream;
+use super::kad_store::KadStore;
 use super::{addressbook, protocol};
 #[cfg(feature = "beetle_bitswap")]
 use bytes::Bytes;
@@ -8,6 +11,7 @@ use libp2p_allow_block_list::BlockedPeers;
 use libp2p_bitswap_next::Bitswap;
 
 use super::peerbook::{self};
+use super::pinger;
 use either::Either;
 use serde::{Deserialize, Serialize};
 
@@ -27,7 +31,6 @@ use libp2p::core::Multiaddr;
 use libp2p::dcutr::Behaviour as Dcutr;
 use libp2p::identify::{Behaviour as Identify, Config as IdentifyConfig};
 use libp2p::identity::{Keypair, PeerId};
-use libp2p::kad::store::{MemoryStore, MemoryStoreConfig};
 use libp2p::kad::{
     Behaviour as Kademlia, BucketInserts as KademliaBucketInserts, Config as KademliaConfig,
     Record, StoreInserts as KademliaStoreInserts,
@@ -59,13 +62,16 @@ where
     pub bitswap: Toggle<Bitswap<Repo>>,
     #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
     pub bitswap: Toggle<super::bitswap::Behaviour>,
-    pub kademlia: Toggle<Kademlia<MemoryStore>>,
+    pub kademlia: Toggle<Filtered<Kademlia<KadStore>>>,
     pub ping: Toggle<Ping>,
-    pub identify: Toggle<Identify>,
+    pub pinger: Toggle<pinger::Behaviour>,
+    pub identify: Toggle<Filtered<Identify>>,
     pub pubsub: Toggle<GossipsubStream>,
     pub autonat: Toggle<autonat::Behaviour>,
     pub upnp: Toggle<libp2p::upnp::tokio::Behaviour>,
     pub block_list: libp2p_allow_block_list::Behaviour<BlockedPeers>,
+    pub gate: gate::Behaviour,
+    pub connection_limits: libp2p_connection_limits::Behaviour,
     pub relay: Toggle<Relay>,
     pub relay_client: Toggle<RelayClient>,
     pub relay_manager: Toggle<libp2p_relay_manager::Behaviour>,
@@ -149,16 +155,20 @@ pub struct IdentifyConfiguration {
     pub interval: Duration,
     pub push_update: bool,
     pub cache: usize,
+    /// Addresses pushed to the peers, and announced through kademlia
+    #[serde(skip)]
+    pub address_filter: AddressFilter,
 }
 
 impl Default for IdentifyConfiguration {
     fn default() -> Self {
         Self {
             protocol_version: "/ipfs/0.1.0".into(),
-            agent_version: "rust-ipfs".into(),
+            agent_version: concat!("rust-ipfs/", env!("CARGO_PKG_VERSION")).into(),
             interval: Duration::from_secs(5 * 60),
             push_update: true,
             cache: 100,
+            address_filter: AddressFilter::All,
         }
     }
 }
@@ -236,10 +246,6 @@ pub enum RateLimit {
     },
 }
 
-#[derive(Default, Clone, Debug)]
-pub struct KadStoreConfig {
-    pub memory: Option<MemoryStoreConfig>,
-}
 #[derive(Clone, Debug)]
 pub struct KadConfig {
     pub protocol: Option<Vec<Cow<'static, str>>>,
@@ -392,6 +398,7 @@ where
         keypair: &Keypair,
         options: &IpfsOptions,
         repo: &Repo,
+        gate: GateHandle,
         custom: Option<C>,
     ) -> Result<(Self, Option<ClientTransport>), Error> {
         let protocols = options.protocols;
@@ -407,22 +414,25 @@ where
         }
         .into();
 
-        let store = {
-            //TODO: Make customizable
-            //TODO: Use persistent store for kad
-            let config = options.kad_store_config.memory.clone().unwrap_or_default();
-
-            MemoryStore::with_config(peer_id, config)
-        };
+        let store = KadStore::new(peer_id, &options.kad_store_config, repo).await;
 
-        let kad_config = match options.kad_configuration.clone() {
+        let mut kad_config: KademliaConfig = match options.kad_configuration.clone() {
             Either::Left(kad) => kad.into(),
             Either::Right(kad) => kad,
         };
+        if options.dht_write_limit.is_some() {
+            // the writes within the limit are stored by the task
+            kad_config.set_record_filtering(KademliaStoreInserts::FilterBoth);
+        }
 
-        let mut kademlia: Toggle<Kademlia<MemoryStore>> = Toggle::from(
-            (protocols.kad).then(|| Kademlia::with_config(peer_id, store, kad_config)),
-        );
+        let address_filter = options.identify_configuration.address_filter;
+        let mut kademlia: Toggle<Filtered<Kademlia<KadStore>>> =
+            Toggle::from((protocols.kad).then(|| {
+                Filtered::new(
+                    Kademlia::with_config(peer_id, store, kad_config),
+                    address_filter,
+                )
+            }));
 
         if let Some(kad) = kademlia.as_mut() {
             for mut addr in options.bootstrap.clone() {
@@ -459,10 +469,24 @@ where
             })
             .into();
 
+        #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+        let peer_ledgers = match protocols.bitswap && options.persist_bitswap_ledgers {
+            true => super::ledger::load_ledgers(repo).await.unwrap_or_else(|e| {
+                warn!("failed to load the bitswap ledgers: {e}");
+                Default::default()
+            }),
+            false => Default::default(),
+        };
+
         #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
         let bitswap = protocols
             .bitswap
-            .then(|| super::bitswap::Behaviour::new(repo))
+            .then(|| {
+                super::bitswap::Behaviour::new(repo)
+                    .with_compression(options.bitswap_compression())
+                    .with_serve_order(options.bitswap_serve_order)
+                    .with_peer_ledgers(peer_ledgers)
+            })
             .into();
 
         let ping = protocols
@@ -470,14 +494,19 @@ where
             .then(|| Ping::new(options.ping_configuration.clone()))
             .into();
 
+        let pinger = protocols.ping.then(pinger::Behaviour::default).into();
+
         let identify = protocols
             .identify
             .then(|| {
-                Identify::new(
-                    options
-                        .identify_configuration
-                        .clone()
-                        .into(keypair.public()),
+                Filtered::new(
+                    Identify::new(
+                        options
+                            .identify_configuration
+                            .clone()
+                            .into(keypair.public()),
+                    ),
+                    address_filter,
                 )
             })
             .into();
@@ -533,11 +562,14 @@ where
             false => (None, None.into(), None.into()),
         };
 
-        let peerbook = peerbook::Behaviour::default();
+        let peerbook = peerbook::Behaviour::with_meta_config(options.peer_meta);
 
         let addressbook = addressbook::Behaviour::with_config(options.addr_config);
 
         let block_list = libp2p_allow_block_list::Behaviour::default();
+        let gate = gate::Behaviour::new(gate);
+        let connection_limits =
+            libp2p_connection_limits::Behaviour::new(options.connection_limits.into());
         let protocol = protocol::Behaviour::default();
         let custom = Toggle::from(custom);
 
@@ -560,6 +592,7 @@ where
                 kademlia,
                 bitswap,
                 ping,
+                pinger,
                 identify,
                 autonat,
                 pubsub,
@@ -568,6 +601,8 @@ where
                 relay_client,
                 relay_manager,
                 block_list,
+                gate,
+                connection_limits,
                 #[cfg(feature = "experimental_stream")]
                 stream,
                 upnp,
@@ -7,7 +7,7 @@ use std::{
     collections::{hash_map::Entry, BTreeSet, HashMap, HashSet, VecDeque},
     sync::Arc,
     task::{Context, Poll, Waker},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use bytes::Bytes;
@@ -21,7 +21,8 @@ use libp2p::{
     swarm::{
         behaviour::ConnectionEstablished, dial_opts::DialOpts, ConnectionClosed, ConnectionDenied,
         ConnectionId, DialFailure, FromSwarm, NetworkBehaviour, NotifyHandler, OneShotHandler,
-        THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
+        StreamUpgradeError, SubstreamProtocol, THandler, THandlerInEvent, THandlerOutEvent,
+        ToSwarm,
     },
     Multiaddr, PeerId,
 };
@@ -38,13 +39,22 @@ mod bitswap_pb {
     }
 }
 
-use crate::{repo::Repo, Block};
+use crate::{
+    p2p::{PeerLedger, ServeOrder},
+    repo::{policy::PolicyViolation, BlockSource, Repo, StorageFull},
+    Block,
+};
 
 use self::{
     message::{BitswapMessage, BitswapRequest, BitswapResponse, RequestType},
-    protocol::{BitswapProtocol, Message},
+    protocol::{BitswapProtocol, Message, OutboundMessage},
 };
 
+pub(crate) use self::protocol::PROTOCOL;
+
+/// Least time between two content discoveries asked for the same want after failed dials.
+const NEED_BLOCK_INTERVAL: Duration = Duration::from_secs(10);
+
 #[derive(Default, Debug, Clone, Copy)]
 pub struct Config {
     pub max_wanted_blocks: Option<u8>,
@@ -56,6 +66,7 @@ pub enum Event {
     NeedBlock { cid: Cid },
     BlockRetrieved { cid: Cid },
     CancelBlock { cid: Cid },
+    ProtocolUnsupported { peer_id: PeerId },
 }
 
 type StreamList = SelectAll<BoxStream<'static, TaskHandle>>;
@@ -66,16 +77,23 @@ enum TaskHandle {
     HaveBlock { cid: Cid },
     DontHaveBlock { cid: Cid },
     BlockStored { cid: Cid },
+    StorageFull { cid: Cid },
+    Rejected { cid: Cid },
     Cancel { cid: Cid },
 }
 
 pub struct Behaviour {
-    events: VecDeque<ToSwarm<<Self as NetworkBehaviour>::ToSwarm, THandlerInEvent<Self>>>,
+    events: VecDeque<ToSwarm<Event, BitswapMessage>>,
     connections: HashMap<PeerId, HashSet<(ConnectionId, Multiaddr)>>,
     blacklist_connections: HashMap<PeerId, BTreeSet<ConnectionId>>,
     store: Repo,
     ledger: Ledger,
     tasks: StreamMap<(PeerId, ConnectionId), StreamList>,
+    /// Whether the messages are compressed with the peers supporting it
+    compression: bool,
+    serve_order: ServeOrder,
+    /// Responses ready to be sent, in the order they became ready
+    responses: VecDeque<(PeerId, ConnectionId, (Cid, BitswapResponse))>,
     waker: Option<Waker>,
 }
 
@@ -88,14 +106,90 @@ impl Behaviour {
             store: store.clone(),
             ledger: Ledger::default(),
             tasks: StreamMap::new(),
+            compression: false,
+            serve_order: ServeOrder::default(),
+            responses: VecDeque::new(),
             waker: None,
         }
     }
 
+    /// Offers to compress the messages exchanged, which is used with the peers offering it too.
+    /// Has no effect without the `bitswap_compression` feature.
+    pub fn with_compression(mut self, compression: bool) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the order the responses ready at once are sent in.
+    pub fn with_serve_order(mut self, serve_order: ServeOrder) -> Self {
+        self.serve_order = serve_order;
+        self
+    }
+
+    /// Starts from the ledgers of a previous run.
+    pub fn with_peer_ledgers(self, ledgers: HashMap<PeerId, PeerLedger>) -> Self {
+        self.ledger.write().exchanged = ledgers;
+        self
+    }
+
+    pub fn peer_ledger(&self, peer_id: &PeerId) -> PeerLedger {
+        let ledger = &*self.ledger.read();
+        ledger.exchanged.get(peer_id).copied().unwrap_or_default()
+    }
+
+    pub fn peer_ledgers(&self) -> HashMap<PeerId, PeerLedger> {
+        self.ledger.read().exchanged.clone()
+    }
+
+    pub fn reset_peer_ledger(&mut self, peer_id: &PeerId) {
+        self.ledger.write().exchanged.remove(peer_id);
+    }
+
+    /// Takes the next response to send, as per the serve order.
+    fn next_response(&mut self) -> Option<(PeerId, ConnectionId, (Cid, BitswapResponse))> {
+        let index = match self.serve_order {
+            ServeOrder::Fifo => 0,
+            ServeOrder::DebtRatio => {
+                let ledger = &*self.ledger.read();
+                let debt_ratio = |peer_id: &PeerId| {
+                    ledger
+                        .exchanged
+                        .get(peer_id)
+                        .map(PeerLedger::debt_ratio)
+                        .unwrap_or_default()
+                };
+                // the first of the lowest ratios, keeping the order within a peer
+                self.responses
+                    .iter()
+                    .enumerate()
+                    .min_by(|(_, (a, ..)), (_, (b, ..))| debt_ratio(a).total_cmp(&debt_ratio(b)))
+                    .map(|(index, _)| index)?
+            }
+        };
+        self.responses.remove(index)
+    }
+
+    fn handler(&self) -> THandler<Self> {
+        let protocol = BitswapProtocol {
+            compression: self.compression,
+        };
+        OneShotHandler::new(SubstreamProtocol::new(protocol, ()), Default::default())
+    }
+
     pub fn get(&mut self, cid: &Cid, providers: &[PeerId]) {
+        self.get_with_priority(cid, providers, 1)
+    }
+
+    /// Wants the block with the given priority, which providers use to order what they send us.
+    /// A block wanted more than once keeps the highest of the priorities, the want being sent
+    /// again to the peers already asked only when its priority is raised.
+    pub fn get_with_priority(&mut self, cid: &Cid, providers: &[PeerId], priority: i32) {
         let ledger = &mut *self.ledger.write();
 
-        ledger.local_want_list.insert(*cid, 1);
+        let previous = ledger.local_want_list.get(cid).copied();
+        let priority = previous.map_or(priority, |previous| previous.max(priority));
+        let raised = previous.map_or(true, |previous| priority > previous);
+        ledger.local_want_list.insert(*cid, priority);
 
         let wants = ledger.sent_wants.entry(*cid).or_default();
 
@@ -118,6 +212,11 @@ impl Behaviour {
                         connected.push_back(*peer_id);
                         continue;
                     }
+                    ledger
+                        .dialing_providers
+                        .entry(*peer_id)
+                        .or_default()
+                        .insert(*cid);
                     let opts = DialOpts::peer_id(*peer_id).build();
 
                     self.events.push_back(ToSwarm::Dial { opts });
@@ -127,18 +226,32 @@ impl Behaviour {
         };
 
         if peers.is_empty() {
-            // Since no connections, peers or providers are provided, we need to notify swarm to attempt a form of content discovery
-            self.events
-                .push_back(ToSwarm::GenerateEvent(Event::NeedBlock { cid: *cid }));
+            // The wants are sent to the providers being dialed once connected
+            let dialing = providers
+                .iter()
+                .any(|peer_id| !self.blacklist_connections.contains_key(peer_id));
+            if !dialing {
+                // Since no connections, peers or providers are provided, we need to notify swarm to attempt a form of content discovery
+                self.events
+                    .push_back(ToSwarm::GenerateEvent(Event::NeedBlock { cid: *cid }));
+            }
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
             return;
         }
 
         let requests = peers
             .iter()
+            .filter(|peer_id| raised || !wants.contains(*peer_id))
             .map(|peer_id| {
                 (
                     peer_id,
-                    BitswapMessage::Request(BitswapRequest::have(*cid).send_dont_have(true)),
+                    BitswapMessage::Request(
+                        BitswapRequest::have(*cid)
+                            .send_dont_have(true)
+                            .set_priority(priority),
+                    ),
                 )
             })
             .collect::<VecDeque<_>>();
@@ -157,9 +270,53 @@ impl Behaviour {
         }
     }
 
-    pub fn gets(&mut self, cid: Vec<Cid>, providers: &[PeerId]) {
+    /// Wants the block in the session. The peers are sent a single want for the sessions wanting
+    /// the same block, at the highest of their priorities, and the block received once completes
+    /// every session.
+    pub fn get_in_session(&mut self, session: u64, cid: &Cid, providers: &[PeerId], priority: i32) {
+        self.ledger
+            .write()
+            .session_wants
+            .entry(*cid)
+            .or_default()
+            .entry(session)
+            .and_modify(|current| *current = (*current).max(priority))
+            .or_insert(priority);
+        self.get_with_priority(cid, providers, priority)
+    }
+
+    /// Stops wanting the block in the session, the peers being sent a cancel once no session
+    /// wants it anymore.
+    pub fn cancel_in_session(&mut self, session: u64, cid: Cid) {
+        {
+            let ledger = &mut *self.ledger.write();
+            if let Entry::Occupied(mut e) = ledger.session_wants.entry(cid) {
+                e.get_mut().remove(&session);
+                if let Some(priority) = e.get().values().copied().max() {
+                    // the want already sent keeps its priority, the remaining sessions raising it
+                    // again from theirs
+                    if let Some(current) = ledger.local_want_list.get_mut(&cid) {
+                        *current = priority;
+                    }
+                    return;
+                }
+                e.remove();
+            }
+        }
+        self.cancel(cid)
+    }
+
+    /// Asks the providers for a block which is still wanted, keeping its priority.
+    pub fn want_from(&mut self, cid: &Cid, providers: &[PeerId]) {
+        let Some(priority) = self.ledger.read().local_want_list.get(cid).copied() else {
+            return;
+        };
+        self.get_with_priority(cid, providers, priority)
+    }
+
+    pub fn gets(&mut self, cid: Vec<Cid>, providers: &[PeerId], priority: i32) {
         for cid in cid {
-            self.get(&cid, providers)
+            self.get_with_priority(&cid, providers, priority)
         }
     }
 
@@ -177,14 +334,31 @@ impl Behaviour {
             .unwrap_or_default()
     }
 
+    /// Returns whether blocks are being exchanged with the peer, either wanted by the peer or
+    /// wanted from it.
+    pub fn has_session(&self, peer_id: &PeerId) -> bool {
+        let ledger = &*self.ledger.read();
+        ledger
+            .peer_wantlist
+            .get(peer_id)
+            .map_or(false, |list| !list.is_empty())
+            || ledger
+                .sent_wants
+                .values()
+                .any(|peers| peers.contains(peer_id))
+            || ledger.pending_have_block.values().any(|id| id == peer_id)
+    }
+
     // Note: This is called specifically to cancel the request and not just emitting a request
     //       after receiving a request.
     pub fn cancel(&mut self, cid: Cid) {
         let ledger = &mut *self.ledger.write();
 
+        ledger.session_wants.remove(&cid);
         if ledger.local_want_list.remove(&cid).is_none() {
             return;
         }
+        ledger.forget_want(&cid);
 
         let request = BitswapRequest::cancel(cid);
 
@@ -269,6 +443,9 @@ impl Behaviour {
             ..
         }: ConnectionEstablished,
     ) {
+        // the wants are sent to the provider below
+        self.ledger.write().dialing_providers.remove(&peer_id);
+
         let address = endpoint.get_remote_address().clone();
         self.connections
             .entry(peer_id)
@@ -291,6 +468,7 @@ impl Behaviour {
             ..
         }: ConnectionClosed,
     ) {
+        self.responses.retain(|(_, id, _)| *id != connection_id);
         let ledger = &mut *self.ledger.write();
         let address = endpoint.get_remote_address().clone();
         if let Entry::Occupied(mut entry) = self.connections.entry(peer_id) {
@@ -346,17 +524,53 @@ impl Behaviour {
 
         let ledger = &mut *self.ledger.write();
 
-        // Remove entry from all wants
-        for list in ledger.sent_wants.values_mut() {
-            list.remove(&peer_id);
+        // Remove entry from all wants, keeping the wants which were waiting on the peer
+        let mut affected = ledger
+            .dialing_providers
+            .remove(&peer_id)
+            .unwrap_or_default();
+        for (cid, list) in ledger.sent_wants.iter_mut() {
+            if list.remove(&peer_id) {
+                affected.insert(*cid);
+            }
+        }
+
+        // Fall back to content discovery for the wants which have no other peer left, at most
+        // once per `NEED_BLOCK_INTERVAL` as every failed dial of a provider would ask again
+        let now = Instant::now();
+        for cid in affected {
+            if !ledger.local_want_list.contains_key(&cid) {
+                continue;
+            }
+            let waiting = ledger
+                .sent_wants
+                .get(&cid)
+                .map(|list| !list.is_empty())
+                .unwrap_or_default()
+                || ledger.pending_have_block.contains_key(&cid)
+                || ledger.have_block.contains_key(&cid)
+                || ledger
+                    .dialing_providers
+                    .values()
+                    .any(|cids| cids.contains(&cid));
+            if !waiting && ledger.need_block(cid, now) {
+                self.events
+                    .push_back(ToSwarm::GenerateEvent(Event::NeedBlock { cid }));
+            }
         }
     }
 
     fn send_wants(&mut self, peer_id: PeerId) {
-        let list = Vec::from_iter(self.ledger.read().local_want_list.keys().copied());
-
-        for cid in list {
-            self.get(&cid, &[peer_id]);
+        let list = Vec::from_iter(
+            self.ledger
+                .read()
+                .local_want_list
+                .iter()
+                .map(|(cid, priority)| (*cid, *priority)),
+        );
+
+        for (cid, priority) in list {
+            self.get_with_priority(&cid, &[peer_id], priority);
         }
     }
 
@@ -365,17 +579,29 @@ impl Behaviour {
         peer_id: PeerId,
         connection_id: ConnectionId,
         handle: TaskHandle,
-    ) -> Option<ToSwarm<<Behaviour as NetworkBehaviour>::ToSwarm, THandlerInEvent<Self>>> {
+    ) -> Option<ToSwarm<Event, BitswapMessage>> {
+        if let TaskHandle::StorageFull { cid } | TaskHandle::Rejected { cid } = handle {
+            self.cancel(cid);
+            return self.events.pop_front();
+        }
+
         let ledger = &mut *self.ledger.write();
         match handle {
             TaskHandle::SendResponse {
                 source: (cid, response),
             } => {
+                if let BitswapResponse::Block(data) = &response {
+                    ledger
+                        .exchanged
+                        .entry(peer_id)
+                        .or_default()
+                        .record_sent(data.len());
+                }
                 return Some(ToSwarm::NotifyHandler {
                     peer_id,
                     handler: NotifyHandler::One(connection_id),
                     event: BitswapMessage::Response(cid, response),
-                })
+                });
             }
             TaskHandle::HaveBlock { cid } => {
                 if let Entry::Occupied(mut e) = ledger.sent_wants.entry(cid) {
@@ -413,7 +639,9 @@ impl Behaviour {
                         peer_id: next_peer_id,
                         handler: NotifyHandler::One(connection_id),
                         event: BitswapMessage::Request(
-                            BitswapRequest::block(cid).send_dont_have(true),
+                            BitswapRequest::block(cid)
+                                .send_dont_have(true)
+                                .set_priority(ledger.want_priority(&cid)),
                         ),
                     });
                 }
@@ -456,7 +684,9 @@ impl Behaviour {
                             peer_id: next_peer_id,
                             handler: NotifyHandler::One(next_connection_id),
                             event: BitswapMessage::Request(
-                                BitswapRequest::block(cid).send_dont_have(true),
+                                BitswapRequest::block(cid)
+                                    .send_dont_have(true)
+                                    .set_priority(ledger.want_priority(&cid)),
                             ),
                         });
                     }
@@ -471,7 +701,10 @@ impl Behaviour {
                 }
             }
             TaskHandle::BlockStored { cid } => {
+                // the single block received completes every session wanting it
                 ledger.local_want_list.remove(&cid);
+                ledger.session_wants.remove(&cid);
+                ledger.forget_want(&cid);
 
                 // First notify the peer that we sent a block request too
                 let peer_id = ledger.pending_have_block.remove(&cid);
@@ -519,13 +752,16 @@ impl Behaviour {
                     }
                 }
             }
+            TaskHandle::StorageFull { .. } | TaskHandle::Rejected { .. } => {
+                unreachable!("cancelled before taking the ledger")
+            }
         }
         None
     }
 }
 
 impl NetworkBehaviour for Behaviour {
-    type ConnectionHandler = OneShotHandler<BitswapProtocol, BitswapMessage, Message>;
+    type ConnectionHandler = OneShotHandler<BitswapProtocol, OutboundMessage, Message>;
     type ToSwarm = Event;
 
     fn handle_pending_inbound_connection(
@@ -554,7 +790,7 @@ impl NetworkBehaviour for Behaviour {
         _: &Multiaddr,
         _: &Multiaddr,
     ) -> Result<THandler<Self>, ConnectionDenied> {
-        Ok(OneShotHandler::default())
+        Ok(self.handler())
     }
 
     fn handle_established_outbound_connection(
@@ -564,7 +800,7 @@ impl NetworkBehaviour for Behaviour {
         _: &Multiaddr,
         _: Endpoint,
     ) -> Result<THandler<Self>, ConnectionDenied> {
-        Ok(OneShotHandler::default())
+        Ok(self.handler())
     }
 
     fn on_connection_handler_event(
@@ -590,6 +826,18 @@ impl NetworkBehaviour for Behaviour {
                 tracing::trace!(%peer_id, %connection_id, "message sent");
                 return;
             }
+            Err(StreamUpgradeError::NegotiationFailed) => {
+                tracing::debug!(%peer_id, %connection_id, "bitswap is not supported by the peer");
+                self.blacklist_connections
+                    .entry(peer_id)
+                    .or_default()
+                    .insert(connection_id);
+                self.events
+                    .push_back(ToSwarm::GenerateEvent(Event::ProtocolUnsupported {
+                        peer_id,
+                    }));
+                return;
+            }
             Err(e) => {
                 tracing::error!(%peer_id, %connection_id, error = %e, "error sending or receiving message");
                 //TODO: Depending on the underlining error, maybe blacklist the peer from further sending/receiving
@@ -602,7 +850,8 @@ impl NetworkBehaviour for Behaviour {
             }
         };
 
-        let messages = BitswapMessage::from_proto(message).unwrap_or_default();
+        let mut messages = BitswapMessage::from_proto(message).unwrap_or_default();
+        prioritize(&mut messages);
 
         let task_handler = self
             .tasks
@@ -655,6 +904,12 @@ impl NetworkBehaviour for Behaviour {
                             }
                             BitswapResponse::Block(data) => {
                                 tracing::info!(block = %cid, %peer_id, %connection_id, block_size=data.len(), "received block");
+                                ledger
+                                    .write()
+                                    .exchanged
+                                    .entry(peer_id)
+                                    .or_default()
+                                    .record_received(data.len());
                                 if repo.contains(&cid).await.unwrap_or_default() {
                                     tracing::info!(block = %cid, %peer_id, %connection_id, "block exist locally. skipping");
                                     continue;
@@ -668,11 +923,33 @@ impl NetworkBehaviour for Behaviour {
                                     continue;
                                 };
 
-                                match repo.put_block(block).await {
-                                    Ok(local_cid) => {
+                                // the same block sent by several peers at once is only stored once
+                                if !ledger.write().receiving.insert(cid) {
+                                    tracing::info!(block = %cid, %peer_id, %connection_id, "block already being stored. skipping");
+                                    continue;
+                                }
+                                let stored = repo.put_block_from(block, BlockSource::Bitswap(Some(peer_id))).await;
+                                ledger.write().receiving.remove(&cid);
+
+                                match stored {
+                                    Ok((local_cid, _)) => {
                                         tracing::info!(block = %local_cid, %peer_id, %connection_id, "block stored in block store.");
                                         yield TaskHandle::BlockStored { cid }
                                     },
+                                    Err(e) if e.is::<StorageFull>() => {
+                                        // requesting the block again would fail the same way
+                                        tracing::error!(block = %cid, %peer_id, %connection_id, error = %e, "no room for block in block store");
+                                        repo.fail_subscriptions(&cid, &e);
+                                        yield TaskHandle::StorageFull { cid };
+                                        continue;
+                                    }
+                                    Err(e) if e.is::<PolicyViolation>() => {
+                                        // the block of a cid is the same whichever peer sends it
+                                        tracing::warn!(block = %cid, %peer_id, %connection_id, error = %e, "block rejected by the block policy");
+                                        repo.fail_subscriptions(&cid, &e);
+                                        yield TaskHandle::Rejected { cid };
+                                        continue;
+                                    }
                                     Err(e) => {
                                         tracing::error!(block = %cid, %peer_id, %connection_id, error = %e, "error inserting block into block store");
                                         yield TaskHandle::DontHaveBlock { cid };
@@ -699,15 +976,33 @@ impl NetworkBehaviour for Behaviour {
     }
 
     fn poll(&mut self, ctx: &mut Context) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        let compression = self.compression;
+        let outbound = |message| OutboundMessage {
+            message,
+            compression,
+        };
+
         if let Some(event) = self.events.pop_front() {
-            return Poll::Ready(event);
+            return Poll::Ready(event.map_in(outbound));
         }
 
         while let Poll::Ready(Some(((peer_id, connection_id), handle))) =
             self.tasks.poll_next_unpin(ctx)
         {
+            // the responses contend with each other for the serve order
+            if let TaskHandle::SendResponse { source } = handle {
+                self.responses.push_back((peer_id, connection_id, source));
+                continue;
+            }
             if let Some(event) = self.process_handle(peer_id, connection_id, handle) {
-                return Poll::Ready(event);
+                return Poll::Ready(event.map_in(outbound));
+            }
+        }
+
+        if let Some((peer_id, connection_id, source)) = self.next_response() {
+            let handle = TaskHandle::SendResponse { source };
+            if let Some(event) = self.process_handle(peer_id, connection_id, handle) {
+                return Poll::Ready(event.map_in(outbound));
             }
         }
 
@@ -739,6 +1034,16 @@ pub enum BitswapResult {
     EmptyResponse,
 }
 
+/// Orders the requests of an inbound message by descending priority so the most wanted blocks
+/// are served first. Requests of the same priority keep their order, and the responses follow the
+/// requests as they do in [`BitswapMessage::from_proto`].
+fn prioritize(messages: &mut [BitswapMessage]) {
+    messages.sort_by_key(|message| match message {
+        BitswapMessage::Request(request) => std::cmp::Reverse(request.priority),
+        BitswapMessage::Response(..) => std::cmp::Reverse(i32::MIN),
+    });
+}
+
 pub async fn handle_inbound_request(
     from: PeerId,
     repo: &Repo,
@@ -751,7 +1056,12 @@ pub async fn handle_inbound_request(
 
     match request.ty {
         RequestType::Have => {
-            let have = repo.contains(&request.cid).await.unwrap_or_default();
+            // the block might be stored under a cid of another codec or version
+            let have = repo.contains(&request.cid).await.unwrap_or_default()
+                || matches!(
+                    repo.get_block_by_multihash(request.cid.hash()).await,
+                    Ok(Some(_))
+                );
 
             ledger
                 .write()
@@ -767,7 +1077,13 @@ pub async fn handle_inbound_request(
             }
         }
         RequestType::Block => {
-            let block = repo.get_block_now(&request.cid).await.unwrap_or_default();
+            let block = match repo.get_block_now(&request.cid).await {
+                Ok(Some(block)) => Some(block),
+                _ => repo
+                    .get_block_by_multihash(request.cid.hash())
+                    .await
+                    .unwrap_or_default(),
+            };
             if let Some(data) = block.map(|b| Bytes::copy_from_slice(b.data())) {
                 Some(BitswapResponse::Block(data))
             } else if request.send_dont_have {
@@ -791,6 +1107,41 @@ pub struct LedgerInner {
     pub sent_wants: HashMap<Cid, HashSet<PeerId>>,
     pub have_block: HashMap<Cid, VecDeque<(PeerId, ConnectionId)>>,
     pub pending_have_block: HashMap<Cid, PeerId>,
+    /// Priority each session wants the blocks of `local_want_list` at
+    pub session_wants: HashMap<Cid, HashMap<u64, i32>>,
+    /// Blocks received and being stored
+    pub receiving: HashSet<Cid>,
+    /// Bytes exchanged with the peers, kept once they disconnect
+    pub exchanged: HashMap<PeerId, PeerLedger>,
+    /// Wants waiting on the provider hints being dialed
+    pub dialing_providers: HashMap<PeerId, HashSet<Cid>>,
+    /// When content discovery was last asked for a want after failed dials
+    pub need_block_at: HashMap<Cid, Instant>,
+}
+
+impl LedgerInner {
+    fn want_priority(&self, cid: &Cid) -> i32 {
+        self.local_want_list.get(cid).copied().unwrap_or(1)
+    }
+
+    /// Returns whether content discovery can be asked for the want again.
+    fn need_block(&mut self, cid: Cid, now: Instant) -> bool {
+        if let Some(at) = self.need_block_at.get(&cid) {
+            if now.duration_since(*at) < NEED_BLOCK_INTERVAL {
+                return false;
+            }
+        }
+        self.need_block_at.insert(cid, now);
+        true
+    }
+
+    fn forget_want(&mut self, cid: &Cid) {
+        self.need_block_at.remove(cid);
+        self.dialing_providers.retain(|_, cids| {
+            cids.remove(cid);
+            !cids.is_empty()
+        });
+    }
 }
 
 impl core::ops::Deref for Ledger {
@@ -802,7 +1153,7 @@ impl core::ops::Deref for Ledger {
 
 #[cfg(test)]
 mod test {
-    use std::time::Duration;
+    use std::{collections::HashMap, time::Duration};
 
     use futures::StreamExt;
     use libipld::{
@@ -810,11 +1161,20 @@ mod test {
         Cid, IpldCodec,
     };
     use libp2p::{
-        swarm::{dial_opts::DialOpts, SwarmEvent},
+        swarm::{dial_opts::DialOpts, ConnectionId, SwarmEvent, ToSwarm},
         Multiaddr, PeerId, Swarm, SwarmBuilder,
     };
 
-    use crate::{repo::Repo, Block};
+    use crate::{
+        p2p::{PeerLedger, ServeOrder},
+        repo::Repo,
+        Block,
+    };
+
+    use super::{
+        bitswap_pb,
+        message::{BitswapMessage, BitswapRequest, BitswapResponse, RequestType},
+    };
 
     fn create_block() -> Block {
         let data = b"hello block\n".to_vec();
@@ -879,6 +1239,243 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn ledgers_count_the_exchanged_blocks() -> anyhow::Result<()> {
+        let (peer1, _, mut swarm1, repo) = build_swarm().await;
+        let (peer2, addr2, mut swarm2, repo2) = build_swarm().await;
+
+        let block = create_block();
+        let cid = *block.cid();
+        repo.put_block(block.clone()).await?;
+
+        swarm1.dial(DialOpts::peer_id(peer2).addresses(vec![addr2]).build())?;
+
+        loop {
+            futures::select! {
+                event = swarm1.select_next_some() => {
+                    if let SwarmEvent::ConnectionEstablished { .. } = event {
+                        break;
+                    }
+                }
+                _ = swarm2.next() => {}
+            }
+        }
+
+        swarm2.behaviour_mut().get(&cid, &[peer1]);
+
+        loop {
+            tokio::select! {
+                _ = swarm1.next() => {}
+                _ = swarm2.next() => {}
+                Ok(true) = repo2.contains(&cid) => {
+                    break;
+                }
+            }
+        }
+
+        let size = block.data().len() as u64;
+
+        let sent = swarm1.behaviour().peer_ledger(&peer2);
+        assert_eq!((sent.bytes_sent, sent.blocks_sent), (size, 1));
+        assert_eq!(sent.bytes_received, 0);
+
+        let received = swarm2.behaviour().peer_ledger(&peer1);
+        assert_eq!(
+            (received.bytes_received, received.blocks_received),
+            (size, 1)
+        );
+        assert_eq!(received.bytes_sent, 0);
+
+        swarm2.behaviour_mut().reset_peer_ledger(&peer1);
+        assert_eq!(
+            swarm2.behaviour().peer_ledger(&peer1),
+            PeerLedger::default()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn debt_ratio_order_serves_the_contributing_peers_first() {
+        let (leecher, seeder) = (PeerId::random(), PeerId::random());
+        let mut ledgers = HashMap::from([
+            (leecher, PeerLedger::default()),
+            (seeder, PeerLedger::default()),
+        ]);
+        ledgers.get_mut(&leecher).unwrap().record_sent(1000);
+        ledgers.get_mut(&seeder).unwrap().record_received(1000);
+
+        let mut behaviour = super::Behaviour::new(&Repo::new_memory())
+            .with_serve_order(ServeOrder::DebtRatio)
+            .with_peer_ledgers(ledgers);
+
+        let cids = (0..3u8)
+            .map(|i| Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&[i])))
+            .collect::<Vec<_>>();
+        let connection_id = ConnectionId::new_unchecked(0);
+        for (peer_id, cid) in [(leecher, cids[0]), (seeder, cids[1]), (leecher, cids[2])] {
+            let response = (cid, BitswapResponse::Have(true));
+            behaviour
+                .responses
+                .push_back((peer_id, connection_id, response));
+        }
+
+        let order = std::iter::from_fn(|| behaviour.next_response())
+            .map(|(peer_id, _, (cid, _))| (peer_id, cid))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            [(seeder, cids[1]), (leecher, cids[0]), (leecher, cids[2])]
+        );
+    }
+
+    /// Takes the wants and cancels queued for the peers, as (peer, cid, priority, cancel).
+    fn sent_requests(behaviour: &mut super::Behaviour) -> Vec<(PeerId, Cid, i32, bool)> {
+        std::mem::take(&mut behaviour.events)
+            .into_iter()
+            .filter_map(|event| match event {
+                ToSwarm::NotifyHandler {
+                    peer_id,
+                    event: BitswapMessage::Request(request),
+                    ..
+                } => Some((peer_id, request.cid, request.priority, request.cancel)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn connected_behaviour(peers: &[PeerId]) -> super::Behaviour {
+        let mut behaviour = super::Behaviour::new(&Repo::new_memory());
+        for peer_id in peers {
+            behaviour
+                .connections
+                .entry(*peer_id)
+                .or_default()
+                .insert((ConnectionId::new_unchecked(0), Multiaddr::empty()));
+        }
+        behaviour
+    }
+
+    #[test]
+    fn sessions_share_a_single_want() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let mut behaviour = connected_behaviour(&[a, b]);
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(b"shared"));
+
+        behaviour.get_in_session(1, &cid, &[], 1);
+        let sent = sent_requests(&mut behaviour);
+        assert_eq!(sent.len(), 2);
+        assert!(sent
+            .iter()
+            .all(|(_, c, p, cancel)| *c == cid && *p == 1 && !cancel));
+
+        // wanted by another session at the same priority, the peers are not asked again
+        behaviour.get_in_session(2, &cid, &[], 1);
+        assert!(sent_requests(&mut behaviour).is_empty());
+
+        // a higher priority updates the want of the peers
+        behaviour.get_in_session(3, &cid, &[], 5);
+        let sent = sent_requests(&mut behaviour);
+        assert_eq!(sent.len(), 2);
+        assert!(sent.iter().all(|(_, _, p, cancel)| *p == 5 && !cancel));
+
+        // only the last session cancelling cancels the want
+        behaviour.cancel_in_session(3, cid);
+        behaviour.cancel_in_session(1, cid);
+        assert!(sent_requests(&mut behaviour).is_empty());
+        assert_eq!(behaviour.local_wantlist(), vec![cid]);
+
+        behaviour.cancel_in_session(2, cid);
+        let sent = sent_requests(&mut behaviour);
+        assert_eq!(sent.len(), 2);
+        assert!(sent.iter().all(|(_, _, _, cancel)| *cancel));
+        assert!(behaviour.local_wantlist().is_empty());
+    }
+
+    #[test]
+    fn received_block_completes_every_session() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let mut behaviour = connected_behaviour(&[a, b]);
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(b"arrival"));
+
+        behaviour.get_in_session(1, &cid, &[], 1);
+        behaviour.get_in_session(2, &cid, &[], 1);
+        behaviour.get_in_session(3, &cid, &[], 1);
+        assert_eq!(sent_requests(&mut behaviour).len(), 2);
+
+        // cancelled by a session before the block arrives
+        behaviour.cancel_in_session(1, cid);
+        assert!(sent_requests(&mut behaviour).is_empty());
+
+        let event = behaviour.process_handle(
+            a,
+            ConnectionId::new_unchecked(0),
+            super::TaskHandle::BlockStored { cid },
+        );
+        assert!(matches!(
+            event,
+            Some(ToSwarm::GenerateEvent(super::Event::BlockRetrieved { cid: retrieved })) if retrieved == cid
+        ));
+        assert!(behaviour.local_wantlist().is_empty());
+        assert!(behaviour.ledger.read().session_wants.is_empty());
+        // the peers still asked for it are told it is no longer wanted
+        assert!(sent_requests(&mut behaviour)
+            .iter()
+            .all(|(_, _, _, cancel)| *cancel));
+
+        // the completed sessions cancelling afterwards send nothing
+        behaviour.cancel_in_session(2, cid);
+        behaviour.cancel_in_session(3, cid);
+        assert!(sent_requests(&mut behaviour).is_empty());
+
+        // and a session wanting it again asks the peers again
+        behaviour.get_in_session(4, &cid, &[], 1);
+        assert_eq!(sent_requests(&mut behaviour).len(), 2);
+    }
+
+    #[cfg(feature = "bitswap_compression")]
+    #[tokio::test]
+    async fn exchange_compressed_blocks() -> anyhow::Result<()> {
+        // the peer not offering compression is sent the uncompressed messages
+        for remote_compression in [true, false] {
+            let (peer1, _, mut swarm1, repo) = build_swarm_with_compression(true).await;
+            let (peer2, addr2, mut swarm2, repo2) =
+                build_swarm_with_compression(remote_compression).await;
+
+            let data = b"a highly compressible block\n".repeat(1024);
+            let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+            let block = Block::new_unchecked(cid, data);
+            repo.put_block(block.clone()).await?;
+
+            swarm1.dial(DialOpts::peer_id(peer2).addresses(vec![addr2]).build())?;
+
+            loop {
+                futures::select! {
+                    event = swarm1.select_next_some() => {
+                        if let SwarmEvent::ConnectionEstablished { .. } = event {
+                            break;
+                        }
+                    }
+                    _ = swarm2.next() => {}
+                }
+            }
+
+            swarm2.behaviour_mut().get(&cid, &[peer1]);
+
+            loop {
+                tokio::select! {
+                    _ = swarm1.next() => {}
+                    _ = swarm2.next() => {}
+                    Ok(true) = repo2.contains(&cid) => break,
+                }
+            }
+
+            assert_eq!(repo2.get_block_now(&cid).await?, Some(block));
+        }
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn notify_after_block_exchange() -> anyhow::Result<()> {
         let (peer1, _, mut swarm1, repo) = build_swarm().await;
@@ -988,6 +1585,109 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn priority_round_trips() -> anyhow::Result<()> {
+        let cid = *create_block().cid();
+
+        for request in [
+            BitswapRequest::have(cid).set_priority(42),
+            BitswapRequest::block(cid)
+                .send_dont_have(true)
+                .set_priority(-7),
+        ] {
+            let message = BitswapMessage::Request(request).into_proto()?;
+            let decoded = BitswapMessage::from_proto(message)?;
+            match decoded.as_slice() {
+                [BitswapMessage::Request(decoded)] => assert_eq!(*decoded, request),
+                other => panic!("unexpected messages {other:?}"),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn higher_priorities_are_served_first() -> anyhow::Result<()> {
+        let repo = Repo::new_memory();
+        let ledger = super::Ledger::default();
+        let peer_id = PeerId::random();
+
+        let mut entries = vec![];
+        let mut wanted = vec![];
+        for priority in [1, 10, 5, 3, 10] {
+            let data = format!("block with priority {priority} #{}", entries.len()).into_bytes();
+            let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+            repo.put_block(Block::new_unchecked(cid, data)).await?;
+
+            entries.push(bitswap_pb::message::wantlist::Entry {
+                block: cid.to_bytes(),
+                wantType: RequestType::Block.into(),
+                priority,
+                ..Default::default()
+            });
+            wanted.push(cid);
+        }
+
+        let message = bitswap_pb::Message {
+            wantlist: Some(bitswap_pb::message::Wantlist {
+                entries,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let mut messages = BitswapMessage::from_proto(message)?;
+        super::prioritize(&mut messages);
+
+        // only as many blocks as the budget allows are sent for this message
+        let budget = 3;
+        let mut sent = vec![];
+        for message in messages.into_iter().take(budget) {
+            let BitswapMessage::Request(request) = message else {
+                panic!("responses are ordered after requests");
+            };
+            let response = super::handle_inbound_request(peer_id, &repo, &ledger, &request).await;
+            assert!(matches!(response, Some(BitswapResponse::Block(_))));
+            sent.push(request.cid);
+        }
+
+        // equal priorities keep the order they were wanted in
+        assert_eq!(sent, vec![wanted[1], wanted[4], wanted[2]]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wants_are_served_by_multihash() -> anyhow::Result<()> {
+        let repo = Repo::new_memory();
+        let ledger = super::Ledger::default();
+        let peer_id = PeerId::random();
+
+        let block = create_block();
+        repo.put_block(block.clone()).await?;
+
+        // the same hash under the dag-pb codec
+        let cid = Cid::new_v1(IpldCodec::DagPb.into(), *block.cid().hash());
+        assert!(!repo.contains(&cid).await?);
+
+        let request = BitswapRequest::have(cid).send_dont_have(true);
+        let response = super::handle_inbound_request(peer_id, &repo, &ledger, &request).await;
+        assert!(matches!(response, Some(BitswapResponse::Have(true))));
+
+        let request = BitswapRequest::block(cid);
+        let response = super::handle_inbound_request(peer_id, &repo, &ledger, &request).await;
+        match response {
+            Some(BitswapResponse::Block(data)) => assert_eq!(data.as_ref(), block.data()),
+            other => panic!("unexpected response {other:?}"),
+        }
+
+        // removed blocks are no longer found
+        repo.remove_block(block.cid(), false).await?;
+        assert_eq!(repo.get_block_by_multihash(cid.hash()).await?, None);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn local_wantlist() -> anyhow::Result<()> {
         let (_, _, mut swarm1, _) = build_swarm().await;
@@ -1061,7 +1761,54 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn failed_dials_ask_for_content_discovery_once() {
+        use libp2p::swarm::{DialError, DialFailure, FromSwarm, NetworkBehaviour};
+
+        fn fail_dial(behaviour: &mut super::Behaviour, peer_id: PeerId) -> Vec<Cid> {
+            behaviour.events.clear();
+            behaviour.on_swarm_event(FromSwarm::DialFailure(DialFailure {
+                peer_id: Some(peer_id),
+                error: &DialError::Aborted,
+                connection_id: ConnectionId::new_unchecked(0),
+            }));
+            behaviour
+                .events
+                .drain(..)
+                .filter_map(|event| match event {
+                    ToSwarm::GenerateEvent(super::Event::NeedBlock { cid }) => Some(cid),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        let repo = Repo::new_memory();
+        let mut behaviour = super::Behaviour::new(&repo);
+        let cid = *create_block().cid();
+        let other = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(b"other"));
+        let provider = PeerId::random();
+        let other_provider = PeerId::random();
+
+        behaviour.get(&cid, &[provider]);
+        behaviour.get(&other, &[other_provider]);
+
+        // only the want waiting on the failed provider falls back to content discovery
+        assert_eq!(fail_dial(&mut behaviour, provider), vec![cid]);
+
+        // the provider failing again right away does not ask again
+        behaviour.get(&cid, &[provider]);
+        assert!(fail_dial(&mut behaviour, provider).is_empty());
+
+        assert_eq!(fail_dial(&mut behaviour, other_provider), vec![other]);
+    }
+
     async fn build_swarm() -> (PeerId, Multiaddr, Swarm<super::Behaviour>, Repo) {
+        build_swarm_with_compression(false).await
+    }
+
+    async fn build_swarm_with_compression(
+        compression: bool,
+    ) -> (PeerId, Multiaddr, Swarm<super::Behaviour>, Repo) {
         let repo = Repo::new_memory();
 
         let mut swarm = SwarmBuilder::with_new_identity()
@@ -1072,7 +1819,7 @@ mod test {
                 libp2p::yamux::Config::default,
             )
             .expect("")
-            .with_behaviour(|_| super::Behaviour::new(&repo))
+            .with_behaviour(|_| super::Behaviour::new(&repo).with_compression(compression))
             .expect("")
             .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(30)))
             .build();
@@ -1,24 +1,46 @@
 use std::io;
-use std::iter;
 
 use asynchronous_codec::{FramedRead, FramedWrite};
 use futures::{future::BoxFuture, AsyncRead, AsyncWrite, SinkExt, StreamExt};
 use libp2p::{core::UpgradeInfo, InboundUpgrade, OutboundUpgrade, StreamProtocol};
+#[cfg(feature = "bitswap_compression")]
+use {
+    asynchronous_codec::{BytesMut, Decoder, Encoder},
+    futures::{AsyncReadExt, AsyncWriteExt},
+    std::io::Read,
+};
 
 use super::{bitswap_pb, message::BitswapMessage};
 
-const PROTOCOL: StreamProtocol = StreamProtocol::new("/ipfs/bitswap/1.2.0");
+pub(crate) const PROTOCOL: StreamProtocol = StreamProtocol::new("/ipfs/bitswap/1.2.0");
+/// Same as [`PROTOCOL`], with the message compressed with zstd. Only offered when compression is
+/// enabled, so that peers not supporting it negotiate the plain protocol.
+#[cfg(feature = "bitswap_compression")]
+const PROTOCOL_ZSTD: StreamProtocol = StreamProtocol::new("/ipfs/bitswap/1.2.0/zstd");
 const MAX_BUF_SIZE: usize = 2_097_152;
+#[cfg(feature = "bitswap_compression")]
+const ZSTD_LEVEL: i32 = 3;
+
+/// Without the `bitswap_compression` feature, only the plain protocol is offered.
+fn protocols(compression: bool) -> Vec<StreamProtocol> {
+    match compression {
+        #[cfg(feature = "bitswap_compression")]
+        true => vec![PROTOCOL_ZSTD, PROTOCOL],
+        _ => vec![PROTOCOL],
+    }
+}
 
 #[derive(Debug, Clone, Default)]
-pub struct BitswapProtocol;
+pub struct BitswapProtocol {
+    pub compression: bool,
+}
 
 impl UpgradeInfo for BitswapProtocol {
     type Info = StreamProtocol;
-    type InfoIter = iter::Once<Self::Info>;
+    type InfoIter = std::vec::IntoIter<Self::Info>;
 
     fn protocol_info(&self) -> Self::InfoIter {
-        iter::once(PROTOCOL)
+        protocols(self.compression).into_iter()
     }
 }
 
@@ -30,8 +52,15 @@ where
     type Error = io::Error;
     type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
 
-    fn upgrade_inbound(self, socket: TSocket, _: Self::Info) -> Self::Future {
+    fn upgrade_inbound(self, socket: TSocket, info: Self::Info) -> Self::Future {
         Box::pin(async move {
+            #[cfg(feature = "bitswap_compression")]
+            if info == PROTOCOL_ZSTD {
+                return read_compressed(socket).await;
+            }
+            #[cfg(not(feature = "bitswap_compression"))]
+            debug_assert_eq!(info, PROTOCOL);
+
             let mut framed = FramedRead::new(
                 socket,
                 quick_protobuf_codec::Codec::<bitswap_pb::Message>::new(MAX_BUF_SIZE),
@@ -48,16 +77,54 @@ where
     }
 }
 
-impl UpgradeInfo for BitswapMessage {
+/// Reads a message compressed as a whole, limiting the size of the decompressed message as for
+/// uncompressed ones.
+#[cfg(feature = "bitswap_compression")]
+async fn read_compressed<TSocket>(socket: TSocket) -> io::Result<bitswap_pb::Message>
+where
+    TSocket: AsyncRead + Unpin,
+{
+    // a compressed message may be slightly larger than the original when it does not compress
+    let mut compressed = vec![];
+    socket
+        .take(2 * MAX_BUF_SIZE as u64)
+        .read_to_end(&mut compressed)
+        .await?;
+
+    let mut data = vec![];
+    zstd::stream::read::Decoder::with_buffer(&compressed[..])?
+        .take(MAX_BUF_SIZE as u64 + 1)
+        .read_to_end(&mut data)?;
+    if data.len() > MAX_BUF_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "decompressed message exceeds the maximum size",
+        ));
+    }
+
+    let mut data = BytesMut::from(&data[..]);
+    quick_protobuf_codec::Codec::<bitswap_pb::Message>::new(MAX_BUF_SIZE)
+        .decode(&mut data)?
+        .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+}
+
+/// A message to be sent, along with whether it can be sent compressed.
+#[derive(Debug)]
+pub struct OutboundMessage {
+    pub message: BitswapMessage,
+    pub compression: bool,
+}
+
+impl UpgradeInfo for OutboundMessage {
     type Info = StreamProtocol;
-    type InfoIter = iter::Once<Self::Info>;
+    type InfoIter = std::vec::IntoIter<Self::Info>;
 
     fn protocol_info(&self) -> Self::InfoIter {
-        iter::once(PROTOCOL)
+        protocols(self.compression).into_iter()
     }
 }
 
-impl<TSocket> OutboundUpgrade<TSocket> for BitswapMessage
+impl<TSocket> OutboundUpgrade<TSocket> for OutboundMessage
 where
     TSocket: AsyncRead + AsyncWrite + Send + Unpin + 'static,
 {
@@ -66,14 +133,26 @@ where
     type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
 
     #[inline]
-    fn upgrade_outbound(self, socket: TSocket, _info: Self::Info) -> Self::Future {
+    fn upgrade_outbound(self, socket: TSocket, info: Self::Info) -> Self::Future {
         Box::pin(async move {
-            let mut framed = FramedWrite::new(
-                socket,
-                quick_protobuf_codec::Codec::<bitswap_pb::Message>::new(MAX_BUF_SIZE),
-            );
-
-            let message = self.into_proto()?;
+            let message = self.message.into_proto()?;
+            let codec = quick_protobuf_codec::Codec::<bitswap_pb::Message>::new(MAX_BUF_SIZE);
+
+            #[cfg(feature = "bitswap_compression")]
+            if info == PROTOCOL_ZSTD {
+                let mut socket = socket;
+                let mut data = BytesMut::new();
+                let mut codec = codec;
+                codec.encode(message, &mut data)?;
+                let compressed = zstd::bulk::compress(&data, ZSTD_LEVEL)?;
+                socket.write_all(&compressed).await?;
+                socket.close().await?;
+                return Ok(());
+            }
+            #[cfg(not(feature = "bitswap_compression"))]
+            debug_assert_eq!(info, PROTOCOL);
+
+            let mut framed = FramedWrite::new(socket, codec);
 
             framed.send(message).await?;
             framed.close().await?;
@@ -101,3 +180,17 @@ impl From<()> for Message {
         Message::Sent
     }
 }
+
+#[cfg(all(test, feature = "bitswap_compression"))]
+mod tests {
+    use super::{read_compressed, MAX_BUF_SIZE};
+
+    #[tokio::test]
+    async fn decompressed_size_is_limited() {
+        let bomb = zstd::bulk::compress(&vec![0; 4 * MAX_BUF_SIZE], 3).unwrap();
+        assert!(bomb.len() < MAX_BUF_SIZE);
+
+        let error = read_compressed(&bomb[..]).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use libp2p::PeerId;
+
+/// Limit on the records and provider announcements each peer may store on the node through the
+/// DHT, counted over a sliding window. The writes over the limit are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DhtWriteLimit {
+    pub max_writes: u32,
+    pub interval: Duration,
+}
+
+impl Default for DhtWriteLimit {
+    /// Far above the republishing of a well behaved peer, which announces the same keys every
+    /// few hours.
+    fn default() -> Self {
+        Self {
+            max_writes: 256,
+            interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Inbound DHT writes of the peers, see [`Ipfs::dht_stats`](crate::Ipfs::dht_stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DhtStats {
+    pub stored_records: u64,
+    pub stored_providers: u64,
+    /// Records dropped as their peer was over the [`DhtWriteLimit`]
+    pub rejected_records: u64,
+    /// Provider announcements dropped as their peer was over the [`DhtWriteLimit`]
+    pub rejected_providers: u64,
+}
+
+/// Peers with writes in the window above which the peers done writing are forgotten.
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Times of the recent writes of each peer.
+#[derive(Debug)]
+pub(crate) struct DhtWriteLimiter {
+    limit: DhtWriteLimit,
+    writes: HashMap<PeerId, VecDeque<Instant>>,
+}
+
+impl DhtWriteLimiter {
+    pub(crate) fn new(limit: DhtWriteLimit) -> Self {
+        Self {
+            limit,
+            writes: HashMap::new(),
+        }
+    }
+
+    /// Counts a write of the peer, returning whether it is within the limit. The writes dropped
+    /// are not counted.
+    pub(crate) fn allow(&mut self, peer_id: PeerId, now: Instant) -> bool {
+        if self.writes.len() > PRUNE_THRESHOLD {
+            let interval = self.limit.interval;
+            self.writes.retain(|_, writes| {
+                writes
+                    .back()
+                    .map(|last| now.duration_since(*last) < interval)
+                    .unwrap_or_default()
+            });
+        }
+
+        let writes = self.writes.entry(peer_id).or_default();
+        while let Some(first) = writes.front() {
+            if now.duration_since(*first) < self.limit.interval {
+                break;
+            }
+            writes.pop_front();
+        }
+
+        if writes.len() >= self.limit.max_writes as usize {
+            return false;
+        }
+        writes.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use libp2p::PeerId;
+
+    use super::{DhtWriteLimit, DhtWriteLimiter};
+
+    #[test]
+    fn writes_over_the_limit_are_refused_until_the_window_slides() {
+        let mut limiter = DhtWriteLimiter::new(DhtWriteLimit {
+            max_writes: 3,
+            interval: Duration::from_secs(10),
+        });
+        let (flooder, other) = (PeerId::random(), PeerId::random());
+        let start = Instant::now();
+
+        for i in 0..3 {
+            assert!(limiter.allow(flooder, start + Duration::from_secs(i)));
+        }
+        assert!(!limiter.allow(flooder, start + Duration::from_secs(3)));
+        // the limit is per peer
+        assert!(limiter.allow(other, start + Duration::from_secs(3)));
+
+        // the first write left the window
+        assert!(limiter.allow(flooder, start + Duration::from_secs(10)));
+        assert!(!limiter.allow(flooder, start + Duration::from_secs(10)));
+        assert!(limiter.allow(flooder, start + Duration::from_secs(12)));
+    }
+}
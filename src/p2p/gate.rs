@@ -0,0 +1,751 @@
+//! Connection gating, deciding which peers and addresses connections are established with.
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use libp2p::core::transport::{ListenerId, TransportError, TransportEvent};
+use libp2p::core::{Endpoint, Multiaddr, Transport};
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::{self, dummy::ConnectionHandler as DummyConnectionHandler, NetworkBehaviour};
+use libp2p::swarm::{
+    behaviour::{ConnectionClosed, ConnectionEstablished},
+    CloseConnection, ConnectionDenied, ConnectionId, FromSwarm, THandler, THandlerInEvent, ToSwarm,
+};
+use libp2p::PeerId;
+use parking_lot::RwLock;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use super::transport::TTransport;
+
+/// Decides whether a connection is established, checked once the peer of the connection is
+/// known. Denied connections are closed during negotiation.
+pub trait ConnectionGate: Send + Sync + 'static {
+    /// Returns whether the inbound connection from `peer_id` on `addr` is allowed.
+    fn allow_inbound(&self, peer_id: &PeerId, addr: &Multiaddr) -> bool;
+
+    /// Returns whether the outbound connection to `peer_id` at `addr` is allowed.
+    fn allow_outbound(&self, peer_id: &PeerId, addr: &Multiaddr) -> bool;
+}
+
+/// Connections denied by the gate since the node started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GateStats {
+    pub denied_inbound: u64,
+    pub denied_outbound: u64,
+}
+
+/// The reason a connection was denied, given to the swarm as the cause of the
+/// [`ConnectionDenied`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum GateDenied {
+    #[error("peer is denied")]
+    DeniedPeer,
+    #[error("peer is not in the allowed peers")]
+    NotAllowedPeer,
+    #[error("address is denied")]
+    DeniedAddress,
+    #[error("address is banned")]
+    BannedAddress,
+    #[error("address is private")]
+    PrivateAddress,
+    #[error("denied by the connection gate")]
+    Gate,
+    #[error("node is offline")]
+    Offline,
+}
+
+#[derive(Default)]
+struct GateRules {
+    allowed_peers: HashSet<PeerId>,
+    denied_peers: HashSet<PeerId>,
+    denied_addrs: Vec<Multiaddr>,
+    /// Addresses banned through [`Ipfs::ban_address`](crate::Ipfs::ban_address), kept apart
+    /// from the denied addresses so that a ban expiring does not lift a deny.
+    banned_addrs: Vec<Multiaddr>,
+    deny_private_addrs: bool,
+}
+
+#[derive(Default)]
+struct GateInner {
+    rules: RwLock<GateRules>,
+    gate: Option<Box<dyn ConnectionGate>>,
+    /// Refuses every outbound connection, see [`GateHandle::set_offline`].
+    offline: AtomicBool,
+    denied_inbound: AtomicU64,
+    denied_outbound: AtomicU64,
+}
+
+/// Handle to the allow and deny rules of the connection gate, updated at runtime and applied to
+/// connections established afterwards. Returned from [`Ipfs::gate`](crate::Ipfs::gate).
+///
+/// The rules are checked before the [`ConnectionGate`] given to the node, if any.
+#[derive(Clone, Default)]
+pub struct GateHandle {
+    inner: Arc<GateInner>,
+}
+
+impl std::fmt::Debug for GateHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GateHandle")
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl GateHandle {
+    pub(crate) fn new(gate: Option<Box<dyn ConnectionGate>>) -> Self {
+        Self {
+            inner: Arc::new(GateInner {
+                gate,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Only accepts inbound connections from the allowed peers, once any peer is allowed.
+    /// Returns false if the peer was already allowed.
+    pub fn allow_peer(&self, peer_id: PeerId) -> bool {
+        self.inner.rules.write().allowed_peers.insert(peer_id)
+    }
+
+    /// Removes the peer from the allowed peers.
+    pub fn remove_allowed_peer(&self, peer_id: &PeerId) -> bool {
+        self.inner.rules.write().allowed_peers.remove(peer_id)
+    }
+
+    /// Denies any connection with the peer. Returns false if the peer was already denied.
+    pub fn deny_peer(&self, peer_id: PeerId) -> bool {
+        self.inner.rules.write().denied_peers.insert(peer_id)
+    }
+
+    /// Removes the peer from the denied peers.
+    pub fn remove_denied_peer(&self, peer_id: &PeerId) -> bool {
+        self.inner.rules.write().denied_peers.remove(peer_id)
+    }
+
+    /// Denies any connection on an address starting with `addr`, e.g. `/ip4/1.2.3.4` denies every
+    /// port and transport of that ip. Returns false if the address was already denied.
+    pub fn deny_addr(&self, addr: Multiaddr) -> bool {
+        let mut rules = self.inner.rules.write();
+        if rules.denied_addrs.contains(&addr) {
+            return false;
+        }
+        rules.denied_addrs.push(addr);
+        true
+    }
+
+    /// Removes the address from the denied addresses.
+    pub fn remove_denied_addr(&self, addr: &Multiaddr) -> bool {
+        let mut rules = self.inner.rules.write();
+        let len = rules.denied_addrs.len();
+        rules.denied_addrs.retain(|denied| denied != addr);
+        rules.denied_addrs.len() != len
+    }
+
+    pub(crate) fn ban_addr(&self, addr: Multiaddr) -> bool {
+        let mut rules = self.inner.rules.write();
+        if rules.banned_addrs.contains(&addr) {
+            return false;
+        }
+        rules.banned_addrs.push(addr);
+        true
+    }
+
+    pub(crate) fn unban_addr(&self, addr: &Multiaddr) -> bool {
+        let mut rules = self.inner.rules.write();
+        let len = rules.banned_addrs.len();
+        rules.banned_addrs.retain(|banned| banned != addr);
+        rules.banned_addrs.len() != len
+    }
+
+    /// Denies connections on private, carrier-grade nat, loopback and link-local ip addresses.
+    pub fn set_deny_private_addrs(&self, deny: bool) {
+        self.inner.rules.write().deny_private_addrs = deny;
+    }
+
+    /// Refuses every outbound connection, for a node started offline.
+    pub(crate) fn set_offline(&self, offline: bool) {
+        self.inner.offline.store(offline, Ordering::Relaxed);
+    }
+
+    fn is_offline(&self) -> bool {
+        self.inner.offline.load(Ordering::Relaxed)
+    }
+
+    /// Returns the connections denied so far.
+    pub fn stats(&self) -> GateStats {
+        GateStats {
+            denied_inbound: self.inner.denied_inbound.load(Ordering::Relaxed),
+            denied_outbound: self.inner.denied_outbound.load(Ordering::Relaxed),
+        }
+    }
+
+    fn check(
+        &self,
+        peer_id: &PeerId,
+        addr: &Multiaddr,
+        endpoint: Endpoint,
+    ) -> Result<(), GateDenied> {
+        if endpoint == Endpoint::Dialer && self.is_offline() {
+            return Err(GateDenied::Offline);
+        }
+
+        {
+            let rules = self.inner.rules.read();
+            if rules.denied_peers.contains(peer_id) {
+                return Err(GateDenied::DeniedPeer);
+            }
+
+            if endpoint == Endpoint::Listener
+                && !rules.allowed_peers.is_empty()
+                && !rules.allowed_peers.contains(peer_id)
+            {
+                return Err(GateDenied::NotAllowedPeer);
+            }
+
+            rules.check_addr(addr)?;
+        }
+
+        let allowed = match (&self.inner.gate, endpoint) {
+            (None, _) => true,
+            (Some(gate), Endpoint::Listener) => gate.allow_inbound(peer_id, addr),
+            (Some(gate), Endpoint::Dialer) => gate.allow_outbound(peer_id, addr),
+        };
+
+        match allowed {
+            true => Ok(()),
+            false => Err(GateDenied::Gate),
+        }
+    }
+
+    /// Checks the address rules of an outbound dial, counting it as denied if it fails.
+    fn check_dial(&self, addr: &Multiaddr) -> Result<(), GateDenied> {
+        let denied = match self.is_offline() {
+            true => Err(GateDenied::Offline),
+            false => self.inner.rules.read().check_addr(addr),
+        };
+        denied.map_err(|reason| {
+            self.deny(None, Some(addr), Endpoint::Dialer, reason);
+            reason
+        })
+    }
+
+    /// Wraps the transport, refusing to dial the denied and, if set, private addresses. Every
+    /// address is checked before it is dialed, including the addresses of a peer found by other
+    /// behaviours, which [`Behaviour`] doesn't see.
+    pub(crate) fn wrap(&self, transport: TTransport) -> TTransport {
+        GatedTransport {
+            inner: transport,
+            handle: self.clone(),
+        }
+        .boxed()
+    }
+
+    fn deny(
+        &self,
+        peer_id: Option<PeerId>,
+        addr: Option<&Multiaddr>,
+        endpoint: Endpoint,
+        reason: GateDenied,
+    ) -> ConnectionDenied {
+        let counter = match endpoint {
+            Endpoint::Listener => &self.inner.denied_inbound,
+            Endpoint::Dialer => &self.inner.denied_outbound,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        trace!(?peer_id, ?addr, ?endpoint, %reason, "gate: connection denied");
+        ConnectionDenied::new(reason)
+    }
+}
+
+impl GateRules {
+    fn check_addr(&self, addr: &Multiaddr) -> Result<(), GateDenied> {
+        if self
+            .denied_addrs
+            .iter()
+            .any(|denied| starts_with(addr, denied))
+        {
+            return Err(GateDenied::DeniedAddress);
+        }
+
+        if self
+            .banned_addrs
+            .iter()
+            .any(|banned| starts_with(addr, banned))
+        {
+            return Err(GateDenied::BannedAddress);
+        }
+
+        if self.deny_private_addrs && is_private(addr) {
+            return Err(GateDenied::PrivateAddress);
+        }
+
+        Ok(())
+    }
+}
+
+fn starts_with(addr: &Multiaddr, prefix: &Multiaddr) -> bool {
+    let mut addr = addr.iter();
+    prefix.iter().all(|protocol| addr.next() == Some(protocol))
+}
+
+fn is_private(addr: &Multiaddr) -> bool {
+    let ip = match addr.iter().next() {
+        Some(Protocol::Ip4(ip)) => IpAddr::V4(ip),
+        Some(Protocol::Ip6(ip)) => IpAddr::V6(ip),
+        _ => return false,
+    };
+
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            // shared address space (100.64.0.0/10) used by carrier-grade nat
+            let shared = octets[0] == 100 && (octets[1] & 0xc0) == 64;
+            ip.is_private() || ip.is_loopback() || ip.is_link_local() || shared
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            // unique local (fc00::/7) and link-local (fe80::/10)
+            ip.is_loopback() || (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/// Transport refusing to dial the addresses denied by the rules of the gate.
+struct GatedTransport {
+    inner: TTransport,
+    handle: GateHandle,
+}
+
+impl GatedTransport {
+    fn check(&self, addr: &Multiaddr) -> Result<(), TransportError<io::Error>> {
+        self.handle.check_dial(addr).map_err(|reason| {
+            TransportError::Other(io::Error::new(io::ErrorKind::PermissionDenied, reason))
+        })
+    }
+}
+
+impl Transport for GatedTransport {
+    type Output = <TTransport as Transport>::Output;
+    type Error = <TTransport as Transport>::Error;
+    type ListenerUpgrade = <TTransport as Transport>::ListenerUpgrade;
+    type Dial = <TTransport as Transport>::Dial;
+
+    fn listen_on(
+        &mut self,
+        id: ListenerId,
+        addr: Multiaddr,
+    ) -> Result<(), TransportError<Self::Error>> {
+        self.inner.listen_on(id, addr)
+    }
+
+    fn remove_listener(&mut self, id: ListenerId) -> bool {
+        self.inner.remove_listener(id)
+    }
+
+    fn dial(&mut self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        self.check(&addr)?;
+        self.inner.dial(addr)
+    }
+
+    fn dial_as_listener(
+        &mut self,
+        addr: Multiaddr,
+    ) -> Result<Self::Dial, TransportError<Self::Error>> {
+        self.check(&addr)?;
+        self.inner.dial_as_listener(addr)
+    }
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<TransportEvent<Self::ListenerUpgrade, Self::Error>> {
+        Pin::new(&mut self.inner).poll(cx)
+    }
+
+    fn address_translation(&self, listen: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.address_translation(listen, observed)
+    }
+}
+
+pub struct Behaviour {
+    handle: GateHandle,
+    /// Remote address of the established connections, to close the ones on a banned address.
+    connections: HashMap<ConnectionId, (PeerId, Multiaddr)>,
+    pending_close: VecDeque<(PeerId, ConnectionId)>,
+    waker: Option<Waker>,
+}
+
+impl Behaviour {
+    pub fn new(handle: GateHandle) -> Self {
+        Self {
+            handle,
+            connections: HashMap::new(),
+            pending_close: VecDeque::new(),
+            waker: None,
+        }
+    }
+
+    /// Closes the established connections on an address starting with `addr`. Returns the
+    /// number of connections closed.
+    pub(crate) fn close_connections_on(&mut self, addr: &Multiaddr) -> usize {
+        let before = self.pending_close.len();
+        self.pending_close.extend(
+            self.connections
+                .iter()
+                .filter(|(_, (_, remote))| starts_with(remote, addr))
+                .map(|(id, (peer_id, _))| (*peer_id, *id)),
+        );
+        let closed = self.pending_close.len() - before;
+        if closed > 0 {
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
+        }
+        closed
+    }
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler = DummyConnectionHandler;
+    type ToSwarm = void::Void;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        // the peer is not known yet, only the address can be checked
+        let denied = self.handle.inner.rules.read().check_addr(remote_addr);
+        denied.map_err(|reason| {
+            self.handle
+                .deny(None, Some(remote_addr), Endpoint::Listener, reason)
+        })
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer_id: Option<PeerId>,
+        addresses: &[Multiaddr],
+        _: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        if self.handle.is_offline() {
+            return Err(self.handle.deny(
+                peer_id,
+                addresses.first(),
+                Endpoint::Dialer,
+                GateDenied::Offline,
+            ));
+        }
+
+        // the addresses of the peer found by other behaviours are checked by the transport
+        // before they are dialed
+        if !addresses.is_empty() {
+            let rules = self.handle.inner.rules.read();
+            let denied = addresses
+                .iter()
+                .map(|addr| rules.check_addr(addr))
+                .collect::<Vec<_>>();
+            drop(rules);
+
+            if denied.iter().all(Result::is_err) {
+                let reason = denied
+                    .into_iter()
+                    .find_map(Result::err)
+                    .unwrap_or(GateDenied::DeniedAddress);
+                return Err(self
+                    .handle
+                    .deny(peer_id, addresses.first(), Endpoint::Dialer, reason));
+            }
+        }
+
+        if let Some(peer_id) = peer_id {
+            if self
+                .handle
+                .inner
+                .rules
+                .read()
+                .denied_peers
+                .contains(&peer_id)
+            {
+                return Err(self.handle.deny(
+                    Some(peer_id),
+                    None,
+                    Endpoint::Dialer,
+                    GateDenied::DeniedPeer,
+                ));
+            }
+        }
+        Ok(vec![])
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer_id: PeerId,
+        _: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.handle
+            .check(&peer_id, remote_addr, Endpoint::Listener)
+            .map_err(|reason| {
+                self.handle
+                    .deny(Some(peer_id), Some(remote_addr), Endpoint::Listener, reason)
+            })?;
+        Ok(DummyConnectionHandler)
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer_id: PeerId,
+        addr: &Multiaddr,
+        _: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.handle
+            .check(&peer_id, addr, Endpoint::Dialer)
+            .map_err(|reason| {
+                self.handle
+                    .deny(Some(peer_id), Some(addr), Endpoint::Dialer, reason)
+            })?;
+        Ok(DummyConnectionHandler)
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _: libp2p::PeerId,
+        _: swarm::ConnectionId,
+        _: swarm::THandlerOutEvent<Self>,
+    ) {
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        match event {
+            FromSwarm::ConnectionEstablished(ConnectionEstablished {
+                peer_id,
+                connection_id,
+                endpoint,
+                ..
+            }) => {
+                self.connections.insert(
+                    connection_id,
+                    (peer_id, endpoint.get_remote_address().clone()),
+                );
+            }
+            FromSwarm::ConnectionClosed(ConnectionClosed { connection_id, .. }) => {
+                self.connections.remove(&connection_id);
+            }
+            _ => {}
+        }
+    }
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        if let Some((peer_id, connection_id)) = self.pending_close.pop_front() {
+            return Poll::Ready(ToSwarm::CloseConnection {
+                peer_id,
+                connection: CloseConnection::One(connection_id),
+            });
+        }
+        self.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct DenyAll;
+
+    impl ConnectionGate for DenyAll {
+        fn allow_inbound(&self, _: &PeerId, _: &Multiaddr) -> bool {
+            false
+        }
+
+        fn allow_outbound(&self, _: &PeerId, _: &Multiaddr) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn rules_are_checked_before_the_gate() {
+        let handle = GateHandle::new(Some(Box::new(DenyAll)));
+        let peer_id = PeerId::random();
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/4001".parse().unwrap();
+
+        assert_eq!(handle.check(&peer_id, &addr, Endpoint::Dialer), Ok(()));
+        assert_eq!(
+            handle.check(&peer_id, &addr, Endpoint::Listener),
+            Err(GateDenied::Gate)
+        );
+
+        handle.deny_addr("/ip4/1.2.3.4".parse().unwrap());
+        assert_eq!(
+            handle.check(&peer_id, &addr, Endpoint::Dialer),
+            Err(GateDenied::DeniedAddress)
+        );
+        assert!(handle.remove_denied_addr(&"/ip4/1.2.3.4".parse().unwrap()));
+
+        handle.deny_peer(peer_id);
+        assert_eq!(
+            handle.check(&peer_id, &addr, Endpoint::Dialer),
+            Err(GateDenied::DeniedPeer)
+        );
+    }
+
+    #[test]
+    fn allowed_peers_only_restrict_inbound() {
+        let handle = GateHandle::default();
+        let allowed = PeerId::random();
+        let other = PeerId::random();
+        let addr: Multiaddr = "/ip4/10.0.0.1/tcp/4001".parse().unwrap();
+
+        handle.allow_peer(allowed);
+        assert_eq!(handle.check(&allowed, &addr, Endpoint::Listener), Ok(()));
+        assert_eq!(
+            handle.check(&other, &addr, Endpoint::Listener),
+            Err(GateDenied::NotAllowedPeer)
+        );
+        assert_eq!(handle.check(&other, &addr, Endpoint::Dialer), Ok(()));
+
+        handle.set_deny_private_addrs(true);
+        assert_eq!(
+            handle.check(&allowed, &addr, Endpoint::Listener),
+            Err(GateDenied::PrivateAddress)
+        );
+    }
+
+    #[test]
+    fn private_addresses_include_shared_address_space() {
+        for addr in [
+            "/ip4/10.1.2.3/tcp/4001",
+            "/ip4/100.64.0.1/tcp/4001",
+            "/ip4/100.127.255.254/udp/4001/quic-v1",
+            "/ip6/fd00::1/tcp/4001",
+        ] {
+            assert!(is_private(&addr.parse().unwrap()), "{addr}");
+        }
+
+        for addr in ["/ip4/100.128.0.1/tcp/4001", "/ip4/1.2.3.4/tcp/4001"] {
+            assert!(!is_private(&addr.parse().unwrap()), "{addr}");
+        }
+    }
+
+    #[test]
+    fn denied_addresses_are_not_dialed() {
+        let handle = GateHandle::default();
+        let mut transport =
+            handle.wrap(libp2p::core::transport::dummy::DummyTransport::new().boxed());
+
+        handle.deny_addr("/ip4/1.2.3.4".parse().unwrap());
+        handle.set_deny_private_addrs(true);
+
+        for addr in ["/ip4/1.2.3.4/tcp/4001", "/ip4/192.168.1.1/tcp/4001"] {
+            match transport.dial(addr.parse().unwrap()) {
+                Err(TransportError::Other(e)) => {
+                    assert_eq!(e.kind(), io::ErrorKind::PermissionDenied)
+                }
+                Err(e) => panic!("unexpected error dialing {addr}: {e}"),
+                Ok(_) => panic!("{addr} was dialed"),
+            }
+        }
+        assert_eq!(handle.stats().denied_outbound, 2);
+
+        // allowed addresses reach the inner transport
+        assert!(matches!(
+            transport.dial("/ip4/5.6.7.8/tcp/4001".parse().unwrap()),
+            Err(TransportError::MultiaddrNotSupported(_))
+        ));
+        assert_eq!(handle.stats().denied_outbound, 2);
+    }
+
+    #[test]
+    fn bans_are_kept_apart_from_denies() {
+        let handle = GateHandle::default();
+        let peer_id = PeerId::random();
+        let prefix: Multiaddr = "/ip4/1.2.3.4".parse().unwrap();
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/4001".parse().unwrap();
+
+        handle.deny_addr(prefix.clone());
+        assert!(handle.ban_addr(prefix.clone()));
+        assert!(handle.unban_addr(&prefix));
+        assert_eq!(
+            handle.check(&peer_id, &addr, Endpoint::Dialer),
+            Err(GateDenied::DeniedAddress)
+        );
+
+        assert!(handle.remove_denied_addr(&prefix));
+        handle.ban_addr(prefix);
+        assert_eq!(
+            handle.check(&peer_id, &addr, Endpoint::Listener),
+            Err(GateDenied::BannedAddress)
+        );
+    }
+
+    #[test]
+    fn banning_closes_the_connections_on_the_address() {
+        let mut behaviour = Behaviour::new(GateHandle::default());
+        let banned = PeerId::random();
+        let other = PeerId::random();
+        behaviour.connections.insert(
+            ConnectionId::new_unchecked(0),
+            (banned, "/ip4/1.2.3.4/tcp/4001".parse().unwrap()),
+        );
+        behaviour.connections.insert(
+            ConnectionId::new_unchecked(1),
+            (other, "/ip4/5.6.7.8/tcp/4001".parse().unwrap()),
+        );
+
+        assert_eq!(
+            behaviour.close_connections_on(&"/ip4/1.2.3.4".parse().unwrap()),
+            1
+        );
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(matches!(
+            behaviour.poll(&mut cx),
+            Poll::Ready(ToSwarm::CloseConnection {
+                peer_id,
+                connection: CloseConnection::One(id),
+            }) if peer_id == banned && id == ConnectionId::new_unchecked(0)
+        ));
+        assert!(behaviour.poll(&mut cx).is_pending());
+    }
+
+    #[test]
+    fn offline_gate_refuses_every_dial() {
+        let handle = GateHandle::default();
+        handle.set_offline(true);
+        let mut transport =
+            handle.wrap(libp2p::core::transport::dummy::DummyTransport::new().boxed());
+        let mut behaviour = Behaviour::new(handle.clone());
+        let peer_id = PeerId::random();
+        let addr: Multiaddr = "/ip4/5.6.7.8/tcp/4001".parse().unwrap();
+
+        assert!(matches!(
+            transport.dial(addr.clone()),
+            Err(TransportError::Other(e)) if e.kind() == io::ErrorKind::PermissionDenied
+        ));
+        // dials by peer id only, e.g. the reconnections to protected peers
+        assert!(behaviour
+            .handle_pending_outbound_connection(
+                ConnectionId::new_unchecked(0),
+                Some(peer_id),
+                &[],
+                Endpoint::Dialer,
+            )
+            .is_err());
+        assert_eq!(handle.stats().denied_outbound, 2);
+
+        // inbound connections are still accepted
+        assert_eq!(handle.check(&peer_id, &addr, Endpoint::Listener), Ok(()));
+        assert_eq!(
+            handle.check(&peer_id, &addr, Endpoint::Dialer),
+            Err(GateDenied::Offline)
+        );
+    }
+}
@@ -2,12 +2,13 @@ use async_broadcast::TrySendError;
 use futures::channel::mpsc::{self as channel};
 use futures::stream::{FusedStream, Stream};
 use libp2p::gossipsub::PublishError;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use tracing::debug;
 
 use libp2p::core::{Endpoint, Multiaddr};
@@ -39,6 +40,45 @@ pub struct GossipsubStream {
         channel::UnboundedSender<TopicHash>,
         channel::UnboundedReceiver<TopicHash>,
     ),
+
+    /// Topics published to without being subscribed, with when they last were
+    published: HashMap<TopicHash, Instant>,
+
+    /// Mesh last sent to the listeners of the mesh changes
+    mesh: HashMap<TopicHash, HashSet<PeerId>>,
+    mesh_listeners: Vec<channel::UnboundedSender<MeshEvent>>,
+}
+
+/// How long gossipsub keeps the fanout of a topic after publishing to it, its default.
+const FANOUT_TTL: Duration = Duration::from_secs(60);
+
+/// The peers of a topic gossipsub sends the messages to, see
+/// [`Ipfs::pubsub_mesh`](crate::Ipfs::pubsub_mesh).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MeshInfo {
+    /// Peers grafted to the mesh of the topic, if subscribed to it
+    pub mesh_peers: Vec<PeerId>,
+    /// Peers subscribed to the topic if published to within the fanout ttl without being
+    /// subscribed to it. Gossipsub does not expose the fanout it picks among them.
+    pub fanout_peers: Vec<PeerId>,
+    /// Peers subscribed to the topic outside of the mesh, only gossiped the ids of the messages
+    pub gossip_peers: Vec<PeerId>,
+}
+
+/// A peer grafted to or pruned from the mesh of a topic, see
+/// [`Ipfs::pubsub_mesh_events`](crate::Ipfs::pubsub_mesh_events).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MeshEvent {
+    Graft { topic: String, peer_id: PeerId },
+    Prune { topic: String, peer_id: PeerId },
+}
+
+impl MeshEvent {
+    pub fn topic(&self) -> &str {
+        match self {
+            MeshEvent::Graft { topic, .. } | MeshEvent::Prune { topic, .. } => topic,
+        }
+    }
 }
 
 impl core::ops::Deref for GossipsubStream {
@@ -141,6 +181,9 @@ impl From<Gossipsub> for GossipsubStream {
             gossipsub,
             unsubscriptions: (tx, rx),
             active_streams: Default::default(),
+            published: Default::default(),
+            mesh: Default::default(),
+            mesh_listeners: Default::default(),
         }
     }
 }
@@ -217,7 +260,11 @@ impl GossipsubStream {
         topic: impl Into<String>,
         data: impl Into<Vec<u8>>,
     ) -> Result<MessageId, PublishError> {
-        self.gossipsub.publish(Topic::new(topic), data)
+        let topic = Topic::new(topic);
+        if !self.streams.contains_key(&topic.hash()) {
+            self.published.insert(topic.hash(), Instant::now());
+        }
+        self.gossipsub.publish(topic, data)
     }
 
     /// Returns the known peers subscribed to any topic
@@ -239,6 +286,115 @@ impl GossipsubStream {
     pub fn subscribed_topics(&self) -> Vec<String> {
         self.streams.keys().map(|t| t.to_string()).collect()
     }
+
+    /// Returns the mesh of the topic, empty unless subscribed to it, and the candidates for its
+    /// fanout if published to without being subscribed.
+    pub fn mesh_info(&mut self, topic: &str) -> MeshInfo {
+        let hash = Topic::new(topic).hash();
+        self.published
+            .retain(|_, published| published.elapsed() < FANOUT_TTL);
+
+        let subscribed = self.subscribed_peers(topic);
+        if self.gossipsub.topics().any(|t| *t == hash) {
+            let mesh_peers = self
+                .gossipsub
+                .mesh_peers(&hash)
+                .copied()
+                .collect::<Vec<_>>();
+            let gossip_peers = subscribed
+                .into_iter()
+                .filter(|peer_id| !mesh_peers.contains(peer_id))
+                .collect();
+            return MeshInfo {
+                mesh_peers,
+                fanout_peers: vec![],
+                gossip_peers,
+            };
+        }
+
+        MeshInfo {
+            fanout_peers: match self.published.contains_key(&hash) {
+                true => subscribed,
+                false => vec![],
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Returns the grafts and prunes of the meshes, starting with a graft for each peer of the
+    /// current meshes.
+    pub fn mesh_events(&mut self) -> channel::UnboundedReceiver<MeshEvent> {
+        self.mesh_listeners.retain(|tx| !tx.is_closed());
+        if self.mesh_listeners.is_empty() {
+            self.mesh = self.current_mesh();
+        }
+
+        let (tx, rx) = channel::unbounded();
+        for (topic, peers) in &self.mesh {
+            for peer_id in peers {
+                let _ = tx.unbounded_send(MeshEvent::Graft {
+                    topic: topic.to_string(),
+                    peer_id: *peer_id,
+                });
+            }
+        }
+        self.mesh_listeners.push(tx);
+        rx
+    }
+
+    fn current_mesh(&self) -> HashMap<TopicHash, HashSet<PeerId>> {
+        self.gossipsub
+            .topics()
+            .map(|topic| {
+                (
+                    topic.clone(),
+                    self.gossipsub.mesh_peers(topic).copied().collect(),
+                )
+            })
+            .collect()
+    }
+
+    /// Sends the changes of the meshes since the last call to the listeners, if any.
+    fn send_mesh_changes(&mut self) {
+        if self.mesh_listeners.is_empty() {
+            return;
+        }
+
+        let mesh = self.current_mesh();
+        let mut events = Vec::new();
+        for (topic, peers) in &mesh {
+            let previous = self.mesh.get(topic);
+            for peer_id in peers {
+                if !previous.is_some_and(|previous| previous.contains(peer_id)) {
+                    events.push(MeshEvent::Graft {
+                        topic: topic.to_string(),
+                        peer_id: *peer_id,
+                    });
+                }
+            }
+        }
+        for (topic, peers) in &self.mesh {
+            let current = mesh.get(topic);
+            for peer_id in peers {
+                if !current.is_some_and(|current| current.contains(peer_id)) {
+                    events.push(MeshEvent::Prune {
+                        topic: topic.to_string(),
+                        peer_id: *peer_id,
+                    });
+                }
+            }
+        }
+        self.mesh = mesh;
+
+        if events.is_empty() {
+            return;
+        }
+        self.mesh_listeners.retain(|tx| {
+            events
+                .iter()
+                .all(|event| tx.unbounded_send(event.clone()).is_ok())
+        });
+    }
 }
 
 impl NetworkBehaviour for GossipsubStream {
@@ -307,6 +463,18 @@ impl NetworkBehaviour for GossipsubStream {
     fn poll(
         &mut self,
         ctx: &mut Context,
+    ) -> Poll<ToSwarm<libp2p::gossipsub::Event, THandlerInEvent<Self>>> {
+        let poll = self.poll_gossipsub(ctx);
+        // the mesh changes on the heartbeats and the control messages, both followed by a poll
+        self.send_mesh_changes();
+        poll
+    }
+}
+
+impl GossipsubStream {
+    fn poll_gossipsub(
+        &mut self,
+        ctx: &mut Context,
     ) -> Poll<ToSwarm<libp2p::gossipsub::Event, THandlerInEvent<Self>>> {
         use futures::stream::StreamExt;
         use std::collections::hash_map::Entry;
@@ -0,0 +1,451 @@
+//! Kademlia record store kept in memory, and in the datastore of the repo with
+//! [`KadStoreConfig::Persistent`].
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::StreamExt;
+use libp2p::kad::store::{self, MemoryStore, MemoryStoreConfig, RecordStore};
+use libp2p::kad::{ProviderRecord, Record, RecordKey};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::repo::Repo;
+
+/// Prefix of the keys of the records in the datastore, followed by the key of the record.
+const RECORD_PREFIX: &str = "/local/kad/record/";
+
+/// Prefix of the keys of the provider records in the datastore, followed by the key of the
+/// record and the provider.
+const PROVIDER_PREFIX: &str = "/local/kad/provider/";
+
+/// Where the records and provider records of kademlia are stored. The limits of the
+/// [`MemoryStoreConfig`] are enforced on insert either way.
+#[derive(Clone, Debug)]
+pub enum KadStoreConfig {
+    /// In memory, the records being lost on restart
+    Memory(MemoryStoreConfig),
+    /// In memory and in the datastore of the repo, from which the records not yet expired are
+    /// loaded on start
+    Persistent(MemoryStoreConfig),
+}
+
+impl Default for KadStoreConfig {
+    fn default() -> Self {
+        KadStoreConfig::Memory(MemoryStoreConfig {
+            max_provided_keys: 50 * 1024,
+            ..Default::default()
+        })
+    }
+}
+
+impl KadStoreConfig {
+    pub(crate) fn limits_mut(&mut self) -> &mut MemoryStoreConfig {
+        match self {
+            KadStoreConfig::Memory(limits) | KadStoreConfig::Persistent(limits) => limits,
+        }
+    }
+}
+
+/// Records held by the kademlia store of the node, see
+/// [`Ipfs::dht_store`](crate::Ipfs::dht_store).
+#[derive(Debug, Clone, Default)]
+pub struct DhtStoreContents {
+    pub records: Vec<Record>,
+    pub providers: Vec<ProviderRecord>,
+}
+
+/// Change of the datastore, written in order by the task spawned in [`KadStore::new`].
+enum Write {
+    Put(Vec<u8>, Vec<u8>),
+    Remove(Vec<u8>),
+}
+
+/// Kademlia store of the node, keeping the records in a [`MemoryStore`] and writing the changes
+/// through to the datastore of the repo when persistent.
+pub struct KadStore {
+    memory: MemoryStore,
+    /// Keys with provider records, as the memory store only lists the locally provided ones
+    provider_keys: HashSet<RecordKey>,
+    persist: Option<UnboundedSender<Write>>,
+}
+
+impl KadStore {
+    pub(crate) async fn new(peer_id: PeerId, config: &KadStoreConfig, repo: &Repo) -> Self {
+        let (limits, persistent) = match config {
+            KadStoreConfig::Memory(limits) => (limits, false),
+            KadStoreConfig::Persistent(limits) => (limits, true),
+        };
+
+        let mut store = KadStore {
+            memory: MemoryStore::with_config(peer_id, limits.clone()),
+            provider_keys: HashSet::new(),
+            persist: None,
+        };
+
+        if !persistent {
+            return store;
+        }
+
+        let dropped = store.load(repo).await;
+
+        let (tx, mut rx) = unbounded();
+        let repo = repo.clone();
+        tokio::spawn(async move {
+            while let Some(write) = rx.next().await {
+                let written = match &write {
+                    Write::Put(key, value) => repo.data_store().put(key, value).await,
+                    Write::Remove(key) => repo.data_store().remove(key).await,
+                };
+                if let Err(e) = written {
+                    warn!("kad: failed to write a record to the datastore: {e}");
+                }
+            }
+        });
+
+        for key in dropped {
+            let _ = tx.unbounded_send(Write::Remove(key));
+        }
+        store.persist = Some(tx);
+        store
+    }
+
+    /// Loads the records written to the datastore, returning the keys of the ones which expired
+    /// since, could not be decoded or are over the limits.
+    async fn load(&mut self, repo: &Repo) -> Vec<Vec<u8>> {
+        let now = (Instant::now(), SystemTime::now());
+        let mut dropped = Vec::new();
+
+        let mut entries = repo.data_store().iter().await;
+        while let Some((key, value)) = entries.next().await {
+            let Ok(path) = std::str::from_utf8(&key) else {
+                continue;
+            };
+
+            let loaded = if let Some(encoded) = path.strip_prefix(RECORD_PREFIX) {
+                decode_record(encoded, &value, now)
+                    .is_some_and(|record| self.memory.put(record).is_ok())
+            } else if let Some(encoded) = path.strip_prefix(PROVIDER_PREFIX) {
+                decode_provider(encoded, &value, now).is_some_and(|record| {
+                    let key = record.key.clone();
+                    let provider = record.provider;
+                    // over the limits, the record is either rejected or left out of the providers
+                    let added = self.memory.add_provider(record).is_ok()
+                        && self
+                            .memory
+                            .providers(&key)
+                            .iter()
+                            .any(|p| p.provider == provider);
+                    if added {
+                        self.provider_keys.insert(key);
+                    }
+                    added
+                })
+            } else {
+                continue;
+            };
+
+            if !loaded {
+                dropped.push(key);
+            }
+        }
+
+        dropped
+    }
+
+    /// Removes the expired records and provider records, which the memory store only drops when
+    /// they are looked up.
+    pub(crate) fn remove_expired(&mut self, now: Instant) {
+        let expired = self
+            .memory
+            .records()
+            .filter(|record| record.is_expired(now))
+            .map(|record| record.key.clone())
+            .collect::<Vec<_>>();
+        for key in expired {
+            self.remove(&key);
+        }
+
+        let expired = self
+            .provider_keys
+            .iter()
+            .flat_map(|key| self.memory.providers(key))
+            .filter(|record| record.is_expired(now))
+            .map(|record| (record.key, record.provider))
+            .collect::<Vec<_>>();
+        for (key, provider) in expired {
+            self.remove_provider(&key, &provider);
+        }
+    }
+
+    pub(crate) fn contents(&self) -> DhtStoreContents {
+        DhtStoreContents {
+            records: self.memory.records().map(Cow::into_owned).collect(),
+            providers: self
+                .provider_keys
+                .iter()
+                .flat_map(|key| self.memory.providers(key))
+                .collect(),
+        }
+    }
+
+    fn send(&self, write: impl FnOnce() -> Write) {
+        if let Some(tx) = &self.persist {
+            let _ = tx.unbounded_send(write());
+        }
+    }
+}
+
+impl RecordStore for KadStore {
+    type RecordsIter<'a> = <MemoryStore as RecordStore>::RecordsIter<'a>;
+    type ProvidedIter<'a> = <MemoryStore as RecordStore>::ProvidedIter<'a>;
+
+    fn get(&self, k: &RecordKey) -> Option<Cow<'_, Record>> {
+        self.memory.get(k)
+    }
+
+    fn put(&mut self, r: Record) -> store::Result<()> {
+        let key = r.key.clone();
+        self.memory.put(r)?;
+        if let Some(record) = self.memory.get(&key) {
+            self.send(|| Write::Put(record_key(&key), encode_record(&record)));
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, k: &RecordKey) {
+        self.memory.remove(k);
+        self.send(|| Write::Remove(record_key(k)));
+    }
+
+    fn records(&self) -> Self::RecordsIter<'_> {
+        self.memory.records()
+    }
+
+    fn add_provider(&mut self, record: ProviderRecord) -> store::Result<()> {
+        let key = record.key.clone();
+        let provider = record.provider;
+        let before = self.memory.providers(&key);
+        self.memory.add_provider(record)?;
+        let after = self.memory.providers(&key);
+
+        // the memory store replaces the provider farthest from the key once full
+        for replaced in before
+            .iter()
+            .filter(|b| !after.iter().any(|a| a.provider == b.provider))
+        {
+            self.send(|| Write::Remove(provider_key(&key, &replaced.provider)));
+        }
+
+        if let Some(added) = after.iter().find(|a| a.provider == provider) {
+            self.send(|| Write::Put(provider_key(&key, &provider), encode_provider(added)));
+        }
+        if !after.is_empty() {
+            self.provider_keys.insert(key);
+        }
+        Ok(())
+    }
+
+    fn providers(&self, key: &RecordKey) -> Vec<ProviderRecord> {
+        self.memory.providers(key)
+    }
+
+    fn provided(&self) -> Self::ProvidedIter<'_> {
+        self.memory.provided()
+    }
+
+    fn remove_provider(&mut self, k: &RecordKey, p: &PeerId) {
+        self.memory.remove_provider(k, p);
+        if self.memory.providers(k).is_empty() {
+            self.provider_keys.remove(k);
+        }
+        self.send(|| Write::Remove(provider_key(k, p)));
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredRecord {
+    value: Vec<u8>,
+    publisher: Option<String>,
+    /// Milliseconds since the unix epoch, as the instants do not survive restarts
+    expires: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredProvider {
+    addresses: Vec<String>,
+    /// Milliseconds since the unix epoch
+    expires: Option<u64>,
+}
+
+fn record_key(key: &RecordKey) -> Vec<u8> {
+    format!("{RECORD_PREFIX}{}", URL_SAFE_NO_PAD.encode(key.as_ref())).into_bytes()
+}
+
+fn provider_key(key: &RecordKey, provider: &PeerId) -> Vec<u8> {
+    format!(
+        "{PROVIDER_PREFIX}{}/{provider}",
+        URL_SAFE_NO_PAD.encode(key.as_ref())
+    )
+    .into_bytes()
+}
+
+fn encode_record(record: &Record) -> Vec<u8> {
+    let stored = StoredRecord {
+        value: record.value.clone(),
+        publisher: record.publisher.map(|peer_id| peer_id.to_string()),
+        expires: record.expires.map(to_unix_millis),
+    };
+    serde_json::to_vec(&stored).expect("serializing to a vec does not fail")
+}
+
+fn encode_provider(record: &ProviderRecord) -> Vec<u8> {
+    let stored = StoredProvider {
+        addresses: record.addresses.iter().map(ToString::to_string).collect(),
+        expires: record.expires.map(to_unix_millis),
+    };
+    serde_json::to_vec(&stored).expect("serializing to a vec does not fail")
+}
+
+/// Decodes a record, unless it expired.
+fn decode_record(encoded: &str, value: &[u8], now: (Instant, SystemTime)) -> Option<Record> {
+    let key = RecordKey::from(URL_SAFE_NO_PAD.decode(encoded).ok()?);
+    let stored: StoredRecord = serde_json::from_slice(value).ok()?;
+    let expires = match stored.expires {
+        Some(millis) => Some(from_unix_millis(millis, now)?),
+        None => None,
+    };
+    let publisher = match stored.publisher {
+        Some(publisher) => Some(publisher.parse().ok()?),
+        None => None,
+    };
+    Some(Record {
+        key,
+        value: stored.value,
+        publisher,
+        expires,
+    })
+}
+
+/// Decodes a provider record, unless it expired.
+fn decode_provider(
+    encoded: &str,
+    value: &[u8],
+    now: (Instant, SystemTime),
+) -> Option<ProviderRecord> {
+    let (key, provider) = encoded.split_once('/')?;
+    let key = RecordKey::from(URL_SAFE_NO_PAD.decode(key).ok()?);
+    let provider = provider.parse().ok()?;
+    let stored: StoredProvider = serde_json::from_slice(value).ok()?;
+    let expires = match stored.expires {
+        Some(millis) => Some(from_unix_millis(millis, now)?),
+        None => None,
+    };
+    Some(ProviderRecord {
+        key,
+        provider,
+        expires,
+        addresses: stored
+            .addresses
+            .iter()
+            .filter_map(|addr| addr.parse().ok())
+            .collect(),
+    })
+}
+
+fn to_unix_millis(expires: Instant) -> u64 {
+    let at = SystemTime::now() + expires.saturating_duration_since(Instant::now());
+    at.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Returns `None` once expired.
+fn from_unix_millis(millis: u64, (now, system_now): (Instant, SystemTime)) -> Option<Instant> {
+    let at = UNIX_EPOCH + Duration::from_millis(millis);
+    let remaining = at.duration_since(system_now).ok()?;
+    (!remaining.is_zero()).then(|| now + remaining)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::Multiaddr;
+
+    /// Waits for the writes of the store to reach the datastore.
+    async fn persisted(repo: &Repo, count: usize) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let written = repo
+                    .data_store()
+                    .iter()
+                    .await
+                    .filter(|(key, _)| futures::future::ready(key.starts_with(b"/local/kad/")))
+                    .count()
+                    .await;
+                if written == count {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the writes to be persisted");
+    }
+
+    #[tokio::test]
+    async fn records_are_reloaded_until_expired() {
+        let repo = Repo::new_memory();
+        repo.init().await.unwrap();
+        let peer_id = PeerId::random();
+        let config = KadStoreConfig::Persistent(MemoryStoreConfig {
+            max_records: 2,
+            max_value_bytes: 16,
+            ..Default::default()
+        });
+
+        let mut store = KadStore::new(peer_id, &config, &repo).await;
+        let mut record = Record::new(b"kept".to_vec(), b"value".to_vec());
+        record.expires = Some(Instant::now() + Duration::from_secs(60));
+        store.put(record.clone()).unwrap();
+        let mut expiring = Record::new(b"expiring".to_vec(), b"value".to_vec());
+        expiring.expires = Some(Instant::now() + Duration::from_millis(200));
+        store.put(expiring).unwrap();
+
+        // the limits are enforced on insert
+        let large = Record::new(b"large".to_vec(), vec![0; 17]);
+        assert!(matches!(store.put(large), Err(store::Error::ValueTooLarge)));
+        let extra = Record::new(b"extra".to_vec(), b"value".to_vec());
+        assert!(matches!(store.put(extra), Err(store::Error::MaxRecords)));
+
+        let provider = ProviderRecord {
+            key: RecordKey::new(b"content"),
+            provider: PeerId::random(),
+            expires: Some(Instant::now() + Duration::from_secs(60)),
+            addresses: vec!["/ip4/127.0.0.1/tcp/4001".parse::<Multiaddr>().unwrap()],
+        };
+        store.add_provider(provider.clone()).unwrap();
+        persisted(&repo, 3).await;
+        drop(store);
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let mut store = KadStore::new(peer_id, &config, &repo).await;
+        let contents = store.contents();
+        assert_eq!(contents.records.len(), 1);
+        assert_eq!(contents.records[0].key, record.key);
+        assert_eq!(contents.records[0].value, record.value);
+        assert_eq!(contents.providers.len(), 1);
+        assert_eq!(contents.providers[0].provider, provider.provider);
+        assert_eq!(contents.providers[0].addresses, provider.addresses);
+        // the expired record was removed from the datastore as well
+        persisted(&repo, 2).await;
+
+        store.remove_expired(Instant::now() + Duration::from_secs(120));
+        assert!(store.contents().records.is_empty());
+        assert!(store.contents().providers.is_empty());
+        persisted(&repo, 0).await;
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::repo::Repo;
+
+/// Key of the ledgers in the datastore of the repo, when persisted.
+const LEDGERS_KEY: &[u8] = b"/local/bitswap/ledgers";
+
+/// Bytes and blocks exchanged with a peer through bitswap since the ledger was started or reset,
+/// see [`Ipfs::bitswap_ledger`](crate::Ipfs::bitswap_ledger).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerLedger {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub blocks_sent: u64,
+    pub blocks_received: u64,
+}
+
+impl PeerLedger {
+    /// Bytes sent to the peer for every byte received from it. The lower the ratio, the more the
+    /// peer served us relative to what it was served.
+    pub fn debt_ratio(&self) -> f64 {
+        self.bytes_sent as f64 / (self.bytes_received as f64 + 1.0)
+    }
+
+    pub(crate) fn record_sent(&mut self, bytes: usize) {
+        self.bytes_sent += bytes as u64;
+        self.blocks_sent += 1;
+    }
+
+    pub(crate) fn record_received(&mut self, bytes: usize) {
+        self.bytes_received += bytes as u64;
+        self.blocks_received += 1;
+    }
+}
+
+/// Order in which the responses to the wants of the peers are sent when several are ready at
+/// once. Only used by the default bitswap implementation.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ServeOrder {
+    /// In the order the responses became ready
+    #[default]
+    Fifo,
+    /// The peers with the lowest [`PeerLedger::debt_ratio`] first, rewarding the peers which
+    /// served us
+    DebtRatio,
+}
+
+/// Loads the ledgers saved by [`save_ledgers`], if any.
+pub(crate) async fn load_ledgers(repo: &Repo) -> Result<HashMap<PeerId, PeerLedger>, Error> {
+    let Some(data) = repo.data_store().get(LEDGERS_KEY).await? else {
+        return Ok(HashMap::new());
+    };
+
+    let ledgers: HashMap<String, PeerLedger> = serde_json::from_slice(&data)?;
+    Ok(ledgers
+        .into_iter()
+        .filter_map(|(peer_id, ledger)| Some((peer_id.parse().ok()?, ledger)))
+        .collect())
+}
+
+pub(crate) async fn save_ledgers(
+    repo: &Repo,
+    ledgers: &HashMap<PeerId, PeerLedger>,
+) -> Result<(), Error> {
+    let ledgers = ledgers
+        .iter()
+        .map(|(peer_id, ledger)| (peer_id.to_string(), ledger))
+        .collect::<HashMap<_, _>>();
+    let data = serde_json::to_vec(&ledgers)?;
+    repo.data_store().put(LEDGERS_KEY, &data).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use libp2p::PeerId;
+
+    use super::{load_ledgers, save_ledgers, PeerLedger};
+    use crate::repo::Repo;
+
+    #[tokio::test]
+    async fn ledgers_roundtrip_through_the_datastore() {
+        let repo = Repo::new_memory();
+        repo.init().await.unwrap();
+        assert!(load_ledgers(&repo).await.unwrap().is_empty());
+
+        let mut ledger = PeerLedger::default();
+        ledger.record_sent(100);
+        ledger.record_received(300);
+        ledger.record_received(50);
+        let ledgers = HashMap::from([(PeerId::random(), ledger)]);
+
+        save_ledgers(&repo, &ledgers).await.unwrap();
+        assert_eq!(load_ledgers(&repo).await.unwrap(), ledgers);
+        assert_eq!(ledger.blocks_received, 2);
+        assert!(ledger.debt_ratio() < 1.0);
+    }
+}
@@ -1,41 +1,66 @@
 //! P2P handling for IPFS nodes.
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::num::{NonZeroU8, NonZeroUsize};
+use std::time::Duration;
 
 use crate::error::Error;
 use crate::repo::Repo;
 use crate::{IpfsOptions, TTransportFn};
 
+use libp2p::core::transport::timeout::TransportTimeout;
 use libp2p::gossipsub::ValidationMode;
 use libp2p::identify::Info as IdentifyInfo;
 use libp2p::identity::{Keypair, PublicKey};
 use libp2p::swarm::NetworkBehaviour;
 use libp2p::{Multiaddr, PeerId};
-use libp2p::{StreamProtocol, Swarm};
+use libp2p::{StreamProtocol, Swarm, Transport};
 use tracing::Span;
 
+use self::bandwidth::BandwidthCounters;
+
 pub(crate) mod addr;
+pub(crate) mod addr_filter;
 pub(crate) mod addressbook;
+pub(crate) mod announce;
+pub(crate) mod bandwidth;
 #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
 pub mod bitswap;
+pub(crate) mod dht_limit;
+pub(crate) mod gate;
+pub(crate) mod kad_store;
+pub(crate) mod ledger;
+pub(crate) mod observed;
 pub(crate) mod peerbook;
+pub(crate) mod pinger;
 pub mod protocol;
+pub(crate) mod ranking;
 
 mod behaviour;
+pub use self::addr_filter::AddressFilter;
 pub use self::addressbook::Config as AddressBookConfig;
+pub use self::announce::ContentAnnouncerConfig;
+pub use self::bandwidth::BandwidthStats;
 pub use self::behaviour::BehaviourEvent;
 pub use self::behaviour::IdentifyConfiguration;
+pub use self::dht_limit::{DhtStats, DhtWriteLimit};
+pub use self::gate::{ConnectionGate, GateDenied, GateHandle, GateStats};
+pub use self::kad_store::{DhtStoreContents, KadStoreConfig};
+pub use self::ledger::{PeerLedger, ServeOrder};
+pub use self::observed::ObservedAddrConfig;
+pub use self::peerbook::{PeerMetaConfig, PeerMetaError, PeerProtectionStatus};
+pub use self::ranking::ProviderRanking;
 
 #[cfg(feature = "beetle_bitswap")]
 pub use self::behaviour::{BitswapConfig, BitswapProtocol};
 
-pub use self::behaviour::{KadConfig, KadInserts, KadStoreConfig};
+pub use self::behaviour::{KadConfig, KadInserts};
 pub use self::behaviour::{RateLimit, RelayConfig};
 pub use self::transport::{DnsResolver, TransportConfig, UpgradeVersion};
 pub(crate) mod gossipsub;
-mod transport;
+pub(crate) mod transport;
 
-pub use addr::MultiaddrExt;
+pub use addr::{InterfaceFilter, MultiaddrExt};
 pub use behaviour::KadResult;
 
 /// Type alias for [`libp2p::Swarm`] running the [`behaviour::Behaviour`] with the given [`IpfsTypes`].
@@ -66,6 +91,114 @@ pub struct PeerInfo {
 
     /// Address observed by or for the remote.
     pub observed_addr: Option<Multiaddr>,
+
+    /// Round-trip time of the latest ping, known for connected peers only.
+    pub rtt: Option<Duration>,
+
+    /// Addresses of the established connections with the peer.
+    pub connection_addrs: Vec<Multiaddr>,
+
+    /// Metadata attached to the peer, see [`Ipfs::peer_set_meta`](crate::Ipfs::peer_set_meta).
+    pub meta: BTreeMap<String, Vec<u8>>,
+
+    /// Standing of the peer, lowered when it misbehaves, e.g. flooding the DHT with writes over
+    /// the [`DhtWriteLimit`].
+    pub score: i64,
+}
+
+/// The reason dialing failed, see [`Ipfs::connect`](crate::Ipfs::connect) and
+/// [`Ipfs::connect_addr`](crate::Ipfs::connect_addr).
+#[derive(Debug, thiserror::Error)]
+pub enum DialError {
+    #[error("node is offline")]
+    Offline,
+    #[error("the address belongs to the local peer")]
+    LocalPeerId,
+    #[error("no address to dial")]
+    NoAddresses,
+    /// Only returned when
+    /// [`IpfsOptions::fail_if_connected`](crate::IpfsOptions::fail_if_connected) is set.
+    #[error("already connected to the peer")]
+    AlreadyConnected,
+    #[error("already dialing the peer")]
+    AlreadyDialing,
+    #[error("dialing was aborted")]
+    Aborted,
+    /// The peer at the address is not the one given in its `/p2p/` component.
+    #[error("expected peer {expected} at {address} but connected to {obtained}")]
+    WrongPeerId {
+        expected: PeerId,
+        obtained: PeerId,
+        address: Multiaddr,
+    },
+    #[error("connection was denied: {0}")]
+    Denied(String),
+    #[error("no transport supports {0}")]
+    UnsupportedAddress(Multiaddr),
+    /// Either the transport timed out on the address, or the dial did not complete within
+    /// [`IpfsOptions::dial_timeout`](crate::IpfsOptions::dial_timeout).
+    #[error("dialing{} timed out", .0.as_ref().map(|addr| format!(" {addr}")).unwrap_or_default())]
+    Timeout(Option<Multiaddr>),
+    #[error("connection to {0} was refused")]
+    Refused(Multiaddr),
+    #[error("dialing {0} failed: {1}")]
+    Transport(Multiaddr, std::io::Error),
+}
+
+impl DialError {
+    pub(crate) fn new(error: libp2p::swarm::DialError, expected: Option<PeerId>) -> Self {
+        use libp2p::swarm::DialError as SwarmDialError;
+        use libp2p::TransportError;
+
+        match error {
+            SwarmDialError::LocalPeerId { .. } => DialError::LocalPeerId,
+            SwarmDialError::NoAddresses => DialError::NoAddresses,
+            SwarmDialError::DialPeerConditionFalse(_) => DialError::AlreadyDialing,
+            SwarmDialError::Aborted => DialError::Aborted,
+            SwarmDialError::WrongPeerId { obtained, endpoint } => match expected {
+                Some(expected) => DialError::WrongPeerId {
+                    expected,
+                    obtained,
+                    address: endpoint.get_remote_address().clone(),
+                },
+                // only raised when a peer was expected
+                None => DialError::Aborted,
+            },
+            SwarmDialError::Denied { cause } => DialError::Denied(cause.to_string()),
+            SwarmDialError::Transport(errors) => match errors.into_iter().next() {
+                Some((_, TransportError::MultiaddrNotSupported(addr))) => {
+                    DialError::UnsupportedAddress(addr)
+                }
+                Some((addr, TransportError::Other(e)))
+                    if has_io_kind(&e, std::io::ErrorKind::TimedOut) =>
+                {
+                    DialError::Timeout(Some(addr))
+                }
+                Some((addr, TransportError::Other(e)))
+                    if has_io_kind(&e, std::io::ErrorKind::ConnectionRefused) =>
+                {
+                    DialError::Refused(addr)
+                }
+                Some((addr, TransportError::Other(e))) => DialError::Transport(addr, e),
+                None => DialError::NoAddresses,
+            },
+        }
+    }
+}
+
+/// Whether the error, or one it wraps, is an io error of the kind. The transports wrap the errors
+/// of those they are built upon, ending up as an io error of an `Other` kind.
+fn has_io_kind(error: &(dyn std::error::Error + 'static), kind: std::io::ErrorKind) -> bool {
+    let mut next = Some(error);
+    while let Some(error) = next {
+        next = match error.downcast_ref::<std::io::Error>() {
+            Some(e) if e.kind() == kind => return true,
+            // the source of an io error skips the error it wraps
+            Some(e) => e.get_ref().map(|e| e as &(dyn std::error::Error + 'static)),
+            None => error.source(),
+        };
+    }
+    false
 }
 
 impl core::hash::Hash for PeerInfo {
@@ -101,10 +234,48 @@ impl From<IdentifyInfo> for PeerInfo {
             listen_addrs,
             protocols,
             observed_addr,
+            rtt: None,
+            connection_addrs: vec![],
+            meta: BTreeMap::new(),
+            score: 0,
         }
     }
 }
 
+impl From<&IdentifyInfo> for PeerInfo {
+    fn from(info: &IdentifyInfo) -> Self {
+        Self {
+            peer_id: info.public_key.to_peer_id(),
+            public_key: info.public_key.clone(),
+            protocol_version: info.protocol_version.clone(),
+            agent_version: info.agent_version.clone(),
+            listen_addrs: info.listen_addrs.clone(),
+            protocols: info.protocols.clone(),
+            observed_addr: Some(info.observed_addr.clone()),
+            rtt: None,
+            connection_addrs: vec![],
+            meta: BTreeMap::new(),
+            score: 0,
+        }
+    }
+}
+
+/// What is banned, see [`Ipfs::list_bans`](crate::Ipfs::list_bans).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BanTarget {
+    Peer(PeerId),
+    /// Connections on addresses starting with the address are refused
+    Address(Multiaddr),
+}
+
+/// An active ban.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ban {
+    pub target: BanTarget,
+    /// Time until the ban expires, `None` if permanent
+    pub remaining: Option<Duration>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PubsubConfig {
     /// Custom protocol name
@@ -163,6 +334,9 @@ pub struct SwarmConfig {
     pub notify_handler_buffer_size: NonZeroUsize,
     pub connection_event_buffer_size: usize,
     pub max_inbound_stream: usize,
+    /// Timeout of each outbound connection attempt, including the upgrades. Dials are only
+    /// bounded by the transport timeout if `None`.
+    pub dial_timeout: Option<Duration>,
 }
 
 impl Default for SwarmConfig {
@@ -172,10 +346,180 @@ impl Default for SwarmConfig {
             notify_handler_buffer_size: 32.try_into().expect("256 > 0"),
             connection_event_buffer_size: 7,
             max_inbound_stream: 10_000,
+            dial_timeout: None,
+        }
+    }
+}
+
+/// Configuration of the relay v2 client, with dcutr to upgrade relayed connections to direct ones.
+///
+/// With `auto`, a reservation is requested once AutoNAT reports the node as private, from the
+/// `static_relays` first, then from the identified peers supporting the relay hop protocol. The
+/// `/p2p-circuit` addresses of the reservation are added to the external addresses, advertised by
+/// identify. Another relay is picked when the reservation fails or is closed.
+#[derive(Debug, Clone, Default)]
+pub struct RelayClientConfig {
+    pub enabled: bool,
+    /// Addresses of the relays, including their peer id
+    pub static_relays: Vec<Multiaddr>,
+    pub auto: bool,
+}
+
+/// Configuration of the rendezvous client, used to discover peers without the DHT.
+///
+/// Once connected to one of the `servers`, the node registers its external addresses under the
+/// `namespaces`, renewing the registrations before they expire, and discovers the peers
+/// registered under them every `discovery_interval`. Discovered peers are added to the
+/// addressbook and dialed with `dial_discovered`.
+#[derive(Debug, Clone)]
+pub struct RendezvousConfig {
+    /// Addresses of the rendezvous servers, including their peer id
+    pub servers: Vec<Multiaddr>,
+    pub namespaces: Vec<String>,
+    /// Requested ttl of the registrations in seconds, the server default if `None`
+    pub register_ttl: Option<u64>,
+    /// Discovery only happens on connecting to the servers if zero
+    pub discovery_interval: Duration,
+    pub dial_discovered: bool,
+}
+
+impl Default for RendezvousConfig {
+    fn default() -> Self {
+        Self {
+            servers: vec![],
+            namespaces: vec![],
+            register_ttl: None,
+            discovery_interval: Duration::from_secs(5 * 60),
+            dial_discovered: false,
         }
     }
 }
 
+/// When the node bootstraps the DHT by itself, besides [`Ipfs::bootstrap`](crate::Ipfs::bootstrap).
+///
+/// A bootstrap is skipped while another one is in progress. The configured bootstrap nodes are
+/// added to the routing table before each of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BootstrapPolicy {
+    /// Bootstrap once the first listening address is up
+    pub on_start: bool,
+    /// Bootstrap again every `interval`
+    pub interval: Option<Duration>,
+    /// Bootstrap when fewer peers than this are connected, checked every minute
+    pub min_peers_trigger: Option<usize>,
+}
+
+/// Outcome of a bootstrap, see [`Ipfs::bootstrap_report`](crate::Ipfs::bootstrap_report).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootstrapReport {
+    /// Bootstrap nodes connected to during the bootstrap, or already before it
+    pub contacted: Vec<PeerId>,
+    /// Addresses of the bootstrap nodes which were never connected to
+    pub failed: Vec<Multiaddr>,
+    pub duration: Duration,
+}
+
+/// A reservation on a relay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayReservation {
+    pub relay: PeerId,
+    /// Our addresses through the relay
+    pub addrs: Vec<Multiaddr>,
+}
+
+/// The relay reservations of the node, see [`Ipfs::relay_status`](crate::Ipfs::relay_status).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RelayStatus {
+    /// Whether AutoNAT reported the node as private
+    pub private: bool,
+    pub reservations: Vec<RelayReservation>,
+    /// Relay picked automatically whose reservation is pending
+    pub pending: Option<PeerId>,
+}
+
+/// Reachability of the node reported by AutoNAT, see [`Ipfs::nat_status`](crate::Ipfs::nat_status).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutonatStatus {
+    pub status: libp2p::autonat::NatStatus,
+    /// Number of probes which confirmed the status in a row
+    pub confidence: usize,
+    /// Address the node was reached on when public
+    pub public_address: Option<Multiaddr>,
+}
+
+/// A listener of the node, see [`Ipfs::listeners`](crate::Ipfs::listeners).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenerInfo {
+    /// Address the listener was requested with, possibly with an unspecified ip or port
+    pub requested: Multiaddr,
+    /// Addresses confirmed by the transport, one per interface for an unspecified ip
+    pub confirmed: Vec<Multiaddr>,
+}
+
+/// Where an external address of the node was learned from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExternalAddressSource {
+    /// Added with [`Ipfs::add_external_address`](crate::Ipfs::add_external_address)
+    Manual,
+    /// A listened address, either global or added with `listen_as_external_addr`
+    Listener,
+    /// Confirmed by the AutoNAT probes
+    Autonat,
+    /// The `/p2p-circuit` address of a relay reservation
+    Relay,
+    /// Mapped on the gateway with UPnP
+    Upnp,
+    /// Observed by enough identified peers, see [`ObservedAddrConfig`]
+    Observed,
+}
+
+/// An address advertised to the peers, see
+/// [`Ipfs::external_address_info`](crate::Ipfs::external_address_info).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalAddressInfo {
+    pub address: Multiaddr,
+    pub source: ExternalAddressSource,
+}
+
+/// Activity of the relay server, see [`Ipfs::relay_server_stats`](crate::Ipfs::relay_server_stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RelayServerStats {
+    pub active_reservations: usize,
+    pub active_circuits: usize,
+    /// Bytes relayed in both directions of the circuits, including the protocol overhead
+    pub bytes_relayed: u64,
+    pub denied_reservations: u64,
+    pub denied_circuits: u64,
+}
+
+/// Limits on the connections of the node. Connections beyond the `max_*` limits are denied, while
+/// the peers connected beyond `high_water` are pruned down to `low_water` every minute.
+///
+/// Pruning skips peers with an active bitswap exchange, whitelisted peers (see
+/// [`Ipfs::whitelist_peer`](crate::Ipfs::whitelist_peer)) and bootstrap nodes, disconnecting the
+/// peers with the highest latency first.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectionLimits {
+    pub max_established: Option<u32>,
+    pub max_established_per_peer: Option<u32>,
+    pub max_pending_incoming: Option<u32>,
+    pub max_pending_outgoing: Option<u32>,
+    /// Number of connected peers above which peers are pruned.
+    pub high_water: Option<usize>,
+    /// Number of connected peers kept when pruning, defaults to `high_water`.
+    pub low_water: Option<usize>,
+}
+
+impl From<ConnectionLimits> for libp2p_connection_limits::ConnectionLimits {
+    fn from(limits: ConnectionLimits) -> Self {
+        libp2p_connection_limits::ConnectionLimits::default()
+            .with_max_established(limits.max_established)
+            .with_max_established_per_peer(limits.max_established_per_peer)
+            .with_max_pending_incoming(limits.max_pending_incoming)
+            .with_max_pending_outgoing(limits.max_pending_outgoing)
+    }
+}
+
 #[allow(clippy::type_complexity)]
 #[allow(deprecated)]
 //TODO: use libp2p::SwarmBuilder
@@ -185,6 +529,8 @@ pub async fn create_swarm<C>(
     options: &IpfsOptions,
     repo: &Repo,
     span: Span,
+    gate: GateHandle,
+    bandwidth: &BandwidthCounters,
     (custom, custom_transport): (Option<C>, Option<TTransportFn>),
 ) -> Result<TSwarm<C>, Error>
 where
@@ -200,7 +546,7 @@ where
     let idle = options.connection_idle;
 
     let (behaviour, relay_transport) =
-        behaviour::Behaviour::new(&keypair, options, repo, custom).await?;
+        behaviour::Behaviour::new(&keypair, options, repo, gate.clone(), custom).await?;
 
     // Set up an encrypted TCP transport over the Yamux. If relay transport is supplied, that will be apart
     let transport = match custom_transport {
@@ -208,6 +554,15 @@ where
         None => transport::build_transport(keypair, relay_transport, transport_config)?,
     };
 
+    let transport = match swarm_config.dial_timeout {
+        Some(timeout) => TransportTimeout::with_outgoing_timeout(transport, timeout).boxed(),
+        None => transport,
+    };
+
+    let transport = gate.wrap(transport);
+
+    let transport = bandwidth.wrap(transport);
+
     let swarm = libp2p::Swarm::new(
         transport,
         behaviour,
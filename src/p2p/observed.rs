@@ -0,0 +1,216 @@
+//! Confidence in the addresses the peers observe us at, see [`ObservedAddrConfig`].
+
+use std::collections::{HashMap, HashSet};
+
+use libp2p::core::address_translation;
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+
+use super::addr_filter::AddressFilter;
+
+/// Promotion of the addresses observed by the identified peers to external addresses.
+///
+/// The observed address is translated to the port of the listening address of the same
+/// transport, as the peers observe the ephemeral port of the connections we dialed. It is only
+/// advertised once observed by `min_peers` distinct peers, and stops being advertised once the
+/// AutoNAT servers failed to dial us back `max_failures` times in a row, after which it has to be
+/// observed by as many peers again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObservedAddrConfig {
+    /// Number of distinct peers which must observe the address, or 0 not to promote any
+    pub min_peers: usize,
+    /// Number of consecutive failed dial backs after which the address is no longer advertised
+    pub max_failures: u32,
+}
+
+impl Default for ObservedAddrConfig {
+    fn default() -> Self {
+        Self {
+            min_peers: 3,
+            max_failures: 3,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ObservedAddrs {
+    config: ObservedAddrConfig,
+    /// Translated address last observed by each peer
+    by_peer: HashMap<PeerId, Multiaddr>,
+    observers: HashMap<Multiaddr, HashSet<PeerId>>,
+    /// Promoted addresses, with the failed dial backs since the last successful one
+    promoted: HashMap<Multiaddr, u32>,
+}
+
+impl ObservedAddrs {
+    pub(crate) fn new(config: ObservedAddrConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Records the address observed by the peer, returning it translated once observed by enough
+    /// peers to be promoted.
+    pub(crate) fn observe<'a>(
+        &mut self,
+        peer_id: PeerId,
+        observed: &Multiaddr,
+        listen_addrs: impl IntoIterator<Item = &'a Multiaddr>,
+    ) -> Option<Multiaddr> {
+        if self.config.min_peers == 0 || !AddressFilter::PublicOnly.allows(observed) {
+            // the private addresses are the listening ones, already advertised
+            return None;
+        }
+
+        let addr = translate(observed, listen_addrs);
+        if let Some(previous) = self.by_peer.insert(peer_id, addr.clone()) {
+            if previous == addr {
+                return None;
+            }
+            self.forget(&previous, &peer_id);
+        }
+
+        let observers = self.observers.entry(addr.clone()).or_default();
+        observers.insert(peer_id);
+        if observers.len() < self.config.min_peers || self.promoted.contains_key(&addr) {
+            return None;
+        }
+
+        self.promoted.insert(addr.clone(), 0);
+        Some(addr)
+    }
+
+    /// Forgets the address observed by the disconnected peer. The promoted addresses stay so
+    /// until the dial backs fail.
+    pub(crate) fn remove_peer(&mut self, peer_id: &PeerId) {
+        if let Some(addr) = self.by_peer.remove(peer_id) {
+            self.forget(&addr, peer_id);
+        }
+    }
+
+    /// Resets the failures of the address an AutoNAT server dialed us back at.
+    pub(crate) fn reachable(&mut self, addr: &Multiaddr) {
+        if let Some(failures) = self.promoted.get_mut(addr) {
+            *failures = 0;
+        }
+    }
+
+    /// Counts a failed dial back against the promoted addresses, returning the ones demoted.
+    pub(crate) fn unreachable(&mut self) -> Vec<Multiaddr> {
+        let max_failures = self.config.max_failures;
+        let mut demoted = Vec::new();
+        self.promoted.retain(|addr, failures| {
+            *failures += 1;
+            if *failures < max_failures {
+                return true;
+            }
+            demoted.push(addr.clone());
+            false
+        });
+
+        for addr in &demoted {
+            // observed by as many peers again before being promoted again
+            if let Some(observers) = self.observers.remove(addr) {
+                for peer_id in observers {
+                    self.by_peer.remove(&peer_id);
+                }
+            }
+        }
+        demoted
+    }
+
+    fn forget(&mut self, addr: &Multiaddr, peer_id: &PeerId) {
+        if let Some(observers) = self.observers.get_mut(addr) {
+            observers.remove(peer_id);
+            if observers.is_empty() {
+                self.observers.remove(addr);
+            }
+        }
+    }
+}
+
+/// Replaces the port of the observed address with the one of the listening address of the same
+/// transport, if any.
+fn translate<'a>(
+    observed: &Multiaddr,
+    listen_addrs: impl IntoIterator<Item = &'a Multiaddr>,
+) -> Multiaddr {
+    listen_addrs
+        .into_iter()
+        .filter(|listen| same_transport(listen, observed))
+        .find_map(|listen| address_translation(listen, observed))
+        .unwrap_or_else(|| observed.clone())
+}
+
+/// Whether the addresses have the same protocols after the ip, ignoring the ports.
+fn same_transport(a: &Multiaddr, b: &Multiaddr) -> bool {
+    let shape = |addr: &Multiaddr| {
+        addr.iter()
+            .skip(1)
+            .take_while(|protocol| !matches!(protocol, Protocol::P2p(_)))
+            .map(|protocol| match protocol {
+                Protocol::Tcp(_) => Protocol::Tcp(0),
+                Protocol::Udp(_) => Protocol::Udp(0),
+                protocol => protocol,
+            })
+            .collect::<Vec<_>>()
+    };
+    shape(a) == shape(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> Multiaddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn observed_addresses_are_translated_and_promoted() {
+        let mut observed = ObservedAddrs::new(ObservedAddrConfig {
+            min_peers: 2,
+            max_failures: 2,
+        });
+        let listen = [
+            addr("/ip4/10.0.0.2/tcp/4001"),
+            addr("/ip4/10.0.0.2/udp/4002/quic-v1"),
+        ];
+        let (a, b, c) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let external = addr("/ip4/198.51.100.1/tcp/4001");
+
+        // the peers see the ephemeral ports of the dialed connections
+        let first = addr("/ip4/198.51.100.1/tcp/50001");
+        assert_eq!(observed.observe(a, &first, &listen), None);
+        // observed again by the same peer
+        assert_eq!(observed.observe(a, &first, &listen), None);
+        // the private addresses are not promoted
+        assert_eq!(
+            observed.observe(b, &addr("/ip4/192.168.1.2/tcp/50002"), &listen),
+            None
+        );
+        let second = addr("/ip4/198.51.100.1/tcp/50003");
+        assert_eq!(
+            observed.observe(c, &second, &listen),
+            Some(external.clone())
+        );
+        // already promoted
+        assert_eq!(observed.observe(b, &second, &listen), None);
+
+        let quic = addr("/ip4/198.51.100.1/udp/4002/quic-v1");
+        assert_eq!(translate(&quic, &listen), quic);
+
+        // demoted once the dial backs failed in a row
+        assert!(observed.unreachable().is_empty());
+        observed.reachable(&external);
+        assert!(observed.unreachable().is_empty());
+        assert_eq!(observed.unreachable(), vec![external.clone()]);
+
+        // observed by enough peers again
+        assert_eq!(observed.observe(a, &first, &listen), None);
+        observed.remove_peer(&a);
+        assert_eq!(observed.observe(b, &first, &listen), None);
+        assert_eq!(observed.observe(c, &second, &listen), Some(external));
+    }
+}
@@ -1,16 +1,97 @@
-use core::task::{Context, Poll};
+use core::task::{Context, Poll, Waker};
+use futures::FutureExt;
+use futures_timer::Delay;
 use libp2p::core::{Endpoint, Multiaddr};
 use libp2p::identify::Info;
 use libp2p::swarm::derive_prelude::ConnectionEstablished;
-use libp2p::swarm::{self, dummy::ConnectionHandler as DummyConnectionHandler, NetworkBehaviour};
+use libp2p::swarm::dial_opts::{DialOpts, PeerCondition};
+use libp2p::swarm::{self, NetworkBehaviour, NotifyHandler};
 use libp2p::swarm::{
-    ConnectionClosed, ConnectionDenied, ConnectionId, FromSwarm, THandler, THandlerInEvent, ToSwarm,
+    ConnectionClosed, ConnectionDenied, ConnectionId, DialFailure, FromSwarm, THandler,
+    THandlerInEvent, ToSwarm,
 };
 use libp2p::PeerId;
 use std::collections::hash_map::Entry;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+mod handler;
+
+/// Delay before the first attempt to reconnect to a protected peer, doubled on every failure.
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// The protection of a peer, see [`Ipfs::protect_peer`](crate::Ipfs::protect_peer).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerProtectionStatus {
+    pub peer_id: PeerId,
+    /// The tags protecting the peer with their reference count
+    pub tags: Vec<(String, usize)>,
+    pub connected: bool,
+    /// Failed attempts to reconnect since the peer disconnected
+    pub reconnect_attempts: u32,
+    /// Time until the next attempt to reconnect, if disconnected
+    pub next_attempt: Option<Duration>,
+}
+
+/// Bounds of the metadata attached to peers, see [`Ipfs::peer_set_meta`](crate::Ipfs::peer_set_meta).
+#[derive(Debug, Clone, Copy)]
+pub struct PeerMetaConfig {
+    /// Largest size of a value
+    pub max_value_size: usize,
+    /// Largest size of the keys and values of a peer together
+    pub max_peer_size: usize,
+    /// Save the metadata along with the addressbook, when it is persisted.
+    pub persist: bool,
+}
+
+impl Default for PeerMetaConfig {
+    fn default() -> Self {
+        Self {
+            max_value_size: 4 * 1024,
+            max_peer_size: 64 * 1024,
+            persist: false,
+        }
+    }
+}
+
+/// Why metadata could not be attached to a peer.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PeerMetaError {
+    #[error("value of {key} is {size} bytes, over the limit of {max} bytes")]
+    ValueTooLarge {
+        key: String,
+        size: usize,
+        max: usize,
+    },
+    #[error("metadata of {peer_id} would be {size} bytes, over the limit of {max} bytes")]
+    PeerFull {
+        peer_id: PeerId,
+        size: usize,
+        max: usize,
+    },
+}
+
+#[derive(Debug)]
+struct Reconnect {
+    attempts: u32,
+    at: Instant,
+    delay: Delay,
+}
+
+impl Reconnect {
+    fn new(attempts: u32) -> Self {
+        let backoff = RECONNECT_BACKOFF
+            .saturating_mul(2u32.saturating_pow(attempts))
+            .min(MAX_RECONNECT_BACKOFF);
+        Self {
+            attempts,
+            at: Instant::now() + backoff,
+            delay: Delay::new(backoff),
+        }
+    }
+}
 
 #[derive(Default, Debug)]
 pub struct Behaviour {
@@ -18,12 +99,63 @@ pub struct Behaviour {
     peer_info: HashMap<PeerId, Info>,
     peer_rtt: HashMap<PeerId, [Duration; 3]>,
     peer_connections: HashMap<PeerId, Vec<(ConnectionId, Multiaddr)>>,
+    whitelist: HashSet<PeerId>,
+    observed_addrs: HashMap<Multiaddr, usize>,
+    protected: HashMap<PeerId, HashMap<String, usize>>,
+    reconnect: HashMap<PeerId, Reconnect>,
+    // reconnect attempts dialing, with the number of failed attempts before
+    reconnecting: HashMap<PeerId, u32>,
+    meta: HashMap<PeerId, BTreeMap<String, Vec<u8>>>,
+    meta_config: PeerMetaConfig,
+    // kept once disconnected, for the misbehaving peers not to start over by reconnecting
+    scores: HashMap<PeerId, i64>,
+    waker: Option<Waker>,
 }
 
 impl Behaviour {
-    pub fn inject_peer_info(&mut self, info: Info) {
+    pub fn with_meta_config(meta_config: PeerMetaConfig) -> Self {
+        Self {
+            meta_config,
+            ..Default::default()
+        }
+    }
+
+    /// Records the info the peer identified with, which is received again when the peer pushes
+    /// an update. Returns the info it replaces.
+    pub fn inject_peer_info(&mut self, info: Info) -> Option<Info> {
         let peer_id = info.public_key.to_peer_id();
-        self.peer_info.insert(peer_id, info);
+        *self
+            .observed_addrs
+            .entry(info.observed_addr.clone())
+            .or_default() += 1;
+        let old = self.peer_info.insert(peer_id, info)?;
+        self.forget_observed_addr(&old.observed_addr);
+        Some(old)
+    }
+
+    fn forget_observed_addr(&mut self, addr: &Multiaddr) {
+        if let Entry::Occupied(mut entry) = self.observed_addrs.entry(addr.clone()) {
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        }
+    }
+
+    /// Returns our addresses as observed by the identified peers, along with the number of peers
+    /// having reported each, most reported first.
+    pub fn observed_addrs(&self) -> Vec<(Multiaddr, usize)> {
+        let mut addrs = self
+            .observed_addrs
+            .iter()
+            .map(|(addr, count)| (addr.clone(), *count))
+            .collect::<Vec<_>>();
+        addrs.sort_by(|(_, a), (_, b)| b.cmp(a));
+        addrs
+    }
+
+    pub fn peers_info(&self) -> impl Iterator<Item = &Info> {
+        self.peer_info.values()
     }
 
     pub fn peers(&self) -> impl Iterator<Item = &PeerId> {
@@ -64,7 +196,191 @@ impl Behaviour {
     }
 
     pub fn remove_peer_info(&mut self, peer_id: PeerId) {
-        self.peer_info.remove(&peer_id);
+        if let Some(info) = self.peer_info.remove(&peer_id) {
+            self.forget_observed_addr(&info.observed_addr);
+        }
+    }
+
+    /// Lowers the score of the peer, see [`PeerInfo::score`](crate::p2p::PeerInfo::score).
+    pub fn penalize_peer(&mut self, peer_id: PeerId, penalty: i64) {
+        let score = self.scores.entry(peer_id).or_default();
+        *score = score.saturating_sub(penalty);
+    }
+
+    pub fn peer_score(&self, peer_id: &PeerId) -> i64 {
+        self.scores.get(peer_id).copied().unwrap_or_default()
+    }
+
+    /// Sets the metadata of the peer under the key, returning the value it replaced. The metadata
+    /// is kept when the peer disconnects.
+    pub fn set_peer_meta(
+        &mut self,
+        peer_id: PeerId,
+        key: String,
+        value: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, PeerMetaError> {
+        let config = self.meta_config;
+        if value.len() > config.max_value_size {
+            return Err(PeerMetaError::ValueTooLarge {
+                key,
+                size: value.len(),
+                max: config.max_value_size,
+            });
+        }
+
+        let meta = self.meta.entry(peer_id).or_default();
+        let size = meta
+            .iter()
+            .filter(|(existing, _)| **existing != key)
+            .map(|(key, value)| key.len() + value.len())
+            .sum::<usize>()
+            + key.len()
+            + value.len();
+
+        if size > config.max_peer_size {
+            if meta.is_empty() {
+                self.meta.remove(&peer_id);
+            }
+            return Err(PeerMetaError::PeerFull {
+                peer_id,
+                size,
+                max: config.max_peer_size,
+            });
+        }
+
+        Ok(meta.insert(key, value))
+    }
+
+    pub fn peer_meta(&self, peer_id: &PeerId, key: &str) -> Option<&[u8]> {
+        self.meta.get(peer_id)?.get(key).map(Vec::as_slice)
+    }
+
+    /// Removes the metadata of the peer under the key, returning its value.
+    pub fn remove_peer_meta(&mut self, peer_id: &PeerId, key: &str) -> Option<Vec<u8>> {
+        let Entry::Occupied(mut entry) = self.meta.entry(*peer_id) else {
+            return None;
+        };
+
+        let value = entry.get_mut().remove(key);
+        if entry.get().is_empty() {
+            entry.remove();
+        }
+        value
+    }
+
+    pub fn all_peer_meta(&self, peer_id: &PeerId) -> Option<&BTreeMap<String, Vec<u8>>> {
+        self.meta.get(peer_id)
+    }
+
+    /// Peers with metadata, if it is to be persisted.
+    pub(crate) fn persisted_meta(
+        &self,
+    ) -> impl Iterator<Item = (&PeerId, &BTreeMap<String, Vec<u8>>)> {
+        self.meta.iter().filter(move |_| self.meta_config.persist)
+    }
+
+    pub fn whitelist_peer(&mut self, peer_id: PeerId) -> bool {
+        self.whitelist.insert(peer_id)
+    }
+
+    pub fn remove_whitelisted_peer(&mut self, peer_id: &PeerId) -> bool {
+        self.whitelist.remove(peer_id)
+    }
+
+    pub fn is_whitelisted(&self, peer_id: &PeerId) -> bool {
+        self.whitelist.contains(peer_id)
+    }
+
+    /// Protects the peer with the tag, returning false if it was already protected by it.
+    pub fn protect_peer(&mut self, peer_id: PeerId, tag: &str) -> bool {
+        let tags = self.protected.entry(peer_id).or_default();
+        let newly_protected = tags.is_empty();
+        let count = tags.entry(tag.to_string()).or_default();
+        *count += 1;
+        let first = *count == 1;
+
+        if newly_protected {
+            self.notify_keep_alive(peer_id, true);
+        }
+        first
+    }
+
+    /// Removes one reference of the tag from the peer, returning false if the peer was not
+    /// protected by it. The peer is no longer protected once no tag is left.
+    pub fn unprotect_peer(&mut self, peer_id: PeerId, tag: &str) -> bool {
+        let Entry::Occupied(mut entry) = self.protected.entry(peer_id) else {
+            return false;
+        };
+
+        let tags = entry.get_mut();
+        let Some(count) = tags.get_mut(tag) else {
+            return false;
+        };
+
+        *count -= 1;
+        if *count == 0 {
+            tags.remove(tag);
+        }
+
+        if tags.is_empty() {
+            entry.remove();
+            self.reconnect.remove(&peer_id);
+            self.reconnecting.remove(&peer_id);
+            self.notify_keep_alive(peer_id, false);
+        }
+        true
+    }
+
+    pub fn is_protected(&self, peer_id: &PeerId) -> bool {
+        self.protected.contains_key(peer_id)
+    }
+
+    pub fn protection_status(&self) -> Vec<PeerProtectionStatus> {
+        let now = Instant::now();
+        self.protected
+            .iter()
+            .map(|(peer_id, tags)| {
+                let reconnect = self.reconnect.get(peer_id);
+                PeerProtectionStatus {
+                    peer_id: *peer_id,
+                    tags: tags
+                        .iter()
+                        .map(|(tag, count)| (tag.clone(), *count))
+                        .collect(),
+                    connected: self.peer_connections.contains_key(peer_id),
+                    reconnect_attempts: reconnect
+                        .map(|r| r.attempts)
+                        .or_else(|| self.reconnecting.get(peer_id).copied())
+                        .unwrap_or_default(),
+                    next_attempt: reconnect.map(|r| r.at.saturating_duration_since(now)),
+                }
+            })
+            .collect()
+    }
+
+    fn notify_keep_alive(&mut self, peer_id: PeerId, keep_alive: bool) {
+        let Some(connections) = self.peer_connections.get(&peer_id) else {
+            return;
+        };
+
+        for (connection_id, _) in connections {
+            self.events.push_back(ToSwarm::NotifyHandler {
+                peer_id,
+                handler: NotifyHandler::One(*connection_id),
+                event: keep_alive,
+            });
+        }
+
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn schedule_reconnect(&mut self, peer_id: PeerId, attempts: u32) {
+        self.reconnect.insert(peer_id, Reconnect::new(attempts));
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
     }
 
     pub fn peer_connections(&self, peer_id: PeerId) -> Option<Vec<Multiaddr>> {
@@ -75,7 +391,7 @@ impl Behaviour {
 }
 
 impl NetworkBehaviour for Behaviour {
-    type ConnectionHandler = DummyConnectionHandler;
+    type ConnectionHandler = handler::Handler;
     type ToSwarm = void::Void;
 
     fn handle_pending_inbound_connection(
@@ -100,21 +416,21 @@ impl NetworkBehaviour for Behaviour {
     fn handle_established_inbound_connection(
         &mut self,
         _: ConnectionId,
-        _: PeerId,
+        peer_id: PeerId,
         _: &Multiaddr,
         _: &Multiaddr,
     ) -> Result<THandler<Self>, ConnectionDenied> {
-        Ok(DummyConnectionHandler)
+        Ok(handler::Handler::new(self.is_protected(&peer_id)))
     }
 
     fn handle_established_outbound_connection(
         &mut self,
         _: ConnectionId,
-        _: PeerId,
+        peer_id: PeerId,
         _: &Multiaddr,
         _: Endpoint,
     ) -> Result<THandler<Self>, ConnectionDenied> {
-        Ok(DummyConnectionHandler)
+        Ok(handler::Handler::new(self.is_protected(&peer_id)))
     }
 
     fn on_connection_handler_event(
@@ -138,6 +454,8 @@ impl NetworkBehaviour for Behaviour {
                     .entry(peer_id)
                     .or_default()
                     .push((connection_id, multiaddr));
+                self.reconnect.remove(&peer_id);
+                self.reconnecting.remove(&peer_id);
             }
             FromSwarm::ConnectionClosed(ConnectionClosed {
                 peer_id,
@@ -157,7 +475,22 @@ impl NetworkBehaviour for Behaviour {
 
                 if remaining_established == 0 {
                     self.peer_rtt.remove(&(peer_id));
-                    self.peer_info.remove(&peer_id);
+                    self.remove_peer_info(peer_id);
+
+                    if self.is_protected(&peer_id) {
+                        self.schedule_reconnect(peer_id, 0);
+                    }
+                }
+            }
+            FromSwarm::DialFailure(DialFailure {
+                peer_id: Some(peer_id),
+                ..
+            }) => {
+                if let Some(attempts) = self.reconnecting.remove(&peer_id) {
+                    if self.is_protected(&peer_id) && !self.peer_connections.contains_key(&peer_id)
+                    {
+                        self.schedule_reconnect(peer_id, attempts + 1);
+                    }
                 }
             }
 
@@ -165,11 +498,84 @@ impl NetworkBehaviour for Behaviour {
         }
     }
 
-    fn poll(&mut self, _: &mut Context) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+    fn poll(&mut self, cx: &mut Context) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
         if let Some(event) = self.events.pop_front() {
             return Poll::Ready(event);
         }
 
+        let ready = self.reconnect.iter_mut().find_map(|(peer_id, reconnect)| {
+            reconnect
+                .delay
+                .poll_unpin(cx)
+                .is_ready()
+                .then_some(*peer_id)
+        });
+
+        if let Some(peer_id) = ready {
+            let reconnect = self
+                .reconnect
+                .remove(&peer_id)
+                .expect("peer is reconnecting");
+            self.reconnecting.insert(peer_id, reconnect.attempts);
+            let opts = DialOpts::peer_id(peer_id)
+                .condition(PeerCondition::DisconnectedAndNotDialing)
+                .build();
+            return Poll::Ready(ToSwarm::Dial { opts });
+        }
+
+        self.waker = Some(cx.waker().clone());
         Poll::Pending
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use libp2p::PeerId;
+
+    use super::{Behaviour, PeerMetaConfig, PeerMetaError};
+
+    #[test]
+    fn peer_meta_is_bounded() {
+        let mut peerbook = Behaviour::with_meta_config(PeerMetaConfig {
+            max_value_size: 8,
+            max_peer_size: 16,
+            persist: false,
+        });
+        let peer_id = PeerId::random();
+
+        assert_eq!(
+            peerbook.set_peer_meta(peer_id, "role".into(), vec![0; 9]),
+            Err(PeerMetaError::ValueTooLarge {
+                key: "role".into(),
+                size: 9,
+                max: 8
+            })
+        );
+        assert!(peerbook.all_peer_meta(&peer_id).is_none());
+
+        peerbook
+            .set_peer_meta(peer_id, "role".into(), b"leader".to_vec())
+            .unwrap();
+        assert_eq!(
+            peerbook.set_peer_meta(peer_id, "account".into(), b"42".to_vec()),
+            Err(PeerMetaError::PeerFull {
+                peer_id,
+                size: 19,
+                max: 16
+            })
+        );
+
+        // replacing a value only counts the new one
+        let old = peerbook
+            .set_peer_meta(peer_id, "role".into(), b"follower".to_vec())
+            .unwrap();
+        assert_eq!(old.as_deref(), Some(&b"leader"[..]));
+        assert_eq!(peerbook.peer_meta(&peer_id, "role"), Some(&b"follower"[..]));
+
+        assert_eq!(
+            peerbook.remove_peer_meta(&peer_id, "role").as_deref(),
+            Some(&b"follower"[..])
+        );
+        assert!(peerbook.all_peer_meta(&peer_id).is_none());
+    }
+}
@@ -0,0 +1,245 @@
+//! Pings peers on demand over the ping protocol, the ping behaviour only pinging the connections
+//! every interval of its configuration. The inbound pings are answered by the ping behaviour.
+use core::task::{Context, Poll, Waker};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::time::{Duration, Instant};
+
+use futures::{future::BoxFuture, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, FutureExt};
+use futures_timer::Delay;
+use libp2p::core::upgrade::DeniedUpgrade;
+use libp2p::core::{Endpoint, Multiaddr, UpgradeInfo};
+use libp2p::swarm::derive_prelude::ConnectionEstablished;
+use libp2p::swarm::{
+    ConnectionClosed, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, NotifyHandler,
+    OneShotHandler, StreamUpgradeError, SubstreamProtocol, THandler, THandlerInEvent,
+    THandlerOutEvent, ToSwarm,
+};
+use libp2p::{OutboundUpgrade, PeerId, StreamProtocol};
+use void::Void;
+
+const PROTOCOL: StreamProtocol = StreamProtocol::new("/ipfs/ping/1.0.0");
+const PING_SIZE: usize = 32;
+
+/// Delay between the pings of a peer pinged continuously, as `ipfs ping` does.
+pub const INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sends a single ping over the substream, resolving to the round-trip time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PingRequest;
+
+impl UpgradeInfo for PingRequest {
+    type Info = StreamProtocol;
+    type InfoIter = std::iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        std::iter::once(PROTOCOL)
+    }
+}
+
+impl<TSocket> OutboundUpgrade<TSocket> for PingRequest
+where
+    TSocket: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Output = Pong;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_outbound(self, mut socket: TSocket, _: Self::Info) -> Self::Future {
+        async move {
+            let payload: [u8; PING_SIZE] = rand::random();
+            let started = Instant::now();
+            socket.write_all(&payload).await?;
+            socket.flush().await?;
+
+            let mut pong = [0u8; PING_SIZE];
+            socket.read_exact(&mut pong).await?;
+            let rtt = started.elapsed();
+            if pong != payload {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "ping payload mismatch",
+                ));
+            }
+
+            // the peer answers the pings until the substream is closed
+            _ = socket.close().await;
+            Ok(Pong(rtt))
+        }
+        .boxed()
+    }
+}
+
+/// Round-trip time of a ping.
+#[derive(Debug, Clone, Copy)]
+pub struct Pong(Duration);
+
+impl From<Void> for Pong {
+    fn from(void: Void) -> Self {
+        void::unreachable(void)
+    }
+}
+
+/// Outcome of pinging a peer on demand.
+#[derive(Debug)]
+pub struct Event {
+    pub peer_id: PeerId,
+    pub result: Result<Duration, StreamUpgradeError<io::Error>>,
+}
+
+#[derive(Default, Debug)]
+pub struct Behaviour {
+    events: VecDeque<ToSwarm<Event, THandlerInEvent<Self>>>,
+    connections: HashMap<PeerId, Vec<ConnectionId>>,
+    /// Connection each peer is being pinged over
+    pinging: HashMap<PeerId, ConnectionId>,
+    /// Peers pinged again once their delay elapsed
+    scheduled: HashMap<PeerId, Delay>,
+    waker: Option<Waker>,
+}
+
+impl Behaviour {
+    /// Pings the connected peer right away, unless it is being pinged already.
+    pub fn ping(&mut self, peer_id: PeerId) {
+        self.scheduled.remove(&peer_id);
+        if self.pinging.contains_key(&peer_id) {
+            return;
+        }
+        let Some(connection_id) = self
+            .connections
+            .get(&peer_id)
+            .and_then(|connections| connections.first())
+            .copied()
+        else {
+            return;
+        };
+
+        self.pinging.insert(peer_id, connection_id);
+        self.events.push_back(ToSwarm::NotifyHandler {
+            peer_id,
+            handler: NotifyHandler::One(connection_id),
+            event: PingRequest,
+        });
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Pings the connected peer once the delay elapsed, unless it is being pinged or scheduled
+    /// to be pinged already.
+    pub fn ping_after(&mut self, peer_id: PeerId, delay: Duration) {
+        if self.pinging.contains_key(&peer_id) || !self.connections.contains_key(&peer_id) {
+            return;
+        }
+        self.scheduled
+            .entry(peer_id)
+            .or_insert_with(|| Delay::new(delay));
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler = OneShotHandler<DeniedUpgrade, PingRequest, Pong>;
+    type ToSwarm = Event;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: PeerId,
+        _: &Multiaddr,
+        _: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(OneShotHandler::new(
+            SubstreamProtocol::new(DeniedUpgrade, ()),
+            Default::default(),
+        ))
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: PeerId,
+        _: &Multiaddr,
+        _: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(OneShotHandler::new(
+            SubstreamProtocol::new(DeniedUpgrade, ()),
+            Default::default(),
+        ))
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        if self.pinging.get(&peer_id) != Some(&connection_id) {
+            return;
+        }
+        self.pinging.remove(&peer_id);
+        self.events.push_back(ToSwarm::GenerateEvent(Event {
+            peer_id,
+            result: event.map(|Pong(rtt)| rtt),
+        }));
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        match event {
+            FromSwarm::ConnectionEstablished(ConnectionEstablished {
+                peer_id,
+                connection_id,
+                ..
+            }) => {
+                self.connections
+                    .entry(peer_id)
+                    .or_default()
+                    .push(connection_id);
+            }
+            FromSwarm::ConnectionClosed(ConnectionClosed {
+                peer_id,
+                connection_id,
+                ..
+            }) => {
+                if let Entry::Occupied(mut entry) = self.connections.entry(peer_id) {
+                    entry.get_mut().retain(|id| *id != connection_id);
+                    if entry.get().is_empty() {
+                        entry.remove();
+                        self.scheduled.remove(&peer_id);
+                    }
+                }
+
+                // the ping is sent again over another connection, if any
+                if self.pinging.get(&peer_id) == Some(&connection_id) {
+                    self.pinging.remove(&peer_id);
+                    self.ping(peer_id);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        if let Some(event) = self.events.pop_front() {
+            return Poll::Ready(event);
+        }
+
+        // pinging removes the peer from the scheduled ones
+        while let Some(peer_id) = self
+            .scheduled
+            .iter_mut()
+            .find_map(|(peer_id, delay)| delay.poll_unpin(cx).is_ready().then_some(*peer_id))
+        {
+            self.ping(peer_id);
+            if let Some(event) = self.events.pop_front() {
+                return Poll::Ready(event);
+            }
+        }
+
+        self.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
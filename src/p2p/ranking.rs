@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use libp2p::PeerId;
+use rand::seq::SliceRandom;
+
+/// Order in which the providers found for a wanted block are dialed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderRanking {
+    /// The providers with the lowest round-trip time first. The providers of unknown latency are
+    /// dialed in the order they were found, once the others failed or are slow.
+    #[default]
+    Latency,
+    Random,
+    FirstDiscovered,
+}
+
+impl ProviderRanking {
+    fn rank(&self, providers: &mut [PeerId], rtt: impl Fn(&PeerId) -> Option<Duration>) {
+        match self {
+            // the sort is stable, keeping the unknown latencies in the order found
+            ProviderRanking::Latency => providers.sort_by_key(|peer_id| match rtt(peer_id) {
+                Some(rtt) => (false, rtt),
+                None => (true, Duration::ZERO),
+            }),
+            ProviderRanking::Random => providers.shuffle(&mut rand::thread_rng()),
+            ProviderRanking::FirstDiscovered => {}
+        }
+    }
+
+    /// Ranks the candidates and removes the next `count` to dial from them. Ranked by latency,
+    /// the providers of unknown latency are only selected once none of known latency is left.
+    pub(crate) fn select(
+        &self,
+        candidates: &mut Vec<PeerId>,
+        count: usize,
+        rtt: impl Fn(&PeerId) -> Option<Duration>,
+    ) -> Vec<PeerId> {
+        self.rank(candidates, &rtt);
+
+        let mut count = count.min(candidates.len());
+        if *self == ProviderRanking::Latency {
+            let known = candidates
+                .iter()
+                .take_while(|peer_id| rtt(*peer_id).is_some())
+                .count();
+            if known > 0 {
+                count = count.min(known);
+            }
+        }
+
+        candidates.drain(..count).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use libp2p::PeerId;
+
+    use super::ProviderRanking;
+
+    #[test]
+    fn lowest_latencies_are_selected_first() {
+        let peers = (0..6).map(|_| PeerId::random()).collect::<Vec<_>>();
+        let rtts = HashMap::from([
+            (peers[1], Duration::from_millis(300)),
+            (peers[3], Duration::from_millis(20)),
+            (peers[4], Duration::from_millis(80)),
+        ]);
+        let rtt = |peer_id: &PeerId| rtts.get(peer_id).copied();
+
+        let mut candidates = peers.clone();
+        let ranking = ProviderRanking::Latency;
+
+        assert_eq!(
+            ranking.select(&mut candidates, 2, rtt),
+            [peers[3], peers[4]]
+        );
+        // the unknown latencies wait for the known ones to be tried
+        assert_eq!(ranking.select(&mut candidates, 2, rtt), [peers[1]]);
+        assert_eq!(
+            ranking.select(&mut candidates, 2, rtt),
+            [peers[0], peers[2]]
+        );
+        assert_eq!(ranking.select(&mut candidates, 2, rtt), [peers[5]]);
+        assert!(candidates.is_empty());
+    }
+
+    #[test]
+    fn other_rankings_ignore_latency() {
+        let peers = (0..4).map(|_| PeerId::random()).collect::<Vec<_>>();
+        let rtt = |peer_id: &PeerId| (*peer_id == peers[3]).then_some(Duration::from_millis(1));
+
+        let mut candidates = peers.clone();
+        let first = ProviderRanking::FirstDiscovered.select(&mut candidates, 3, rtt);
+        assert_eq!(first, peers[..3]);
+
+        let mut candidates = peers.clone();
+        let mut random = ProviderRanking::Random.select(&mut candidates, 3, rtt);
+        random.extend(candidates);
+        random.sort();
+        let mut expected = peers.clone();
+        expected.sort();
+        assert_eq!(random, expected);
+    }
+}
@@ -163,7 +163,6 @@ pub(crate) fn build_transport(
     Ok(transport)
 }
 
-#[allow(dead_code)]
 pub(crate) fn memory_transport(
     keypair: &identity::Keypair,
     relay: Option<ClientTransport>,
@@ -43,6 +43,21 @@ impl FromStr for IpfsPath {
     type Err = Error;
 
     fn from_str(string: &str) -> Result<Self, Error> {
+        let (root, subpath) = IpfsPath::parse_root(string)?;
+
+        let mut path = IpfsPath::new(root);
+        path.path.push_split(subpath).map_err(|e| match e {
+            e @ IpfsPathError::RelativeSegment(_) => e,
+            _ => IpfsPathError::InvalidPath(string.to_owned()),
+        })?;
+        Ok(path)
+    }
+}
+
+impl IpfsPath {
+    /// Splits off the [`PathRoot`] of the given string, returning it along with the remaining
+    /// segments.
+    fn parse_root(string: &str) -> Result<(PathRoot, std::str::Split<'_, char>), Error> {
         let mut subpath = string.split('/');
         let empty = subpath.next().expect("there's always the first split");
 
@@ -59,7 +74,7 @@ impl FromStr for IpfsPath {
                 ("", Some("ipns"), Some(key)) => match PeerId::from_str(key).ok() {
                     Some(peer_id) => PathRoot::Ipns(peer_id),
                     None => {
-                        let result = |key: &str| -> Result<PathRoot, Self::Err> {
+                        let result = |key: &str| -> Result<PathRoot, Error> {
                             let p = PeerId::from_bytes(&Cid::from_str(key)?.hash().to_bytes())?;
 
                             Ok(PathRoot::Ipns(p))
@@ -77,15 +92,127 @@ impl FromStr for IpfsPath {
             }
         };
 
+        Ok((root, subpath))
+    }
+
+    /// Parses an [`IpfsPath`] where the segments following the root are percent-encoded, as
+    /// produced by [`IpfsPath::to_encoded_string`]. Unlike [`FromStr`], this allows segments to
+    /// contain reserved characters such as a literal `/` (encoded as `%2F`).
+    pub fn from_encoded_str(string: &str) -> Result<Self, Error> {
+        let (root, subpath) = IpfsPath::parse_root(string)?;
+
+        let segments = subpath
+            .map(|segment| {
+                percent_decode(segment).ok_or_else(|| IpfsPathError::InvalidPath(string.to_owned()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
         let mut path = IpfsPath::new(root);
         path.path
-            .push_split(subpath)
-            .map_err(|_| IpfsPathError::InvalidPath(string.to_owned()))?;
+            .push_split(segments.iter().map(String::as_str))
+            .map_err(|e| match e {
+                e @ IpfsPathError::RelativeSegment(_) => e,
+                _ => IpfsPathError::InvalidPath(string.to_owned()),
+            })?;
         Ok(path)
     }
-}
 
-impl IpfsPath {
+    /// Returns the string form of the path where reserved characters within the segments are
+    /// percent-encoded. See [`IpfsPath::from_encoded_str`] for the reverse operation.
+    pub fn to_encoded_string(&self) -> String {
+        let mut string = self.root.to_string();
+        for segment in self.path.iter() {
+            string.push('/');
+            percent_encode_into(segment, &mut string);
+        }
+        string
+    }
+
+    /// Parses an [`IpfsPath`] out of the forms users commonly copy around, producing the same
+    /// value as parsing the canonical `/ipfs/<cid>/path` form would. Accepted inputs are:
+    ///
+    /// - the canonical form, as accepted by [`FromStr`]
+    /// - bare [`Cid`]s, optionally followed by a path (`<cid>/path`)
+    /// - native `ipfs://<cid>/path` and `ipns://<name>/path` URLs
+    /// - path gateway URLs (`https://gateway/ipfs/<cid>/path`)
+    /// - subdomain gateway URLs (`https://<cid>.ipfs.gateway/path`), with or without the scheme
+    ///
+    /// Segments following the root are percent-decoded as with [`IpfsPath::from_encoded_str`],
+    /// while query and fragment are ignored. A subdomain gateway host whose `.ipfs.` label fails
+    /// to decode as a [`Cid`] is rejected instead of being treated as a regular host.
+    pub fn from_url(url: &str) -> Result<Self, Error> {
+        let url = url.trim();
+
+        if url.starts_with('/') {
+            return IpfsPath::from_encoded_str(url);
+        }
+
+        let (scheme, rest) = match url.split_once("://") {
+            Some((scheme, rest)) => (Some(scheme.to_ascii_lowercase()), rest),
+            None => (None, url),
+        };
+
+        let rest = rest.split(['?', '#']).next().unwrap_or_default();
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+
+        match scheme.as_deref() {
+            Some(namespace @ ("ipfs" | "ipns")) => {
+                return IpfsPath::from_encoded_str(&format!("/{namespace}/{rest}"));
+            }
+            Some("http" | "https") => {}
+            None if Cid::try_from(authority).is_ok() => {
+                return IpfsPath::from_encoded_str(&format!("/ipfs/{rest}"));
+            }
+            None => {}
+            Some(_) => return Err(IpfsPathError::InvalidPath(url.to_owned()).into()),
+        }
+
+        let host = authority
+            .rsplit_once('@')
+            .map_or(authority, |(_, host)| host);
+        let host = match host.rsplit_once(':') {
+            Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+            _ => host,
+        };
+
+        let labels = host.split('.').collect::<Vec<_>>();
+
+        match labels.as_slice() {
+            [label, "ipfs", _, ..] => {
+                let cid = Cid::try_from(label.to_ascii_lowercase().as_str())
+                    .map_err(|_| IpfsPathError::InvalidPath(url.to_owned()))?;
+                IpfsPath::from_encoded_str(&format!("/ipfs/{cid}/{path}"))
+            }
+            [label, "ipns", _, ..] => {
+                // dnslink names are inlined into a single label by replacing `-` with `--` and
+                // `.` with `-`
+                let name = label
+                    .to_ascii_lowercase()
+                    .split("--")
+                    .map(|part| part.replace('-', "."))
+                    .collect::<Vec<_>>()
+                    .join("-");
+                IpfsPath::from_encoded_str(&format!("/ipns/{name}/{path}"))
+            }
+            _ if path.starts_with("ipfs/") || path.starts_with("ipns/") => {
+                IpfsPath::from_encoded_str(&format!("/{path}"))
+            }
+            _ => Err(IpfsPathError::InvalidPath(url.to_owned()).into()),
+        }
+    }
+
+    /// Returns the path with a CIDv0 root upgraded to CIDv1, which is displayed in base32 as
+    /// required by subdomain gateways. Other roots are returned unchanged.
+    pub fn canonicalize(self) -> Self {
+        match self.root {
+            PathRoot::Ipld(cid) if cid.version() == libipld::cid::Version::V0 => IpfsPath {
+                root: PathRoot::Ipld(Cid::new_v1(cid.codec(), *cid.hash())),
+                path: self.path,
+            },
+            _ => self,
+        }
+    }
+
     /// Creates a new [`IpfsPath`] from a [`PathRoot`].
     pub fn new(root: PathRoot) -> Self {
         IpfsPath {
@@ -112,6 +239,37 @@ impl IpfsPath {
         Ok(path)
     }
 
+    /// Returns a new [`IpfsPath`] with the given relative path appended. Unlike
+    /// [`IpfsPath::sub_path`], this reports an error when given an absolute path such as
+    /// `/ipfs/<cid>/a`.
+    pub fn join(&self, segments: impl AsRef<str>) -> Result<Self, Error> {
+        let segments = segments.as_ref();
+        if segments.starts_with('/') {
+            return Err(IpfsPathError::InvalidPath(segments.to_owned()).into());
+        }
+        self.sub_path(segments)
+    }
+
+    /// Returns the path without its last segment, or `None` if the path only consists of the
+    /// root.
+    pub fn parent(&self) -> Option<Self> {
+        self.split_last().map(|(_, parent)| parent)
+    }
+
+    /// Returns the last segment of the path, or `None` if the path only consists of the root.
+    pub fn file_name(&self) -> Option<&str> {
+        self.path.iter().last().map(|s| s.as_str())
+    }
+
+    /// Returns the last segment of the path along with the path leading up to it, or `None` if
+    /// the path only consists of the root.
+    pub fn split_last(&self) -> Option<(&str, Self)> {
+        let last = self.file_name()?;
+        let mut parent = self.clone();
+        parent.path.truncate(self.path.len() - 1);
+        Some((last, parent))
+    }
+
     /// Returns an iterator over the path segments following the root.
     pub fn iter(&self) -> impl Iterator<Item = &str> {
         self.path.iter().map(|s| s.as_str())
@@ -160,6 +318,25 @@ impl<T: Into<PathRoot>> From<T> for IpfsPath {
     }
 }
 
+impl serde::Serialize for IpfsPath {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for IpfsPath {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let string = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        IpfsPath::from_str(&string).map_err(serde::de::Error::custom)
+    }
+}
+
 /// SlashedPath is internal to IpfsPath variants, and basically holds a unixfs-compatible path
 /// where segments do not contain slashes but can pretty much contain all other valid UTF-8.
 ///
@@ -175,15 +352,17 @@ impl SlashedPath {
         if path.is_empty() {
             Ok(())
         } else {
-            self.push_split(path.split('/'))
-                .map_err(|_| IpfsPathError::SegmentContainsSlash(path.to_owned()))
+            self.push_split(path.split('/')).map_err(|e| match e {
+                e @ IpfsPathError::RelativeSegment(_) => e,
+                _ => IpfsPathError::SegmentContainsSlash(path.to_owned()),
+            })
         }
     }
 
     pub(crate) fn push_split<'a>(
         &mut self,
         split: impl Iterator<Item = &'a str>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), IpfsPathError> {
         let mut split = split.peekable();
         while let Some(sub_path) = split.next() {
             if sub_path.is_empty() {
@@ -192,9 +371,13 @@ impl SlashedPath {
                     Ok(())
                 } else {
                     // no empty segments in the middle
-                    Err(())
+                    Err(IpfsPathError::InvalidPath(sub_path.to_owned()))
                 };
             }
+            if sub_path == "." || sub_path == ".." {
+                // these would only fail later on during resolving with a confusing error
+                return Err(IpfsPathError::RelativeSegment(sub_path.to_owned()));
+            }
             self.path.push(sub_path.to_owned());
         }
         Ok(())
@@ -337,11 +520,69 @@ pub enum IpfsPathError {
     /// Path segment contains a slash, which is not allowed.
     #[error("Invalid segment {0:?}")]
     SegmentContainsSlash(String),
+
+    /// Path segment is either `.` or `..`, which are not supported.
+    #[error("Relative segment {0:?} is not allowed")]
+    RelativeSegment(String),
+}
+
+/// Returns true for the bytes which can appear unencoded within a path segment, following the
+/// `pchar` production of RFC 3986.
+fn is_segment_char(byte: u8) -> bool {
+    matches!(byte,
+        b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9'
+        | b'-' | b'.' | b'_' | b'~'
+        | b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'='
+        | b':' | b'@')
+}
+
+fn percent_encode_into(segment: &str, out: &mut String) {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    for &byte in segment.as_bytes() {
+        if is_segment_char(byte) {
+            out.push(byte as char);
+        } else {
+            out.push('%');
+            out.push(HEX[(byte >> 4) as usize] as char);
+            out.push(HEX[(byte & 0x0f) as usize] as char);
+        }
+    }
+}
+
+/// Decodes a percent-encoded segment, returning `None` on malformed escapes or if the decoded
+/// bytes are not valid UTF-8.
+fn percent_decode(segment: &str) -> Option<String> {
+    if !segment.contains('%') {
+        return Some(segment.to_owned());
+    }
+
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3)?;
+                if !hex.iter().all(u8::is_ascii_hexdigit) {
+                    return None;
+                }
+                let hex = std::str::from_utf8(hex).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).ok()
 }
 
 #[cfg(test)]
 mod tests {
-    use super::IpfsPath;
+    use super::{IpfsPath, IpfsPathError};
     use std::convert::TryFrom;
 
     #[test]
@@ -448,6 +689,205 @@ mod tests {
         IpfsPath::try_from("/ipfs/QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n///a").unwrap_err();
     }
 
+    #[test]
+    fn relative_segments_are_rejected() {
+        let bad = [
+            "/ipfs/QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n/.",
+            "/ipfs/QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n/a/../b",
+            "/ipns/foobar.com/./a",
+        ];
+
+        for &bad in &bad {
+            let e = IpfsPath::try_from(bad).unwrap_err();
+            assert!(
+                matches!(
+                    e.downcast_ref::<IpfsPathError>(),
+                    Some(IpfsPathError::RelativeSegment(_))
+                ),
+                "{bad:?} failed with {e:?}"
+            );
+        }
+
+        let root =
+            IpfsPath::try_from("/ipfs/QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n").unwrap();
+        root.sub_path("a/..").unwrap_err();
+
+        // segments which merely start with a dot are fine
+        assert_eq!(root.sub_path(".a/...").unwrap().iter().count(), 2);
+    }
+
+    #[test]
+    fn encoded_round_trip() {
+        let input = [
+            (
+                "/ipfs/QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n/a%2Fb/c",
+                &["a/b", "c"][..],
+            ),
+            (
+                "/ipfs/QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n/what%3F%23/100%25",
+                &["what?#", "100%"][..],
+            ),
+            (
+                "/ipns/foobar.com/hello%20world/%C3%A4",
+                &["hello world", "ä"][..],
+            ),
+        ];
+
+        for (encoded, segments) in input {
+            let path = IpfsPath::from_encoded_str(encoded).unwrap();
+            assert_eq!(path.iter().collect::<Vec<_>>(), segments);
+            assert_eq!(path.to_encoded_string(), encoded);
+            assert_eq!(
+                IpfsPath::from_encoded_str(&path.to_encoded_string()).unwrap(),
+                path
+            );
+        }
+
+        // plain parsing keeps the percent signs as is
+        let plain =
+            IpfsPath::try_from("/ipfs/QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n/a%2Fb")
+                .unwrap();
+        assert_eq!(plain.iter().collect::<Vec<_>>(), ["a%2Fb"]);
+    }
+
+    #[test]
+    fn bad_encoded_paths() {
+        let bad = [
+            "/ipfs/QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n/a%2",
+            "/ipfs/QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n/a%zz",
+            "/ipfs/QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n/%FF",
+            "/ipfs/QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n/%2E%2E",
+        ];
+
+        for &bad in &bad {
+            IpfsPath::from_encoded_str(bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn join_parent_and_file_name() {
+        let root =
+            IpfsPath::try_from("/ipfs/QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n").unwrap();
+
+        assert!(root.parent().is_none());
+        assert!(root.file_name().is_none());
+        assert!(root.split_last().is_none());
+
+        let path = root.join("a/b").unwrap();
+        assert_eq!(
+            path.to_string(),
+            "/ipfs/QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n/a/b"
+        );
+        assert_eq!(path.file_name(), Some("b"));
+
+        let (last, parent) = path.split_last().unwrap();
+        assert_eq!(last, "b");
+        assert_eq!(parent, root.join("a").unwrap());
+        assert_eq!(parent.parent(), Some(root.clone()));
+
+        root.join("/ipfs/QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n/a")
+            .unwrap_err();
+        root.join("/a").unwrap_err();
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let path =
+            IpfsPath::try_from("/ipfs/QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n/a/b").unwrap();
+
+        let json = serde_json::to_string(&path).unwrap();
+        assert_eq!(
+            json,
+            "\"/ipfs/QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n/a/b\""
+        );
+        assert_eq!(serde_json::from_str::<IpfsPath>(&json).unwrap(), path);
+
+        serde_json::from_str::<IpfsPath>("\"/ipfs/foo\"").unwrap_err();
+    }
+
+    #[test]
+    fn from_url_round_trip() {
+        let v0 = "QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n";
+        let v1 = "bafybeihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+
+        let examples = [
+            (format!("/ipfs/{v0}/a/b"), format!("/ipfs/{v0}/a/b")),
+            (v0.to_owned(), format!("/ipfs/{v0}")),
+            (format!("{v1}/a/b"), format!("/ipfs/{v1}/a/b")),
+            (format!("ipfs://{v1}/a/b"), format!("/ipfs/{v1}/a/b")),
+            (
+                "ipns://en.wikipedia-on-ipfs.org/wiki".to_owned(),
+                "/ipns/en.wikipedia-on-ipfs.org/wiki".to_owned(),
+            ),
+            (
+                format!("https://ipfs.io/ipfs/{v0}/a/b?filename=b#top"),
+                format!("/ipfs/{v0}/a/b"),
+            ),
+            (
+                format!("http://127.0.0.1:8080/ipfs/{v1}/a%20b/"),
+                format!("/ipfs/{v1}/a b"),
+            ),
+            (
+                format!("https://{v1}.ipfs.dweb.link/a/b"),
+                format!("/ipfs/{v1}/a/b"),
+            ),
+            (
+                format!("{}.ipfs.dweb.link/a", v1.to_ascii_uppercase()),
+                format!("/ipfs/{v1}/a"),
+            ),
+            (
+                format!("http://{v1}.ipfs.localhost:8080"),
+                format!("/ipfs/{v1}"),
+            ),
+            (
+                "https://en-wikipedia--on--ipfs-org.ipns.dweb.link/wiki/".to_owned(),
+                "/ipns/en.wikipedia-on-ipfs.org/wiki".to_owned(),
+            ),
+        ];
+
+        for (url, canonical) in &examples {
+            let parsed = IpfsPath::from_url(url).unwrap();
+            assert_eq!(
+                parsed,
+                IpfsPath::try_from(canonical.as_str()).unwrap(),
+                "{url}"
+            );
+            assert_eq!(&parsed.to_string(), canonical, "{url}");
+        }
+    }
+
+    #[test]
+    fn from_url_rejects_lookalikes() {
+        let bad = [
+            "https://notacid.ipfs.dweb.link/a",
+            "https://bafybeihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquv.ipfs.dweb.link/a",
+            "https://example.com/a/b",
+            "https://example.com",
+            "ftp://ipfs.io/ipfs/QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n",
+            "notacid/a",
+        ];
+
+        for &bad in &bad {
+            IpfsPath::from_url(bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn canonicalize_upgrades_v0() {
+        let v0 = IpfsPath::try_from("/ipfs/QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n/a")
+            .unwrap()
+            .canonicalize();
+
+        assert_eq!(
+            v0.to_string(),
+            "/ipfs/bafybeihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku/a"
+        );
+        assert_eq!(v0.clone().canonicalize(), v0);
+
+        let dns = IpfsPath::try_from("/ipns/ipfs.io/a").unwrap();
+        assert_eq!(dns.clone().canonicalize(), dns);
+    }
+
     #[test]
     fn shifting() {
         let mut p = super::SlashedPath::default();
@@ -32,12 +32,50 @@ impl fmt::Debug for Edge {
     }
 }
 
+/// A reference listed by [`Ipfs::refs`](crate::Ipfs::refs).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ref {
+    /// The linked document, when the edges are not listed
+    Cid(Cid),
+    /// The link to the document
+    Edge(Edge),
+}
+
+impl Ref {
+    /// Returns the `Cid` of the linked document.
+    pub fn cid(&self) -> &Cid {
+        match self {
+            Ref::Cid(cid) => cid,
+            Ref::Edge(edge) => &edge.destination,
+        }
+    }
+}
+
+/// Options of [`Ipfs::refs`](crate::Ipfs::refs), which lists the links of the document only by
+/// default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefsOptions {
+    /// List the links of the linked documents as well
+    pub recursive: bool,
+    /// List each linked document once
+    pub unique: bool,
+    /// Stop at this depth when recursive, the links of the document being at depth one
+    pub max_depth: Option<u64>,
+    /// List [`Ref::Edge`]s instead of [`Ref::Cid`]s
+    pub edges: bool,
+    /// Only use the local blocks. The missing ones are listed as [`IpldRefsError::BlockNotFound`]
+    /// and their links are skipped.
+    pub offline: bool,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum IpldRefsError {
     #[error("loading failed")]
     Loading(#[from] crate::Error),
     #[error("block not found locally: {}", .0)]
     BlockNotFound(Cid),
+    #[error("cancelled")]
+    Cancelled,
 }
 
 pub(crate) struct IpldRefs {
@@ -45,6 +83,7 @@ pub(crate) struct IpldRefs {
     unique: bool,
     download_blocks: bool,
     exit_on_error: bool,
+    inline_errors: bool,
     providers: Vec<PeerId>,
     timeout: Option<Duration>,
 }
@@ -56,6 +95,7 @@ impl Default for IpldRefs {
             unique: false,
             download_blocks: true,
             exit_on_error: false,
+            inline_errors: false,
             providers: vec![],
             timeout: None,
         }
@@ -65,7 +105,6 @@ impl Default for IpldRefs {
 impl IpldRefs {
     /// Overrides the default maximum depth of "unlimited" with the given maximum depth. Zero is
     /// allowed and will result in an empty stream.
-    #[allow(dead_code)]
     pub fn with_max_depth(mut self, depth: u64) -> IpldRefs {
         self.max_depth = Some(depth);
         self
@@ -112,6 +151,13 @@ impl IpldRefs {
         self
     }
 
+    /// Yield the blocks which could not be loaded as errors and continue with the others, instead
+    /// of skipping them silently
+    pub fn with_inline_errors(mut self) -> IpldRefs {
+        self.inline_errors = true;
+        self
+    }
+
     pub fn refs_of_resolved<'a, MaybeOwned, Iter>(
         self,
         repo: MaybeOwned,
@@ -160,6 +206,7 @@ where
         timeout: None,
         providers: vec![],
         exit_on_error: true,
+        inline_errors: false,
     };
     iplds_refs_inner(repo, iplds, opts).map_err(|e| match e {
         IpldRefsError::Loading(e) => e,
@@ -188,6 +235,7 @@ where
         download_blocks,
         timeout,
         exit_on_error,
+        inline_errors,
         providers,
     } = opts;
 
@@ -221,10 +269,16 @@ where
                     continue;
                 },
                 // no need to list links which would be filtered out
-                Some(d) if d + 1 == depth => false,
+                Some(d) if depth + 1 == d => false,
                 _ => true
             };
 
+            // nor to load the block
+            if !traverse_links {
+                yield Ok(Edge { source, destination: cid, name: link_name });
+                continue;
+            }
+
             // if this is not bound to a local variable it'll introduce a Sync requirement on
             // `MaybeOwned` which we don't necessarily need.
             let borrowed = repo.borrow();
@@ -234,8 +288,10 @@ where
                     Ok(block) => block,
                     Err(e) => {
                         warn!("failed to load {}, linked from {}: {}", cid, source, e);
-                        if exit_on_error {
+                        if exit_on_error || inline_errors {
                             yield Err(IpldRefsError::from(e));
+                        }
+                        if exit_on_error {
                             return;
                         }
                         continue;
@@ -245,15 +301,19 @@ where
                 match borrowed.get_block_now(&cid).await {
                     Ok(Some(block)) => block,
                     Ok(None) => {
-                        if exit_on_error {
+                        if exit_on_error || inline_errors {
                             yield Err(IpldRefsError::BlockNotFound(cid.to_owned()));
+                        }
+                        if exit_on_error {
                             return;
                         }
                         continue;
                     }
                     Err(e) => {
-                        if exit_on_error {
+                        if exit_on_error || inline_errors {
                             yield Err(IpldRefsError::from(e));
+                        }
+                        if exit_on_error {
                             return;
                         }
                         continue;
@@ -372,9 +432,9 @@ fn dagpb_links(ipld: Ipld) -> Vec<(Option<String>, Cid)> {
 
 #[cfg(test)]
 mod tests {
-    use super::{ipld_links, iplds_refs, Edge};
+    use super::{ipld_links, iplds_refs, Edge, IpldRefsError, Ref, RefsOptions};
     use crate::{Block, Node};
-    use futures::stream::TryStreamExt;
+    use futures::stream::{StreamExt, TryStreamExt};
     use hex_literal::hex;
     use libipld::{Cid, Ipld, IpldCodec};
     use std::collections::HashSet;
@@ -498,6 +558,91 @@ mod tests {
         assert!(diff.is_empty(), "{diff:?}");
     }
 
+    #[tokio::test]
+    async fn refs_of_path_with_options() {
+        let Node { ipfs, .. } = preloaded_testing_ipfs().await;
+
+        let (root, dag0, unixfs0, dag1, unixfs1) = (
+            "bafyreihpc3vupfos5yqnlakgpjxtyx3smkg26ft7e2jnqf3qkyhromhb64",
+            "bafyreidquig3arts3bmee53rutt463hdyu6ff4zeas2etf2h2oh4dfms44",
+            "QmPJ4A6Su27ABvvduX78x2qdWMzkdAYxqeH5TVrHeo3xyy",
+            "bafyreibvjvcv745gig4mvqs4hctx4zfkono4rjejm2ta6gtyzkqxfjeily",
+            "QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL",
+        );
+        let root = Cid::try_from(root).unwrap();
+
+        let refs = |options| {
+            let ipfs = ipfs.clone();
+            async move {
+                ipfs.refs(root.into(), options)
+                    .await
+                    .unwrap()
+                    .collect::<Vec<_>>()
+                    .await
+            }
+        };
+
+        // only the links of the root
+        let found = refs(RefsOptions::default()).await;
+        let found = found
+            .into_iter()
+            .map(|r| match r.unwrap() {
+                Ref::Cid(cid) => cid.to_string(),
+                edge => panic!("unexpected {edge:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(found, [dag0, unixfs0, dag1, unixfs1]);
+
+        let found = refs(RefsOptions {
+            recursive: true,
+            max_depth: Some(2),
+            edges: true,
+            ..Default::default()
+        })
+        .await;
+        let found = found
+            .into_iter()
+            .map(|r| match r.unwrap() {
+                Ref::Edge(Edge {
+                    source,
+                    destination,
+                    ..
+                }) => (source.to_string(), destination.to_string()),
+                cid => panic!("unexpected {cid:?}"),
+            })
+            .collect::<Vec<_>>();
+        let root_str = root.to_string();
+        let expected = [
+            (root_str.as_str(), dag0),
+            (dag0, unixfs0),
+            (dag0, dag1),
+            (root_str.as_str(), unixfs0),
+            (root_str.as_str(), dag1),
+            (dag1, unixfs1),
+            (root_str.as_str(), unixfs1),
+        ];
+        assert_eq!(found.len(), expected.len());
+        assert_edges(&expected, &found);
+
+        // the missing block is listed as an error, the others still being listed
+        ipfs.remove_block(Cid::try_from(unixfs1).unwrap(), false)
+            .await
+            .unwrap();
+        let found = refs(RefsOptions {
+            recursive: true,
+            unique: true,
+            offline: true,
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(found.len(), 4);
+        assert_eq!(found.iter().filter(|r| r.is_ok()).count(), 3);
+        assert!(found.iter().any(|r| matches!(
+            r,
+            Err(IpldRefsError::BlockNotFound(cid)) if cid.to_string() == unixfs1
+        )));
+    }
+
     fn assert_edges(expected: &[(&str, &str)], actual: &[(String, String)]) {
         let expected: HashSet<_> = expected.iter().map(|&(a, b)| (a, b)).collect();
 
@@ -1,21 +1,41 @@
 use crate::error::Error;
-use crate::repo::paths::{block_path, filestem_to_block_cid};
-use crate::repo::{BlockPut, BlockStore};
+use crate::repo::paths::{
+    block_path, filestem_to_block_cid, filestem_to_multihash_cid, multihash_block_path,
+};
+use crate::repo::{multihash_cids, BlockEntry, BlockPut, BlockStore};
 use crate::Block;
 use async_trait::async_trait;
 use futures::stream::{self, BoxStream};
-use futures::{StreamExt, TryFutureExt, TryStreamExt};
-use libipld::Cid;
-use std::io::{self, ErrorKind, Read};
-use std::path::PathBuf;
+use futures::{StreamExt, TryStreamExt};
+use libipld::multihash::Multihash;
+use libipld::{Cid, IpldCodec};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::fs;
 use tokio::sync::RwLock;
 use tokio_stream::wrappers::ReadDirStream;
 
+/// Contents of the `SHARDING` file of the go-ipfs flatfs datastore for the sharding used by
+/// [`FsLayout::Multihash`].
+const SHARDING: &str = "/repo/flatfs/shard/v1/next-to-last/2\n";
+
+/// How the files of the blocks are named in a [`FsBlockStore`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FsLayout {
+    /// Files are named after the base32 CIDv1 of the block, see `block_path`.
+    #[default]
+    Cid,
+    /// Files are named after the base32 multihash of the block, as in the `blocks` directory of
+    /// a go-ipfs repository, see `multihash_block_path`. As the codec is not stored, blocks are
+    /// listed as CIDv1 with the raw codec.
+    Multihash,
+}
+
 /// File system backed block store.
 ///
-/// For information on path mangling, please see `block_path` and `filestem_to_block_cid`.
+/// For information on path mangling, please see `block_path` and `filestem_to_block_cid`, or
+/// `multihash_block_path` and `filestem_to_multihash_cid` for [`FsLayout::Multihash`].
 #[derive(Debug)]
 pub struct FsBlockStore {
     inner: Arc<RwLock<FsBlockStoreInner>>,
@@ -24,22 +44,57 @@ pub struct FsBlockStore {
 #[derive(Debug)]
 struct FsBlockStoreInner {
     path: PathBuf,
+    layout: FsLayout,
+    read_only: bool,
+    /// Size and count of the stored blocks, built on first use.
+    stats: Option<BlockStats>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct BlockStats {
+    size: usize,
+    count: usize,
 }
 
 impl FsBlockStore {
     pub fn new(path: PathBuf) -> Self {
-        let inner = Arc::new(RwLock::new(FsBlockStoreInner { path }));
+        Self::with_layout(path, FsLayout::default())
+    }
+
+    pub fn with_layout(path: PathBuf, layout: FsLayout) -> Self {
+        Self::create(path, layout, false)
+    }
+
+    /// Opens an existing directory of blocks without ever modifying it, e.g. the `blocks`
+    /// directory of a go-ipfs repository with [`FsLayout::Multihash`]. Putting new blocks and
+    /// removing blocks fails.
+    pub fn read_only(path: PathBuf, layout: FsLayout) -> Self {
+        Self::create(path, layout, true)
+    }
+
+    fn create(path: PathBuf, layout: FsLayout, read_only: bool) -> Self {
+        let inner = Arc::new(RwLock::new(FsBlockStoreInner {
+            path,
+            layout,
+            read_only,
+            stats: None,
+        }));
 
         FsBlockStore { inner }
     }
+
+    /// Returns the number of stored blocks.
+    pub async fn count(&self) -> Result<usize, Error> {
+        let inner = &mut *self.inner.write().await;
+        Ok(inner.stats().await?.count)
+    }
 }
 
 #[async_trait]
 impl BlockStore for FsBlockStore {
     async fn init(&self) -> Result<(), Error> {
         let inner = &*self.inner.read().await;
-        fs::create_dir_all(inner.path.clone()).await?;
-        Ok(())
+        inner.init().await
     }
 
     async fn open(&self) -> Result<(), Error> {
@@ -51,6 +106,22 @@ impl BlockStore for FsBlockStore {
         inner.contains(cid).await
     }
 
+    async fn cids_by_multihash(&self, hash: &Multihash) -> Result<Vec<Cid>, Error> {
+        let inner = &*self.inner.read().await;
+        let cids = match inner.layout {
+            // the file is named after the multihash, whatever the codec
+            FsLayout::Multihash => vec![Cid::new_v1(IpldCodec::Raw.into(), *hash)],
+            FsLayout::Cid => multihash_cids(hash),
+        };
+        let mut found = vec![];
+        for cid in cids {
+            if inner.contains(&cid).await? {
+                found.push(cid);
+            }
+        }
+        Ok(found)
+    }
+
     async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error> {
         let inner = &*self.inner.read().await;
         inner.get(cid).await
@@ -62,8 +133,8 @@ impl BlockStore for FsBlockStore {
     }
 
     async fn total_size(&self) -> Result<usize, Error> {
-        let inner = &*self.inner.read().await;
-        Ok(inner.total_size().await)
+        let inner = &mut *self.inner.write().await;
+        Ok(inner.stats().await?.size)
     }
 
     //TODO: Allow multiple puts without holding a lock. We could probably hold a read lock instead
@@ -73,6 +144,29 @@ impl BlockStore for FsBlockStore {
         inner.put(block).await
     }
 
+    async fn put_many(&self, blocks: Vec<Block>) -> Result<Vec<(Cid, BlockPut)>, Error> {
+        let inner = &mut *self.inner.write().await;
+        let mut puts = Vec::with_capacity(blocks.len());
+        for block in blocks {
+            puts.push(inner.put(block).await?);
+        }
+        Ok(puts)
+    }
+
+    async fn get_many(&self, cids: &[Cid]) -> Result<Vec<Option<Block>>, Error> {
+        let inner = &*self.inner.read().await;
+        let mut blocks = Vec::with_capacity(cids.len());
+        for cid in cids {
+            blocks.push(inner.get(cid).await?);
+        }
+        Ok(blocks)
+    }
+
+    async fn replace(&self, block: Block) -> Result<(), Error> {
+        let inner = &mut *self.inner.write().await;
+        inner.replace(block).await
+    }
+
     async fn remove(&self, cid: &Cid) -> Result<(), Error> {
         let inner = &mut *self.inner.write().await;
         inner.remove(cid).await
@@ -82,12 +176,11 @@ impl BlockStore for FsBlockStore {
         let inner = self.inner.clone();
         let stream = async_stream::stream! {
             let inner = &mut *inner.write().await;
-            let path = inner.path.clone();
-            for await cid in blocks
-                .map(move |cid| (cid, block_path(path.clone(), &cid)))
-                .filter_map(|(cid, path)| async move { fs::remove_file(path).await.ok().map(|_| cid) }) {
+            for await cid in blocks {
+                if inner.remove(&cid).await.is_ok() {
                     yield cid;
                 }
+            }
         };
 
         stream.boxed()
@@ -97,11 +190,48 @@ impl BlockStore for FsBlockStore {
         let inner = &*self.inner.read().await;
         inner.list().await
     }
+
+    async fn list_entries(&self) -> BoxStream<'static, BlockEntry> {
+        let inner = &*self.inner.read().await;
+        inner.list_entries().await
+    }
 }
 
 impl FsBlockStoreInner {
+    fn block_path(&self, cid: &Cid) -> PathBuf {
+        match self.layout {
+            FsLayout::Cid => block_path(self.path.clone(), cid),
+            FsLayout::Multihash => multihash_block_path(self.path.clone(), cid),
+        }
+    }
+
+    async fn init(&self) -> Result<(), Error> {
+        if self.read_only {
+            if !fs::metadata(&self.path).await?.is_dir() {
+                anyhow::bail!("{} is not a directory", self.path.display());
+            }
+        } else {
+            fs::create_dir_all(&self.path).await?;
+        }
+
+        if self.layout == FsLayout::Multihash {
+            let sharding = self.path.join("SHARDING");
+            match fs::read_to_string(&sharding).await {
+                Ok(found) if found.trim_end() == SHARDING.trim_end() => {}
+                Ok(found) => anyhow::bail!("unsupported sharding {:?}", found.trim_end()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound && !self.read_only => {
+                    fs::write(&sharding, SHARDING).await?;
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+
     async fn contains(&self, cid: &Cid) -> Result<bool, Error> {
-        let path = block_path(self.path.clone(), cid);
+        let path = self.block_path(cid);
 
         let metadata = match fs::metadata(path).await {
             Ok(m) => m,
@@ -113,7 +243,7 @@ impl FsBlockStoreInner {
     }
 
     async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error> {
-        let path = block_path(self.path.clone(), cid);
+        let path = self.block_path(cid);
 
         let cid = *cid;
 
@@ -139,37 +269,44 @@ impl FsBlockStoreInner {
     }
 
     async fn put(&mut self, block: Block) -> Result<(Cid, BlockPut), Error> {
-        let target_path = block_path(self.path.clone(), block.cid());
         let cid = *block.cid();
 
-        let je = tokio::task::spawn_blocking(move || {
+        if self.contains(&cid).await? {
+            trace!("block exists");
+            return Ok((cid, BlockPut::Existed));
+        }
+
+        if self.read_only {
+            anyhow::bail!("blockstore at {} is read-only", self.path.display());
+        }
+
+        let target_path = self.block_path(&cid);
+        let written = block.data().len();
+
+        tokio::task::spawn_blocking(move || {
             let sharded = target_path
                 .parent()
                 .expect("we already have at least the shard parent");
 
             std::fs::create_dir_all(sharded)?;
 
-            let target = std::fs::OpenOptions::new()
-                .write(true)
-                .create_new(true)
-                .open(&target_path)?;
-
             let temp_path = target_path.with_extension("tmp");
 
-            match write_through_tempfile(target, &target_path, temp_path, block.data()) {
+            // puts are serialized by the lock, so nothing else writes to the temporary file
+            match write_through_tempfile(&target_path, &temp_path, block.data()) {
                 Ok(()) => {
                     trace!("successfully wrote the block");
-                    Ok::<_, std::io::Error>(Ok(block.data().len()))
+                    Ok(())
                 }
                 Err(e) => {
-                    match std::fs::remove_file(&target_path) {
-                        Ok(_) => debug!("removed partially written {:?}", target_path),
+                    match std::fs::remove_file(&temp_path) {
+                        Ok(_) => debug!("removed partially written {:?}", temp_path),
                         Err(removal) => warn!(
                             "failed to remove partially written {:?}: {}",
-                            target_path, removal
+                            temp_path, removal
                         ),
                     }
-                    Ok(Err(e))
+                    Err(e)
                 }
             }
         })
@@ -177,31 +314,61 @@ impl FsBlockStoreInner {
         .map_err(|e| {
             error!("blocking put task error: {}", e);
             e
-        })?;
+        })??;
 
-        match je {
-            Ok(Ok(written)) => {
-                trace!(bytes = written, "block writing succeeded");
-                Ok((cid, BlockPut::NewBlock))
-            }
-            Ok(Err(e)) => {
-                trace!("write failed but hopefully the target was removed");
+        trace!(bytes = written, "block writing succeeded");
+
+        if let Some(stats) = self.stats.as_mut() {
+            stats.size += written;
+            stats.count += 1;
+        }
+
+        Ok((cid, BlockPut::NewBlock))
+    }
+
+    /// Writes the block over the stored one, which is renamed over and never missing.
+    async fn replace(&mut self, block: Block) -> Result<(), Error> {
+        if self.read_only {
+            anyhow::bail!("blockstore at {} is read-only", self.path.display());
+        }
+
+        let cid = *block.cid();
+        let previous = match self.contains(&cid).await? {
+            true => self.size(&[cid]).await,
+            false => None,
+        };
+        let target_path = self.block_path(&cid);
+        let written = block.data().len();
 
-                Err(Error::new(e))
+        tokio::task::spawn_blocking(move || {
+            let sharded = target_path
+                .parent()
+                .expect("we already have at least the shard parent");
+            std::fs::create_dir_all(sharded)?;
+
+            let temp_path = target_path.with_extension("tmp");
+            let result = write_through_tempfile(&target_path, &temp_path, block.data());
+            if result.is_err() {
+                let _ = std::fs::remove_file(&temp_path);
             }
-            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
-                trace!("block exist: {}", e);
-                Ok((cid, BlockPut::Existed))
+            result
+        })
+        .await??;
+
+        if let Some(stats) = self.stats.as_mut() {
+            stats.size = stats.size - previous.unwrap_or_default() + written;
+            if previous.is_none() {
+                stats.count += 1;
             }
-            Err(e) => Err(Error::new(e)),
         }
+        Ok(())
     }
 
     async fn size(&self, cids: &[Cid]) -> Option<usize> {
         let mut block_sizes = 0;
 
         for cid in cids {
-            let path = block_path(self.path.clone(), cid);
+            let path = self.block_path(cid);
             if let Ok(size) = fs::metadata(path).await.map(|m| m.len() as usize) {
                 block_sizes += size;
             }
@@ -210,34 +377,49 @@ impl FsBlockStoreInner {
         Some(block_sizes)
     }
 
-    async fn total_size(&self) -> usize {
-        self.list_stream()
-            .and_then(|blocks| async move {
-                let list = blocks
-                    .try_filter_map(|(_, path)| async move {
-                        let meta = fs::metadata(path).await?;
-                        Ok(Some(meta.len()))
-                    })
-                    .try_collect::<Vec<_>>()
-                    .await
-                    .unwrap_or_default();
-                Ok(list.into_iter().sum::<u64>() as usize)
+    /// Returns the size and count of the blocks, reading through the directory the first time.
+    async fn stats(&mut self) -> Result<BlockStats, Error> {
+        if let Some(stats) = self.stats {
+            return Ok(stats);
+        }
+
+        let stats = self
+            .list_stream()
+            .await?
+            .try_fold(BlockStats::default(), |mut stats, (_, path)| async move {
+                stats.size += fs::metadata(path).await?.len() as usize;
+                stats.count += 1;
+                Ok(stats)
             })
-            .await
-            .unwrap_or_default()
+            .await?;
+
+        self.stats = Some(stats);
+        Ok(stats)
     }
 
     async fn remove(&mut self, cid: &Cid) -> Result<(), Error> {
-        let path = block_path(self.path.clone(), cid);
+        if self.read_only {
+            anyhow::bail!("blockstore at {} is read-only", self.path.display());
+        }
+
+        let path = self.block_path(cid);
         trace!(cid = %cid, "removing block after synchronizing");
-        fs::remove_file(path).await.map_err(anyhow::Error::from)
+        let size = fs::metadata(&path).await?.len() as usize;
+        fs::remove_file(path).await?;
+
+        if let Some(stats) = self.stats.as_mut() {
+            stats.size = stats.size.saturating_sub(size);
+            stats.count = stats.count.saturating_sub(1);
+        }
+
+        Ok(())
     }
 
     async fn list_stream(
         &self,
     ) -> Result<BoxStream<'static, Result<(Cid, PathBuf), io::Error>>, Error> {
         let stream = ReadDirStream::new(fs::read_dir(&self.path).await?);
-        let path = self.path.clone();
+        let layout = self.layout;
         Ok(stream
             .try_filter_map(|d| async move {
                 // map over the shard directories
@@ -250,24 +432,21 @@ impl FsBlockStoreInner {
             // flatten each; there could be unordered execution pre-flattening
             .try_flatten()
             // convert the paths ending in ".data" into cid
-            .try_filter_map(|d| {
+            .try_filter_map(move |d| {
+                let path = d.path();
                 let name = d.file_name();
-                let path: &std::path::Path = name.as_ref();
+                let name: &Path = name.as_ref();
 
-                futures::future::ready(if path.extension() != Some("data".as_ref()) {
+                futures::future::ready(if name.extension() != Some("data".as_ref()) {
                     Ok(None)
                 } else {
-                    let maybe_cid = filestem_to_block_cid(path.file_stem());
-                    Ok(maybe_cid)
+                    let maybe_cid = match layout {
+                        FsLayout::Cid => filestem_to_block_cid(name.file_stem()),
+                        FsLayout::Multihash => filestem_to_multihash_cid(name.file_stem()),
+                    };
+                    Ok(maybe_cid.map(|cid| (cid, path)))
                 })
             })
-            .try_filter_map(move |cid| {
-                let path = path.clone();
-                async move {
-                    let path = block_path(path, &cid);
-                    Ok(Some((cid, path)))
-                }
-            })
             .boxed())
     }
 
@@ -278,10 +457,25 @@ impl FsBlockStoreInner {
             .filter_map(|cid| futures::future::ready(cid.ok()))
             .boxed()
     }
+
+    /// Lists the blocks with the size and modification time of their files.
+    async fn list_entries(&self) -> BoxStream<'static, BlockEntry> {
+        let stream = self.list_stream().await.unwrap_or(stream::empty().boxed());
+        stream
+            .filter_map(|entry| async move {
+                let (cid, path) = entry.ok()?;
+                let metadata = fs::metadata(&path).await.ok()?;
+                Some(BlockEntry {
+                    cid,
+                    size: metadata.len() as usize,
+                    written_at: metadata.modified().ok(),
+                })
+            })
+            .boxed()
+    }
 }
 
 fn write_through_tempfile(
-    target: std::fs::File,
     target_path: impl AsRef<std::path::Path>,
     temp_path: impl AsRef<std::path::Path>,
     data: &[u8],
@@ -301,8 +495,8 @@ fn write_through_tempfile(
     temp.sync_all()?;
 
     drop(temp);
-    drop(target);
 
+    // the block only becomes visible once it has been completely written
     std::fs::rename(temp_path, target_path)?;
 
     // FIXME: there should be a directory fsync here as well
@@ -473,6 +667,180 @@ mod tests {
         (writes, existing)
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_put_and_get_of_same_block() {
+        let mut tmp = temp_dir();
+        tmp.push("concurrent_put_and_get");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let store = Arc::new(FsBlockStore::new(tmp.clone()));
+        store.init().await.unwrap();
+
+        let data = vec![7u8; 256 * 1024];
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let block = Block::new(cid, data).unwrap();
+
+        let puts = (0..4)
+            .map(|_| {
+                let store = Arc::clone(&store);
+                let block = block.clone();
+                tokio::spawn(async move { store.put(block).await.unwrap().1 })
+            })
+            .collect::<Vec<_>>();
+
+        // readers either find nothing or the complete block, never a partially written one
+        let gets = (0..4)
+            .map(|_| {
+                let store = Arc::clone(&store);
+                let block = block.clone();
+                tokio::spawn(async move {
+                    loop {
+                        match store.get(&cid).await.unwrap() {
+                            Some(found) => {
+                                assert_eq!(found, block);
+                                break;
+                            }
+                            None => tokio::task::yield_now().await,
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut new_blocks = 0;
+        for put in puts {
+            if put.await.unwrap() == BlockPut::NewBlock {
+                new_blocks += 1;
+            }
+        }
+        for get in gets {
+            get.await.unwrap();
+        }
+
+        assert_eq!(new_blocks, 1);
+        assert_eq!(store.count().await.unwrap(), 1);
+        assert_eq!(store.total_size().await.unwrap(), block.data().len());
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    #[tokio::test]
+    async fn open_prepopulated_go_ipfs_blocks() {
+        let mut tmp = temp_dir();
+        tmp.push("prepopulated_go_ipfs_blocks");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        // laid out like the blocks directory of a go-ipfs repository
+        let cid = Cid::try_from("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap();
+        let data = hex!("0a0d08021207666f6f6261720a1807");
+        std::fs::create_dir_all(tmp.join("YM")).unwrap();
+        std::fs::write(tmp.join("SHARDING"), SHARDING).unwrap();
+        std::fs::write(tmp.join("_README"), "flatfs").unwrap();
+        std::fs::write(
+            tmp.join("YM/CIQDDQ6VOCANQRR2HRR3FER56WQ5ICWXU47K4WQUV5MEEE7F6UCKYMY.data"),
+            data,
+        )
+        .unwrap();
+
+        let store = FsBlockStore::read_only(tmp.clone(), FsLayout::Multihash);
+        store.init().await.unwrap();
+
+        assert!(store.contains(&cid).await.unwrap());
+        assert_eq!(
+            store.get(&cid).await.unwrap(),
+            Some(Block::new(cid, data.to_vec()).unwrap())
+        );
+
+        let listed = store.list().await.collect::<Vec<_>>().await;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].hash(), cid.hash());
+
+        assert_eq!(store.count().await.unwrap(), 1);
+        assert_eq!(store.total_size().await.unwrap(), data.len());
+
+        // existing blocks can be put, but nothing is written or removed
+        let (_, put) = store
+            .put(Block::new(cid, data.to_vec()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(put, BlockPut::Existed);
+
+        let other = b"1".to_vec();
+        let other_cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&other));
+        store
+            .put(Block::new(other_cid, other).unwrap())
+            .await
+            .unwrap_err();
+        store.remove(&cid).await.unwrap_err();
+        assert!(store.contains(&cid).await.unwrap());
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    #[tokio::test]
+    async fn multihash_layout_is_readable_by_codec() {
+        let mut tmp = temp_dir();
+        tmp.push("multihash_layout");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let store = FsBlockStore::with_layout(tmp.clone(), FsLayout::Multihash);
+        store.init().await.unwrap();
+
+        let cid = Cid::try_from("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap();
+        let data = hex!("0a0d08021207666f6f6261720a1807");
+        store
+            .put(Block::new(cid, data.to_vec()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(tmp.join("SHARDING")).unwrap(),
+            SHARDING
+        );
+
+        // the file is found through the multihash regardless of the codec of the cid
+        let raw = Cid::new_v1(IpldCodec::Raw.into(), *cid.hash());
+        assert!(store.contains(&raw).await.unwrap());
+        assert_eq!(store.count().await.unwrap(), 1);
+
+        store.remove(&raw).await.unwrap();
+        assert!(!store.contains(&cid).await.unwrap());
+        assert_eq!(store.count().await.unwrap(), 0);
+        assert_eq!(store.total_size().await.unwrap(), 0);
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    #[tokio::test]
+    async fn blocks_are_found_by_multihash() {
+        for layout in [FsLayout::Cid, FsLayout::Multihash] {
+            let mut tmp = temp_dir();
+            tmp.push(format!("by_multihash_{layout:?}"));
+            std::fs::remove_dir_all(&tmp).ok();
+
+            let store = FsBlockStore::with_layout(tmp.clone(), layout);
+            store.init().await.unwrap();
+
+            let cid = Cid::try_from("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap();
+            let data = hex!("0a0d08021207666f6f6261720a1807");
+            store
+                .put(Block::new(cid, data.to_vec()).unwrap())
+                .await
+                .unwrap();
+
+            let found = store.cids_by_multihash(cid.hash()).await.unwrap();
+            assert!(!found.is_empty(), "{layout:?}");
+            for cid in found {
+                assert_eq!(store.get(&cid).await.unwrap().unwrap().data(), data);
+            }
+
+            let other = Code::Sha2_256.digest(b"not stored");
+            assert!(store.cids_by_multihash(&other).await.unwrap().is_empty());
+
+            std::fs::remove_dir_all(&tmp).ok();
+        }
+    }
+
     #[tokio::test]
     async fn remove() {
         // FIXME: why not tempdir?
@@ -1,6 +1,6 @@
 //! Volatile memory backed repo
 use crate::error::Error;
-use crate::repo::{BlockPut, BlockStore};
+use crate::repo::{BlockEntry, BlockPut, BlockStore};
 use crate::Block;
 use async_trait::async_trait;
 use futures::stream::{self, BoxStream};
@@ -98,6 +98,35 @@ impl BlockStore for MemBlockStore {
         }
     }
 
+    async fn put_many(&self, blocks: Vec<Block>) -> Result<Vec<(Cid, BlockPut)>, Error> {
+        let inner = &mut *self.inner.write().await;
+        let puts = blocks
+            .into_iter()
+            .map(|block| {
+                let cid = *block.cid();
+                match inner.blocks.insert(cid, block) {
+                    Some(_) => (cid, BlockPut::Existed),
+                    None => (cid, BlockPut::NewBlock),
+                }
+            })
+            .collect();
+        Ok(puts)
+    }
+
+    async fn get_many(&self, cids: &[Cid]) -> Result<Vec<Option<Block>>, Error> {
+        let inner = &*self.inner.read().await;
+        Ok(cids
+            .iter()
+            .map(|cid| inner.blocks.get(cid).cloned())
+            .collect())
+    }
+
+    async fn replace(&self, block: Block) -> Result<(), Error> {
+        let inner = &mut *self.inner.write().await;
+        inner.blocks.insert(*block.cid(), block);
+        Ok(())
+    }
+
     async fn remove(&self, cid: &Cid) -> Result<(), Error> {
         let inner = &mut *self.inner.write().await;
 
@@ -126,6 +155,20 @@ impl BlockStore for MemBlockStore {
         let inner = &*self.inner.read().await;
         stream::iter(inner.blocks.keys().copied().collect::<Vec<_>>()).boxed()
     }
+
+    async fn list_entries(&self) -> BoxStream<'static, BlockEntry> {
+        let inner = &*self.inner.read().await;
+        let entries = inner
+            .blocks
+            .iter()
+            .map(|(cid, block)| BlockEntry {
+                cid: *cid,
+                size: block.data().len(),
+                written_at: None,
+            })
+            .collect::<Vec<_>>();
+        stream::iter(entries).boxed()
+    }
 }
 
 #[cfg(test)]
@@ -192,4 +235,35 @@ mod tests {
             assert!(mem_store.contains(cid).await.unwrap());
         }
     }
+
+    #[tokio::test]
+    async fn test_mem_blockstore_batch() {
+        let tmp = std::env::temp_dir();
+        let store = MemBlockStore::new(tmp);
+
+        let blocks = [b"1", b"2", b"1"]
+            .iter()
+            .map(|data| {
+                let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(*data));
+                Block::new(cid, data.to_vec()).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let puts = store.put_many(blocks.clone()).await.unwrap();
+        let puts = puts.into_iter().map(|(_, put)| put).collect::<Vec<_>>();
+        assert_eq!(
+            puts,
+            vec![BlockPut::NewBlock, BlockPut::NewBlock, BlockPut::Existed]
+        );
+
+        let missing = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(b"3"));
+        let got = store
+            .get_many(&[*blocks[1].cid(), missing, *blocks[0].cid()])
+            .await
+            .unwrap();
+        assert_eq!(
+            got,
+            vec![Some(blocks[1].clone()), None, Some(blocks[0].clone())]
+        );
+    }
 }
@@ -1,2 +1,4 @@
 pub mod flatfs;
 pub mod memory;
+#[cfg(feature = "redb_data_store")]
+pub mod redb;
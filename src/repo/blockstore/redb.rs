@@ -0,0 +1,347 @@
+//! Blocks kept in the database of a redb datastore, see
+//! [`RedbDataStore::with_blocks`](crate::repo::datastore::redb::RedbDataStore::with_blocks).
+use crate::error::Error;
+use crate::repo::datastore::redb::{SharedDatabase, BLOCKTABLE};
+use crate::repo::{BlockEntry, BlockPut, BlockStore};
+use crate::Block;
+use async_trait::async_trait;
+use futures::stream::{BoxStream, StreamExt};
+use libipld::Cid;
+use redb::ReadableTable;
+use std::convert::TryFrom;
+
+/// Blockstore sharing the database of a redb datastore, created with
+/// [`RedbDataStore::block_store`](crate::repo::datastore::redb::RedbDataStore::block_store).
+#[derive(Debug, Clone)]
+pub struct RedbBlockStore {
+    db: SharedDatabase,
+}
+
+impl RedbBlockStore {
+    pub(crate) fn new(db: SharedDatabase) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl BlockStore for RedbBlockStore {
+    async fn init(&self) -> Result<(), Error> {
+        self.db.init().await
+    }
+
+    async fn open(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn contains(&self, cid: &Cid) -> Result<bool, Error> {
+        let key = cid.to_bytes();
+        let db = self.db.get();
+        tokio::task::spawn_blocking(move || {
+            let read_tx = db.begin_read()?;
+            let table = read_tx.open_table(BLOCKTABLE)?;
+            let item = table.get(key.as_slice())?;
+            Ok::<_, Error>(item.is_some())
+        })
+        .await?
+    }
+
+    async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        let cid = *cid;
+        let db = self.db.get();
+        tokio::task::spawn_blocking(move || {
+            let read_tx = db.begin_read()?;
+            let table = read_tx.open_table(BLOCKTABLE)?;
+            let item = table.get(cid.to_bytes().as_slice())?;
+            item.map(|data| Block::new(cid, data.value().to_vec()))
+                .transpose()
+                .map_err(Error::from)
+        })
+        .await?
+    }
+
+    async fn size(&self, cids: &[Cid]) -> Result<Option<usize>, Error> {
+        let keys = cids.iter().map(Cid::to_bytes).collect::<Vec<_>>();
+        let db = self.db.get();
+        tokio::task::spawn_blocking(move || {
+            let read_tx = db.begin_read()?;
+            let table = read_tx.open_table(BLOCKTABLE)?;
+            let mut size = 0;
+            for key in keys {
+                if let Some(data) = table.get(key.as_slice())? {
+                    size += data.value().len();
+                }
+            }
+            Ok::<_, Error>(Some(size))
+        })
+        .await?
+    }
+
+    async fn total_size(&self) -> Result<usize, Error> {
+        let db = self.db.get();
+        tokio::task::spawn_blocking(move || {
+            let read_tx = db.begin_read()?;
+            let table = read_tx.open_table(BLOCKTABLE)?;
+            let mut size = 0;
+            for item in table.iter()? {
+                let (_, data) = item?;
+                size += data.value().len();
+            }
+            Ok::<_, Error>(size)
+        })
+        .await?
+    }
+
+    async fn put(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
+        let db = self.db.get();
+        tokio::task::spawn_blocking(move || {
+            let key = block.cid().to_bytes();
+            let tx = db.begin_write()?;
+            let put = {
+                let mut table = tx.open_table(BLOCKTABLE)?;
+                if table.get(key.as_slice())?.is_some() {
+                    BlockPut::Existed
+                } else {
+                    table.insert(key.as_slice(), block.data())?;
+                    BlockPut::NewBlock
+                }
+            };
+            tx.commit()?;
+            Ok::<_, Error>((*block.cid(), put))
+        })
+        .await?
+    }
+
+    async fn put_many(&self, blocks: Vec<Block>) -> Result<Vec<(Cid, BlockPut)>, Error> {
+        let db = self.db.get();
+        tokio::task::spawn_blocking(move || {
+            // written in a single transaction
+            let tx = db.begin_write()?;
+            let mut puts = Vec::with_capacity(blocks.len());
+            {
+                let mut table = tx.open_table(BLOCKTABLE)?;
+                for block in blocks {
+                    let key = block.cid().to_bytes();
+                    if table.get(key.as_slice())?.is_some() {
+                        puts.push((*block.cid(), BlockPut::Existed));
+                    } else {
+                        table.insert(key.as_slice(), block.data())?;
+                        puts.push((*block.cid(), BlockPut::NewBlock));
+                    }
+                }
+            }
+            tx.commit()?;
+            Ok::<_, Error>(puts)
+        })
+        .await?
+    }
+
+    async fn get_many(&self, cids: &[Cid]) -> Result<Vec<Option<Block>>, Error> {
+        let cids = cids.to_vec();
+        let db = self.db.get();
+        tokio::task::spawn_blocking(move || {
+            let read_tx = db.begin_read()?;
+            let table = read_tx.open_table(BLOCKTABLE)?;
+            let mut blocks = Vec::with_capacity(cids.len());
+            for cid in cids {
+                let item = table.get(cid.to_bytes().as_slice())?;
+                blocks.push(
+                    item.map(|data| Block::new(cid, data.value().to_vec()))
+                        .transpose()?,
+                );
+            }
+            Ok::<_, Error>(blocks)
+        })
+        .await?
+    }
+
+    async fn remove(&self, cid: &Cid) -> Result<(), Error> {
+        let key = cid.to_bytes();
+        let db = self.db.get();
+        tokio::task::spawn_blocking(move || {
+            let tx = db.begin_write()?;
+            let removed = tx.open_table(BLOCKTABLE)?.remove(key.as_slice())?.is_some();
+            tx.commit()?;
+            match removed {
+                true => Ok(()),
+                false => Err(std::io::Error::from(std::io::ErrorKind::NotFound).into()),
+            }
+        })
+        .await?
+    }
+
+    async fn replace(&self, block: Block) -> Result<(), Error> {
+        let db = self.db.get();
+        tokio::task::spawn_blocking(move || {
+            let key = block.cid().to_bytes();
+            let tx = db.begin_write()?;
+            tx.open_table(BLOCKTABLE)?
+                .insert(key.as_slice(), block.data())?;
+            tx.commit()?;
+            Ok::<_, Error>(())
+        })
+        .await?
+    }
+
+    async fn remove_many(&self, blocks: BoxStream<'static, Cid>) -> BoxStream<'static, Cid> {
+        let db = self.db.get();
+        let stream = async_stream::stream! {
+            let cids = blocks.collect::<Vec<_>>().await;
+
+            // removed in a single transaction
+            let removed = tokio::task::spawn_blocking(move || {
+                let tx = db.begin_write()?;
+                let mut removed = Vec::with_capacity(cids.len());
+                {
+                    let mut table = tx.open_table(BLOCKTABLE)?;
+                    for cid in cids {
+                        if table.remove(cid.to_bytes().as_slice())?.is_some() {
+                            removed.push(cid);
+                        }
+                    }
+                }
+                tx.commit()?;
+                Ok::<_, Error>(removed)
+            })
+            .await;
+
+            match removed {
+                Ok(Ok(removed)) => {
+                    for cid in removed {
+                        yield cid;
+                    }
+                }
+                Ok(Err(e)) => error!("failed to remove blocks: {}", e),
+                Err(e) => error!("blocking remove task error: {}", e),
+            }
+        };
+
+        stream.boxed()
+    }
+
+    async fn list(&self) -> BoxStream<'static, Cid> {
+        let db = self.db.get();
+        let cids = tokio::task::spawn_blocking(move || {
+            let read_tx = db.begin_read()?;
+            let table = read_tx.open_table(BLOCKTABLE)?;
+            let mut cids = Vec::new();
+            for item in table.iter()? {
+                let (key, _) = item?;
+                if let Ok(cid) = Cid::try_from(key.value()) {
+                    cids.push(cid);
+                }
+            }
+            Ok::<_, Error>(cids)
+        })
+        .await;
+
+        let cids = match cids {
+            Ok(Ok(cids)) => cids,
+            Ok(Err(e)) => {
+                error!("failed to list blocks: {}", e);
+                Vec::new()
+            }
+            Err(e) => {
+                error!("blocking list task error: {}", e);
+                Vec::new()
+            }
+        };
+
+        futures::stream::iter(cids).boxed()
+    }
+
+    async fn list_entries(&self) -> BoxStream<'static, BlockEntry> {
+        let db = self.db.get();
+        let entries = tokio::task::spawn_blocking(move || {
+            let read_tx = db.begin_read()?;
+            let table = read_tx.open_table(BLOCKTABLE)?;
+            let mut entries = Vec::new();
+            for item in table.iter()? {
+                let (key, value) = item?;
+                if let Ok(cid) = Cid::try_from(key.value()) {
+                    entries.push(BlockEntry {
+                        cid,
+                        size: value.value().len(),
+                        written_at: None,
+                    });
+                }
+            }
+            Ok::<_, Error>(entries)
+        })
+        .await;
+
+        let entries = match entries {
+            Ok(Ok(entries)) => entries,
+            Ok(Err(e)) => {
+                error!("failed to list blocks: {}", e);
+                Vec::new()
+            }
+            Err(e) => {
+                error!("blocking list task error: {}", e);
+                Vec::new()
+            }
+        };
+
+        futures::stream::iter(entries).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::datastore::redb::RedbDataStore;
+    use crate::repo::DataStore;
+    use libipld::{
+        multihash::{Code, MultihashDigest},
+        IpldCodec,
+    };
+
+    #[tokio::test]
+    async fn shares_database_with_datastore() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let data_store = RedbDataStore::with_blocks(tmp.path().to_owned());
+        let block_store = data_store.block_store();
+
+        // either can be initialized first
+        BlockStore::init(&block_store).await.unwrap();
+        DataStore::init(&data_store).await.unwrap();
+
+        let data = b"1".to_vec();
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let block = Block::new(cid, data).unwrap();
+
+        assert!(!block_store.contains(&cid).await.unwrap());
+        let (_, put) = block_store.put(block.clone()).await.unwrap();
+        assert_eq!(put, BlockPut::NewBlock);
+        let (_, put) = block_store.put(block.clone()).await.unwrap();
+        assert_eq!(put, BlockPut::Existed);
+
+        assert_eq!(block_store.get(&cid).await.unwrap(), Some(block.clone()));
+        assert_eq!(block_store.total_size().await.unwrap(), 1);
+        assert_eq!(
+            block_store.list().await.collect::<Vec<_>>().await,
+            vec![cid]
+        );
+
+        let removed = block_store
+            .remove_many(futures::stream::iter(vec![cid]).boxed())
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(removed, vec![cid]);
+        assert!(!block_store.contains(&cid).await.unwrap());
+        block_store.remove(&cid).await.unwrap_err();
+
+        let puts = block_store
+            .put_many(vec![block.clone(), block.clone()])
+            .await
+            .unwrap();
+        assert_eq!(
+            puts,
+            vec![(cid, BlockPut::NewBlock), (cid, BlockPut::Existed)]
+        );
+        assert_eq!(
+            block_store.get_many(&[cid, cid]).await.unwrap(),
+            vec![Some(block.clone()), Some(block)]
+        );
+    }
+}
@@ -54,7 +54,7 @@ macro_rules! pinstore_interface_tests {
             use std::collections::HashMap;
             use std::convert::TryFrom;
             use $crate::repo::common_tests::DSTestContext;
-            use $crate::repo::{PinKind, PinMode, PinStore};
+            use $crate::repo::{PinKind, PinLabel, PinMode, PinStore};
 
             #[tokio::test]
             async fn pin_direct_twice_is_good() {
@@ -329,6 +329,60 @@ macro_rules! pinstore_interface_tests {
                 // go-ipfs it's different than path resolving
                 assert_eq!(e.to_string(), "already pinned recursively");
             }
+
+            #[tokio::test]
+            async fn label_follows_pin() {
+                let repo = DSTestContext::with($factory).await;
+
+                // root/nested/deeper: QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp
+                let root = Cid::try_from("QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp").unwrap();
+                let empty =
+                    Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap();
+
+                let label = PinLabel {
+                    name: Some("root".into()),
+                    metadata: [("origin".to_string(), "test".to_string())].into(),
+                    ..Default::default()
+                };
+
+                assert_eq!(repo.label(&root).await.unwrap(), None);
+                repo.set_label(&root, &label)
+                    .await
+                    .expect_err("cannot label unpinned");
+
+                repo.insert_direct_pin(&root).await.unwrap();
+                assert_eq!(repo.label(&root).await.unwrap(), Some(PinLabel::default()));
+
+                repo.set_label(&root, &label).await.unwrap();
+                repo.insert_direct_pin(&root).await.unwrap();
+                assert_eq!(repo.label(&root).await.unwrap().as_ref(), Some(&label));
+
+                // upgrading to recursive keeps the label
+                repo.insert_recursive_pin(
+                    &root,
+                    futures::stream::iter(vec![Ok(empty.clone())]).boxed(),
+                )
+                .await
+                .unwrap();
+                assert_eq!(repo.label(&root).await.unwrap().as_ref(), Some(&label));
+
+                assert_eq!(repo.label(&empty).await.unwrap(), None);
+                repo.set_label(&empty, &label)
+                    .await
+                    .expect_err("cannot label indirect");
+
+                repo.remove_recursive_pin(
+                    &root,
+                    futures::stream::iter(vec![Ok(empty.clone())]).boxed(),
+                )
+                .await
+                .unwrap();
+                assert_eq!(repo.label(&root).await.unwrap(), None);
+
+                // pinning again starts without a label
+                repo.insert_direct_pin(&root).await.unwrap();
+                assert_eq!(repo.label(&root).await.unwrap(), Some(PinLabel::default()));
+            }
         }
     };
 }
@@ -1,14 +1,17 @@
 //! Persistent filesystem backed pin store. See [`FsDataStore`] for more information.
 use crate::error::Error;
 use crate::repo::paths::{filestem_to_pin_cid, pin_path};
-use crate::repo::{DataStore, PinKind, PinMode, PinModeRequirement, PinStore, References};
+use crate::repo::{
+    DataStore, PinKind, PinLabel, PinMode, PinModeRequirement, PinStore, References,
+};
 use async_trait::async_trait;
 use core::convert::TryFrom;
 use futures::stream::TryStreamExt;
 use futures::StreamExt;
 use libipld::Cid;
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::fs;
 use tokio::sync::Semaphore;
@@ -16,8 +19,12 @@ use tokio_stream::{empty, wrappers::ReadDirStream};
 use tokio_util::either::Either;
 
 /// FsDataStore which uses the filesystem as a lockable key-value store. Maintains a similar to
-/// [`FsBlockStore`] sharded two level storage. Direct have empty files, recursive pins record all of
-/// their indirect descendants. Pin files are separated by their file extensions.
+/// [`FsBlockStore`] sharded two level storage. Direct pins have files holding their label (empty
+/// when unlabeled), recursive pins record their label and all of their indirect descendants. Pin
+/// files are separated by their file extensions.
+///
+/// The layout of the pin files is versioned by the `pins/version` file; older layouts are migrated
+/// on [`DataStore::init`].
 ///
 /// When modifying, single lock is used.
 ///
@@ -52,7 +59,7 @@ impl DataStore for FsDataStore {
     async fn init(&self) -> Result<(), Error> {
         // Although `pins` directory is created when inserting a data, is it not created when there are any attempts at listing the pins (thus causing to fail)
         tokio::fs::create_dir_all(&self.path.join("pins")).await?;
-        Ok(())
+        self.migrate_pins().await
     }
 
     async fn open(&self) -> Result<(), Error> {
@@ -135,6 +142,10 @@ impl PinStore for FsDataStore {
             }
 
             path.set_extension("direct");
+            if path.is_file() {
+                // keep the label of the existing pin
+                return Ok(());
+            }
             let f = std::fs::File::create(path)?;
             f.sync_all()?;
             Ok(())
@@ -164,35 +175,28 @@ impl PinStore for FsDataStore {
             let _entered = span.enter();
 
             std::fs::create_dir_all(path.parent().expect("shard parent has to exist"))?;
-            let count = set.len();
-            let cids = set.into_iter().map(|cid| cid.to_string());
 
-            path.set_extension("recursive_temp");
-
-            let file = std::fs::File::create(&path)?;
-
-            match sync_write_recursive_pin(file, count, cids) {
-                Ok(_) => {
-                    let final_path = path.with_extension("recursive");
-                    std::fs::rename(&path, final_path)?
-                }
-                Err(e) => {
-                    let removed = std::fs::remove_file(&path);
-
-                    match removed {
-                        Ok(_) => debug!("cleaned up ok after botched recursive pin write"),
-                        Err(e) => warn!("failed to cleanup temporary file: {}", e),
-                    }
-
-                    return Err(e);
-                }
-            }
+            // keep the label of an existing recursive pin, or carry over the one of a direct pin
+            path.set_extension("direct");
+            let label = match sync_read_recursive_pin(&path)? {
+                Some(record) => record.label,
+                None => match std::fs::read(&path) {
+                    Ok(bytes) => PinLabel::from_bytes(&bytes)?,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => PinLabel::default(),
+                    Err(e) => return Err(e.into()),
+                },
+            };
+
+            let record = RecursivePinRecord {
+                label,
+                references: set.into_iter().map(|cid| cid.to_string()).collect(),
+            };
+
+            sync_write_recursive_pin(&path, &record)?;
 
             // if we got this far, we have now written and renamed the recursive_temp into place.
             // now we just need to remove the direct pin, if it exists
 
-            path.set_extension("direct");
-
             match std::fs::remove_file(&path) {
                 Ok(_) => { /* good */ }
                 Err(e) if e.kind() == std::io::ErrorKind::NotFound => { /* good as well */ }
@@ -386,6 +390,59 @@ impl PinStore for FsDataStore {
         Box::pin(st)
     }
 
+    async fn set_label(&self, target: &Cid, label: &PinLabel) -> Result<(), Error> {
+        let bytes = label.to_bytes()?;
+        let label = label.clone();
+
+        let permit = Semaphore::acquire_owned(Arc::clone(&self.lock)).await?;
+
+        let mut path = pin_path(self.path.join("pins"), target);
+
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit; // move into threadpool thread
+            let _entered = span.enter();
+
+            match sync_read_direct_or_recursive(&mut path) {
+                Some(PinMode::Recursive) => match sync_read_recursive_pin(&path)? {
+                    Some(mut record) => {
+                        record.label = label;
+                        sync_write_recursive_pin(&path, &record)
+                    }
+                    None => Err(anyhow::anyhow!("not pinned or pinned indirectly")),
+                },
+                Some(PinMode::Direct) => {
+                    let temp = path.with_extension("direct_temp");
+                    std::fs::write(&temp, bytes)?;
+                    std::fs::File::open(&temp)?.sync_all()?;
+                    std::fs::rename(&temp, &path)?;
+                    Ok(())
+                }
+                _ => Err(anyhow::anyhow!("not pinned or pinned indirectly")),
+            }
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn label(&self, target: &Cid) -> Result<Option<PinLabel>, Error> {
+        let mut path = pin_path(self.path.join("pins"), target);
+
+        tokio::task::spawn_blocking(move || match sync_read_direct_or_recursive(&mut path) {
+            Some(PinMode::Recursive) => Ok(sync_read_recursive_pin(&path)?.map(|r| r.label)),
+            Some(PinMode::Direct) => match std::fs::read(&path) {
+                Ok(bytes) => PinLabel::from_bytes(&bytes).map(Some),
+                // removed while reading, see `read_recursively_pinned`
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            },
+            _ => Ok(None),
+        })
+        .await?
+    }
+
     async fn query(
         &self,
         ids: Vec<Cid>,
@@ -549,13 +606,12 @@ impl FsDataStore {
     }
 }
 
-/// Reads our serialized format for recusive pins, which is JSON array of stringified Cids.
+/// Reads our serialized format for recusive pins, see [`RecursivePinRecord`].
 ///
 /// On file not found error returns an empty Vec as if nothing had happened. This is because we
 /// do "atomic writes" and file removals are expected to be atomic, but reads don't synchronize on
 /// writes, so while iterating it's possible that recursive pin is removed.
 async fn read_recursively_pinned(path: PathBuf, cid: Cid) -> Result<(Cid, Vec<Cid>), Error> {
-    // our fancy format is a Vec<Cid> as json
     let mut path = pin_path(path, &cid);
     path.set_extension("recursive");
     let contents = match tokio::fs::read(path).await {
@@ -568,13 +624,14 @@ async fn read_recursively_pinned(path: PathBuf, cid: Cid) -> Result<(Cid, Vec<Ci
         Err(e) => return Err(e.into()),
     };
 
-    let cids: Vec<&str> = serde_json::from_slice(&contents)?;
+    let record: RecursivePinRecord = serde_json::from_slice(&contents)?;
 
     // returning a stream which is updated 8kB at time or such might be better, but this should
     // scale quite up as well.
-    let found = cids
-        .into_iter()
-        .map(Cid::try_from)
+    let found = record
+        .references
+        .iter()
+        .map(|s| Cid::try_from(s.as_str()))
         .collect::<Result<Vec<Cid>, _>>()?;
 
     trace!(cid = %cid, count = found.len(), "read indirect pins");
@@ -601,29 +658,149 @@ fn sync_read_direct_or_recursive(block_path: &mut PathBuf) -> Option<PinMode> {
     None
 }
 
-fn sync_write_recursive_pin(
-    file: std::fs::File,
-    count: usize,
-    cids: impl Iterator<Item = String>,
-) -> Result<(), Error> {
-    use serde::{ser::SerializeSeq, Serializer};
+/// Version of the pin file layout written by this implementation. The unversioned layout stored
+/// recursive pins as a bare JSON array of the stringified Cids of their descendants.
+const PIN_LAYOUT_VERSION: u32 = 1;
+
+/// Contents of a `.recursive` pin file: the label of the pin and the stringified Cids of all of its
+/// indirect descendants, as JSON.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RecursivePinRecord {
+    #[serde(default, skip_serializing_if = "PinLabel::is_empty")]
+    label: PinLabel,
+    references: Vec<String>,
+}
+
+/// Blocking version of [`read_recursively_pinned`] returning the whole record, or `None` if the
+/// pin file no longer exists.
+fn sync_read_recursive_pin(path: &Path) -> Result<Option<RecursivePinRecord>, Error> {
+    match std::fs::read(path.with_extension("recursive")) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Writes the `.recursive` pin file for the pin at `path` through a temporary file, so that
+/// readers only ever see complete records.
+fn sync_write_recursive_pin(path: &Path, record: &RecursivePinRecord) -> Result<(), Error> {
     use std::io::{BufWriter, Write};
-    let writer = BufWriter::new(file);
 
-    let mut serializer = serde_json::ser::Serializer::new(writer);
+    let temp_path = path.with_extension("recursive_temp");
 
-    let mut seq = serializer.serialize_seq(Some(count))?;
-    for cid in cids {
-        seq.serialize_element(&cid)?;
+    let write = || {
+        let file = std::fs::File::create(&temp_path)?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, record)?;
+        writer.flush()?;
+
+        let file = writer.into_inner()?;
+        file.sync_all()?;
+        Ok::<_, Error>(())
+    };
+
+    match write() {
+        Ok(_) => {
+            std::fs::rename(&temp_path, path.with_extension("recursive"))?;
+            Ok(())
+        }
+        Err(e) => {
+            let removed = std::fs::remove_file(&temp_path);
+
+            match removed {
+                Ok(_) => debug!("cleaned up ok after botched recursive pin write"),
+                Err(e) => warn!("failed to cleanup temporary file: {}", e),
+            }
+
+            Err(e)
+        }
     }
-    seq.end()?;
+}
+
+impl FsDataStore {
+    /// Upgrades pin files written in older layouts, see [`PIN_LAYOUT_VERSION`]. Pins written
+    /// before labels existed become unlabeled pins.
+    async fn migrate_pins(&self) -> Result<(), Error> {
+        let permit = Semaphore::acquire_owned(Arc::clone(&self.lock)).await?;
 
-    let mut writer = serializer.into_inner();
-    writer.flush()?;
+        let pins = self.path.join("pins");
 
-    let file = writer.into_inner()?;
-    file.sync_all()?;
-    Ok(())
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit; // move into threadpool thread
+            let _entered = span.enter();
+
+            let version_path = pins.join("version");
+
+            let version = match std::fs::read_to_string(&version_path) {
+                Ok(version) => version.trim().parse::<u32>()?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+                Err(e) => return Err(e.into()),
+            };
+
+            if version == PIN_LAYOUT_VERSION {
+                return Ok(());
+            }
+
+            if version > PIN_LAYOUT_VERSION {
+                return Err(anyhow::anyhow!(
+                    "unsupported pin layout version {}",
+                    version
+                ));
+            }
+
+            let mut migrated = 0;
+
+            for shard in std::fs::read_dir(&pins)? {
+                let shard = shard?;
+                if !shard.file_type()?.is_dir() {
+                    continue;
+                }
+
+                for entry in std::fs::read_dir(shard.path())? {
+                    let path = entry?.path();
+                    if path.extension() != Some("recursive".as_ref()) {
+                        continue;
+                    }
+
+                    let contents = std::fs::read(&path)?;
+
+                    let references = match serde_json::from_slice::<Vec<String>>(&contents) {
+                        Ok(references) => references,
+                        // already migrated by an earlier, interrupted run
+                        Err(_)
+                            if serde_json::from_slice::<RecursivePinRecord>(&contents).is_ok() =>
+                        {
+                            continue
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
+
+                    let record = RecursivePinRecord {
+                        label: PinLabel::default(),
+                        references,
+                    };
+
+                    sync_write_recursive_pin(&path, &record)?;
+                    migrated += 1;
+                }
+            }
+
+            // direct pins were empty files which are still read as unlabeled
+            std::fs::write(&version_path, PIN_LAYOUT_VERSION.to_string())?;
+
+            debug!(
+                migrated,
+                from = version,
+                to = PIN_LAYOUT_VERSION,
+                "migrated pins"
+            );
+
+            Ok(())
+        })
+        .await?
+    }
 }
 
 #[cfg(test)]
@@ -631,3 +808,75 @@ crate::pinstore_interface_tests!(
     common_tests,
     crate::repo::datastore::flatfs::FsDataStore::new
 );
+
+#[cfg(test)]
+mod test {
+    use super::FsDataStore;
+    use crate::repo::paths::pin_path;
+    use crate::repo::{DataStore, PinLabel, PinMode, PinStore};
+    use futures::TryStreamExt;
+    use libipld::Cid;
+    use std::convert::TryFrom;
+
+    #[tokio::test]
+    async fn migrates_unlabeled_pins() {
+        let tempdir = tempfile::TempDir::new().unwrap();
+        let pins = tempdir.path().join("pins");
+
+        // root/nested/deeper: QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp
+        let root = Cid::try_from("QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp").unwrap();
+        let empty = Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap();
+        let direct = Cid::try_from("QmYmmkD3dGZjuozuqSzDYjU4ZyhAgc4T4P4SUgY6qjzBi8").unwrap();
+
+        // the unversioned layout: a bare array for recursive and an empty file for direct pins
+        let recursive_path = pin_path(pins.clone(), &root).with_extension("recursive");
+        std::fs::create_dir_all(recursive_path.parent().unwrap()).unwrap();
+        std::fs::write(&recursive_path, format!("[\"{empty}\"]")).unwrap();
+
+        let direct_path = pin_path(pins.clone(), &direct).with_extension("direct");
+        std::fs::create_dir_all(direct_path.parent().unwrap()).unwrap();
+        std::fs::File::create(&direct_path).unwrap();
+
+        let store = FsDataStore::new(tempdir.path().to_owned());
+        store.init().await.unwrap();
+        store.open().await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(pins.join("version")).unwrap(), "1");
+
+        let mut listed = store
+            .list(None)
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        listed.sort_by_key(|(_, mode)| *mode as u8);
+        assert_eq!(
+            listed,
+            vec![
+                (empty, PinMode::Indirect),
+                (direct, PinMode::Direct),
+                (root, PinMode::Recursive)
+            ]
+        );
+
+        assert_eq!(store.label(&root).await.unwrap(), Some(PinLabel::default()));
+        assert_eq!(
+            store.label(&direct).await.unwrap(),
+            Some(PinLabel::default())
+        );
+        assert_eq!(store.label(&empty).await.unwrap(), None);
+
+        let label = PinLabel {
+            name: Some("root".into()),
+            ..Default::default()
+        };
+        store.set_label(&root, &label).await.unwrap();
+        assert_eq!(store.label(&root).await.unwrap(), Some(label));
+        assert!(store.is_pinned(&empty).await.unwrap());
+
+        // opening again is a no-op
+        let store = FsDataStore::new(tempdir.path().to_owned());
+        store.init().await.unwrap();
+        assert!(store.is_pinned(&empty).await.unwrap());
+    }
+}
@@ -1,10 +1,10 @@
 use crate::error::Error;
-use crate::repo::{DataStore, PinKind, PinMode, PinModeRequirement, PinStore};
+use crate::repo::{DataStore, PinKind, PinLabel, PinMode, PinModeRequirement, PinStore};
 use async_trait::async_trait;
 use futures::StreamExt;
 use libipld::{cid, Cid};
 use std::path::PathBuf;
-use tokio::sync::{Mutex, OwnedMutexGuard};
+use tokio::sync::Mutex;
 
 use std::collections::hash_map::Entry;
 
@@ -29,7 +29,7 @@ impl MemDataStore {
 
     /// Returns true if the pin document was changed, false otherwise.
     fn insert_pin<'a>(
-        g: &mut OwnedMutexGuard<HashMap<Vec<u8>, Vec<u8>>>,
+        g: &mut HashMap<Vec<u8>, Vec<u8>>,
         target: &'a Cid,
         kind: &'a PinKind<&'_ Cid>,
     ) -> Result<bool, Error> {
@@ -62,6 +62,7 @@ impl MemDataStore {
                         cid::Version::V1 => 1,
                     },
                     indirect_by: Vec::new(),
+                    label: None,
                 };
 
                 doc.update(true, kind).unwrap();
@@ -75,7 +76,7 @@ impl MemDataStore {
 
     /// Returns true if the pin document was changed, false otherwise.
     fn remove_pin<'a>(
-        g: &mut OwnedMutexGuard<HashMap<Vec<u8>, Vec<u8>>>,
+        g: &mut HashMap<Vec<u8>, Vec<u8>>,
         target: &'a Cid,
         kind: &'a PinKind<&'_ Cid>,
     ) -> Result<bool, Error> {
@@ -93,6 +94,10 @@ impl MemDataStore {
                 if doc.can_remove() {
                     oe.remove();
                 } else {
+                    if !doc.direct && !doc.recursive.is_set() {
+                        // only indirect pins remain, which are not labeled
+                        doc.label = None;
+                    }
                     let vec = oe.get_mut();
                     vec.clear();
                     serde_json::to_writer(vec, &doc)?;
@@ -103,48 +108,16 @@ impl MemDataStore {
             Entry::Vacant(_) => Err(anyhow::anyhow!("not pinned")),
         }
     }
-}
-
-#[async_trait]
-impl PinStore for MemDataStore {
-    async fn is_pinned(&self, block: &Cid) -> Result<bool, Error> {
-        let key = block.to_bytes();
-
-        let g = self.pin.lock().await;
-
-        // the use of PinKind::RecursiveIntention necessitates the only return fast for
-        // only the known pins; we should somehow now query to see if there are any
-        // RecursiveIntention's. If there are any, we must walk the refs of each to see if the
-        // `block` is amongst of those recursive references which are not yet written to disk.
-        //
-        // doing this without holding a repo lock is not possible, so leaving this as partial
-        // implementation right now.
-        Ok(g.contains_key(&key))
-    }
-
-    async fn insert_direct_pin(&self, target: &Cid) -> Result<(), Error> {
-        let mut g = Mutex::lock_owned(Arc::clone(&self.pin)).await;
-        Self::insert_pin(&mut g, target, &PinKind::Direct)?;
-        Ok(())
-    }
-
-    async fn remove_direct_pin(&self, target: &Cid) -> Result<(), Error> {
-        let mut g = Mutex::lock_owned(Arc::clone(&self.pin)).await;
-        Self::remove_pin(&mut g, target, &PinKind::Direct)?;
-        Ok(())
-    }
 
-    async fn insert_recursive_pin(
-        &self,
+    async fn insert_recursive(
+        g: &mut HashMap<Vec<u8>, Vec<u8>>,
         target: &Cid,
         mut refs: crate::repo::References<'_>,
     ) -> Result<(), Error> {
         use futures::stream::TryStreamExt;
 
-        let mut g = Mutex::lock_owned(Arc::clone(&self.pin)).await;
-
         // this must fail if it is already fully pinned
-        Self::insert_pin(&mut g, target, &PinKind::RecursiveIntention)?;
+        Self::insert_pin(g, target, &PinKind::RecursiveIntention)?;
 
         let target_v1 = if target.version() == cid::Version::V1 {
             target.to_owned()
@@ -161,25 +134,23 @@ impl PinStore for MemDataStore {
         let kind = PinKind::IndirectFrom(&target_v1);
         while let Some(next) = refs.try_next().await? {
             // no rollback, nothing
-            Self::insert_pin(&mut g, &next, &kind)?;
+            Self::insert_pin(g, &next, &kind)?;
             count += 1;
         }
 
         let kind = PinKind::Recursive(count as u64);
-        Self::insert_pin(&mut g, target, &kind)?;
+        Self::insert_pin(g, target, &kind)?;
 
         Ok(())
     }
 
-    async fn remove_recursive_pin(
-        &self,
+    async fn remove_recursive(
+        g: &mut HashMap<Vec<u8>, Vec<u8>>,
         target: &Cid,
         mut refs: crate::repo::References<'_>,
     ) -> Result<(), Error> {
         use futures::TryStreamExt;
 
-        let mut g = Mutex::lock_owned(Arc::clone(&self.pin)).await;
-
         let doc: PinDocument = match g.get(&target.to_bytes()) {
             Some(raw) => match serde_json::from_slice(raw) {
                 Ok(doc) => doc,
@@ -193,7 +164,7 @@ impl PinStore for MemDataStore {
             Some(Ok(kind @ PinKind::Recursive(_)))
             | Some(Ok(kind @ PinKind::RecursiveIntention)) => kind,
             Some(Ok(PinKind::Direct)) => {
-                Self::remove_pin(&mut g, target, &PinKind::Direct)?;
+                Self::remove_pin(g, target, &PinKind::Direct)?;
                 return Ok(());
             }
             Some(Ok(PinKind::IndirectFrom(cid))) => {
@@ -204,7 +175,7 @@ impl PinStore for MemDataStore {
         };
 
         // this must fail if it is already fully pinned
-        Self::remove_pin(&mut g, target, &kind.as_ref())?;
+        Self::remove_pin(g, target, &kind.as_ref())?;
 
         let target_v1 = if target.version() == cid::Version::V1 {
             target.to_owned()
@@ -216,11 +187,79 @@ impl PinStore for MemDataStore {
         let kind = PinKind::IndirectFrom(&target_v1);
         while let Some(next) = refs.try_next().await? {
             // no rollback, nothing
-            Self::remove_pin(&mut g, &next, &kind)?;
+            Self::remove_pin(g, &next, &kind)?;
         }
 
         Ok(())
     }
+}
+
+#[async_trait]
+impl PinStore for MemDataStore {
+    async fn is_pinned(&self, block: &Cid) -> Result<bool, Error> {
+        let key = block.to_bytes();
+
+        let g = self.pin.lock().await;
+
+        // the use of PinKind::RecursiveIntention necessitates the only return fast for
+        // only the known pins; we should somehow now query to see if there are any
+        // RecursiveIntention's. If there are any, we must walk the refs of each to see if the
+        // `block` is amongst of those recursive references which are not yet written to disk.
+        //
+        // doing this without holding a repo lock is not possible, so leaving this as partial
+        // implementation right now.
+        Ok(g.contains_key(&key))
+    }
+
+    async fn insert_direct_pin(&self, target: &Cid) -> Result<(), Error> {
+        let mut g = Mutex::lock_owned(Arc::clone(&self.pin)).await;
+        Self::insert_pin(&mut g, target, &PinKind::Direct)?;
+        Ok(())
+    }
+
+    async fn remove_direct_pin(&self, target: &Cid) -> Result<(), Error> {
+        let mut g = Mutex::lock_owned(Arc::clone(&self.pin)).await;
+        Self::remove_pin(&mut g, target, &PinKind::Direct)?;
+        Ok(())
+    }
+
+    async fn insert_recursive_pin(
+        &self,
+        target: &Cid,
+        refs: crate::repo::References<'_>,
+    ) -> Result<(), Error> {
+        let mut g = Mutex::lock_owned(Arc::clone(&self.pin)).await;
+        Self::insert_recursive(&mut g, target, refs).await
+    }
+
+    async fn remove_recursive_pin(
+        &self,
+        target: &Cid,
+        refs: crate::repo::References<'_>,
+    ) -> Result<(), Error> {
+        let mut g = Mutex::lock_owned(Arc::clone(&self.pin)).await;
+        Self::remove_recursive(&mut g, target, refs).await
+    }
+
+    async fn update_recursive_pins(
+        &self,
+        insert: Vec<(Cid, crate::repo::References<'_>)>,
+        remove: Vec<(Cid, crate::repo::References<'_>)>,
+    ) -> Result<(), Error> {
+        let mut g = Mutex::lock_owned(Arc::clone(&self.pin)).await;
+
+        // the changes are made to a copy, which replaces the pins once all of them succeeded
+        let mut staged = g.clone();
+        for (target, refs) in insert {
+            Self::insert_recursive(&mut staged, &target, refs).await?;
+        }
+        for (target, refs) in remove {
+            Self::remove_recursive(&mut staged, &target, refs).await?;
+        }
+        *g = staged;
+
+        Ok(())
+    }
 
     async fn list(
         &self,
@@ -253,6 +292,40 @@ impl PinStore for MemDataStore {
         futures::stream::iter(copy).boxed()
     }
 
+    async fn set_label(&self, target: &Cid, label: &PinLabel) -> Result<(), Error> {
+        let mut g = self.pin.lock().await;
+
+        let raw = match g.get_mut(&target.to_bytes()) {
+            Some(raw) => raw,
+            None => return Err(anyhow::anyhow!("not pinned or pinned indirectly")),
+        };
+
+        let mut doc: PinDocument = serde_json::from_slice(raw)?;
+        if !doc.direct && !doc.recursive.is_set() {
+            return Err(anyhow::anyhow!("not pinned or pinned indirectly"));
+        }
+
+        doc.label = (!label.is_empty()).then(|| label.clone());
+        raw.clear();
+        serde_json::to_writer(raw, &doc)?;
+        Ok(())
+    }
+
+    async fn label(&self, target: &Cid) -> Result<Option<PinLabel>, Error> {
+        let g = self.pin.lock().await;
+
+        let doc: PinDocument = match g.get(&target.to_bytes()) {
+            Some(raw) => serde_json::from_slice(raw)?,
+            None => return Ok(None),
+        };
+
+        if !doc.direct && !doc.recursive.is_set() {
+            return Ok(None);
+        }
+
+        Ok(Some(doc.label.unwrap_or_default()))
+    }
+
     async fn query(
         &self,
         cids: Vec<Cid>,
@@ -396,6 +469,9 @@ struct PinDocument {
     cid_version: u8,
     // using the cidv1 versions of all cids here, not sure if that makes sense or is important
     indirect_by: Vec<String>,
+    // documents written before labels existed do not have this field
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<PinLabel>,
 }
 
 impl PinDocument {
@@ -630,6 +706,7 @@ mod tests {
             recursive: Recursive::Not,
             cid_version: 0,
             indirect_by: Vec::new(),
+            label: None,
         };
 
         assert!(doc.update(true, &PinKind::Direct).unwrap());
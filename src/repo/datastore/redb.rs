@@ -1,6 +1,8 @@
 use crate::error::Error;
-use crate::repo::{DataStore, PinModeRequirement};
-use crate::repo::{PinKind, PinMode, PinStore, References};
+use crate::repo::blockstore::redb::RedbBlockStore;
+use crate::repo::{BlockPut, DataStore, PinModeRequirement};
+use crate::repo::{PinKind, PinLabel, PinMode, PinStore, References};
+use crate::Block;
 use async_trait::async_trait;
 use either::Either;
 use futures::stream::{StreamExt, TryStreamExt};
@@ -9,57 +11,108 @@ use redb::{Database, ReadableTable, TableDefinition};
 use std::collections::BTreeSet;
 use std::path::PathBuf;
 use std::str::{self, FromStr};
-use std::sync::{Arc, OnceLock};
+use std::sync::Arc;
+use tokio::sync::OnceCell;
 
 const DATATABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("data");
 const PINTABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("pin");
-
+/// Blocks keyed by the bytes of their `Cid`, only used by [`RedbDataStore::with_blocks`].
+pub(crate) const BLOCKTABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("block");
+
+/// [`redb`] based datastore and pinstore.
+///
+/// Created with [`RedbDataStore::with_blocks`], the blocks of the repo are kept in the same
+/// database through [`RedbDataStore::block_store`], so a block can be put and pinned in a single
+/// transaction with [`DataStore::put_pinned_block`].
+///
+/// Pins are stored as [`get_pin_key`] in their own table. Indirect pins count the recursive pins
+/// referencing the block, see [`indirect_value`], and are kept alongside direct and recursive
+/// pins of the same block.
+///
+/// [`redb`]: https://github.com/cberner/redb
 #[derive(Debug)]
 pub struct RedbDataStore {
+    db: SharedDatabase,
+    blocks: bool,
+}
+
+/// The database, opened on the first `init` of the datastore or the blockstore sharing it.
+#[derive(Debug, Clone)]
+pub(crate) struct SharedDatabase {
     path: PathBuf,
-    // it is a trick for not modifying the Data:init
-    db: OnceLock<Arc<Database>>,
+    db: Arc<OnceCell<Arc<Database>>>,
+}
+
+impl SharedDatabase {
+    pub(crate) async fn init(&self) -> Result<(), Error> {
+        self.db
+            .get_or_try_init(|| async {
+                tokio::fs::create_dir_all(&self.path).await?;
+
+                let db = Arc::new(Database::create(self.path.join("ipfs_datastore.db"))?);
+                tokio::task::spawn_blocking({
+                    let db = db.clone();
+                    move || {
+                        let initial_tx = db.begin_write()?;
+                        {
+                            _ = initial_tx.open_table(DATATABLE)?;
+                        }
+                        {
+                            _ = initial_tx.open_table(PINTABLE)?;
+                        }
+                        {
+                            _ = initial_tx.open_table(BLOCKTABLE)?;
+                        }
+                        initial_tx.commit()?;
+                        Ok::<_, Error>(())
+                    }
+                })
+                .await??;
+                Ok::<_, Error>(db)
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub(crate) fn get(&self) -> Arc<Database> {
+        let db = self.db.get().cloned();
+        db.expect("Datastore to be initialized")
+    }
 }
 
 impl RedbDataStore {
     pub fn new(root: PathBuf) -> RedbDataStore {
         RedbDataStore {
-            path: root,
-            db: Default::default(),
+            db: SharedDatabase {
+                path: root,
+                db: Default::default(),
+            },
+            blocks: false,
         }
     }
 
+    /// Creates a datastore which also keeps the blocks, through [`RedbDataStore::block_store`].
+    pub fn with_blocks(root: PathBuf) -> RedbDataStore {
+        RedbDataStore {
+            blocks: true,
+            ..Self::new(root)
+        }
+    }
+
+    /// Returns the blockstore sharing the database of this datastore.
+    pub fn block_store(&self) -> RedbBlockStore {
+        RedbBlockStore::new(self.db.clone())
+    }
+
     fn get_db(&self) -> Arc<Database> {
-        let db = self.db.get().cloned();
-        db.expect("Datastore to be initialized")
+        self.db.get()
     }
 }
 
 #[async_trait]
 impl DataStore for RedbDataStore {
     async fn init(&self) -> Result<(), Error> {
-        tokio::fs::create_dir_all(&self.path).await?;
-
-        let db = Arc::new(Database::create(self.path.join("ipfs_datastore.db"))?);
-        tokio::task::spawn_blocking({
-            let db = db.clone();
-            move || {
-                let initial_tx = db.begin_write()?;
-                {
-                    _ = initial_tx.open_table(DATATABLE)?;
-                }
-                {
-                    _ = initial_tx.open_table(PINTABLE)?;
-                }
-                initial_tx.commit()?;
-                Ok::<_, Error>(())
-            }
-        })
-        .await??;
-        match self.db.set(db) {
-            Ok(()) => Ok(()),
-            Err(_) => Err(anyhow::anyhow!("failed to init redb")),
-        }
+        self.db.init().await
     }
 
     async fn open(&self) -> Result<(), Error> {
@@ -163,6 +216,41 @@ impl DataStore for RedbDataStore {
 
         UnboundedReceiverStream::new(rx).boxed()
     }
+
+    async fn put_pinned_block(&self, block: &Block) -> Result<Option<BlockPut>, Error> {
+        if !self.blocks {
+            return Ok(None);
+        }
+
+        let block = block.clone();
+        let db = self.get_db();
+
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            let span = tracing::trace_span!(parent: &span, "blocking");
+            let _g = span.enter();
+            let tx = db.begin_write()?;
+            let put = {
+                let mut blocks = tx.open_table(BLOCKTABLE)?;
+                let key = block.cid().to_bytes();
+                if blocks.get(key.as_slice())?.is_some() {
+                    BlockPut::Existed
+                } else {
+                    blocks.insert(key.as_slice(), block.data())?;
+                    BlockPut::NewBlock
+                }
+            };
+            {
+                let mut table = tx.open_table(PINTABLE)?;
+                insert_direct(&mut table, block.cid())?;
+            }
+            tx.commit()?;
+
+            Ok::<_, anyhow::Error>(Some(put))
+        })
+        .await?
+    }
 }
 
 #[async_trait]
@@ -194,23 +282,7 @@ impl PinStore for RedbDataStore {
             let tx = db.begin_write()?;
             {
                 let mut table = tx.open_table(PINTABLE)?;
-
-                let already_pinned = get_pinned_mode(Either::Right(&mut table), &target)?;
-
-                match already_pinned {
-                    Some((PinMode::Direct, _)) => return Ok(()),
-                    Some((PinMode::Recursive, _)) => {
-                        return Err(anyhow::anyhow!("already pinned recursively"))
-                    }
-                    Some((PinMode::Indirect, key)) => {
-                        // TODO: I think the direct should live alongside the indirect?
-                        table.remove(key.as_bytes())?;
-                    }
-                    None => {}
-                }
-
-                let direct_key = get_pin_key(&target, &PinMode::Direct);
-                table.insert(direct_key.as_bytes(), direct_value())?;
+                insert_direct(&mut table, &target)?;
             }
 
             tx.commit()?;
@@ -239,31 +311,7 @@ impl PinStore for RedbDataStore {
             let tx = db.begin_write()?;
             {
                 let mut table = tx.open_table(PINTABLE)?;
-
-                let already_pinned = get_pinned_mode(Either::Right(&mut table), &target)?;
-
-                match already_pinned {
-                    Some((PinMode::Recursive, _)) => return Ok(()),
-                    Some((PinMode::Direct, key)) | Some((PinMode::Indirect, key)) => {
-                        table.remove(key.as_bytes())?;
-                    }
-                    None => {}
-                }
-
-                let recursive_key = get_pin_key(&target, &PinMode::Recursive);
-                table.insert(recursive_key.as_bytes(), recursive_value())?;
-
-                let target_value = indirect_value(&target);
-
-                for cid in set.iter() {
-                    let indirect_key = get_pin_key(cid, &PinMode::Indirect);
-
-                    if get_pinned_mode(Either::Right(&mut table), cid)?.is_some() {
-                        continue;
-                    }
-
-                    table.insert(indirect_key.as_bytes(), target_value.as_bytes())?;
-                }
+                insert_recursive(&mut table, &target, &set)?;
             }
 
             tx.commit()?;
@@ -321,26 +369,44 @@ impl PinStore for RedbDataStore {
             let tx = db.begin_write()?;
             {
                 let mut table = tx.open_table(PINTABLE)?;
+                remove_recursive(&mut table, &target, &set)?;
+            }
 
-                if is_not_pinned_or_pinned_indirectly(Either::Right(&mut table), &target)? {
-                    return Err(anyhow::anyhow!("not pinned or pinned indirectly"));
-                }
+            tx.commit()?;
+            Ok::<_, anyhow::Error>(())
+        })
+        .await?
+    }
+
+    async fn update_recursive_pins(
+        &self,
+        insert: Vec<(Cid, References<'_>)>,
+        remove: Vec<(Cid, References<'_>)>,
+    ) -> Result<(), Error> {
+        let mut inserted = Vec::with_capacity(insert.len());
+        for (target, referenced) in insert {
+            inserted.push((target, referenced.try_collect::<BTreeSet<_>>().await?));
+        }
+        let mut removed = Vec::with_capacity(remove.len());
+        for (target, referenced) in remove {
+            removed.push((target, referenced.try_collect::<BTreeSet<_>>().await?));
+        }
 
-                let recursive_key = get_pin_key(&target, &PinMode::Recursive);
-                table.remove(recursive_key.as_bytes())?;
+        let db = self.get_db().to_owned();
 
-                for cid in &set {
-                    let already_pinned = get_pinned_mode(Either::Right(&mut table), cid)?;
+        let span = tracing::Span::current();
 
-                    match already_pinned {
-                        Some((PinMode::Recursive, _)) | Some((PinMode::Direct, _)) => continue, // this should be unreachable
-                        Some((PinMode::Indirect, key)) => {
-                            // FIXME: not really sure of this but it might be that recursive removed
-                            // the others...?
-                            table.remove(key.as_bytes())?;
-                        }
-                        None => {}
-                    }
+        tokio::task::spawn_blocking(move || {
+            let span = tracing::trace_span!(parent: &span, "blocking");
+            let _g = span.enter();
+            let tx = db.begin_write()?;
+            {
+                let mut table = tx.open_table(PINTABLE)?;
+                for (target, set) in &inserted {
+                    insert_recursive(&mut table, target, set)?;
+                }
+                for (target, set) in &removed {
+                    remove_recursive(&mut table, target, set)?;
                 }
             }
 
@@ -390,6 +456,7 @@ impl PinStore for RedbDataStore {
             };
 
             let requirement = PinModeRequirement::from(requirement);
+            let pins = &table;
 
             let adapted =
                 iter.map(|res| res.map_err(Error::from))
@@ -426,6 +493,16 @@ impl PinStore for RedbDataStore {
                                         &*String::from_utf8_lossy(val)
                                     ))
                                 });
+
+                                // indirect pins are only listed for blocks not pinned otherwise
+                                if let (Ok(cid), PinMode::Indirect) = (&cid, mode) {
+                                    match get_pinned_mode(Either::Left(pins), cid) {
+                                        Ok(Some((PinMode::Indirect, _))) => {}
+                                        Ok(_) => return None,
+                                        Err(e) => return Some(Err(e)),
+                                    }
+                                }
+
                                 Some(cid.map(move |cid| (cid, mode)))
                             }
                         }
@@ -442,6 +519,64 @@ impl PinStore for RedbDataStore {
         UnboundedReceiverStream::new(rx).boxed()
     }
 
+    async fn set_label(&self, target: &Cid, label: &PinLabel) -> Result<(), Error> {
+        let target = target.to_owned();
+        let value = label.to_bytes()?;
+        let db = self.get_db();
+
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            let span = tracing::trace_span!(parent: &span, "blocking");
+            let _g = span.enter();
+            let tx = db.begin_write()?;
+            {
+                let mut table = tx.open_table(PINTABLE)?;
+
+                match get_pinned_mode(Either::Right(&mut table), &target)? {
+                    Some((PinMode::Direct, key)) | Some((PinMode::Recursive, key)) => {
+                        table.insert(key.as_bytes(), value.as_slice())?;
+                    }
+                    Some((PinMode::Indirect, _)) | None => {
+                        return Err(anyhow::anyhow!("not pinned or pinned indirectly"))
+                    }
+                }
+            }
+
+            tx.commit()?;
+
+            Ok::<_, anyhow::Error>(())
+        })
+        .await?
+    }
+
+    async fn label(&self, target: &Cid) -> Result<Option<PinLabel>, Error> {
+        let target = target.to_owned();
+        let db = self.get_db();
+
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            let span = tracing::trace_span!(parent: &span, "blocking");
+            let _g = span.enter();
+            let read_tx = db.begin_read()?;
+            let table = read_tx.open_table(PINTABLE)?;
+
+            let key = match get_pinned_mode(Either::Left(&table), &target)? {
+                Some((PinMode::Direct, key)) | Some((PinMode::Recursive, key)) => key,
+                Some((PinMode::Indirect, _)) | None => return Ok(None),
+            };
+
+            let label = match table.get(key.as_bytes())? {
+                Some(value) => PinLabel::from_bytes(value.value())?,
+                None => PinLabel::default(),
+            };
+
+            Ok(Some(label))
+        })
+        .await?
+    }
+
     async fn query(
         &self,
         ids: Vec<Cid>,
@@ -468,8 +603,8 @@ impl PinStore for RedbDataStore {
                         PinMode::Indirect => table
                             .get(key.as_bytes())?
                             .map(|root| {
-                                cid_from_indirect_value(root.value())
-                                    .map(PinKind::IndirectFrom)
+                                from_indirect_value(root.value())
+                                    .map(|(root, _)| PinKind::IndirectFrom(root))
                                     .map_err(|e| {
                                         e.context(format!(
                                             "failed to read indirect pin source: {:?}",
@@ -496,6 +631,7 @@ impl PinStore for RedbDataStore {
 }
 
 /// Name the empty value stored for direct pins; the pin key itself describes the mode and the cid.
+/// This is also the serialized form of an empty [`PinLabel`].
 fn direct_value() -> &'static [u8] {
     Default::default()
 }
@@ -505,16 +641,102 @@ fn recursive_value() -> &'static [u8] {
     Default::default()
 }
 
-/// Name the value stored for indirect pins, currently only the most recent recursive pin.
-fn indirect_value(recursively_pinned: &Cid) -> String {
-    recursively_pinned.to_string()
+/// Name the value stored for indirect pins: the most recent recursive pin referencing the block,
+/// followed by the number of recursive pins referencing it.
+fn indirect_value(recursively_pinned: &Cid, count: u64) -> String {
+    format!("{recursively_pinned} {count}")
 }
 
-/// Inverse of [`indirect_value`].
-fn cid_from_indirect_value(bytes: &[u8]) -> Result<Cid, Error> {
-    str::from_utf8(bytes)
-        .map_err(Error::from)
-        .and_then(|s| Cid::from_str(s).map_err(Error::from))
+/// Inverse of [`indirect_value`]. Values stored before the count was added count as one.
+fn from_indirect_value(bytes: &[u8]) -> Result<(Cid, u64), Error> {
+    let value = str::from_utf8(bytes)?;
+    let (root, count) = match value.split_once(' ') {
+        Some((root, count)) => (root, count.parse()?),
+        None => (value, 1),
+    };
+    Ok((Cid::from_str(root)?, count))
+}
+
+/// Pins `target` directly. An indirect pin of the block is kept, so it remains pinned once the
+/// direct pin is removed while still referenced by a recursive pin.
+fn insert_direct(table: &mut redb::Table<'_, '_, &[u8], &[u8]>, target: &Cid) -> Result<(), Error> {
+    match get_pinned_mode(Either::Right(&mut *table), target)? {
+        Some((PinMode::Direct, _)) => return Ok(()),
+        Some((PinMode::Recursive, _)) => return Err(anyhow::anyhow!("already pinned recursively")),
+        Some((PinMode::Indirect, _)) | None => {}
+    }
+
+    let direct_key = get_pin_key(target, &PinMode::Direct);
+    table.insert(direct_key.as_bytes(), direct_value())?;
+    Ok(())
+}
+
+/// Pins `target` recursively, counting the recursive pin for each of the `referenced` blocks.
+fn insert_recursive(
+    table: &mut redb::Table<'_, '_, &[u8], &[u8]>,
+    target: &Cid,
+    referenced: &BTreeSet<Cid>,
+) -> Result<(), Error> {
+    // the label of a direct pin carries over to the recursive one
+    let mut label = None;
+
+    match get_pinned_mode(Either::Right(&mut *table), target)? {
+        Some((PinMode::Recursive, _)) => return Ok(()),
+        Some((PinMode::Direct, key)) => {
+            label = table
+                .remove(key.as_bytes())?
+                .map(|value| value.value().to_vec());
+        }
+        Some((PinMode::Indirect, _)) | None => {}
+    }
+
+    let recursive_key = get_pin_key(target, &PinMode::Recursive);
+    let value = label.as_deref().unwrap_or(recursive_value());
+    table.insert(recursive_key.as_bytes(), value)?;
+
+    for cid in referenced {
+        let indirect_key = get_pin_key(cid, &PinMode::Indirect);
+        let count = match table.get(indirect_key.as_bytes())? {
+            Some(value) => from_indirect_value(value.value())?.1,
+            None => 0,
+        };
+        let value = indirect_value(target, count + 1);
+        table.insert(indirect_key.as_bytes(), value.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Removes the recursive pin of `target`, along with the indirect pins no longer counted by any
+/// recursive pin.
+fn remove_recursive(
+    table: &mut redb::Table<'_, '_, &[u8], &[u8]>,
+    target: &Cid,
+    referenced: &BTreeSet<Cid>,
+) -> Result<(), Error> {
+    if is_not_pinned_or_pinned_indirectly(Either::Right(&mut *table), target)? {
+        return Err(anyhow::anyhow!("not pinned or pinned indirectly"));
+    }
+
+    let recursive_key = get_pin_key(target, &PinMode::Recursive);
+    table.remove(recursive_key.as_bytes())?;
+
+    for cid in referenced {
+        let indirect_key = get_pin_key(cid, &PinMode::Indirect);
+        let (root, count) = match table.get(indirect_key.as_bytes())? {
+            Some(value) => from_indirect_value(value.value())?,
+            None => continue,
+        };
+
+        if count > 1 {
+            let value = indirect_value(&root, count - 1);
+            table.insert(indirect_key.as_bytes(), value.as_bytes())?;
+        } else {
+            table.remove(indirect_key.as_bytes())?;
+        }
+    }
+
+    Ok(())
 }
 
 fn pin_mode_literal(pin_mode: &PinMode) -> &'static str {
@@ -574,9 +796,196 @@ crate::pinstore_interface_tests!(
     crate::repo::datastore::redb::RedbDataStore::new
 );
 
+#[cfg(test)]
+crate::pinstore_interface_tests!(
+    common_tests_with_blocks,
+    crate::repo::datastore::redb::RedbDataStore::with_blocks
+);
+
 #[cfg(test)]
 mod test {
-    use crate::repo::{datastore::redb::RedbDataStore, DataStore};
+    use super::{insert_recursive, BLOCKTABLE, PINTABLE};
+    use crate::repo::{datastore::redb::RedbDataStore, BlockPut, BlockStore, DataStore};
+    use crate::repo::{PinKind, PinStore};
+    use crate::Block;
+    use futures::StreamExt;
+    use hex_literal::hex;
+    use libipld::Cid;
+    use std::collections::BTreeSet;
+    use std::convert::TryFrom;
+
+    fn cids() -> (Cid, Cid, Cid) {
+        // root/nested/deeper: QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp
+        let root = Cid::try_from("QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp").unwrap();
+        let other = Cid::try_from("QmPTotyhVnnfCu9R4qwR4cdhpi5ENaiP8ZJfdqsm8Dw2jB").unwrap();
+        let empty = Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap();
+        (root, other, empty)
+    }
+
+    async fn open(path: &std::path::Path) -> RedbDataStore {
+        let store = RedbDataStore::with_blocks(path.to_owned());
+        DataStore::init(&store).await.unwrap();
+        store
+    }
+
+    #[tokio::test]
+    async fn indirect_pins_are_counted() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = open(tmp.path()).await;
+        let (root, other, empty) = cids();
+
+        for target in [root, other] {
+            let refs = futures::stream::iter(vec![Ok(empty)]).boxed();
+            store.insert_recursive_pin(&target, refs).await.unwrap();
+        }
+
+        let refs = futures::stream::iter(vec![Ok(empty)]).boxed();
+        store.remove_recursive_pin(&root, refs).await.unwrap();
+        assert!(store.is_pinned(&empty).await.unwrap());
+
+        // the direct pin does not replace the indirect one
+        store.insert_direct_pin(&empty).await.unwrap();
+        store.remove_direct_pin(&empty).await.unwrap();
+        assert!(store.is_pinned(&empty).await.unwrap());
+
+        let refs = futures::stream::iter(vec![Ok(empty)]).boxed();
+        store.remove_recursive_pin(&other, refs).await.unwrap();
+        assert!(!store.is_pinned(&empty).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn indirect_pins_without_count() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = open(tmp.path()).await;
+        let (root, _, empty) = cids();
+
+        let refs = futures::stream::iter(vec![Ok(empty)]).boxed();
+        store.insert_recursive_pin(&root, refs).await.unwrap();
+
+        // as written before the count was stored
+        let db = store.get_db();
+        let tx = db.begin_write().unwrap();
+        {
+            let mut table = tx.open_table(PINTABLE).unwrap();
+            let key = format!("pin.i.{empty}");
+            table
+                .insert(key.as_bytes(), root.to_string().as_bytes())
+                .unwrap();
+        }
+        tx.commit().unwrap();
+
+        let found = store.query(vec![empty], None).await.unwrap();
+        assert_eq!(found, vec![(empty, PinKind::IndirectFrom(root))]);
+
+        let refs = futures::stream::iter(vec![Ok(empty)]).boxed();
+        store.remove_recursive_pin(&root, refs).await.unwrap();
+        assert!(!store.is_pinned(&empty).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn uncommitted_pin_is_dropped_on_reopen() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let (root, other, empty) = cids();
+
+        {
+            let store = open(tmp.path()).await;
+            let refs = futures::stream::iter(vec![Ok(empty)]).boxed();
+            store.insert_recursive_pin(&root, refs).await.unwrap();
+
+            // stops while pinning `other`, before the transaction has been committed
+            let db = store.get_db();
+            let tx = db.begin_write().unwrap();
+            {
+                let mut table = tx.open_table(PINTABLE).unwrap();
+                insert_recursive(&mut table, &other, &BTreeSet::from([empty])).unwrap();
+            }
+            drop(tx);
+        }
+
+        let store = open(tmp.path()).await;
+        assert!(store.is_pinned(&root).await.unwrap());
+        assert!(!store.is_pinned(&other).await.unwrap());
+
+        // the count was not left incremented by the dropped pin
+        let refs = futures::stream::iter(vec![Ok(empty)]).boxed();
+        store.remove_recursive_pin(&root, refs).await.unwrap();
+        assert!(!store.is_pinned(&empty).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn failed_pin_update_is_rolled_back() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = open(tmp.path()).await;
+        let (root, other, empty) = cids();
+
+        let refs = futures::stream::iter(vec![Ok(empty)]).boxed();
+        store.insert_recursive_pin(&root, refs).await.unwrap();
+
+        // `empty` is only pinned indirectly, so removing it fails once the other changes are made
+        let insert = vec![(other, futures::stream::iter(vec![Ok(empty)]).boxed())];
+        let remove = vec![
+            (root, futures::stream::iter(vec![Ok(empty)]).boxed()),
+            (empty, futures::stream::iter(vec![]).boxed()),
+        ];
+        assert!(store.update_recursive_pins(insert, remove).await.is_err());
+        assert!(store.is_pinned(&root).await.unwrap());
+        assert!(!store.is_pinned(&other).await.unwrap());
+
+        let insert = vec![(other, futures::stream::iter(vec![Ok(empty)]).boxed())];
+        let remove = vec![(root, futures::stream::iter(vec![Ok(empty)]).boxed())];
+        store.update_recursive_pins(insert, remove).await.unwrap();
+        assert!(!store.is_pinned(&root).await.unwrap());
+        let found = store.query(vec![empty], None).await.unwrap();
+        assert_eq!(found, vec![(empty, PinKind::IndirectFrom(other))]);
+    }
+
+    #[tokio::test]
+    async fn uncommitted_pinned_block_is_dropped_on_reopen() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let cid = Cid::try_from("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap();
+        let block = Block::new(cid, hex!("0a0d08021207666f6f6261720a1807").to_vec()).unwrap();
+
+        {
+            let store = open(tmp.path()).await;
+
+            // stops after writing the block, before pinning it
+            let db = store.get_db();
+            let tx = db.begin_write().unwrap();
+            {
+                let mut blocks = tx.open_table(BLOCKTABLE).unwrap();
+                blocks
+                    .insert(cid.to_bytes().as_slice(), block.data())
+                    .unwrap();
+            }
+            drop(tx);
+        }
+
+        {
+            let store = open(tmp.path()).await;
+            assert!(!store.block_store().contains(&cid).await.unwrap());
+            assert!(!store.is_pinned(&cid).await.unwrap());
+
+            let put = store.put_pinned_block(&block).await.unwrap();
+            assert_eq!(put, Some(BlockPut::NewBlock));
+        }
+
+        let store = open(tmp.path()).await;
+        assert_eq!(store.block_store().get(&cid).await.unwrap(), Some(block));
+        assert!(store.is_pinned(&cid).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn pinned_block_needs_block_store() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = RedbDataStore::new(tmp.path().to_owned());
+        store.init().await.unwrap();
+
+        let cid = Cid::try_from("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap();
+        let block = Block::new(cid, hex!("0a0d08021207666f6f6261720a1807").to_vec()).unwrap();
+
+        assert_eq!(store.put_pinned_block(&block).await.unwrap(), None);
+        assert!(!store.is_pinned(&cid).await.unwrap());
+    }
 
     #[tokio::test]
     async fn test_kv_datastore() {
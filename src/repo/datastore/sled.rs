@@ -1,6 +1,6 @@
 use crate::error::Error;
 use crate::repo::{DataStore, PinModeRequirement};
-use crate::repo::{PinKind, PinMode, PinStore, References};
+use crate::repo::{PinKind, PinLabel, PinMode, PinStore, References};
 use async_trait::async_trait;
 use futures::stream::{StreamExt, TryStreamExt};
 use libipld::cid::Cid;
@@ -24,7 +24,8 @@ use std::sync::OnceLock;
 ///
 /// Current schema is to use the the default tree for storing pins, which are serialized as
 /// [`get_pin_key`]. Depending on the kind of pin values are generated by [`direct_value`],
-/// [`recursive_value`], and [`indirect_value`].
+/// [`recursive_value`], and [`indirect_value`]. Direct and recursive pins may instead hold the
+/// serialized [`PinLabel`] once labeled.
 ///
 /// [`sled`]: https://github.com/spacejam/sled
 #[derive(Debug)]
@@ -196,9 +197,15 @@ impl PinStore for SledDataStore {
             db.transaction::<_, _, Infallible>(move |tx_tree| {
                 let already_pinned = get_pinned_mode(tx_tree, &target)?;
 
+                // the label of a direct pin carries over to the recursive one
+                let mut label = None;
+
                 match already_pinned {
                     Some((PinMode::Recursive, _)) => return Ok(()),
-                    Some((PinMode::Direct, key)) | Some((PinMode::Indirect, key)) => {
+                    Some((PinMode::Direct, key)) => {
+                        label = tx_tree.remove(key.as_str())?;
+                    }
+                    Some((PinMode::Indirect, key)) => {
                         // FIXME: this is probably another lapse in tests that both direct and
                         // indirect can be removed when inserting recursive?
                         tx_tree.remove(key.as_str())?;
@@ -207,7 +214,10 @@ impl PinStore for SledDataStore {
                 }
 
                 let recursive_key = get_pin_key(&target, &PinMode::Recursive);
-                tx_tree.insert(recursive_key.as_str(), recursive_value())?;
+                match &label {
+                    Some(label) => tx_tree.insert(recursive_key.as_str(), label.clone())?,
+                    None => tx_tree.insert(recursive_key.as_str(), recursive_value())?,
+                };
 
                 let target_value = indirect_value(&target);
 
@@ -404,6 +414,61 @@ impl PinStore for SledDataStore {
         UnboundedReceiverStream::new(rx).boxed()
     }
 
+    async fn set_label(&self, target: &Cid, label: &PinLabel) -> Result<(), Error> {
+        use ConflictableTransactionError::Abort;
+        let target = target.to_owned();
+        let value = label.to_bytes()?;
+        let db = self.get_db().to_owned();
+
+        let span = tracing::Span::current();
+
+        let res = tokio::task::spawn_blocking(move || {
+            let span = tracing::trace_span!(parent: &span, "blocking");
+            let _g = span.enter();
+
+            db.transaction(|tx_tree| {
+                match get_pinned_mode(tx_tree, &target)? {
+                    Some((PinMode::Direct, key)) | Some((PinMode::Recursive, key)) => {
+                        tx_tree.insert(key.as_str(), value.as_slice())?;
+                    }
+                    Some((PinMode::Indirect, _)) | None => {
+                        return Err(Abort(anyhow::anyhow!("not pinned or pinned indirectly")))
+                    }
+                }
+
+                tx_tree.flush();
+                Ok(())
+            })
+        })
+        .await?;
+
+        launder(res)
+    }
+
+    async fn label(&self, target: &Cid) -> Result<Option<PinLabel>, Error> {
+        let target = target.to_owned();
+        let db = self.get_db().to_owned();
+
+        let span = tracing::Span::current();
+
+        let value = tokio::task::spawn_blocking(move || {
+            let span = tracing::trace_span!(parent: &span, "blocking");
+            let _g = span.enter();
+
+            db.transaction::<_, _, Infallible>(|tx_tree| {
+                Ok(match get_pinned_mode(tx_tree, &target)? {
+                    Some((PinMode::Direct, key)) | Some((PinMode::Recursive, key)) => {
+                        tx_tree.get(key.as_str())?
+                    }
+                    Some((PinMode::Indirect, _)) | None => None,
+                })
+            })
+        })
+        .await??;
+
+        value.map(|value| PinLabel::from_bytes(&value)).transpose()
+    }
+
     async fn query(
         &self,
         ids: Vec<Cid>,
@@ -469,6 +534,7 @@ impl PinStore for SledDataStore {
 }
 
 /// Name the empty value stored for direct pins; the pin key itself describes the mode and the cid.
+/// This is also the serialized form of an empty [`PinLabel`].
 fn direct_value() -> &'static [u8] {
     Default::default()
 }
@@ -0,0 +1,149 @@
+//! Encryption of the block data at rest, see [`Repo::set_block_cipher`](super::Repo::set_block_cipher).
+//!
+//! Only the data of the blocks is encrypted: their cids, the pins and the other keys of the
+//! datastore stay in plaintext so that the blocks can still be listed and looked up.
+
+use std::fmt::Debug;
+
+use libipld::Cid;
+#[cfg(feature = "block_encryption")]
+use {
+    chacha20poly1305::aead::{Aead, Payload},
+    chacha20poly1305::{KeyInit, XChaCha20Poly1305, XNonce},
+    rand::RngCore,
+    std::fmt,
+};
+
+use crate::error::Error;
+
+/// Datastore key marking the blocks of the repo as encrypted.
+pub(crate) const ENCRYPTED_KEY: &[u8] = b"/local/encrypted";
+
+/// Value of [`ENCRYPTED_KEY`] while the blocks are encrypted in place, not all of them being
+/// encrypted yet.
+pub(crate) const ENCRYPTING: &[u8] = b"in-progress";
+
+/// Encrypts the data of the blocks before they are written to the blockstore, and decrypts it
+/// when read.
+pub trait BlockCipher: Debug + Send + Sync + 'static {
+    /// Encrypts the data of the block with the cid.
+    fn encrypt(&self, cid: &Cid, data: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /// Decrypts the data of the block with the cid, failing with [`DecryptionFailed`] if it was
+    /// not encrypted with the same key.
+    fn decrypt(&self, cid: &Cid, data: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /// Number of bytes the encryption adds to the data of a block.
+    fn overhead(&self) -> usize;
+}
+
+/// The data of the block could not be decrypted, either with a wrong key or because it was
+/// corrupted on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("failed to decrypt block {0}: wrong encryption key or corrupted data")]
+pub struct DecryptionFailed(pub Cid);
+
+/// XChaCha20-Poly1305 with a random nonce prepended to each block, and the cid of the block as
+/// associated data so that the data of a block cannot be swapped with another one. Requires the
+/// `block_encryption` feature.
+#[cfg(feature = "block_encryption")]
+pub struct XChaCha20Poly1305Cipher {
+    cipher: XChaCha20Poly1305,
+}
+
+#[cfg(feature = "block_encryption")]
+impl XChaCha20Poly1305Cipher {
+    const NONCE_LEN: usize = 24;
+    const TAG_LEN: usize = 16;
+
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(&key.into()),
+        }
+    }
+}
+
+#[cfg(feature = "block_encryption")]
+impl Debug for XChaCha20Poly1305Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the key is not printed
+        f.debug_struct("XChaCha20Poly1305Cipher")
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "block_encryption")]
+impl BlockCipher for XChaCha20Poly1305Cipher {
+    fn encrypt(&self, cid: &Cid, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut nonce = [0u8; Self::NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let aad = cid.to_bytes();
+        let sealed = self
+            .cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: data,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("failed to encrypt block {cid}"))?;
+
+        let mut out = Vec::with_capacity(nonce.len() + sealed.len());
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&sealed);
+        Ok(out)
+    }
+
+    fn decrypt(&self, cid: &Cid, data: &[u8]) -> Result<Vec<u8>, Error> {
+        if data.len() < Self::NONCE_LEN + Self::TAG_LEN {
+            return Err(DecryptionFailed(*cid).into());
+        }
+        let (nonce, sealed) = data.split_at(Self::NONCE_LEN);
+
+        let aad = cid.to_bytes();
+        self.cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: sealed,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| DecryptionFailed(*cid).into())
+    }
+
+    fn overhead(&self) -> usize {
+        Self::NONCE_LEN + Self::TAG_LEN
+    }
+}
+
+#[cfg(all(test, feature = "block_encryption"))]
+mod tests {
+    use super::*;
+    use libipld::multihash::{Code, MultihashDigest};
+    use libipld::IpldCodec;
+
+    fn cid_of(data: &[u8]) -> Cid {
+        Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(data))
+    }
+
+    #[test]
+    fn data_is_only_decrypted_with_the_key_and_cid() {
+        let cipher = XChaCha20Poly1305Cipher::new([1; 32]);
+        let data = b"some block data";
+        let cid = cid_of(data);
+
+        let sealed = cipher.encrypt(&cid, data).unwrap();
+        assert_eq!(sealed.len(), data.len() + cipher.overhead());
+        assert_eq!(cipher.decrypt(&cid, &sealed).unwrap(), data);
+
+        let wrong_key = XChaCha20Poly1305Cipher::new([2; 32]);
+        let e = wrong_key.decrypt(&cid, &sealed).unwrap_err();
+        assert_eq!(e.downcast_ref(), Some(&DecryptionFailed(cid)));
+
+        let other = cid_of(b"other data");
+        assert!(cipher.decrypt(&other, &sealed).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+//! Retrieval of blocks from HTTP gateways implementing the trustless gateway specification, as a
+//! fallback for the blocks bitswap did not find in time.
+//!
+//! The configured gateways are requested concurrently and the first verified response is used.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use futures::stream::{FuturesUnordered, StreamExt};
+use libipld::Cid;
+use parking_lot::Mutex;
+use reqwest::{header::ACCEPT, Client, Url};
+
+use crate::car::{self, ImportError, MAX_SECTION_SIZE};
+use crate::error::Error;
+use crate::repo::{BlockSource, Repo};
+use crate::Block;
+
+/// Time a gateway which served data not matching the requested cid is skipped for.
+const UNHEALTHY_COOLDOWN: Duration = Duration::from_secs(5 * 60);
+
+/// Time given to a gateway to accept the connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time given to a gateway to serve the whole response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Largest block accepted, the same as the largest block section of an imported CAR.
+const MAX_BLOCK_SIZE: usize = MAX_SECTION_SIZE as usize;
+
+/// Largest CAR accepted, read in memory before its blocks are stored.
+const MAX_CAR_SIZE: usize = 64 * 1024 * 1024;
+
+/// Fetches verified blocks from the first of the gateways serving them.
+pub(crate) struct GatewayFetcher {
+    client: Client,
+    gateways: Vec<Url>,
+    /// Time given to bitswap before falling back to the gateways
+    pub(crate) delay: Duration,
+    /// Gateways which served corrupt data, with the time they are used again
+    unhealthy: Mutex<HashMap<Url, Instant>>,
+}
+
+impl GatewayFetcher {
+    pub(crate) fn new(gateways: Vec<Url>, delay: Duration) -> Self {
+        Self {
+            client: Client::builder()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("the tls backend can be initialized"),
+            gateways,
+            delay,
+            unhealthy: Default::default(),
+        }
+    }
+
+    fn healthy(&self) -> Vec<Url> {
+        let now = Instant::now();
+        let mut unhealthy = self.unhealthy.lock();
+        unhealthy.retain(|_, until| *until > now);
+        self.gateways
+            .iter()
+            .filter(|gateway| !unhealthy.contains_key(*gateway))
+            .cloned()
+            .collect()
+    }
+
+    fn mark_unhealthy(&self, gateway: &Url) {
+        warn!(%gateway, "gateway served corrupt data, skipping it for {UNHEALTHY_COOLDOWN:?}");
+        self.unhealthy
+            .lock()
+            .insert(gateway.clone(), Instant::now() + UNHEALTHY_COOLDOWN);
+    }
+
+    /// Requests the cid in the format, reading at most `limit` bytes of the response.
+    async fn request(
+        &self,
+        gateway: &Url,
+        cid: &Cid,
+        format: &str,
+        accept: &str,
+        limit: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let mut url = gateway.join(&format!("ipfs/{cid}"))?;
+        url.query_pairs_mut().append_pair("format", format);
+
+        let mut response = self
+            .client
+            .get(url)
+            .header(ACCEPT, accept)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let too_large = || anyhow!("response for {cid} is larger than {limit} bytes");
+
+        if matches!(response.content_length(), Some(len) if len > limit as u64) {
+            return Err(too_large());
+        }
+
+        let mut data = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if data.len() + chunk.len() > limit {
+                return Err(too_large());
+            }
+            data.extend_from_slice(&chunk);
+        }
+
+        Ok(data)
+    }
+
+    /// Requests the cid from every healthy gateway at once, yielding the responses as they
+    /// complete along with the gateway which served them.
+    fn request_all<'a>(
+        &'a self,
+        cid: &'a Cid,
+        format: &'a str,
+        accept: &'a str,
+        limit: usize,
+    ) -> FuturesUnordered<impl std::future::Future<Output = (Url, Result<Vec<u8>, Error>)> + 'a>
+    {
+        self.healthy()
+            .into_iter()
+            .map(|gateway| async move {
+                let res = self.request(&gateway, cid, format, accept, limit).await;
+                (gateway, res)
+            })
+            .collect()
+    }
+
+    /// Fetches the raw block, verified against its cid.
+    pub(crate) async fn fetch_block(&self, cid: &Cid) -> Result<Block, Error> {
+        let mut responses =
+            self.request_all(cid, "raw", "application/vnd.ipld.raw", MAX_BLOCK_SIZE);
+
+        while let Some((gateway, res)) = responses.next().await {
+            let data = match res {
+                Ok(data) => data,
+                Err(e) => {
+                    debug!(%gateway, %cid, "gateway request failed: {e}");
+                    continue;
+                }
+            };
+
+            match Block::new(*cid, data) {
+                Ok(block) => return Ok(block),
+                Err(_) => self.mark_unhealthy(&gateway),
+            }
+        }
+
+        Err(anyhow!("no gateway served {cid}"))
+    }
+
+    /// Fetches the DAG rooted at the cid as a CAR, storing the blocks reachable from the cid once
+    /// verified against their cids.
+    pub(crate) async fn fetch_dag(&self, repo: &Repo, cid: &Cid) -> Result<(), Error> {
+        let mut responses = self.request_all(
+            cid,
+            "car",
+            "application/vnd.ipld.car; version=1",
+            MAX_CAR_SIZE,
+        );
+
+        while let Some((gateway, res)) = responses.next().await {
+            let data = match res {
+                Ok(data) => data,
+                Err(e) => {
+                    debug!(%gateway, %cid, "gateway request failed: {e}");
+                    continue;
+                }
+            };
+
+            match car::import_dag(repo, &data[..], cid, BlockSource::Gateway).await {
+                Ok(0) => debug!(%gateway, %cid, "car from gateway does not contain the root"),
+                Ok(_) => return Ok(()),
+                Err(e) => match e.downcast_ref::<ImportError>() {
+                    Some(ImportError::CorruptBlock { .. }) => self.mark_unhealthy(&gateway),
+                    _ => debug!(%gateway, %cid, "invalid car from gateway: {e}"),
+                },
+            }
+        }
+
+        Err(anyhow!("no gateway served {cid}"))
+    }
+}
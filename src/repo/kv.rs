@@ -0,0 +1,210 @@
+//! Namespaced key-value entries of the applications, kept in the datastore of the repo next to
+//! the entries of the crate, see [`Repo::kv_put`].
+
+use futures::stream::BoxStream;
+use futures::StreamExt;
+
+use super::Repo;
+use crate::error::Error;
+
+/// Prefix of the keys of the entries in the datastore, followed by the namespace. The entries of
+/// the crate are all under `/local/`, out of reach of the namespaces.
+const KV_PREFIX: &str = "/user/";
+
+/// Maximum size of the value of an entry in bytes.
+pub const MAX_KV_VALUE_SIZE: usize = 1024 * 1024;
+
+/// The entry could not be written or read.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum KvError {
+    #[error("namespace {0:?} is empty or contains a '/'")]
+    InvalidNamespace(String),
+    #[error("value of {size} bytes exceeds the maximum of {max} bytes")]
+    ValueTooLarge { size: usize, max: usize },
+}
+
+fn namespace_prefix(namespace: &str) -> Result<Vec<u8>, Error> {
+    if namespace.is_empty() || namespace.contains('/') {
+        return Err(KvError::InvalidNamespace(namespace.to_owned()).into());
+    }
+    Ok(format!("{KV_PREFIX}{namespace}/").into_bytes())
+}
+
+fn kv_key(namespace: &str, key: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut prefixed = namespace_prefix(namespace)?;
+    prefixed.extend_from_slice(key);
+    Ok(prefixed)
+}
+
+fn check_size(value: &[u8]) -> Result<(), Error> {
+    if value.len() > MAX_KV_VALUE_SIZE {
+        return Err(KvError::ValueTooLarge {
+            size: value.len(),
+            max: MAX_KV_VALUE_SIZE,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+impl Repo {
+    /// Returns the value of the key in the namespace.
+    pub async fn kv_get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let key = kv_key(namespace, key)?;
+        self.data_store().get(&key).await
+    }
+
+    /// Puts the value under the key in the namespace, at most [`MAX_KV_VALUE_SIZE`] bytes.
+    pub async fn kv_put(&self, namespace: &str, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let key = kv_key(namespace, key)?;
+        check_size(value)?;
+
+        let _guard = self.inner.kv_lock.lock().await;
+        self.data_store().put(&key, value).await
+    }
+
+    /// Removes the key from the namespace, returning whether it was there.
+    pub async fn kv_delete(&self, namespace: &str, key: &[u8]) -> Result<bool, Error> {
+        let key = kv_key(namespace, key)?;
+
+        let _guard = self.inner.kv_lock.lock().await;
+        if !self.data_store().contains(&key).await? {
+            return Ok(false);
+        }
+        self.data_store().remove(&key).await?;
+        Ok(true)
+    }
+
+    /// Sets the value of the key in the namespace, or removes it if `None`, only if its current
+    /// value is `expected`, `None` meaning absent. Returns whether it was set.
+    pub async fn kv_compare_and_swap(
+        &self,
+        namespace: &str,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        value: Option<&[u8]>,
+    ) -> Result<bool, Error> {
+        let key = kv_key(namespace, key)?;
+        if let Some(value) = value {
+            check_size(value)?;
+        }
+
+        // the writes of the entries all take the lock, the swap cannot interleave with them
+        let _guard = self.inner.kv_lock.lock().await;
+        let current = self.data_store().get(&key).await?;
+        if current.as_deref() != expected {
+            return Ok(false);
+        }
+
+        match value {
+            Some(value) => self.data_store().put(&key, value).await?,
+            None if current.is_some() => self.data_store().remove(&key).await?,
+            None => {}
+        }
+        Ok(true)
+    }
+
+    /// Returns the entries of the namespace whose key starts with `prefix`, the keys without the
+    /// namespace.
+    pub async fn kv_query(
+        &self,
+        namespace: &str,
+        prefix: &[u8],
+    ) -> Result<BoxStream<'static, (Vec<u8>, Vec<u8>)>, Error> {
+        let namespace = namespace_prefix(namespace)?;
+        let prefix = prefix.to_vec();
+
+        let entries = self.data_store().iter().await;
+        Ok(entries
+            .filter_map(move |(key, value)| {
+                let entry = key
+                    .strip_prefix(namespace.as_slice())
+                    .filter(|key| key.starts_with(&prefix))
+                    .map(|key| (key.to_vec(), value));
+                futures::future::ready(entry)
+            })
+            .boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn namespaces_are_isolated() {
+        let repo = Repo::new_memory();
+        repo.init().await.unwrap();
+
+        repo.kv_put("app", b"height", b"12").await.unwrap();
+        repo.kv_put("app", b"name/a", b"1").await.unwrap();
+        repo.kv_put("other", b"name/b", b"2").await.unwrap();
+        assert_eq!(
+            repo.kv_get("app", b"height").await.unwrap(),
+            Some(b"12".to_vec())
+        );
+        assert_eq!(repo.kv_get("other", b"height").await.unwrap(), None);
+
+        let entries = repo
+            .kv_query("app", b"name/")
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(entries, vec![(b"name/a".to_vec(), b"1".to_vec())]);
+
+        // the namespaces cannot reach the keys of the crate
+        let err = repo
+            .kv_put("../local", b"filesroot", b"")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<KvError>(),
+            Some(KvError::InvalidNamespace(_))
+        ));
+        let err = repo
+            .kv_put("app", b"large", &vec![0; MAX_KV_VALUE_SIZE + 1])
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<KvError>(),
+            Some(KvError::ValueTooLarge { .. })
+        ));
+
+        assert!(repo.kv_delete("app", b"height").await.unwrap());
+        assert!(!repo.kv_delete("app", b"height").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn swaps_only_from_the_expected_value() {
+        let repo = Repo::new_memory();
+        repo.init().await.unwrap();
+
+        assert!(repo
+            .kv_compare_and_swap("app", b"key", None, Some(b"1"))
+            .await
+            .unwrap());
+        assert!(!repo
+            .kv_compare_and_swap("app", b"key", None, Some(b"2"))
+            .await
+            .unwrap());
+        assert!(repo
+            .kv_compare_and_swap("app", b"key", Some(b"1"), Some(b"2"))
+            .await
+            .unwrap());
+        assert_eq!(
+            repo.kv_get("app", b"key").await.unwrap(),
+            Some(b"2".to_vec())
+        );
+
+        assert!(!repo
+            .kv_compare_and_swap("app", b"key", Some(b"1"), None)
+            .await
+            .unwrap());
+        assert!(repo
+            .kv_compare_and_swap("app", b"key", Some(b"2"), None)
+            .await
+            .unwrap());
+        assert_eq!(repo.kv_get("app", b"key").await.unwrap(), None);
+    }
+}
@@ -1,26 +1,31 @@
 //! Storage implementation(s) backing the [`crate::Ipfs`].
-use crate::error::Error;
-use crate::{Block, StoragePath};
+use crate::error::{Error, ProtocolDisabled, ProtocolKind, Timeout};
+use crate::facade::FacadeError;
+use crate::{Block, RepoProvider, StoragePath};
 use anyhow::anyhow;
 use async_trait::async_trait;
 use core::fmt::Debug;
+use encryption::{BlockCipher, DecryptionFailed};
 use futures::channel::mpsc::{channel, Receiver, Sender};
+use futures::channel::oneshot;
 use futures::future::BoxFuture;
 use futures::sink::SinkExt;
 use futures::stream::{self, BoxStream, FuturesOrdered};
-use futures::{FutureExt, StreamExt, TryFutureExt, TryStreamExt};
+use futures::{FutureExt, StreamExt, TryStreamExt};
 use libipld::cid::Cid;
+use libipld::multihash::Multihash;
 use libipld::{Ipld, IpldCodec};
 use libp2p::identity::PeerId;
 use parking_lot::{Mutex, RwLock};
+use policy::{BlockPolicy, PolicyViolation};
 use std::borrow::Borrow;
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::path::Path;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 use std::{error, fmt, io};
-use tokio::sync::RwLockReadGuard;
+use tokio_util::sync::CancellationToken;
 use tracing::{log, Span};
 use tracing_futures::Instrument;
 
@@ -30,13 +35,39 @@ mod common_tests;
 
 pub mod blockstore;
 pub mod datastore;
+pub mod encryption;
 pub mod lock;
+pub mod policy;
 
 /// Path mangling done for pins and blocks
 pub(crate) mod paths;
 
+#[cfg(feature = "gateway_fallback")]
+mod gateway;
+mod kv;
+mod verify;
+mod wants;
+pub use kv::{KvError, MAX_KV_VALUE_SIZE};
+pub use verify::{RepairMode, VerifyHandle, VerifyProgress, VerifyReport};
+pub use wants::WantPersistence;
+use wants::WantWrite;
+
+/// Default number of blocks written at once by [`Repo::put_blocks`] callers.
+pub(crate) const PUT_BATCH_SIZE: usize = 64;
+
+/// Key of the totals of the blockstore saved on shutdown. It is removed once loaded or as soon as
+/// the totals change, so that the blocks are counted again after a crash.
+const BLOCK_STAT_KEY: &[u8] = b"/local/blockstat";
+
+/// How long a write exceeding the storage limit waits for the other writes to complete before
+/// making room, see [`Repo::ensure_capacity`].
+const GC_LOCK_WAIT: Duration = Duration::from_secs(5);
+
+/// Bitswap priority blocks are wanted at unless a caller asks for another one.
+pub const DEFAULT_WANT_PRIORITY: i32 = 1;
+
 /// Describes the outcome of `BlockStore::put_block`.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BlockPut {
     /// A new block was written to the blockstore.
     NewBlock,
@@ -44,6 +75,22 @@ pub enum BlockPut {
     Existed,
 }
 
+/// Blocks written and blocks deduplicated by a series of puts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PutStats {
+    pub new_blocks: usize,
+    pub existing_blocks: usize,
+}
+
+impl PutStats {
+    pub(crate) fn record(&mut self, put: BlockPut) {
+        match put {
+            BlockPut::NewBlock => self.new_blocks += 1,
+            BlockPut::Existed => self.existing_blocks += 1,
+        }
+    }
+}
+
 /// Describes the outcome of `BlockStore::remove`.
 #[derive(Debug)]
 pub enum BlockRm {
@@ -61,6 +108,24 @@ pub enum BlockRmError {
     NotFound(Cid),
 }
 
+/// The cids a block with the multihash is usually stored under, see
+/// [`BlockStore::cids_by_multihash`].
+pub(crate) fn multihash_cids(hash: &Multihash) -> Vec<Cid> {
+    let mut cids = Vec::with_capacity(5);
+    if let Ok(cid) = Cid::new_v0(*hash) {
+        cids.push(cid);
+    }
+    for codec in [
+        IpldCodec::Raw,
+        IpldCodec::DagPb,
+        IpldCodec::DagCbor,
+        IpldCodec::DagJson,
+    ] {
+        cids.push(Cid::new_v1(codec.into(), *hash));
+    }
+    cids
+}
+
 /// This API is being discussed and evolved, which will likely lead to breakage.
 #[async_trait]
 pub trait BlockStore: Debug + Send + Sync + 'static {
@@ -69,6 +134,21 @@ pub trait BlockStore: Debug + Send + Sync + 'static {
     async fn open(&self) -> Result<(), Error>;
     /// Returns whether a block is present in the blockstore.
     async fn contains(&self, cid: &Cid) -> Result<bool, Error>;
+    /// Returns the cids of the stored blocks with the multihash, whatever the codec and version of
+    /// the cid they were stored under.
+    ///
+    /// The default implementation only looks for the CIDv0 and the CIDv1 of the raw, dag-pb,
+    /// dag-cbor and dag-json codecs, stores able to look blocks up by multihash should override
+    /// it.
+    async fn cids_by_multihash(&self, hash: &Multihash) -> Result<Vec<Cid>, Error> {
+        let mut cids = vec![];
+        for cid in multihash_cids(hash) {
+            if self.contains(&cid).await? {
+                cids.push(cid);
+            }
+        }
+        Ok(cids)
+    }
     /// Returns a block from the blockstore.
     async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error>;
     /// Get the size of a single block
@@ -77,12 +157,109 @@ pub trait BlockStore: Debug + Send + Sync + 'static {
     async fn total_size(&self) -> Result<usize, Error>;
     /// Inserts a block in the blockstore.
     async fn put(&self, block: Block) -> Result<(Cid, BlockPut), Error>;
+    /// Inserts multiple blocks in the blockstore, in a single batch when the store supports it.
+    async fn put_many(&self, blocks: Vec<Block>) -> Result<Vec<(Cid, BlockPut)>, Error> {
+        let mut puts = Vec::with_capacity(blocks.len());
+        for block in blocks {
+            puts.push(self.put(block).await?);
+        }
+        Ok(puts)
+    }
+    /// Returns multiple blocks from the blockstore, in the order of the given cids.
+    async fn get_many(&self, cids: &[Cid]) -> Result<Vec<Option<Block>>, Error> {
+        let mut blocks = Vec::with_capacity(cids.len());
+        for cid in cids {
+            blocks.push(self.get(cid).await?);
+        }
+        Ok(blocks)
+    }
     /// Removes a block from the blockstore.
     async fn remove(&self, cid: &Cid) -> Result<(), Error>;
+    /// Replaces the data of a stored block, e.g. once encrypted, without the block going missing
+    /// in between.
+    ///
+    /// The default implementation removes the block before putting it again, so the block is
+    /// lost if interrupted in between.
+    async fn replace(&self, block: Block) -> Result<(), Error> {
+        self.remove(block.cid()).await?;
+        self.put(block).await.map(|_| ())
+    }
     /// Remove multiple blocks from the blockstore
     async fn remove_many(&self, blocks: BoxStream<'static, Cid>) -> BoxStream<'static, Cid>;
     /// Returns a list of the blocks (Cids), in the blockstore.
     async fn list(&self) -> BoxStream<'static, Cid>;
+    /// Returns the blocks in the blockstore with the size of their stored data, without locking
+    /// the store while the stream is consumed.
+    ///
+    /// The default implementation looks up the size of each listed block before returning.
+    async fn list_entries(&self) -> BoxStream<'static, BlockEntry> {
+        let cids = self.list().await.collect::<Vec<_>>().await;
+        let mut entries = Vec::with_capacity(cids.len());
+        for cid in cids {
+            if let Ok(Some(size)) = self.size(&[cid]).await {
+                entries.push(BlockEntry {
+                    cid,
+                    size,
+                    written_at: None,
+                });
+            }
+        }
+        stream::iter(entries).boxed()
+    }
+}
+
+/// A block listed by [`BlockStore::list_entries`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockEntry {
+    pub cid: Cid,
+    /// Size of the data as stored, including the encryption overhead when encrypted at rest
+    pub size: usize,
+    /// When the block was written, if the store records it
+    pub written_at: Option<SystemTime>,
+}
+
+/// Restricts the blocks listed by [`Repo::list_blocks`]. The default filter lists every block.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockFilter {
+    /// Only the blocks with this codec
+    pub codec: Option<u64>,
+    /// Only the blocks with at least this many bytes of data
+    pub min_size: Option<usize>,
+    /// Only the blocks with at most this many bytes of data
+    pub max_size: Option<usize>,
+    /// Only the pinned blocks, including the indirectly pinned ones, or only the unpinned ones
+    pub pinned: Option<bool>,
+    /// Only the blocks written at or after this time. Blocks of a store which does not record
+    /// when they were written never match.
+    pub written_after: Option<SystemTime>,
+    /// Only the blocks written before this time. Blocks of a store which does not record when
+    /// they were written never match.
+    pub written_before: Option<SystemTime>,
+}
+
+impl BlockFilter {
+    /// Checks everything but whether the block is pinned.
+    fn matches(&self, cid: &Cid, size: usize, written_at: Option<SystemTime>) -> bool {
+        if matches!(self.codec, Some(codec) if codec != cid.codec()) {
+            return false;
+        }
+        if matches!(self.min_size, Some(min) if size < min)
+            || matches!(self.max_size, Some(max) if size > max)
+        {
+            return false;
+        }
+        if self.written_after.is_some() || self.written_before.is_some() {
+            let Some(written_at) = written_at else {
+                return false;
+            };
+            if matches!(self.written_after, Some(after) if written_at < after)
+                || matches!(self.written_before, Some(before) if written_at >= before)
+            {
+                return false;
+            }
+        }
+        true
+    }
 }
 
 #[async_trait]
@@ -100,6 +277,11 @@ pub trait DataStore: PinStore + Debug + Send + Sync + 'static {
     async fn remove(&self, key: &[u8]) -> Result<(), Error>;
     /// Iterate over the k/v of the datastore
     async fn iter(&self) -> futures::stream::BoxStream<'static, (Vec<u8>, Vec<u8>)>;
+    /// Puts the block and pins it directly in a single transaction, when the blocks of the repo
+    /// are kept in this datastore. Returns `None` when they are not, which is the default.
+    async fn put_pinned_block(&self, _block: &Block) -> Result<Option<BlockPut>, Error> {
+        Ok(None)
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -205,6 +387,25 @@ pub trait PinStore: Debug + Send + Sync + Unpin + 'static {
         referenced: References<'_>,
     ) -> Result<(), Error>;
 
+    /// Writes the recursive pins of the `insert` roots, then removes the recursive pins of the
+    /// `remove` roots. Stores supporting transactions make all the changes or none of them.
+    ///
+    /// The default implementation makes the changes one after the other, in that order, so that a
+    /// root replaced by another stays pinned until the other is.
+    async fn update_recursive_pins(
+        &self,
+        insert: Vec<(Cid, References<'_>)>,
+        remove: Vec<(Cid, References<'_>)>,
+    ) -> Result<(), Error> {
+        for (target, referenced) in insert {
+            self.insert_recursive_pin(&target, referenced).await?;
+        }
+        for (target, referenced) in remove {
+            self.remove_recursive_pin(&target, referenced).await?;
+        }
+        Ok(())
+    }
+
     async fn list(
         &self,
         mode: Option<PinMode>,
@@ -222,6 +423,14 @@ pub trait PinStore: Debug + Send + Sync + Unpin + 'static {
         ids: Vec<Cid>,
         requirement: Option<PinMode>,
     ) -> Result<Vec<(Cid, PinKind<Cid>)>, Error>;
+
+    /// Replaces the label of a direct or recursive pin. Fails if the `target` is not pinned or is
+    /// only pinned indirectly.
+    async fn set_label(&self, target: &Cid, label: &PinLabel) -> Result<(), Error>;
+
+    /// Returns the label of a direct or recursive pin, or `None` if `target` is not pinned or is
+    /// only pinned indirectly. Pins which were never labeled have an empty label.
+    async fn label(&self, target: &Cid) -> Result<Option<PinLabel>, Error>;
 }
 
 /// `PinMode` is the description of pin type for quering purposes.
@@ -311,7 +520,98 @@ impl<C: Borrow<Cid>> PinKind<C> {
     }
 }
 
-type SubscriptionsMap = HashMap<Cid, Vec<futures::channel::oneshot::Sender<Result<Block, String>>>>;
+/// Upper bound for the serialized size of a [`PinLabel`].
+pub const MAX_PIN_LABEL_SIZE: usize = 4 * 1024;
+
+/// Optional name and small metadata map attached to a direct or recursive pin.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PinLabel {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    /// The root is provided through the DHT while pinned, see [`RepoInsertPin::provide`]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub provide: bool,
+}
+
+impl PinLabel {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.metadata.is_empty() && !self.provide
+    }
+
+    /// Serialized form used by the pinstores. An empty label is stored as an empty value, which
+    /// is also what pins written before labels existed contain.
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        if self.is_empty() {
+            return Ok(Vec::new());
+        }
+        let bytes = serde_json::to_vec(self)?;
+        if bytes.len() > MAX_PIN_LABEL_SIZE {
+            anyhow::bail!(
+                "pin label is {} bytes, which exceeds the limit of {MAX_PIN_LABEL_SIZE} bytes",
+                bytes.len()
+            );
+        }
+        Ok(bytes)
+    }
+
+    /// Inverse of [`PinLabel::to_bytes`].
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.is_empty() {
+            return Ok(PinLabel::default());
+        }
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// The requests waiting for each block, with the bitswap session they were made in.
+type SubscriptionsMap = HashMap<
+    Cid,
+    Vec<(
+        Option<u64>,
+        futures::channel::oneshot::Sender<Result<Block, String>>,
+    )>,
+>;
+
+/// A request waiting for a block to be fetched. The block is only wanted from the network by the
+/// first request of each session, and unwanted in the session once every request of the session
+/// has been dropped without receiving it.
+struct BlockSubscription {
+    repo: Repo,
+    session: Option<u64>,
+    cid: Cid,
+    rx: oneshot::Receiver<Result<Block, String>>,
+}
+
+impl Drop for BlockSubscription {
+    fn drop(&mut self) {
+        // the sender is canceled from now on
+        self.rx.close();
+
+        let mut subscriptions = self.repo.inner.subscriptions.lock();
+        let Some(waiters) = subscriptions.get_mut(&self.cid) else {
+            // the block was received, or the fetch failed
+            return;
+        };
+        waiters.retain(|(_, tx)| !tx.is_canceled());
+        if waiters.iter().any(|(session, _)| *session == self.session) {
+            return;
+        }
+        if waiters.is_empty() {
+            subscriptions.remove(&self.cid);
+        }
+
+        // unwanted before releasing the subscriptions, so that a get registering for the block
+        // afterwards sends its want after this unwant and is not cancelled by it
+        self.repo
+            .persist_want(|| WantWrite::Unwant(self.session, self.cid));
+        if let Some(mut events) = self.repo.repo_channel() {
+            _ = events.try_send(RepoEvent::UnwantBlock(self.session, self.cid));
+        }
+        drop(subscriptions);
+    }
+}
 
 /// Describes a repo.
 ///
@@ -326,14 +626,57 @@ pub struct Repo {
 #[derive(Debug)]
 pub(crate) struct RepoInner {
     online: AtomicBool,
+    /// Never fetch blocks from the network, see [`Repo::set_local_only`].
+    local_only: AtomicBool,
+    /// Missing blocks are only fetched from the fallback gateways, see
+    /// [`Repo::set_bitswap_enabled`].
+    bitswap_disabled: AtomicBool,
     initialized: AtomicBool,
     max_storage_size: AtomicUsize,
+    /// Collect garbage when a write would exceed the storage limit.
+    gc_auto: AtomicBool,
+    /// Totals of the blockstore, loaded or counted on first use and kept up to date on writes and
+    /// removals.
+    block_stat: tokio::sync::OnceCell<Mutex<BlockStat>>,
+    /// Whether the totals are saved under [`BLOCK_STAT_KEY`], see [`Repo::save_block_stat`].
+    block_stat_saved: AtomicBool,
+    eviction: Mutex<Eviction>,
+    lru: Mutex<LruState>,
+    evictions: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
     block_store: Box<dyn BlockStore>,
     data_store: Box<dyn DataStore>,
     events: RwLock<Option<Sender<RepoEvent>>>,
+    /// Channels of the nodes attached to the repo, closed along with `events`.
+    attached: Mutex<Vec<Sender<RepoEvent>>>,
     pub(crate) subscriptions: Mutex<SubscriptionsMap>,
     lockfile: Box<dyn Lock>,
-    pub(crate) gclock: tokio::sync::RwLock<()>,
+    pub(crate) gclock: GCLock,
+    /// Blocks may be removed by the garbage collector only after being in the store this long.
+    gc_grace_period: Mutex<Duration>,
+    /// When the blocks were written, oldest first, tracked while there is a grace period.
+    recent_blocks: Mutex<VecDeque<(Cid, Instant)>>,
+    /// Roots of the recursive pins being written, with the number of pins for each.
+    pins_in_progress: Mutex<HashMap<Cid, usize>>,
+    /// Which of the new blocks and pins are provided, see [`Repo::set_provider`].
+    provider: Mutex<RepoProvider>,
+    /// Gateways the blocks bitswap did not find in time are fetched from, if any.
+    #[cfg(feature = "gateway_fallback")]
+    gateway: Mutex<Option<Arc<gateway::GatewayFetcher>>>,
+    /// Cipher of the block data, if encrypted at rest.
+    cipher: RwLock<Option<Arc<dyn BlockCipher>>>,
+    /// Whether the blocks are marked as encrypted, and as still being encrypted in place.
+    encrypted: AtomicBool,
+    encrypting: AtomicBool,
+    /// Policy the blocks are checked against before being written, if any.
+    block_policy: RwLock<Option<Arc<dyn BlockPolicy>>>,
+    /// Writer of the wants, if persisted.
+    wants: tokio::sync::OnceCell<futures::channel::mpsc::UnboundedSender<WantWrite>>,
+    /// Serializes the writes of the key-value entries, see [`Repo::kv_compare_and_swap`].
+    kv_lock: tokio::sync::Mutex<()>,
+    /// Sender of the notifications, dropped with the repo which ends the subscriptions.
+    notifications: tokio::sync::broadcast::Sender<RepoNotification>,
 }
 
 #[cfg(feature = "beetle_bitswap")]
@@ -378,7 +721,9 @@ impl libp2p_bitswap_next::BitswapStore for Repo {
     }
 
     async fn insert(&mut self, block: &libipld::Block<Self::Params>) -> anyhow::Result<()> {
-        self.put_block(block.clone()).await.map(|_| ())
+        self.put_block_from(block.clone(), BlockSource::Bitswap(None))
+            .await
+            .map(|_| ())
     }
 
     async fn missing_blocks(&mut self, cid: &Cid) -> anyhow::Result<Vec<Cid>> {
@@ -399,21 +744,75 @@ impl libp2p_bitswap_next::BitswapStore for Repo {
 /// Events used to communicate to the swarm on repo changes.
 #[derive(Debug)]
 pub enum RepoEvent {
-    /// Signals a desired block.
-    WantBlock(Option<u64>, Vec<Cid>, Vec<PeerId>),
-    /// Signals a desired block is no longer wanted.
-    UnwantBlock(Cid),
-    /// Signals the posession of a new block.
-    NewBlock(Block),
+    /// Signals a desired block, with the bitswap priority it is wanted at.
+    WantBlock(Option<u64>, Vec<Cid>, Vec<PeerId>, i32),
+    /// Signals a desired block is no longer wanted in the session.
+    UnwantBlock(Option<u64>, Cid),
+    /// Signals the posession of a new block, expecting the outcome of providing it in return when
+    /// every block is provided.
+    NewBlock(Block, Option<oneshot::Sender<Result<(), Error>>>),
+    /// Signals a new direct or recursive pin, with the blocks pinned indirectly by a recursive pin
+    /// when the pinned blocks are provided.
+    NewPin(Cid, Vec<Cid>),
+    /// Signals a pin whose root is to be provided, whatever the provider of the node.
+    ProvidePin(Cid),
     /// Signals the removal of a block.
     RemovedBlock(Cid),
 }
 
+/// Number of notifications a subscriber of [`Repo::subscribe`] may fall behind by before missing
+/// the oldest ones.
+const NOTIFICATION_CAPACITY: usize = 1024;
+
+/// Changes of the blocks and pins of the repo, see [`Repo::subscribe`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepoNotification {
+    /// A block was written, not sent for the blocks already stored.
+    BlockAdded {
+        cid: Cid,
+        size: usize,
+        source: BlockSource,
+    },
+    BlockRemoved {
+        cid: Cid,
+    },
+    /// A direct or recursive pin was added.
+    PinAdded {
+        cid: Cid,
+        mode: PinMode,
+    },
+    PinRemoved {
+        cid: Cid,
+    },
+}
+
+/// Where a block written to the repo came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockSource {
+    /// Put by the application or the node itself
+    Local,
+    /// Fetched over bitswap, from the peer if known. The alternative bitswap implementations do
+    /// not tell which peer sent the block.
+    Bitswap(Option<PeerId>),
+    /// Fetched from a fallback gateway
+    Gateway,
+    /// Imported from a CAR file
+    Import,
+}
+
 impl Repo {
     pub fn new(repo_type: &mut StoragePath) -> Self {
         match repo_type {
             StoragePath::Memory => Repo::new_memory(),
             StoragePath::Disk(path) => Repo::new_fs(path),
+            StoragePath::Flatfs {
+                path,
+                blocks,
+                layout,
+                read_only,
+            } => Repo::new_flatfs(path, blocks, *layout, *read_only),
+            #[cfg(feature = "redb_data_store")]
+            StoragePath::Redb(path) => Repo::new_redb(path),
             StoragePath::Custom {
                 blockstore,
                 datastore,
@@ -435,13 +834,37 @@ impl Repo {
         let inner = RepoInner {
             initialized: AtomicBool::default(),
             online: AtomicBool::default(),
+            local_only: AtomicBool::default(),
+            bitswap_disabled: AtomicBool::default(),
             block_store,
             data_store,
             events: Default::default(),
+            attached: Default::default(),
             subscriptions: Default::default(),
             lockfile,
             max_storage_size: Default::default(),
+            gc_auto: Default::default(),
+            block_stat: Default::default(),
+            block_stat_saved: Default::default(),
+            eviction: Default::default(),
+            lru: Default::default(),
+            evictions: Default::default(),
+            hits: Default::default(),
+            misses: Default::default(),
             gclock: Default::default(),
+            gc_grace_period: Default::default(),
+            recent_blocks: Default::default(),
+            pins_in_progress: Default::default(),
+            provider: Default::default(),
+            #[cfg(feature = "gateway_fallback")]
+            gateway: Default::default(),
+            cipher: Default::default(),
+            encrypted: Default::default(),
+            encrypting: Default::default(),
+            block_policy: Default::default(),
+            wants: Default::default(),
+            kv_lock: Default::default(),
+            notifications: tokio::sync::broadcast::channel(NOTIFICATION_CAPACITY).0,
         };
         Repo {
             inner: Arc::new(inner),
@@ -449,15 +872,52 @@ impl Repo {
     }
 
     pub fn new_fs(path: impl AsRef<Path>) -> Self {
+        let mut blockstore_path = path.as_ref().to_path_buf();
+        blockstore_path.push("blockstore");
+
+        let block_store = Box::new(blockstore::flatfs::FsBlockStore::new(blockstore_path));
+        Self::new_fs_with_block_store(path, block_store)
+    }
+
+    /// Creates a repo like [`Repo::new_fs`], except the blocks are kept at `blocks` in the given
+    /// layout. See [`StoragePath::Flatfs`].
+    pub fn new_flatfs(
+        path: impl AsRef<Path>,
+        blocks: impl AsRef<Path>,
+        layout: blockstore::flatfs::FsLayout,
+        read_only: bool,
+    ) -> Self {
+        let blocks = blocks.as_ref().to_path_buf();
+        let block_store = match read_only {
+            true => blockstore::flatfs::FsBlockStore::read_only(blocks, layout),
+            false => blockstore::flatfs::FsBlockStore::with_layout(blocks, layout),
+        };
+        Self::new_fs_with_block_store(path, Box::new(block_store))
+    }
+
+    /// Creates a repo keeping both the blocks and the data in a single redb database, so that
+    /// [`Repo::put_pinned_block`] is atomic. See [`StoragePath::Redb`].
+    #[cfg(feature = "redb_data_store")]
+    pub fn new_redb(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let mut datastore_path = path.clone();
+        let mut lockfile_path = path;
+        datastore_path.push("datastore");
+        lockfile_path.push("repo_lock");
+
+        let data_store = datastore::redb::RedbDataStore::with_blocks(datastore_path);
+        let block_store = Box::new(data_store.block_store());
+        let lockfile = Box::new(lock::FsLock::new(lockfile_path));
+        Self::new_raw(block_store, Box::new(data_store), lockfile)
+    }
+
+    fn new_fs_with_block_store(path: impl AsRef<Path>, block_store: Box<dyn BlockStore>) -> Self {
         let path = path.as_ref().to_path_buf();
-        let mut blockstore_path = path.clone();
         let mut datastore_path = path.clone();
         let mut lockfile_path = path;
-        blockstore_path.push("blockstore");
         datastore_path.push("datastore");
         lockfile_path.push("repo_lock");
 
-        let block_store = Box::new(blockstore::flatfs::FsBlockStore::new(blockstore_path));
         #[cfg(not(any(feature = "sled_data_store", feature = "redb_data_store")))]
         let data_store = Box::new(datastore::flatfs::FsDataStore::new(datastore_path));
         #[cfg(feature = "sled_data_store")]
@@ -483,17 +943,244 @@ impl Repo {
         self.inner.max_storage_size.load(Ordering::SeqCst)
     }
 
+    /// Sets whether garbage is collected when writing a block would exceed the storage limit set
+    /// with [`Repo::set_max_storage_size`], instead of failing right away with [`StorageFull`].
+    pub fn set_gc_auto(&self, enabled: bool) {
+        self.inner.gc_auto.store(enabled, Ordering::SeqCst);
+    }
+
+    pub fn gc_auto(&self) -> bool {
+        self.inner.gc_auto.load(Ordering::SeqCst)
+    }
+
+    /// Sets how blocks are evicted when writing would exceed the storage limit set with
+    /// [`Repo::set_max_storage_size`].
+    pub fn set_eviction(&self, eviction: Eviction) {
+        *self.inner.eviction.lock() = eviction;
+        if eviction == Eviction::None {
+            self.inner.lru.lock().accessed.clear();
+        }
+    }
+
+    pub fn eviction(&self) -> Eviction {
+        *self.inner.eviction.lock()
+    }
+
+    /// Records an access to the blocks, when evicting the least recently accessed ones.
+    fn record_access<'a>(&self, cids: impl IntoIterator<Item = &'a Cid>) {
+        if self.eviction() != Eviction::Lru {
+            return;
+        }
+        let lru = &mut *self.inner.lru.lock();
+        for cid in cids {
+            lru.tick += 1;
+            lru.accessed.insert(*cid, lru.tick);
+        }
+    }
+
+    /// The storage limit in bytes, zero meaning unlimited.
+    fn storage_max(&self) -> Option<u64> {
+        match self.max_storage_size() {
+            0 => None,
+            max => Some(max as u64),
+        }
+    }
+
+    /// Returns the number of blocks once counted by [`Repo::stat`] or by the first write.
+    #[cfg(feature = "metrics")]
+    pub(crate) fn num_blocks(&self) -> Option<u64> {
+        self.inner.block_stat.get().map(|stat| stat.lock().blocks)
+    }
+
+    /// Returns the number of blocks and their total size, along with the storage limit.
+    pub async fn stat(&self) -> Result<RepoStat, Error> {
+        let stat = *self.block_stat().await?.lock();
+        Ok(RepoStat {
+            num_blocks: stat.blocks,
+            size_bytes: stat.bytes,
+            storage_max: self.storage_max(),
+            evictions: self.inner.evictions.load(Ordering::Relaxed),
+            hits: self.inner.hits.load(Ordering::Relaxed),
+            misses: self.inner.misses.load(Ordering::Relaxed),
+        })
+    }
+
+    async fn block_stat(&self) -> Result<&Mutex<BlockStat>, Error> {
+        self.inner
+            .block_stat
+            .get_or_try_init(|| async {
+                // the totals saved on shutdown are only used once, as they are not kept up to date
+                let data_store = &self.inner.data_store;
+                if let Some(saved) = data_store.get(BLOCK_STAT_KEY).await? {
+                    data_store.remove(BLOCK_STAT_KEY).await?;
+                    match serde_json::from_slice::<BlockStat>(&saved) {
+                        Ok(stat) => return Ok(Mutex::new(stat)),
+                        Err(e) => warn!("counting the blocks, the saved totals are invalid: {e}"),
+                    }
+                }
+
+                let bytes = self.inner.block_store.total_size().await? as u64;
+                let blocks = self.inner.block_store.list().await.count().await as u64;
+                Ok::<_, Error>(Mutex::new(BlockStat {
+                    blocks,
+                    bytes,
+                    reserved: 0,
+                }))
+            })
+            .await
+    }
+
+    /// Saves the totals of the blockstore, so that the next start does not count the blocks again.
+    /// Called on shutdown, once the pending writes completed.
+    pub(crate) async fn save_block_stat(&self) -> Result<(), Error> {
+        let Some(stat) = self.inner.block_stat.get() else {
+            return Ok(());
+        };
+
+        let saved = *stat.lock();
+        self.inner
+            .data_store
+            .put(BLOCK_STAT_KEY, &serde_json::to_vec(&saved)?)
+            .await?;
+        self.inner.block_stat_saved.store(true, Ordering::SeqCst);
+
+        // the totals changing from now on remove the saved ones
+        let current = *stat.lock();
+        if (current.blocks, current.bytes) != (saved.blocks, saved.bytes) {
+            self.forget_block_stat().await;
+        }
+        Ok(())
+    }
+
+    /// Adds a newly written block of `stored` bytes to the totals of the blockstore.
+    async fn count_block(&self, stored: u64) {
+        if let Some(stat) = self.inner.block_stat.get() {
+            let mut stat = stat.lock();
+            stat.blocks += 1;
+            stat.bytes += stored;
+        }
+        self.forget_block_stat().await;
+    }
+
+    /// Removes the saved totals of the blockstore once they changed.
+    async fn forget_block_stat(&self) {
+        if self.inner.block_stat_saved.swap(false, Ordering::SeqCst) {
+            if let Err(e) = self.inner.data_store.remove(BLOCK_STAT_KEY).await {
+                warn!("failed to remove the saved totals of the blockstore: {e}");
+            }
+        }
+    }
+
+    /// Reserves room for the blocks, except those already stored, within the storage limit, or
+    /// fails with [`StorageFull`]. The room stays reserved, so that concurrent writes cannot exceed
+    /// the limit together, until the returned [`Reservation`] is dropped once the blocks are
+    /// accounted for.
+    ///
+    /// With [`Eviction::Lru`] the least recently accessed unpinned blocks are evicted first, and
+    /// with [`Repo::gc_auto`] enabled garbage is collected first. Removing blocks waits up to
+    /// [`GC_LOCK_WAIT`] for the other writes to complete, and is given up while a
+    /// [`Repo::gc_guard`] is held for longer.
+    async fn ensure_capacity(&self, blocks: &[Block]) -> Result<Reservation<'_>, Error> {
+        let Some(storage_max) = self.storage_max() else {
+            return Ok(Reservation::default());
+        };
+
+        let mut needed = 0;
+        for block in blocks {
+            if !self.contains(block.cid()).await? {
+                needed += (block.data().len() + self.cipher_overhead()) as u64;
+            }
+        }
+
+        let stat = self.block_stat().await?;
+        let reserve = || {
+            let mut totals = stat.lock();
+            let fits = totals.bytes + totals.reserved + needed <= storage_max;
+            if fits {
+                totals.reserved += needed;
+            }
+            fits.then_some(Reservation {
+                stat: Some(stat),
+                bytes: needed,
+            })
+        };
+        if let Some(reservation) = reserve() {
+            return Ok(reservation);
+        }
+
+        let eviction = self.eviction();
+        if eviction == Eviction::Lru || self.gc_auto() {
+            match tokio::time::timeout(GC_LOCK_WAIT, self.inner.gclock.write()).await {
+                Ok(_g) => {
+                    let GCResult { removed, reclaimed } = match eviction {
+                        // leaving some room, so that the following writes do not evict right away
+                        Eviction::Lru => {
+                            let low_watermark = storage_max - storage_max / 10;
+                            let reserved = stat.lock().reserved;
+                            let target = storage_max
+                                .saturating_sub(needed + reserved)
+                                .min(low_watermark);
+                            self.evict(target).await?
+                        }
+                        Eviction::None => self.cleanup().await?,
+                    };
+                    debug!(
+                        removed = removed.len(),
+                        reclaimed, "removed blocks to make room for {} bytes", needed
+                    );
+                    if let Some(reservation) = reserve() {
+                        return Ok(reservation);
+                    }
+                }
+                Err(_) => debug!("no room made for {needed} bytes, the gc lock is held"),
+            }
+        }
+
+        Err(StorageFull {
+            needed,
+            storage_max,
+        }
+        .into())
+    }
+
+    /// Sets how long newly written blocks are spared by the garbage collector, giving time to
+    /// pin them after they have been put.
+    pub fn set_gc_grace_period(&self, duration: Duration) {
+        *self.inner.gc_grace_period.lock() = duration;
+    }
+
+    pub fn gc_grace_period(&self) -> Duration {
+        *self.inner.gc_grace_period.lock()
+    }
+
+    /// Sets which of the blocks written and pinned from now on are provided, so that the
+    /// ipfs task is only asked for the outcome of providing the new blocks when it provides them.
+    pub(crate) fn set_provider(&self, provider: RepoProvider) {
+        *self.inner.provider.lock() = provider;
+    }
+
+    fn provider(&self) -> RepoProvider {
+        *self.inner.provider.lock()
+    }
+
     pub async fn migrate(&self, repo: &Self) -> Result<(), Error> {
         if self.is_online() || repo.is_online() {
             anyhow::bail!("Repository cannot be online");
         }
         let block_migration = {
             async move {
-                let mut stream = self.list_blocks().await;
-                while let Some(cid) = stream.next().await {
+                let mut stream = self.list_blocks(BlockFilter::default()).await;
+                while let Some((cid, _)) = stream.next().await {
                     match self.get_block_now(&cid).await {
-                        Ok(Some(block)) => match repo.inner.block_store.put(block).await {
-                            Ok(_) => {}
+                        Ok(Some(block)) => match repo.seal(&block) {
+                            Ok(block) => {
+                                let stored = block.data().len() as u64;
+                                match repo.inner.block_store.put(block).await {
+                                    Ok((_, BlockPut::NewBlock)) => repo.count_block(stored).await,
+                                    Ok(_) => {}
+                                    Err(e) => error!("Error migrating {cid}: {e}"),
+                                }
+                            }
                             Err(e) => error!("Error migrating {cid}: {e}"),
                         },
                         Ok(None) => error!("{cid} doesnt exist"),
@@ -507,6 +1194,11 @@ impl Repo {
             async move {
                 let mut data_stream = self.data_store().iter().await;
                 while let Some((k, v)) = data_stream.next().await {
+                    // whether the blocks are encrypted depends on the cipher of the target, and
+                    // the saved totals on its blocks
+                    if k == encryption::ENCRYPTED_KEY || k == BLOCK_STAT_KEY {
+                        continue;
+                    }
                     if let Err(e) = repo.data_store().put(&k, &v).await {
                         error!("Unable to migrate {k:?} into repo: {e}");
                     }
@@ -570,15 +1262,32 @@ impl Repo {
         receiver
     }
 
+    /// Returns the channel of a node attached to the repo started by another node, see
+    /// [`UninitializedIpfs::attach_repo`](crate::UninitializedIpfs::attach_repo). Only the blocks
+    /// written are sent over it, without a channel to answer, the node the repo was started with
+    /// fetching and providing the blocks, and it is closed along with the channel of that node.
+    pub(crate) fn attach_channel(&self) -> Receiver<RepoEvent> {
+        let (sender, receiver) = channel(1);
+        let mut attached = self.inner.attached.lock();
+        attached.retain(|sender| !sender.is_closed());
+        attached.push(sender);
+        receiver
+    }
+
     /// Shutdowns the repo, cancelling any pending subscriptions; Likely going away after some
     /// refactoring, see notes on [`crate::Ipfs::exit_daemon`].
     pub fn shutdown(&self) {
+        // the requests cancelled from now on are resumed on the next start
+        self.stop_persisting_wants();
         let mut map = self.inner.subscriptions.lock();
         map.clear();
         drop(map);
         if let Some(mut event) = self.inner.events.write().take() {
             event.close_channel()
         }
+        for mut event in self.inner.attached.lock().drain(..) {
+            event.close_channel()
+        }
         self.set_offline();
     }
 
@@ -586,86 +1295,572 @@ impl Repo {
         self.inner.online.load(Ordering::SeqCst)
     }
 
-    pub(crate) fn set_online(&self) {
-        if self.is_online() {
-            return;
-        }
+    /// Sets whether blocks missing from the blockstore fail with [`BlockNotLocal`] instead of being
+    /// fetched from the network, as for a node started offline.
+    pub fn set_local_only(&self, local_only: bool) {
+        self.inner.local_only.store(local_only, Ordering::SeqCst);
+    }
 
-        self.inner.online.store(true, Ordering::SeqCst)
+    pub fn is_local_only(&self) -> bool {
+        self.inner.local_only.load(Ordering::SeqCst)
     }
 
-    pub(crate) fn set_offline(&self) {
-        if !self.is_online() {
-            return;
-        }
+    /// Sets whether the node fetches blocks over bitswap. Without it, blocks missing from the
+    /// blockstore fail with [`ProtocolDisabled`] unless a fallback gateway is configured.
+    pub(crate) fn set_bitswap_enabled(&self, enabled: bool) {
+        self.inner
+            .bitswap_disabled
+            .store(!enabled, Ordering::SeqCst);
+    }
 
-        self.inner.online.store(false, Ordering::SeqCst)
+    /// Sets the cipher the data of the blocks is encrypted with at rest, before the repo is
+    /// initialized. Initializing fails if the repo was encrypted and no cipher is set, or if it
+    /// has plaintext blocks and one is, see [`Repo::encrypt_in_place`]. A wrong key is reported
+    /// with [`DecryptionFailed`] when reading the blocks.
+    pub fn set_block_cipher(&self, cipher: Option<Arc<dyn BlockCipher>>) {
+        *self.inner.cipher.write() = cipher;
     }
 
-    fn repo_channel(&self) -> Option<Sender<RepoEvent>> {
-        self.inner.events.read().clone()
+    /// Sets the policy the blocks are checked against before being written, be they put locally
+    /// or received from the peers. Writing a rejected block fails with a [`PolicyViolation`].
+    /// Every block is allowed without one.
+    pub fn set_block_policy(&self, policy: Option<Arc<dyn BlockPolicy>>) {
+        *self.inner.block_policy.write() = policy;
     }
 
-    pub async fn init(&self) -> Result<(), Error> {
-        //Avoid initializing again
-        if self.inner.initialized.load(Ordering::SeqCst) {
+    /// Fails with the [`PolicyViolation`] of the first block rejected by the policy, if any.
+    fn check_policy(&self, blocks: &[Block]) -> Result<(), Error> {
+        let Some(policy) = self.inner.block_policy.read().clone() else {
             return Ok(());
+        };
+        for block in blocks {
+            if let Err(violation) = policy.check(block.cid(), block.data()) {
+                let cid = block.cid();
+                return Err(Error::new(violation)
+                    .context(format!("block {cid} rejected by the block policy")));
+            }
         }
-        // Dropping the guard (even though not strictly necessary to compile) to avoid potential
-        // deadlocks if `block_store` or `data_store` were to try to access `Repo.lockfile`.
-        {
-            log::debug!("Trying lockfile");
-            self.inner.lockfile.try_exclusive()?;
-            log::debug!("lockfile tried");
-        }
+        Ok(())
+    }
 
-        let f1 = self.inner.block_store.init();
-        let f2 = self.inner.data_store.init();
-        let (r1, r2) = futures::future::join(f1, f2).await;
-        let init = &self.inner.initialized;
-        if r1.is_err() {
-            r1.map(|_| {
-                init.store(true, Ordering::SeqCst);
-            })
-        } else {
-            r2.map(|_| {
-                init.store(true, Ordering::SeqCst);
-            })
+    /// Encrypts the blocks stored in plaintext with the cipher, which is then used for the blocks
+    /// read and written. Returns the number of blocks encrypted, the others being already
+    /// encrypted with the cipher.
+    ///
+    /// The repo is marked as being encrypted before the first block is, and every block is
+    /// overwritten with its encrypted data. If interrupted, the repo can only be opened with the
+    /// cipher, the blocks left in plaintext being read as such until encrypted by calling this
+    /// again.
+    pub async fn encrypt_in_place(&self, cipher: Arc<dyn BlockCipher>) -> Result<usize, Error> {
+        let _guard = self.inner.gclock.write().await;
+
+        self.inner
+            .data_store
+            .put(encryption::ENCRYPTED_KEY, encryption::ENCRYPTING)
+            .await?;
+        self.inner.encrypting.store(true, Ordering::SeqCst);
+        self.inner.encrypted.store(true, Ordering::SeqCst);
+        self.set_block_cipher(Some(cipher.clone()));
+
+        let mut encrypted = 0;
+        let mut cids = self.inner.block_store.list().await;
+        while let Some(cid) = cids.next().await {
+            let Some(block) = self.inner.block_store.get(&cid).await? else {
+                continue;
+            };
+            if cipher.decrypt(&cid, block.data()).is_ok() {
+                continue;
+            }
+            // the plaintext matches its cid, unless hashed with a code which is not supported
+            let verifiable = libipld::multihash::Code::try_from(cid.hash().code()).is_ok();
+            if verifiable && Block::new(cid, block.data().to_vec()).is_err() {
+                return Err(DecryptionFailed(cid).into());
+            }
+
+            let sealed = Block::new_unchecked(cid, cipher.encrypt(&cid, block.data())?);
+            self.inner.block_store.replace(sealed).await?;
+            encrypted += 1;
         }
-    }
 
-    pub async fn open(&self) -> Result<(), Error> {
-        let f1 = self.inner.block_store.open();
-        let f2 = self.inner.data_store.open();
-        let (r1, r2) = futures::future::join(f1, f2).await;
-        if r1.is_err() {
-            r1
-        } else {
-            r2
+        // the stored sizes include the overhead of the encryption from now on
+        if let Some(stat) = self.inner.block_stat.get() {
+            stat.lock().bytes = self.inner.block_store.total_size().await? as u64;
         }
+        self.forget_block_stat().await;
+
+        self.inner
+            .data_store
+            .put(encryption::ENCRYPTED_KEY, &[])
+            .await?;
+        self.inner.encrypting.store(false, Ordering::SeqCst);
+        Ok(encrypted)
     }
 
-    /// Puts a block into the block store.
-    pub async fn put_block(&self, block: Block) -> Result<Cid, Error> {
-        let _guard = self.inner.gclock.read().await;
-        let (cid, res) = self.inner.block_store.put(block.clone()).await?;
+    /// Checks that the blocks are encrypted if and only if a cipher is set, marking the blocks
+    /// of a new repo as encrypted.
+    async fn check_encryption(&self) -> Result<(), Error> {
+        let marker = self.inner.data_store.get(encryption::ENCRYPTED_KEY).await?;
+        let cipher = self.inner.cipher.read().is_some();
 
-        if let BlockPut::NewBlock = res {
-            if let Some(mut event) = self.repo_channel() {
-                _ = event.send(RepoEvent::NewBlock(block.clone())).await;
-            }
-            let list = self.inner.subscriptions.lock().remove(&cid);
-            if let Some(mut list) = list {
-                for ch in list.drain(..) {
-                    let block = block.clone();
-                    let _ = ch.send(Ok(block));
+        match (marker, cipher) {
+            (Some(_), false) => {
+                anyhow::bail!("the blocks of the repo are encrypted, but no cipher is set")
+            }
+            (Some(marker), true) => {
+                let encrypting = marker == encryption::ENCRYPTING;
+                if encrypting {
+                    warn!("the encryption of the repo was interrupted, see Repo::encrypt_in_place");
                 }
+                self.inner.encrypting.store(encrypting, Ordering::SeqCst);
+                self.inner.encrypted.store(true, Ordering::SeqCst);
+                Ok(())
+            }
+            (None, true) => {
+                if self.inner.block_store.list().await.next().await.is_some() {
+                    anyhow::bail!(
+                        "the blocks of the repo are not encrypted, see Repo::encrypt_in_place"
+                    );
+                }
+                self.inner
+                    .data_store
+                    .put(encryption::ENCRYPTED_KEY, &[])
+                    .await?;
+                self.inner.encrypted.store(true, Ordering::SeqCst);
+                Ok(())
+            }
+            (None, false) => Ok(()),
+        }
+    }
+
+    /// Encrypts the data of the block to be written, if encrypted at rest.
+    fn seal(&self, block: &Block) -> Result<Block, Error> {
+        let Some(cipher) = self.inner.cipher.read().clone() else {
+            return Ok(block.clone());
+        };
+        let data = cipher.encrypt(block.cid(), block.data())?;
+        Ok(Block::new_unchecked(*block.cid(), data))
+    }
+
+    /// Decrypts the data of the block read, verifying it against the cid.
+    fn unseal(&self, block: Block) -> Result<Block, Error> {
+        let Some(cipher) = self.inner.cipher.read().clone() else {
+            // the data would be the encrypted one
+            if self.inner.encrypted.load(Ordering::SeqCst) {
+                anyhow::bail!("the blocks of the repo are encrypted, but no cipher is set");
+            }
+            return Ok(block);
+        };
+        let cid = *block.cid();
+        match cipher.decrypt(&cid, block.data()) {
+            Ok(data) => Block::new(cid, data).map_err(|_| DecryptionFailed(cid).into()),
+            // not encrypted yet while the repo is encrypted in place
+            Err(_)
+                if self.inner.encrypting.load(Ordering::SeqCst)
+                    && Block::new(cid, block.data().to_vec()).is_ok() =>
+            {
+                Ok(block)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Number of bytes the encryption adds to the stored data of every block.
+    fn cipher_overhead(&self) -> usize {
+        self.inner
+            .cipher
+            .read()
+            .as_ref()
+            .map(|cipher| cipher.overhead())
+            .unwrap_or_default()
+    }
+
+    /// Sets the gateways the blocks still missing `delay` after being wanted are fetched from,
+    /// disabling the fallback without any.
+    #[cfg(feature = "gateway_fallback")]
+    pub fn set_gateway_fallback(&self, gateways: Vec<reqwest::Url>, delay: Duration) {
+        *self.inner.gateway.lock() = match gateways.is_empty() {
+            true => None,
+            false => Some(Arc::new(gateway::GatewayFetcher::new(gateways, delay))),
+        };
+    }
+
+    /// Fetches the DAG rooted at the cid from the fallback gateways as a CAR.
+    #[cfg(feature = "gateway_fallback")]
+    pub async fn fetch_from_gateways(&self, cid: &Cid) -> Result<(), Error> {
+        let gateway = self
+            .inner
+            .gateway
+            .lock()
+            .clone()
+            .ok_or_else(|| anyhow!("no fallback gateway is configured"))?;
+        gateway.fetch_dag(self, cid).await
+    }
+
+    /// Fetches the blocks still waited for once bitswap had the delay to find them from the
+    /// fallback gateways, storing them to complete the requests.
+    #[cfg(feature = "gateway_fallback")]
+    fn fall_back_to_gateways(&self, cids: Vec<Cid>) {
+        let Some(gateway) = self.inner.gateway.lock().clone() else {
+            return;
+        };
+
+        let repo = self.clone();
+        tokio::spawn(
+            async move {
+                tokio::time::sleep(gateway.delay).await;
+
+                for cid in cids {
+                    let waited_for = repo
+                        .inner
+                        .subscriptions
+                        .lock()
+                        .get(&cid)
+                        .map(|waiters| waiters.iter().any(|(_, tx)| !tx.is_canceled()))
+                        .unwrap_or_default();
+
+                    if !waited_for {
+                        continue;
+                    }
+
+                    match gateway.fetch_block(&cid).await {
+                        Ok(block) => {
+                            if let Err(e) = repo.put_block_from(block, BlockSource::Gateway).await {
+                                repo.fail_subscriptions(&cid, &e);
+                            }
+                        }
+                        Err(e) => debug!(%cid, "gateway fallback failed: {e}"),
+                    }
+                }
+            }
+            .in_current_span(),
+        );
+    }
+
+    #[cfg(not(feature = "gateway_fallback"))]
+    fn fall_back_to_gateways(&self, _: Vec<Cid>) {}
+
+    #[cfg(feature = "gateway_fallback")]
+    fn has_gateway_fallback(&self) -> bool {
+        self.inner.gateway.lock().is_some()
+    }
+
+    #[cfg(not(feature = "gateway_fallback"))]
+    fn has_gateway_fallback(&self) -> bool {
+        false
+    }
+
+    pub(crate) fn set_online(&self) {
+        if self.is_online() {
+            return;
+        }
+
+        self.inner.online.store(true, Ordering::SeqCst)
+    }
+
+    pub(crate) fn set_offline(&self) {
+        if !self.is_online() {
+            return;
+        }
+
+        self.inner.online.store(false, Ordering::SeqCst)
+    }
+
+    fn repo_channel(&self) -> Option<Sender<RepoEvent>> {
+        self.inner.events.read().clone()
+    }
+
+    /// Returns a stream of the blocks written to and removed from the repo and of the pins added
+    /// and removed from now on. A subscriber falling behind by more than 1024 notifications misses
+    /// the oldest ones. The stream ends once the repo is dropped, which it does not keep alive.
+    pub fn subscribe(&self) -> BoxStream<'static, RepoNotification> {
+        let mut receiver = self.inner.notifications.subscribe();
+        let stream = async_stream::stream! {
+            loop {
+                match receiver.recv().await {
+                    Ok(notification) => yield notification,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                        debug!("repo subscriber skipped {skipped} notifications");
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        };
+        stream.boxed()
+    }
+
+    fn notify(&self, notification: RepoNotification) {
+        // fails only without subscribers
+        let _ = self.inner.notifications.send(notification);
+    }
+
+    pub async fn init(&self) -> Result<(), Error> {
+        //Avoid initializing again
+        if self.inner.initialized.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        // Dropping the guard (even though not strictly necessary to compile) to avoid potential
+        // deadlocks if `block_store` or `data_store` were to try to access `Repo.lockfile`.
+        {
+            log::debug!("Trying lockfile");
+            self.inner.lockfile.try_exclusive()?;
+            log::debug!("lockfile tried");
+        }
+
+        let f1 = self.inner.block_store.init();
+        let f2 = self.inner.data_store.init();
+        let (r1, r2) = futures::future::join(f1, f2).await;
+        r1?;
+        r2?;
+
+        self.check_encryption().await?;
+
+        // the totals saved on shutdown are loaded, or the blocks counted when there are none
+        self.block_stat().await?;
+
+        self.inner.initialized.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub async fn open(&self) -> Result<(), Error> {
+        let f1 = self.inner.block_store.open();
+        let f2 = self.inner.data_store.open();
+        let (r1, r2) = futures::future::join(f1, f2).await;
+        if r1.is_err() {
+            r1
+        } else {
+            r2
+        }
+    }
+
+    /// Puts a block into the block store, returning whether it was written or already stored.
+    /// Only a newly written block is announced to the ipfs task.
+    ///
+    /// Fails with [`StorageFull`] when the block does not fit within the storage limit, and with
+    /// [`PolicyViolation`] when rejected by the block policy.
+    pub async fn put_block(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
+        self.put_block_from(block, BlockSource::Local).await
+    }
+
+    /// Puts a block as [`Repo::put_block`] does, from the source told to the subscribers.
+    pub(crate) async fn put_block_from(
+        &self,
+        block: Block,
+        source: BlockSource,
+    ) -> Result<(Cid, BlockPut), Error> {
+        self.check_policy(std::slice::from_ref(&block))?;
+        // released once the blocks are accounted for
+        let _reservation = self.ensure_capacity(std::slice::from_ref(&block)).await?;
+        let _guard = self.inner.gclock.read().await;
+        let (cid, res) = self.inner.block_store.put(self.seal(&block)?).await?;
+
+        self.record_written([cid]);
+
+        if let BlockPut::NewBlock = res {
+            self.new_block(block, source).await;
+        }
+
+        Ok((cid, res))
+    }
+
+    /// Puts multiple blocks into the block store, in a single batch when the block store supports
+    /// it, returning their cids in order along with whether they were written or already stored.
+    pub async fn put_blocks(&self, blocks: Vec<Block>) -> Result<Vec<(Cid, BlockPut)>, Error> {
+        self.put_blocks_from(blocks, BlockSource::Local).await
+    }
+
+    /// Puts the blocks as [`Repo::put_blocks`] does, from the source told to the subscribers.
+    pub(crate) async fn put_blocks_from(
+        &self,
+        blocks: Vec<Block>,
+        source: BlockSource,
+    ) -> Result<Vec<(Cid, BlockPut)>, Error> {
+        if blocks.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.check_policy(&blocks)?;
+        let _reservation = self.ensure_capacity(&blocks).await?;
+        let _guard = self.inner.gclock.read().await;
+        let sealed = blocks
+            .iter()
+            .map(|block| self.seal(block))
+            .collect::<Result<Vec<_>, _>>()?;
+        let puts = self.inner.block_store.put_many(sealed).await?;
+
+        self.record_written(puts.iter().map(|(cid, _)| *cid));
+
+        for (block, (_, res)) in blocks.into_iter().zip(&puts) {
+            if let BlockPut::NewBlock = res {
+                self.new_block(block, source).await;
+            }
+        }
+
+        Ok(puts)
+    }
+
+    /// Puts a block into the block store and pins it directly.
+    ///
+    /// When the datastore keeps the blocks as well, like
+    /// [`RedbDataStore::with_blocks`](datastore::redb::RedbDataStore::with_blocks) does, both
+    /// are committed in a single transaction. Otherwise the block is put and then pinned, without
+    /// garbage collection running in between.
+    pub async fn put_pinned_block(&self, block: Block) -> Result<Cid, Error> {
+        self.check_policy(std::slice::from_ref(&block))?;
+        let reservation = self.ensure_capacity(std::slice::from_ref(&block)).await?;
+        let _guard = self.inner.gclock.read().await;
+        let cid = *block.cid();
+
+        match self
+            .inner
+            .data_store
+            .put_pinned_block(&self.seal(&block)?)
+            .await?
+        {
+            Some(put) => {
+                if put == BlockPut::NewBlock {
+                    self.new_block(block, BlockSource::Local).await;
+                }
+                self.new_pin(&cid, PinMode::Direct, vec![]).await;
+            }
+            None => {
+                // reserved again by the put
+                drop(reservation);
+                self.put_block(block).await?;
+                self.insert_direct_pin(&cid).await?;
             }
         }
 
         Ok(cid)
     }
 
+    /// Records when the blocks were written, for the garbage collector to spare them during the
+    /// grace period. The blocks written before the grace period are forgotten along the way.
+    fn record_written(&self, cids: impl IntoIterator<Item = Cid>) {
+        let grace_period = self.gc_grace_period();
+        if grace_period.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let mut recent = self.inner.recent_blocks.lock();
+        while let Some((_, written)) = recent.front() {
+            if now.duration_since(*written) < grace_period {
+                break;
+            }
+            recent.pop_front();
+        }
+        recent.extend(cids.into_iter().map(|cid| (cid, now)));
+    }
+
+    /// Fails the pending requests for a block, e.g. when it was fetched but could not be stored.
+    pub(crate) fn fail_subscriptions(&self, cid: &Cid, error: &Error) {
+        let list = self.inner.subscriptions.lock().remove(cid);
+        self.persist_want(|| WantWrite::Done(*cid));
+        for (_, ch) in list.into_iter().flatten() {
+            let _ = ch.send(Err(error.to_string()));
+        }
+    }
+
+    /// Accounts for a newly written block and notifies the ipfs task and the subscribers about it.
+    async fn new_block(&self, block: Block, source: BlockSource) {
+        self.count_block((block.data().len() + self.cipher_overhead()) as u64)
+            .await;
+        self.record_access([block.cid()]);
+        self.persist_want(|| WantWrite::Done(*block.cid()));
+        self.notify(RepoNotification::BlockAdded {
+            cid: *block.cid(),
+            size: block.data().len(),
+            source,
+        });
+
+        let cid = *block.cid();
+        let list = self.inner.subscriptions.lock().remove(&cid);
+        if let Some(mut list) = list {
+            for (_, ch) in list.drain(..) {
+                let block = block.clone();
+                let _ = ch.send(Ok(block));
+            }
+        }
+
+        // the attached nodes send the block to their peers wanting it
+        let attached = self
+            .inner
+            .attached
+            .lock()
+            .iter()
+            .filter(|sender| !sender.is_closed())
+            .cloned()
+            .collect::<Vec<_>>();
+        for mut event in attached {
+            _ = event.send(RepoEvent::NewBlock(block.clone(), None)).await;
+        }
+
+        if let Some(mut event) = self.repo_channel() {
+            // the block is stored either way, the outcome of providing it is only logged once the
+            // provider query completed, without holding up the write
+            let ret = match self.provider() {
+                RepoProvider::All => {
+                    let (tx, rx) = oneshot::channel();
+                    tokio::spawn(
+                        async move {
+                            match rx.await {
+                                Ok(Ok(())) => debug!("provided {}", cid),
+                                Ok(Err(e)) => debug!("failed to provide {}: {}", cid, e),
+                                Err(_) => debug!("ipfs task dropped before providing {}", cid),
+                            }
+                        }
+                        .in_current_span(),
+                    );
+                    Some(tx)
+                }
+                _ => None,
+            };
+            _ = event.send(RepoEvent::NewBlock(block, ret)).await;
+        }
+    }
+
+    /// Notifies the ipfs task and the subscribers about a new pin, which might be provided along
+    /// with the blocks it pins indirectly.
+    async fn new_pin(&self, cid: &Cid, mode: PinMode, indirect: Vec<Cid>) {
+        self.notify(RepoNotification::PinAdded { cid: *cid, mode });
+        if let Some(mut event) = self.repo_channel() {
+            _ = event.send(RepoEvent::NewPin(*cid, indirect)).await;
+        }
+    }
+
+    /// Whether the blocks pinned indirectly by new recursive pins are provided.
+    fn provides_indirect_pins(&self) -> bool {
+        self.provider() == RepoProvider::Pinned
+    }
+
+    /// Notifies the ipfs task about a pin marked to be provided.
+    async fn provide_pin(&self, cid: &Cid) {
+        if let Some(mut event) = self.repo_channel() {
+            _ = event.send(RepoEvent::ProvidePin(*cid)).await;
+        }
+    }
+
+    /// Notifies the ipfs task about the root of a provided pin which is no longer pinned, to
+    /// stop providing it as if the block was removed.
+    async fn unprovide_pin(&self, cid: &Cid) {
+        if let Some(mut event) = self.repo_channel() {
+            _ = event.send(RepoEvent::RemovedBlock(*cid)).await;
+        }
+    }
+
+    /// Roots of the direct and recursive pins marked to be provided.
+    pub(crate) async fn provided_pins(&self) -> Vec<Cid> {
+        self.list_labeled_pins(None)
+            .await
+            .filter_map(|result| async move {
+                result
+                    .ok()
+                    .filter(|(_, _, label)| label.provide)
+                    .map(|(cid, ..)| cid)
+            })
+            .collect()
+            .await
+    }
+
     /// Retrives a block from the block store, or starts fetching it from the network and awaits
     /// until it has been fetched.
     #[inline]
@@ -695,10 +1890,19 @@ impl Repo {
     /// Get the size of listed blocks
     #[inline]
     pub async fn get_blocks_size(&self, cids: &[Cid]) -> Result<Option<usize>, Error> {
-        self.inner.block_store.size(cids).await
+        let size = self.inner.block_store.size(cids).await?;
+        let overhead = self
+            .inner
+            .cipher
+            .read()
+            .as_ref()
+            .map(|cipher| cipher.overhead())
+            .unwrap_or_default();
+        Ok(size.map(|size| size.saturating_sub(overhead * cids.len())))
     }
 
-    /// Get the total size of the block store
+    /// Get the total size of the block store, which includes the encryption overhead of the
+    /// blocks when encrypted at rest
     #[inline]
     pub async fn get_total_size(&self) -> Result<usize, Error> {
         self.inner.block_store.total_size().await
@@ -711,6 +1915,59 @@ impl Repo {
         peers: &[PeerId],
         local_only: bool,
         timeout: impl Into<Option<Duration>>,
+    ) -> Result<BoxStream<'static, Result<Block, Error>>, Error> {
+        self.get_blocks_with_priority(
+            session,
+            cids,
+            peers,
+            local_only,
+            timeout,
+            DEFAULT_WANT_PRIORITY,
+        )
+        .await
+    }
+
+    /// Same as [`Repo::get_blocks_with_session`], wanting the missing blocks at the given bitswap
+    /// priority. Blocks which are already being fetched keep the priority they were wanted at.
+    pub(crate) async fn get_blocks_with_priority(
+        &self,
+        session: impl Into<Option<u64>>,
+        cids: &[Cid],
+        peers: &[PeerId],
+        local_only: bool,
+        timeout: impl Into<Option<Duration>>,
+        priority: i32,
+    ) -> Result<BoxStream<'static, Result<Block, Error>>, Error> {
+        self.fetch_blocks(session, cids, peers, local_only, timeout, priority, false)
+            .await
+    }
+
+    /// Retrieves a block like [`Repo::get_block`], but fails with [`FacadeError::Busy`] instead of
+    /// waiting for the ipfs task to take the want of the missing block, when it has yet to take
+    /// the previous one.
+    pub(crate) async fn try_get_block(&self, cid: &Cid) -> Result<Block, Error> {
+        let mut blocks = self
+            .fetch_blocks(None, &[*cid], &[], false, None, DEFAULT_WANT_PRIORITY, true)
+            .await?;
+
+        blocks
+            .next()
+            .await
+            .ok_or(anyhow::anyhow!("Unable to locate {} block", *cid))?
+    }
+
+    /// Retrieves the blocks, wanting the missing ones over the events channel of the repo. With
+    /// `try_want`, the want is only queued if the channel has room for it.
+    #[allow(clippy::too_many_arguments)]
+    async fn fetch_blocks(
+        &self,
+        session: impl Into<Option<u64>>,
+        cids: &[Cid],
+        peers: &[PeerId],
+        local_only: bool,
+        timeout: impl Into<Option<Duration>>,
+        priority: i32,
+        try_want: bool,
     ) -> Result<BoxStream<'static, Result<Block, Error>>, Error> {
         let timeout = timeout.into();
         let _guard = self.inner.gclock.read().await;
@@ -728,12 +1985,25 @@ impl Repo {
             }
         }
 
+        let found = cids.iter().filter(|cid| !missing.contains(cid));
+        self.record_access(found);
+        self.inner
+            .hits
+            .fetch_add((cids.len() - missing.len()) as u64, Ordering::Relaxed);
+        self.inner
+            .misses
+            .fetch_add(missing.len() as u64, Ordering::Relaxed);
+
         if missing.is_empty() {
             return Ok(blocks.boxed());
         }
 
-        if local_only || !self.is_online() {
-            anyhow::bail!("Unable to locate missing blocks {missing:?}");
+        if local_only || self.is_local_only() || !self.is_online() {
+            return Err(BlockNotLocal(missing[0]).into());
+        }
+
+        if self.inner.bitswap_disabled.load(Ordering::SeqCst) && !self.has_gateway_fallback() {
+            return Err(ProtocolDisabled(ProtocolKind::Bitswap).into());
         }
 
         // sending only fails if no one is listening anymore
@@ -743,41 +2013,87 @@ impl Repo {
             .repo_channel()
             .ok_or(anyhow::anyhow!("Channel is not available"))?;
 
-        for cid in &missing {
-            let cid = *cid;
+        // only the blocks no one in the session is waiting for yet are wanted, bitswap keeping a
+        // single want for the sessions wanting the same block, and only the blocks no one is
+        // waiting for are fetched from the gateways
+        let session = session.into();
+        let mut wanted = Vec::with_capacity(missing.len());
+        let mut updated = Vec::new();
+        let mut fetched = Vec::with_capacity(missing.len());
+        for cid in missing {
             let (tx, rx) = futures::channel::oneshot::channel();
-            self.inner
-                .subscriptions
-                .lock()
-                .entry(cid)
-                .or_default()
-                .push(tx);
+            {
+                let mut subscriptions = self.inner.subscriptions.lock();
+                let waiters = subscriptions.entry(cid).or_default();
+                waiters.retain(|(_, tx)| !tx.is_canceled());
+                if waiters.is_empty() {
+                    fetched.push(cid);
+                }
+                if !waiters.iter().any(|(waiting, _)| *waiting == session) {
+                    wanted.push(cid);
+                } else if !peers.is_empty() || priority > DEFAULT_WANT_PRIORITY {
+                    // the want already sent is given the provider hints and the priority of
+                    // this get, bitswap keeping the highest priority
+                    updated.push(cid);
+                }
+                waiters.push((session, tx));
+            }
 
+            let mut subscription = BlockSubscription {
+                repo: self.clone(),
+                session,
+                cid,
+                rx,
+            };
             let timeout = timeout.unwrap_or(Duration::from_secs(60));
-            let mut events = events.clone();
             let task = async move {
-                let block = tokio::time::timeout(timeout, rx)
+                let block = tokio::time::timeout(timeout, &mut subscription.rx)
                     .await
-                    .map_err(|_| anyhow::anyhow!("Timeout while resolving {cid}"))??
+                    .map_err(|_| {
+                        anyhow::Error::new(Timeout {
+                            elapsed: timeout,
+                            operation: "get_block",
+                        })
+                        .context(format!("Timeout while resolving {cid}"))
+                    })??
                     .map_err(|e| anyhow!("{e}"))?;
                 Ok::<_, anyhow::Error>(block)
             }
-            .map_err(move |e| {
-                _ = events.try_send(RepoEvent::UnwantBlock(cid));
-                e
-            })
             .boxed();
             blocks.push_back(task);
         }
 
-        events
-            .send(RepoEvent::WantBlock(
-                session.into(),
-                missing,
-                peers.to_vec(),
-            ))
-            .await
-            .ok();
+        if !fetched.is_empty() {
+            self.fall_back_to_gateways(fetched);
+        }
+        if !wanted.is_empty() || !updated.is_empty() {
+            for cid in &wanted {
+                self.persist_want(|| WantWrite::Want((session, *cid, peers.to_vec(), priority)));
+            }
+            wanted.extend(updated);
+            let want = RepoEvent::WantBlock(session, wanted, peers.to_vec(), priority);
+            match try_want {
+                // every clone of the sender is given room for one event, only the sender kept by
+                // the repo is full until the task took the event it sent
+                true => {
+                    let sent = match self.inner.events.write().as_mut() {
+                        Some(sender) => sender.try_send(want),
+                        None => return Err(anyhow!("Channel is not available")),
+                    };
+                    if let Err(e) = sent {
+                        // the subscriptions are dropped along with the blocks, unwanting them
+                        // and removing their persisted wants
+                        return Err(match e.is_full() {
+                            true => FacadeError::Busy.into(),
+                            false => anyhow!("Channel is not available"),
+                        });
+                    }
+                }
+                false => {
+                    events.send(want).await.ok();
+                }
+            }
+        }
 
         Ok(blocks.boxed())
     }
@@ -789,10 +2105,30 @@ impl Repo {
         peers: &[PeerId],
         local_only: bool,
         timeout: impl Into<Option<Duration>>,
+    ) -> Result<Block, Error> {
+        self.get_block_with_priority(
+            session,
+            cid,
+            peers,
+            local_only,
+            timeout,
+            DEFAULT_WANT_PRIORITY,
+        )
+        .await
+    }
+
+    pub(crate) async fn get_block_with_priority(
+        &self,
+        session: impl Into<Option<u64>>,
+        cid: &Cid,
+        peers: &[PeerId],
+        local_only: bool,
+        timeout: impl Into<Option<Duration>>,
+        priority: i32,
     ) -> Result<Block, Error> {
         let cids = vec![*cid];
         let mut blocks = self
-            .get_blocks_with_session(session, &cids, peers, local_only, timeout)
+            .get_blocks_with_priority(session, &cids, peers, local_only, timeout, priority)
             .await?;
 
         blocks
@@ -803,7 +2139,33 @@ impl Repo {
 
     /// Retrieves a block from the block store if it's available locally.
     pub async fn get_block_now(&self, cid: &Cid) -> Result<Option<Block>, Error> {
-        self.inner.block_store.get(cid).await
+        match self.inner.block_store.get(cid).await? {
+            Some(block) => self.unseal(block).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Retrieves multiple blocks from the block store without fetching the missing ones from the
+    /// network, in the order of the given cids.
+    pub async fn get_blocks_now(&self, cids: &[Cid]) -> Result<Vec<Option<Block>>, Error> {
+        let blocks = self.inner.block_store.get_many(cids).await?;
+        blocks
+            .into_iter()
+            .map(|block| block.map(|block| self.unseal(block)).transpose())
+            .collect()
+    }
+
+    /// Retrieves a block with the multihash from the block store, whatever the codec and version of
+    /// the cid it was stored under, as far as [`BlockStore::cids_by_multihash`] finds it. Does not
+    /// fetch it from the network.
+    pub async fn get_block_by_multihash(&self, hash: &Multihash) -> Result<Option<Block>, Error> {
+        let cids = self.inner.block_store.cids_by_multihash(hash).await?;
+        for cid in cids {
+            if let Some(block) = self.get_block_now(&cid).await? {
+                return Ok(Some(block));
+            }
+        }
+        Ok(None)
     }
 
     /// Check to determine if blockstore contain a block
@@ -811,9 +2173,42 @@ impl Repo {
         self.inner.block_store.contains(cid).await
     }
 
-    /// Lists the blocks in the blockstore.
-    pub async fn list_blocks(&self) -> BoxStream<'static, Cid> {
-        self.inner.block_store.list().await
+    /// Lists the blocks in the blockstore matching the filter, along with the size of their data.
+    ///
+    /// The store is not locked while the stream is consumed, so the blocks written or removed
+    /// meanwhile may or may not be listed.
+    pub async fn list_blocks(&self, filter: BlockFilter) -> BoxStream<'static, (Cid, usize)> {
+        let overhead = self
+            .inner
+            .cipher
+            .read()
+            .as_ref()
+            .map(|cipher| cipher.overhead())
+            .unwrap_or_default();
+        let repo = self.clone();
+        let filter = Arc::new(filter);
+
+        self.inner
+            .block_store
+            .list_entries()
+            .await
+            .filter_map(move |entry| {
+                let repo = repo.clone();
+                let filter = filter.clone();
+                async move {
+                    let size = entry.size.saturating_sub(overhead);
+                    if !filter.matches(&entry.cid, size, entry.written_at) {
+                        return None;
+                    }
+                    if let Some(pinned) = filter.pinned {
+                        if repo.is_pinned(&entry.cid).await.ok()? != pinned {
+                            return None;
+                        }
+                    }
+                    Some((entry.cid, size))
+                }
+            })
+            .boxed()
     }
 
     /// Remove block from the block store.
@@ -834,31 +2229,63 @@ impl Repo {
             false => BTreeSet::from_iter(std::iter::once(*cid)),
         };
 
-        let list = stream::iter(
-            FuturesOrdered::from_iter(list.into_iter().map(|cid| async move { cid }))
-                .filter_map(|cid| async move {
-                    (!self.is_pinned(&cid).await.unwrap_or_default()).then_some(cid)
-                })
-                .collect::<Vec<Cid>>()
-                .await,
-        )
-        .boxed();
+        let list = FuturesOrdered::from_iter(list.into_iter().map(|cid| async move { cid }))
+            .filter_map(|cid| async move {
+                (!self.is_pinned(&cid).await.unwrap_or_default()).then_some(cid)
+            })
+            .collect::<Vec<Cid>>()
+            .await;
+
+        let (removed, _) = self.remove_blocks(list).await;
+        Ok(removed)
+    }
+
+    /// Removes the blocks from the block store, returning the removed ones and their total size.
+    async fn remove_blocks(&self, cids: Vec<Cid>) -> (Vec<Cid>, usize) {
+        let mut sizes = HashMap::new();
+        for cid in &cids {
+            if let Ok(Some(size)) = self.get_blocks_size(std::slice::from_ref(cid)).await {
+                sizes.insert(*cid, size);
+            }
+        }
 
         let removed = self
             .inner
             .block_store
-            .remove_many(list)
+            .remove_many(stream::iter(cids).boxed())
             .await
             .collect::<Vec<_>>()
             .await;
 
+        let reclaimed: usize = removed.iter().filter_map(|cid| sizes.get(cid)).sum();
+
+        if let Some(stat) = self.inner.block_stat.get() {
+            // the stored sizes include the overhead of the encryption
+            let stored = reclaimed + removed.len() * self.cipher_overhead();
+            let mut stat = stat.lock();
+            stat.blocks = stat.blocks.saturating_sub(removed.len() as u64);
+            stat.bytes = stat.bytes.saturating_sub(stored as u64);
+        }
+        if !removed.is_empty() {
+            self.forget_block_stat().await;
+        }
+
+        {
+            let lru = &mut *self.inner.lru.lock();
+            for cid in &removed {
+                lru.accessed.remove(cid);
+            }
+        }
+
         for cid in &removed {
+            self.notify(RepoNotification::BlockRemoved { cid: *cid });
             // notify ipfs task about the removed blocks
             if let Some(mut events) = self.repo_channel() {
                 let _ = events.send(RepoEvent::RemovedBlock(*cid)).await;
             }
         }
-        Ok(removed)
+
+        (removed, reclaimed)
     }
 
     fn recursive_collections(&self, cid: Cid) -> BoxFuture<'_, anyhow::Result<BTreeSet<Cid>>> {
@@ -931,9 +2358,181 @@ impl Repo {
         pin_fut.await
     }
 
+    /// Pins the roots recursively, loading the blocks shared between them once, and writes all
+    /// the pins as a single change of the datastore. Roots already pinned recursively are skipped.
+    pub async fn pin_many(&self, roots: &[Cid], local: bool) -> Result<(), Error> {
+        let mut pending = Vec::with_capacity(roots.len());
+        for root in roots {
+            if !pending.contains(root) && !self.is_pinned_recursively(root).await {
+                pending.push(*root);
+            }
+        }
+
+        let _in_progress = pending
+            .iter()
+            .map(|root| self.pin_in_progress(*root))
+            .collect::<Vec<_>>();
+
+        let references = self.references_of_roots(&pending, local).await?;
+        let indirect = match self.provides_indirect_pins() {
+            true => references.clone(),
+            false => vec![vec![]; pending.len()],
+        };
+        let insert = pending
+            .iter()
+            .copied()
+            .zip(references)
+            .map(|(root, refs)| (root, stream::iter(refs.into_iter().map(Ok)).boxed()))
+            .collect();
+
+        self.inner
+            .data_store
+            .update_recursive_pins(insert, vec![])
+            .await?;
+
+        for (root, indirect) in pending.iter().zip(indirect) {
+            self.new_pin(root, PinMode::Recursive, indirect).await;
+        }
+        Ok(())
+    }
+
+    /// Pins `new` recursively in place of the recursive pin of `old`, like `ipfs pin update`.
+    ///
+    /// The blocks shared with `old` are already in the repo, so only the blocks of `new` missing
+    /// from it are fetched. The pin of `new` is written along with the removal of the pin of `old`
+    /// when `unpin_old` is set, as a single change of the datastore where it supports
+    /// transactions: `old` stays pinned until `new` is, so there is no point at which the blocks
+    /// could be collected. The label of the pin of `old` is moved to the pin of `new`.
+    pub async fn pin_update(
+        &self,
+        old: &Cid,
+        new: &Cid,
+        unpin_old: bool,
+        local: bool,
+    ) -> Result<(), Error> {
+        if !self.is_pinned_recursively(old).await {
+            return Err(anyhow::anyhow!("{old} is not pinned recursively"));
+        }
+        if old == new {
+            return Ok(());
+        }
+
+        let _in_progress = self.pin_in_progress(*new);
+
+        let mut insert = vec![];
+        let mut indirect = vec![];
+        if !self.is_pinned_recursively(new).await {
+            let mut references = self.references_of_roots(&[*new], local).await?;
+            let refs = references.pop().unwrap_or_default();
+            if self.provides_indirect_pins() {
+                indirect = refs.clone();
+            }
+            insert.push((*new, stream::iter(refs.into_iter().map(Ok)).boxed()));
+        }
+
+        let mut remove = vec![];
+        let mut label = None;
+        if unpin_old {
+            let block = match self.get_block_now(old).await? {
+                Some(b) => b,
+                None => return Err(anyhow::anyhow!("pinned root not found: {}", old)),
+            };
+            let ipld = block.decode::<IpldCodec, Ipld>()?;
+            let refs = crate::refs::IpldRefs::default()
+                .with_only_unique()
+                .with_existing_blocks()
+                .refs_of_resolved(self, vec![(*old, ipld)])
+                .map_ok(|crate::refs::Edge { destination, .. }| destination)
+                .into_stream()
+                .boxed();
+            remove.push((*old, refs));
+            label = self.pin_label(old).await?.filter(|label| !label.is_empty());
+        }
+
+        let inserted = !insert.is_empty();
+        let _g = self.inner.gclock.read().await;
+        self.inner
+            .data_store
+            .update_recursive_pins(insert, remove)
+            .await?;
+
+        if inserted {
+            self.new_pin(new, PinMode::Recursive, indirect).await;
+        }
+        if unpin_old {
+            self.notify(RepoNotification::PinRemoved { cid: *old });
+        }
+        if let Some(label) = label {
+            self.inner.data_store.set_label(new, &label).await?;
+            if label.provide {
+                self.provide_pin(new).await;
+                if !self.is_pinned(old).await? {
+                    self.unprovide_pin(old).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn is_pinned_recursively(&self, cid: &Cid) -> bool {
+        matches!(
+            self.query_pins(vec![*cid], PinMode::Recursive).await,
+            Ok(pins) if !pins.is_empty()
+        )
+    }
+
+    /// Loads the blocks reachable from each of the roots, the blocks shared between the roots
+    /// being loaded once, and returns the references of each root.
+    async fn references_of_roots(
+        &self,
+        roots: &[Cid],
+        local: bool,
+    ) -> Result<Vec<Vec<Cid>>, Error> {
+        let mut links = HashMap::<Cid, Vec<Cid>>::new();
+        let mut references_of_roots = Vec::with_capacity(roots.len());
+
+        for root in roots {
+            let mut queue = std::collections::VecDeque::from([*root]);
+            let mut visited = HashSet::from([*root]);
+            let mut references = Vec::new();
+
+            while let Some(cid) = queue.pop_front() {
+                if !links.contains_key(&cid) {
+                    let block = self.get_block(&cid, &[], local).await?;
+                    let mut block_links = Vec::new();
+                    block.references(&mut block_links)?;
+                    links.insert(cid, block_links);
+                }
+
+                for link in &links[&cid] {
+                    if visited.insert(*link) {
+                        queue.push_back(*link);
+                        references.push(*link);
+                    }
+                }
+            }
+
+            references_of_roots.push(references);
+        }
+
+        Ok(references_of_roots)
+    }
+
+    /// Keeps `cid` and the blocks reachable from it from being collected until the returned
+    /// guard is dropped.
+    pub(crate) fn pin_in_progress(&self, cid: Cid) -> PinInProgress {
+        *self.inner.pins_in_progress.lock().entry(cid).or_default() += 1;
+        PinInProgress {
+            repo: self.clone(),
+            cid,
+        }
+    }
+
     /// Inserts a direct pin for a `Cid`.
     pub(crate) async fn insert_direct_pin(&self, cid: &Cid) -> Result<(), Error> {
-        self.inner.data_store.insert_direct_pin(cid).await
+        self.inner.data_store.insert_direct_pin(cid).await?;
+        self.new_pin(cid, PinMode::Direct, vec![]).await;
+        Ok(())
     }
 
     /// Inserts a recursive pin for a `Cid`.
@@ -942,7 +2541,23 @@ impl Repo {
         cid: &Cid,
         refs: References<'_>,
     ) -> Result<(), Error> {
-        self.inner.data_store.insert_recursive_pin(cid, refs).await
+        // the references are collected as they are pinned when the pinned blocks are provided
+        let indirect = Arc::new(Mutex::new(Vec::new()));
+        let refs = match self.provides_indirect_pins() {
+            true => {
+                let indirect = indirect.clone();
+                refs.inspect_ok(move |cid| indirect.lock().push(*cid))
+                    .boxed()
+            }
+            false => refs,
+        };
+        self.inner
+            .data_store
+            .insert_recursive_pin(cid, refs)
+            .await?;
+        let indirect = std::mem::take(&mut *indirect.lock());
+        self.new_pin(cid, PinMode::Recursive, indirect).await;
+        Ok(())
     }
 
     /// Removes a direct pin for a `Cid`.
@@ -960,31 +2575,112 @@ impl Repo {
         self.inner.data_store.remove_recursive_pin(cid, refs).await
     }
 
-    /// Function to perform a basic cleanup of unpinned blocks
-    pub(crate) async fn cleanup(&self) -> Result<Vec<Cid>, Error> {
-        let repo = self.clone();
+    /// Removes the blocks which are not reachable from a pin, nor from the files root of
+    /// [`Mfs`](crate::unixfs::Mfs), and were not written within the grace period.
+    ///
+    /// Blocks reachable from the roots of pins still in progress are kept, as they are about to
+    /// become pinned. The caller is expected to hold the write guard of the gc lock.
+    pub(crate) async fn cleanup(&self) -> Result<GCResult, Error> {
+        let unpinned = self.collectable_blocks().await?;
+        let (removed, reclaimed) = self.remove_blocks(unpinned).await;
 
-        let blocks = repo.list_blocks().await;
+        Ok(GCResult { removed, reclaimed })
+    }
 
-        let stream = async_stream::stream! {
-            for await cid in blocks {
-                if repo.is_pinned(&cid).await.unwrap_or_default() {
-                    continue;
-                }
-                yield cid;
+    /// Removes the least recently accessed of the blocks [`Repo::cleanup`] would remove, until
+    /// their total size is at most `target` bytes. The caller is expected to hold the write guard
+    /// of the gc lock.
+    async fn evict(&self, target: u64) -> Result<GCResult, Error> {
+        let mut collectable = self.collectable_blocks().await?;
+        {
+            let lru = self.inner.lru.lock();
+            // blocks not accessed since startup are evicted first
+            collectable.sort_by_key(|cid| lru.accessed.get(cid).copied().unwrap_or_default());
+        }
+
+        let mut size = self.block_stat().await?.lock().bytes;
+        let mut evicted = Vec::new();
+        for cid in collectable {
+            if size <= target {
+                break;
             }
+            if let Ok(Some(block_size)) = self.get_blocks_size(std::slice::from_ref(&cid)).await {
+                size = size.saturating_sub(block_size as u64);
+            }
+            evicted.push(cid);
         }
-        .boxed();
 
-        let removed_blocks = self
+        let (removed, reclaimed) = self.remove_blocks(evicted).await;
+        self.inner
+            .evictions
+            .fetch_add(removed.len() as u64, Ordering::Relaxed);
+
+        Ok(GCResult { removed, reclaimed })
+    }
+
+    /// Lists the blocks which are neither reachable from a pin or the files root, nor within the
+    /// grace period.
+    async fn collectable_blocks(&self) -> Result<Vec<Cid>, Error> {
+        let mut roots = self
             .inner
-            .block_store
-            .remove_many(stream)
+            .pins_in_progress
+            .lock()
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+
+        match self.inner.data_store.get(crate::unixfs::MFS_ROOT_KEY).await {
+            Ok(Some(bytes)) => match Cid::try_from(bytes.as_slice()) {
+                Ok(root) => roots.push(root),
+                Err(e) => warn!("invalid files root while collecting: {}", e),
+            },
+            Ok(None) => {}
+            // not every datastore supports storing the files root
+            Err(e) => debug!("files root unavailable while collecting: {}", e),
+        }
+
+        let mut reachable = HashSet::new();
+        while let Some(cid) = roots.pop() {
+            if !reachable.insert(cid) {
+                continue;
+            }
+            if let Ok(Some(block)) = self.get_block_now(&cid).await {
+                if let Err(e) = block.references(&mut roots) {
+                    warn!(
+                        "failed to read the links of {} while collecting: {}",
+                        cid, e
+                    );
+                }
+            }
+        }
+
+        // compared by the multihash, as blockstores might not keep the codec of the blocks
+        let mut kept = reachable
+            .iter()
+            .map(|cid| *cid.hash())
+            .collect::<HashSet<_>>();
+
+        {
+            let grace_period = self.gc_grace_period();
+            let mut recent_blocks = self.inner.recent_blocks.lock();
+            recent_blocks.retain(|(_, written)| written.elapsed() < grace_period);
+            kept.extend(recent_blocks.iter().map(|(cid, _)| *cid.hash()));
+        }
+
+        let mut pins = self.list_pins(None).await;
+        while let Some((cid, _)) = pins.try_next().await? {
+            kept.insert(*cid.hash());
+        }
+
+        let unpinned = self
+            .list_blocks(BlockFilter::default())
             .await
+            .map(|(cid, _)| cid)
+            .filter(|cid| futures::future::ready(!kept.contains(cid.hash())))
             .collect::<Vec<_>>()
             .await;
 
-        Ok(removed_blocks)
+        Ok(unpinned)
     }
 
     /// Checks if a `Cid` is pinned.
@@ -1008,10 +2704,266 @@ impl Repo {
         let requirement = requirement.into();
         self.inner.data_store.query(cids, requirement).await
     }
+
+    /// Returns the label of a direct or recursive pin.
+    pub async fn pin_label(&self, cid: &Cid) -> Result<Option<PinLabel>, Error> {
+        self.inner.data_store.label(cid).await
+    }
+
+    /// Lists pins like [`Repo::list_pins`] along with the labels of direct and recursive pins.
+    /// Pins which are only indirect are listed with an empty label.
+    pub async fn list_labeled_pins(
+        &self,
+        mode: impl Into<Option<PinMode>>,
+    ) -> BoxStream<'static, Result<(Cid, PinMode, PinLabel), Error>> {
+        let repo = self.clone();
+        let pins = self.list_pins(mode).await;
+
+        async_stream::try_stream! {
+            for await pin in pins {
+                let (cid, mode) = pin?;
+                // a direct pin can also be indirect, in which case it might be listed as such
+                let label = repo.pin_label(&cid).await?.unwrap_or_default();
+                yield (cid, mode, label);
+            }
+        }
+        .boxed()
+    }
+
+    /// Lists direct and recursive pins whose name starts with `prefix`.
+    pub async fn list_pins_by_name(
+        &self,
+        prefix: impl Into<String>,
+    ) -> BoxStream<'static, Result<(Cid, PinMode, PinLabel), Error>> {
+        let prefix = prefix.into();
+        self.list_labeled_pins(None)
+            .await
+            .try_filter(move |(_, _, label)| {
+                let matches = label
+                    .name
+                    .as_deref()
+                    .map(|name| name.starts_with(&prefix))
+                    .unwrap_or_default();
+                futures::future::ready(matches)
+            })
+            .boxed()
+    }
+}
+
+/// The outcome of a garbage collection.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GCResult {
+    /// The removed blocks.
+    pub removed: Vec<Cid>,
+    /// The total size of the removed blocks, in bytes.
+    pub reclaimed: usize,
+}
+
+/// Totals of the blockstore.
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+struct BlockStat {
+    blocks: u64,
+    bytes: u64,
+    /// Room reserved by the writes in progress, see [`Repo::ensure_capacity`].
+    #[serde(skip)]
+    reserved: u64,
+}
+
+/// Room reserved within the storage limit, released on drop.
+#[derive(Default)]
+struct Reservation<'a> {
+    stat: Option<&'a Mutex<BlockStat>>,
+    bytes: u64,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if let Some(stat) = self.stat {
+            let mut stat = stat.lock();
+            stat.reserved = stat.reserved.saturating_sub(self.bytes);
+        }
+    }
+}
+
+/// Statistics of the repo, see [`crate::Ipfs::repo_stat`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RepoStat {
+    /// Number of blocks in the blockstore.
+    pub num_blocks: u64,
+    /// Total size of the blocks, in bytes.
+    pub size_bytes: u64,
+    /// The storage limit in bytes, if any.
+    pub storage_max: Option<u64>,
+    /// Number of blocks evicted with [`Eviction::Lru`].
+    pub evictions: u64,
+    /// Number of requested blocks which were found in the blockstore.
+    pub hits: u64,
+    /// Number of requested blocks which had to be fetched from the network.
+    pub misses: u64,
+}
+
+impl RepoStat {
+    /// The ratio of requested blocks found in the blockstore, zero when none were requested.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+/// How blocks are evicted when writing would exceed the storage limit, see
+/// [`Repo::set_eviction`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Eviction {
+    /// Blocks are not evicted; garbage is collected only if [`Repo::gc_auto`] is enabled.
+    #[default]
+    None,
+    /// The least recently accessed blocks which are neither pinned nor reachable from the files
+    /// root are evicted, as in a cache.
+    Lru,
+}
+
+/// Access order of the blocks for [`Eviction::Lru`].
+#[derive(Debug, Default)]
+struct LruState {
+    tick: u64,
+    accessed: HashMap<Cid, u64>,
+}
+
+/// The block is not in the blockstore and was not fetched from the network, as only local blocks
+/// were requested or the node is offline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("block {0} is not available locally")]
+pub struct BlockNotLocal(pub Cid);
+
+/// Where the blocks missing from the blockstore are looked for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FetchPolicy {
+    /// Missing blocks are fetched from the network.
+    #[default]
+    Network,
+    /// Only the blockstore is read, missing blocks failing with [`BlockNotLocal`].
+    LocalOnly,
+}
+
+/// Writing blocks would exceed the storage limit of the repo, see
+/// [`Repo::set_max_storage_size`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("storage is full: {needed} bytes do not fit within the limit of {storage_max} bytes")]
+pub struct StorageFull {
+    /// Size of the blocks which were about to be written, in bytes.
+    pub needed: u64,
+    /// The storage limit, in bytes.
+    pub storage_max: u64,
+}
+
+/// Keeps garbage collection from running while blocks are being written and pinned.
+///
+/// Unlike [`tokio::sync::RwLock`], a pending collection does not hold back new readers, so a
+/// task holding a guard can take another one, which happens when e.g. [`Repo::put_block`] is
+/// called while holding [`Repo::gc_guard`]. A collection waits until there are no readers, and
+/// readers wait until a running collection has finished.
+#[derive(Debug, Default)]
+pub(crate) struct GCLock {
+    state: Mutex<GCLockState>,
+    notify: tokio::sync::Notify,
+}
+
+#[derive(Debug, Default)]
+struct GCLockState {
+    readers: usize,
+    collecting: bool,
+    /// Collections waiting for the readers to leave, which new readers give way to.
+    waiting_writers: usize,
+}
+
+/// How long new readers give way to a waiting collection, so that a steady flow of writes does
+/// not starve it. They are let in afterwards, as they may be nested in a guard already held, e.g.
+/// one taken with [`Repo::gc_guard`], which the collection waits for.
+const WRITER_PRECEDENCE: Duration = Duration::from_millis(500);
+
+impl GCLock {
+    pub(crate) async fn read(&self) -> GCReadGuard<'_> {
+        let deadline = Instant::now() + WRITER_PRECEDENCE;
+        loop {
+            // registered before checking, so a release in between is not missed
+            let released = self.notify.notified();
+            let collecting = {
+                let mut state = self.state.lock();
+                let giving_way = state.waiting_writers > 0 && Instant::now() < deadline;
+                if !state.collecting && !giving_way {
+                    state.readers += 1;
+                    return GCReadGuard { lock: self };
+                }
+                state.collecting
+            };
+
+            if collecting {
+                released.await;
+            } else {
+                let _ = tokio::time::timeout_at(deadline.into(), released).await;
+            }
+        }
+    }
+
+    pub(crate) async fn write(&self) -> GCWriteGuard<'_> {
+        self.state.lock().waiting_writers += 1;
+        let _waiting = WaitingWriter { lock: self };
+        loop {
+            let released = self.notify.notified();
+            {
+                let mut state = self.state.lock();
+                if !state.collecting && state.readers == 0 {
+                    state.collecting = true;
+                    return GCWriteGuard { lock: self };
+                }
+            }
+            released.await;
+        }
+    }
+}
+
+/// Counts a collection waiting for the gc lock, until it takes the lock or gives up.
+struct WaitingWriter<'a> {
+    lock: &'a GCLock,
+}
+
+impl Drop for WaitingWriter<'_> {
+    fn drop(&mut self) {
+        self.lock.state.lock().waiting_writers -= 1;
+        // the readers giving way are let in, unless the collection started
+        self.lock.notify.notify_waiters();
+    }
+}
+
+pub(crate) struct GCReadGuard<'a> {
+    lock: &'a GCLock,
+}
+
+impl Drop for GCReadGuard<'_> {
+    fn drop(&mut self) {
+        let mut state = self.lock.state.lock();
+        state.readers -= 1;
+        if state.readers == 0 {
+            self.lock.notify.notify_waiters();
+        }
+    }
+}
+
+pub(crate) struct GCWriteGuard<'a> {
+    lock: &'a GCLock,
+}
+
+impl Drop for GCWriteGuard<'_> {
+    fn drop(&mut self) {
+        self.lock.state.lock().collecting = false;
+        self.lock.notify.notify_waiters();
+    }
 }
 
 pub struct GCGuard<'a> {
-    _g: RwLockReadGuard<'a, ()>,
+    _g: GCReadGuard<'a>,
 }
 
 impl Repo {
@@ -1140,7 +3092,10 @@ pub struct RepoInsertPin {
     span: Option<Span>,
     recursive: bool,
     local: bool,
-    refs: crate::refs::IpldRefs,
+    label: PinLabel,
+    depth: Option<u64>,
+    timeout: Option<Duration>,
+    exit_on_error: bool,
 }
 
 impl RepoInsertPin {
@@ -1150,11 +3105,33 @@ impl RepoInsertPin {
             cid,
             recursive: false,
             local: false,
-            refs: Default::default(),
+            label: Default::default(),
+            depth: None,
+            timeout: None,
+            exit_on_error: false,
             span: None,
         }
     }
 
+    /// Name the pin. Replaces the name of an existing pin.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.label.name = Some(name.into());
+        self
+    }
+
+    /// Attach metadata to the pin. Replaces the metadata of an existing pin.
+    pub fn metadata<K, V>(mut self, metadata: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.label.metadata = metadata
+            .into_iter()
+            .map(|(k, v)| (k.into(), v.into()))
+            .collect();
+        self
+    }
+
     /// Recursively pin blocks
     pub fn recursive(mut self) -> Self {
         self.recursive = true;
@@ -1164,35 +3141,38 @@ impl RepoInsertPin {
     /// Pin local blocks only
     pub fn local(mut self) -> Self {
         self.local = true;
-        self.refs = self.refs.with_existing_blocks();
         self
     }
 
     /// Set a flag to pin local blocks only
     pub fn set_local(mut self, local: bool) -> Self {
         self.local = local;
-        if local {
-            self.refs = self.refs.with_existing_blocks();
-        }
+        self
+    }
+
+    /// Provide the root through the DHT once pinned, and again after restarts until unpinned.
+    /// Recorded in the label of the pin, replacing an existing label like [`RepoInsertPin::name`].
+    pub fn provide(mut self) -> Self {
+        self.label.provide = true;
         self
     }
 
     /// Pin to a specific depth of the graph
     pub fn depth(mut self, depth: u64) -> Self {
-        self.refs = self.refs.with_max_depth(depth);
+        self.depth = Some(depth);
         self
     }
 
     /// Duration to fetch the block from the network before
     /// timing out
     pub fn timeout(mut self, duration: Duration) -> Self {
-        self.refs = self.refs.with_timeout(duration);
+        self.timeout = Some(duration);
         self
     }
 
     ///
     pub fn exit_on_error(mut self) -> Self {
-        self.refs = self.refs.with_exit_on_error();
+        self.exit_on_error = true;
         self
     }
 
@@ -1201,39 +3181,107 @@ impl RepoInsertPin {
         self.span = Some(span);
         self
     }
-}
 
-impl std::future::IntoFuture for RepoInsertPin {
-    type Output = Result<(), anyhow::Error>;
-
-    type IntoFuture = BoxFuture<'static, Self::Output>;
+    /// Starts pinning in the background. The returned [`PinHandle`] reports the progress of a
+    /// recursive pin and allows cancelling it.
+    pub fn handle(self) -> PinHandle {
+        let token = CancellationToken::new();
+        let (tx, rx) = tokio::sync::watch::channel(PinProgress::default());
+        let task = tokio::spawn(self.run(token.clone(), tx));
+        PinHandle {
+            progress: rx,
+            token,
+            task,
+        }
+    }
 
-    fn into_future(self) -> Self::IntoFuture {
-        let cid = self.cid;
-        let local = self.local;
-        let span = self.span.unwrap_or(Span::current());
-        let recursive = self.recursive;
-        let repo = self.repo;
+    fn run(
+        self,
+        token: CancellationToken,
+        progress: tokio::sync::watch::Sender<PinProgress>,
+    ) -> BoxFuture<'static, Result<(), Error>> {
+        let RepoInsertPin {
+            repo,
+            cid,
+            span,
+            recursive,
+            local,
+            label,
+            depth,
+            timeout,
+            exit_on_error,
+        } = self;
+        let span = span.unwrap_or(Span::current());
         let span = debug_span!(parent: &span, "insert_pin", cid = %cid, recursive);
         async move {
-            // Although getting a block adds a guard, we will add a read guard here a head of time so we can hold it throughout this future
-            let _g = repo.inner.gclock.read().await;
-            let block = repo.get_block(&cid, &[], local).await?;
+            // fail on oversized labels before fetching anything
+            label.to_bytes()?;
 
             if !recursive {
+                // Although getting a block adds a guard, we will add a read guard here a head of time so we can hold it throughout this future
+                let _g = repo.inner.gclock.read().await;
+                repo.get_block(&cid, &[], local).await?;
+                progress.send_replace(PinProgress {
+                    fetched_blocks: 1,
+                    known_remaining: 0,
+                });
                 repo.insert_direct_pin(&cid).await?
             } else {
-                let ipld = block.decode::<IpldCodec, Ipld>()?;
+                // fetching a large dag can take a long while, so instead of holding off gc for
+                // all of it, the root is kept as a gc root until the pin is written or rolled back
+                let _in_progress = repo.pin_in_progress(cid);
+
+                let fetch = repo.get_block_with_session(None, &cid, &[], local, timeout);
+                let block = tokio::select! {
+                    _ = token.cancelled() => None,
+                    block = fetch => Some(block),
+                };
 
-                let st = self
-                    .refs
-                    .with_only_unique()
-                    .refs_of_resolved(&repo, vec![(cid, ipld.clone())])
-                    .map_ok(|crate::refs::Edge { destination, .. }| destination)
-                    .into_stream()
-                    .boxed();
+                let block = match block {
+                    Some(block) => block?,
+                    None => return Err(anyhow::anyhow!("pinning {cid} was cancelled")),
+                };
 
-                repo.insert_recursive_pin(&cid, st).await?
+                let walked = Arc::new(Mutex::new(Vec::new()));
+
+                let st = PinWalk {
+                    repo: repo.clone(),
+                    local,
+                    depth,
+                    timeout,
+                    exit_on_error,
+                    token: token.clone(),
+                    progress,
+                    walked: walked.clone(),
+                }
+                .references(block);
+
+                if let Err(e) = repo.insert_recursive_pin(&cid, st).await {
+                    if !token.is_cancelled() {
+                        return Err(e);
+                    }
+
+                    // pinstores may have recorded some of the references already
+                    let recorded = matches!(
+                        repo.query_pins(vec![cid], PinMode::Recursive).await,
+                        Ok(pins) if !pins.is_empty()
+                    );
+
+                    if recorded {
+                        let walked = std::mem::take(&mut *walked.lock());
+                        let refs = stream::iter(walked.into_iter().map(Ok)).boxed();
+                        repo.remove_recursive_pin(&cid, refs).await?;
+                    }
+
+                    return Err(anyhow::anyhow!("pinning {cid} was cancelled"));
+                }
+            }
+
+            if !label.is_empty() {
+                repo.inner.data_store.set_label(&cid, &label).await?;
+            }
+            if label.provide {
+                repo.provide_pin(&cid).await;
             }
             Ok(())
         }
@@ -1242,6 +3290,194 @@ impl std::future::IntoFuture for RepoInsertPin {
     }
 }
 
+impl std::future::IntoFuture for RepoInsertPin {
+    type Output = Result<(), anyhow::Error>;
+
+    type IntoFuture = BoxFuture<'static, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        let (tx, _) = tokio::sync::watch::channel(PinProgress::default());
+        self.run(CancellationToken::new(), tx)
+    }
+}
+
+/// Progress of a recursive pin, see [`RepoInsertPin::handle`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PinProgress {
+    /// Blocks loaded so far, including the root.
+    pub fetched_blocks: usize,
+    /// Blocks discovered through links but not yet loaded.
+    pub known_remaining: usize,
+}
+
+/// Handle to a pin started with [`RepoInsertPin::handle`]. Awaiting the handle waits for the pin
+/// to complete; dropping it leaves the pin running.
+pub struct PinHandle {
+    progress: tokio::sync::watch::Receiver<PinProgress>,
+    token: CancellationToken,
+    task: tokio::task::JoinHandle<Result<(), Error>>,
+}
+
+impl PinHandle {
+    /// Stream of progress updates starting with the current progress. The stream ends once the
+    /// pin has been written, has failed or has been rolled back.
+    pub fn progress(&self) -> BoxStream<'static, PinProgress> {
+        let mut rx = self.progress.clone();
+        async_stream::stream! {
+            loop {
+                let progress = *rx.borrow_and_update();
+                yield progress;
+                if rx.changed().await.is_err() {
+                    break;
+                }
+            }
+        }
+        .boxed()
+    }
+
+    /// Stops fetching further blocks and rolls back what was already recorded of the pin. The
+    /// handle then resolves to an error, unless the pin had already been written.
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+}
+
+impl std::future::Future for PinHandle {
+    type Output = Result<(), Error>;
+
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        self.task
+            .poll_unpin(cx)
+            .map(|res| res.map_err(Error::from).and_then(|res| res))
+    }
+}
+
+/// Breadth-first walk over the blocks linked from the root of a recursive pin, yielding each
+/// block once it has been loaded.
+struct PinWalk {
+    repo: Repo,
+    local: bool,
+    depth: Option<u64>,
+    timeout: Option<Duration>,
+    exit_on_error: bool,
+    token: CancellationToken,
+    progress: tokio::sync::watch::Sender<PinProgress>,
+    /// The yielded blocks, needed to roll back a cancelled pin.
+    walked: Arc<Mutex<Vec<Cid>>>,
+}
+
+impl PinWalk {
+    fn references(self, root: Block) -> References<'static> {
+        let PinWalk {
+            repo,
+            local,
+            depth,
+            timeout,
+            exit_on_error,
+            token,
+            progress,
+            walked,
+        } = self;
+
+        async_stream::stream! {
+            let mut queue = std::collections::VecDeque::new();
+            let mut visited = HashSet::new();
+            let mut links = Vec::new();
+            let mut fetched_blocks = 1;
+
+            if let Err(e) = root.references(&mut links) {
+                yield Err(crate::refs::IpldRefsError::Loading(e));
+                return;
+            }
+
+            // the links of the root are at depth zero, like in `IpldRefs`
+            for link in links.drain(..) {
+                if visited.insert(link) {
+                    queue.push_back((0, link));
+                }
+            }
+
+            progress.send_replace(PinProgress {
+                fetched_blocks,
+                known_remaining: queue.len(),
+            });
+
+            while let Some((level, cid)) = queue.pop_front() {
+                if matches!(depth, Some(depth) if depth <= level) {
+                    continue;
+                }
+
+                let fetch = repo.get_block_with_session(None, &cid, &[], local, timeout);
+                let block = tokio::select! {
+                    _ = token.cancelled() => None,
+                    block = fetch => Some(block),
+                };
+
+                let block = match block {
+                    Some(Ok(block)) => block,
+                    Some(Err(e)) => {
+                        warn!("failed to load {} while pinning: {}", cid, e);
+                        if exit_on_error {
+                            yield Err(crate::refs::IpldRefsError::Loading(e));
+                            return;
+                        }
+                        continue;
+                    }
+                    None => {
+                        yield Err(crate::refs::IpldRefsError::Cancelled);
+                        return;
+                    }
+                };
+
+                fetched_blocks += 1;
+
+                if depth.map(|depth| level + 1 < depth).unwrap_or(true) {
+                    if let Err(e) = block.references(&mut links) {
+                        yield Err(crate::refs::IpldRefsError::Loading(e));
+                        return;
+                    }
+
+                    for link in links.drain(..) {
+                        if visited.insert(link) {
+                            queue.push_back((level + 1, link));
+                        }
+                    }
+                }
+
+                progress.send_replace(PinProgress {
+                    fetched_blocks,
+                    known_remaining: queue.len(),
+                });
+
+                walked.lock().push(cid);
+                yield Ok(cid);
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Keeps the root of a pin in progress as a gc root until dropped, see [`Repo::cleanup`].
+pub(crate) struct PinInProgress {
+    repo: Repo,
+    cid: Cid,
+}
+
+impl Drop for PinInProgress {
+    fn drop(&mut self) {
+        let mut pins = self.repo.inner.pins_in_progress.lock();
+        if let std::collections::hash_map::Entry::Occupied(mut oe) = pins.entry(self.cid) {
+            *oe.get_mut() -= 1;
+            if *oe.get() == 0 {
+                oe.remove();
+            }
+        }
+    }
+}
+
 pub struct RepoRemovePin {
     repo: Repo,
     cid: Cid,
@@ -1288,8 +3524,11 @@ impl std::future::IntoFuture for RepoRemovePin {
         let span = debug_span!(parent: &span, "remove_pin", cid = %cid, recursive);
         async move {
             let _g = repo.inner.gclock.read().await;
+            // the label is removed along with the pin
+            let provided = matches!(repo.pin_label(&cid).await, Ok(Some(label)) if label.provide);
+
             if !recursive {
-                repo.remove_direct_pin(&cid).await
+                repo.remove_direct_pin(&cid).await?;
             } else {
                 // start walking refs of the root after loading it
 
@@ -1310,10 +3549,900 @@ impl std::future::IntoFuture for RepoRemovePin {
                     .into_stream()
                     .boxed();
 
-                repo.remove_recursive_pin(&cid, st).await
+                repo.remove_recursive_pin(&cid, st).await?;
+            }
+            repo.notify(RepoNotification::PinRemoved { cid });
+
+            // the root may still be pinned the other way
+            if provided && !repo.is_pinned(&cid).await? {
+                repo.unprovide_pin(&cid).await;
             }
+            Ok(())
         }
         .instrument(span)
         .boxed()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libipld::multihash::{Code, MultihashDigest};
+    use tokio::task::JoinHandle;
+
+    fn block(data: &[u8]) -> Block {
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(data));
+        Block::new(cid, data.to_vec()).unwrap()
+    }
+
+    fn linking(blocks: &[&Block]) -> Block {
+        let links = blocks
+            .iter()
+            .map(|block| Ipld::Link(*block.cid()))
+            .collect();
+        Block::encode(
+            libipld::cbor::DagCborCodec,
+            Code::Sha2_256,
+            &Ipld::List(links),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn gc_lock_is_not_starved_by_overlapping_readers() {
+        let repo = Repo::new_memory();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // two readers taking turns, one of them always holding the lock
+        let readers = (0..2)
+            .map(|i| {
+                let repo = repo.clone();
+                let stop = stop.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_millis(5 * i)).await;
+                    while !stop.load(Ordering::SeqCst) {
+                        let _g = repo.inner.gclock.read().await;
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let collected = tokio::time::timeout(Duration::from_secs(5), async {
+            let _g = repo.inner.gclock.write().await;
+        })
+        .await;
+        stop.store(true, Ordering::SeqCst);
+        for reader in readers {
+            reader.await.unwrap();
+        }
+        assert!(collected.is_ok(), "the collection was starved");
+    }
+
+    #[tokio::test]
+    async fn nested_readers_are_let_in_while_a_collection_waits() {
+        let repo = Repo::new_memory();
+        let outer = repo.inner.gclock.read().await;
+
+        let collection = tokio::spawn({
+            let repo = repo.clone();
+            async move {
+                let _g = repo.inner.gclock.write().await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // as when writing within a gc guard, which the collection waits for
+        let inner = tokio::time::timeout(Duration::from_secs(5), repo.inner.gclock.read())
+            .await
+            .expect("nested reader was let in");
+        assert!(!collection.is_finished());
+
+        drop(inner);
+        drop(outer);
+        collection.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn blocks_past_the_grace_period_are_forgotten_on_write() {
+        let repo = Repo::new_memory();
+        repo.init().await.unwrap();
+        repo.set_gc_grace_period(Duration::from_millis(20));
+
+        repo.put_block(block(b"old")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let (recent, _) = repo.put_block(block(b"recent")).await.unwrap();
+
+        let tracked = repo.inner.recent_blocks.lock().clone();
+        assert_eq!(
+            tracked.into_iter().map(|(cid, _)| cid).collect::<Vec<_>>(),
+            vec![recent]
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_puts_do_not_exceed_the_storage_limit() {
+        let repo = Repo::new_memory();
+        repo.init().await.unwrap();
+        repo.set_max_storage_size(16);
+
+        let puts = (0..4u8)
+            .map(|i| repo.put_block(block(&[i; 8])))
+            .collect::<Vec<_>>();
+        let written = futures::future::join_all(puts)
+            .await
+            .into_iter()
+            .filter(Result::is_ok)
+            .count();
+        assert_eq!(written, 2);
+
+        let stat = repo.stat().await.unwrap();
+        assert_eq!((stat.num_blocks, stat.size_bytes), (2, 16));
+        assert_eq!(repo.block_stat().await.unwrap().lock().reserved, 0);
+    }
+
+    #[tokio::test]
+    async fn saved_block_stat_is_loaded_once_instead_of_counting() {
+        let repo = Repo::new_memory();
+        // differs from the empty blockstore, to tell it was not counted
+        let saved = serde_json::to_vec(&BlockStat {
+            blocks: 3,
+            bytes: 42,
+            reserved: 0,
+        })
+        .unwrap();
+        repo.data_store().put(BLOCK_STAT_KEY, &saved).await.unwrap();
+        repo.init().await.unwrap();
+
+        let stat = repo.stat().await.unwrap();
+        assert_eq!((stat.num_blocks, stat.size_bytes), (3, 42));
+        // a crash from now on has the blocks counted on the next start
+        assert!(!repo.data_store().contains(BLOCK_STAT_KEY).await.unwrap());
+
+        repo.save_block_stat().await.unwrap();
+        assert_eq!(
+            repo.data_store().get(BLOCK_STAT_KEY).await.unwrap(),
+            Some(saved)
+        );
+
+        // the saved totals are removed once they change
+        repo.put_block(block(b"changes the totals")).await.unwrap();
+        assert!(!repo.data_store().contains(BLOCK_STAT_KEY).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn new_blocks_only_expect_a_reply_when_provided() {
+        let repo = Repo::new_memory();
+        repo.init().await.unwrap();
+        let mut events = repo.initialize_channel();
+
+        for (provider, data) in [
+            (RepoProvider::None, &b"not provided"[..]),
+            (RepoProvider::Pinned, b"provided once pinned"),
+            (RepoProvider::All, b"provided"),
+        ] {
+            repo.set_provider(provider);
+            let put = tokio::spawn({
+                let repo = repo.clone();
+                let block = block(data);
+                async move { repo.put_block(block).await }
+            });
+            let Some(RepoEvent::NewBlock(_, ret)) = events.next().await else {
+                panic!("new block was not signaled");
+            };
+            assert_eq!(ret.is_some(), provider == RepoProvider::All);
+            // the write does not wait for the block to be provided
+            put.await.unwrap().unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn indirect_pins_are_only_signaled_when_pinned_blocks_are_provided() {
+        for (provider, signaled) in [
+            (RepoProvider::Pinned, true),
+            (RepoProvider::Roots, false),
+            (RepoProvider::None, false),
+        ] {
+            let repo = Repo::new_memory();
+            repo.init().await.unwrap();
+            repo.set_provider(provider);
+
+            let leaf = block(b"pinned indirectly");
+            let root = linking(&[&leaf]);
+            for block in [&leaf, &root] {
+                repo.put_block(block.clone()).await.unwrap();
+            }
+
+            let mut events = repo.initialize_channel();
+            let pin = tokio::spawn({
+                let repo = repo.clone();
+                let cid = *root.cid();
+                async move { repo.pin(&cid).recursive().local().await }
+            });
+            let Some(RepoEvent::NewPin(cid, indirect)) = events.next().await else {
+                panic!("new pin was not signaled");
+            };
+            pin.await.unwrap().unwrap();
+
+            assert_eq!(cid, *root.cid());
+            match signaled {
+                true => assert_eq!(indirect, vec![*leaf.cid()]),
+                false => assert!(indirect.is_empty()),
+            }
+        }
+    }
+
+    /// Counts the blocks wanted and unwanted until the repo is shut down, acknowledging the new
+    /// blocks as the ipfs task does.
+    fn count_events(repo: &Repo) -> JoinHandle<(usize, usize)> {
+        let mut events = repo.initialize_channel();
+        tokio::spawn(async move {
+            let (mut wanted, mut unwanted) = (0, 0);
+            while let Some(event) = events.next().await {
+                match event {
+                    RepoEvent::WantBlock(_, cids, _, _) => wanted += cids.len(),
+                    RepoEvent::UnwantBlock(_, _) => unwanted += 1,
+                    RepoEvent::NewBlock(_, Some(ret)) => {
+                        _ = ret.send(Ok(()));
+                    }
+                    _ => {}
+                }
+            }
+            (wanted, unwanted)
+        })
+    }
+
+    #[tokio::test]
+    async fn subscribers_are_notified_of_the_blocks_and_pins() {
+        let repo = Repo::new_memory();
+        repo.init().await.unwrap();
+        let mut notifications = repo.subscribe();
+
+        let block = block(b"notified");
+        let cid = *block.cid();
+        repo.put_block(block.clone()).await.unwrap();
+        // already stored
+        repo.put_block(block.clone()).await.unwrap();
+        repo.insert_direct_pin(&cid).await.unwrap();
+        repo.remove_pin(&cid).await.unwrap();
+        repo.remove_block(&cid, false).await.unwrap();
+
+        assert_eq!(
+            notifications.next().await,
+            Some(RepoNotification::BlockAdded {
+                cid,
+                size: block.data().len(),
+                source: BlockSource::Local,
+            })
+        );
+        assert_eq!(
+            notifications.next().await,
+            Some(RepoNotification::PinAdded {
+                cid,
+                mode: PinMode::Direct,
+            })
+        );
+        assert_eq!(
+            notifications.next().await,
+            Some(RepoNotification::PinRemoved { cid })
+        );
+        assert_eq!(
+            notifications.next().await,
+            Some(RepoNotification::BlockRemoved { cid })
+        );
+
+        // the subscription does not keep the repo alive
+        drop(repo);
+        assert_eq!(notifications.next().await, None);
+    }
+
+    #[cfg(feature = "block_encryption")]
+    #[tokio::test]
+    async fn blocks_are_encrypted_at_rest() {
+        use encryption::XChaCha20Poly1305Cipher;
+
+        let repo = Repo::new_memory();
+        repo.init().await.unwrap();
+
+        let plain = block(b"stored before the encryption");
+        repo.put_block(plain.clone()).await.unwrap();
+
+        let cipher = Arc::new(XChaCha20Poly1305Cipher::new([7; 32]));
+        assert_eq!(repo.encrypt_in_place(cipher.clone()).await.unwrap(), 1);
+        // already encrypted
+        assert_eq!(repo.encrypt_in_place(cipher).await.unwrap(), 0);
+
+        let sealed = block(b"stored encrypted");
+        repo.put_block(sealed.clone()).await.unwrap();
+
+        for block in [&plain, &sealed] {
+            let stored = repo.inner.block_store.get(block.cid()).await.unwrap();
+            assert_ne!(stored.unwrap().data(), block.data());
+            let read = repo.get_block_now(block.cid()).await.unwrap();
+            assert_eq!(read.as_ref(), Some(block));
+        }
+        assert_eq!(
+            repo.get_blocks_size(&[*sealed.cid()]).await.unwrap(),
+            Some(sealed.data().len())
+        );
+
+        repo.set_block_cipher(Some(Arc::new(XChaCha20Poly1305Cipher::new([8; 32]))));
+        let e = repo.get_block_now(sealed.cid()).await.unwrap_err();
+        assert_eq!(e.downcast_ref(), Some(&DecryptionFailed(*sealed.cid())));
+
+        repo.set_block_cipher(None);
+        assert!(repo.check_encryption().await.is_err());
+    }
+
+    #[cfg(feature = "block_encryption")]
+    #[tokio::test]
+    async fn interrupted_encryption_is_resumed() {
+        use encryption::XChaCha20Poly1305Cipher;
+
+        let repo = Repo::new_memory();
+        let cipher = Arc::new(XChaCha20Poly1305Cipher::new([7; 32]));
+        let sealed = block(b"encrypted before the interruption");
+        let plain = block(b"still in plaintext");
+
+        // as left by an interrupted Repo::encrypt_in_place
+        repo.inner
+            .data_store
+            .put(encryption::ENCRYPTED_KEY, encryption::ENCRYPTING)
+            .await
+            .unwrap();
+        let data = cipher.encrypt(sealed.cid(), sealed.data()).unwrap();
+        repo.inner
+            .block_store
+            .put(Block::new_unchecked(*sealed.cid(), data))
+            .await
+            .unwrap();
+        repo.inner.block_store.put(plain.clone()).await.unwrap();
+
+        // the encrypted blocks are never read as plaintext
+        assert!(repo.check_encryption().await.is_err());
+        repo.set_block_cipher(Some(cipher.clone()));
+        repo.init().await.unwrap();
+
+        for block in [&sealed, &plain] {
+            let read = repo.get_block_now(block.cid()).await.unwrap();
+            assert_eq!(read.as_ref(), Some(block));
+        }
+
+        assert_eq!(repo.encrypt_in_place(cipher).await.unwrap(), 1);
+        let stored = repo.inner.block_store.get(plain.cid()).await.unwrap();
+        assert_ne!(stored.unwrap().data(), plain.data());
+        assert_eq!(
+            repo.inner
+                .data_store
+                .get(encryption::ENCRYPTED_KEY)
+                .await
+                .unwrap(),
+            Some(vec![])
+        );
+
+        repo.set_block_cipher(None);
+        assert!(repo.get_block_now(plain.cid()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn blocks_rejected_by_the_policy_are_not_written() {
+        let repo = Repo::new_memory();
+        repo.init().await.unwrap();
+        repo.set_block_policy(Some(Arc::new(policy::BlockLimits::max_size(8))));
+
+        let small = block(b"small");
+        let large = block(b"larger than the limit");
+        repo.put_block(small.clone()).await.unwrap();
+
+        let e = repo.put_block(large.clone()).await.unwrap_err();
+        assert_eq!(
+            e.downcast_ref(),
+            Some(&PolicyViolation::TooLarge { size: 21, max: 8 })
+        );
+        let e = repo
+            .put_blocks(vec![small, large.clone()])
+            .await
+            .unwrap_err();
+        assert!(e.is::<PolicyViolation>());
+        assert!(repo.put_pinned_block(large.clone()).await.is_err());
+        assert!(!repo.contains(large.cid()).await.unwrap());
+    }
+
+    /// A repo with `old` pinned recursively, `old` and `new` sharing the first leaf.
+    async fn versioned_repo() -> (Repo, Block, Block, [Block; 3]) {
+        let repo = Repo::new_memory();
+        repo.init().await.unwrap();
+
+        let leaves = [block(b"shared"), block(b"old only"), block(b"new only")];
+        let old = linking(&[&leaves[0], &leaves[1]]);
+        let new = linking(&[&leaves[0], &leaves[2]]);
+        for block in [&leaves[0], &leaves[1], &old] {
+            repo.put_block(block.clone()).await.unwrap();
+        }
+        repo.pin(old.cid()).recursive().local().await.unwrap();
+
+        (repo, old, new, leaves)
+    }
+
+    async fn subscribed(repo: &Repo, cid: &Cid, count: usize) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while repo.inner.subscriptions.lock().get(cid).map_or(0, Vec::len) < count {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("every get to subscribe");
+    }
+
+    /// Starts a bitswap session worker for every wanted block like the task does, answering the
+    /// written blocks, and returns the number of workers started in each session.
+    fn session_workers(repo: &Repo) -> JoinHandle<HashMap<Option<u64>, usize>> {
+        let mut events = repo.initialize_channel();
+        tokio::spawn(async move {
+            let mut workers = HashMap::new();
+            while let Some(event) = events.next().await {
+                match event {
+                    RepoEvent::WantBlock(session, cids, _, _) => {
+                        *workers.entry(session).or_default() += cids.len();
+                    }
+                    RepoEvent::NewBlock(_, Some(ret)) => {
+                        _ = ret.send(Ok(()));
+                    }
+                    _ => {}
+                }
+            }
+            workers
+        })
+    }
+
+    #[tokio::test]
+    async fn concurrent_gets_want_the_block_once() {
+        let repo = Repo::new_memory();
+        repo.init().await.unwrap();
+        let workers = session_workers(&repo);
+
+        let block = block(b"wanted once");
+        let cid = *block.cid();
+
+        let gets = (0..100)
+            .map(|_| {
+                let repo = repo.clone();
+                tokio::spawn(async move { repo.get_block(&cid, &[], false).await })
+            })
+            .collect::<Vec<_>>();
+        subscribed(&repo, &cid, 100).await;
+
+        repo.put_block(block.clone()).await.unwrap();
+        for get in gets {
+            assert_eq!(get.await.unwrap().unwrap(), block);
+        }
+
+        repo.shutdown();
+        assert_eq!(workers.await.unwrap(), HashMap::from([(None, 1)]));
+    }
+
+    #[tokio::test]
+    async fn later_gets_forward_their_providers_and_priority() {
+        let repo = Repo::new_memory();
+        repo.init().await.unwrap();
+        let mut events = repo.initialize_channel();
+
+        let block = block(b"wanted with hints");
+        let cid = *block.cid();
+        let provider = PeerId::random();
+
+        let first = tokio::spawn({
+            let repo = repo.clone();
+            async move { repo.get_block(&cid, &[], false).await }
+        });
+        subscribed(&repo, &cid, 1).await;
+        // a get without hints nor a higher priority is not wanted again
+        let second = tokio::spawn({
+            let repo = repo.clone();
+            async move { repo.get_block(&cid, &[], false).await }
+        });
+        subscribed(&repo, &cid, 2).await;
+        let hinted = tokio::spawn({
+            let repo = repo.clone();
+            async move {
+                repo.get_block_with_priority(None, &cid, &[provider], false, None, 10)
+                    .await
+            }
+        });
+        subscribed(&repo, &cid, 3).await;
+
+        let mut wants = vec![];
+        while wants.len() < 2 {
+            if let Some(RepoEvent::WantBlock(_, cids, peers, priority)) = events.next().await {
+                wants.push((cids, peers, priority));
+            }
+        }
+        assert_eq!(
+            wants,
+            vec![
+                (vec![cid], vec![], DEFAULT_WANT_PRIORITY),
+                (vec![cid], vec![provider], 10)
+            ]
+        );
+
+        tokio::spawn(async move {
+            while let Some(event) = events.next().await {
+                if let RepoEvent::NewBlock(_, Some(ret)) = event {
+                    _ = ret.send(Ok(()));
+                }
+            }
+        });
+        repo.put_block(block.clone()).await.unwrap();
+        for get in [first, second, hinted] {
+            assert_eq!(get.await.unwrap().unwrap(), block);
+        }
+    }
+
+    #[tokio::test]
+    async fn block_is_unwanted_once_every_get_is_dropped() {
+        let repo = Repo::new_memory();
+        repo.init().await.unwrap();
+        let events = count_events(&repo);
+
+        let cid = *block(b"unwanted").cid();
+
+        let gets = (0..10)
+            .map(|_| {
+                let repo = repo.clone();
+                tokio::spawn(async move { repo.get_block(&cid, &[], false).await })
+            })
+            .collect::<Vec<_>>();
+        subscribed(&repo, &cid, 10).await;
+
+        for get in gets {
+            get.abort();
+            assert!(get.await.unwrap_err().is_cancelled());
+        }
+        assert!(!repo.inner.subscriptions.lock().contains_key(&cid));
+
+        repo.shutdown();
+        assert_eq!(events.await.unwrap(), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn try_get_block_is_busy_until_the_task_takes_the_wants() {
+        let repo = Repo::new_memory();
+        repo.init().await.unwrap();
+        let mut events = repo.initialize_channel();
+
+        // the channel holds the wants the task has yet to take, up to its capacity
+        let mut pending = Vec::new();
+        let busy = loop {
+            let block = block(format!("missing {}", pending.len()).as_bytes());
+            let fetch = tokio::spawn({
+                let repo = repo.clone();
+                async move { repo.try_get_block(block.cid()).await }
+            });
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            if fetch.is_finished() {
+                break fetch.await.unwrap().unwrap_err();
+            }
+            pending.push(fetch);
+            assert!(pending.len() <= 2, "the wants were never refused");
+        };
+        assert_eq!(busy.downcast_ref::<FacadeError>(), Some(&FacadeError::Busy));
+        assert!(!pending.is_empty());
+
+        // room is made once the task took the wants
+        for _ in &pending {
+            assert!(matches!(
+                events.next().await,
+                Some(RepoEvent::WantBlock(..))
+            ));
+        }
+        let fetch = tokio::spawn({
+            let repo = repo.clone();
+            async move { repo.try_get_block(block(b"wanted later").cid()).await }
+        });
+        // the refused block was unwanted when its request failed
+        loop {
+            match events.next().await {
+                Some(RepoEvent::UnwantBlock(..)) => continue,
+                Some(RepoEvent::WantBlock(..)) => break,
+                other => panic!("unexpected event {other:?}"),
+            }
+        }
+
+        repo.shutdown();
+        assert!(fetch.await.unwrap().is_err());
+        for fetch in pending {
+            assert!(fetch.await.unwrap().is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn block_is_wanted_and_unwanted_in_each_session() {
+        let repo = Repo::new_memory();
+        repo.init().await.unwrap();
+        let events = count_events(&repo);
+
+        let cid = *block(b"in sessions").cid();
+
+        let gets = [Some(1), Some(1), Some(2)]
+            .into_iter()
+            .map(|session| {
+                let repo = repo.clone();
+                tokio::spawn(async move {
+                    repo.get_block_with_session(session, &cid, &[], false, None)
+                        .await
+                })
+            })
+            .collect::<Vec<_>>();
+        subscribed(&repo, &cid, 3).await;
+
+        for get in gets {
+            get.abort();
+            assert!(get.await.unwrap_err().is_cancelled());
+        }
+        assert!(!repo.inner.subscriptions.lock().contains_key(&cid));
+
+        repo.shutdown();
+        assert_eq!(events.await.unwrap(), (2, 2));
+    }
+
+    #[tokio::test]
+    async fn pin_update_only_fetches_the_blocks_not_shared() {
+        let (repo, old, new, leaves) = versioned_repo().await;
+
+        // serves the wanted blocks as the peers would
+        let network = HashMap::from([
+            (*new.cid(), new.clone()),
+            (*leaves[2].cid(), leaves[2].clone()),
+        ]);
+        let mut events = repo.initialize_channel();
+        let fetcher = tokio::spawn({
+            let repo = repo.clone();
+            async move {
+                let mut fetched = Vec::new();
+                while let Some(event) = events.next().await {
+                    match event {
+                        RepoEvent::WantBlock(_, cids, _, _) => {
+                            for cid in cids {
+                                fetched.push(cid);
+                                let (repo, block) = (repo.clone(), network[&cid].clone());
+                                tokio::spawn(async move { repo.put_block(block).await });
+                            }
+                        }
+                        RepoEvent::NewBlock(_, Some(ret)) => {
+                            _ = ret.send(Ok(()));
+                        }
+                        _ => {}
+                    }
+                }
+                fetched
+            }
+        });
+
+        repo.pin_update(old.cid(), new.cid(), true, false)
+            .await
+            .unwrap();
+        repo.shutdown();
+        assert_eq!(fetcher.await.unwrap(), vec![*new.cid(), *leaves[2].cid()]);
+
+        assert!(repo.is_pinned_recursively(new.cid()).await);
+        assert!(!repo.is_pinned(old.cid()).await.unwrap());
+        assert!(!repo.is_pinned(leaves[1].cid()).await.unwrap());
+        let shared = repo.query_pins(vec![*leaves[0].cid()], None).await.unwrap();
+        assert_eq!(
+            shared,
+            vec![(*leaves[0].cid(), PinKind::IndirectFrom(*new.cid()))]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_pin_update_keeps_the_old_pin() {
+        let (repo, old, new, leaves) = versioned_repo().await;
+        repo.put_block(new.clone()).await.unwrap();
+
+        // fails while loading the new blocks, before any pin is written
+        assert!(repo
+            .pin_update(old.cid(), new.cid(), true, true)
+            .await
+            .is_err());
+        assert!(repo.is_pinned_recursively(old.cid()).await);
+        assert!(!repo.is_pinned(new.cid()).await.unwrap());
+
+        // fails between writing the new pin and removing the old one
+        let insert = vec![(
+            *new.cid(),
+            stream::iter(vec![Ok(*leaves[0].cid()), Ok(*leaves[2].cid())]).boxed(),
+        )];
+        let remove = vec![(
+            *old.cid(),
+            stream::iter(vec![
+                Ok(*leaves[0].cid()),
+                Err(crate::refs::IpldRefsError::Cancelled),
+            ])
+            .boxed(),
+        )];
+        let data_store = repo.data_store();
+        assert!(data_store
+            .update_recursive_pins(insert, remove)
+            .await
+            .is_err());
+        assert!(repo.is_pinned_recursively(old.cid()).await);
+        assert!(!repo.is_pinned(new.cid()).await.unwrap());
+        assert!(repo.is_pinned(leaves[1].cid()).await.unwrap());
+
+        repo.put_block(leaves[2].clone()).await.unwrap();
+        repo.pin_update(old.cid(), new.cid(), false, true)
+            .await
+            .unwrap();
+        assert!(repo.is_pinned_recursively(old.cid()).await);
+        assert!(repo.is_pinned_recursively(new.cid()).await);
+    }
+
+    #[tokio::test]
+    async fn pin_many_pins_every_root() {
+        let (repo, old, new, leaves) = versioned_repo().await;
+        repo.put_block(new.clone()).await.unwrap();
+        repo.put_block(leaves[2].clone()).await.unwrap();
+
+        repo.pin_many(&[*old.cid(), *new.cid(), *new.cid()], true)
+            .await
+            .unwrap();
+        assert!(repo.is_pinned_recursively(new.cid()).await);
+
+        let shared = repo.query_pins(vec![*leaves[0].cid()], None).await.unwrap();
+        assert!(matches!(shared[0].1, PinKind::IndirectFrom(_)));
+        repo.remove_pin(old.cid()).recursive().await.unwrap();
+        assert!(repo.is_pinned(leaves[0].cid()).await.unwrap());
+        assert!(!repo.is_pinned(leaves[1].cid()).await.unwrap());
+    }
+
+    async fn listed(repo: &Repo, filter: BlockFilter) -> Vec<(Cid, usize)> {
+        let mut blocks = repo.list_blocks(filter).await.collect::<Vec<_>>().await;
+        blocks.sort();
+        blocks
+    }
+
+    #[tokio::test]
+    async fn blocks_are_listed_by_filter() {
+        let (repo, old, _, leaves) = versioned_repo().await;
+        let unpinned = block(b"unpinned block");
+        repo.put_block(unpinned.clone()).await.unwrap();
+
+        let mut all = [&leaves[0], &leaves[1], &old, &unpinned]
+            .map(|block| (*block.cid(), block.data().len()))
+            .to_vec();
+        all.sort();
+        assert_eq!(listed(&repo, BlockFilter::default()).await, all);
+
+        let filter = BlockFilter {
+            codec: Some(IpldCodec::DagCbor.into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            listed(&repo, filter).await,
+            vec![(*old.cid(), old.data().len())]
+        );
+
+        let filter = BlockFilter {
+            min_size: Some(leaves[1].data().len()),
+            max_size: Some(leaves[1].data().len()),
+            ..Default::default()
+        };
+        assert_eq!(
+            listed(&repo, filter).await,
+            vec![(*leaves[1].cid(), leaves[1].data().len())]
+        );
+
+        let filter = BlockFilter {
+            pinned: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(
+            listed(&repo, filter).await,
+            vec![(*unpinned.cid(), unpinned.data().len())]
+        );
+
+        // the memory store does not record when the blocks were written
+        let filter = BlockFilter {
+            written_after: Some(SystemTime::UNIX_EPOCH),
+            ..Default::default()
+        };
+        assert!(listed(&repo, filter).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn blocks_are_listed_by_write_time() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let repo = Repo::new_fs(tmp.path());
+        repo.init().await.unwrap();
+
+        let earlier = block(b"written earlier");
+        repo.put_block(earlier.clone()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let start = SystemTime::now();
+        let later = block(b"written later");
+        repo.put_block(later.clone()).await.unwrap();
+
+        let filter = BlockFilter {
+            written_after: Some(start),
+            ..Default::default()
+        };
+        assert_eq!(
+            listed(&repo, filter).await,
+            vec![(*later.cid(), later.data().len())]
+        );
+
+        let filter = BlockFilter {
+            written_before: Some(start),
+            ..Default::default()
+        };
+        assert_eq!(
+            listed(&repo, filter).await,
+            vec![(*earlier.cid(), earlier.data().len())]
+        );
+    }
+
+    #[tokio::test]
+    async fn provided_pins_are_provided_until_unpinned() {
+        let repo = Repo::new_memory();
+        repo.init().await.unwrap();
+
+        let block = block(b"provided root");
+        let cid = *block.cid();
+        repo.put_block(block).await.unwrap();
+        let mut events = repo.initialize_channel();
+
+        let pin = tokio::spawn({
+            let repo = repo.clone();
+            async move { repo.pin(&cid).provide().await }
+        });
+        assert!(matches!(events.next().await, Some(RepoEvent::NewPin(c, _)) if c == cid));
+        assert!(matches!(events.next().await, Some(RepoEvent::ProvidePin(c)) if c == cid));
+        pin.await.unwrap().unwrap();
+        assert!(repo.pin_label(&cid).await.unwrap().unwrap().provide);
+        assert_eq!(repo.provided_pins().await, vec![cid]);
+
+        let unpin = tokio::spawn({
+            let repo = repo.clone();
+            async move { repo.remove_pin(&cid).await }
+        });
+        assert!(matches!(events.next().await, Some(RepoEvent::RemovedBlock(c)) if c == cid));
+        unpin.await.unwrap().unwrap();
+        assert!(repo.provided_pins().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn attached_channels_close_with_the_repo() {
+        let repo = Repo::new_memory();
+        repo.init().await.unwrap();
+
+        let mut events = repo.initialize_channel();
+        let mut attached = repo.attach_channel();
+
+        let block = block(b"shared block");
+        let put = tokio::spawn({
+            let repo = repo.clone();
+            let block = block.clone();
+            async move { repo.put_block(block).await }
+        });
+        // the attached node is told about the block, without providing it
+        match attached.next().await {
+            Some(RepoEvent::NewBlock(b, ret)) => {
+                assert_eq!(b.cid(), block.cid());
+                assert!(ret.is_none());
+            }
+            other => panic!("unexpected event {other:?}"),
+        }
+        match events.next().await {
+            Some(RepoEvent::NewBlock(b, _)) => {
+                assert_eq!(b.cid(), block.cid());
+            }
+            other => panic!("unexpected event {other:?}"),
+        }
+        put.await.unwrap().unwrap();
+
+        repo.shutdown();
+        assert!(events.next().await.is_none());
+        assert!(attached.next().await.is_none());
+    }
+}
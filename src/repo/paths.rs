@@ -1,5 +1,6 @@
 use core::convert::TryFrom;
-use libipld::{cid, multibase, Cid};
+use libipld::multihash::Multihash;
+use libipld::{cid, multibase, Cid, IpldCodec};
 use std::path::PathBuf;
 
 pub fn block_path(mut base: PathBuf, cid: &Cid) -> PathBuf {
@@ -36,6 +37,30 @@ pub fn filestem_to_block_cid(file_stem: Option<&std::ffi::OsStr>) -> Option<Cid>
     })
 }
 
+/// Path of the block in the layout of the go-ipfs flatfs datastore with the default
+/// `next-to-last/2` sharding: the file is named after the uppercase, unpadded base32 of the
+/// multihash, so the codec of the `Cid` does not matter.
+pub fn multihash_block_path(mut base: PathBuf, cid: &Cid) -> PathBuf {
+    let key = multibase::Base::Base32Upper.encode(cid.hash().to_bytes());
+
+    shard(&mut base, &key);
+
+    base.set_extension("data");
+    base
+}
+
+/// Decodes the file stem produced by [`multihash_block_path`], ignoring errors. As the codec is
+/// not known, the returned `Cid` is a CIDv1 with the raw codec.
+pub fn filestem_to_multihash_cid(file_stem: Option<&std::ffi::OsStr>) -> Option<Cid> {
+    file_stem.and_then(|stem| stem.to_str()).and_then(|s| {
+        let bytes = multibase::Base::Base32Upper.decode(s).ok()?;
+        let hash = Multihash::from_bytes(&bytes).ok()?;
+
+        // See filestem_to_block_cid for discusison on why the error is ignored
+        Some(Cid::new_v1(IpldCodec::Raw.into(), hash))
+    })
+}
+
 /// Same as `block_path` except it doesn't canonicalize the cid to later version. The produced
 /// filename must be converted back to `Cid` using [`filestem_to_pin_cid`].
 pub fn pin_path(mut base: PathBuf, cid: &Cid) -> PathBuf {
@@ -152,6 +177,31 @@ mod tests {
         assert_eq!(parsed, Some(cid_v1));
     }
 
+    #[test]
+    fn cid_to_multihash_block_path_and_back() {
+        let cid_v0 = "QmTEn8ypAkbJXZUXCRHBorwF2jM8uTUW9yRLzrcQouSoD4";
+        let cid_v0 = Cid::try_from(cid_v0).unwrap();
+        let cid_v1 = "bafybeicizfmyaovkw4pnrwpa4kcirzaveabyw4vsixt45mrrhr2xm2d5lm";
+        let cid_v1 = Cid::try_from(cid_v1).unwrap();
+
+        let base = PathBuf::from("blocks");
+
+        let cid_v0_path = super::multihash_block_path(base.clone(), &cid_v0);
+        let cid_v1_path = super::multihash_block_path(base, &cid_v1);
+
+        assert_eq!(cid_v0_path, cid_v1_path);
+
+        // same as the path written by go-ipfs
+        let expected = "blocks/2W/CIQERSKZQA5KVNY63DM6BYUERDSBKIADRNZLERPHZ2ZDCPDVOZUH2WY.data";
+
+        assert_eq!(cid_v0_path, Path::new(expected));
+
+        let parsed = super::filestem_to_multihash_cid(cid_v0_path.file_stem()).unwrap();
+
+        assert_eq!(parsed.hash(), cid_v0.hash());
+        assert_eq!(parsed.codec(), u64::from(libipld::IpldCodec::Raw));
+    }
+
     #[test]
     fn invalid_block_path_is_silently_ignored() {
         let block_path = Path::new("another_root/ba/foobar.data");
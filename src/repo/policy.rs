@@ -0,0 +1,96 @@
+//! Checks of the blocks written to the repo, see [`Repo::set_block_policy`](super::Repo::set_block_policy).
+
+use std::collections::HashSet;
+use std::fmt::Debug;
+
+use libipld::Cid;
+
+/// Decides whether a block may be written to the repo, whether it was put locally or received
+/// from a peer.
+pub trait BlockPolicy: Debug + Send + Sync + 'static {
+    /// Checks the block with the cid and data before it is written.
+    fn check(&self, cid: &Cid, data: &[u8]) -> Result<(), PolicyViolation>;
+}
+
+/// The block was rejected by the [`BlockPolicy`] of the repo.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PolicyViolation {
+    #[error("block of {size} bytes exceeds the maximum of {max} bytes")]
+    TooLarge { size: usize, max: usize },
+    #[error("codec {0:#x} is not allowed")]
+    CodecNotAllowed(u64),
+    #[error("{0}")]
+    Rejected(String),
+}
+
+/// Limits on the size and codec of the blocks, the common policy not needing a custom
+/// [`BlockPolicy`].
+#[derive(Debug, Clone, Default)]
+pub struct BlockLimits {
+    /// Maximum size of the data of a block in bytes, unlimited if `None`
+    pub max_size: Option<usize>,
+    /// Codecs the blocks may be encoded with, any if `None`
+    pub codecs: Option<HashSet<u64>>,
+}
+
+impl BlockLimits {
+    pub fn max_size(max_size: usize) -> Self {
+        Self {
+            max_size: Some(max_size),
+            codecs: None,
+        }
+    }
+}
+
+impl BlockPolicy for BlockLimits {
+    fn check(&self, cid: &Cid, data: &[u8]) -> Result<(), PolicyViolation> {
+        if let Some(max) = self.max_size {
+            if data.len() > max {
+                return Err(PolicyViolation::TooLarge {
+                    size: data.len(),
+                    max,
+                });
+            }
+        }
+
+        match &self.codecs {
+            Some(codecs) if !codecs.contains(&cid.codec()) => {
+                Err(PolicyViolation::CodecNotAllowed(cid.codec()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libipld::multihash::{Code, MultihashDigest};
+    use libipld::IpldCodec;
+
+    #[test]
+    fn limits_reject_large_blocks_and_other_codecs() {
+        let data = b"some block data";
+        let raw = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(data));
+        let cbor = Cid::new_v1(IpldCodec::DagCbor.into(), Code::Sha2_256.digest(data));
+
+        assert_eq!(BlockLimits::default().check(&raw, data), Ok(()));
+        assert_eq!(
+            BlockLimits::max_size(4).check(&raw, data),
+            Err(PolicyViolation::TooLarge {
+                size: data.len(),
+                max: 4
+            })
+        );
+
+        let limits = BlockLimits {
+            max_size: Some(1024),
+            codecs: Some(HashSet::from([IpldCodec::Raw.into()])),
+        };
+        assert_eq!(limits.check(&raw, data), Ok(()));
+        assert_eq!(
+            limits.check(&cbor, data),
+            Err(PolicyViolation::CodecNotAllowed(IpldCodec::DagCbor.into()))
+        );
+    }
+}
@@ -0,0 +1,452 @@
+//! Consistency check of the blocks and pins of a [`Repo`], see [`Repo::verify`].
+
+use std::collections::{BTreeSet, HashSet, VecDeque};
+
+use futures::future::BoxFuture;
+use futures::sink::SinkExt;
+use futures::stream::{self, BoxStream};
+use futures::{FutureExt, StreamExt};
+use libipld::Cid;
+use tokio_util::sync::CancellationToken;
+use tracing::Span;
+use tracing_futures::Instrument;
+
+use super::encryption::DecryptionFailed;
+use super::{BlockFilter, PinMode, Repo, RepoEvent, DEFAULT_WANT_PRIORITY};
+use crate::error::Error;
+use crate::Block;
+
+/// What [`Repo::verify`] does about the problems it finds.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RepairMode {
+    /// Only report the problems.
+    #[default]
+    Report,
+    /// Delete the corrupt blocks and want the missing blocks of the pins again, including the
+    /// roots of orphaned pins.
+    Fix,
+    /// Same as [`RepairMode::Fix`], except that the orphaned pins are removed instead of their
+    /// roots being wanted again.
+    FixDroppingOrphanedPins,
+}
+
+/// Outcome of [`Repo::verify`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Blocks whose data does not hash to their cid, or which could not be read.
+    pub corrupt: Vec<Cid>,
+    /// Blocks missing from the dag of a direct or recursive pin, as `(root, missing)`. Corrupt
+    /// blocks count as missing.
+    pub missing: Vec<(Cid, Cid)>,
+    /// Direct or recursive pins whose root block is missing or corrupt.
+    pub orphaned_pins: Vec<Cid>,
+}
+
+impl VerifyReport {
+    /// Returns true if no problems were found.
+    pub fn is_consistent(&self) -> bool {
+        self.corrupt.is_empty() && self.missing.is_empty() && self.orphaned_pins.is_empty()
+    }
+}
+
+/// Progress of [`Repo::verify`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyProgress {
+    /// Blocks of the blockstore which have been re-hashed.
+    pub scanned_blocks: usize,
+    /// Direct and recursive pins whose dag has been checked.
+    pub checked_pins: usize,
+}
+
+/// Handle to a check started with [`Repo::verify`]. Awaiting the handle waits for the
+/// [`VerifyReport`]; dropping it leaves the check running.
+pub struct VerifyHandle {
+    progress: tokio::sync::watch::Receiver<VerifyProgress>,
+    token: CancellationToken,
+    task: tokio::task::JoinHandle<Result<VerifyReport, Error>>,
+}
+
+impl VerifyHandle {
+    /// Stream of progress updates starting with the current progress. The stream ends once the
+    /// check has completed, has failed or has been cancelled.
+    pub fn progress(&self) -> BoxStream<'static, VerifyProgress> {
+        let mut rx = self.progress.clone();
+        async_stream::stream! {
+            loop {
+                let progress = *rx.borrow_and_update();
+                yield progress;
+                if rx.changed().await.is_err() {
+                    break;
+                }
+            }
+        }
+        .boxed()
+    }
+
+    /// Stops the check, after which the handle resolves to an error. Repairs already made are
+    /// kept.
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+}
+
+impl std::future::Future for VerifyHandle {
+    type Output = Result<VerifyReport, Error>;
+
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        self.task
+            .poll_unpin(cx)
+            .map(|res| res.map_err(Error::from).and_then(|res| res))
+    }
+}
+
+impl Repo {
+    /// Checks the repo for damage left behind by an unclean shutdown: every block is re-hashed to
+    /// find the corrupt ones, and the dags of the direct and recursive pins are walked to find
+    /// the missing blocks. Depending on `mode`, the problems found are repaired.
+    ///
+    /// The check runs in the background and holds off garbage collection until it completes.
+    pub fn verify(&self, mode: RepairMode) -> VerifyHandle {
+        let token = CancellationToken::new();
+        let (tx, rx) = tokio::sync::watch::channel(VerifyProgress::default());
+        let task = tokio::spawn(verify(self.clone(), mode, token.clone(), tx));
+        VerifyHandle {
+            progress: rx,
+            token,
+            task,
+        }
+    }
+}
+
+fn verify(
+    repo: Repo,
+    mode: RepairMode,
+    token: CancellationToken,
+    progress: tokio::sync::watch::Sender<VerifyProgress>,
+) -> BoxFuture<'static, Result<VerifyReport, Error>> {
+    let span = debug_span!(parent: &Span::current(), "repo_verify", ?mode);
+    async move {
+        let _g = repo.inner.gclock.read().await;
+        let mut report = VerifyReport::default();
+        let mut current = VerifyProgress::default();
+
+        let mut decrypted = false;
+        let mut blocks = repo.list_blocks(BlockFilter::default()).await;
+        while let Some((cid, _)) = blocks.next().await {
+            if token.is_cancelled() {
+                anyhow::bail!("verifying the repo was cancelled");
+            }
+
+            let corrupt = match repo.get_block_now(&cid).await {
+                Ok(Some(block)) => {
+                    decrypted = true;
+                    Block::new(cid, block.data().to_vec()).is_err()
+                }
+                // removed since being listed
+                Ok(None) => false,
+                // a wrong key rather than corrupt blocks, unless others could be decrypted
+                Err(e) if !decrypted && e.is::<DecryptionFailed>() => return Err(e),
+                Err(_) => true,
+            };
+
+            if corrupt {
+                warn!("block {} is corrupt", cid);
+                report.corrupt.push(cid);
+            }
+
+            current.scanned_blocks += 1;
+            progress.send_replace(current);
+        }
+
+        if mode != RepairMode::Report && !report.corrupt.is_empty() {
+            // the corrupt blocks are gone from now on, so that they can be fetched again
+            repo.remove_blocks(report.corrupt.clone()).await;
+        }
+
+        let corrupt = HashSet::<_>::from_iter(report.corrupt.iter().copied());
+        let mut pins = Vec::new();
+        let mut list = repo.list_pins(None).await;
+        while let Some((cid, pin_mode)) = list.next().await.transpose()? {
+            if pin_mode != PinMode::Indirect {
+                pins.push((cid, pin_mode));
+            }
+        }
+
+        let mut orphaned = Vec::new();
+        for (root, pin_mode) in pins {
+            if token.is_cancelled() {
+                anyhow::bail!("verifying the repo was cancelled");
+            }
+
+            let missing =
+                missing_blocks(&repo, &corrupt, root, pin_mode == PinMode::Recursive).await;
+            match missing.first() {
+                Some(cid) if *cid == root => {
+                    report.orphaned_pins.push(root);
+                    orphaned.push((root, pin_mode));
+                }
+                _ => report
+                    .missing
+                    .extend(missing.into_iter().map(|cid| (root, cid))),
+            }
+
+            current.checked_pins += 1;
+            progress.send_replace(current);
+        }
+
+        if mode == RepairMode::Report {
+            return Ok(report);
+        }
+
+        let mut wanted = BTreeSet::from_iter(report.missing.iter().map(|(_, cid)| *cid));
+
+        if mode == RepairMode::FixDroppingOrphanedPins {
+            for (root, pin_mode) in orphaned {
+                // the indirect pins cannot be walked without the root, only the pin on the root
+                // is removed
+                match pin_mode {
+                    PinMode::Recursive => {
+                        repo.remove_recursive_pin(&root, stream::empty().boxed())
+                            .await?
+                    }
+                    _ => repo.remove_direct_pin(&root).await?,
+                }
+            }
+        } else {
+            wanted.extend(report.orphaned_pins.iter().copied());
+        }
+
+        if !wanted.is_empty() && !repo.is_local_only() {
+            if let Some(mut events) = repo.repo_channel() {
+                _ = events
+                    .send(RepoEvent::WantBlock(
+                        None,
+                        Vec::from_iter(wanted),
+                        vec![],
+                        DEFAULT_WANT_PRIORITY,
+                    ))
+                    .await;
+            }
+        }
+
+        Ok(report)
+    }
+    .instrument(span)
+    .boxed()
+}
+
+/// Walks the dag of a pin over the local blocks, returning the blocks which are missing or
+/// corrupt. The root comes first if it is missing itself, in which case nothing else is walked.
+async fn missing_blocks(
+    repo: &Repo,
+    corrupt: &HashSet<Cid>,
+    root: Cid,
+    recursive: bool,
+) -> Vec<Cid> {
+    let mut missing = Vec::new();
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::from([root]);
+    let mut links = Vec::new();
+
+    while let Some(cid) = queue.pop_front() {
+        if !visited.insert(cid) {
+            continue;
+        }
+
+        let block = match repo.get_block_now(&cid).await {
+            Ok(Some(block)) if !corrupt.contains(&cid) => block,
+            _ => {
+                missing.push(cid);
+                continue;
+            }
+        };
+
+        if !recursive {
+            break;
+        }
+
+        if let Err(e) = block.references(&mut links) {
+            warn!("failed to decode the links of {}: {}", cid, e);
+        }
+        queue.extend(links.drain(..));
+    }
+
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::paths;
+    use libipld::multihash::{Code, MultihashDigest};
+    use libipld::{ipld, IpldCodec};
+
+    fn block(data: &[u8]) -> Block {
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(data));
+        Block::new(cid, data.to_vec()).unwrap()
+    }
+
+    /// A recursively pinned root linking to two leaves, and a directly pinned block.
+    async fn pinned_repo(path: &std::path::Path) -> (Repo, Cid, [Cid; 2], Cid) {
+        let repo = Repo::new_fs(path);
+        repo.init().await.unwrap();
+
+        let leaves = [block(b"first leaf"), block(b"second leaf")];
+        for leaf in &leaves {
+            repo.put_block(leaf.clone()).await.unwrap();
+        }
+
+        let root = Block::encode(
+            libipld::cbor::DagCborCodec,
+            Code::Sha2_256,
+            &ipld!([*leaves[0].cid(), *leaves[1].cid()]),
+        )
+        .unwrap();
+        repo.put_block(root.clone()).await.unwrap();
+        repo.pin(root.cid()).recursive().local().await.unwrap();
+
+        let direct = block(b"directly pinned");
+        repo.put_block(direct.clone()).await.unwrap();
+        repo.pin(direct.cid()).local().await.unwrap();
+
+        (
+            repo,
+            *root.cid(),
+            [*leaves[0].cid(), *leaves[1].cid()],
+            *direct.cid(),
+        )
+    }
+
+    fn corrupt(path: &std::path::Path, cid: &Cid) {
+        let block = paths::block_path(path.join("blockstore"), cid);
+        std::fs::write(block, b"not the data the cid hashes").unwrap();
+    }
+
+    #[tokio::test]
+    async fn consistent_repo() {
+        let dir = tempfile::tempdir().unwrap();
+        let (repo, ..) = pinned_repo(dir.path()).await;
+
+        let handle = repo.verify(RepairMode::Report);
+        let report = handle.await.unwrap();
+        assert!(report.is_consistent(), "{report:?}");
+    }
+
+    #[tokio::test]
+    async fn reports_corrupt_and_missing_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let (repo, root, leaves, direct) = pinned_repo(dir.path()).await;
+
+        corrupt(dir.path(), &leaves[0]);
+        corrupt(dir.path(), &direct);
+
+        let report = repo.verify(RepairMode::Report).await.unwrap();
+        assert_eq!(
+            BTreeSet::from_iter(report.corrupt.iter().copied()),
+            BTreeSet::from([leaves[0], direct])
+        );
+        assert_eq!(report.missing, vec![(root, leaves[0])]);
+        assert_eq!(report.orphaned_pins, vec![direct]);
+
+        // nothing was repaired
+        assert!(repo.get_block_now(&leaves[0]).await.is_err());
+        assert!(repo.is_pinned(&direct).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn fix_removes_corrupt_blocks_and_wants_them_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let (repo, root, leaves, direct) = pinned_repo(dir.path()).await;
+        let mut events = repo.initialize_channel();
+
+        corrupt(dir.path(), &leaves[1]);
+        corrupt(dir.path(), &direct);
+
+        let report = repo.verify(RepairMode::Fix).await.unwrap();
+        assert_eq!(report.missing, vec![(root, leaves[1])]);
+        assert_eq!(report.orphaned_pins, vec![direct]);
+
+        assert!(repo.get_block_now(&leaves[1]).await.unwrap().is_none());
+        assert!(repo.get_block_now(&direct).await.unwrap().is_none());
+        // the pins are kept until their blocks are fetched again
+        assert!(repo.is_pinned(&direct).await.unwrap());
+
+        let wanted = loop {
+            match events.next().await.expect("the blocks to be wanted") {
+                RepoEvent::WantBlock(_, cids, ..) => break cids,
+                _ => continue,
+            }
+        };
+        assert_eq!(
+            BTreeSet::from_iter(wanted),
+            BTreeSet::from([leaves[1], direct])
+        );
+    }
+
+    #[tokio::test]
+    async fn orphaned_pins_are_only_dropped_when_allowed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (repo, root, leaves, direct) = pinned_repo(dir.path()).await;
+
+        corrupt(dir.path(), &root);
+        corrupt(dir.path(), &direct);
+
+        let report = repo
+            .verify(RepairMode::FixDroppingOrphanedPins)
+            .await
+            .unwrap();
+        assert!(report.missing.is_empty());
+        assert_eq!(
+            BTreeSet::from_iter(report.orphaned_pins),
+            BTreeSet::from([root, direct])
+        );
+
+        assert!(!repo.is_pinned(&root).await.unwrap());
+        assert!(!repo.is_pinned(&direct).await.unwrap());
+        // the intact blocks of the dag are left to gc
+        assert!(repo.get_block_now(&leaves[0]).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn verify_is_cancellable() {
+        let dir = tempfile::tempdir().unwrap();
+        let (repo, ..) = pinned_repo(dir.path()).await;
+        for i in 0..256u32 {
+            repo.put_block(block(&i.to_be_bytes())).await.unwrap();
+        }
+
+        // hold off the check so that it is cancelled before scanning
+        let gc = repo.inner.gclock.write().await;
+        let handle = repo.verify(RepairMode::Report);
+        let mut progress = handle.progress();
+        assert_eq!(progress.next().await, Some(VerifyProgress::default()));
+        handle.cancel();
+        drop(gc);
+
+        handle.await.unwrap_err();
+        // the stream ends with the check
+        while progress.next().await.is_some() {}
+    }
+
+    #[tokio::test]
+    async fn progress_counts_blocks_and_pins() {
+        let dir = tempfile::tempdir().unwrap();
+        let (repo, ..) = pinned_repo(dir.path()).await;
+
+        let handle = repo.verify(RepairMode::Report);
+        let progress = handle.progress();
+        handle.await.unwrap();
+
+        let last = progress.collect::<Vec<_>>().await.pop().unwrap();
+        assert_eq!(
+            last,
+            VerifyProgress {
+                scanned_blocks: 4,
+                checked_pins: 2,
+            }
+        );
+    }
+}
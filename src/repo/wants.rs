@@ -0,0 +1,263 @@
+//! Persistence of the blocks wanted from the network, see [`WantPersistence`].
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::{SinkExt, StreamExt};
+use libipld::Cid;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+use super::{Repo, RepoEvent};
+use crate::error::Error;
+
+/// Prefix of the keys of the wants in the datastore, followed by the cid and the session.
+const WANT_PREFIX: &str = "/local/wants/";
+
+/// Persistence of the blocks wanted over bitswap in the datastore, so that the fetches
+/// interrupted by a restart resume without the application asking for the blocks again.
+///
+/// A want is written when a block is first wanted in a session, and removed once the block is
+/// stored or no longer wanted in the session. The wants are no longer changed once the node
+/// shuts down, the requests cancelled by the shutdown keeping their wants. The wants left when
+/// the node stops are wanted again once it started, with their session, priority and provider
+/// hints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WantPersistence {
+    /// Want the persisted blocks again on start, instead of dropping them
+    pub resume_wants: bool,
+    /// Age past which a persisted want is dropped instead of resumed
+    pub max_age: Duration,
+}
+
+impl Default for WantPersistence {
+    fn default() -> Self {
+        Self {
+            resume_wants: true,
+            max_age: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// A block wanted in a session, with its provider hints and priority.
+pub(crate) type Want = (Option<u64>, Cid, Vec<PeerId>, i32);
+
+/// Change of the wants, written in order by the task spawned in [`Repo::persist_wants`].
+#[derive(Debug)]
+pub(crate) enum WantWrite {
+    Want(Want),
+    Unwant(Option<u64>, Cid),
+    /// The block was stored or failed to be fetched, no longer wanted in any session
+    Done(Cid),
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredWant {
+    priority: i32,
+    peers: Vec<String>,
+    /// Milliseconds since the unix epoch
+    created: u64,
+}
+
+fn want_key(session: Option<u64>, cid: &Cid) -> Vec<u8> {
+    match session {
+        Some(session) => format!("{WANT_PREFIX}{cid}/{session}"),
+        None => format!("{WANT_PREFIX}{cid}/none"),
+    }
+    .into_bytes()
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Decodes the want stored under the path, without the prefix, unless older than `max_age`.
+fn decode_want(path: &str, value: &[u8], max_age: Duration) -> Option<Want> {
+    let (cid, session) = path.split_once('/')?;
+    let cid = Cid::try_from(cid).ok()?;
+    let session = match session {
+        "none" => None,
+        session => Some(session.parse().ok()?),
+    };
+
+    let stored: StoredWant = serde_json::from_slice(value).ok()?;
+    if now_millis().saturating_sub(stored.created) > max_age.as_millis() as u64 {
+        return None;
+    }
+    let peers = stored
+        .peers
+        .iter()
+        .filter_map(|peer| peer.parse().ok())
+        .collect();
+    Some((session, cid, peers, stored.priority))
+}
+
+impl Repo {
+    /// Persists the wants from now on, returning the wants left by the previous run which are
+    /// to be resumed. The stale ones, the ones of the blocks stored since, and every one unless
+    /// they are resumed are removed.
+    pub(crate) async fn persist_wants(&self, config: WantPersistence) -> Result<Vec<Want>, Error> {
+        let mut resumed = Vec::new();
+        let mut dropped = Vec::new();
+
+        let mut entries = self.data_store().iter().await;
+        while let Some((key, value)) = entries.next().await {
+            let Some(path) = std::str::from_utf8(&key)
+                .ok()
+                .and_then(|path| path.strip_prefix(WANT_PREFIX))
+            else {
+                continue;
+            };
+
+            match decode_want(path, &value, config.max_age) {
+                Some(want) if config.resume_wants && !self.contains(&want.1).await? => {
+                    resumed.push(want)
+                }
+                _ => dropped.push(key),
+            }
+        }
+        drop(entries);
+
+        for key in dropped {
+            self.data_store().remove(&key).await?;
+        }
+
+        let mut persisted: HashMap<Cid, HashSet<Option<u64>>> = HashMap::new();
+        for (session, cid, _, _) in &resumed {
+            persisted.entry(*cid).or_default().insert(*session);
+        }
+
+        let (tx, mut rx) = unbounded();
+        // the task does not keep the repo alive, its sender being dropped with the repo
+        let inner = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            while let Some(write) = rx.next().await {
+                let Some(repo) = Weak::upgrade(&inner).map(|inner| Repo { inner }) else {
+                    break;
+                };
+
+                let keys = match write {
+                    WantWrite::Want((session, cid, peers, priority)) => {
+                        persisted.entry(cid).or_default().insert(session);
+                        let stored = StoredWant {
+                            priority,
+                            peers: peers.iter().map(ToString::to_string).collect(),
+                            created: now_millis(),
+                        };
+                        let value = serde_json::to_vec(&stored).expect("serializable");
+                        if let Err(e) = repo
+                            .data_store()
+                            .put(&want_key(session, &cid), &value)
+                            .await
+                        {
+                            warn!("failed to persist the want of {cid}: {e}");
+                        }
+                        continue;
+                    }
+                    WantWrite::Unwant(session, cid) => match persisted.get_mut(&cid) {
+                        Some(sessions) if sessions.remove(&session) => {
+                            if sessions.is_empty() {
+                                persisted.remove(&cid);
+                            }
+                            vec![want_key(session, &cid)]
+                        }
+                        _ => continue,
+                    },
+                    WantWrite::Done(cid) => match persisted.remove(&cid) {
+                        Some(sessions) => sessions
+                            .into_iter()
+                            .map(|session| want_key(session, &cid))
+                            .collect(),
+                        None => continue,
+                    },
+                };
+
+                for key in keys {
+                    if let Err(e) = repo.data_store().remove(&key).await {
+                        warn!("failed to remove a persisted want: {e}");
+                    }
+                }
+            }
+        });
+
+        let _ = self.inner.wants.set(tx);
+        Ok(resumed)
+    }
+
+    /// Wants the blocks again, after [`Repo::persist_wants`] and once the task is running. They
+    /// stay persisted when the node does not fetch blocks from the network.
+    pub(crate) async fn resume_wants(&self, wants: Vec<Want>) {
+        if !self.is_online() || self.is_local_only() {
+            return;
+        }
+        let Some(mut events) = self.repo_channel() else {
+            return;
+        };
+        for (session, cid, peers, priority) in wants {
+            debug!("resuming the want of {cid}");
+            let _ = events
+                .send(RepoEvent::WantBlock(session, vec![cid], peers, priority))
+                .await;
+        }
+    }
+
+    /// Stops writing the changes of the wants, the ones already recorded still being written.
+    /// Called once the repo shuts down, so that the requests cancelled by the shutdown stay
+    /// persisted and are resumed on the next start.
+    pub(crate) fn stop_persisting_wants(&self) {
+        if let Some(tx) = self.inner.wants.get() {
+            tx.close_channel();
+        }
+    }
+
+    /// Records the change of the wants, if persisted.
+    pub(crate) fn persist_want(&self, write: impl FnOnce() -> WantWrite) {
+        if let Some(tx) = self.inner.wants.get() {
+            let _ = tx.unbounded_send(write());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libipld::multihash::{Code, MultihashDigest};
+    use libipld::IpldCodec;
+
+    #[test]
+    fn stale_and_invalid_wants_are_not_decoded() {
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(b"wanted"));
+        let peer = PeerId::random();
+        let stored = |created| {
+            serde_json::to_vec(&StoredWant {
+                priority: 3,
+                peers: vec![peer.to_string()],
+                created,
+            })
+            .unwrap()
+        };
+        let max_age = Duration::from_secs(60);
+
+        let key = String::from_utf8(want_key(Some(7), &cid)).unwrap();
+        let path = key.strip_prefix(WANT_PREFIX).unwrap();
+        assert_eq!(
+            decode_want(path, &stored(now_millis()), max_age),
+            Some((Some(7), cid, vec![peer], 3))
+        );
+        let stale = now_millis() - 2 * 60 * 1000;
+        assert_eq!(decode_want(path, &stored(stale), max_age), None);
+
+        let key = String::from_utf8(want_key(None, &cid)).unwrap();
+        let path = key.strip_prefix(WANT_PREFIX).unwrap();
+        assert_eq!(
+            decode_want(path, &stored(now_millis()), max_age),
+            Some((None, cid, vec![peer], 3))
+        );
+        assert_eq!(decode_want("not-a-cid/none", &stored(0), max_age), None);
+    }
+}
@@ -5,7 +5,8 @@ use futures::{
         mpsc::{unbounded, Receiver, UnboundedSender},
         oneshot,
     },
-    stream::Fuse,
+    future::BoxFuture,
+    stream::{BoxStream, Fuse, FuturesUnordered, SelectAll},
     FutureExt, StreamExt,
 };
 
@@ -22,25 +23,50 @@ use beetle_bitswap_next::BitswapEvent;
 use libp2p_bitswap_next::BitswapEvent;
 
 #[cfg(feature = "libp2p_bitswap")]
-use libipld::Cid;
+use libipld::multihash::Multihash;
+use libipld::{Cid, IpldCodec};
 
 #[cfg(feature = "beetle_bitswap")]
 use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
 
 use wasm_timer::Interval;
 
 use std::{
-    collections::{hash_map::Entry, HashMap, HashSet},
-    time::Duration,
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    fmt::Debug,
+    net::IpAddr,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
 };
 
+use std::panic::AssertUnwindSafe;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use tracing::{field::Empty, Span};
 
-use crate::{config::BOOTSTRAP_NODES, IpfsEvent, TSwarmEventFn};
+use crate::{
+    config::BOOTSTRAP_NODES,
+    error::{ProtocolDisabled, ProtocolKind},
+    Components, ConnectionEvent, DhtEvent, DisconnectCause, Disconnection, IpfsEvent,
+    ReadyCriteria, ReadyState, RepoProvider, TCustomBehaviourFn, TIntervalFn, TSwarmEventFn,
+    TSwarmObserverFn, DHT_EVENTS_CAPACITY,
+};
 
 use crate::{
-    p2p::TSwarm,
+    p2p::{
+        addr,
+        addressbook::{self, PeerRecord},
+        announce::ContentAnnouncer,
+        bandwidth::BandwidthCounters,
+        dht_limit::DhtWriteLimiter,
+        gossipsub::SubscriptionStream,
+        observed::ObservedAddrs,
+        pinger, AutonatStatus, Ban, BanTarget, BootstrapPolicy, BootstrapReport, ConnectionLimits,
+        ContentAnnouncerConfig, DhtStats, DhtStoreContents, DialError, ExternalAddressInfo,
+        ExternalAddressSource, GateHandle, IdentifyConfiguration, InterfaceFilter, ListenerInfo,
+        PeerInfo, RelayReservation, RelayServerStats, RelayStatus, RendezvousConfig, TSwarm,
+    },
     repo::{Repo, RepoEvent},
 };
 
@@ -55,34 +81,75 @@ pub use libp2p::{
 use libp2p::{
     autonat,
     identify::{Event as IdentifyEvent, Info as IdentifyInfo},
+    identity::PublicKey,
     kad::{
-        AddProviderError, AddProviderOk, BootstrapError, BootstrapOk, Event as KademliaEvent,
-        GetClosestPeersError, GetClosestPeersOk, GetProvidersError, GetProvidersOk, GetRecordError,
-        GetRecordOk, PutRecordError, PutRecordOk, QueryId, QueryResult::*, Record,
+        store::RecordStore, AddProviderError, AddProviderOk, BootstrapError, BootstrapOk,
+        Event as KademliaEvent, GetClosestPeersError, GetClosestPeersOk, GetProvidersError,
+        GetProvidersOk, GetRecordError, GetRecordOk, InboundRequest, PutRecordError, PutRecordOk,
+        QueryId, QueryResult::*, Record,
     },
     mdns::Event as MdnsEvent,
+    multiaddr::Protocol,
     rendezvous::{Cookie, Namespace},
-    swarm::{ConnectionId, SwarmEvent},
+    swarm::{
+        dial_opts::DialOpts, ConnectionError, ConnectionId, DialError as SwarmDialError, SwarmEvent,
+    },
+    StreamProtocol,
 };
 
 /// Background task of `Ipfs` created when calling `UninitializedIpfs::start`.
 // The receivers are Fuse'd so that we don't have to manage state on them being exhausted.
 #[allow(clippy::type_complexity)]
 #[allow(dead_code)]
-pub(crate) struct IpfsTask<C: NetworkBehaviour<ToSwarm = void::Void>> {
+pub(crate) struct IpfsTask<C: NetworkBehaviour>
+where
+    <C as NetworkBehaviour>::ToSwarm: Debug,
+{
     pub(crate) swarm: TSwarm<C>,
     pub(crate) repo_events: Fuse<Receiver<RepoEvent>>,
-    pub(crate) from_facade: Fuse<Receiver<IpfsEvent>>,
+    pub(crate) from_facade: Fuse<ReceiverStream<IpfsEvent>>,
     pub(crate) listening_addresses: HashMap<ListenerId, Vec<Multiaddr>>,
-    pub(crate) provider_stream: HashMap<QueryId, UnboundedSender<PeerId>>,
+    pub(crate) provider_stream: HashMap<QueryId, Vec<UnboundedSender<PeerId>>>,
     pub(crate) bitswap_provider_stream:
-        HashMap<QueryId, futures::channel::mpsc::Sender<Result<HashSet<PeerId>, String>>>,
+        HashMap<QueryId, Vec<futures::channel::mpsc::Sender<Result<HashSet<PeerId>, String>>>>,
+    /// Provider queries in progress by key, shared by all the lookups of the key
+    pub(crate) provider_lookups: HashMap<Key, ProviderLookup>,
     pub(crate) record_stream: HashMap<QueryId, UnboundedSender<Record>>,
     pub(crate) repo: Repo,
     pub(crate) kad_subscriptions: HashMap<QueryId, Channel<KadResult>>,
-    pub(crate) dht_peer_lookup: HashMap<PeerId, Vec<Channel<libp2p::identify::Info>>>,
+    /// Provider queries of the new blocks, answered with the outcome of the query
+    pub(crate) provided_blocks: HashMap<QueryId, Channel<()>>,
+    pub(crate) dht_peer_lookup: HashMap<PeerId, Vec<Channel<PeerInfo>>>,
+    /// Ping streams of each peer, with the number of round-trip times they still expect if bounded
+    pub(crate) ping_subscribers: HashMap<
+        PeerId,
+        Vec<(
+            UnboundedSender<Result<Duration, anyhow::Error>>,
+            Option<usize>,
+        )>,
+    >,
     pub(crate) bootstraps: HashSet<Multiaddr>,
     pub(crate) swarm_event: Option<TSwarmEventFn<C>>,
+    pub(crate) swarm_observers: Vec<TSwarmObserverFn<C>>,
+    /// Receives the events of the custom behaviour, see `Ipfs::custom_events`
+    pub(crate) custom_events: Option<UnboundedSender<C::ToSwarm>>,
+    pub(crate) mdns_auto_dial: bool,
+    /// Interface addresses the unspecified listening addresses are expanded into
+    pub(crate) interface_filter: InterfaceFilter,
+    pub(crate) interfaces: Vec<IpAddr>,
+    pub(crate) fail_if_connected: bool,
+    /// Most recent disconnections, up to `disconnection_history`
+    pub(crate) disconnections: VecDeque<Disconnection>,
+    pub(crate) disconnection_history: usize,
+    /// Why we are closing the connections to the peers
+    pub(crate) closing: HashMap<PeerId, DisconnectCause>,
+    /// Connections whose last ping timed out
+    pub(crate) ping_timeouts: HashSet<ConnectionId>,
+    /// Identify configuration and public key reported as our identity
+    pub(crate) identify_conf: IdentifyConfiguration,
+    pub(crate) public_key: Option<PublicKey>,
+    /// Peers discovered by mdns, until their addresses expire
+    pub(crate) mdns_peers: HashMap<PeerId, Vec<Multiaddr>>,
     #[cfg(feature = "beetle_bitswap")]
     pub(crate) bitswap_sessions: HashMap<u64, Vec<(oneshot::Sender<()>, JoinHandle<()>)>>,
     #[cfg(feature = "libp2p_bitswap")]
@@ -91,24 +158,172 @@ pub(crate) struct IpfsTask<C: NetworkBehaviour<ToSwarm = void::Void>> {
     pub(crate) bitswap_sessions: HashMap<i64, libipld::Cid>,
     pub(crate) pubsub_event_stream: Vec<UnboundedSender<InnerPubsubEvent>>,
     pub(crate) timer: TaskTimer,
+    /// Creates the periodic timers of `IpfsTask::run`, tokio intervals if `None`
+    pub(crate) interval_factory: Option<TIntervalFn>,
     pub(crate) local_external_addr: bool,
+    /// Where the external addresses of the swarm were learned from
+    pub(crate) external_addresses: HashMap<Multiaddr, ExternalAddressSource>,
+    /// Addresses the identified peers observe us at, promoted to external addresses
+    pub(crate) observed_addrs: ObservedAddrs,
+    /// Which of the new blocks and pins are provided.
+    pub(crate) provider: RepoProvider,
+    /// Started offline, refusing to dial.
+    pub(crate) offline: bool,
+    pub(crate) connection_limits: ConnectionLimits,
+    pub(crate) connection_events: tokio::sync::broadcast::Sender<ConnectionEvent>,
+    pub(crate) nat_events: tokio::sync::broadcast::Sender<AutonatStatus>,
+    /// File the addressbook is saved to, if persisted.
+    pub(crate) addressbook_path: Option<PathBuf>,
+    pub(crate) gate: GateHandle,
+    pub(crate) bandwidth: BandwidthCounters,
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics: crate::metrics::Metrics,
+    /// Banned peers and addresses, with the time their ban expires
+    pub(crate) bans: HashMap<BanTarget, Option<Instant>>,
     pub(crate) relay_listener: HashMap<PeerId, Vec<Channel<()>>>,
+    /// Request a relay reservation when AutoNAT reports the node as private
+    pub(crate) auto_relay: bool,
+    pub(crate) static_relays: Vec<PeerId>,
+    pub(crate) nat_private: bool,
+    /// Relay picked automatically, until its reservation fails or is closed
+    pub(crate) relay_candidate: Option<PeerId>,
+    pub(crate) failed_relays: HashSet<PeerId>,
+    pub(crate) relay_reservations: HashMap<PeerId, Vec<Multiaddr>>,
+    /// Peers with a reservation on our relay server
+    pub(crate) served_reservations: HashSet<PeerId>,
+    /// Circuits relayed between the peers
+    pub(crate) relayed_circuits: HashMap<(PeerId, PeerId), usize>,
+    pub(crate) denied_reservations: u64,
+    /// Limit on the inbound DHT writes, which are then stored by the task
+    pub(crate) dht_write_limiter: Option<DhtWriteLimiter>,
+    pub(crate) dht_stats: DhtStats,
+    /// Sender of the routing table changes, while subscribed to
+    pub(crate) dht_events: Option<tokio::sync::broadcast::Sender<DhtEvent>>,
+    pub(crate) denied_circuits: u64,
     pub(crate) rzv_register_pending: HashMap<(PeerId, Namespace), Vec<Channel<()>>>,
     pub(crate) rzv_discover_pending:
         HashMap<(PeerId, Namespace), Vec<Channel<HashMap<PeerId, Vec<Multiaddr>>>>>,
     pub(crate) rzv_cookie: HashMap<PeerId, Option<Cookie>>,
-
-    pub(crate) pending_connection: HashMap<ConnectionId, Channel<()>>,
+    /// Rendezvous servers the namespaces are registered on and discovered from
+    pub(crate) rzv_servers: Vec<PeerId>,
+    pub(crate) rzv_namespaces: Vec<Namespace>,
+    pub(crate) rzv_register_ttl: Option<u64>,
+    pub(crate) rzv_discovery_interval: Duration,
+    pub(crate) rzv_dial_discovered: bool,
+    /// Registrations renewed before they expire, with their requested ttl and the time of the
+    /// renewal once registered
+    pub(crate) rzv_registrations: HashMap<(PeerId, Namespace), (Option<u64>, Option<Instant>)>,
+    pub(crate) rzv_timers: FuturesUnordered<BoxFuture<'static, RendezvousTimer>>,
+    pub(crate) bootstrap_policy: BootstrapPolicy,
+    /// Bootstrap started by the policy or the facade, until its last step
+    pub(crate) bootstrap_query: Option<QueryId>,
+    /// Bootstraps awaiting their report, with when they started and the bootstrap nodes
+    /// connected since
+    pub(crate) bootstrap_reports:
+        HashMap<QueryId, (Instant, HashSet<PeerId>, Channel<BootstrapReport>)>,
+    /// Waiting for the first listening address to bootstrap
+    pub(crate) bootstrap_on_listen: bool,
+    /// Intervals of the policy, which only bootstrap if the policy was not replaced since
+    pub(crate) bootstrap_timers: FuturesUnordered<BoxFuture<'static, u64>>,
+    pub(crate) bootstrap_generation: u64,
+    /// Waiters of [`Ipfs::wait_ready`](crate::Ipfs::wait_ready), until the criteria are met
+    pub(crate) ready_waiters: Vec<(ReadyCriteria, Channel<ReadyState>)>,
+    /// Content announced by the peers, when the announcer is enabled
+    pub(crate) content_announcer: Option<ContentAnnouncer>,
+    /// Subscription to the announcement topic, empty unless the announcer is enabled
+    pub(crate) announcements: SelectAll<SubscriptionStream>,
+
+    /// Outbound connection attempts, with the addresses known for them and when they started
+    pub(crate) dials: HashMap<ConnectionId, (Option<PeerId>, Vec<Multiaddr>, Instant)>,
+    pub(crate) pending_connection: HashMap<ConnectionId, oneshot::Sender<Result<(), DialError>>>,
+    pub(crate) pending_dial:
+        HashMap<ConnectionId, (Option<PeerId>, oneshot::Sender<Result<PeerId, DialError>>)>,
     pub(crate) pending_disconnection: HashMap<PeerId, Vec<Channel<()>>>,
-    pub(crate) pending_add_listener: HashMap<ListenerId, Channel<Multiaddr>>,
+    pub(crate) pending_add_listener: HashMap<ListenerId, Channel<Vec<Multiaddr>>>,
     pub(crate) pending_remove_listener: HashMap<ListenerId, Channel<()>>,
+    /// Addresses the listeners were requested with
+    pub(crate) listener_requests: HashMap<ListenerId, Multiaddr>,
+    /// Listeners on an unspecified ip waiting for the addresses of the other interfaces
+    pub(crate) listener_settle: FuturesUnordered<BoxFuture<'static, ListenerId>>,
+    /// Listen again on the requested addresses whose listener expired or failed
+    pub(crate) relisten: bool,
+    /// Requested addresses to listen on again, with the failed attempts so far
+    pub(crate) relisten_attempts: HashMap<Multiaddr, u32>,
+    /// Listeners listening on a requested address again, until they report an address
+    pub(crate) relisteners: HashMap<ListenerId, Multiaddr>,
+    pub(crate) relisten_timers: FuturesUnordered<BoxFuture<'static, Multiaddr>>,
+    /// Spans of the kad queries in progress, closed with the last step of the query
+    pub(crate) kad_query_spans: HashMap<QueryId, (Span, Instant)>,
+    /// Spans of the blocks wanted from bitswap, closed once retrieved or cancelled
+    #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+    pub(crate) bitswap_fetch_spans: HashMap<Cid, (Span, Instant)>,
+    /// Providers found for the blocks wanted from bitswap
+    #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+    pub(crate) provider_fetches: HashMap<Cid, ProviderFetch>,
+    #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+    pub(crate) provider_queries: HashMap<QueryId, Vec<Cid>>,
+    #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+    pub(crate) provider_ranking: crate::p2p::ProviderRanking,
+    /// Save the bitswap ledgers along with the addressbook
+    #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+    pub(crate) persist_bitswap_ledgers: bool,
+    /// Wanted blocks whose dialed providers are given up on as slow
+    pub(crate) provider_stalls: FuturesUnordered<BoxFuture<'static, Cid>>,
+    /// Provider streams ended once their timeout elapses
+    pub(crate) provider_deadlines:
+        FuturesUnordered<BoxFuture<'static, (QueryId, UnboundedSender<PeerId>)>>,
+}
+
+/// Provider query in progress, with the providers it found so far for the lookups attached late
+pub(crate) struct ProviderLookup {
+    id: QueryId,
+    found: HashSet<PeerId>,
 }
 
-impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
+/// Providers found for a wanted block
+#[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+#[derive(Default)]
+pub(crate) struct ProviderFetch {
+    /// The providers not dialed yet
+    candidates: Vec<PeerId>,
+    found: HashSet<PeerId>,
+}
+
+/// Providers dialed at once for a wanted block, the next ones being dialed when they fail or are
+/// slow.
+#[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+const PROVIDER_BATCH: usize = 3;
+
+/// Time given to the dialed providers to send a wanted block before dialing the next ones.
+#[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+const PROVIDER_STALL: Duration = Duration::from_secs(5);
+
+/// Time given to a listener on an unspecified ip to report the addresses of every interface
+/// after the first one.
+const LISTENER_SETTLE: Duration = Duration::from_millis(250);
+
+/// Delay before listening again on a requested address, doubled after every failed attempt.
+const RELISTEN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Maximum delay between the attempts to listen again on a requested address.
+const MAX_RELISTEN_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Score lost by a peer for every DHT write dropped as over the limit.
+const DHT_FLOOD_PENALTY: i64 = 1;
+
+pub(crate) enum RendezvousTimer {
+    Renew(PeerId, Namespace),
+    Discover,
+}
+
+impl<C: NetworkBehaviour> IpfsTask<C>
+where
+    <C as NetworkBehaviour>::ToSwarm: Debug,
+{
     pub fn new(
         swarm: TSwarm<C>,
         repo_events: Fuse<Receiver<RepoEvent>>,
-        from_facade: Fuse<Receiver<IpfsEvent>>,
+        from_facade: Fuse<ReceiverStream<IpfsEvent>>,
         repo: &Repo,
     ) -> Self {
         IpfsTask {
@@ -117,25 +332,105 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             swarm,
             provider_stream: HashMap::new(),
             bitswap_provider_stream: Default::default(),
+            provider_lookups: Default::default(),
             record_stream: HashMap::new(),
             dht_peer_lookup: Default::default(),
+            ping_subscribers: Default::default(),
             bitswap_sessions: Default::default(),
             pubsub_event_stream: Default::default(),
             kad_subscriptions: Default::default(),
+            provided_blocks: Default::default(),
             repo: repo.clone(),
             bootstraps: Default::default(),
             swarm_event: Default::default(),
+            swarm_observers: Default::default(),
+            custom_events: None,
+            mdns_auto_dial: false,
+            interface_filter: Default::default(),
+            interfaces: vec![],
+            fail_if_connected: false,
+            disconnections: Default::default(),
+            disconnection_history: 0,
+            closing: Default::default(),
+            ping_timeouts: Default::default(),
+            identify_conf: Default::default(),
+            public_key: None,
+            mdns_peers: Default::default(),
             timer: Default::default(),
+            interval_factory: None,
             relay_listener: Default::default(),
+            auto_relay: false,
+            static_relays: Default::default(),
+            nat_private: false,
+            relay_candidate: None,
+            failed_relays: Default::default(),
+            relay_reservations: Default::default(),
+            served_reservations: Default::default(),
+            relayed_circuits: Default::default(),
+            denied_reservations: 0,
+            dht_write_limiter: None,
+            dht_stats: Default::default(),
+            dht_events: None,
+            denied_circuits: 0,
             local_external_addr: false,
+            external_addresses: Default::default(),
+            observed_addrs: Default::default(),
+            provider: RepoProvider::None,
+            offline: false,
+            connection_limits: Default::default(),
+            connection_events: tokio::sync::broadcast::channel(1).0,
+            nat_events: tokio::sync::broadcast::channel(1).0,
+            addressbook_path: None,
+            gate: Default::default(),
+            bandwidth: Default::default(),
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
+            bans: Default::default(),
             rzv_register_pending: Default::default(),
             rzv_discover_pending: Default::default(),
             rzv_cookie: Default::default(),
+            rzv_servers: Default::default(),
+            rzv_namespaces: Default::default(),
+            rzv_register_ttl: None,
+            rzv_discovery_interval: Duration::ZERO,
+            rzv_dial_discovered: false,
+            rzv_registrations: Default::default(),
+            rzv_timers: Default::default(),
+            bootstrap_policy: Default::default(),
+            bootstrap_query: None,
+            bootstrap_reports: Default::default(),
+            bootstrap_on_listen: false,
+            bootstrap_timers: Default::default(),
+            bootstrap_generation: 0,
+            ready_waiters: Default::default(),
+            content_announcer: None,
+            announcements: Default::default(),
             listening_addresses: HashMap::new(),
             pending_disconnection: Default::default(),
             pending_connection: Default::default(),
+            pending_dial: Default::default(),
+            dials: Default::default(),
             pending_add_listener: Default::default(),
             pending_remove_listener: Default::default(),
+            listener_requests: Default::default(),
+            listener_settle: Default::default(),
+            relisten: false,
+            relisten_attempts: Default::default(),
+            relisteners: Default::default(),
+            relisten_timers: Default::default(),
+            kad_query_spans: Default::default(),
+            #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+            bitswap_fetch_spans: Default::default(),
+            #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+            provider_fetches: Default::default(),
+            #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+            provider_queries: Default::default(),
+            #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+            provider_ranking: Default::default(),
+            #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+            persist_bitswap_ledgers: false,
+            provider_stalls: Default::default(),
+            provider_deadlines: Default::default(),
         }
     }
 }
@@ -160,13 +455,19 @@ impl Default for TaskTimer {
     }
 }
 
-impl<C: NetworkBehaviour<ToSwarm = void::Void>> futures::Future for IpfsTask<C> {
+impl<C: NetworkBehaviour> futures::Future for IpfsTask<C>
+where
+    <C as NetworkBehaviour>::ToSwarm: Debug,
+{
     type Output = ();
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         loop {
             match self.swarm.poll_next_unpin(cx) {
-                Poll::Ready(Some(event)) => self.handle_swarm_event(event),
+                Poll::Ready(Some(event)) => {
+                    self.handle_swarm_event(event);
+                    self.resolve_ready_waiters();
+                }
                 Poll::Ready(None) => return Poll::Ready(()),
                 Poll::Pending => break,
             }
@@ -185,9 +486,43 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> futures::Future for IpfsTask<C>
                 Poll::Pending => break,
             }
         }
+        while let Poll::Ready(Some(listener_id)) = self.listener_settle.poll_next_unpin(cx) {
+            self.settle_listener(listener_id);
+        }
+        while let Poll::Ready(Some(addr)) = self.relisten_timers.poll_next_unpin(cx) {
+            self.relisten_on(addr);
+        }
+        while let Poll::Ready(Some(timer)) = self.rzv_timers.poll_next_unpin(cx) {
+            self.handle_rendezvous_timer(timer);
+        }
+        while let Poll::Ready(Some(generation)) = self.bootstrap_timers.poll_next_unpin(cx) {
+            self.handle_bootstrap_timer(generation);
+        }
+        while let Poll::Ready(Some(cid)) = self.provider_stalls.poll_next_unpin(cx) {
+            self.dial_providers(cid);
+        }
+        while let Poll::Ready(Some((id, tx))) = self.provider_deadlines.poll_next_unpin(cx) {
+            self.expire_provider_stream(id, tx);
+        }
+        while let Poll::Ready(Some(message)) = self.announcements.poll_next_unpin(cx) {
+            self.handle_announcement(message);
+        }
 
         if self.timer.event_cleanup.poll_next_unpin(cx).is_ready() {
             self.pubsub_event_stream.retain(|ch| !ch.is_closed());
+            self.prune_connections();
+            self.save_addressbook();
+            #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+            self.save_bitswap_ledgers();
+            self.sweep_bans();
+            self.sweep_announcements();
+            self.sweep_kad_store();
+            self.bootstrap_below_min_peers();
+            self.bandwidth.sample();
+            self.select_auto_relay();
+            self.refresh_interfaces();
+            #[cfg(feature = "metrics")]
+            self.update_metrics();
         }
 
         #[cfg(feature = "beetle_bitswap")]
@@ -219,30 +554,74 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> futures::Future for IpfsTask<C>
     }
 }
 
-impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
+impl<C: NetworkBehaviour> IpfsTask<C>
+where
+    <C as NetworkBehaviour>::ToSwarm: Debug,
+{
     pub(crate) async fn run(&mut self) {
-        let mut session_cleanup = tokio::time::interval(Duration::from_secs(5 * 60));
-        let mut event_cleanup = tokio::time::interval(Duration::from_secs(60));
+        let mut session_cleanup = self.interval(Duration::from_secs(5 * 60));
+        let mut event_cleanup = self.interval(Duration::from_secs(60));
 
         loop {
             tokio::select! {
                 biased;
                 Some(swarm) = self.swarm.next() => {
                     self.handle_swarm_event(swarm);
+                    self.resolve_ready_waiters();
                 },
                 Some(repo) = self.repo_events.next() => {
                     self.handle_repo_event(repo);
                 },
-                Some(event) = self.from_facade.next() => {
-                    if matches!(event, IpfsEvent::Exit) {
+                Some(listener_id) = self.listener_settle.next() => {
+                    self.settle_listener(listener_id);
+                },
+                Some(addr) = self.relisten_timers.next() => {
+                    self.relisten_on(addr);
+                },
+                Some(timer) = self.rzv_timers.next() => {
+                    self.handle_rendezvous_timer(timer);
+                },
+                Some(generation) = self.bootstrap_timers.next() => {
+                    self.handle_bootstrap_timer(generation);
+                },
+                Some(cid) = self.provider_stalls.next() => {
+                    self.dial_providers(cid);
+                },
+                Some((id, tx)) = self.provider_deadlines.next() => {
+                    self.expire_provider_stream(id, tx);
+                },
+                Some(message) = self.announcements.next() => {
+                    self.handle_announcement(message);
+                },
+                Some(event) = self.from_facade.next() => match event {
+                    IpfsEvent::Exit => {
+                        self.shutdown(Duration::from_secs(5)).await;
+                        break;
+                    }
+                    IpfsEvent::Shutdown(timeout, ret) => {
+                        self.shutdown(timeout).await;
+                        let _ = ret.send(Ok(()));
                         break;
                     }
-                    self.handle_event(event);
+                    event => self.handle_event(event),
                 },
-                _ = event_cleanup.tick() => {
+                Some(_) = event_cleanup.next() => {
                     self.pubsub_event_stream.retain(|ch| !ch.is_closed());
+                    self.prune_connections();
+                    self.save_addressbook();
+                    #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+                    self.save_bitswap_ledgers();
+                    self.sweep_bans();
+                    self.sweep_announcements();
+                    self.sweep_kad_store();
+                    self.bootstrap_below_min_peers();
+                    self.bandwidth.sample();
+                    self.select_auto_relay();
+                    self.refresh_interfaces();
+                    #[cfg(feature = "metrics")]
+                    self.update_metrics();
                 }
-                _ = session_cleanup.tick() => {
+                Some(_) = session_cleanup.next() => {
                     #[cfg(feature = "beetle_bitswap")]
                     {
                         let mut to_remove = Vec::new();
@@ -270,6 +649,627 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
         }
     }
 
+    fn interval(&self, period: Duration) -> BoxStream<'static, ()> {
+        match self.interval_factory.as_ref() {
+            Some(factory) => factory(period),
+            None => futures::stream::unfold(tokio::time::interval(period), |mut interval| async {
+                interval.tick().await;
+                Some(((), interval))
+            })
+            .boxed(),
+        }
+    }
+
+    pub(crate) fn listen_on(&mut self, addr: Multiaddr, ret: Channel<Vec<Multiaddr>>) {
+        match self.swarm.listen_on(addr.clone()) {
+            Ok(id) => {
+                self.listener_requests.insert(id, addr);
+                self.pending_add_listener.insert(id, ret);
+            }
+            Err(e) => {
+                let _ = ret.send(Err(anyhow::anyhow!(e)));
+            }
+        }
+    }
+
+    /// Opens a `kad.query` span, within the current one, followed until the last step of the
+    /// query.
+    fn trace_kad_query(&mut self, id: QueryId, kind: &'static str, key: &[u8]) {
+        let key = multibase::encode(Base::Base32Lower, key);
+        let span = debug_span!("kad.query", query_id = ?id, kind, key = %key, elapsed_ms = Empty);
+        self.kad_query_spans.insert(id, (span, Instant::now()));
+    }
+
+    /// Starts a provider query for the key, unless one is already in progress. Returns the id of
+    /// the query along with the providers it found so far, or `None` if kad is disabled.
+    fn get_providers(&mut self, key: Key) -> Option<(QueryId, HashSet<PeerId>)> {
+        if let Some(lookup) = self.provider_lookups.get(&key) {
+            return Some((lookup.id, lookup.found.clone()));
+        }
+
+        let kad = self.swarm.behaviour_mut().kademlia.as_mut()?;
+        let id = kad.get_providers(key.clone());
+        self.trace_kad_query(id, "get_providers", key.as_ref());
+        self.provider_lookups.insert(
+            key,
+            ProviderLookup {
+                id,
+                found: HashSet::new(),
+            },
+        );
+        Some((id, HashSet::new()))
+    }
+
+    /// Stores the record or provider announcement handed over by kad, unless its peer is over the
+    /// write limit, in which case the peer is penalized.
+    fn store_inbound_dht_write(&mut self, request: InboundRequest) {
+        let Some(limiter) = self.dht_write_limiter.as_mut() else {
+            return;
+        };
+
+        let (peer_id, write) = match request {
+            InboundRequest::PutRecord {
+                source,
+                record: Some(record),
+                ..
+            } => (source, Either::Left(record)),
+            InboundRequest::AddProvider {
+                record: Some(record),
+            } => (record.provider, Either::Right(record)),
+            _ => return,
+        };
+
+        if !limiter.allow(peer_id, Instant::now()) {
+            debug!(%peer_id, "kad: dropping a write over the limit of the peer");
+            match write {
+                Either::Left(_) => self.dht_stats.rejected_records += 1,
+                Either::Right(_) => self.dht_stats.rejected_providers += 1,
+            }
+            self.swarm
+                .behaviour_mut()
+                .peerbook
+                .penalize_peer(peer_id, DHT_FLOOD_PENALTY);
+            return;
+        }
+
+        let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
+            return;
+        };
+        let stored = match write {
+            Either::Left(record) => kad
+                .store_mut()
+                .put(record)
+                .map(|_| self.dht_stats.stored_records += 1),
+            Either::Right(record) => kad
+                .store_mut()
+                .add_provider(record)
+                .map(|_| self.dht_stats.stored_providers += 1),
+        };
+        if let Err(e) = stored {
+            debug!(%peer_id, "kad: failed to store the write of the peer: {e}");
+        }
+    }
+
+    /// Opens a `bitswap.fetch` span for the wanted blocks not already being fetched.
+    #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+    fn trace_bitswap_fetch(&mut self, cids: &[Cid], providers: usize) {
+        for cid in cids {
+            self.bitswap_fetch_spans.entry(*cid).or_insert_with(|| {
+                let span = debug_span!(
+                    "bitswap.fetch",
+                    cid = %cid,
+                    providers,
+                    outcome = Empty,
+                    elapsed_ms = Empty
+                );
+                (span, Instant::now())
+            });
+        }
+    }
+
+    #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+    fn close_bitswap_fetch(&mut self, cid: &Cid, outcome: &'static str) {
+        if let Some((span, started)) = self.bitswap_fetch_spans.remove(cid) {
+            span.record("outcome", outcome);
+            span.record("elapsed_ms", started.elapsed().as_millis() as u64);
+        }
+        self.provider_fetches.remove(cid);
+
+        // the providers of the block are no longer needed
+        let mut unused = vec![];
+        for (id, cids) in &mut self.provider_queries {
+            cids.retain(|wanted| wanted != cid);
+            if cids.is_empty() {
+                unused.push(*id);
+            }
+        }
+        for id in unused {
+            self.provider_queries.remove(&id);
+            self.finish_unused_query(id);
+        }
+    }
+
+    /// Ends a provider stream whose timeout elapsed, finishing its query unless it is shared with
+    /// other lookups.
+    fn expire_provider_stream(&mut self, id: QueryId, tx: UnboundedSender<PeerId>) {
+        tx.close_channel();
+        if let Entry::Occupied(mut entry) = self.provider_stream.entry(id) {
+            entry.get_mut().retain(|tx| !tx.is_closed());
+            if !entry.get().is_empty() {
+                return;
+            }
+            entry.remove();
+        }
+        self.finish_unused_query(id);
+    }
+
+    /// Finishes a provider query no lookup waits for anymore, its last step closing the lookups.
+    fn finish_unused_query(&mut self, id: QueryId) {
+        if self.provider_stream.contains_key(&id) || self.bitswap_provider_stream.contains_key(&id)
+        {
+            return;
+        }
+        #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+        if self.provider_queries.contains_key(&id) {
+            return;
+        }
+
+        if let Some(mut query) = self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .as_mut()
+            .and_then(|kad| kad.query_mut(&id))
+        {
+            query.finish();
+        }
+    }
+
+    /// Adds the providers found for a wanted block, dialing the first ones found.
+    #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+    fn add_providers(&mut self, cid: Cid, providers: &HashSet<PeerId>) {
+        let Some(fetch) = self.provider_fetches.get_mut(&cid) else {
+            return;
+        };
+
+        let first = fetch.found.is_empty();
+        for provider in providers {
+            if fetch.found.insert(*provider) {
+                fetch.candidates.push(*provider);
+            }
+        }
+
+        if first {
+            self.dial_providers(cid);
+        }
+    }
+
+    /// Dials the next best ranked providers of a wanted block, if it is still wanted.
+    fn dial_providers(&mut self, cid: Cid) {
+        #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+        {
+            let Some(fetch) = self.provider_fetches.get_mut(&cid) else {
+                return;
+            };
+
+            let peerbook = &self.swarm.behaviour().peerbook;
+            let providers =
+                self.provider_ranking
+                    .select(&mut fetch.candidates, PROVIDER_BATCH, |peer_id| {
+                        peerbook.get_peer_latest_rtt(*peer_id)
+                    });
+
+            if providers.is_empty() {
+                return;
+            }
+
+            debug!(%cid, "dialing {} providers", providers.len());
+            if let Some(bitswap) = self.swarm.behaviour_mut().bitswap.as_mut() {
+                bitswap.want_from(&cid, &providers);
+            }
+
+            if !fetch.candidates.is_empty() {
+                self.provider_stalls.push(
+                    futures_timer::Delay::new(PROVIDER_STALL)
+                        .map(move |_| cid)
+                        .boxed(),
+                );
+            }
+        }
+
+        #[cfg(any(feature = "libp2p_bitswap", feature = "beetle_bitswap"))]
+        let _ = cid;
+    }
+
+    fn autonat_status(&self) -> Option<AutonatStatus> {
+        let autonat = self.swarm.behaviour().autonat.as_ref()?;
+        Some(AutonatStatus {
+            status: autonat.nat_status(),
+            confidence: autonat.confidence(),
+            public_address: autonat.public_address().cloned(),
+        })
+    }
+
+    /// Resolves a pending listener with every address reported so far.
+    fn settle_listener(&mut self, listener_id: ListenerId) {
+        if let Some(ret) = self.pending_add_listener.remove(&listener_id) {
+            let addrs = self
+                .listening_addresses
+                .get(&listener_id)
+                .cloned()
+                .unwrap_or_default();
+            let _ = ret.send(Ok(addrs));
+        }
+    }
+
+    /// Schedules listening again on the requested address, after a backoff doubled with every
+    /// failed attempt.
+    fn schedule_relisten(&mut self, addr: Multiaddr) {
+        let attempts = *self.relisten_attempts.entry(addr.clone()).or_default();
+        let delay = RELISTEN_BACKOFF
+            .saturating_mul(1 << attempts.min(6))
+            .min(MAX_RELISTEN_BACKOFF);
+        debug!("listening again on {addr} in {delay:?}");
+        self.relisten_timers
+            .push(futures_timer::Delay::new(delay).map(move |_| addr).boxed());
+    }
+
+    /// Listens again on the requested address, unless it was removed meanwhile.
+    fn relisten_on(&mut self, addr: Multiaddr) {
+        if !self.relisten_attempts.contains_key(&addr) {
+            return;
+        }
+        match self.swarm.listen_on(addr.clone()) {
+            Ok(id) => {
+                self.listener_requests.insert(id, addr.clone());
+                self.relisteners.insert(id, addr);
+            }
+            Err(e) => self.relisten_failed(addr, e.to_string()),
+        }
+    }
+
+    fn relisten_failed(&mut self, address: Multiaddr, error: String) {
+        let attempt = self.relisten_attempts.entry(address.clone()).or_default();
+        *attempt += 1;
+        warn!("failed to listen again on {address} (attempt {attempt}): {error}");
+        let _ = self
+            .connection_events
+            .send(ConnectionEvent::RelistenFailed {
+                address: address.clone(),
+                attempt: *attempt,
+                error,
+            });
+        self.schedule_relisten(address);
+    }
+
+    /// Handles the loss of the listener requested on the address, listening on it again if
+    /// supervised. Returns whether it is listened on again.
+    fn listener_lost(&mut self, listener_id: ListenerId, error: String) -> bool {
+        if let Some(addr) = self.relisteners.remove(&listener_id) {
+            self.relisten_failed(addr, error);
+            return true;
+        }
+        let Some(addr) = self.listener_requests.get(&listener_id) else {
+            return false;
+        };
+        if !self.relisten || self.pending_remove_listener.contains_key(&listener_id) {
+            return false;
+        }
+        let addr = addr.clone();
+        debug!("listener on {addr} lost: {error}");
+        self.relisten_attempts.insert(addr.clone(), 0);
+        self.schedule_relisten(addr);
+        true
+    }
+
+    pub(crate) fn set_rendezvous(&mut self, config: RendezvousConfig) {
+        let RendezvousConfig {
+            servers,
+            namespaces,
+            register_ttl,
+            discovery_interval,
+            dial_discovered,
+        } = config;
+
+        for addr in servers {
+            let Some(peer_id) = addr.peer_id() else {
+                warn!("rendezvous server address {addr} without a peer id");
+                continue;
+            };
+            self.swarm
+                .behaviour_mut()
+                .addressbook
+                .add_address(peer_id, addr);
+            if !self.rzv_servers.contains(&peer_id) {
+                self.rzv_servers.push(peer_id);
+            }
+        }
+
+        for namespace in namespaces {
+            match Namespace::new(namespace) {
+                Ok(ns) if !self.rzv_namespaces.contains(&ns) => self.rzv_namespaces.push(ns),
+                Ok(_) => {}
+                Err(e) => warn!("invalid rendezvous namespace: {e}"),
+            }
+        }
+
+        self.rzv_register_ttl = register_ttl;
+        self.rzv_discovery_interval = discovery_interval;
+        self.rzv_dial_discovered = dial_discovered;
+
+        if !self.rzv_servers.is_empty() {
+            self.rzv_timers
+                .push(futures::future::ready(RendezvousTimer::Discover).boxed());
+        }
+    }
+
+    /// Subscribes to the announcement topic, the announcements being published from then on.
+    pub(crate) fn set_content_announcer(&mut self, config: ContentAnnouncerConfig) {
+        let Some(pubsub) = self.swarm.behaviour_mut().pubsub.as_mut() else {
+            warn!("content announcer requires pubsub, which is disabled");
+            return;
+        };
+
+        match pubsub.subscribe(config.topic.clone()) {
+            Ok(stream) => {
+                self.announcements.push(stream);
+                self.content_announcer = Some(ContentAnnouncer::new(config));
+            }
+            Err(e) => warn!("failed to subscribe to {}: {e}", config.topic),
+        }
+    }
+
+    /// Publishes the root of a new pin to the peers subscribed to the announcement topic.
+    fn announce_content(&mut self, cid: &Cid) {
+        let Some(announcer) = self.content_announcer.as_ref() else {
+            return;
+        };
+        let topic = announcer.config.topic.clone();
+        let Some(pubsub) = self.swarm.behaviour_mut().pubsub.as_mut() else {
+            return;
+        };
+        // fails without any peer subscribed to the topic
+        if let Err(e) = pubsub.publish(topic, cid.to_bytes()) {
+            debug!("failed to announce {cid}: {e}");
+        }
+    }
+
+    fn handle_announcement(&mut self, message: libp2p::gossipsub::Message) {
+        let local_peer_id = *self.swarm.local_peer_id();
+        let Some(announcer) = self.content_announcer.as_mut() else {
+            return;
+        };
+        if let Some((peer_id, cid)) = announcer.receive(&local_peer_id, &message, Instant::now()) {
+            trace!(%peer_id, %cid, "content announced");
+        }
+    }
+
+    fn sweep_announcements(&mut self) {
+        if let Some(announcer) = self.content_announcer.as_mut() {
+            announcer.sweep(Instant::now());
+        }
+    }
+
+    /// Returns the peers which announced the cid, and whether they replace the DHT lookup.
+    fn announced_providers(&self, cid: &Cid) -> (HashSet<PeerId>, bool) {
+        self.content_announcer
+            .as_ref()
+            .map(|announcer| {
+                (
+                    announcer.providers(cid, Instant::now()),
+                    announcer.config.replace_dht,
+                )
+            })
+            .unwrap_or_default()
+    }
+
+    /// Registers on the server, renewing the registration once registered.
+    fn register_rendezvous(&mut self, server: PeerId, ns: Namespace) -> anyhow::Result<()> {
+        let ttl = self.rzv_register_ttl;
+        let rz = self
+            .swarm
+            .behaviour_mut()
+            .rendezvous_client
+            .as_mut()
+            .ok_or(ProtocolDisabled(ProtocolKind::Rendezvous))?;
+        rz.register(ns.clone(), server, ttl)?;
+        self.rzv_registrations.insert((server, ns), (ttl, None));
+        Ok(())
+    }
+
+    /// Registers the namespaces which are not yet registered on the server and discovers the
+    /// peers registered under them.
+    fn sync_rendezvous(&mut self, server: PeerId) {
+        for ns in self.rzv_namespaces.clone() {
+            if !self.rzv_registrations.contains_key(&(server, ns.clone())) {
+                // fails without any external address, retried on the next discovery
+                if let Err(e) = self.register_rendezvous(server, ns.clone()) {
+                    debug!("rendezvous: failed to register {ns} on {server}: {e}");
+                }
+            }
+            if let Some(rz) = self.swarm.behaviour_mut().rendezvous_client.as_mut() {
+                rz.discover(Some(ns), None, None, server);
+            }
+        }
+    }
+
+    fn handle_rendezvous_timer(&mut self, timer: RendezvousTimer) {
+        match timer {
+            RendezvousTimer::Renew(server, ns) => {
+                let key = (server, ns);
+                let Some((ttl, renew_at)) = self.rzv_registrations.get_mut(&key) else {
+                    return;
+                };
+                // superseded by a later registration
+                if !renew_at.is_some_and(|at| at <= Instant::now()) {
+                    return;
+                }
+                *renew_at = None;
+                let ttl = *ttl;
+
+                let (server, ns) = key;
+                let Some(rz) = self.swarm.behaviour_mut().rendezvous_client.as_mut() else {
+                    return;
+                };
+                if let Err(e) = rz.register(ns.clone(), server, ttl) {
+                    warn!("rendezvous: failed to renew {ns} on {server}: {e}");
+                    self.rzv_registrations.remove(&(server, ns));
+                }
+            }
+            RendezvousTimer::Discover => {
+                for server in self.rzv_servers.clone() {
+                    match self.swarm.is_connected(&server) {
+                        true => self.sync_rendezvous(server),
+                        // synced once connected
+                        false => {
+                            if let Err(e) = self.swarm.dial(server) {
+                                debug!("rendezvous: failed to dial {server}: {e}");
+                            }
+                        }
+                    }
+                }
+
+                if !self.rzv_discovery_interval.is_zero() {
+                    self.rzv_timers.push(
+                        futures_timer::Delay::new(self.rzv_discovery_interval)
+                            .map(|_| RendezvousTimer::Discover)
+                            .boxed(),
+                    );
+                }
+            }
+        }
+    }
+
+    pub(crate) fn set_bootstrap_policy(&mut self, policy: BootstrapPolicy) {
+        self.bootstrap_policy = policy;
+        self.bootstrap_generation += 1;
+        self.bootstrap_on_listen = policy.on_start && self.listening_addresses.is_empty();
+        self.schedule_bootstrap();
+    }
+
+    fn schedule_bootstrap(&mut self) {
+        let interval = self.bootstrap_policy.interval;
+        if let Some(interval) = interval.filter(|interval| !interval.is_zero()) {
+            let generation = self.bootstrap_generation;
+            self.bootstrap_timers.push(
+                futures_timer::Delay::new(interval)
+                    .map(move |_| generation)
+                    .boxed(),
+            );
+        }
+    }
+
+    fn handle_bootstrap_timer(&mut self, generation: u64) {
+        // superseded by a later policy
+        if generation != self.bootstrap_generation {
+            return;
+        }
+        self.auto_bootstrap("interval");
+        self.schedule_bootstrap();
+    }
+
+    fn bootstrap_below_min_peers(&mut self) {
+        let Some(min_peers) = self.bootstrap_policy.min_peers_trigger else {
+            return;
+        };
+        if self.swarm.connected_peers().count() < min_peers {
+            self.auto_bootstrap("below the minimum peers");
+        }
+    }
+
+    fn ready_state(&mut self) -> ReadyState {
+        let listeners = self
+            .listening_addresses
+            .values()
+            .filter(|addrs| !addrs.is_empty())
+            .count();
+        let connected_peers = self.swarm.connected_peers().count();
+        let dht_routing_peers = match self.swarm.behaviour_mut().kademlia.as_mut() {
+            Some(kad) => kad.kbuckets().map(|bucket| bucket.num_entries()).sum(),
+            None => 0,
+        };
+        ReadyState {
+            listeners,
+            connected_peers,
+            dht_routing_peers,
+        }
+    }
+
+    /// Answers the waiters whose criteria are met, dropping the ones which timed out.
+    fn resolve_ready_waiters(&mut self) {
+        if self.ready_waiters.is_empty() {
+            return;
+        }
+        let state = self.ready_state();
+        for (criteria, ret) in std::mem::take(&mut self.ready_waiters) {
+            if ret.is_canceled() {
+                continue;
+            }
+            match state.meets(&criteria) {
+                true => {
+                    let _ = ret.send(Ok(state));
+                }
+                false => self.ready_waiters.push((criteria, ret)),
+            }
+        }
+    }
+
+    /// Sorts the configured bootstrap nodes into the contacted and failed ones.
+    fn bootstrap_report(&self, started: Instant, connected: &HashSet<PeerId>) -> BootstrapReport {
+        let mut contacted = Vec::new();
+        let mut failed = Vec::new();
+        for addr in &self.bootstraps {
+            match addr.peer_id() {
+                Some(peer_id) if connected.contains(&peer_id) => {
+                    if !contacted.contains(&peer_id) {
+                        contacted.push(peer_id);
+                    }
+                }
+                _ => failed.push(addr.clone()),
+            }
+        }
+
+        BootstrapReport {
+            contacted,
+            failed,
+            duration: started.elapsed(),
+        }
+    }
+
+    /// Bootstraps the DHT for the policy, unless a bootstrap is already in progress.
+    fn auto_bootstrap(&mut self, reason: &'static str) {
+        if self.bootstrap_query.is_some() {
+            trace!(reason, "kad: bootstrap already in progress");
+            return;
+        }
+
+        let bootstrappers = self
+            .bootstraps
+            .iter()
+            .filter_map(|addr| {
+                let mut addr = addr.clone();
+                addr.extract_peer_id().map(|peer_id| (peer_id, addr))
+            })
+            .collect::<Vec<_>>();
+
+        let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
+            return;
+        };
+        for (peer_id, addr) in bootstrappers {
+            kad.add_address(&peer_id, addr);
+        }
+
+        match kad.bootstrap() {
+            Ok(id) => {
+                debug!(reason, "kad: bootstrapping");
+                self.bootstrap_query = Some(id);
+                let key = self.swarm.local_peer_id().to_bytes();
+                self.trace_kad_query(id, "bootstrap", &key);
+            }
+            Err(e) => debug!(reason, "kad: can't bootstrap the node: {:?}", e),
+        }
+    }
+
     #[cfg(feature = "beetle_bitswap")]
     fn destroy_bs_session(&mut self, ctx: u64, ret: oneshot::Sender<anyhow::Result<()>>) {
         if let Some(bitswap) = self.swarm.behaviour().bitswap.as_ref() {
@@ -299,6 +1299,545 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
         }
     }
 
+    /// Requests a reservation on a relay if the node is private and none was picked yet, trying
+    /// the static relays before the identified peers supporting the hop protocol.
+    pub(crate) fn select_auto_relay(&mut self) {
+        if !self.auto_relay || !self.nat_private || self.relay_candidate.is_some() {
+            return;
+        }
+
+        let hop_peers = self
+            .swarm
+            .behaviour()
+            .peerbook
+            .peers_info()
+            .filter(|info| {
+                info.protocols
+                    .iter()
+                    .any(|p| libp2p::relay::HOP_PROTOCOL_NAME.eq(p))
+            })
+            .map(|info| (info.public_key.to_peer_id(), info.listen_addrs.clone()))
+            .collect::<Vec<_>>();
+
+        let Some(manager) = self.swarm.behaviour_mut().relay_manager.as_mut() else {
+            return;
+        };
+
+        let candidate = self
+            .static_relays
+            .iter()
+            .copied()
+            .chain(hop_peers.iter().map(|(peer_id, _)| *peer_id))
+            .find(|peer_id| !self.failed_relays.contains(peer_id));
+
+        let Some(peer_id) = candidate else {
+            // every relay failed, they are tried again on the next round
+            self.failed_relays.clear();
+            return;
+        };
+
+        if let Some((_, addrs)) = hop_peers.iter().find(|(id, _)| *id == peer_id) {
+            for addr in addrs.iter().filter(|addr| !addr.is_relay()) {
+                manager.add_address(peer_id, addr.clone());
+            }
+        }
+
+        debug!(%peer_id, "requesting a relay reservation");
+        self.relay_candidate = Some(peer_id);
+        manager.select(peer_id);
+    }
+
+    /// Removes the relayed addresses of the reservation, picking another relay if it was picked
+    /// automatically.
+    fn on_reservation_end(&mut self, peer_id: PeerId, failed: bool) {
+        for addr in self.relay_reservations.remove(&peer_id).unwrap_or_default() {
+            self.remove_external_address(&addr);
+        }
+
+        if self.relay_candidate != Some(peer_id) {
+            return;
+        }
+
+        self.relay_candidate = None;
+        if failed {
+            self.failed_relays.insert(peer_id);
+        }
+        self.select_auto_relay();
+    }
+
+    /// Adds an external address of the swarm, pushing it to the connected peers.
+    fn add_external_address(&mut self, addr: Multiaddr, source: ExternalAddressSource) {
+        let Entry::Vacant(entry) = self.external_addresses.entry(addr.clone()) else {
+            return;
+        };
+        entry.insert(source);
+        self.swarm.add_external_address(addr);
+        self.push_identify();
+    }
+
+    fn remove_external_address(&mut self, addr: &Multiaddr) {
+        if self.external_addresses.remove(addr).is_none() {
+            return;
+        }
+        self.swarm.remove_external_address(addr);
+        self.push_identify();
+    }
+
+    fn expand_unspecified(&self, address: &Multiaddr) -> Vec<Multiaddr> {
+        addr::expand_unspecified(address, &self.interfaces, self.interface_filter)
+    }
+
+    /// Adds the reachable addresses a listening address stands for as external addresses.
+    fn advertise_listen_address(&mut self, address: &Multiaddr) {
+        for address in self.expand_unspecified(address) {
+            if address.is_unspecified() {
+                continue;
+            }
+
+            if self.local_external_addr
+                && !address.is_relay()
+                && (address.is_loopback() || address.is_private())
+            {
+                self.add_external_address(address.clone(), ExternalAddressSource::Listener);
+            }
+
+            if !address.is_loopback() && !address.is_private() {
+                // We will assume that the address is global and reachable externally
+                self.add_external_address(address, ExternalAddressSource::Listener);
+            }
+        }
+    }
+
+    fn withdraw_listen_address(&mut self, address: &Multiaddr) {
+        for address in self.expand_unspecified(address) {
+            self.remove_external_address(&address);
+        }
+    }
+
+    /// Detects the interface addresses again, replacing the external addresses of the
+    /// unspecified listening addresses when the interfaces changed.
+    fn refresh_interfaces(&mut self) {
+        let interfaces = addr::interface_addresses();
+        if interfaces == self.interfaces {
+            return;
+        }
+
+        let unspecified = self
+            .listening_addresses
+            .values()
+            .flatten()
+            .filter(|address| address.is_unspecified())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let expanded = |task: &Self| {
+            unspecified
+                .iter()
+                .flat_map(|address| task.expand_unspecified(address))
+                .collect::<HashSet<_>>()
+        };
+
+        let previous = expanded(self);
+        self.interfaces = interfaces;
+        let current = expanded(self);
+
+        for address in previous.difference(&current) {
+            if matches!(
+                self.external_addresses.get(address),
+                Some(ExternalAddressSource::Listener)
+            ) {
+                self.remove_external_address(address);
+            }
+        }
+
+        for address in &unspecified {
+            self.advertise_listen_address(address);
+        }
+    }
+
+    /// Pushes our identify info to the connected peers, which otherwise only learn about the
+    /// changed addresses once they identify us again.
+    fn push_identify(&mut self) {
+        let peers = self.swarm.connected_peers().copied().collect::<Vec<_>>();
+        if let Some(identify) = self.swarm.behaviour_mut().identify.as_mut() {
+            identify.push(peers);
+        }
+    }
+
+    fn unban(&mut self, target: &BanTarget) {
+        self.bans.remove(target);
+        match target {
+            BanTarget::Peer(peer_id) => {
+                self.swarm.behaviour_mut().block_list.unblock_peer(*peer_id);
+            }
+            BanTarget::Address(addr) => {
+                self.gate.unban_addr(addr);
+            }
+        }
+    }
+
+    /// Lifts the bans which expired.
+    pub(crate) fn sweep_bans(&mut self) {
+        let now = Instant::now();
+        let expired = self
+            .bans
+            .iter()
+            .filter(|(_, expires)| matches!(expires, Some(expires) if *expires <= now))
+            .map(|(target, _)| target.clone())
+            .collect::<Vec<_>>();
+
+        for target in expired {
+            debug!(?target, "ban expired");
+            self.unban(&target);
+        }
+    }
+
+    /// Removes the expired records from the kademlia store, which only drops them when they are
+    /// looked up.
+    fn sweep_kad_store(&mut self) {
+        if let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() {
+            kad.store_mut().remove_expired(Instant::now());
+        }
+    }
+
+    /// Sends the routing table change to the subscribers, dropping the sender once they are all
+    /// gone.
+    fn emit_dht_event(&mut self, event: DhtEvent) {
+        let Some(tx) = self.dht_events.as_ref() else {
+            return;
+        };
+        if tx.send(event).is_err() {
+            self.dht_events = None;
+        }
+    }
+
+    /// Saves the bitswap ledgers of the peers in the datastore, if persisted.
+    #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+    pub(crate) fn save_bitswap_ledgers(&self) -> Option<JoinHandle<()>> {
+        if !self.persist_bitswap_ledgers {
+            return None;
+        }
+
+        let ledgers = self.swarm.behaviour().bitswap.as_ref()?.peer_ledgers();
+        let repo = self.repo.clone();
+        Some(tokio::spawn(async move {
+            if let Err(e) = crate::p2p::ledger::save_ledgers(&repo, &ledgers).await {
+                warn!("failed to save the bitswap ledgers: {e}");
+            }
+        }))
+    }
+
+    /// Saves the addresses of the addressbook, along with the addresses of the peers in the
+    /// routing table and the listen addresses the connected peers identified with, marking the
+    /// peers found in the routing table.
+    pub(crate) fn save_addressbook(&mut self) -> Option<JoinHandle<()>> {
+        let path = self.addressbook_path.clone()?;
+
+        let dht_peers = match self.swarm.behaviour_mut().kademlia.as_mut() {
+            Some(kad) => kad
+                .kbuckets()
+                .flat_map(|bucket| {
+                    bucket
+                        .iter()
+                        .map(|entry| {
+                            let addrs = entry.node.value.iter().cloned().collect::<Vec<_>>();
+                            (*entry.node.key.preimage(), addrs)
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<HashMap<_, _>>(),
+            None => HashMap::new(),
+        };
+
+        let behaviour = self.swarm.behaviour();
+        let now = addressbook::unix_secs(std::time::SystemTime::now());
+        let mut records = behaviour
+            .addressbook
+            .records()
+            .map(|(peer_id, addrs, last_seen)| {
+                let record = PeerRecord {
+                    peer_id: *peer_id,
+                    addrs: addrs.clone(),
+                    last_seen: addressbook::unix_secs(last_seen),
+                    dht: false,
+                    meta: Default::default(),
+                };
+                (*peer_id, record)
+            })
+            .collect::<HashMap<_, _>>();
+
+        let routed = dht_peers
+            .into_iter()
+            .map(|(peer_id, addrs)| (peer_id, addrs, true));
+        let identified = behaviour.peerbook.peers_info().map(|info| {
+            let peer_id = info.public_key.to_peer_id();
+            (peer_id, info.listen_addrs.clone(), false)
+        });
+        for (peer_id, addrs, in_dht) in routed.chain(identified) {
+            let record = records.entry(peer_id).or_insert_with(|| PeerRecord {
+                peer_id,
+                addrs: vec![],
+                last_seen: now,
+                dht: false,
+                meta: Default::default(),
+            });
+            record.dht |= in_dht;
+            for addr in addrs {
+                if !record.addrs.contains(&addr) {
+                    record.addrs.push(addr);
+                }
+            }
+        }
+
+        // the metadata of peers without addresses is kept as well
+        for (peer_id, meta) in behaviour.peerbook.persisted_meta() {
+            records
+                .entry(*peer_id)
+                .or_insert_with(|| PeerRecord {
+                    peer_id: *peer_id,
+                    addrs: vec![],
+                    last_seen: now,
+                    dht: false,
+                    meta: Default::default(),
+                })
+                .meta = meta.clone();
+        }
+        let records = records.into_values().collect::<Vec<_>>();
+
+        let handle = tokio::task::spawn_blocking(move || {
+            if let Err(e) = addressbook::save_records(&path, &records) {
+                warn!(path = %path.display(), error = %e, "failed to save the addressbook");
+            }
+        });
+
+        Some(handle)
+    }
+
+    #[cfg(feature = "metrics")]
+    fn update_metrics(&mut self) {
+        let metrics = &self.metrics;
+        metrics
+            .connected_peers
+            .set(self.swarm.connected_peers().count() as i64);
+
+        let behaviour = self.swarm.behaviour_mut();
+        let known_addresses = behaviour
+            .addressbook
+            .records()
+            .map(|(_, addrs, _)| addrs.len())
+            .sum::<usize>();
+        metrics.known_addresses.set(known_addresses as i64);
+
+        if let Some(kad) = behaviour.kademlia.as_mut() {
+            metrics
+                .pending_kad_queries
+                .set(kad.iter_queries().count() as i64);
+        }
+
+        #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+        if let Some(bitswap) = behaviour.bitswap.as_ref() {
+            metrics
+                .wantlist_length
+                .set(bitswap.local_wantlist().len() as i64);
+        }
+
+        if let Some(blocks) = self.repo.num_blocks() {
+            metrics.repo_blocks.set(blocks as i64);
+        }
+
+        metrics.set_bandwidth(self.bandwidth.stats());
+    }
+
+    /// Tears the node down before the task exits: no more facade events are accepted, the
+    /// listeners are closed, and the bitswap sessions, the pending writes of the repo and the
+    /// saving of the addressbook are awaited for at most `timeout`.
+    pub(crate) async fn shutdown(&mut self, timeout: Duration) {
+        self.from_facade.get_mut().close();
+
+        let listeners = self.listening_addresses.keys().copied().collect::<Vec<_>>();
+        for listener_id in listeners {
+            self.swarm.remove_listener(listener_id);
+        }
+
+        let mut pending = Vec::new();
+
+        #[cfg(feature = "beetle_bitswap")]
+        {
+            let sessions = self.bitswap_sessions.keys().copied().collect::<Vec<_>>();
+            for id in sessions {
+                let (tx, rx) = oneshot::channel();
+                self.destroy_bs_session(id, tx);
+                pending.push(rx.map(|_| ()).boxed());
+            }
+        }
+
+        #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+        if let Some(handle) = self.save_bitswap_ledgers() {
+            pending.push(handle.map(|_| ()).boxed());
+        }
+
+        // writes to the repo hold the gc lock until completed, after which the totals of the
+        // blockstore are saved for the next start
+        let repo = self.repo.clone();
+        pending.push(
+            async move {
+                let _g = repo.inner.gclock.write().await;
+                if let Err(e) = repo.save_block_stat().await {
+                    warn!("failed to save the totals of the blockstore: {e}");
+                }
+            }
+            .boxed(),
+        );
+
+        if let Some(handle) = self.save_addressbook() {
+            pending.push(handle.map(|_| ()).boxed());
+        }
+
+        if tokio::time::timeout(timeout, futures::future::join_all(pending))
+            .await
+            .is_err()
+        {
+            warn!("shutdown did not complete within {timeout:?}");
+        }
+    }
+
+    /// Disconnects peers above the high-water mark down to the low-water mark, starting with the
+    /// peers with the highest latency. Peers exchanging blocks, whitelisted peers and bootstrap
+    /// nodes are kept.
+    pub(crate) fn prune_connections(&mut self) {
+        let Some(high_water) = self.connection_limits.high_water else {
+            return;
+        };
+
+        let connected = self.swarm.connected_peers().count();
+        if connected <= high_water {
+            return;
+        }
+
+        let low_water = self.connection_limits.low_water.unwrap_or(high_water);
+        let excess = connected - low_water.min(high_water);
+
+        let bootstrap_peers = self
+            .bootstraps
+            .iter()
+            .filter_map(MultiaddrExt::peer_id)
+            .collect::<HashSet<_>>();
+
+        let behaviour = self.swarm.behaviour();
+        let mut candidates = self
+            .swarm
+            .connected_peers()
+            .filter(|peer_id| {
+                !behaviour.peerbook.is_whitelisted(peer_id)
+                    && !behaviour.peerbook.is_protected(peer_id)
+                    && !bootstrap_peers.contains(peer_id)
+                    && !self.has_bitswap_session(peer_id)
+            })
+            .map(|peer_id| (*peer_id, behaviour.peerbook.get_peer_latest_rtt(*peer_id)))
+            .collect::<Vec<_>>();
+
+        // peers without a known latency are pruned first
+        candidates.sort_by(|(_, a), (_, b)| match (a, b) {
+            (Some(a), Some(b)) => b.cmp(a),
+            _ => a.is_some().cmp(&b.is_some()),
+        });
+
+        debug!(
+            connected,
+            high_water,
+            low_water,
+            "pruning {} peers",
+            excess.min(candidates.len())
+        );
+
+        for (peer_id, _) in candidates.into_iter().take(excess) {
+            if self.swarm.disconnect_peer_id(peer_id).is_ok() {
+                self.closing.insert(peer_id, DisconnectCause::Pruned);
+            }
+        }
+    }
+
+    fn record_disconnection(
+        &mut self,
+        peer: PeerId,
+        connection_id: ConnectionId,
+        addr: Multiaddr,
+        error: Option<&ConnectionError>,
+        last: bool,
+    ) {
+        let closing = match last {
+            true => self.closing.remove(&peer),
+            false => self.closing.get(&peer).cloned(),
+        };
+        let ping_timeout = self.ping_timeouts.remove(&connection_id);
+
+        let cause = match (closing, error) {
+            (Some(cause), _) => cause,
+            (None, None | Some(ConnectionError::KeepAliveTimeout)) => DisconnectCause::Idle,
+            (None, Some(_)) if ping_timeout => DisconnectCause::PingTimeout,
+            (None, Some(ConnectionError::IO(e))) => match e.kind() {
+                std::io::ErrorKind::UnexpectedEof
+                | std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::BrokenPipe => DisconnectCause::RemoteClosed,
+                _ => DisconnectCause::Transport(e.to_string()),
+            },
+        };
+
+        if self.disconnection_history == 0 {
+            return;
+        }
+        if self.disconnections.len() == self.disconnection_history {
+            self.disconnections.pop_front();
+        }
+        self.disconnections.push_back(Disconnection {
+            peer,
+            addr,
+            when: SystemTime::now(),
+            cause,
+        });
+    }
+
+    #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+    fn has_bitswap_session(&self, peer_id: &PeerId) -> bool {
+        self.swarm
+            .behaviour()
+            .bitswap
+            .as_ref()
+            .map_or(false, |bitswap| bitswap.has_session(peer_id))
+    }
+
+    // the sessions of the other bitswap implementations are not tracked per peer
+    #[cfg(any(feature = "libp2p_bitswap", feature = "beetle_bitswap"))]
+    fn has_bitswap_session(&self, _: &PeerId) -> bool {
+        false
+    }
+
+    fn emit_ping<F: Fn() -> Result<Duration, anyhow::Error>>(
+        &mut self,
+        peer_id: PeerId,
+        result: F,
+    ) {
+        if let Entry::Occupied(mut entry) = self.ping_subscribers.entry(peer_id) {
+            entry.get_mut().retain_mut(|(tx, remaining)| {
+                if tx.unbounded_send(result()).is_err() {
+                    return false;
+                }
+                // the bounded streams end with their last round-trip time
+                match remaining {
+                    Some(remaining) => {
+                        *remaining -= 1;
+                        *remaining > 0
+                    }
+                    None => true,
+                }
+            });
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+        }
+    }
+
     fn emit_pubsub_event(&self, event: InnerPubsubEvent) {
         for ch in &self.pubsub_event_stream {
             let event = event.clone();
@@ -307,6 +1846,14 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
     }
 
     fn handle_swarm_event(&mut self, swarm_event: TSwarmEvent<C>) {
+        self.swarm_observers.retain(|observer| {
+            let result = std::panic::catch_unwind(AssertUnwindSafe(|| observer(&swarm_event)));
+            if result.is_err() {
+                warn!("swarm observer panicked; removing it");
+            }
+            result.is_ok()
+        });
+
         if let Some(handler) = self.swarm_event.as_ref() {
             handler(&mut self.swarm, &swarm_event)
         }
@@ -315,42 +1862,164 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 listener_id,
                 address,
             } => {
-                if self.local_external_addr
-                    && !address.is_relay()
-                    && (address.is_loopback() || address.is_private())
-                {
-                    self.swarm.add_external_address(address.clone());
-                }
+                self.advertise_listen_address(&address);
+
+                if std::mem::take(&mut self.bootstrap_on_listen) {
+                    self.auto_bootstrap("started");
+                }
+
+                let addrs = self.listening_addresses.entry(listener_id).or_default();
+                addrs.push(address);
+                let first = addrs.len() == 1;
+
+                if let Some(address) = self.relisteners.remove(&listener_id) {
+                    info!("listening again on {address}");
+                    self.relisten_attempts.remove(&address);
+                    let _ = self
+                        .connection_events
+                        .send(ConnectionEvent::Relistened { address });
+                }
+
+                if self.pending_add_listener.contains_key(&listener_id) {
+                    let unspecified = self
+                        .listener_requests
+                        .get(&listener_id)
+                        .map(|addr| addr.is_unspecified())
+                        .unwrap_or_default();
+
+                    match unspecified {
+                        // the other interfaces are reported right after the first one
+                        true if first => {
+                            self.listener_settle.push(
+                                futures_timer::Delay::new(LISTENER_SETTLE)
+                                    .map(move |_| listener_id)
+                                    .boxed(),
+                            );
+                        }
+                        true => {}
+                        false => self.settle_listener(listener_id),
+                    }
+                }
+            }
+            SwarmEvent::Dialing {
+                peer_id,
+                connection_id,
+            } => {
+                let addresses = peer_id
+                    .and_then(|peer_id| {
+                        self.swarm
+                            .behaviour()
+                            .addressbook
+                            .get_peer_addresses(&peer_id)
+                            .cloned()
+                    })
+                    .unwrap_or_default();
+                self.dials
+                    .entry(connection_id)
+                    .or_insert_with(|| (peer_id, addresses, Instant::now()));
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                connection_id,
+                endpoint,
+                num_established,
+                ..
+            } => {
+                self.dials.remove(&connection_id);
 
-                if !address.is_loopback() && !address.is_private() {
-                    // We will assume that the address is global and reachable externally
-                    self.swarm.add_external_address(address.clone());
+                for (_, connected, _) in self.bootstrap_reports.values_mut() {
+                    connected.insert(peer_id);
                 }
 
-                self.listening_addresses
-                    .entry(listener_id)
-                    .or_default()
-                    .push(address.clone());
+                let _ = self.connection_events.send(ConnectionEvent::Connected {
+                    peer: peer_id,
+                    addr: endpoint.get_remote_address().clone(),
+                    direction: endpoint.to_endpoint(),
+                });
+
+                #[cfg(feature = "metrics")]
+                self.metrics
+                    .connected_peers
+                    .set(self.swarm.connected_peers().count() as i64);
 
-                if let Some(ret) = self.pending_add_listener.remove(&listener_id) {
-                    let _ = ret.send(Ok(address));
-                }
-            }
-            SwarmEvent::ConnectionEstablished { connection_id, .. } => {
                 if let Some(ch) = self.pending_connection.remove(&connection_id) {
                     _ = ch.send(Ok(()));
                 }
+
+                if let Some((_, ch)) = self.pending_dial.remove(&connection_id) {
+                    _ = ch.send(Ok(peer_id));
+                }
+
+                if num_established.get() == 1 && self.rzv_servers.contains(&peer_id) {
+                    self.sync_rendezvous(peer_id);
+                }
             }
             SwarmEvent::OutgoingConnectionError {
                 connection_id,
+                peer_id,
                 error,
-                ..
             } => {
-                if let Some(ch) = self.pending_connection.remove(&connection_id) {
-                    _ = ch.send(Err(anyhow::Error::from(error)));
+                self.dials.remove(&connection_id);
+
+                let addr = match &error {
+                    SwarmDialError::Transport(addrs) => addrs.first().map(|(addr, _)| addr.clone()),
+                    SwarmDialError::WrongPeerId { endpoint, .. } => {
+                        Some(endpoint.get_remote_address().clone())
+                    }
+                    _ => None,
+                };
+                let _ = self.connection_events.send(ConnectionEvent::DialFailure {
+                    peer: peer_id,
+                    addr,
+                    error: error.to_string(),
+                });
+
+                if let Some((expected, ch)) = self.pending_dial.remove(&connection_id) {
+                    _ = ch.send(Err(DialError::new(error, expected)));
+                } else if let Some(ch) = self.pending_connection.remove(&connection_id) {
+                    _ = ch.send(Err(DialError::new(error, peer_id)));
                 }
             }
-            SwarmEvent::ConnectionClosed { peer_id, .. } => {
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                connection_id,
+                endpoint,
+                num_established,
+                cause,
+            } => {
+                self.record_disconnection(
+                    peer_id,
+                    connection_id,
+                    endpoint.get_remote_address().clone(),
+                    cause.as_ref(),
+                    num_established == 0,
+                );
+
+                let _ = self.connection_events.send(ConnectionEvent::Disconnected {
+                    peer: peer_id,
+                    remaining_connections: num_established,
+                    cause: cause.map(|e| e.to_string()),
+                });
+
+                #[cfg(feature = "metrics")]
+                self.metrics
+                    .connected_peers
+                    .set(self.swarm.connected_peers().count() as i64);
+
+                if num_established == 0 {
+                    self.observed_addrs.remove_peer(&peer_id);
+                    if let Some(subscribers) = self.ping_subscribers.remove(&peer_id) {
+                        for (tx, _) in subscribers {
+                            let _ = tx.unbounded_send(Err(anyhow!("peer disconnected")));
+                        }
+                    }
+
+                    // reservations and circuits end with the connection
+                    self.served_reservations.remove(&peer_id);
+                    self.relayed_circuits
+                        .retain(|(src, dst), _| *src != peer_id && *dst != peer_id);
+                }
+
                 if let Some(ch) = self.pending_disconnection.remove(&peer_id) {
                     for ch in ch {
                         let _ = ch.send(Ok(()));
@@ -365,16 +2034,64 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     list.retain(|addr| &address != addr);
                 }
 
-                self.swarm.remove_external_address(&address);
+                self.withdraw_listen_address(&address);
+                let _ = self
+                    .connection_events
+                    .send(ConnectionEvent::ListenAddrExpired {
+                        address: address.clone(),
+                    });
+
+                let remaining = self
+                    .listening_addresses
+                    .get(&listener_id)
+                    .map(Vec::len)
+                    .unwrap_or_default();
+                if remaining == 0 {
+                    if let Some(ret) = self.pending_add_listener.remove(&listener_id) {
+                        let _ = ret.send(Err(anyhow!(
+                            "{address} expired before the listener settled"
+                        )));
+                    }
+
+                    // the listeners on an unspecified ip report the address again once the
+                    // interface is back, the others are replaced
+                    let unspecified = self
+                        .listener_requests
+                        .get(&listener_id)
+                        .map(|addr| addr.is_unspecified())
+                        .unwrap_or(true);
+                    if !unspecified && self.listener_lost(listener_id, format!("{address} expired"))
+                    {
+                        self.listener_requests.remove(&listener_id);
+                        self.swarm.remove_listener(listener_id);
+                    }
+                }
             }
             SwarmEvent::ListenerClosed {
                 listener_id,
                 reason,
                 addresses,
             } => {
+                self.listening_addresses.remove(&listener_id);
                 for address in addresses {
-                    self.listening_addresses.remove(&listener_id);
-                    self.swarm.remove_external_address(&address);
+                    self.withdraw_listen_address(&address);
+                }
+
+                let relistened = match &reason {
+                    Err(e) if is_transient(e) => self.listener_lost(listener_id, e.to_string()),
+                    _ => false,
+                };
+                if !relistened {
+                    if let Some(address) = self.relisteners.remove(&listener_id) {
+                        warn!("no longer listening again on {address}: {reason:?}");
+                        self.relisten_attempts.remove(&address);
+                    }
+                }
+
+                self.listener_requests.remove(&listener_id);
+
+                if let Some(ret) = self.pending_add_listener.remove(&listener_id) {
+                    let _ = ret.send(Err(anyhow!("listener closed before listening")));
                 }
 
                 if let Some(ret) = self.pending_remove_listener.remove(&listener_id) {
@@ -388,12 +2105,36 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             SwarmEvent::Behaviour(BehaviourEvent::Mdns(event)) => match event {
                 MdnsEvent::Discovered(list) => {
+                    let mut discovered: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
                     for (peer, addr) in list {
-                        self.swarm.behaviour_mut().add_peer(peer, addr);
+                        self.swarm.behaviour_mut().add_peer(peer, addr.clone());
+                        let addrs = self.mdns_peers.entry(peer).or_default();
+                        if !addrs.contains(&addr) {
+                            addrs.push(addr.clone());
+                        }
+                        discovered.entry(peer).or_default().push(addr);
+                    }
+
+                    if self.mdns_auto_dial {
+                        for (peer, addrs) in discovered {
+                            if self.swarm.is_connected(&peer) {
+                                continue;
+                            }
+                            let opts = DialOpts::peer_id(peer).addresses(addrs).build();
+                            if let Err(e) = self.swarm.dial(opts) {
+                                debug!("mdns: failed to dial {peer}: {e}");
+                            }
+                        }
                     }
                 }
                 MdnsEvent::Expired(list) => {
-                    for (peer, _) in list {
+                    for (peer, addr) in list {
+                        if let Entry::Occupied(mut entry) = self.mdns_peers.entry(peer) {
+                            entry.get_mut().retain(|a| a != &addr);
+                            if entry.get().is_empty() {
+                                entry.remove();
+                            }
+                        }
                         if let Some(mdns) = self.swarm.behaviour().mdns.as_ref() {
                             if !mdns.discovered_nodes().any(|p| p == &peer) {
                                 trace!("mdns: Expired peer {}", peer.to_base58());
@@ -406,10 +2147,18 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 match event {
                     KademliaEvent::InboundRequest { request } => {
                         trace!("kad: inbound {:?} request handled", request);
+                        self.store_inbound_dht_write(request);
                     }
                     KademliaEvent::OutboundQueryProgressed {
                         result, id, step, ..
                     } => {
+                        let span = self
+                            .kad_query_spans
+                            .get(&id)
+                            .map(|(span, _)| span.clone())
+                            .unwrap_or_else(Span::none);
+                        let _entered = span.enter();
+
                         // make sure the query is exhausted
 
                         if self
@@ -420,6 +2169,16 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                             .and_then(|kad| kad.query(&id))
                             .is_none()
                         {
+                            if self.bootstrap_query == Some(id) {
+                                self.bootstrap_query = None;
+                            }
+
+                            if let Some((started, connected, ret)) =
+                                self.bootstrap_reports.remove(&id)
+                            {
+                                let _ = ret.send(Ok(self.bootstrap_report(started, &connected)));
+                            }
+
                             match result {
                                 // these subscriptions return actual values
                                 GetClosestPeers(_) | GetProviders(_) | GetRecord(_) => {}
@@ -518,44 +2277,43 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                                     }
                                 }
                             }
-                            GetProviders(Ok(GetProvidersOk::FoundProviders {
-                                key: _,
-                                providers,
-                            })) => {
+                            GetProviders(Ok(GetProvidersOk::FoundProviders { key, providers })) => {
+                                if let Some(lookup) = self.provider_lookups.get_mut(&key) {
+                                    lookup.found.extend(providers.iter().copied());
+                                }
+                                #[cfg(not(any(
+                                    feature = "libp2p_bitswap",
+                                    feature = "beetle_bitswap"
+                                )))]
+                                if let Some(cids) = self.provider_queries.get(&id).cloned() {
+                                    for cid in cids {
+                                        self.add_providers(cid, &providers);
+                                    }
+                                }
                                 if !providers.is_empty() {
                                     #[cfg(feature = "beetle_bitswap")]
-                                    {
-                                        if let Entry::Occupied(entry) =
-                                            self.bitswap_provider_stream.entry(id)
-                                        {
+                                    if let Some(sinks) = self.bitswap_provider_stream.get(&id) {
+                                        for tx in sinks {
                                             let providers = providers.clone();
-                                            let mut tx = entry.get().clone();
+                                            let mut tx = tx.clone();
                                             tokio::spawn(async move {
                                                 let _ = tx.send(Ok(providers)).await;
                                             });
                                         }
                                     }
                                 }
-                                if let Entry::Occupied(entry) = self.provider_stream.entry(id) {
-                                    if !providers.is_empty() {
-                                        for provider in providers {
-                                            let _ = entry.get().unbounded_send(provider);
+                                if let Some(sinks) = self.provider_stream.get(&id) {
+                                    for tx in sinks {
+                                        for provider in &providers {
+                                            let _ = tx.unbounded_send(*provider);
                                         }
                                     }
                                 }
                             }
+                            // the lookups are closed with the last step of the query below
                             GetProviders(Ok(GetProvidersOk::FinishedWithNoAdditionalRecord {
                                 ..
-                            })) => {
-                                if step.last {
-                                    if let Some(tx) = self.provider_stream.remove(&id) {
-                                        tx.close_channel();
-                                    }
-                                    if let Some(tx) = self.bitswap_provider_stream.remove(&id) {
-                                        drop(tx);
-                                    }
-                                }
-                            }
+                            })) => {}
                             GetProviders(Err(GetProvidersError::Timeout { key, .. })) => {
                                 let key = multibase::encode(Base::Base32Lower, key);
                                 warn!("kad: timed out while trying to get providers for {}", key);
@@ -576,6 +2334,9 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                             StartProviding(Ok(AddProviderOk { key })) => {
                                 let key = multibase::encode(Base::Base32Lower, key);
                                 debug!("kad: providing {}", key);
+                                if let Some(ret) = self.provided_blocks.remove(&id) {
+                                    let _ = ret.send(Ok(()));
+                                }
                             }
                             StartProviding(Err(AddProviderError::Timeout { key })) => {
                                 let key = multibase::encode(Base::Base32Lower, key);
@@ -594,6 +2355,11 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                                             "kad: timed out while trying to provide the record"
                                         )));
                                     }
+                                    if let Some(ret) = self.provided_blocks.remove(&id) {
+                                        let _ = ret.send(Err(anyhow::anyhow!(
+                                            "kad: timed out while trying to provide the block"
+                                        )));
+                                    }
                                 }
                             }
                             RepublishProvider(Ok(AddProviderOk { key })) => {
@@ -748,21 +2514,51 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                                 warn!("kad: timed out while trying to republish record {}", key);
                             }
                         }
+
+                        if step.last {
+                            if let Some((span, started)) = self.kad_query_spans.remove(&id) {
+                                span.record("elapsed_ms", started.elapsed().as_millis() as u64);
+                            }
+                            #[cfg(not(any(
+                                feature = "libp2p_bitswap",
+                                feature = "beetle_bitswap"
+                            )))]
+                            self.provider_queries.remove(&id);
+
+                            // the lookups attached from now on start a new query
+                            self.provider_lookups.retain(|_, lookup| lookup.id != id);
+                            for tx in self.provider_stream.remove(&id).unwrap_or_default() {
+                                tx.close_channel();
+                            }
+                            self.bitswap_provider_stream.remove(&id);
+                        }
                     }
                     KademliaEvent::RoutingUpdated {
                         peer,
-                        is_new_peer: _,
+                        is_new_peer,
                         addresses,
-                        bucket_range: _,
-                        old_peer: _,
+                        bucket_range,
+                        old_peer,
                     } => {
                         trace!("kad: routing updated; {}: {:?}", peer, addresses);
+                        if let Some(old_peer) = old_peer {
+                            self.emit_dht_event(DhtEvent::PeerEvicted { peer: old_peer });
+                        }
+                        if is_new_peer {
+                            self.emit_dht_event(DhtEvent::PeerAdded {
+                                peer,
+                                addresses: addresses.into_vec(),
+                                bucket: bucket_range.0.ilog2().unwrap_or_default(),
+                            });
+                        }
                     }
                     KademliaEvent::UnroutablePeer { peer } => {
                         trace!("kad: peer {} is unroutable", peer);
+                        self.emit_dht_event(DhtEvent::UnroutablePeer { peer });
                     }
                     KademliaEvent::RoutablePeer { peer, address } => {
                         trace!("kad: peer {} ({}) is routable", peer, address);
+                        self.emit_dht_event(DhtEvent::RoutablePeer { peer, address });
                     }
                     KademliaEvent::PendingRoutablePeer { peer, address } => {
                         trace!("kad: pending routable peer {} ({})", peer, address);
@@ -781,11 +2577,28 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     }
                 }
                 BitswapEvent::FindProviders { key, response, .. } => {
-                    if let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() {
-                        info!("Looking for providers for {key}");
-                        let key = key.hash().to_bytes();
-                        let id = kad.get_providers(key.into());
-                        self.bitswap_provider_stream.insert(id, response);
+                    let (announced, replace_dht) = self.announced_providers(&key);
+                    if !announced.is_empty() {
+                        let mut tx = response.clone();
+                        tokio::spawn(async move {
+                            let _ = tx.send(Ok(announced)).await;
+                        });
+                        if replace_dht {
+                            return;
+                        }
+                    }
+                    info!("Looking for providers for {key}");
+                    if let Some((id, found)) = self.get_providers(key.hash().to_bytes().into()) {
+                        if !found.is_empty() {
+                            let mut tx = response.clone();
+                            tokio::spawn(async move {
+                                let _ = tx.send(Ok(found)).await;
+                            });
+                        }
+                        self.bitswap_provider_stream
+                            .entry(id)
+                            .or_default()
+                            .push(response);
                     }
                 }
                 BitswapEvent::Ping { peer, response } => {
@@ -832,11 +2645,101 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     if let Some(m) = self.swarm.behaviour_mut().relay_manager.as_mut() {
                         m.set_peer_rtt(peer, connection, rtt)
                     }
+
+                    self.ping_timeouts.remove(&connection);
+                    self.emit_ping(peer, || Ok(rtt));
                 }
-                libp2p::ping::Event { .. } => {
+                libp2p::ping::Event {
+                    peer,
+                    connection,
+                    result: Result::Err(e),
+                } => {
+                    if matches!(e, libp2p::ping::Failure::Timeout) {
+                        self.ping_timeouts.insert(connection);
+                    }
                     //TODO: Determine if we should continue handling ping errors and if we should disconnect/close connection.
+                    self.emit_ping(peer, || Err(anyhow!("ping failed: {e}")));
                 }
             },
+            SwarmEvent::Behaviour(BehaviourEvent::Pinger(pinger::Event { peer_id, result })) => {
+                match result {
+                    Ok(rtt) => {
+                        trace!("ping: rtt to {peer_id} is {} ms", rtt.as_millis());
+                        self.emit_ping(peer_id, || Ok(rtt));
+                    }
+                    Err(e) => self.emit_ping(peer_id, || Err(anyhow!("ping failed: {e}"))),
+                }
+
+                // pinged until every stream ended
+                if self.ping_subscribers.contains_key(&peer_id) {
+                    if let Some(pinger) = self.swarm.behaviour_mut().pinger.as_mut() {
+                        pinger.ping_after(peer_id, pinger::INTERVAL);
+                    }
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Relay(event)) => {
+                debug!("Relay Server Event: {event:?}");
+                match event {
+                    libp2p::relay::Event::ReservationReqAccepted { src_peer_id, .. } => {
+                        self.served_reservations.insert(src_peer_id);
+                    }
+                    libp2p::relay::Event::ReservationReqDenied { .. } => {
+                        self.denied_reservations += 1;
+                    }
+                    libp2p::relay::Event::ReservationTimedOut { src_peer_id } => {
+                        self.served_reservations.remove(&src_peer_id);
+                    }
+                    libp2p::relay::Event::CircuitReqAccepted {
+                        src_peer_id,
+                        dst_peer_id,
+                    } => {
+                        *self
+                            .relayed_circuits
+                            .entry((src_peer_id, dst_peer_id))
+                            .or_default() += 1;
+                        let _ = self
+                            .connection_events
+                            .send(ConnectionEvent::RelayCircuitOpened {
+                                src: src_peer_id,
+                                dst: dst_peer_id,
+                            });
+                    }
+                    libp2p::relay::Event::CircuitReqDenied {
+                        src_peer_id,
+                        dst_peer_id,
+                    } => {
+                        self.denied_circuits += 1;
+                        let _ = self
+                            .connection_events
+                            .send(ConnectionEvent::RelayCircuitDenied {
+                                src: src_peer_id,
+                                dst: dst_peer_id,
+                            });
+                    }
+                    libp2p::relay::Event::CircuitClosed {
+                        src_peer_id,
+                        dst_peer_id,
+                        error,
+                    } => {
+                        if let Entry::Occupied(mut entry) =
+                            self.relayed_circuits.entry((src_peer_id, dst_peer_id))
+                        {
+                            *entry.get_mut() -= 1;
+                            if *entry.get() == 0 {
+                                entry.remove();
+                            }
+                        }
+                        let _ = self
+                            .connection_events
+                            .send(ConnectionEvent::RelayCircuitClosed {
+                                src: src_peer_id,
+                                dst: dst_peer_id,
+                                error: error.map(|e| e.to_string()),
+                            });
+                    }
+                    _ => {}
+                }
+            }
             SwarmEvent::Behaviour(BehaviourEvent::RelayClient(event)) => {
                 debug!("Relay Client Event: {event:?}");
                 if let Some(m) = self.swarm.behaviour_mut().relay_manager.as_mut() {
@@ -846,7 +2749,18 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             SwarmEvent::Behaviour(BehaviourEvent::RelayManager(event)) => {
                 debug!("Relay Manager Event: {event:?}");
                 match event {
-                    libp2p_relay_manager::Event::ReservationSuccessful { peer_id, .. } => {
+                    libp2p_relay_manager::Event::ReservationSuccessful {
+                        peer_id,
+                        initial_addr,
+                    } => {
+                        self.add_external_address(
+                            initial_addr.clone(),
+                            ExternalAddressSource::Relay,
+                        );
+                        self.relay_reservations
+                            .entry(peer_id)
+                            .or_default()
+                            .push(initial_addr);
                         if let Some(chs) = self.relay_listener.remove(&peer_id) {
                             for ch in chs {
                                 let _ = ch.send(Ok(()));
@@ -854,6 +2768,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                         }
                     }
                     libp2p_relay_manager::Event::ReservationClosed { peer_id, result } => {
+                        self.on_reservation_end(peer_id, result.is_err());
                         if let Some(chs) = self.relay_listener.remove(&peer_id) {
                             match result {
                                 Ok(()) => {
@@ -874,6 +2789,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                         peer_id,
                         result: err,
                     } => {
+                        self.on_reservation_end(peer_id, true);
                         if let Some(chs) = self.relay_listener.remove(&peer_id) {
                             let e = err.to_string();
                             for ch in chs {
@@ -890,9 +2806,18 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     let IdentifyInfo {
                         listen_addrs,
                         protocols,
+                        observed_addr,
                         ..
                     } = &info;
 
+                    let promoted =
+                        self.observed_addrs
+                            .observe(peer_id, observed_addr, self.swarm.listeners());
+                    if let Some(addr) = promoted {
+                        debug!(%addr, "observed by enough peers to be advertised");
+                        self.add_external_address(addr, ExternalAddressSource::Observed);
+                    }
+
                     if let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() {
                         if protocols.iter().any(|p| libp2p::kad::PROTOCOL_NAME.eq(p)) {
                             for addr in listen_addrs {
@@ -912,13 +2837,41 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                         }
                     }
 
-                    if let Some(rets) = self.dht_peer_lookup.remove(&peer_id) {
+                    if let Some(mut rets) = self.dht_peer_lookup.remove(&peer_id) {
+                        // converted once, the last lookup taking it
+                        let info = PeerInfo::from(&info);
+                        let last = rets.pop();
                         for ret in rets {
                             let _ = ret.send(Ok(info.clone()));
                         }
+                        if let Some(ret) = last {
+                            let _ = ret.send(Ok(info));
+                        }
                     }
 
-                    self.swarm.behaviour_mut().peerbook.inject_peer_info(info);
+                    // received again with the changes the peer pushes
+                    let speaks_kad = |info: &IdentifyInfo| {
+                        info.protocols
+                            .iter()
+                            .any(|p| libp2p::kad::PROTOCOL_NAME.eq(p))
+                    };
+                    let kad_now = speaks_kad(&info);
+                    let old = self.swarm.behaviour_mut().peerbook.inject_peer_info(info);
+                    let kad_before = old.as_ref().map(speaks_kad).unwrap_or(true);
+
+                    if !kad_now && kad_before && self.swarm.behaviour().kademlia.is_enabled() {
+                        // no longer reachable through kad, such as switched to client mode
+                        if old.is_some() {
+                            if let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() {
+                                kad.remove_peer(&peer_id);
+                            }
+                        }
+                        let event = ConnectionEvent::ProtocolUnsupported {
+                            peer: peer_id,
+                            protocol: libp2p::kad::PROTOCOL_NAME.to_string(),
+                        };
+                        let _ = self.connection_events.send(event);
+                    }
                 }
                 event => debug!("identify: {:?}", event),
             },
@@ -926,10 +2879,50 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 old,
                 new,
             })) => {
-                //TODO: Use status to indicate if we should use a relay or not
                 debug!("Old Nat Status: {:?}", old);
                 debug!("New Nat Status: {:?}", new);
+                if let Some(status) = self.autonat_status() {
+                    let _ = self.nat_events.send(status);
+                }
+                match new {
+                    autonat::NatStatus::Private => {
+                        self.nat_private = true;
+                        self.select_auto_relay();
+                    }
+                    autonat::NatStatus::Public(_) => {
+                        self.nat_private = false;
+                        // reachable directly, the relay is no longer needed
+                        if let Some(peer_id) = self.relay_candidate.take() {
+                            if let Some(manager) = self.swarm.behaviour_mut().relay_manager.as_mut()
+                            {
+                                manager.disable_relay(peer_id);
+                            }
+                        }
+                    }
+                    autonat::NatStatus::Unknown => {}
+                }
             }
+            SwarmEvent::Behaviour(BehaviourEvent::Autonat(autonat::Event::OutboundProbe(
+                event,
+            ))) => match event {
+                autonat::OutboundProbeEvent::Response { address, .. } => {
+                    self.observed_addrs.reachable(&address);
+                }
+                autonat::OutboundProbeEvent::Error {
+                    error: autonat::OutboundProbeError::Response(autonat::ResponseError::DialError),
+                    ..
+                } => {
+                    for addr in self.observed_addrs.unreachable() {
+                        if self.external_addresses.get(&addr)
+                            == Some(&ExternalAddressSource::Observed)
+                        {
+                            debug!(%addr, "observed address no longer advertised");
+                            self.remove_external_address(&addr);
+                        }
+                    }
+                }
+                _ => {}
+            },
             SwarmEvent::Behaviour(BehaviourEvent::RendezvousClient(
                 libp2p::rendezvous::client::Event::Discovered {
                     rendezvous_node,
@@ -960,6 +2953,26 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     ns_list.insert(namespace);
                 }
 
+                if self.rzv_dial_discovered {
+                    let local_peer_id = *self.swarm.local_peer_id();
+                    let mut discovered: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
+                    for (peer_id, addrs) in ns_book.values().flatten() {
+                        discovered
+                            .entry(*peer_id)
+                            .or_default()
+                            .extend(addrs.iter().cloned());
+                    }
+                    for (peer_id, addrs) in discovered {
+                        if peer_id == local_peer_id || self.swarm.is_connected(&peer_id) {
+                            continue;
+                        }
+                        let opts = DialOpts::peer_id(peer_id).addresses(addrs).build();
+                        if let Err(e) = self.swarm.dial(opts) {
+                            debug!("rendezvous: failed to dial {peer_id}: {e}");
+                        }
+                    }
+                }
+
                 for ns in ns_list {
                     let map = ns_book.remove(&ns).unwrap_or_default();
                     if let Some(channels) = self.rzv_discover_pending.remove(&(rendezvous_node, ns))
@@ -1001,6 +3014,21 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             )) => {
                 info!("Registered to {rendezvous_node} under {namespace} for {ttl} secs");
 
+                if let Some((_, renew_at)) = self
+                    .rzv_registrations
+                    .get_mut(&(rendezvous_node, namespace.clone()))
+                {
+                    // renewed ahead of the expiry to keep the registration without a gap
+                    let renewal = Duration::from_secs(ttl.saturating_mul(3) / 4);
+                    *renew_at = Some(Instant::now() + renewal);
+                    let key = (rendezvous_node, namespace.clone());
+                    self.rzv_timers.push(
+                        futures_timer::Delay::new(renewal)
+                            .map(move |_| RendezvousTimer::Renew(key.0, key.1))
+                            .boxed(),
+                    );
+                }
+
                 if let Some(channels) = self
                     .rzv_register_pending
                     .remove(&(rendezvous_node, namespace.clone()))
@@ -1019,6 +3047,9 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             )) => {
                 error!("Error registering namespace {namespace} to {rendezvous_node}: {error:?}");
 
+                self.rzv_registrations
+                    .remove(&(rendezvous_node, namespace.clone()));
+
                 if let Some(channels) = self
                     .rzv_register_pending
                     .remove(&(rendezvous_node, namespace.clone()))
@@ -1031,42 +3062,237 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
             SwarmEvent::Behaviour(BehaviourEvent::Bitswap(event)) => match event {
                 crate::p2p::bitswap::Event::NeedBlock { cid } => {
-                    if let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() {
-                        info!("Looking for providers for {cid}");
-                        let key = cid.hash().to_bytes();
-                        kad.get_providers(key.into());
+                    let found = self
+                        .provider_fetches
+                        .get(&cid)
+                        .map(|fetch| !fetch.candidates.is_empty())
+                        .unwrap_or_default();
+
+                    if found {
+                        // the dialed providers failed, the next found ones are tried first
+                        self.dial_providers(cid);
+                    } else {
+                        // the provider discovery is part of the fetch
+                        let fetch = self
+                            .bitswap_fetch_spans
+                            .get(&cid)
+                            .map(|(span, _)| span.clone())
+                            .unwrap_or_else(Span::none);
+                        let _entered = fetch.enter();
+
+                        // the peers which announced the block are tried first, the DHT being
+                        // left for when they all failed with `replace_dht`
+                        let (mut announced, replace_dht) = self.announced_providers(&cid);
+                        if let Some(fetch) = self.provider_fetches.get(&cid) {
+                            announced.retain(|peer_id| !fetch.found.contains(peer_id));
+                        }
+                        if !announced.is_empty() {
+                            info!("{} peers announced {cid}", announced.len());
+                            self.provider_fetches.entry(cid).or_default();
+                            self.add_providers(cid, &announced);
+                            if replace_dht {
+                                return;
+                            }
+                        }
+
+                        if let Some((id, found)) = self.get_providers(cid.hash().to_bytes().into())
+                        {
+                            info!("Looking for providers for {cid}");
+                            self.provider_queries.entry(id).or_default().push(cid);
+                            self.provider_fetches.entry(cid).or_default();
+                            if !found.is_empty() {
+                                self.add_providers(cid, &found);
+                            }
+                        }
                     }
                 }
                 crate::p2p::bitswap::Event::CancelBlock { cid } => {
-                    info!(%cid, "block request cancelled")
+                    info!(%cid, "block request cancelled");
+                    self.close_bitswap_fetch(&cid, "cancelled");
                 }
                 crate::p2p::bitswap::Event::BlockRetrieved { cid } => {
-                    info!(%cid, "block retrieved")
+                    info!(%cid, "block retrieved");
+                    self.close_bitswap_fetch(&cid, "retrieved");
+                }
+                crate::p2p::bitswap::Event::ProtocolUnsupported { peer_id } => {
+                    let _ = self
+                        .connection_events
+                        .send(ConnectionEvent::ProtocolUnsupported {
+                            peer: peer_id,
+                            protocol: crate::p2p::bitswap::PROTOCOL.to_string(),
+                        });
+                }
+            },
+            // confirmed by the behaviours, such as autonat and upnp
+            SwarmEvent::ExternalAddrConfirmed { address } => {
+                if let Entry::Vacant(entry) = self.external_addresses.entry(address) {
+                    entry.insert(ExternalAddressSource::Autonat);
+                    self.push_identify();
                 }
+            }
+            SwarmEvent::ExternalAddrExpired { address } => {
+                if self.external_addresses.remove(&address).is_some() {
+                    self.push_identify();
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Upnp(event)) => match event {
+                libp2p::upnp::Event::NewExternalAddr(address) => {
+                    if self
+                        .external_addresses
+                        .insert(address, ExternalAddressSource::Upnp)
+                        .is_none()
+                    {
+                        self.push_identify();
+                    }
+                }
+                event => debug!("upnp: {event:?}"),
             },
+            SwarmEvent::Behaviour(BehaviourEvent::Custom(event)) => {
+                match self.custom_events.as_ref() {
+                    Some(tx) => {
+                        if let Err(e) = tx.unbounded_send(event) {
+                            self.custom_events = None;
+                            debug!("custom behaviour event dropped: {:?}", e.into_inner());
+                        }
+                    }
+                    None => debug!("custom behaviour event dropped: {event:?}"),
+                }
+            }
             _ => debug!("Swarm event: {:?}", swarm_event),
         }
     }
 
     fn handle_event(&mut self, event: IpfsEvent) {
         match event {
+            IpfsEvent::Connect(_, ret) if self.offline => {
+                _ = ret.send(Err(DialError::Offline));
+            }
             IpfsEvent::Connect(target, ret) => {
                 let connection_id = target.connection_id();
+                let peer_id = target.get_peer_id();
 
-                if let Err(e) = self.swarm.dial(target) {
-                    _ = ret.send(Err(anyhow::Error::from(e)));
-                    return;
+                match self.swarm.dial(target) {
+                    Ok(()) => {
+                        self.pending_connection.insert(connection_id, ret);
+                    }
+                    // dialing is skipped when already connected
+                    Err(SwarmDialError::DialPeerConditionFalse(_))
+                        if peer_id.map_or(false, |peer_id| self.swarm.is_connected(&peer_id)) =>
+                    {
+                        _ = ret.send(if self.fail_if_connected {
+                            Err(DialError::AlreadyConnected)
+                        } else {
+                            Ok(())
+                        });
+                    }
+                    Err(e) => {
+                        _ = ret.send(Err(DialError::new(e, peer_id)));
+                    }
+                }
+            }
+            IpfsEvent::ConnectAddr(_, ret) if self.offline => {
+                _ = ret.send(Err(DialError::Offline));
+            }
+            IpfsEvent::ConnectAddr(addr, ret) => {
+                let expected = addr.peer_id();
+                let opts = match expected {
+                    Some(peer_id) => DialOpts::peer_id(peer_id)
+                        .addresses(vec![addr.clone()])
+                        .build(),
+                    None => DialOpts::unknown_peer_id().address(addr.clone()).build(),
+                };
+                let connection_id = opts.connection_id();
+
+                match self.swarm.dial(opts) {
+                    Ok(()) => {
+                        self.pending_dial.insert(connection_id, (expected, ret));
+                        self.dials
+                            .insert(connection_id, (expected, vec![addr], Instant::now()));
+                    }
+                    // dialing is skipped when already connected
+                    Err(SwarmDialError::DialPeerConditionFalse(_))
+                        if expected.map_or(false, |peer_id| self.swarm.is_connected(&peer_id)) =>
+                    {
+                        _ = ret.send(if self.fail_if_connected {
+                            Err(DialError::AlreadyConnected)
+                        } else {
+                            Ok(expected.expect("peer id is known"))
+                        });
+                    }
+                    Err(e) => {
+                        _ = ret.send(Err(DialError::new(e, expected)));
+                    }
                 }
-                self.pending_connection.insert(connection_id, ret);
+            }
+            IpfsEvent::DhtQueries(ret) => {
+                let Some(kad) = self.swarm.behaviour().kademlia.as_ref() else {
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
+                    return;
+                };
+                let _ = ret.send(Ok(kad.iter_queries().count()));
             }
             IpfsEvent::Protocol(ret) => {
                 let info = self.swarm.behaviour().supported_protocols();
                 let _ = ret.send(info);
             }
+            IpfsEvent::PeerProtocols(peer_id, ret) => {
+                let protocols = self
+                    .swarm
+                    .behaviour()
+                    .peerbook
+                    .get_peer_info(peer_id)
+                    .map(|info| info.protocols.iter().map(|p| p.to_string()).collect())
+                    .unwrap_or_default();
+                let _ = ret.send(Ok(protocols));
+            }
+            IpfsEvent::LocalIdentity(ret) => {
+                let Some(public_key) = self.public_key.clone() else {
+                    let _ = ret.send(Err(anyhow!("local identity is unknown")));
+                    return;
+                };
+                let peer_id = *self.swarm.local_peer_id();
+
+                let mut listen_addrs = Vec::new();
+                for addr in self
+                    .swarm
+                    .listeners()
+                    .chain(self.swarm.external_addresses())
+                {
+                    let mut addr = addr.clone();
+                    if !matches!(addr.iter().last(), Some(Protocol::P2p(_))) {
+                        addr.push(Protocol::P2p(peer_id));
+                    }
+                    if !listen_addrs.contains(&addr) {
+                        listen_addrs.push(addr);
+                    }
+                }
+
+                let protocols = self
+                    .swarm
+                    .behaviour()
+                    .supported_protocols()
+                    .into_iter()
+                    .filter_map(|protocol| StreamProtocol::try_from_owned(protocol).ok())
+                    .collect();
+
+                let _ = ret.send(Ok(PeerInfo {
+                    peer_id,
+                    public_key,
+                    protocol_version: self.identify_conf.protocol_version.clone(),
+                    agent_version: self.identify_conf.agent_version.clone(),
+                    listen_addrs,
+                    protocols,
+                    observed_addr: None,
+                    rtt: None,
+                    connection_addrs: vec![],
+                    meta: Default::default(),
+                    score: 0,
+                }));
+            }
             #[cfg(feature = "experimental_stream")]
             IpfsEvent::StreamControlHandle(ret) => {
                 let Some(stream) = self.swarm.behaviour_mut().stream.as_ref() else {
-                    let _ = ret.send(Err(anyhow!("stream protocol is disabled")));
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Stream).into()));
                     return;
                 };
 
@@ -1075,7 +3301,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             #[cfg(feature = "experimental_stream")]
             IpfsEvent::NewStream(protocol, ret) => {
                 let Some(stream) = self.swarm.behaviour_mut().stream.as_ref() else {
-                    let _ = ret.send(Err(anyhow!("stream protocol is disabled")));
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Stream).into()));
                     return;
                 };
 
@@ -1091,7 +3317,29 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 ret.send(Ok(addrs)).ok();
             }
             IpfsEvent::Listeners(ret) => {
-                let listeners = self.swarm.listeners().cloned().collect::<Vec<Multiaddr>>();
+                let mut listeners = Vec::new();
+                for address in self.swarm.listeners() {
+                    for address in self.expand_unspecified(address) {
+                        if !listeners.contains(&address) {
+                            listeners.push(address);
+                        }
+                    }
+                }
+                ret.send(Ok(listeners)).ok();
+            }
+            IpfsEvent::ListenerInfo(ret) => {
+                let listeners = self
+                    .listener_requests
+                    .iter()
+                    .map(|(id, requested)| ListenerInfo {
+                        requested: requested.clone(),
+                        confirmed: self
+                            .listening_addresses
+                            .get(id)
+                            .cloned()
+                            .unwrap_or_default(),
+                    })
+                    .collect();
                 ret.send(Ok(listeners)).ok();
             }
             IpfsEvent::ExternalAddresses(ret) => {
@@ -1103,36 +3351,202 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
 
                 ret.send(Ok(external)).ok();
             }
+            IpfsEvent::ExternalAddressInfo(ret) => {
+                let info = self
+                    .external_addresses
+                    .iter()
+                    .map(|(address, source)| ExternalAddressInfo {
+                        address: address.clone(),
+                        source: *source,
+                    })
+                    .collect();
+
+                ret.send(Ok(info)).ok();
+            }
+            IpfsEvent::AddExternalAddress(addr, ret) => {
+                self.add_external_address(addr, ExternalAddressSource::Manual);
+                ret.send(Ok(())).ok();
+            }
+            IpfsEvent::RemoveExternalAddress(addr, ret) => {
+                self.remove_external_address(&addr);
+                ret.send(Ok(())).ok();
+            }
             IpfsEvent::IsConnected(peer_id, ret) => {
                 let connected = self.swarm.is_connected(&peer_id);
                 ret.send(Ok(connected)).ok();
             }
-            IpfsEvent::Connected(ret) => {
-                let connections = self.swarm.connected_peers().copied();
-                ret.send(Ok(connections.collect())).ok();
+            IpfsEvent::Connected(ret) => {
+                let connections = self.swarm.connected_peers().copied();
+                ret.send(Ok(connections.collect())).ok();
+            }
+            IpfsEvent::Disconnect(peer, ret) => {
+                if self.swarm.disconnect_peer_id(peer).is_err() {
+                    _ = ret.send(Err(anyhow::anyhow!("Peer is not connected")));
+                    return;
+                }
+                self.closing.insert(peer, DisconnectCause::Requested);
+
+                self.pending_disconnection
+                    .entry(peer)
+                    .or_default()
+                    .push(ret);
+            }
+            IpfsEvent::Ban(peer, duration, ret) => {
+                self.swarm.behaviour_mut().block_list.block_peer(peer);
+                if self.swarm.is_connected(&peer) {
+                    self.closing.insert(peer, DisconnectCause::Banned);
+                }
+                self.bans.insert(
+                    BanTarget::Peer(peer),
+                    duration.map(|duration| Instant::now() + duration),
+                );
+                let _ = ret.send(Ok(()));
+            }
+            IpfsEvent::Unban(peer, ret) => {
+                self.unban(&BanTarget::Peer(peer));
+                let _ = ret.send(Ok(()));
+            }
+            IpfsEvent::BanAddress(mut addr, duration, ret) => {
+                // the address is compared with the remote address, which has no peer id
+                if matches!(addr.iter().last(), Some(Protocol::P2p(_))) {
+                    addr.pop();
+                }
+                self.gate.ban_addr(addr.clone());
+                self.swarm.behaviour_mut().gate.close_connections_on(&addr);
+                self.bans.insert(
+                    BanTarget::Address(addr),
+                    duration.map(|duration| Instant::now() + duration),
+                );
+                let _ = ret.send(Ok(()));
+            }
+            IpfsEvent::UnbanAddress(mut addr, ret) => {
+                if matches!(addr.iter().last(), Some(Protocol::P2p(_))) {
+                    addr.pop();
+                }
+                self.unban(&BanTarget::Address(addr));
+                let _ = ret.send(Ok(()));
+            }
+            IpfsEvent::Bans(ret) => {
+                self.sweep_bans();
+                let now = Instant::now();
+                let bans = self
+                    .bans
+                    .iter()
+                    .map(|(target, expires)| Ban {
+                        target: target.clone(),
+                        remaining: expires.map(|expires| expires.saturating_duration_since(now)),
+                    })
+                    .collect();
+                let _ = ret.send(Ok(bans));
+            }
+            IpfsEvent::WhitelistPeer(peer, ret) => {
+                self.swarm.behaviour_mut().peerbook.whitelist_peer(peer);
+                let _ = ret.send(Ok(()));
+            }
+            IpfsEvent::PeersInfo(ret) => {
+                let peerbook = &self.swarm.behaviour().peerbook;
+                let peers = peerbook
+                    .peers_info()
+                    .map(|info| {
+                        let mut info = PeerInfo::from(info);
+                        info.rtt = peerbook.get_peer_latest_rtt(info.peer_id);
+                        info.connection_addrs =
+                            peerbook.peer_connections(info.peer_id).unwrap_or_default();
+                        info.meta = peerbook
+                            .all_peer_meta(&info.peer_id)
+                            .cloned()
+                            .unwrap_or_default();
+                        info.score = peerbook.peer_score(&info.peer_id);
+                        info
+                    })
+                    .collect();
+                let _ = ret.send(Ok(peers));
+            }
+            IpfsEvent::ObservedAddresses(ret) => {
+                let addrs = self.swarm.behaviour().peerbook.observed_addrs();
+                let _ = ret.send(Ok(addrs));
             }
-            IpfsEvent::Disconnect(peer, ret) => {
-                if self.swarm.disconnect_peer_id(peer).is_err() {
-                    _ = ret.send(Err(anyhow::anyhow!("Peer is not connected")));
+            IpfsEvent::Ping(peer_id, count, ret) => {
+                if self.swarm.behaviour().ping.as_ref().is_none() {
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Ping).into()));
                     return;
                 }
 
-                self.pending_disconnection
-                    .entry(peer)
-                    .or_default()
-                    .push(ret);
+                if !self.swarm.is_connected(&peer_id) {
+                    let _ = ret.send(Err(anyhow!("peer is not connected")));
+                    return;
+                }
+
+                let (tx, rx) = unbounded();
+                if count != Some(0) {
+                    self.ping_subscribers
+                        .entry(peer_id)
+                        .or_default()
+                        .push((tx, count));
+                    // the ping behaviour only pings every interval of its config
+                    if let Some(pinger) = self.swarm.behaviour_mut().pinger.as_mut() {
+                        pinger.ping(peer_id);
+                    }
+                }
+                let _ = ret.send(Ok(rx));
             }
-            IpfsEvent::Ban(peer, ret) => {
-                self.swarm.behaviour_mut().block_list.block_peer(peer);
+            IpfsEvent::ProtectPeer(peer, tag, ret) => {
+                self.swarm.behaviour_mut().peerbook.protect_peer(peer, &tag);
                 let _ = ret.send(Ok(()));
             }
-            IpfsEvent::Unban(peer, ret) => {
-                self.swarm.behaviour_mut().block_list.unblock_peer(peer);
+            IpfsEvent::UnprotectPeer(peer, tag, ret) => {
+                let removed = self
+                    .swarm
+                    .behaviour_mut()
+                    .peerbook
+                    .unprotect_peer(peer, &tag);
+                let _ = ret.send(Ok(removed));
+            }
+            IpfsEvent::PeerSetMeta(peer_id, key, value, ret) => {
+                let result = self
+                    .swarm
+                    .behaviour_mut()
+                    .peerbook
+                    .set_peer_meta(peer_id, key, value)
+                    .map_err(anyhow::Error::from);
+                let _ = ret.send(result);
+            }
+            IpfsEvent::PeerGetMeta(peer_id, key, ret) => {
+                let value = self
+                    .swarm
+                    .behaviour()
+                    .peerbook
+                    .peer_meta(&peer_id, &key)
+                    .map(<[u8]>::to_vec);
+                let _ = ret.send(Ok(value));
+            }
+            IpfsEvent::PeerDelMeta(peer_id, key, ret) => {
+                let value = self
+                    .swarm
+                    .behaviour_mut()
+                    .peerbook
+                    .remove_peer_meta(&peer_id, &key);
+                let _ = ret.send(Ok(value));
+            }
+            IpfsEvent::PeerProtectionStatus(ret) => {
+                let status = self.swarm.behaviour().peerbook.protection_status();
+                let _ = ret.send(Ok(status));
+            }
+            IpfsEvent::PruneConnections(ret) => {
+                self.prune_connections();
                 let _ = ret.send(Ok(()));
             }
+            IpfsEvent::RemoveWhitelistedPeer(peer, ret) => {
+                let removed = self
+                    .swarm
+                    .behaviour_mut()
+                    .peerbook
+                    .remove_whitelisted_peer(&peer);
+                let _ = ret.send(Ok(removed));
+            }
             IpfsEvent::PubsubSubscribe(topic, ret) => {
                 let Some(pubsub) = self.swarm.behaviour_mut().pubsub.as_mut() else {
-                    let _ = ret.send(Err(anyhow!("pubsub protocol is disabled")));
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Pubsub).into()));
                     return;
                 };
 
@@ -1140,7 +3554,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::PubsubUnsubscribe(topic, ret) => {
                 let Some(pubsub) = self.swarm.behaviour_mut().pubsub.as_mut() else {
-                    let _ = ret.send(Err(anyhow!("pubsub protocol is disabled")));
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Pubsub).into()));
                     return;
                 };
 
@@ -1148,7 +3562,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::PubsubPublish(topic, data, ret) => {
                 let Some(pubsub) = self.swarm.behaviour_mut().pubsub.as_mut() else {
-                    let _ = ret.send(Err(anyhow!("pubsub protocol is disabled")));
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Pubsub).into()));
                     return;
                 };
 
@@ -1156,7 +3570,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::PubsubPeers(Some(topic), ret) => {
                 let Some(pubsub) = self.swarm.behaviour_mut().pubsub.as_mut() else {
-                    let _ = ret.send(Err(anyhow!("pubsub protocol is disabled")));
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Pubsub).into()));
                     return;
                 };
 
@@ -1164,7 +3578,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::PubsubPeers(None, ret) => {
                 let Some(pubsub) = self.swarm.behaviour_mut().pubsub.as_mut() else {
-                    let _ = ret.send(Err(anyhow!("pubsub protocol is disabled")));
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Pubsub).into()));
                     return;
                 };
 
@@ -1172,12 +3586,32 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::PubsubSubscribed(ret) => {
                 let Some(pubsub) = self.swarm.behaviour_mut().pubsub.as_mut() else {
-                    let _ = ret.send(Err(anyhow!("pubsub protocol is disabled")));
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Pubsub).into()));
                     return;
                 };
 
                 let _ = ret.send(Ok(pubsub.subscribed_topics()));
             }
+            IpfsEvent::PubsubMesh(topic, ret) => {
+                let Some(pubsub) = self.swarm.behaviour_mut().pubsub.as_mut() else {
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Pubsub).into()));
+                    return;
+                };
+
+                let _ = ret.send(Ok(pubsub.mesh_info(&topic)));
+            }
+            IpfsEvent::PubsubMeshEvents(ret) => {
+                let Some(pubsub) = self.swarm.behaviour_mut().pubsub.as_mut() else {
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Pubsub).into()));
+                    return;
+                };
+
+                let _ = ret.send(Ok(pubsub.mesh_events()));
+            }
+            IpfsEvent::AnnouncedProviders(cid, ret) => {
+                let (providers, _) = self.announced_providers(&cid);
+                let _ = ret.send(Ok(providers.into_iter().collect()));
+            }
             // IpfsEvent::WantList(peer, ret) => {
             //     let list = if let Some(peer) = peer {
             //         self.swarm
@@ -1201,14 +3635,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 self.pubsub_event_stream.push(tx);
                 let _ = ret.send(rx);
             }
-            IpfsEvent::AddListeningAddress(addr, ret) => match self.swarm.listen_on(addr) {
-                Ok(id) => {
-                    self.pending_add_listener.insert(id, ret);
-                }
-                Err(e) => {
-                    let _ = ret.send(Err(anyhow::anyhow!(e)));
-                }
-            },
+            IpfsEvent::AddListeningAddress(addr, ret) => self.listen_on(addr, ret),
             IpfsEvent::RemoveListeningAddress(addr, ret) => {
                 let Some(listener_id) = self.listening_addresses.iter().find_map(|(id, list)| {
                     if list.contains(&addr) {
@@ -1217,6 +3644,21 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
 
                     None
                 }) else {
+                    // no longer listened on, but still to be listened on again
+                    if self.relisten_attempts.remove(&addr).is_some() {
+                        let relisteners = self
+                            .relisteners
+                            .iter()
+                            .filter(|(_, requested)| **requested == addr)
+                            .map(|(id, _)| *id)
+                            .collect::<Vec<_>>();
+                        for listener_id in relisteners {
+                            self.relisteners.remove(&listener_id);
+                            self.swarm.remove_listener(listener_id);
+                        }
+                        let _ = ret.send(Ok(()));
+                        return;
+                    }
                     let _ = ret.send(Err(format_err!(
                         "Address was not listened to before: {}",
                         addr
@@ -1238,7 +3680,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::Bootstrap(ret) => {
                 let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
-                    let _ = ret.send(Err(anyhow!("kad protocol is disabled")));
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
                     return;
                 };
 
@@ -1246,6 +3688,33 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     Ok(id) => {
                         let (tx, rx) = oneshot::channel();
                         self.kad_subscriptions.insert(id, tx);
+                        self.bootstrap_query.get_or_insert(id);
+                        let key = self.swarm.local_peer_id().to_bytes();
+                        self.trace_kad_query(id, "bootstrap", &key);
+                        Ok(rx)
+                    }
+                    Err(e) => {
+                        error!("kad: can't bootstrap the node: {:?}", e);
+                        Err(anyhow!("kad: can't bootstrap the node: {:?}", e))
+                    }
+                };
+                let _ = ret.send(future);
+            }
+            IpfsEvent::BootstrapReport(ret) => {
+                let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
+                    return;
+                };
+
+                let future = match kad.bootstrap() {
+                    Ok(id) => {
+                        let (tx, rx) = oneshot::channel();
+                        let connected = self.swarm.connected_peers().copied().collect();
+                        self.bootstrap_reports
+                            .insert(id, (Instant::now(), connected, tx));
+                        self.bootstrap_query.get_or_insert(id);
+                        let key = self.swarm.local_peer_id().to_bytes();
+                        self.trace_kad_query(id, "bootstrap", &key);
                         Ok(rx)
                     }
                     Err(e) => {
@@ -1279,7 +3748,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::GetClosestPeers(peer_id, ret) => {
                 let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
-                    let _ = ret.send(Err(anyhow!("kad protocol is disabled")));
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
                     return;
                 };
 
@@ -1288,6 +3757,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 let (tx, rx) = oneshot::channel();
 
                 self.kad_subscriptions.insert(id, tx);
+                self.trace_kad_query(id, "get_closest_peers", &peer_id.to_bytes());
                 let _ = ret.send(Ok(rx));
             }
             IpfsEvent::WantList(peer, ret) => {
@@ -1331,6 +3801,41 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     let _ = ret.send(Ok(futures::future::ready(list).boxed()));
                 }
             }
+            IpfsEvent::BitswapLedger(peer_id, ret) => {
+                #[cfg(any(feature = "libp2p_bitswap", feature = "beetle_bitswap"))]
+                {
+                    _ = peer_id;
+                    let _ = ret.send(Err(anyhow!(
+                        "ledgers are only kept by the default bitswap implementation"
+                    )));
+                }
+                #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+                {
+                    let Some(bitswap) = self.swarm.behaviour().bitswap.as_ref() else {
+                        let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Bitswap).into()));
+                        return;
+                    };
+                    let _ = ret.send(Ok(bitswap.peer_ledger(&peer_id)));
+                }
+            }
+            IpfsEvent::BitswapLedgerReset(peer_id, ret) => {
+                #[cfg(any(feature = "libp2p_bitswap", feature = "beetle_bitswap"))]
+                {
+                    _ = peer_id;
+                    let _ = ret.send(Err(anyhow!(
+                        "ledgers are only kept by the default bitswap implementation"
+                    )));
+                }
+                #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+                {
+                    let Some(bitswap) = self.swarm.behaviour_mut().bitswap.as_mut() else {
+                        let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Bitswap).into()));
+                        return;
+                    };
+                    bitswap.reset_peer_ledger(&peer_id);
+                    let _ = ret.send(Ok(()));
+                }
+            }
             IpfsEvent::GetBitswapPeers(ret) => {
                 #[cfg(feature = "beetle_bitswap")]
                 {
@@ -1357,11 +3862,11 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
 
                 match locally_known {
                     Some(info) => {
-                        let _ = tx.send(Ok(info.clone()));
+                        let _ = tx.send(Ok(PeerInfo::from(info)));
                     }
                     None => {
                         let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
-                            let _ = ret.send(Err(anyhow!("kad protocol is disabled")));
+                            let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
                             return;
                         };
 
@@ -1402,7 +3907,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     Either::Left(locally_known_addrs)
                 } else {
                     let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
-                        let _ = ret.send(Err(anyhow!("kad protocol is disabled")));
+                        let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
                         return;
                     };
 
@@ -1411,22 +3916,23 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
 
                         let (tx, rx) = oneshot::channel();
                         self.kad_subscriptions.insert(id, tx);
+                        self.trace_kad_query(id, "get_closest_peers", &peer_id.to_bytes());
 
                         rx
                     })
                 };
                 let _ = ret.send(Ok(addrs));
             }
-            IpfsEvent::GetProviders(cid, ret) => {
-                let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
-                    let _ = ret.send(Err(anyhow!("kad protocol is disabled")));
+            IpfsEvent::GetProviders(cid, timeout, ret) => {
+                let Some((id, found)) = self.get_providers(Key::from(cid.hash().to_bytes())) else {
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
                     return;
                 };
 
-                let key = Key::from(cid.hash().to_bytes());
-                let id = kad.get_providers(key);
-
                 let (tx, mut rx) = futures::channel::mpsc::unbounded();
+                for provider in found {
+                    let _ = tx.unbounded_send(provider);
+                }
                 let stream = async_stream::stream! {
                     let mut current_providers: HashSet<PeerId> = Default::default();
                     while let Some(provider) = rx.next().await {
@@ -1435,22 +3941,31 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                         }
                     }
                 };
-                self.provider_stream.insert(id, tx);
+                if let Some(timeout) = timeout {
+                    let tx = tx.clone();
+                    self.provider_deadlines.push(
+                        futures_timer::Delay::new(timeout)
+                            .map(move |_| (id, tx))
+                            .boxed(),
+                    );
+                }
+                self.provider_stream.entry(id).or_default().push(tx);
 
                 let _ = ret.send(Ok(Some(stream.boxed())));
             }
             IpfsEvent::Provide(cid, ret) => {
                 let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
-                    let _ = ret.send(Err(anyhow!("kad protocol is disabled")));
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
                     return;
                 };
 
                 let key = Key::from(cid.hash().to_bytes());
 
-                let future = match kad.start_providing(key) {
+                let future = match kad.start_providing(key.clone()) {
                     Ok(id) => {
                         let (tx, rx) = oneshot::channel();
                         self.kad_subscriptions.insert(id, tx);
+                        self.trace_kad_query(id, "start_providing", key.as_ref());
                         Ok(rx)
                     }
                     Err(e) => {
@@ -1460,9 +3975,111 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 };
                 let _ = ret.send(future);
             }
+            IpfsEvent::ProvideLazy(cid, ret) => {
+                let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
+                    return;
+                };
+
+                let key = Key::from(cid.hash().to_bytes());
+
+                // no subscription, the outcome of the query is only logged
+                let result = match kad.start_providing(key.clone()) {
+                    Ok(id) => {
+                        self.trace_kad_query(id, "start_providing", key.as_ref());
+                        Ok(())
+                    }
+                    Err(e) => {
+                        error!("kad: can't provide a key: {:?}", e);
+                        Err(anyhow!("kad: can't provide the key: {:?}", e))
+                    }
+                };
+                let _ = ret.send(result);
+            }
+            IpfsEvent::Provided(ret) => {
+                let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
+                    return;
+                };
+                let provided = kad
+                    .store_mut()
+                    .provided()
+                    .filter_map(|record| Multihash::from_bytes(record.key.as_ref()).ok())
+                    .map(|hash| Cid::new_v1(IpldCodec::Raw.into(), hash))
+                    .collect();
+                let _ = ret.send(Ok(provided));
+            }
+            IpfsEvent::DhtStats(ret) => {
+                if self.swarm.behaviour().kademlia.as_ref().is_none() {
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
+                    return;
+                }
+                let _ = ret.send(Ok(self.dht_stats));
+            }
+            IpfsEvent::DhtStore(ret) => {
+                let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
+                    return;
+                };
+                let _ = ret.send(Ok(kad.store_mut().contents()));
+            }
+            IpfsEvent::DhtEvents(ret) => {
+                if self.swarm.behaviour().kademlia.as_ref().is_none() {
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
+                    return;
+                }
+                let tx = self
+                    .dht_events
+                    .get_or_insert_with(|| tokio::sync::broadcast::channel(DHT_EVENTS_CAPACITY).0);
+                let _ = ret.send(Ok(tx.subscribe()));
+            }
+            IpfsEvent::RecentDisconnections(ret) => {
+                let _ = ret.send(Ok(self.disconnections.iter().cloned().collect()));
+            }
+            IpfsEvent::PendingDials(ret) => {
+                let mut dials = self.dials.values().cloned().collect::<Vec<_>>();
+                dials.sort_by_key(|(_, _, started)| *started);
+                let _ = ret.send(Ok(dials));
+            }
+            IpfsEvent::AbortDial(peer_id, ret) => {
+                let pending = self
+                    .dials
+                    .values()
+                    .any(|(peer, _, _)| *peer == Some(peer_id));
+                // aborting the dials would close the established connections too
+                let abort = pending && !self.swarm.is_connected(&peer_id);
+                if abort {
+                    let _ = self.swarm.disconnect_peer_id(peer_id);
+                }
+                let _ = ret.send(Ok(abort));
+            }
+            IpfsEvent::WaitReady(criteria, ret) => {
+                if criteria.dht_routing_peers > 0 && !self.swarm.behaviour().kademlia.is_enabled() {
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
+                    return;
+                }
+                self.ready_waiters.push((criteria, ret));
+                self.resolve_ready_waiters();
+            }
+            IpfsEvent::EnabledComponents(ret) => {
+                let behaviour = self.swarm.behaviour();
+                let _ = ret.send(Ok(Components {
+                    kad: behaviour.kademlia.is_enabled(),
+                    mdns: behaviour.mdns.is_enabled(),
+                    bitswap: behaviour.bitswap.is_enabled(),
+                    autonat: behaviour.autonat.is_enabled(),
+                    relay_client: behaviour.relay_client.is_enabled(),
+                    relay_server: behaviour.relay.is_enabled(),
+                    pubsub: behaviour.pubsub.is_enabled(),
+                    upnp: behaviour.upnp.is_enabled(),
+                }));
+            }
+            IpfsEvent::ReadyState(ret) => {
+                let _ = ret.send(Ok(self.ready_state()));
+            }
             IpfsEvent::DhtMode(mode, ret) => {
                 let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
-                    let _ = ret.send(Err(anyhow!("kad protocol is disabled")));
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
                     return;
                 };
 
@@ -1472,11 +4089,12 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::DhtGet(key, ret) => {
                 let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
-                    let _ = ret.send(Err(anyhow!("kad protocol is disabled")));
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
                     return;
                 };
 
-                let id = kad.get_record(key);
+                let id = kad.get_record(key.clone());
+                self.trace_kad_query(id, "get_record", key.as_ref());
 
                 let (tx, mut rx) = futures::channel::mpsc::unbounded();
                 let stream = async_stream::stream! {
@@ -1490,12 +4108,12 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::DhtPut(key, value, quorum, ret) => {
                 let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
-                    let _ = ret.send(Err(anyhow!("kad protocol is disabled")));
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
                     return;
                 };
 
                 let record = Record {
-                    key,
+                    key: key.clone(),
                     value,
                     publisher: None,
                     expires: None,
@@ -1505,6 +4123,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     Ok(id) => {
                         let (tx, rx) = oneshot::channel();
                         self.kad_subscriptions.insert(id, tx);
+                        self.trace_kad_query(id, "put_record", key.as_ref());
                         Ok(rx)
                     }
                     Err(e) => {
@@ -1521,7 +4140,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::AddBootstrapper(mut addr, ret) => {
                 if !self.swarm.behaviour().kademlia.is_enabled() {
-                    let _ = ret.send(Err(anyhow!("kad protocol is disabled")));
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
                     return;
                 };
 
@@ -1538,7 +4157,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::RemoveBootstrapper(mut addr, ret) => {
                 let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
-                    let _ = ret.send(Err(anyhow!("kad protocol is disabled")));
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
                     return;
                 };
 
@@ -1560,7 +4179,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::ClearBootstrappers(ret) => {
                 let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
-                    let _ = ret.send(Err(anyhow!("kad protocol is disabled")));
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
                     return;
                 };
 
@@ -1586,7 +4205,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::DefaultBootstrap(ret) => {
                 if !self.swarm.behaviour().kademlia.is_enabled() {
-                    let _ = ret.send(Err(anyhow!("kad protocol is disabled")));
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
                     return;
                 };
 
@@ -1611,9 +4230,24 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
 
                 let _ = ret.send(Ok(rets));
             }
+            IpfsEvent::BootstrapPolicy(ret) => {
+                if !self.swarm.behaviour().kademlia.is_enabled() {
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
+                    return;
+                };
+                let _ = ret.send(Ok(self.bootstrap_policy));
+            }
+            IpfsEvent::SetBootstrapPolicy(policy, ret) => {
+                if !self.swarm.behaviour().kademlia.is_enabled() {
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Kademlia).into()));
+                    return;
+                };
+                self.set_bootstrap_policy(policy);
+                let _ = ret.send(Ok(()));
+            }
             IpfsEvent::AddRelay(peer_id, addr, tx) => {
                 let Some(relay) = self.swarm.behaviour_mut().relay_manager.as_mut() else {
-                    let _ = tx.send(Err(anyhow::anyhow!("Relay is not enabled")));
+                    let _ = tx.send(Err(ProtocolDisabled(ProtocolKind::Relay).into()));
                     return;
                 };
 
@@ -1623,7 +4257,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::RemoveRelay(peer_id, addr, tx) => {
                 let Some(relay) = self.swarm.behaviour_mut().relay_manager.as_mut() else {
-                    let _ = tx.send(Err(anyhow::anyhow!("Relay is not enabled")));
+                    let _ = tx.send(Err(ProtocolDisabled(ProtocolKind::Relay).into()));
                     return;
                 };
 
@@ -1633,7 +4267,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::EnableRelay(Some(peer_id), tx) => {
                 let Some(relay) = self.swarm.behaviour_mut().relay_manager.as_mut() else {
-                    let _ = tx.send(Err(anyhow::anyhow!("Relay is not enabled")));
+                    let _ = tx.send(Err(ProtocolDisabled(ProtocolKind::Relay).into()));
                     return;
                 };
 
@@ -1643,7 +4277,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::EnableRelay(None, tx) => {
                 let Some(relay) = self.swarm.behaviour_mut().relay_manager.as_mut() else {
-                    let _ = tx.send(Err(anyhow::anyhow!("Relay is not enabled")));
+                    let _ = tx.send(Err(ProtocolDisabled(ProtocolKind::Relay).into()));
                     return;
                 };
 
@@ -1658,7 +4292,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::DisableRelay(peer_id, tx) => {
                 let Some(relay) = self.swarm.behaviour_mut().relay_manager.as_mut() else {
-                    let _ = tx.send(Err(anyhow::anyhow!("Relay is not enabled")));
+                    let _ = tx.send(Err(ProtocolDisabled(ProtocolKind::Relay).into()));
                     return;
                 };
                 relay.disable_relay(peer_id);
@@ -1667,7 +4301,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::ListRelays(tx) => {
                 let Some(relay) = self.swarm.behaviour().relay_manager.as_ref() else {
-                    let _ = tx.send(Err(anyhow::anyhow!("Relay is not enabled")));
+                    let _ = tx.send(Err(ProtocolDisabled(ProtocolKind::Relay).into()));
                     return;
                 };
 
@@ -1680,7 +4314,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::ListActiveRelays(tx) => {
                 let Some(relay) = self.swarm.behaviour().relay_manager.as_ref() else {
-                    let _ = tx.send(Err(anyhow::anyhow!("Relay is not enabled")));
+                    let _ = tx.send(Err(ProtocolDisabled(ProtocolKind::Relay).into()));
                     return;
                 };
 
@@ -1688,9 +4322,96 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
 
                 let _ = tx.send(Ok(list));
             }
+            IpfsEvent::MdnsPeers(tx) => {
+                if self.swarm.behaviour().mdns.as_ref().is_none() {
+                    let _ = tx.send(Err(ProtocolDisabled(ProtocolKind::Mdns).into()));
+                    return;
+                }
+                let peers = self
+                    .mdns_peers
+                    .iter()
+                    .map(|(peer_id, addrs)| (*peer_id, addrs.clone()))
+                    .collect();
+                let _ = tx.send(Ok(peers));
+            }
+            IpfsEvent::SetMdnsEnabled(enabled, tx) => {
+                match (enabled, self.swarm.behaviour().mdns.is_enabled()) {
+                    (true, false) => {
+                        let peer_id = *self.swarm.local_peer_id();
+                        match libp2p::mdns::tokio::Behaviour::new(Default::default(), peer_id) {
+                            Ok(behaviour) => {
+                                self.swarm.behaviour_mut().mdns = Some(behaviour).into();
+                            }
+                            Err(e) => {
+                                let _ = tx.send(Err(anyhow!("failed to start mdns: {e}")));
+                                return;
+                            }
+                        }
+                    }
+                    (false, true) => {
+                        self.swarm.behaviour_mut().mdns = None.into();
+                        self.mdns_peers.clear();
+                    }
+                    _ => {}
+                }
+                let _ = tx.send(Ok(()));
+            }
+            IpfsEvent::NatStatus(tx) => {
+                let _ = tx.send(
+                    self.autonat_status()
+                        .ok_or_else(|| ProtocolDisabled(ProtocolKind::Autonat).into()),
+                );
+            }
+            IpfsEvent::AddAutonatServer(peer_id, addr, tx) => {
+                let Some(autonat) = self.swarm.behaviour_mut().autonat.as_mut() else {
+                    let _ = tx.send(Err(ProtocolDisabled(ProtocolKind::Autonat).into()));
+                    return;
+                };
+                autonat.add_server(peer_id, addr);
+                let _ = tx.send(Ok(()));
+            }
+            IpfsEvent::RelayServerStats(tx) => {
+                if self.swarm.behaviour().relay.as_ref().is_none() {
+                    let _ = tx.send(Err(ProtocolDisabled(ProtocolKind::RelayServer).into()));
+                    return;
+                }
+
+                let _ = tx.send(Ok(RelayServerStats {
+                    active_reservations: self.served_reservations.len(),
+                    active_circuits: self.relayed_circuits.values().sum(),
+                    bytes_relayed: self.bandwidth.relayed(),
+                    denied_reservations: self.denied_reservations,
+                    denied_circuits: self.denied_circuits,
+                }));
+            }
+            IpfsEvent::RelayStatus(tx) => {
+                if self.swarm.behaviour().relay_manager.as_ref().is_none() {
+                    let _ = tx.send(Err(ProtocolDisabled(ProtocolKind::Relay).into()));
+                    return;
+                }
+
+                let reservations = self
+                    .relay_reservations
+                    .iter()
+                    .map(|(relay, addrs)| RelayReservation {
+                        relay: *relay,
+                        addrs: addrs.clone(),
+                    })
+                    .collect();
+
+                let pending = self
+                    .relay_candidate
+                    .filter(|peer_id| !self.relay_reservations.contains_key(peer_id));
+
+                let _ = tx.send(Ok(RelayStatus {
+                    private: self.nat_private,
+                    reservations,
+                    pending,
+                }));
+            }
             IpfsEvent::RegisterRendezvousNamespace(ns, peer_id, ttl, res) => {
                 let Some(rz) = self.swarm.behaviour_mut().rendezvous_client.as_mut() else {
-                    let _ = res.send(Err(anyhow::anyhow!("Rendezvous client is not enabled")));
+                    let _ = res.send(Err(ProtocolDisabled(ProtocolKind::Rendezvous).into()));
                     return;
                 };
 
@@ -1698,6 +4419,8 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     let _ = res.send(Err(anyhow::Error::from(e)));
                     return;
                 }
+                self.rzv_registrations
+                    .insert((peer_id, ns.clone()), (ttl, None));
                 self.rzv_register_pending
                     .entry((peer_id, ns))
                     .or_default()
@@ -1705,17 +4428,20 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             IpfsEvent::UnregisterRendezvousNamespace(ns, peer_id, res) => {
                 let Some(rz) = self.swarm.behaviour_mut().rendezvous_client.as_mut() else {
-                    let _ = res.send(Err(anyhow::anyhow!("Rendezvous client is not enabled")));
+                    let _ = res.send(Err(ProtocolDisabled(ProtocolKind::Rendezvous).into()));
                     return;
                 };
 
                 rz.unregister(ns.clone(), peer_id);
+                self.rzv_registrations.remove(&(peer_id, ns.clone()));
+                // no longer registered automatically on the next connection to the server
+                self.rzv_namespaces.retain(|namespace| namespace != &ns);
 
                 let _ = res.send(Ok(()));
             }
             IpfsEvent::RendezvousNamespaceDiscovery(ns, use_cookie, ttl, peer_id, res) => {
                 let Some(rz) = self.swarm.behaviour_mut().rendezvous_client.as_mut() else {
-                    let _ = res.send(Err(anyhow::anyhow!("Rendezvous client is not enabled")));
+                    let _ = res.send(Err(ProtocolDisabled(ProtocolKind::Rendezvous).into()));
                     return;
                 };
 
@@ -1736,8 +4462,102 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     }
                 }
             }
+            IpfsEvent::RendezvousRegister(ns, ret) => {
+                if !self.swarm.behaviour().rendezvous_client.is_enabled() {
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Rendezvous).into()));
+                    return;
+                }
+
+                if !self.rzv_namespaces.contains(&ns) {
+                    self.rzv_namespaces.push(ns.clone());
+                }
+
+                let mut pending = Vec::with_capacity(self.rzv_servers.len());
+                for server in self.rzv_servers.clone() {
+                    let (tx, rx) = oneshot::channel();
+                    match self.register_rendezvous(server, ns.clone()) {
+                        Ok(()) => self
+                            .rzv_register_pending
+                            .entry((server, ns.clone()))
+                            .or_default()
+                            .push(tx),
+                        Err(e) => {
+                            let _ = tx.send(Err(e));
+                        }
+                    }
+                    pending.push(rx);
+                }
+                let _ = ret.send(Ok(pending));
+            }
+            IpfsEvent::RendezvousDiscover(ns, ret) => {
+                let Some(rz) = self.swarm.behaviour_mut().rendezvous_client.as_mut() else {
+                    let _ = ret.send(Err(ProtocolDisabled(ProtocolKind::Rendezvous).into()));
+                    return;
+                };
+
+                let mut pending = Vec::with_capacity(self.rzv_servers.len());
+                for server in &self.rzv_servers {
+                    let (tx, rx) = oneshot::channel();
+                    rz.discover(Some(ns.clone()), None, None, *server);
+                    self.rzv_discover_pending
+                        .entry((*server, ns.clone()))
+                        .or_default()
+                        .push(tx);
+                    pending.push(rx);
+                }
+                let _ = ret.send(Ok(pending));
+            }
+            IpfsEvent::AddSwarmObserver(observer, ret) => {
+                match observer.downcast::<TSwarmObserverFn<C>>() {
+                    Ok(observer) => {
+                        self.swarm_observers.push(*observer);
+                        let _ = ret.send(Ok(()));
+                    }
+                    Err(_) => {
+                        let _ = ret.send(Err(anyhow!(
+                            "observer does not match the custom behaviour of the node"
+                        )));
+                    }
+                }
+            }
+            IpfsEvent::CustomEvents(tx, ret) => {
+                match tx.downcast::<UnboundedSender<C::ToSwarm>>() {
+                    Ok(tx) => {
+                        self.custom_events = Some(*tx);
+                        let _ = ret.send(Ok(()));
+                    }
+                    Err(_) => {
+                        let _ = ret.send(Err(anyhow!(
+                            "events do not match the custom behaviour of the node"
+                        )));
+                    }
+                }
+            }
+            IpfsEvent::WithCustomBehaviour(func, ret) => {
+                let Some(behaviour) = self.swarm.behaviour_mut().custom.as_mut() else {
+                    let _ = ret.send(Err(anyhow!("Custom behaviour is not enabled")));
+                    return;
+                };
+
+                match func.downcast::<TCustomBehaviourFn<C>>() {
+                    Ok(func) => {
+                        func(behaviour);
+                        let _ = ret.send(Ok(()));
+                    }
+                    Err(_) => {
+                        let _ = ret.send(Err(anyhow!(
+                            "closure does not match the custom behaviour of the node"
+                        )));
+                    }
+                }
+            }
+            // the teardown is awaited in `IpfsTask::run`, which handles these first
             IpfsEvent::Exit => {
-                // FIXME: we could do a proper teardown
+                self.save_addressbook();
+            }
+            IpfsEvent::Shutdown(_, ret) => {
+                self.save_addressbook();
+                let _ = ret.send(Ok(()));
             }
         }
     }
@@ -1745,7 +4565,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
     #[cfg(feature = "beetle_bitswap")]
     fn handle_repo_event(&mut self, event: RepoEvent) {
         match event {
-            RepoEvent::WantBlock(session, mut cids, peers) => {
+            RepoEvent::WantBlock(session, mut cids, peers, _) => {
                 if let Some(bitswap) = self.swarm.behaviour().bitswap.as_ref() {
                     let client = bitswap.client().clone();
                     let repo = self.repo.clone();
@@ -1754,7 +4574,23 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     let ctx = session.unwrap_or(0);
                     let entry = self.bitswap_sessions.entry(ctx).or_default();
 
+                    use tracing_futures::Instrument;
+                    let span = debug_span!(
+                        "bitswap.fetch",
+                        session = ctx,
+                        blocks = cids.len(),
+                        outcome = Empty,
+                        elapsed_ms = Empty
+                    );
+
                     let worker = tokio::task::spawn(async move {
+                        let started = Instant::now();
+                        let close = |outcome: &'static str| {
+                            let span = Span::current();
+                            span.record("outcome", outcome);
+                            span.record("elapsed_ms", started.elapsed().as_millis() as u64);
+                        };
+
                         let session: beetle_bitswap_next::session::Session =
                             client.get_or_create_session(ctx).await;
                         for cid in &cids {
@@ -1768,6 +4604,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                                 Ok(bs) => bs,
                                 Err(e) => {
                                     warn!("Unable to create a block stream for {cids:?}: {e}. Dropping task");
+                                    close("failed");
                                     return;
                                 }
                             };
@@ -1797,6 +4634,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
 
                                     if cids.is_empty() {
                                         tracing::info!("Resolved all blocks in session {ctx}. Terminating task.");
+                                        close("retrieved");
                                         drop(_guard);
                                         break;
                                     }
@@ -1805,17 +4643,20 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                                 _ = &mut closer_r => {
                                     // Explicit sesssion stop.
                                     debug!("session {}: stopped: closed", ctx);
+                                    close("cancelled");
                                     drop(_guard);
                                     break;
                                 }
                             }
                         }
-                    });
+                    }
+                    .instrument(span));
                     entry.push((closer_s, worker));
                 }
             }
-            RepoEvent::UnwantBlock(_cid) => {}
-            RepoEvent::NewBlock(block) => {
+            RepoEvent::UnwantBlock(_, _cid) => {}
+            RepoEvent::NewBlock(block, ret) => {
+                let cid = *block.cid();
                 if let Some(bitswap) = self.swarm.behaviour().bitswap.as_ref() {
                     let client = bitswap.client().clone();
                     let server = bitswap.server().cloned();
@@ -1834,8 +4675,13 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                         }
                     });
                 }
-                // let _ = ret.send(Err(anyhow!("not actively providing blocks yet")));
+                self.provide_new_block(&cid, ret);
             }
+            RepoEvent::NewPin(cid, indirect) => {
+                self.provide_new_pin(&cid, &indirect);
+                self.announce_content(&cid);
+            }
+            RepoEvent::ProvidePin(cid) => self.provide_pin(&cid),
             RepoEvent::RemovedBlock(cid) => self.swarm.behaviour_mut().stop_providing_block(&cid),
         }
     }
@@ -1843,7 +4689,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
     #[cfg(feature = "libp2p_bitswap")]
     fn handle_repo_event(&mut self, event: RepoEvent) {
         match event {
-            RepoEvent::WantBlock(_, cids, peers) => {
+            RepoEvent::WantBlock(_, cids, peers, _) => {
                 let Some(bs) = self.swarm.behaviour_mut().bitswap.as_mut() else {
                     return;
                 };
@@ -1853,34 +4699,132 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     self.bitswap_sessions.insert(id, cid);
                 }
             }
-            RepoEvent::UnwantBlock(_) => {}
-            RepoEvent::NewBlock(_) => {}
-            RepoEvent::RemovedBlock(_) => {}
+            RepoEvent::UnwantBlock(_, _) => {}
+            RepoEvent::NewBlock(block, ret) => self.provide_new_block(block.cid(), ret),
+            RepoEvent::NewPin(cid, indirect) => {
+                self.provide_new_pin(&cid, &indirect);
+                self.announce_content(&cid);
+            }
+            RepoEvent::ProvidePin(cid) => self.provide_pin(&cid),
+            RepoEvent::RemovedBlock(cid) => self.swarm.behaviour_mut().stop_providing_block(&cid),
         }
     }
 
     #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
     fn handle_repo_event(&mut self, event: RepoEvent) {
         match event {
-            RepoEvent::WantBlock(_, cids, peers) => {
+            RepoEvent::WantBlock(session, cids, peers, priority) => {
                 let Some(bs) = self.swarm.behaviour_mut().bitswap.as_mut() else {
                     return;
                 };
-                bs.gets(cids, &peers);
+                for cid in &cids {
+                    bs.get_in_session(session.unwrap_or(0), cid, &peers, priority);
+                }
+                self.trace_bitswap_fetch(&cids, peers.len());
             }
-            RepoEvent::UnwantBlock(cid) => {
+            RepoEvent::UnwantBlock(session, cid) => {
                 let Some(bs) = self.swarm.behaviour_mut().bitswap.as_mut() else {
                     return;
                 };
-                bs.cancel(cid);
+                bs.cancel_in_session(session.unwrap_or(0), cid);
             }
-            RepoEvent::NewBlock(block) => {
-                let Some(bs) = self.swarm.behaviour_mut().bitswap.as_mut() else {
-                    return;
-                };
-                bs.notify_new_blocks([*block.cid()]);
+            RepoEvent::NewBlock(block, ret) => {
+                if let Some(bs) = self.swarm.behaviour_mut().bitswap.as_mut() {
+                    bs.notify_new_blocks([*block.cid()]);
+                }
+                self.close_bitswap_fetch(block.cid(), "stored");
+                self.provide_new_block(block.cid(), ret);
+            }
+            RepoEvent::NewPin(cid, indirect) => {
+                self.provide_new_pin(&cid, &indirect);
+                self.announce_content(&cid);
+            }
+            RepoEvent::ProvidePin(cid) => self.provide_pin(&cid),
+            RepoEvent::RemovedBlock(cid) => self.swarm.behaviour_mut().stop_providing_block(&cid),
+        }
+    }
+
+    /// Starts providing a block written to the repo when every block is provided, answering
+    /// `ret` with the outcome of the provider query once it completed.
+    fn provide_new_block(&mut self, cid: &Cid, ret: Option<Channel<()>>) {
+        let Some(ret) = ret else {
+            return;
+        };
+        if self.provider != RepoProvider::All {
+            let _ = ret.send(Ok(()));
+            return;
+        }
+        match self.start_providing(cid) {
+            Ok(id) => {
+                self.provided_blocks.insert(id, ret);
+            }
+            Err(e) => {
+                let _ = ret.send(Err(e));
             }
-            RepoEvent::RemovedBlock(_) => {}
         }
     }
+
+    /// Starts providing the root of a new pin when only pinned blocks or roots are provided, and
+    /// the blocks it pins indirectly when every pinned block is provided; with every block
+    /// provided, they already were when written.
+    fn provide_new_pin(&mut self, cid: &Cid, indirect: &[Cid]) {
+        let provided = match self.provider {
+            RepoProvider::Pinned => indirect,
+            RepoProvider::Roots => &[],
+            RepoProvider::None | RepoProvider::All => return,
+        };
+        for cid in std::iter::once(cid).chain(provided) {
+            if let Err(e) = self.start_providing(cid) {
+                debug!("failed to provide pinned {}: {}", cid, e);
+            }
+        }
+    }
+
+    /// Starts providing the root of a pin marked to be provided, unless the provider of the node
+    /// already did.
+    fn provide_pin(&mut self, cid: &Cid) {
+        if !matches!(self.provider, RepoProvider::None) {
+            return;
+        }
+        if let Err(e) = self.start_providing(cid) {
+            debug!("failed to provide pinned {}: {}", cid, e);
+        }
+    }
+
+    fn start_providing(&mut self, cid: &Cid) -> anyhow::Result<QueryId> {
+        let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
+            return Err(ProtocolDisabled(ProtocolKind::Kademlia).into());
+        };
+        let key = Key::from(cid.hash().to_bytes());
+        let id = kad
+            .start_providing(key.clone())
+            .map_err(|e| anyhow!("kad: can't provide the key: {:?}", e))?;
+        self.trace_kad_query(id, "start_providing", key.as_ref());
+        Ok(id)
+    }
+}
+
+/// Whether the listener failed with an error which may go away, such as the one of its interface
+/// going down, rather than one listening again would fail with as well.
+fn is_transient(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+
+    !matches!(
+        error.kind(),
+        ErrorKind::PermissionDenied | ErrorKind::InvalidInput | ErrorKind::Unsupported
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_transient;
+    use std::io::{Error, ErrorKind};
+
+    #[test]
+    fn only_transient_listener_errors_are_retried() {
+        assert!(is_transient(&Error::from(ErrorKind::AddrNotAvailable)));
+        assert!(is_transient(&Error::from(ErrorKind::ConnectionAborted)));
+        assert!(!is_transient(&Error::from(ErrorKind::PermissionDenied)));
+        assert!(!is_transient(&Error::from(ErrorKind::Unsupported)));
+    }
 }
@@ -0,0 +1,82 @@
+//! Helpers to test against nodes connected in memory rather than over the network, with the
+//! periodic work of their background task driven by hand.
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use parking_lot::Mutex;
+
+use crate::{Node, TTransportFn, UninitializedIpfsNoop};
+
+/// Transport connecting the nodes through `/memory` addresses, see
+/// [`UninitializedIpfs::with_custom_transport`](crate::UninitializedIpfs::with_custom_transport).
+pub fn memory_transport() -> TTransportFn {
+    Box::new(crate::p2p::transport::memory_transport)
+}
+
+/// Starts `n` nodes with the default behaviours over the memory transport, each connected to
+/// every other one.
+pub async fn memory_nodes(n: usize) -> Vec<Node> {
+    let mut nodes = Vec::with_capacity(n);
+
+    for i in 0..n {
+        let uninit = UninitializedIpfsNoop::new()
+            .with_default()
+            .with_custom_transport(memory_transport())
+            .set_span(trace_span!("ipfs", node = i));
+        let node = Node::with_builder(uninit, vec!["/memory/0".parse().unwrap()]).await;
+
+        for peer in &nodes {
+            node.connect(peer.addrs[0].clone())
+                .await
+                .expect("memory nodes to connect");
+        }
+        nodes.push(node);
+    }
+
+    nodes
+}
+
+/// Intervals of the background task which only tick when told to, see
+/// [`UninitializedIpfs::with_interval_factory`](crate::UninitializedIpfs::with_interval_factory).
+///
+/// The periodic work of the task, e.g. pruning the connections every minute, then happens
+/// exactly when the test calls [`ManualIntervals::tick`].
+#[derive(Clone, Default)]
+pub struct ManualIntervals {
+    intervals: Arc<Mutex<Vec<(Duration, UnboundedSender<()>)>>>,
+}
+
+impl ManualIntervals {
+    /// Returns the factory to give to the builder
+    pub fn factory(&self) -> impl Fn(Duration) -> BoxStream<'static, ()> + Sync + Send + 'static {
+        let intervals = self.intervals.clone();
+        move |period| {
+            let (tx, rx) = unbounded();
+            intervals.lock().push((period, tx));
+            rx.boxed()
+        }
+    }
+
+    /// Ticks the intervals of the given period once, returning how many were ticked
+    pub fn tick(&self, period: Duration) -> usize {
+        self.tick_matching(|p| p == period)
+    }
+
+    /// Ticks every interval once
+    pub fn tick_all(&self) -> usize {
+        self.tick_matching(|_| true)
+    }
+
+    fn tick_matching(&self, matches: impl Fn(Duration) -> bool) -> usize {
+        let mut intervals = self.intervals.lock();
+        // the intervals of the stopped nodes are dropped
+        intervals.retain(|(period, tx)| !matches(*period) || tx.unbounded_send(()).is_ok());
+        intervals
+            .iter()
+            .filter(|(period, _)| matches(*period))
+            .count()
+    }
+}
@@ -1,6 +1,9 @@
 use std::{path::PathBuf, task::Poll};
 
-use crate::{repo::Repo, Block};
+use crate::{
+    repo::{PutStats, Repo},
+    Block,
+};
 use bytes::Bytes;
 use either::Either;
 use futures::{
@@ -8,7 +11,8 @@ use futures::{
     stream::{BoxStream, FusedStream},
     FutureExt, Stream, StreamExt, TryFutureExt,
 };
-use rust_unixfs::file::adder::{Chunker, FileAdderBuilder};
+use libipld::{cid::Version, multihash::Code};
+use rust_unixfs::file::adder::{BalancedCollector, Chunker, FileAdderBuilder, TrickleCollector};
 use tokio_util::io::ReaderStream;
 use tracing::{Instrument, Span};
 
@@ -31,12 +35,84 @@ impl From<PathBuf> for AddOpt {
     }
 }
 
+/// Options controlling how the file content is chunked and laid out into blocks.
+#[derive(Debug, Clone, Copy)]
+pub struct AddOptions {
+    /// Chunking strategy, by default 256 KiB sized chunks.
+    pub chunker: Chunker,
+    /// Shape of the file tree.
+    pub layout: Layout,
+    /// Store the file content in raw blocks instead of wrapping it in UnixFs nodes.
+    pub raw_leaves: bool,
+    /// Cid version of the UnixFs nodes, either 0 or 1. Raw leaves always use version 1.
+    pub cid_version: u8,
+    /// Hash function for all blocks. Cid version 0 only supports sha2-256.
+    pub hash: Code,
+    /// Number of blocks written to the repo at once, by default 64.
+    pub batch_size: usize,
+}
+
+impl Default for AddOptions {
+    fn default() -> Self {
+        Self {
+            chunker: Chunker::default(),
+            layout: Layout::default(),
+            raw_leaves: false,
+            cid_version: 0,
+            hash: Code::Sha2_256,
+            batch_size: crate::repo::PUT_BATCH_SIZE,
+        }
+    }
+}
+
+impl AddOptions {
+    fn builder(&self) -> Result<FileAdderBuilder, anyhow::Error> {
+        match self.chunker {
+            Chunker::Size(0) => anyhow::bail!("chunk size must be non-zero"),
+            Chunker::Rabin { min, avg, max } if min < 64 || min > avg || avg > max => {
+                anyhow::bail!("rabin chunker requires 64 <= min <= avg <= max")
+            }
+            _ => {}
+        }
+
+        let version = match (self.cid_version, self.hash) {
+            (0, Code::Sha2_256) => Version::V0,
+            (0, _) => anyhow::bail!("cid version 0 only supports sha2-256"),
+            (1, _) => Version::V1,
+            (version, _) => anyhow::bail!("unsupported cid version {version}"),
+        };
+
+        let builder = FileAdderBuilder::default()
+            .with_chunker(self.chunker)
+            .with_raw_leaves(self.raw_leaves)
+            .with_cid_version(version)
+            .with_hash(self.hash);
+
+        Ok(match self.layout {
+            Layout::Balanced => builder.with_collector(BalancedCollector::default()),
+            Layout::Trickle => builder.with_collector(TrickleCollector::default()),
+        })
+    }
+}
+
+/// Shape of the file tree; see the [Layout section of the spec].
+///
+/// [Layout section of the spec]: https://github.com/ipfs/specs/blob/master/UNIXFS.md#layout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// Balanced trees, optimized for random access.
+    #[default]
+    Balanced,
+    /// Trickle trees, optimized for streaming from the start.
+    Trickle,
+}
+
 #[must_use = "do nothing unless you `.await` or poll the stream"]
 pub struct UnixfsAdd {
     core: Option<Either<Ipfs, Repo>>,
     opt: Option<AddOpt>,
     span: Span,
-    chunk: Chunker,
+    options: AddOptions,
     pin: bool,
     provide: bool,
     wrap: bool,
@@ -58,7 +134,7 @@ impl UnixfsAdd {
             core: Some(core),
             opt: Some(opt),
             span: Span::current(),
-            chunk: Chunker::Size(256 * 1024),
+            options: AddOptions::default(),
             pin: true,
             provide: false,
             wrap: false,
@@ -72,7 +148,13 @@ impl UnixfsAdd {
     }
 
     pub fn chunk(mut self, chunk: Chunker) -> Self {
-        self.chunk = chunk;
+        self.options.chunker = chunk;
+        self
+    }
+
+    /// Set the chunker, layout and block format to use. Any previously set chunker is replaced.
+    pub fn options(mut self, options: AddOptions) -> Self {
+        self.options = options;
         self
     }
 
@@ -109,7 +191,7 @@ impl Stream for UnixfsAdd {
                         Either::Right(repo) => (None, repo),
                     };
                     let option = self.opt.take().expect("option already constructed");
-                    let chunk = self.chunk;
+                    let options = self.options;
                     let pin = self.pin;
                     let provide = self.provide;
                     let wrap = self.wrap;
@@ -139,12 +221,20 @@ impl Stream for UnixfsAdd {
                             AddOpt::Stream { name, total, stream } => (name, total, stream),
                         };
 
-                        let mut adder = FileAdderBuilder::default()
-                            .with_chunker(chunk)
-                            .build();
+                        let mut adder = match options.builder() {
+                            Ok(builder) => builder.build(),
+                            Err(e) => {
+                                yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
+                                return;
+                            }
+                        };
 
                         yield UnixfsStatus::ProgressStatus { written, total_size };
 
+                        let batch_size = options.batch_size.max(1);
+                        let mut batch = Vec::with_capacity(batch_size);
+                        let mut blocks_put = PutStats::default();
+
                         while let Some(buffer) = stream.next().await {
                             let buffer = match buffer {
                                 Ok(buf) => buf,
@@ -158,20 +248,22 @@ impl Stream for UnixfsAdd {
                             while total < buffer.len() {
                                 let (blocks, consumed) = adder.push(&buffer[total..]);
                                 for (cid, block) in blocks {
-                                    let block = match Block::new(cid, block) {
-                                        Ok(block) => block,
+                                    match Block::new(cid, block) {
+                                        Ok(block) => batch.push(block),
                                         Err(e) => {
                                             yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
                                             return;
                                         }
                                     };
-                                    let _cid = match repo.put_block(block).await {
-                                        Ok(cid) => cid,
+                                }
+                                if batch.len() >= batch_size {
+                                    match repo.put_blocks(std::mem::take(&mut batch)).await {
+                                        Ok(puts) => puts.into_iter().for_each(|(_, put)| blocks_put.record(put)),
                                         Err(e) => {
                                             yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
                                             return;
                                         }
-                                    };
+                                    }
                                 }
                                 total += consumed;
                                 written += consumed;
@@ -184,15 +276,8 @@ impl Stream for UnixfsAdd {
                         let mut last_cid = None;
 
                         for (cid, block) in blocks {
-                            let block = match Block::new(cid, block) {
-                                Ok(block) => block,
-                                Err(e) => {
-                                    yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
-                                    return;
-                                }
-                            };
-                            let _cid = match repo.put_block(block).await {
-                                Ok(cid) => cid,
+                            match Block::new(cid, block) {
+                                Ok(block) => batch.push(block),
                                 Err(e) => {
                                     yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
                                     return;
@@ -201,6 +286,14 @@ impl Stream for UnixfsAdd {
                             last_cid = Some(cid);
                         }
 
+                        match repo.put_blocks(batch).await {
+                            Ok(puts) => puts.into_iter().for_each(|(_, put)| blocks_put.record(put)),
+                            Err(e) => {
+                                yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
+                                return;
+                            }
+                        }
+
                         let cid = match last_cid {
                             Some(cid) => cid,
                             None => {
@@ -224,24 +317,29 @@ impl Stream for UnixfsAdd {
 
                                         let mut iter = tree.build();
                                         let mut cids = Vec::new();
+                                        let mut blocks = Vec::new();
 
                                         while let Some(node) = iter.next_borrowed() {
                                             let node = node?;
                                             let block = Block::new(node.cid.to_owned(), node.block.into())?;
 
-                                            repo.put_block(block).await?;
-
+                                            blocks.push(block);
                                             cids.push(*node.cid);
                                         }
+
+                                        let puts = repo.put_blocks(blocks).await?;
                                         let cid = cids.last().ok_or(anyhow::anyhow!("no cid available"))?;
                                         let path = IpfsPath::from(*cid).sub_path(&name)?;
 
-                                        Ok::<_, anyhow::Error>(path)
+                                        Ok::<_, anyhow::Error>((path, puts))
                                     }
                                 };
 
                                 path = match result.await {
-                                    Ok(path) => path,
+                                    Ok((path, puts)) => {
+                                        puts.into_iter().for_each(|(_, put)| blocks_put.record(put));
+                                        path
+                                    }
                                     Err(e) => {
                                         yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
                                         return;
@@ -269,7 +367,7 @@ impl Stream for UnixfsAdd {
                         }
 
 
-                        yield UnixfsStatus::CompletedStatus { path, written, total_size }
+                        yield UnixfsStatus::CompletedStatus { path, written, total_size, blocks: blocks_put }
                     };
 
                     self.stream = StatusStreamState::Pending {
@@ -1,17 +1,20 @@
-use crate::{dag::IpldDag, repo::Repo, Block, Ipfs};
+use crate::{dag::IpldDag, error::Timeout, repo::FetchPolicy, repo::Repo, Block, Ipfs};
 use async_stream::stream;
 use bytes::Bytes;
 use either::Either;
 use futures::future::BoxFuture;
 use futures::stream::{BoxStream, FusedStream, Stream};
 use futures::{FutureExt, StreamExt, TryStreamExt};
+use libipld::IpldCodec;
 use libp2p::PeerId;
 use rust_unixfs::file::visit::IdleFileVisit;
 use std::ops::Range;
+use std::pin::Pin;
 use std::task::Poll;
-use std::{borrow::Borrow, time::Duration};
+use std::time::Duration;
 use tracing::{Instrument, Span};
 
+use super::read_ahead::{ReadAhead, DEFAULT_READ_AHEAD};
 use super::TraversalFailed;
 
 /// IPFS cat operation, producing a stream of file bytes. This is generic over the different kinds
@@ -29,6 +32,10 @@ pub struct UnixfsCat {
     providers: Vec<PeerId>,
     local_only: bool,
     timeout: Option<Duration>,
+    deadline: Option<Duration>,
+    /// Started along with the walk
+    expiry: Option<Pin<Box<tokio::time::Sleep>>>,
+    window: usize,
     stream: Option<BoxStream<'static, Result<Bytes, TraversalFailed>>>,
 }
 
@@ -51,6 +58,9 @@ impl UnixfsCat {
             providers: Vec::new(),
             local_only: false,
             timeout: None,
+            deadline: None,
+            expiry: None,
+            window: DEFAULT_READ_AHEAD,
             stream: None,
         }
     }
@@ -77,6 +87,25 @@ impl UnixfsCat {
         self
     }
 
+    /// Time given to the whole file, rather than to each block like [`UnixfsCat::timeout`], after
+    /// which the stream yields [`TraversalFailed::Deadline`] and ends
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Only yields the given byte range of the file.
+    pub fn range(mut self, range: Range<u64>) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    /// Number of blocks of the file fetched ahead of the one being yielded. Defaults to 16.
+    pub fn window(mut self, blocks: usize) -> Self {
+        self.window = blocks;
+        self
+    }
+
     pub fn local(mut self) -> Self {
         self.local_only = true;
         self
@@ -86,6 +115,13 @@ impl UnixfsCat {
         self.local_only = local;
         self
     }
+
+    /// Whether the missing blocks are fetched from the network, [`FetchPolicy::LocalOnly`] being
+    /// the same as [`UnixfsCat::local`].
+    pub fn fetch_policy(mut self, policy: FetchPolicy) -> Self {
+        self.local_only = policy == FetchPolicy::LocalOnly;
+        self
+    }
 }
 
 /// The starting point for unixfs walks. Can be converted from IpfsPath and Blocks, and Cids can be
@@ -114,10 +150,23 @@ impl Stream for UnixfsCat {
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Option<Self::Item>> {
         loop {
+            if let Some(expiry) = self.expiry.as_mut() {
+                if expiry.poll_unpin(cx).is_ready() {
+                    // dropping the walk cancels the blocks it wants
+                    self.expiry.take();
+                    self.stream.take();
+                    return Poll::Ready(Some(Err(TraversalFailed::Deadline(Timeout {
+                        elapsed: self.deadline.unwrap_or_default(),
+                        operation: "cat_unixfs",
+                    }))));
+                }
+            }
+
             match &mut self.stream {
                 Some(stream) => match futures::ready!(stream.poll_next_unpin(cx)) {
                     None => {
                         self.stream.take();
+                        self.expiry.take();
                         return Poll::Ready(None);
                     }
                     task => return Poll::Ready(task),
@@ -153,6 +202,7 @@ impl Stream for UnixfsCat {
                     let providers = std::mem::take(&mut self.providers);
                     let local_only = self.local_only;
                     let timeout = self.timeout;
+                    let window = self.window;
 
                     // using async_stream here at least to get on faster; writing custom streams is not too easy
                     // but this might be easy enough to write open.
@@ -180,7 +230,13 @@ impl Stream for UnixfsCat {
                         let mut cache = None;
                         // Start the visit from the root block. We need to move the both components as Options into the
                         // stream as we can't yet return them from this Future context.
-                        let (visit, bytes) = match visit.start(block.data()) {
+                        let started = if block.cid().codec() == u64::from(IpldCodec::Raw) {
+                            Ok(visit.start_raw(block.data()))
+                        } else {
+                            visit.start(block.data())
+                        };
+
+                        let (visit, bytes) = match started {
                             Ok((bytes, _, _, visit)) => {
                                 let bytes = if !bytes.is_empty() {
                                     Some(Bytes::copy_from_slice(bytes))
@@ -205,16 +261,12 @@ impl Stream for UnixfsCat {
                             None => return,
                         };
 
+                        let mut read_ahead = ReadAhead::new(repo, session, providers, local_only, timeout, window);
+
                         loop {
-                            // TODO: if it was possible, it would make sense to start downloading N of these
-                            // we could just create an FuturesUnordered which would drop the value right away. that
-                            // would probably always cost many unnecessary clones, but it would be nice to "shut"
-                            // the subscriber so that it will only resolve to a value but still keep the operation
-                            // going. Not that we have any "operation" concept of the Want yet.
-                            let (next, _) = visit.pending_links();
-
-                            let borrow = repo.borrow();
-                            let block = match borrow.get_block_with_session(session, next, &providers, local_only, timeout).await {
+                            let (next, following) = visit.pending_links();
+
+                            let block = match read_ahead.get(next, following).await {
                                 Ok(block) => block,
                                 Err(e) => {
                                     yield Err(TraversalFailed::Loading(*next, e));
@@ -243,6 +295,9 @@ impl Stream for UnixfsCat {
                     }.boxed();
 
                     self.stream.replace(stream);
+                    self.expiry = self
+                        .deadline
+                        .map(|deadline| Box::pin(tokio::time::sleep(deadline)));
                 }
             }
         }
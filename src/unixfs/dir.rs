@@ -0,0 +1,139 @@
+//! Building of UnixFS directories out of existing links and loading of their entries.
+
+use std::collections::BTreeMap;
+
+use anyhow::Error;
+use libipld::Cid;
+use rust_unixfs::dir::builder::{render_directory, HAMT_SHARDING_SIZE};
+
+use super::{DirEntry, UnixfsLs};
+use crate::{Block, Ipfs};
+
+/// The directory could not be built with [`UnixfsDirBuilder::build`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DirBuildError {
+    #[error("invalid entry name {0:?}")]
+    InvalidName(String),
+    #[error("duplicate entry name {0:?}")]
+    DuplicateName(String),
+}
+
+#[derive(Debug)]
+enum Entry {
+    Link(Cid, u64),
+    Directory(UnixfsDirBuilder),
+}
+
+/// Builder of a UnixFS directory out of links to existing files, directories or symlinks and of
+/// nested directories, producing the same dag-pb nodes as go-ipfs for the same entries. The
+/// directories are HAMT sharded once too large, as go-ipfs does.
+#[derive(Debug)]
+pub struct UnixfsDirBuilder {
+    entries: Vec<(String, Entry)>,
+    sharding_size: Option<usize>,
+}
+
+impl Default for UnixfsDirBuilder {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            sharding_size: Some(HAMT_SHARDING_SIZE),
+        }
+    }
+}
+
+impl UnixfsDirBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an entry linking to the cid, `size` being the cumulative size of its DAG.
+    pub fn add_link(mut self, name: impl Into<String>, cid: Cid, size: u64) -> Self {
+        self.entries.push((name.into(), Entry::Link(cid, size)));
+        self
+    }
+
+    /// Adds an entry for the directory built along with this one.
+    pub fn add_dir(mut self, name: impl Into<String>, dir: UnixfsDirBuilder) -> Self {
+        self.entries.push((name.into(), Entry::Directory(dir)));
+        self
+    }
+
+    /// Sets the estimated size at which the directory is sharded, the nested ones having their
+    /// own. Defaults to the one of go-ipfs, `None` never sharding it.
+    pub fn sharding_size(mut self, size: Option<usize>) -> Self {
+        self.sharding_size = size;
+        self
+    }
+
+    /// Writes the blocks of the directory and of the nested ones to the repo, returning the cid
+    /// of the directory.
+    pub async fn build(self, ipfs: &Ipfs) -> Result<Cid, Error> {
+        let mut blocks = Vec::new();
+        let (cid, _) = self.render(&mut blocks)?;
+
+        for block in blocks {
+            ipfs.put_block(block).await?;
+        }
+        Ok(cid)
+    }
+
+    /// Renders the nested directories then this one, returning its cid and cumulative size.
+    fn render(self, blocks: &mut Vec<Block>) -> Result<(Cid, u64), Error> {
+        let mut links = BTreeMap::new();
+        for (name, entry) in self.entries {
+            if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+                return Err(DirBuildError::InvalidName(name).into());
+            }
+            if links.contains_key(&name) {
+                return Err(DirBuildError::DuplicateName(name).into());
+            }
+
+            let link = match entry {
+                Entry::Link(cid, size) => (cid, size),
+                Entry::Directory(dir) => dir.render(blocks)?,
+            };
+            links.insert(name, link);
+        }
+
+        let nodes = render_directory(&links, self.sharding_size)?;
+        let root = nodes.last().map(|node| (node.cid, node.total_size));
+        for node in nodes {
+            blocks.push(Block::new(node.cid, node.block.into())?);
+        }
+        Ok(root.expect("the root is rendered"))
+    }
+}
+
+/// The entries of a UnixFS directory, sharded or not, see [`UnixfsDir::load`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnixfsDir {
+    cid: Cid,
+    entries: Vec<DirEntry>,
+}
+
+impl UnixfsDir {
+    /// Loads the directory, along with the root of its entries to tell their types, fetching them
+    /// if needed.
+    pub async fn load(ipfs: &Ipfs, cid: Cid) -> Result<Self, Error> {
+        let entries = UnixfsLs::with_ipfs(ipfs, cid).await?;
+        Ok(Self { cid, entries })
+    }
+
+    pub fn cid(&self) -> &Cid {
+        &self.cid
+    }
+
+    pub fn entries(&self) -> &[DirEntry] {
+        &self.entries
+    }
+
+    /// Returns the entry with the name, if any.
+    pub fn get(&self, name: &str) -> Option<&DirEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    pub fn into_entries(self) -> Vec<DirEntry> {
+        self.entries
+    }
+}
@@ -0,0 +1,749 @@
+//! Resolution of [`IpfsPath`]s into the responses a path gateway would serve, without depending on
+//! any HTTP server. The caller translates the request headers into a [`GatewayRequest`] and writes
+//! the [`GatewayResponse`] out with whatever server it uses.
+//!
+//! `_redirects` files and subdomain gateways are not supported.
+
+use std::fmt;
+use std::ops::Range;
+
+use anyhow::Error;
+use bytes::Bytes;
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt, TryStreamExt,
+};
+use libipld::IpldCodec;
+use rust_unixfs::walk::{ContinuedWalk, Walker};
+use tokio_util::io::ReaderStream;
+
+use super::{DirEntry, EntryType};
+use crate::{dag::ResolveError, Block, Ipfs, IpfsPath};
+
+/// Cache lifetime of the immutable `/ipfs/` responses, as used by the public gateways.
+const IMMUTABLE: &str = "public, max-age=29030400, immutable";
+
+/// Number of bytes read from the start of a file to sniff the content type from.
+const SNIFF_LEN: u64 = 512;
+
+/// The request headers taken into account, as their raw values.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GatewayRequest {
+    /// The `Range` header. Only a single range of bytes is supported, other ranges are ignored
+    /// and the whole file is served.
+    pub range: Option<String>,
+    /// The `Accept` header. `application/vnd.ipld.raw` and `application/vnd.ipld.car` select the
+    /// raw block or a CAR of the DAG instead of the deserialized content.
+    pub accept: Option<String>,
+    /// The `If-None-Match` header.
+    pub if_none_match: Option<String>,
+}
+
+/// The response to a [`GatewayRequest`].
+pub struct GatewayResponse {
+    pub status: u16,
+    /// Header names are lowercase.
+    pub headers: Vec<(String, String)>,
+    pub body: BoxStream<'static, Result<Bytes, Error>>,
+}
+
+impl fmt::Debug for GatewayResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GatewayResponse")
+            .field("status", &self.status)
+            .field("headers", &self.headers)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GatewayResponse {
+    fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: stream::empty().boxed(),
+        }
+    }
+
+    fn error(status: u16, message: impl fmt::Display) -> Self {
+        Self::new(status)
+            .with_header("content-type", "text/plain; charset=utf-8")
+            .with_body(Bytes::from(format!("{message}\n")))
+    }
+
+    fn with_header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_owned(), value.into()));
+        self
+    }
+
+    fn with_body(self, body: Bytes) -> Self {
+        let response = self.with_header("content-length", body.len().to_string());
+        GatewayResponse {
+            body: stream::once(async move { Ok(body) }).boxed(),
+            ..response
+        }
+    }
+
+    /// Returns the value of the first header with the given name, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub(crate) async fn respond(
+    ipfs: &Ipfs,
+    path: IpfsPath,
+    request: GatewayRequest,
+) -> Result<GatewayResponse, Error> {
+    let immutable = path.root().cid().is_some();
+
+    let resolved = match ipfs.dag().resolve(path.clone(), true, &[], false).await {
+        Ok((resolved, _)) => resolved,
+        Err(e @ ResolveError::NotFound(..)) | Err(e @ ResolveError::NoLinks(..)) => {
+            return Ok(GatewayResponse::error(404, e))
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let accept = request.accept.as_deref().unwrap_or_default();
+    if accept.contains("application/vnd.ipld.raw") || accept.contains("application/vnd.ipld.car") {
+        let cid = *resolved.source();
+        let car = !accept.contains("application/vnd.ipld.raw");
+        let etag = format!("\"{cid}.{}\"", if car { "car" } else { "raw" });
+        if matches_etag(&request, &etag) {
+            return Ok(not_modified(etag));
+        }
+
+        let response = GatewayResponse::new(200)
+            .with_header("etag", etag)
+            .with_header("x-ipfs-path", path.to_encoded_string())
+            .with_header("x-content-type-options", "nosniff");
+        let response = match immutable {
+            true => response.with_header("cache-control", IMMUTABLE),
+            false => response,
+        };
+
+        if !car {
+            let block = ipfs.get_block(&cid).await?;
+            return Ok(response
+                .with_header("content-type", "application/vnd.ipld.raw")
+                .with_body(Bytes::copy_from_slice(block.data())));
+        }
+
+        // the size of the export is not known up front, so it is streamed without a length
+        let (writer, reader) = tokio::io::duplex(64 * 1024);
+        let export = ipfs.dag_export(cid, writer);
+        tokio::spawn(async move {
+            if let Err(e) = export.await {
+                debug!(%cid, "car export for the gateway failed: {e}");
+            }
+        });
+        return Ok(GatewayResponse {
+            body: ReaderStream::new(reader).map_err(Error::from).boxed(),
+            ..response.with_header("content-type", "application/vnd.ipld.car; version=1")
+        });
+    }
+
+    let block = match resolved.into_unixfs_block() {
+        Ok(block) => block,
+        Err(e) => {
+            // other documents are only served as blocks or CARs
+            let message = format!("{e}; only UnixFS content is deserialized");
+            return Ok(GatewayResponse::error(501, message));
+        }
+    };
+
+    let response = match immutable {
+        true => GatewayResponse::new(200).with_header("cache-control", IMMUTABLE),
+        false => GatewayResponse::new(200),
+    };
+
+    match Content::of(&block)? {
+        Content::File(size) => {
+            let name = path.file_name().map(ToOwned::to_owned);
+            serve_file(ipfs, block, size, name.as_deref(), path, &request, response).await
+        }
+        Content::Symlink(target) => {
+            let etag = format!("\"{}\"", block.cid());
+            if matches_etag(&request, &etag) {
+                return Ok(not_modified(etag));
+            }
+            Ok(response
+                .with_header("etag", etag)
+                .with_header("x-ipfs-path", path.to_encoded_string())
+                .with_header("content-type", "inode/symlink")
+                .with_body(target))
+        }
+        Content::Directory => {
+            let entries = ipfs.ls_unixfs(path.clone()).try_collect::<Vec<_>>().await?;
+
+            if let Some(index) = entries.iter().find(|entry| entry.name == "index.html") {
+                let index_path = path.join("index.html")?;
+                let block = ipfs.get_block(&index.cid).await?;
+                if let Content::File(size) = Content::of(&block)? {
+                    return serve_file(
+                        ipfs,
+                        block,
+                        size,
+                        Some("index.html"),
+                        index_path,
+                        &request,
+                        response,
+                    )
+                    .await;
+                }
+            }
+
+            let etag = format!("\"DirIndex-{}\"", block.cid());
+            if matches_etag(&request, &etag) {
+                return Ok(not_modified(etag));
+            }
+
+            Ok(response
+                .with_header("etag", etag)
+                .with_header("x-ipfs-path", path.to_encoded_string())
+                .with_header("content-type", "text/html; charset=utf-8")
+                .with_body(directory_listing(&path, &entries).into()))
+        }
+    }
+}
+
+enum Content {
+    /// A file of the given size
+    File(u64),
+    Symlink(Bytes),
+    Directory,
+}
+
+impl Content {
+    fn of(block: &Block) -> Result<Self, Error> {
+        if block.cid().codec() == u64::from(IpldCodec::Raw) {
+            return Ok(Content::File(block.data().len() as u64));
+        }
+
+        let mut walker = Walker::new(*block.cid(), String::new());
+        Ok(match walker.next(block.data(), &mut None)? {
+            ContinuedWalk::File(.., size) => Content::File(size),
+            ContinuedWalk::Symlink(target, ..) => Content::Symlink(Bytes::copy_from_slice(target)),
+            ContinuedWalk::RootDirectory(..)
+            | ContinuedWalk::Directory(..)
+            | ContinuedWalk::Bucket(..) => Content::Directory,
+        })
+    }
+}
+
+async fn serve_file(
+    ipfs: &Ipfs,
+    block: Block,
+    size: u64,
+    name: Option<&str>,
+    path: IpfsPath,
+    request: &GatewayRequest,
+    response: GatewayResponse,
+) -> Result<GatewayResponse, Error> {
+    let etag = format!("\"{}\"", block.cid());
+    if matches_etag(request, &etag) {
+        return Ok(not_modified(etag));
+    }
+
+    let content_type = match name.and_then(content_type_of_name) {
+        Some(content_type) => content_type,
+        None if size == 0 => sniff(&[]),
+        None => {
+            let head = ipfs
+                .cat_unixfs(block.clone())
+                .range(0..SNIFF_LEN.min(size))
+                .await?;
+            sniff(&head)
+        }
+    };
+
+    let response = response
+        .with_header("etag", etag)
+        .with_header("x-ipfs-path", path.to_encoded_string())
+        .with_header("content-type", content_type)
+        .with_header("accept-ranges", "bytes");
+
+    let (response, range) = match request.range.as_deref().and_then(parse_range) {
+        None => (response, 0..size),
+        Some(requested) => match requested.satisfiable(size) {
+            Some(range) => (
+                GatewayResponse {
+                    status: 206,
+                    ..response
+                }
+                .with_header(
+                    "content-range",
+                    format!("bytes {}-{}/{size}", range.start, range.end - 1),
+                ),
+                range,
+            ),
+            None => {
+                return Ok(GatewayResponse {
+                    status: 416,
+                    ..response.with_header("content-range", format!("bytes */{size}"))
+                }
+                .with_body(Bytes::new()))
+            }
+        },
+    };
+
+    let length = range.end - range.start;
+    let body = match length {
+        0 => stream::empty().boxed(),
+        _ => ipfs
+            .cat_unixfs(block)
+            .range(range)
+            .map_err(Error::from)
+            .boxed(),
+    };
+
+    Ok(GatewayResponse {
+        body,
+        ..response.with_header("content-length", length.to_string())
+    })
+}
+
+fn not_modified(etag: String) -> GatewayResponse {
+    GatewayResponse::new(304).with_header("etag", etag)
+}
+
+fn matches_etag(request: &GatewayRequest, etag: &str) -> bool {
+    request.if_none_match.as_deref().map_or(false, |header| {
+        header
+            .split(',')
+            .map(str::trim)
+            .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+    })
+}
+
+/// A single range of the `Range` header, before the size of the file is known.
+#[derive(Debug, PartialEq, Eq)]
+enum RequestedRange {
+    /// `bytes=first-` or `bytes=first-last`, with both ends inclusive.
+    From(u64, Option<u64>),
+    /// `bytes=-suffix`
+    Suffix(u64),
+}
+
+impl RequestedRange {
+    /// Returns the requested part of a file of the given size, or `None` if no part of it was
+    /// requested.
+    fn satisfiable(&self, size: u64) -> Option<Range<u64>> {
+        match *self {
+            RequestedRange::From(first, _) if first >= size => None,
+            RequestedRange::From(first, last) => {
+                let end = last.map_or(size, |last| last.saturating_add(1).min(size));
+                Some(first..end)
+            }
+            RequestedRange::Suffix(0) => None,
+            RequestedRange::Suffix(_) if size == 0 => None,
+            RequestedRange::Suffix(suffix) => Some(size.saturating_sub(suffix)..size),
+        }
+    }
+}
+
+/// Parses the header of a request for a single range of bytes, returning `None` for headers which
+/// are to be ignored.
+fn parse_range(header: &str) -> Option<RequestedRange> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+    match (first.is_empty(), last.is_empty()) {
+        (true, true) => None,
+        (true, false) => last.parse().ok().map(RequestedRange::Suffix),
+        (false, true) => first
+            .parse()
+            .ok()
+            .map(|first| RequestedRange::From(first, None)),
+        (false, false) => {
+            let (first, last) = (first.parse().ok()?, last.parse().ok()?);
+            (first <= last).then_some(RequestedRange::From(first, Some(last)))
+        }
+    }
+}
+
+fn content_type_of_name(name: &str) -> Option<&'static str> {
+    let (_, extension) = name.rsplit_once('.')?;
+    Some(match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "woff2" => "font/woff2",
+        _ => return None,
+    })
+}
+
+/// Guesses the content type from the first bytes of a file.
+fn sniff(head: &[u8]) -> &'static str {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"\0asm", "application/wasm"),
+        (b"\x1f\x8b\x08", "application/gzip"),
+        (b"PK\x03\x04", "application/zip"),
+    ];
+
+    if let Some((_, content_type)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| head.starts_with(signature))
+    {
+        return content_type;
+    }
+
+    if head.len() >= 12 && head.starts_with(b"RIFF") && &head[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        // the sniffed bytes may end in the middle of a character
+        Err(e) if e.error_len().is_none() => {
+            std::str::from_utf8(&head[..e.valid_up_to()]).expect("valid up to the error")
+        }
+        Err(_) => return "application/octet-stream",
+    };
+
+    let start = text.trim_start().to_ascii_lowercase();
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        "text/html; charset=utf-8"
+    } else if start.starts_with("<svg") {
+        "image/svg+xml"
+    } else {
+        "text/plain; charset=utf-8"
+    }
+}
+
+fn directory_listing(path: &IpfsPath, entries: &[DirEntry]) -> String {
+    let title = escape_html(&path.to_string());
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n<body>\n<h1>Index of {title}</h1>\n<table>\n"
+    );
+
+    if let Some(parent) = path.parent() {
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}\">..</a></td><td></td><td></td></tr>\n",
+            escape_html(&parent.to_encoded_string())
+        ));
+    }
+
+    for entry in entries {
+        let Ok(href) = path.join(&entry.name) else {
+            continue;
+        };
+        let name = match entry.entry_type {
+            EntryType::Directory => format!("{}/", entry.name),
+            _ => entry.name.clone(),
+        };
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            escape_html(&href.to_encoded_string()),
+            escape_html(&name),
+            entry.size,
+            entry.cid,
+        ));
+    }
+
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{StreamExt, TryStreamExt};
+    use libipld::Cid;
+    use rust_unixfs::dir::builder::{BufferingTreeBuilder, TreeOptions};
+
+    use super::{parse_range, sniff, GatewayRequest, GatewayResponse, RequestedRange};
+    use crate::{Block, Ipfs, IpfsPath, Node};
+
+    async fn add_file(ipfs: &Ipfs, data: &[u8]) -> (Cid, u64) {
+        let path = ipfs.add_unixfs(data.to_vec()).await.unwrap();
+        (*path.root().cid().unwrap(), data.len() as u64)
+    }
+
+    async fn add_directory(ipfs: &Ipfs, links: &[(&str, Cid, u64)]) -> Cid {
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+        let mut tree = BufferingTreeBuilder::new(opts);
+        for (name, cid, size) in links {
+            tree.put_link(name, *cid, *size).unwrap();
+        }
+
+        let mut iter = tree.build();
+        let mut root = None;
+        while let Some(node) = iter.next_borrowed() {
+            let node = node.unwrap();
+            ipfs.put_block(Block::new(*node.cid, node.block.into()).unwrap())
+                .await
+                .unwrap();
+            root = Some(*node.cid);
+        }
+        root.unwrap()
+    }
+
+    /// /site/index.html, /site/style.css, /docs/readme, /docs/image and /docs/empty
+    async fn fixture(ipfs: &Ipfs) -> Cid {
+        let index = add_file(ipfs, b"<!doctype html><p>hello</p>").await;
+        let style = add_file(ipfs, b"p { color: red }").await;
+        let site = add_directory(
+            ipfs,
+            &[
+                ("index.html", index.0, index.1),
+                ("style.css", style.0, style.1),
+            ],
+        )
+        .await;
+
+        let readme = add_file(ipfs, b"0123456789abcdefghij").await;
+        let image = add_file(ipfs, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").await;
+        let empty = add_file(ipfs, b"").await;
+        let docs = add_directory(
+            ipfs,
+            &[
+                ("readme", readme.0, readme.1),
+                ("image", image.0, image.1),
+                ("empty", empty.0, empty.1),
+            ],
+        )
+        .await;
+
+        add_directory(ipfs, &[("site", site, 0), ("docs", docs, 0)]).await
+    }
+
+    async fn fetch(ipfs: &Ipfs, path: &str, request: GatewayRequest) -> (GatewayResponse, Vec<u8>) {
+        let mut response = ipfs
+            .fetch_unixfs_as_http_response(path.parse::<IpfsPath>().unwrap(), request)
+            .await
+            .unwrap();
+        let body = std::mem::replace(&mut response.body, futures::stream::empty().boxed())
+            .try_concat()
+            .await
+            .unwrap();
+        (response, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn directory_with_index_serves_it() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+        let root = fixture(&ipfs).await;
+
+        let (response, body) =
+            fetch(&ipfs, &format!("/ipfs/{root}/site"), Default::default()).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(body, b"<!doctype html><p>hello</p>");
+        assert_eq!(
+            response.header("content-type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(
+            response.header("x-ipfs-path"),
+            Some(format!("/ipfs/{root}/site/index.html").as_str())
+        );
+        assert_eq!(
+            response.header("cache-control"),
+            Some("public, max-age=29030400, immutable")
+        );
+
+        let (response, _) = fetch(
+            &ipfs,
+            &format!("/ipfs/{root}/site/style.css"),
+            Default::default(),
+        )
+        .await;
+        assert_eq!(
+            response.header("content-type"),
+            Some("text/css; charset=utf-8")
+        );
+    }
+
+    #[tokio::test]
+    async fn directory_without_index_is_listed() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+        let root = fixture(&ipfs).await;
+
+        let (response, body) =
+            fetch(&ipfs, &format!("/ipfs/{root}/docs"), Default::default()).await;
+        assert_eq!(response.status, 200);
+        assert!(response.header("etag").unwrap().starts_with("\"DirIndex-"));
+
+        let body = String::from_utf8(body).unwrap();
+        for name in ["readme", "image", "empty"] {
+            assert!(body.contains(&format!("href=\"/ipfs/{root}/docs/{name}\"")));
+        }
+        assert!(body.contains(&format!("href=\"/ipfs/{root}\"")));
+    }
+
+    #[tokio::test]
+    async fn content_type_is_sniffed_without_extension() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+        let root = fixture(&ipfs).await;
+
+        for (name, expected) in [
+            ("readme", "text/plain; charset=utf-8"),
+            ("image", "image/png"),
+        ] {
+            let (response, _) = fetch(
+                &ipfs,
+                &format!("/ipfs/{root}/docs/{name}"),
+                Default::default(),
+            )
+            .await;
+            assert_eq!(response.header("content-type"), Some(expected), "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn ranges_are_served() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+        let root = fixture(&ipfs).await;
+        let readme = format!("/ipfs/{root}/docs/readme");
+
+        for (range, status, expected, content_range) in [
+            ("bytes=2-5", 206, &b"2345"[..], Some("bytes 2-5/20")),
+            ("bytes=15-", 206, b"fghij", Some("bytes 15-19/20")),
+            ("bytes=-3", 206, b"hij", Some("bytes 17-19/20")),
+            ("bytes=18-100", 206, b"ij", Some("bytes 18-19/20")),
+            ("bytes=20-", 416, b"", Some("bytes */20")),
+            // multiple ranges are not supported and ignored
+            ("bytes=0-1,3-4", 200, b"0123456789abcdefghij", None),
+        ] {
+            let request = GatewayRequest {
+                range: Some(range.into()),
+                ..Default::default()
+            };
+            let (response, body) = fetch(&ipfs, &readme, request).await;
+            assert_eq!(response.status, status, "{range}");
+            assert_eq!(body, expected, "{range}");
+            assert_eq!(response.header("content-range"), content_range, "{range}");
+            assert_eq!(
+                response.header("content-length"),
+                Some(expected.len().to_string().as_str()),
+                "{range}"
+            );
+        }
+
+        let request = GatewayRequest {
+            range: Some("bytes=0-".into()),
+            ..Default::default()
+        };
+        let (response, _) = fetch(&ipfs, &format!("/ipfs/{root}/docs/empty"), request).await;
+        assert_eq!(response.status, 416);
+    }
+
+    #[tokio::test]
+    async fn matching_etag_is_not_modified() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+        let root = fixture(&ipfs).await;
+        let readme = format!("/ipfs/{root}/docs/readme");
+
+        let (response, _) = fetch(&ipfs, &readme, Default::default()).await;
+        let etag = response.header("etag").unwrap().to_owned();
+
+        let request = GatewayRequest {
+            if_none_match: Some(format!("\"other\", W/{etag}")),
+            ..Default::default()
+        };
+        let (response, body) = fetch(&ipfs, &readme, request).await;
+        assert_eq!(response.status, 304);
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn missing_entry_is_not_found() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+        let root = fixture(&ipfs).await;
+
+        let (response, _) = fetch(
+            &ipfs,
+            &format!("/ipfs/{root}/docs/missing"),
+            Default::default(),
+        )
+        .await;
+        assert_eq!(response.status, 404);
+    }
+
+    #[tokio::test]
+    async fn raw_block_is_served_when_accepted() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+        let root = fixture(&ipfs).await;
+
+        let request = GatewayRequest {
+            accept: Some("application/vnd.ipld.raw".into()),
+            ..Default::default()
+        };
+        let (response, body) = fetch(&ipfs, &format!("/ipfs/{root}"), request).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.header("content-type"),
+            Some("application/vnd.ipld.raw")
+        );
+        assert_eq!(body, ipfs.get_block(&root).await.unwrap().data());
+    }
+
+    #[test]
+    fn range_headers() {
+        assert_eq!(
+            parse_range("bytes=0-0"),
+            Some(RequestedRange::From(0, Some(0)))
+        );
+        assert_eq!(parse_range("bytes=5-"), Some(RequestedRange::From(5, None)));
+        assert_eq!(parse_range("bytes=-5"), Some(RequestedRange::Suffix(5)));
+        assert_eq!(parse_range("bytes=5-1"), None);
+        assert_eq!(parse_range("bytes=-"), None);
+        assert_eq!(parse_range("items=0-1"), None);
+    }
+
+    #[test]
+    fn sniffing() {
+        assert_eq!(sniff(b"GIF89a..."), "image/gif");
+        assert_eq!(sniff(b"  <!DOCTYPE html>"), "text/html; charset=utf-8");
+        assert_eq!(sniff("plain ä".as_bytes()), "text/plain; charset=utf-8");
+        // cut in the middle of a multibyte character
+        assert_eq!(
+            sniff(&"plain ä".as_bytes()[..7]),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(sniff(b"\xff\xfe\x00"), "application/octet-stream");
+    }
+}
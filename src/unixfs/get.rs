@@ -1,20 +1,52 @@
 use std::{
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
     task::Poll,
     time::Duration,
 };
 
 use either::Either;
-use futures::{future::BoxFuture, stream::FusedStream, FutureExt, Stream, StreamExt};
+use futures::{
+    channel::mpsc::Sender, future::BoxFuture, stream::FusedStream, FutureExt, SinkExt, Stream,
+    StreamExt,
+};
 use libp2p::PeerId;
 use rust_unixfs::walk::{ContinuedWalk, Walker};
 use tokio::io::AsyncWriteExt;
 use tracing::{Instrument, Span};
 
-use crate::{dag::IpldDag, repo::Repo, Ipfs, IpfsPath};
+use crate::{dag::IpldDag, repo::FetchPolicy, repo::Repo, Ipfs, IpfsPath};
 
+use super::read_ahead::{ReadAhead, DEFAULT_READ_AHEAD};
 use super::{StatusStreamState, TraversalFailed, UnixfsStatus};
 
+/// Options for writing a UnixFS file or directory to the local filesystem.
+///
+/// Files are first written next to their destination with a `.part` suffix and renamed once all
+/// of their contents have been written. If the operation fails, the entries completed so far are
+/// kept and the file being written at the time is left behind with the `.part` suffix.
+///
+/// There is no counterpart to the `--compress` flag of `ipfs get`: the entries are always written
+/// as they are, and archiving or compressing them is left to the caller.
+#[derive(Debug, Clone, Default)]
+pub struct GetOptions {
+    /// Replace existing files and reuse existing directories at the destination, instead of
+    /// failing when the destination exists.
+    pub overwrite: bool,
+    /// Skip symlink entries instead of creating them.
+    pub skip_symlinks: bool,
+    /// Receives an event for every created entry and every written part of a file.
+    pub progress: Option<Sender<GetProgress>>,
+}
+
+/// Progress of [`UnixfsGet`] sent through [`GetOptions::progress`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetProgress {
+    /// Total number of file bytes written so far.
+    pub written: usize,
+    /// Local path of the entry being written.
+    pub entry: PathBuf,
+}
+
 #[must_use = "do nothing unless you `.await` or poll the stream"]
 pub struct UnixfsGet {
     core: Option<Either<Ipfs, Repo>>,
@@ -24,6 +56,8 @@ pub struct UnixfsGet {
     providers: Vec<PeerId>,
     local_only: bool,
     timeout: Option<Duration>,
+    window: usize,
+    options: GetOptions,
     stream: StatusStreamState,
 }
 
@@ -51,6 +85,8 @@ impl UnixfsGet {
             providers: Vec::new(),
             local_only: false,
             timeout: None,
+            window: DEFAULT_READ_AHEAD,
+            options: GetOptions::default(),
             stream: StatusStreamState::None,
         }
     }
@@ -77,6 +113,12 @@ impl UnixfsGet {
         self
     }
 
+    /// Number of blocks fetched ahead of the one being written. Defaults to 16.
+    pub fn window(mut self, blocks: usize) -> Self {
+        self.window = blocks;
+        self
+    }
+
     pub fn local(mut self) -> Self {
         self.local_only = true;
         self
@@ -86,6 +128,19 @@ impl UnixfsGet {
         self.local_only = local;
         self
     }
+
+    /// Whether the missing blocks are fetched from the network, [`FetchPolicy::LocalOnly`] being
+    /// the same as [`UnixfsGet::local`].
+    pub fn fetch_policy(mut self, policy: FetchPolicy) -> Self {
+        self.local_only = policy == FetchPolicy::LocalOnly;
+        self
+    }
+
+    /// Set the options for writing the entries to the destination.
+    pub fn options(mut self, options: GetOptions) -> Self {
+        self.options = options;
+        self
+    }
 }
 
 impl Stream for UnixfsGet {
@@ -118,24 +173,21 @@ impl Stream for UnixfsGet {
                     let providers = std::mem::take(&mut self.providers);
                     let local_only = self.local_only;
                     let timeout = self.timeout;
+                    let window = self.window;
                     let dest = self.dest.clone();
+                    let mut options = std::mem::take(&mut self.options);
 
                     let stream = async_stream::stream! {
 
-
                         let mut cache = None;
                         let mut total_size = None;
                         let mut written = 0;
 
-                        let mut file = match tokio::fs::File::create(dest)
-                            .await
-                            .map_err(TraversalFailed::Io) {
-                                Ok(f) => f,
-                                Err(e) => {
-                                    yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::Error::from(e)) };
-                                    return;
-                                }
-                            };
+                        if !options.overwrite && tokio::fs::symlink_metadata(&dest).await.is_ok() {
+                            let error = std::io::Error::new(std::io::ErrorKind::AlreadyExists, format!("{} already exists", dest.display()));
+                            yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::Error::from(TraversalFailed::Io(error))) };
+                            return;
+                        }
 
                         let block  = match dag
                             .resolve_with_session(session, path.clone(), true, &providers, local_only, timeout)
@@ -152,11 +204,15 @@ impl Stream for UnixfsGet {
                         let cid = block.cid();
                         let root_name = block.cid().to_string();
 
-                        let mut walker = Walker::new(*cid, root_name);
+                        let mut walker = Walker::new(*cid, root_name.clone());
+                        let mut read_ahead = ReadAhead::new(repo, session, providers, local_only, timeout, window);
+
+                        // the file being written along with its temporary and final paths
+                        let mut current: Option<(tokio::fs::File, PathBuf, PathBuf)> = None;
 
                         while walker.should_continue() {
-                            let (next, _) = walker.pending_links();
-                            let block = match repo.get_block_with_session(session, next, &providers, local_only, timeout).await {
+                            let (next, following) = walker.pending_links();
+                            let block = match read_ahead.get(next, following).await {
                                 Ok(block) => block,
                                 Err(e) => {
                                     yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
@@ -165,52 +221,109 @@ impl Stream for UnixfsGet {
                             };
                             let block_data = block.data();
 
-                            match walker.next(block_data, &mut cache) {
-                                Ok(ContinuedWalk::Bucket(..)) => {}
-                                Ok(ContinuedWalk::File(segment, _, _, _, size)) => {
+                            let target = match walker.next(block_data, &mut cache) {
+                                Ok(ContinuedWalk::Bucket(..)) => continue,
+                                Ok(ContinuedWalk::File(segment, _, path, _, size)) => {
+                                    let target = match local_path(&dest, path, &root_name, false).await {
+                                        Ok(target) => target,
+                                        Err(e) => {
+                                            yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::Error::from(TraversalFailed::Io(e))) };
+                                            return;
+                                        }
+                                    };
 
                                     if segment.is_first() {
-                                        total_size = Some(size as usize);
+                                        if target == dest {
+                                            total_size = Some(size as usize);
+                                        }
+
+                                        let mut part = target.clone().into_os_string();
+                                        part.push(".part");
+                                        let part = PathBuf::from(part);
+
+                                        let file = match create_part(&part).await {
+                                            Ok(file) => file,
+                                            Err(e) => {
+                                                yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::Error::from(TraversalFailed::Io(e))) };
+                                                return;
+                                            }
+                                        };
+
+                                        current = Some((file, part, target.clone()));
                                         yield UnixfsStatus::ProgressStatus { written, total_size };
                                     }
-                                    // even if the largest of files can have 256 kB blocks and about the same
-                                    // amount of content, try to consume it in small parts not to grow the buffers
-                                    // too much.
-
-                                    let mut n = 0usize;
-                                    let slice = segment.as_ref();
-                                    let total = slice.len();
-
-                                    while n < total {
-                                        let next = &slice[n..];
-                                        n += next.len();
-                                        if let Err(e) = file.write_all(next).await {
-                                            yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::Error::from(e)) };
+
+                                    let (file, _, _) = current.as_mut().expect("file was created at the first segment");
+
+                                    if let Err(e) = file.write_all(segment.as_ref()).await {
+                                        yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::Error::from(TraversalFailed::Io(e))) };
+                                        return;
+                                    }
+
+                                    written += segment.as_ref().len();
+                                    yield UnixfsStatus::ProgressStatus { written, total_size };
+
+                                    if segment.is_last() {
+                                        let (file, part, target) = current.take().expect("file was created at the first segment");
+
+                                        if let Err(e) = finish_file(file, &part, &target).await {
+                                            yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::Error::from(TraversalFailed::Io(e))) };
                                             return;
                                         }
-                                        if let Err(e) = file.sync_all().await {
-                                            yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::Error::from(e)) };
+                                    }
+
+                                    target
+                                },
+                                Ok(ContinuedWalk::Directory(_, path, _)) | Ok(ContinuedWalk::RootDirectory(_, path, _)) => {
+                                    let target = match local_path(&dest, path, &root_name, true).await {
+                                        Ok(target) => target,
+                                        Err(e) => {
+                                            yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::Error::from(TraversalFailed::Io(e))) };
                                             return;
                                         }
+                                    };
 
-                                        written += n;
-                                        yield UnixfsStatus::ProgressStatus { written, total_size };
+                                    if let Err(e) = tokio::fs::create_dir_all(&target).await {
+                                        yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::Error::from(TraversalFailed::Io(e))) };
+                                        return;
                                     }
 
-                                    if segment.is_last() {
-                                        yield UnixfsStatus::ProgressStatus { written, total_size };
+                                    target
+                                },
+                                Ok(ContinuedWalk::Symlink(link, _, path, _)) => {
+                                    if options.skip_symlinks {
+                                        continue;
+                                    }
+
+                                    let target = match local_path(&dest, path, &root_name, false).await {
+                                        Ok(target) => target,
+                                        Err(e) => {
+                                            yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::Error::from(TraversalFailed::Io(e))) };
+                                            return;
+                                        }
+                                    };
+
+                                    let link = PathBuf::from(String::from_utf8_lossy(link).into_owned());
+
+                                    if let Err(e) = create_symlink(&link, &target, options.overwrite).await {
+                                        yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::Error::from(TraversalFailed::Io(e))) };
+                                        return;
                                     }
+
+                                    target
                                 },
-                                Ok(ContinuedWalk::Directory( .. )) | Ok(ContinuedWalk::RootDirectory( .. )) => {}, //TODO
-                                Ok(ContinuedWalk::Symlink( .. )) => {},
                                 Err(e) => {
                                     yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::Error::from(e)) };
                                     return;
                                 }
                             };
+
+                            if let Some(progress) = options.progress.as_mut() {
+                                _ = progress.send(GetProgress { written, entry: target }).await;
+                            }
                         };
 
-                        yield UnixfsStatus::CompletedStatus { path, written, total_size }
+                        yield UnixfsStatus::CompletedStatus { path, written, total_size, blocks: Default::default() }
                     };
 
                     self.stream = StatusStreamState::Pending {
@@ -272,3 +385,92 @@ impl FusedStream for UnixfsGet {
         matches!(self.stream, StatusStreamState::Done) && self.core.is_none()
     }
 }
+
+/// Returns the local path of an entry found by the walk rooted at `root_name`. Fails for the
+/// entries which would end up outside of `dest`, by their name or through a symlink created
+/// earlier by the walk, the directory itself not being allowed to be a symlink either.
+async fn local_path(
+    dest: &Path,
+    path: &Path,
+    root_name: &str,
+    directory: bool,
+) -> std::io::Result<PathBuf> {
+    let relative = path.strip_prefix(root_name).unwrap_or(path);
+
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("invalid entry name {}", path.display()),
+        ));
+    }
+
+    if relative.as_os_str().is_empty() {
+        return Ok(dest.to_path_buf());
+    }
+
+    let mut components = relative.components().collect::<Vec<_>>();
+    if !directory {
+        components.pop();
+    }
+    let mut current = dest.to_path_buf();
+    for component in components {
+        current.push(component);
+        if let Ok(metadata) = tokio::fs::symlink_metadata(&current).await {
+            if metadata.file_type().is_symlink() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{} is a symlink", current.display()),
+                ));
+            }
+        }
+    }
+
+    Ok(dest.join(relative))
+}
+
+/// Creates the temporary file, replacing a previous one without following it if it is a
+/// symlink.
+async fn create_part(part: &Path) -> std::io::Result<tokio::fs::File> {
+    if tokio::fs::symlink_metadata(part).await.is_ok() {
+        tokio::fs::remove_file(part).await?;
+    }
+    tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(part)
+        .await
+}
+
+async fn finish_file(mut file: tokio::fs::File, part: &Path, target: &Path) -> std::io::Result<()> {
+    file.flush().await?;
+    file.sync_all().await?;
+    drop(file);
+    tokio::fs::rename(part, target).await
+}
+
+async fn create_symlink(link: &Path, target: &Path, overwrite: bool) -> std::io::Result<()> {
+    if overwrite && tokio::fs::symlink_metadata(target).await.is_ok() {
+        tokio::fs::remove_file(target).await?;
+    }
+
+    #[cfg(unix)]
+    {
+        tokio::fs::symlink(link, target).await
+    }
+
+    #[cfg(windows)]
+    {
+        tokio::fs::symlink_file(link, target).await
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "symlinks are not supported on this platform",
+        ))
+    }
+}
@@ -6,19 +6,40 @@ use futures::{
     stream::{BoxStream, FusedStream},
     FutureExt, Stream, StreamExt,
 };
-use libipld::Cid;
+use libipld::{Cid, IpldCodec};
 use libp2p::PeerId;
-use rust_unixfs::walk::{ContinuedWalk, Walker};
+use rust_unixfs::{
+    walk::{ContinuedWalk, Walker},
+    ListedLink, ResolveError,
+};
 use tracing::{Instrument, Span};
 
-use crate::{dag::IpldDag, repo::Repo, Ipfs, IpfsPath};
+use crate::{dag::IpldDag, repo::FetchPolicy, repo::Repo, Ipfs, IpfsPath};
+
+use super::TraversalFailed;
+
+/// An entry of a listed UnixFS directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub cid: Cid,
+    /// Size of the file contents when the child root was resolved, otherwise the cumulative size
+    /// of the linked DAG.
+    pub size: u64,
+    pub entry_type: EntryType,
+}
+
+/// Former name of [`DirEntry`].
+#[deprecated(note = "use `DirEntry`, the entries are listed one level deep")]
+pub type Entry = DirEntry;
 
-#[derive(Debug)]
-pub enum Entry {
-    Error { error: anyhow::Error },
-    RootDirectory { cid: Cid, path: String },
-    Directory { cid: Cid, path: String },
-    File { cid: Cid, file: String, size: usize },
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryType {
+    File,
+    Directory,
+    Symlink,
+    /// The type cannot be known without loading the child root.
+    Unknown,
 }
 
 #[must_use = "do nothing unless you `.await` or poll the stream"]
@@ -29,7 +50,8 @@ pub struct UnixfsLs {
     providers: Vec<PeerId>,
     local_only: bool,
     timeout: Option<Duration>,
-    stream: Option<BoxStream<'static, Entry>>,
+    resolve_children_size: bool,
+    stream: Option<BoxStream<'static, Result<DirEntry, TraversalFailed>>>,
 }
 
 impl UnixfsLs {
@@ -50,6 +72,7 @@ impl UnixfsLs {
             providers: Vec::new(),
             local_only: false,
             timeout: None,
+            resolve_children_size: true,
             stream: None,
         }
     }
@@ -85,10 +108,25 @@ impl UnixfsLs {
         self.local_only = local;
         self
     }
+
+    /// Whether the missing blocks are fetched from the network, [`FetchPolicy::LocalOnly`] being
+    /// the same as [`UnixfsLs::local`].
+    pub fn fetch_policy(mut self, policy: FetchPolicy) -> Self {
+        self.local_only = policy == FetchPolicy::LocalOnly;
+        self
+    }
+
+    /// Load the root block of every child to find out its type and the size of the file contents.
+    /// When disabled, the size is the cumulative size recorded in the directory link and the type
+    /// is only known for raw blocks. Defaults to true.
+    pub fn resolve_children_size(mut self, resolve: bool) -> Self {
+        self.resolve_children_size = resolve;
+        self
+    }
 }
 
 impl Stream for UnixfsLs {
-    type Item = Entry;
+    type Item = Result<DirEntry, TraversalFailed>;
     fn poll_next(
         mut self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
@@ -127,9 +165,8 @@ impl Stream for UnixfsLs {
                     let providers = std::mem::take(&mut self.providers);
                     let local_only = self.local_only;
                     let timeout = self.timeout;
+                    let resolve_children_size = self.resolve_children_size;
 
-                    // using async_stream here at least to get on faster; writing custom streams is not too easy
-                    // but this might be easy enough to write open.
                     let stream = async_stream::stream! {
 
                         let resolved = match dag
@@ -137,7 +174,7 @@ impl Stream for UnixfsLs {
                             .await {
                                 Ok((resolved, _)) => resolved,
                                 Err(e) => {
-                                    yield Entry::Error { error: e.into() };
+                                    yield Err(TraversalFailed::Resolving(e));
                                     return;
                                 }
                             };
@@ -145,50 +182,95 @@ impl Stream for UnixfsLs {
                         let block = match resolved.into_unixfs_block() {
                             Ok(block) => block,
                             Err(e) => {
-                                yield Entry::Error { error: e.into() };
+                                yield Err(TraversalFailed::Path(e));
                                 return;
                             }
                         };
 
-                        let cid = block.cid();
-                        let root_name = cid.to_string();
+                        let root = *block.cid();
+
+                        if root.codec() == u64::from(IpldCodec::Raw) {
+                            yield Err(TraversalFailed::NotADirectory(root));
+                            return;
+                        }
+
+                        let links = match rust_unixfs::list(block.data()) {
+                            Ok(links) => links,
+                            Err(ResolveError::UnexpectedType(_)) => {
+                                yield Err(TraversalFailed::NotADirectory(root));
+                                return;
+                            }
+                            Err(e) => {
+                                yield Err(TraversalFailed::Listing(root, e));
+                                return;
+                            }
+                        };
 
-                        let mut walker = Walker::new(*cid, root_name);
-                        let mut cache = None;
-                        let mut root_directory = String::new();
-                        while walker.should_continue() {
-                            let (next, _) = walker.pending_links();
-                            let block = match repo.get_block_with_session(session, next, &providers, local_only, timeout).await {
+                        // buckets of sharded directories are loaded only once the listing reaches
+                        // them, so that large directories are not loaded up front.
+                        let mut pending = vec![links.into_iter()];
+
+                        while let Some(links) = pending.last_mut() {
+                            let Some(link) = links.next() else {
+                                pending.pop();
+                                continue;
+                            };
+
+                            let (name, cid, size) = match link {
+                                ListedLink::Entry { name, cid, size } => (name, cid, size),
+                                ListedLink::Bucket(cid) => {
+                                    let block = match repo.get_block_with_session(session, &cid, &providers, local_only, timeout).await {
+                                        Ok(block) => block,
+                                        Err(e) => {
+                                            yield Err(TraversalFailed::Loading(cid, e));
+                                            return;
+                                        }
+                                    };
+
+                                    match rust_unixfs::list(block.data()) {
+                                        Ok(links) => pending.push(links.into_iter()),
+                                        Err(e) => {
+                                            yield Err(TraversalFailed::Listing(cid, e));
+                                            return;
+                                        }
+                                    }
+                                    continue;
+                                }
+                            };
+
+                            if !resolve_children_size {
+                                let entry_type = if cid.codec() == u64::from(IpldCodec::Raw) {
+                                    EntryType::File
+                                } else {
+                                    EntryType::Unknown
+                                };
+                                yield Ok(DirEntry { name, cid, size, entry_type });
+                                continue;
+                            }
+
+                            let block = match repo.get_block_with_session(session, &cid, &providers, local_only, timeout).await {
                                 Ok(block) => block,
-                                Err(error) => {
-                                    yield Entry::Error { error };
+                                Err(e) => {
+                                    yield Err(TraversalFailed::Loading(cid, e));
                                     return;
                                 }
                             };
-                            let block_data = block.data();
-
-                            match walker.next(block_data, &mut cache) {
-                                Ok(ContinuedWalk::Bucket(..)) => {}
-                                Ok(ContinuedWalk::File(_, cid, path, _, size)) => {
-                                    let file = path.to_string_lossy().to_string().replace(&format!("{root_directory}/"), "");
-                                    yield Entry::File { cid: *cid, file, size: size as _ };
-                                },
-                                Ok(ContinuedWalk::RootDirectory( cid, path, _)) => {
-                                    let path = path.to_string_lossy().to_string();
-                                    root_directory = path.clone();
-                                    yield Entry::RootDirectory { cid: *cid, path };
-                                }
-                                Ok(ContinuedWalk::Directory( cid, path, _)) => {
-                                    let path = path.to_string_lossy().to_string().replace(&format!("{root_directory}/"), "");
-                                    yield Entry::Directory { cid: *cid, path };
-                                }
-                                Ok(ContinuedWalk::Symlink( .. )) => {},
-                                Err(error) => {
-                                    yield Entry::Error { error: anyhow::Error::from(error) };
+
+                            let mut walker = Walker::new(cid, String::new());
+
+                            let (entry_type, size) = match walker.next(block.data(), &mut None) {
+                                Ok(ContinuedWalk::File(.., file_size)) => (EntryType::File, file_size),
+                                Ok(ContinuedWalk::RootDirectory(..)) => (EntryType::Directory, size),
+                                Ok(ContinuedWalk::Symlink(target, ..)) => (EntryType::Symlink, target.len() as u64),
+                                Ok(ContinuedWalk::Directory(..)) | Ok(ContinuedWalk::Bucket(..)) => (EntryType::Unknown, size),
+                                Err(e) => {
+                                    yield Err(TraversalFailed::Loading(cid, e.into()));
                                     return;
                                 }
                             };
-                        };
+
+                            yield Ok(DirEntry { name, cid, size, entry_type });
+                        }
 
                     }.boxed();
 
@@ -200,7 +282,7 @@ impl Stream for UnixfsLs {
 }
 
 impl std::future::IntoFuture for UnixfsLs {
-    type Output = Result<Vec<Entry>, anyhow::Error>;
+    type Output = Result<Vec<DirEntry>, TraversalFailed>;
 
     type IntoFuture = BoxFuture<'static, Self::Output>;
 
@@ -208,11 +290,8 @@ impl std::future::IntoFuture for UnixfsLs {
         let span = self.span.clone();
         async move {
             let mut items = vec![];
-            while let Some(status) = self.next().await {
-                match status {
-                    Entry::Error { error } => return Err(error),
-                    item => items.push(item),
-                }
+            while let Some(entry) = self.next().await {
+                items.push(entry?);
             }
             Ok(items)
         }
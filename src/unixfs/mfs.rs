@@ -0,0 +1,928 @@
+//! Mutable file system (MFS) on top of UnixFS directories, similar to `ipfs files` of go-ipfs.
+//!
+//! The tree is addressed with absolute paths such as `/photos/cat.jpg`. Every modification
+//! re-creates only the directories along the modified path, the rest of the tree is shared with
+//! the previous root. Writes into a file likewise only re-create the chunks they touch and the
+//! nodes above them. Large directories are sharded the same way as go-ipfs does. The latest root
+//! is stored in the repo datastore, so it is available after a restart.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use bytes::Bytes;
+use libipld::{Cid, IpldCodec};
+use rust_unixfs::{
+    dir::builder::{render_directory, HAMT_SHARDING_SIZE},
+    file::{adder::FileAdder, FileLink},
+    walk::{ContinuedWalk, Walker},
+    ListedLink, ResolveError,
+};
+use tokio::sync::Mutex;
+
+use crate::{repo::Repo, Block};
+
+use super::{EntryType, TraversalFailed, UnixfsCat};
+
+/// Datastore key of the MFS root; same as the one used by go-ipfs.
+pub(crate) const MFS_ROOT_KEY: &[u8] = b"/local/filesroot";
+
+/// Links of a single directory: name to the target and its cumulative size.
+type Entries = BTreeMap<String, (Cid, u64)>;
+
+/// Handle to the mutable file system of a repo.
+///
+/// Clones share the same root and all of the operations are serialized, so that concurrent
+/// modifications do not overwrite each other.
+#[derive(Clone)]
+pub struct Mfs {
+    repo: Repo,
+    root: Arc<Mutex<Option<Cid>>>,
+}
+
+/// Options for [`Mfs::write`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Create the file if it does not exist.
+    pub create: bool,
+    /// Discard the existing contents of the file before writing.
+    pub truncate: bool,
+    /// Byte offset at which the data is written. Can be at most the current length of the file.
+    pub offset: u64,
+}
+
+/// Result of [`Mfs::stat`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MfsStat {
+    pub cid: Cid,
+    /// Size of the file contents, or the length of the target for symlinks. Zero for directories.
+    pub size: u64,
+    /// Size of the whole DAG behind the entry.
+    pub cumulative_size: u64,
+    pub entry_type: EntryType,
+}
+
+/// Failures of the MFS operations.
+#[derive(Debug, thiserror::Error)]
+pub enum MfsError {
+    #[error("invalid path {0:?}")]
+    InvalidPath(String),
+
+    #[error("{0} does not exist")]
+    NotFound(String),
+
+    #[error("{0} already exists")]
+    AlreadyExists(String),
+
+    #[error("{0} is not a directory")]
+    NotADirectory(String),
+
+    #[error("{0} is a directory")]
+    IsADirectory(String),
+
+    #[error("cannot move {0} into itself")]
+    MoveIntoItself(String),
+
+    #[error("offset {offset} is past the end of {path} ({size} bytes)")]
+    OffsetPastEnd {
+        path: String,
+        offset: u64,
+        size: u64,
+    },
+
+    #[error(transparent)]
+    Traversal(#[from] TraversalFailed),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl Mfs {
+    pub(crate) fn new(repo: Repo) -> Self {
+        Self {
+            repo,
+            root: Default::default(),
+        }
+    }
+
+    /// Creates a directory. With `parents`, creates the missing parent directories as well and
+    /// does not fail if the directory already exists.
+    pub async fn mkdir(&self, path: &str, parents: bool) -> Result<(), MfsError> {
+        let segments = split_path(path)?;
+        let Some((name, dirs)) = segments.split_last() else {
+            if parents {
+                return Ok(());
+            }
+            return Err(MfsError::AlreadyExists(path.to_owned()));
+        };
+
+        let mut state = self.root.lock().await;
+        let _g = self.repo.gc_guard().await;
+        let root = self.root(&mut state).await?;
+
+        let mut loaded = self.load_path(root, dirs, parents).await?;
+        let parent = loaded.last_mut().expect("root is always loaded");
+
+        match parent.get(*name) {
+            Some((cid, _)) if parents => {
+                return self.load_dir(cid, path).await.map(|_| ());
+            }
+            Some(_) => return Err(MfsError::AlreadyExists(path.to_owned())),
+            None => {
+                let empty = self.store_dir(&Entries::new()).await?;
+                parent.insert(name.to_string(), empty);
+            }
+        }
+
+        let root = self.store_path(dirs, loaded).await?;
+        self.set_root(&mut state, root).await
+    }
+
+    /// Writes `data` into the file at `path`, starting from [`WriteOptions::offset`]. Any
+    /// existing contents after the written range are kept, unless the file is truncated first.
+    pub async fn write(
+        &self,
+        path: &str,
+        data: impl AsRef<[u8]>,
+        options: WriteOptions,
+    ) -> Result<(), MfsError> {
+        let data = data.as_ref();
+        let segments = split_path(path)?;
+        let Some((name, dirs)) = segments.split_last() else {
+            return Err(MfsError::IsADirectory(path.to_owned()));
+        };
+
+        let mut state = self.root.lock().await;
+        let _g = self.repo.gc_guard().await;
+        let root = self.root(&mut state).await?;
+
+        let mut loaded = self.load_path(root, dirs, false).await?;
+        let parent = loaded.last_mut().expect("root is always loaded");
+
+        let existing = match parent.get(*name) {
+            Some((cid, _)) if options.truncate => {
+                self.file_size(cid, path).await?;
+                None
+            }
+            Some((cid, _)) => Some((*cid, self.file_size(cid, path).await?)),
+            None if options.create => None,
+            None => return Err(MfsError::NotFound(path.to_owned())),
+        };
+
+        let size = existing.map(|(_, size)| size).unwrap_or_default();
+        if options.offset > size {
+            return Err(MfsError::OffsetPastEnd {
+                path: path.to_owned(),
+                offset: options.offset,
+                size,
+            });
+        }
+
+        let file = match existing {
+            Some(_) if data.is_empty() => return Ok(()),
+            Some((cid, size)) => self.patch_file(&cid, size, options.offset, data).await?,
+            None => self.store_file(data).await?,
+        };
+        parent.insert(name.to_string(), file);
+
+        let root = self.store_path(dirs, loaded).await?;
+        self.set_root(&mut state, root).await
+    }
+
+    /// Reads the whole contents of the file at `path`.
+    pub async fn read(&self, path: &str) -> Result<Bytes, MfsError> {
+        let segments = split_path(path)?;
+
+        let mut state = self.root.lock().await;
+        let root = self.root(&mut state).await?;
+
+        let (cid, _) = self.lookup(root, &segments, path).await?;
+        self.read_file(&cid, path).await
+    }
+
+    /// Moves the file or directory at `from` to `to`. When `to` is an existing directory, the
+    /// entry is moved inside it.
+    pub async fn mv(&self, from: &str, to: &str) -> Result<(), MfsError> {
+        let source = split_path(from)?;
+        let mut target = split_path(to)?;
+
+        let Some((name, source_dirs)) = source.split_last() else {
+            return Err(MfsError::InvalidPath(from.to_owned()));
+        };
+
+        if target == source {
+            return Ok(());
+        }
+
+        if target.starts_with(&source) {
+            return Err(MfsError::MoveIntoItself(from.to_owned()));
+        }
+
+        let mut state = self.root.lock().await;
+        let _g = self.repo.gc_guard().await;
+        let root = self.root(&mut state).await?;
+
+        let mut loaded = self.load_path(root, source_dirs, false).await?;
+        let entry = loaded
+            .last_mut()
+            .expect("root is always loaded")
+            .remove(*name)
+            .ok_or_else(|| MfsError::NotFound(from.to_owned()))?;
+        let root = self.store_path(source_dirs, loaded).await?;
+
+        match self.lookup(root, &target, to).await {
+            Ok((cid, _)) => match self.load_dir(&cid, to).await {
+                Ok(_) => target.push(*name),
+                Err(MfsError::NotADirectory(_)) => {
+                    return Err(MfsError::AlreadyExists(to.to_owned()))
+                }
+                Err(e) => return Err(e),
+            },
+            Err(MfsError::NotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
+
+        let (name, dirs) = target.split_last().expect("root always exists");
+
+        let mut loaded = self.load_path(root, dirs, false).await?;
+        let parent = loaded.last_mut().expect("root is always loaded");
+
+        if parent.contains_key(*name) {
+            return Err(MfsError::AlreadyExists(join_path(&target)));
+        }
+        parent.insert(name.to_string(), entry);
+
+        let root = self.store_path(dirs, loaded).await?;
+        self.set_root(&mut state, root).await
+    }
+
+    /// Removes the file at `path`. Directories are only removed when `recursive` is set.
+    pub async fn rm(&self, path: &str, recursive: bool) -> Result<(), MfsError> {
+        let segments = split_path(path)?;
+        let Some((name, dirs)) = segments.split_last() else {
+            return Err(MfsError::InvalidPath(path.to_owned()));
+        };
+
+        let mut state = self.root.lock().await;
+        let _g = self.repo.gc_guard().await;
+        let root = self.root(&mut state).await?;
+
+        let mut loaded = self.load_path(root, dirs, false).await?;
+        let parent = loaded.last_mut().expect("root is always loaded");
+
+        let (cid, _) = *parent
+            .get(*name)
+            .ok_or_else(|| MfsError::NotFound(path.to_owned()))?;
+
+        if !recursive && self.entry_type(&cid).await?.0 == EntryType::Directory {
+            return Err(MfsError::IsADirectory(path.to_owned()));
+        }
+
+        parent.remove(*name);
+
+        let root = self.store_path(dirs, loaded).await?;
+        self.set_root(&mut state, root).await
+    }
+
+    /// Returns the [`Cid`], type and sizes of the entry at `path`.
+    pub async fn stat(&self, path: &str) -> Result<MfsStat, MfsError> {
+        let segments = split_path(path)?;
+
+        let mut state = self.root.lock().await;
+        let root = self.root(&mut state).await?;
+
+        let (cid, cumulative_size) = if segments.is_empty() {
+            let block = self.repo.get_block(&root, &[], true).await?;
+            let links = self.load_dir(&root, path).await?;
+            let size = links.values().map(|(_, size)| size).sum::<u64>();
+            (root, block.data().len() as u64 + size)
+        } else {
+            self.lookup(root, &segments, path).await?
+        };
+
+        let (entry_type, size) = self.entry_type(&cid).await?;
+
+        Ok(MfsStat {
+            cid,
+            size,
+            cumulative_size,
+            entry_type,
+        })
+    }
+
+    /// Returns the [`Cid`] of the current root. The root is stored after every modification, so
+    /// this only creates and stores the initial empty root when nothing has been written yet.
+    pub async fn flush(&self) -> Result<Cid, MfsError> {
+        let mut state = self.root.lock().await;
+        self.root(&mut state).await
+    }
+
+    async fn root(&self, state: &mut Option<Cid>) -> Result<Cid, MfsError> {
+        if let Some(root) = *state {
+            return Ok(root);
+        }
+
+        let root = match self.repo.data_store().get(MFS_ROOT_KEY).await? {
+            Some(bytes) => Cid::try_from(bytes.as_slice()).map_err(anyhow::Error::from)?,
+            None => {
+                let (root, _) = self.store_dir(&Entries::new()).await?;
+                self.repo
+                    .data_store()
+                    .put(MFS_ROOT_KEY, &root.to_bytes())
+                    .await?;
+                root
+            }
+        };
+
+        *state = Some(root);
+        Ok(root)
+    }
+
+    async fn set_root(&self, state: &mut Option<Cid>, root: Cid) -> Result<(), MfsError> {
+        self.repo
+            .data_store()
+            .put(MFS_ROOT_KEY, &root.to_bytes())
+            .await?;
+        *state = Some(root);
+        Ok(())
+    }
+
+    /// Returns the link to the entry at `segments`, with the cumulative size as recorded in the
+    /// parent directory.
+    async fn lookup(
+        &self,
+        root: Cid,
+        segments: &[&str],
+        path: &str,
+    ) -> Result<(Cid, u64), MfsError> {
+        let Some((name, dirs)) = segments.split_last() else {
+            return Ok((root, 0));
+        };
+
+        let loaded = self.load_path(root, dirs, false).await?;
+        let parent = loaded.last().expect("root is always loaded");
+
+        parent
+            .get(*name)
+            .copied()
+            .ok_or_else(|| MfsError::NotFound(path.to_owned()))
+    }
+
+    /// Loads the directories from the root down to `dirs`, optionally starting new empty ones
+    /// for the missing directories.
+    async fn load_path(
+        &self,
+        root: Cid,
+        dirs: &[&str],
+        create: bool,
+    ) -> Result<Vec<Entries>, MfsError> {
+        let mut loaded = vec![self.load_dir(&root, "/").await?];
+
+        for (i, name) in dirs.iter().enumerate() {
+            let current = loaded.last().expect("root is always loaded");
+            let entries = match current.get(*name) {
+                Some((cid, _)) => self.load_dir(cid, &join_path(&dirs[..=i])).await?,
+                None if create => Entries::new(),
+                None => return Err(MfsError::NotFound(join_path(&dirs[..=i]))),
+            };
+            loaded.push(entries);
+        }
+
+        Ok(loaded)
+    }
+
+    /// Stores the directories loaded by [`Mfs::load_path`] from the deepest one upwards, linking
+    /// each to its parent, and returns the new root.
+    async fn store_path(&self, dirs: &[&str], mut loaded: Vec<Entries>) -> Result<Cid, MfsError> {
+        let mut entries = loaded.pop().expect("root is always loaded");
+
+        for name in dirs.iter().rev() {
+            let stored = self.store_dir(&entries).await?;
+            entries = loaded.pop().expect("one directory per segment");
+            entries.insert(name.to_string(), stored);
+        }
+
+        Ok(self.store_dir(&entries).await?.0)
+    }
+
+    async fn load_dir(&self, cid: &Cid, path: &str) -> Result<Entries, MfsError> {
+        let mut entries = Entries::new();
+        let mut pending = vec![*cid];
+
+        while let Some(cid) = pending.pop() {
+            if cid.codec() == u64::from(IpldCodec::Raw) {
+                return Err(MfsError::NotADirectory(path.to_owned()));
+            }
+
+            let block = self.repo.get_block(&cid, &[], true).await?;
+
+            let links = match rust_unixfs::list(block.data()) {
+                Ok(links) => links,
+                Err(ResolveError::UnexpectedType(_)) => {
+                    return Err(MfsError::NotADirectory(path.to_owned()))
+                }
+                Err(e) => return Err(anyhow::Error::from(e).into()),
+            };
+
+            for link in links {
+                match link {
+                    ListedLink::Entry { name, cid, size } => {
+                        entries.insert(name, (cid, size));
+                    }
+                    ListedLink::Bucket(cid) => pending.push(cid),
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Stores the directory, sharding it once it grows past the go-ipfs threshold and turning it
+    /// back into a plain directory when it shrinks, and returns its root and cumulative size.
+    async fn store_dir(&self, entries: &Entries) -> Result<(Cid, u64), MfsError> {
+        let nodes =
+            render_directory(entries, Some(HAMT_SHARDING_SIZE)).map_err(anyhow::Error::from)?;
+        let mut stored = None;
+
+        for node in nodes {
+            self.repo
+                .put_block(Block::new(node.cid, node.block.into())?)
+                .await?;
+            stored = Some((node.cid, node.total_size));
+        }
+
+        Ok(stored.expect("the root is always rendered"))
+    }
+
+    /// Stores a new file with the default chunking, returning its root and the cumulative size.
+    async fn store_file(&self, content: &[u8]) -> Result<(Cid, u64), MfsError> {
+        let mut adder = FileAdder::default();
+        let mut blocks = Vec::new();
+
+        push_content(&mut adder, content, &mut blocks);
+        blocks.extend(adder.finish());
+
+        self.store_file_blocks(blocks).await
+    }
+
+    /// Writes `data` at `offset` of the file of `size` bytes at `cid`. Only the leaves the write
+    /// touches are read and chunked again, along with the appended bytes; the leaves after them
+    /// and the whole subtrees before them are linked as they are. Only the new leaves and the
+    /// nodes above them are stored.
+    async fn patch_file(
+        &self,
+        cid: &Cid,
+        size: u64,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(Cid, u64), MfsError> {
+        let end = offset + data.len() as u64;
+
+        let mut adder = FileAdder::default();
+        let mut blocks = Vec::new();
+
+        // the subtrees before the write with their level below the root; their depth is only
+        // known once the first leaf is reached
+        let mut before = Vec::new();
+        let mut leaf_level = None;
+
+        let mut pending: Vec<(Cid, Option<FileLink>, u64, usize)> = vec![(*cid, None, 0, 0)];
+
+        while let Some((cid, link, start, level)) = pending.pop() {
+            if let Some(link) = link {
+                let stop = start + link.file_size;
+
+                // the last part of the file is read when appending, to fill up its last chunk
+                if leaf_level.is_none() && (stop < offset || (stop == offset && stop < size)) {
+                    before.push((link, level));
+                    continue;
+                }
+
+                if start >= end && leaf_level.map(|leaves| level >= leaves).unwrap_or(false) {
+                    blocks.extend(adder.push_link(link, 0));
+                    continue;
+                }
+            }
+
+            let block = self.repo.get_block(&cid, &[], true).await?;
+            let (content, links) = if cid.codec() == u64::from(IpldCodec::Raw) {
+                (block.data(), Vec::new())
+            } else {
+                rust_unixfs::file::links(block.data()).map_err(anyhow::Error::from)?
+            };
+
+            if leaf_level.is_none() && (links.is_empty() || !content.is_empty()) {
+                let leaves = if links.is_empty() { level } else { level + 1 };
+                for (link, level) in before.drain(..) {
+                    blocks.extend(adder.push_link(link, leaves.saturating_sub(level)));
+                }
+                leaf_level = Some(leaves);
+            }
+
+            if !content.is_empty() {
+                let mut content = content.to_vec();
+                let from = offset.max(start);
+                let to = end.min(start + content.len() as u64);
+                if from < to {
+                    content[(from - start) as usize..(to - start) as usize]
+                        .copy_from_slice(&data[(from - offset) as usize..(to - offset) as usize]);
+                }
+                push_content(&mut adder, &content, &mut blocks);
+            }
+
+            let mut child_start = start + content.len() as u64;
+            let children = links
+                .into_iter()
+                .map(|link| {
+                    let child = (link.cid, Some(link), child_start, level + 1);
+                    child_start += link.file_size;
+                    child
+                })
+                .collect::<Vec<_>>();
+            pending.extend(children.into_iter().rev());
+        }
+
+        if end > size {
+            push_content(&mut adder, &data[(size - offset) as usize..], &mut blocks);
+        }
+        blocks.extend(adder.finish());
+
+        self.store_file_blocks(blocks).await
+    }
+
+    /// Stores the blocks created by a [`FileAdder`], the last of which is the root, and returns the
+    /// root with its cumulative size.
+    async fn store_file_blocks(&self, blocks: Vec<(Cid, Vec<u8>)>) -> Result<(Cid, u64), MfsError> {
+        let (root, block) = blocks
+            .last()
+            .ok_or_else(|| anyhow::anyhow!("no blocks were created"))?;
+
+        let (_, links) = rust_unixfs::file::links(block).map_err(anyhow::Error::from)?;
+        let size = block.len() as u64 + links.iter().map(|link| link.total_size).sum::<u64>();
+        let root = *root;
+
+        for (cid, block) in blocks {
+            self.repo.put_block(Block::new(cid, block)?).await?;
+        }
+
+        Ok((root, size))
+    }
+
+    async fn read_file(&self, cid: &Cid, path: &str) -> Result<Bytes, MfsError> {
+        self.file_size(cid, path).await?;
+        Ok(UnixfsCat::with_repo(&self.repo, *cid).local().await?)
+    }
+
+    /// Checks that the entry at `path` is a file, returning the size of its contents.
+    async fn file_size(&self, cid: &Cid, path: &str) -> Result<u64, MfsError> {
+        match self.entry_type(cid).await? {
+            (EntryType::File, size) => Ok(size),
+            (EntryType::Directory, _) => Err(MfsError::IsADirectory(path.to_owned())),
+            _ => Err(anyhow::anyhow!("{path} is not a file").into()),
+        }
+    }
+
+    /// Returns the type and the size of the contents of the given entry.
+    async fn entry_type(&self, cid: &Cid) -> Result<(EntryType, u64), MfsError> {
+        let block = self.repo.get_block(cid, &[], true).await?;
+        let mut walker = Walker::new(*cid, String::new());
+
+        let found = match walker
+            .next(block.data(), &mut None)
+            .map_err(anyhow::Error::from)?
+        {
+            ContinuedWalk::File(.., size) => (EntryType::File, size),
+            ContinuedWalk::RootDirectory(..) => (EntryType::Directory, 0),
+            ContinuedWalk::Symlink(target, ..) => (EntryType::Symlink, target.len() as u64),
+            ContinuedWalk::Directory(..) | ContinuedWalk::Bucket(..) => (EntryType::Unknown, 0),
+        };
+
+        Ok(found)
+    }
+}
+
+/// Pushes `content` to the adder, collecting the created blocks.
+fn push_content(adder: &mut FileAdder, content: &[u8], blocks: &mut Vec<(Cid, Vec<u8>)>) {
+    let mut total = 0;
+
+    while total < content.len() {
+        let (produced, consumed) = adder.push(&content[total..]);
+        blocks.extend(produced);
+        total += consumed;
+    }
+}
+
+/// Splits an absolute MFS path into its segments; the root has none.
+fn split_path(path: &str) -> Result<Vec<&str>, MfsError> {
+    let invalid = || MfsError::InvalidPath(path.to_owned());
+
+    let rest = path.strip_prefix('/').ok_or_else(invalid)?;
+    let rest = rest.strip_suffix('/').unwrap_or(rest);
+
+    if rest.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    rest.split('/')
+        .map(|segment| match segment {
+            "" | "." | ".." => Err(invalid()),
+            segment => Ok(segment),
+        })
+        .collect()
+}
+
+fn join_path(segments: &[&str]) -> String {
+    format!("/{}", segments.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entries, EntryType, Mfs, MfsError, WriteOptions};
+    use crate::repo::Repo;
+    use libipld::Cid;
+    use rust_unixfs::ListedLink;
+
+    const CREATE: WriteOptions = WriteOptions {
+        create: true,
+        truncate: false,
+        offset: 0,
+    };
+
+    #[tokio::test]
+    async fn nested_writes() {
+        let mfs = Mfs::new(Repo::new_memory());
+
+        mfs.mkdir("/a/b/c", true).await.unwrap();
+        mfs.write("/a/b/c/file", b"hello", CREATE).await.unwrap();
+        mfs.write("/a/other", b"world", CREATE).await.unwrap();
+
+        assert_eq!(mfs.read("/a/b/c/file").await.unwrap(), "hello");
+        assert_eq!(mfs.read("/a/other").await.unwrap(), "world");
+
+        let stat = mfs.stat("/a/b/c/file").await.unwrap();
+        assert_eq!(stat.entry_type, EntryType::File);
+        assert_eq!(stat.size, 5);
+
+        let stat = mfs.stat("/a/b").await.unwrap();
+        assert_eq!(stat.entry_type, EntryType::Directory);
+
+        // only the directories along the modified path change
+        let before = mfs.stat("/a/b").await.unwrap().cid;
+        mfs.write("/a/other", b"again", CREATE).await.unwrap();
+        assert_eq!(mfs.stat("/a/b").await.unwrap().cid, before);
+
+        assert!(matches!(
+            mfs.write("/missing/file", b"x", CREATE).await,
+            Err(MfsError::NotFound(path)) if path == "/missing"
+        ));
+        assert!(matches!(
+            mfs.write("/a/b", b"x", CREATE).await,
+            Err(MfsError::IsADirectory(_))
+        ));
+        assert!(matches!(
+            mfs.mkdir("/a/other/dir", false).await,
+            Err(MfsError::NotADirectory(_))
+        ));
+        assert!(matches!(
+            mfs.mkdir("/a", false).await,
+            Err(MfsError::AlreadyExists(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn partial_offset_writes() {
+        let mfs = Mfs::new(Repo::new_memory());
+
+        mfs.write("/file", b"hello world", CREATE).await.unwrap();
+
+        let overwrite = WriteOptions {
+            offset: 6,
+            ..Default::default()
+        };
+        mfs.write("/file", b"there", overwrite).await.unwrap();
+        assert_eq!(mfs.read("/file").await.unwrap(), "hello there");
+
+        let append = WriteOptions {
+            offset: 11,
+            ..Default::default()
+        };
+        mfs.write("/file", b"!!", append).await.unwrap();
+        assert_eq!(mfs.read("/file").await.unwrap(), "hello there!!");
+
+        let truncate = WriteOptions {
+            truncate: true,
+            ..Default::default()
+        };
+        mfs.write("/file", b"bye", truncate).await.unwrap();
+        assert_eq!(mfs.read("/file").await.unwrap(), "bye");
+
+        let past_end = WriteOptions {
+            offset: 4,
+            ..Default::default()
+        };
+        assert!(matches!(
+            mfs.write("/file", b"x", past_end).await,
+            Err(MfsError::OffsetPastEnd { size: 3, .. })
+        ));
+
+        // a write spanning multiple chunks of the default chunker
+        let large = vec![1u8; 600 * 1024];
+        mfs.write("/large", &large, CREATE).await.unwrap();
+
+        let middle = WriteOptions {
+            offset: 300 * 1024,
+            ..Default::default()
+        };
+        mfs.write("/large", [2u8; 10], middle).await.unwrap();
+
+        let read = mfs.read("/large").await.unwrap();
+        assert_eq!(read.len(), large.len());
+        assert_eq!(&read[300 * 1024..300 * 1024 + 10], &[2u8; 10]);
+        assert_eq!(read.iter().filter(|b| **b == 1).count(), large.len() - 10);
+    }
+
+    #[tokio::test]
+    async fn writes_only_replace_the_touched_chunks() {
+        const CHUNK: usize = 256 * 1024;
+
+        let mfs = Mfs::new(Repo::new_memory());
+
+        let content = vec![1u8; 3 * CHUNK + 100];
+        mfs.write("/file", &content, CREATE).await.unwrap();
+        let before = leaves(&mfs, "/file").await;
+        assert_eq!(before.len(), 4);
+
+        let middle = WriteOptions {
+            offset: (CHUNK + 10) as u64,
+            ..Default::default()
+        };
+        mfs.write("/file", [2u8; 10], middle).await.unwrap();
+
+        let after = leaves(&mfs, "/file").await;
+        assert_eq!(after.len(), 4);
+        assert_ne!(after[1], before[1]);
+        assert_eq!(
+            [after[0], after[2], after[3]],
+            [before[0], before[2], before[3]]
+        );
+
+        // the last chunk is filled up before new ones are added
+        let append = WriteOptions {
+            offset: content.len() as u64,
+            ..Default::default()
+        };
+        mfs.write("/file", vec![3u8; CHUNK], append).await.unwrap();
+
+        let appended = leaves(&mfs, "/file").await;
+        assert_eq!(appended.len(), 5);
+        assert_eq!(appended[..3], after[..3]);
+        assert_ne!(appended[3], after[3]);
+
+        let mut expected = content;
+        expected[CHUNK + 10..CHUNK + 20].fill(2);
+        expected.extend(vec![3u8; CHUNK]);
+        assert_eq!(mfs.read("/file").await.unwrap(), expected);
+
+        // same as writing the contents at once
+        mfs.write("/fresh", &expected, CREATE).await.unwrap();
+        assert_eq!(
+            mfs.stat("/fresh").await.unwrap(),
+            mfs.stat("/file").await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn large_directories_stay_sharded() {
+        let mfs = Mfs::new(Repo::new_memory());
+
+        mfs.write("/file", b"shared", CREATE).await.unwrap();
+        let file = mfs
+            .lookup(mfs.flush().await.unwrap(), &["file"], "/file")
+            .await
+            .unwrap();
+
+        // large enough for go-ipfs to shard it
+        let entries = (0..7000)
+            .map(|i| (format!("{i:05}"), file))
+            .collect::<Entries>();
+        let big = mfs.store_dir(&entries).await.unwrap();
+
+        {
+            let mut state = mfs.root.lock().await;
+            let root = mfs.root(&mut state).await.unwrap();
+            let mut loaded = mfs.load_path(root, &[], false).await.unwrap();
+            loaded[0].insert("big".into(), big);
+            let root = mfs.store_path(&[], loaded).await.unwrap();
+            mfs.set_root(&mut state, root).await.unwrap();
+        }
+        assert!(is_sharded(&mfs, "/big").await);
+
+        mfs.write("/big/new", b"x", CREATE).await.unwrap();
+        mfs.rm("/big/00000", false).await.unwrap();
+
+        assert!(is_sharded(&mfs, "/big").await);
+        assert_eq!(ls_names(&mfs, "/big").await.len(), 7000);
+        assert_eq!(mfs.read("/big/new").await.unwrap(), "x");
+        assert_eq!(mfs.read("/big/06999").await.unwrap(), "shared");
+        assert!(!is_sharded(&mfs, "/").await);
+    }
+
+    #[tokio::test]
+    async fn directory_moves() {
+        let mfs = Mfs::new(Repo::new_memory());
+
+        mfs.mkdir("/src/nested", true).await.unwrap();
+        mfs.write("/src/nested/file", b"content", CREATE)
+            .await
+            .unwrap();
+        mfs.mkdir("/dst", false).await.unwrap();
+
+        // into an existing directory
+        mfs.mv("/src/nested", "/dst").await.unwrap();
+        assert_eq!(mfs.read("/dst/nested/file").await.unwrap(), "content");
+        assert!(matches!(
+            mfs.stat("/src/nested").await,
+            Err(MfsError::NotFound(_))
+        ));
+
+        // renaming
+        mfs.mv("/dst/nested", "/renamed").await.unwrap();
+        assert_eq!(mfs.read("/renamed/file").await.unwrap(), "content");
+
+        assert!(matches!(
+            mfs.mv("/renamed", "/renamed/inside").await,
+            Err(MfsError::MoveIntoItself(_))
+        ));
+
+        mfs.write("/other", b"x", CREATE).await.unwrap();
+        assert!(matches!(
+            mfs.mv("/renamed/file", "/other").await,
+            Err(MfsError::AlreadyExists(_))
+        ));
+
+        assert!(matches!(
+            mfs.rm("/renamed", false).await,
+            Err(MfsError::IsADirectory(_))
+        ));
+        mfs.rm("/renamed", true).await.unwrap();
+        mfs.rm("/other", false).await.unwrap();
+
+        let root = mfs.stat("/").await.unwrap();
+        assert_eq!(root.entry_type, EntryType::Directory);
+        assert_eq!(ls_names(&mfs, "/").await, ["dst", "src"]);
+    }
+
+    #[tokio::test]
+    async fn root_survives_restart() {
+        let repo = Repo::new_memory();
+
+        let mfs = Mfs::new(repo.clone());
+        let empty = mfs.flush().await.unwrap();
+        mfs.write("/file", b"persisted", CREATE).await.unwrap();
+        let root = mfs.flush().await.unwrap();
+        assert_ne!(root, empty);
+
+        let mfs = Mfs::new(repo);
+        assert_eq!(mfs.flush().await.unwrap(), root);
+        assert_eq!(mfs.read("/file").await.unwrap(), "persisted");
+    }
+
+    #[tokio::test]
+    async fn concurrent_writes_do_not_fork() {
+        let mfs = Mfs::new(Repo::new_memory());
+
+        let writes = (0..16).map(|i| {
+            let mfs = mfs.clone();
+            async move { mfs.write(&format!("/file-{i}"), [i as u8], CREATE).await }
+        });
+
+        for result in futures::future::join_all(writes).await {
+            result.unwrap();
+        }
+
+        assert_eq!(ls_names(&mfs, "/").await.len(), 16);
+    }
+
+    async fn leaves(mfs: &Mfs, path: &str) -> Vec<Cid> {
+        let cid = mfs.stat(path).await.unwrap().cid;
+        let block = mfs.repo.get_block(&cid, &[], true).await.unwrap();
+        let (_, links) = rust_unixfs::file::links(block.data()).unwrap();
+        links.into_iter().map(|link| link.cid).collect()
+    }
+
+    async fn is_sharded(mfs: &Mfs, path: &str) -> bool {
+        let cid = mfs.stat(path).await.unwrap().cid;
+        let block = mfs.repo.get_block(&cid, &[], true).await.unwrap();
+        rust_unixfs::list(block.data())
+            .unwrap()
+            .iter()
+            .any(|link| matches!(link, ListedLink::Bucket(_)))
+    }
+
+    async fn ls_names(mfs: &Mfs, path: &str) -> Vec<String> {
+        let cid = mfs.stat(path).await.unwrap().cid;
+        mfs.load_dir(&cid, path)
+            .await
+            .unwrap()
+            .into_keys()
+            .collect()
+    }
+}
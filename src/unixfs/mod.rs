@@ -17,15 +17,26 @@ pub use rust_unixfs as ll;
 
 pub mod add;
 mod cat;
+mod dir;
+mod gateway;
 mod get;
 mod ls;
-pub use add::UnixfsAdd;
+mod mfs;
+mod read_ahead;
+pub use add::{AddOptions, Layout, UnixfsAdd};
 pub use cat::{StartingPoint, UnixfsCat};
-pub use get::UnixfsGet;
-pub use ls::{Entry, UnixfsLs};
+pub use dir::{DirBuildError, UnixfsDir, UnixfsDirBuilder};
+pub(crate) use gateway::respond as gateway_respond;
+pub use gateway::{GatewayRequest, GatewayResponse};
+pub use get::{GetOptions, GetProgress, UnixfsGet};
+#[allow(deprecated)]
+pub use ls::{DirEntry, Entry, EntryType, UnixfsLs};
+pub(crate) use mfs::MFS_ROOT_KEY;
+pub use mfs::{Mfs, MfsError, MfsStat, WriteOptions};
 
 use crate::{
     dag::{ResolveError, UnexpectedResolved},
+    repo::PutStats,
     Ipfs, IpfsPath,
 };
 
@@ -159,7 +170,7 @@ impl IpfsUnixfs {
         UnixfsGet::with_ipfs(&self.ipfs, path, dest)
     }
 
-    /// List directory contents
+    /// List the entries of a directory, including the entries of HAMT sharded directories
     pub fn ls(&self, path: IpfsPath) -> UnixfsLs {
         UnixfsLs::with_ipfs(&self.ipfs, path)
     }
@@ -175,6 +186,8 @@ pub enum UnixfsStatus {
         path: IpfsPath,
         written: usize,
         total_size: Option<usize>,
+        /// Blocks written and deduplicated by an add, none for a get
+        blocks: PutStats,
     },
     FailedStatus {
         written: usize,
@@ -210,16 +223,496 @@ pub enum TraversalFailed {
     #[error("Timeout while resolving {path}")]
     Timeout { path: IpfsPath },
 
+    /// The walk did not complete before its deadline
+    #[error(transparent)]
+    Deadline(crate::error::Timeout),
+
     /// Processing of the block failed
     #[error("walk failed on {}", .0)]
     Walking(Cid, #[source] FileReadFailed),
 
     #[error(transparent)]
     Io(std::io::Error),
+
+    /// The given path was resolved to something other than a directory
+    #[error("{} is not a directory", .0)]
+    NotADirectory(Cid),
+
+    /// Listing the links of a directory or a bucket of a sharded directory failed
+    #[error("listing {} failed", .0)]
+    Listing(Cid, #[source] ll::ResolveError),
 }
 
 #[cfg(test)]
 mod tests {
+    use super::{
+        AddOptions, DirEntry, EntryType, GetOptions, GetProgress, Layout, TraversalFailed,
+        UnixfsStatus, WriteOptions,
+    };
+    use crate::{repo::PutStats, Block, IpfsPath, Node};
+    use futures::StreamExt;
+    use libipld::multihash::{Code, MultihashDigest};
+    use libipld::Cid;
+    use rust_unixfs::dir::builder::{BufferingTreeBuilder, TreeOptions};
+    use rust_unixfs::file::adder::{Chunker, FileAdder};
+
+    #[tokio::test]
+    async fn add_with_options() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        let data = b"hello, world\n".repeat(1024);
+
+        let options = [
+            AddOptions {
+                chunker: Chunker::Size(1024),
+                raw_leaves: true,
+                cid_version: 1,
+                batch_size: 3,
+                ..Default::default()
+            },
+            AddOptions {
+                chunker: Chunker::Rabin {
+                    min: 256,
+                    avg: 1024,
+                    max: 2048,
+                },
+                layout: Layout::Trickle,
+                hash: Code::Sha2_512,
+                cid_version: 1,
+                ..Default::default()
+            },
+        ];
+
+        for options in options {
+            let path = ipfs
+                .add_unixfs(("hello.txt".to_string(), data.clone()))
+                .options(options)
+                .wrap()
+                .await
+                .unwrap();
+
+            let cid = path.root().cid().unwrap();
+            assert_eq!(cid.version(), libipld::cid::Version::V1);
+            assert_eq!(cid.hash().code(), u64::from(options.hash));
+
+            let cat = ipfs.cat_unixfs(path).local().await.unwrap();
+            assert_eq!(cat, data, "{options:?}");
+        }
+
+        // the single raw leaf is the root
+        let path = ipfs
+            .add_unixfs(b"hello world".to_vec())
+            .options(AddOptions {
+                raw_leaves: true,
+                cid_version: 1,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            path.to_string(),
+            "/ipfs/bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e"
+        );
+        assert_eq!(ipfs.cat_unixfs(path).local().await.unwrap(), "hello world");
+
+        ipfs.add_unixfs(b"hello world".to_vec())
+            .options(AddOptions {
+                hash: Code::Sha2_512,
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn add_counts_the_deduplicated_blocks() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        // four identical leaves under a single root
+        let data = vec![0u8; 4096];
+        let options = AddOptions {
+            chunker: Chunker::Size(1024),
+            batch_size: 2,
+            ..Default::default()
+        };
+
+        for expected in [
+            PutStats {
+                new_blocks: 2,
+                existing_blocks: 3,
+            },
+            PutStats {
+                new_blocks: 0,
+                existing_blocks: 5,
+            },
+        ] {
+            let mut statuses = ipfs.add_unixfs(data.clone()).options(options);
+            let mut blocks = None;
+            while let Some(status) = statuses.next().await {
+                if let UnixfsStatus::CompletedStatus { blocks: put, .. } = status {
+                    blocks = Some(put);
+                }
+            }
+            assert_eq!(blocks, Some(expected));
+        }
+    }
+
+    #[tokio::test]
+    async fn ls_directory() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        let data = b"hello world".to_vec();
+
+        let path = ipfs
+            .add_unixfs(("hello.txt".to_string(), data.clone()))
+            .wrap()
+            .await
+            .unwrap();
+
+        let file = path.sub_path("hello.txt").unwrap();
+
+        let entries = ipfs.ls_unixfs(path.clone()).local().await.unwrap();
+        assert_eq!(entries.len(), 1);
+
+        let DirEntry {
+            name,
+            cid,
+            size,
+            entry_type,
+        } = &entries[0];
+        assert_eq!(name, "hello.txt");
+        assert_eq!(*size, data.len() as u64);
+        assert_eq!(*entry_type, EntryType::File);
+
+        let entries = ipfs
+            .ls_unixfs(path)
+            .resolve_children_size(false)
+            .local()
+            .await
+            .unwrap();
+        assert_eq!(entries[0].cid, *cid);
+        assert_eq!(entries[0].entry_type, EntryType::Unknown);
+
+        match ipfs.ls_unixfs(file).local().await {
+            Err(TraversalFailed::NotADirectory(found)) => assert_eq!(found, *cid),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn ls_sharded_directory() {
+        use crate::{Block, IpfsPath};
+        use hex_literal::hex;
+        use libipld::{multihash::MultihashDigest, Cid};
+        use std::collections::BTreeSet;
+
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        // go-ipfs sharded directory QmZbFPTnDBMWbQ6iBxQAhuhLz8Nu9XptYS96e7cuf5wvbk, its buckets
+        // holding two names each, all linking to the empty file
+        let blocks: [&[u8]; 10] = [
+            hex!("122b0a221220904e1485d68b56a71f79d44cd306d536ee52adb6a90c29b6a1fa95a504a038f71202303718b501122b0a221220772026a2c0e021710f8d0d8f72080255d5133556d3ae881e3405e673692f79a81202313318b001122b0a22122075e9df118a625120006c63b75c8f25f1e28397555ccf8c107029332d5e9b648a1202353418aa01122b0a221220db916fd000e12decdf0724965cbf419233a187ae415d59fbafea2c3851e584ad1202353618b101122b0a2212209adc67f730bd8b2f7eff8f2910ec8391814da9d7ae08d076165a9832bce99f921202383218af01122b0a221220bb48edba8f029483a6983ba70aef2cd86d14aa633f33007ce175680105da8d811202433118af01122b0a22122047b1f317152eb425d878e5e3577dd7c40af4bc2b005083c4bc9ec19157a8605c1202443418a601122b0a2212207b7e161cf9246d7fca2e2986aac98bbf2fef4f13f6fea497fc8f43d8899e0de51202463118a6010a280805121f020000001000020000000000000004000000000050000000000000000800802822308002"),
+            hex!("0a0408021800"),
+            hex!("123d0a221220bfccda787baba32b59c78450ac3d20b633360b43992c77289f9ed46d843561e6121534386c6f6e672d6e616d65642d66696c652d3031361806123d0a221220bfccda787baba32b59c78450ac3d20b633360b43992c77289f9ed46d843561e6121546426c6f6e672d6e616d65642d66696c652d30333718060a290805122008000000000000000000000000000000000000000000010000000000000000002822308002"),
+            hex!("123d0a221220bfccda787baba32b59c78450ac3d20b633360b43992c77289f9ed46d843561e6121538376c6f6e672d6e616d65642d66696c652d3035381806123d0a221220bfccda787baba32b59c78450ac3d20b633360b43992c77289f9ed46d843561e6121544446c6f6e672d6e616d65642d66696c652d30303918060a250805121c200000000000000000000080000000000000000000000000000000002822308002"),
+            hex!("123d0a221220bfccda787baba32b59c78450ac3d20b633360b43992c77289f9ed46d843561e6121536396c6f6e672d6e616d65642d66696c652d3033381806123d0a221220bfccda787baba32b59c78450ac3d20b633360b43992c77289f9ed46d843561e6121544356c6f6e672d6e616d65642d66696c652d30353018060a240805121b2000000000000000000000000002000000000000000000000000002822308002"),
+            hex!("123d0a221220bfccda787baba32b59c78450ac3d20b633360b43992c77289f9ed46d843561e6121532416c6f6e672d6e616d65642d66696c652d3034391806123d0a221220bfccda787baba32b59c78450ac3d20b633360b43992c77289f9ed46d843561e6121543436c6f6e672d6e616d65642d66696c652d30303418060a230805121a10000000000000000000000000000000000000000400000000002822308002"),
+            hex!("123d0a221220bfccda787baba32b59c78450ac3d20b633360b43992c77289f9ed46d843561e6121543346c6f6e672d6e616d65642d66696c652d3032351806123d0a221220bfccda787baba32b59c78450ac3d20b633360b43992c77289f9ed46d843561e6121543416c6f6e672d6e616d65642d66696c652d30333418060a230805121a04100000000000000000000000000000000000000000000000002822308002"),
+            hex!("123d0a221220bfccda787baba32b59c78450ac3d20b633360b43992c77289f9ed46d843561e6121534356c6f6e672d6e616d65642d66696c652d3034311806123d0a221220bfccda787baba32b59c78450ac3d20b633360b43992c77289f9ed46d843561e6121541366c6f6e672d6e616d65642d66696c652d30333318060a1e080512154000000000000000000000002000000000000000002822308002"),
+            hex!("123d0a221220bfccda787baba32b59c78450ac3d20b633360b43992c77289f9ed46d843561e6121536396c6f6e672d6e616d65642d66696c652d3031371806123d0a221220bfccda787baba32b59c78450ac3d20b633360b43992c77289f9ed46d843561e6121538306c6f6e672d6e616d65642d66696c652d30343018060a1a0805121101000002000000000000000000000000002822308002"),
+            hex!("123d0a221220bfccda787baba32b59c78450ac3d20b633360b43992c77289f9ed46d843561e6121536376c6f6e672d6e616d65642d66696c652d3030331806123d0a221220bfccda787baba32b59c78450ac3d20b633360b43992c77289f9ed46d843561e6121538356c6f6e672d6e616d65642d66696c652d30343818060a1a0805121120000000800000000000000000000000002822308002"),
+        ];
+        let mut cids = vec![];
+        for data in blocks {
+            let cid = Cid::new_v0(Code::Sha2_256.digest(data)).unwrap();
+            ipfs.put_block(Block::new(cid, data.to_vec()).unwrap())
+                .await
+                .unwrap();
+            cids.push(cid);
+        }
+        let (root, file) = (cids[0], cids[1]);
+        assert_eq!(
+            root.to_string(),
+            "QmZbFPTnDBMWbQ6iBxQAhuhLz8Nu9XptYS96e7cuf5wvbk"
+        );
+
+        let entries = ipfs.ls_unixfs(IpfsPath::from(root)).local().await.unwrap();
+        let names = [3, 4, 9, 16, 17, 25, 33, 34, 37, 38, 40, 41, 48, 49, 50, 58]
+            .map(|i| format!("long-named-file-{i:03}"));
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| entry.name.clone())
+                .collect::<BTreeSet<_>>(),
+            BTreeSet::from(names)
+        );
+        assert_eq!(entries.len(), 16);
+        assert!(entries
+            .iter()
+            .all(|entry| entry.cid == file && entry.entry_type == EntryType::File));
+    }
+
+    #[tokio::test]
+    async fn built_directories_are_loaded_with_typed_entries() {
+        use super::{UnixfsDir, UnixfsDirBuilder};
+
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        let file = ipfs
+            .add_unixfs(b"hello world".to_vec())
+            .await
+            .unwrap()
+            .root()
+            .cid()
+            .copied()
+            .unwrap();
+
+        let sharded = (0..20).fold(
+            UnixfsDirBuilder::new().sharding_size(Some(1)),
+            |builder, i| builder.add_link(format!("file-{i}"), file, 19),
+        );
+        let root = UnixfsDirBuilder::new()
+            .add_link("hello.txt", file, 19)
+            .add_dir("nested", UnixfsDirBuilder::new().add_link("a", file, 19))
+            .add_dir("sharded", sharded)
+            .build(&ipfs)
+            .await
+            .unwrap();
+
+        let dir = UnixfsDir::load(&ipfs, root).await.unwrap();
+        assert_eq!(dir.entries().len(), 3);
+        assert_eq!(dir.get("hello.txt").unwrap().entry_type, EntryType::File);
+        assert_eq!(dir.get("nested").unwrap().entry_type, EntryType::Directory);
+        assert_eq!(dir.get("sharded").unwrap().entry_type, EntryType::Directory);
+
+        let sharded = dir.get("sharded").unwrap().cid;
+        let sharded = UnixfsDir::load(&ipfs, sharded).await.unwrap();
+        assert_eq!(sharded.entries().len(), 20);
+        assert!(sharded.entries().iter().all(|entry| entry.cid == file));
+
+        // the names are checked once built
+        UnixfsDirBuilder::new()
+            .add_link("a", file, 19)
+            .add_link("a", file, 19)
+            .build(&ipfs)
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn get_directory() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        let create = WriteOptions {
+            create: true,
+            ..Default::default()
+        };
+        ipfs.files_mkdir("/dir/nested", true).await.unwrap();
+        ipfs.files_write("/dir/nested/file", b"hello", create)
+            .await
+            .unwrap();
+        ipfs.files_write("/dir/top", b"world", create)
+            .await
+            .unwrap();
+        let root = IpfsPath::from(ipfs.files_stat("/dir").await.unwrap().cid);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dest = tmp.path().join("out");
+
+        let (tx, rx) = futures::channel::mpsc::channel(16);
+        ipfs.get_unixfs(root.clone(), &dest)
+            .options(GetOptions {
+                progress: Some(tx),
+                ..Default::default()
+            })
+            .local()
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(dest.join("nested/file")).unwrap(), b"hello");
+        assert_eq!(std::fs::read(dest.join("top")).unwrap(), b"world");
+
+        let progress = rx.collect::<Vec<_>>().await;
+        assert_eq!(progress[0].entry, dest);
+        assert_eq!(
+            progress.last(),
+            Some(&GetProgress {
+                written: 10,
+                entry: dest.join("top")
+            })
+        );
+
+        // existing destinations are only replaced with overwrite
+        ipfs.get_unixfs(root.clone(), &dest)
+            .local()
+            .await
+            .unwrap_err();
+
+        std::fs::write(dest.join("top"), b"changed").unwrap();
+        ipfs.get_unixfs(root, &dest)
+            .options(GetOptions {
+                overwrite: true,
+                ..Default::default()
+            })
+            .local()
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(dest.join("top")).unwrap(), b"world");
+    }
+
+    #[tokio::test]
+    async fn get_failure_leaves_part_file() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        let mut adder = FileAdder::builder().with_chunker(Chunker::Size(4)).build();
+        let data = b"hello world";
+        let mut blocks = Vec::new();
+        let mut total = 0;
+        while total < data.len() {
+            let (produced, consumed) = adder.push(&data[total..]);
+            blocks.extend(produced);
+            total += consumed;
+        }
+        blocks.extend(adder.finish());
+
+        let root = blocks.last().unwrap().0;
+
+        // the second leaf, "o wo", is missing
+        for (i, (cid, data)) in blocks.into_iter().enumerate() {
+            if i != 1 {
+                ipfs.put_block(Block::new(cid, data).unwrap())
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dest = tmp.path().join("file");
+
+        ipfs.get_unixfs(IpfsPath::from(root), &dest)
+            .local()
+            .await
+            .unwrap_err();
+
+        assert!(!dest.exists());
+        assert_eq!(
+            std::fs::read(tmp.path().join("file.part")).unwrap(),
+            b"hell"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn get_symlinks() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        let mut symlink = Vec::new();
+        rust_unixfs::symlink::serialize_symlink_block("file", &mut symlink);
+        let symlink_cid = Cid::new_v0(Code::Sha2_256.digest(&symlink)).unwrap();
+        ipfs.put_block(Block::new(symlink_cid, symlink).unwrap())
+            .await
+            .unwrap();
+
+        let file = ipfs.add_unixfs(b"target".to_vec()).await.unwrap();
+        let file_cid = *file.root().cid().unwrap();
+
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+        let mut tree = BufferingTreeBuilder::new(opts);
+        tree.put_link("file", file_cid, 14).unwrap();
+        tree.put_link("link", symlink_cid, 0).unwrap();
+
+        let mut iter = tree.build();
+        let mut root = None;
+        while let Some(node) = iter.next_borrowed() {
+            let node = node.unwrap();
+            ipfs.put_block(Block::new(*node.cid, node.block.into()).unwrap())
+                .await
+                .unwrap();
+            root = Some(*node.cid);
+        }
+        let root = IpfsPath::from(root.unwrap());
+
+        let tmp = tempfile::tempdir().unwrap();
+
+        let dest = tmp.path().join("with");
+        ipfs.get_unixfs(root.clone(), &dest).local().await.unwrap();
+        assert_eq!(
+            std::fs::read_link(dest.join("link")).unwrap(),
+            std::path::Path::new("file")
+        );
+        assert_eq!(std::fs::read(dest.join("link")).unwrap(), b"target");
+
+        let dest = tmp.path().join("without");
+        ipfs.get_unixfs(root, &dest)
+            .options(GetOptions {
+                skip_symlinks: true,
+                ..Default::default()
+            })
+            .local()
+            .await
+            .unwrap();
+        assert!(dest.join("file").exists());
+        assert!(std::fs::symlink_metadata(dest.join("link")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn get_does_not_write_through_symlinks() {
+        use libipld::pb::{PbLink, PbNode};
+
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let outside = tmp.path().join("outside");
+        std::fs::create_dir(&outside).unwrap();
+
+        let mut symlink = Vec::new();
+        rust_unixfs::symlink::serialize_symlink_block(outside.to_str().unwrap(), &mut symlink);
+        let symlink_cid = Cid::new_v0(Code::Sha2_256.digest(&symlink)).unwrap();
+        ipfs.put_block(Block::new(symlink_cid, symlink).unwrap())
+            .await
+            .unwrap();
+
+        let file = ipfs.add_unixfs(b"escaped".to_vec()).await.unwrap();
+        let file_cid = *file.root().cid().unwrap();
+        let nested = super::UnixfsDirBuilder::new()
+            .add_link("evil", file_cid, 15)
+            .build(&ipfs)
+            .await
+            .unwrap();
+
+        // a malicious directory with a symlink followed by a directory of the same name
+        let link = |cid| PbLink {
+            cid,
+            name: Some("a".into()),
+            size: Some(0),
+        };
+        let root = PbNode {
+            links: vec![link(symlink_cid), link(nested)],
+            data: Some(vec![0x08, 0x01].into()),
+        }
+        .into_bytes()
+        .to_vec();
+        let root_cid = Cid::new_v0(Code::Sha2_256.digest(&root)).unwrap();
+        ipfs.put_block(Block::new(root_cid, root).unwrap())
+            .await
+            .unwrap();
+
+        let dest = tmp.path().join("out");
+        ipfs.get_unixfs(IpfsPath::from(root_cid), &dest)
+            .local()
+            .await
+            .unwrap_err();
+
+        assert!(std::fs::symlink_metadata(dest.join("a"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert!(!outside.join("evil").exists());
+        assert!(!outside.join("evil.part").exists());
+    }
+
     #[test]
     fn test_file_cid() {
         // note: old versions of `ipfs::unixfs::File` was an interface where user would provide the
@@ -0,0 +1,126 @@
+//! Fetching of the blocks about to be visited while the current one is being walked.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::Error;
+use libipld::Cid;
+use libp2p::PeerId;
+use tokio::task::JoinHandle;
+
+use crate::{repo::Repo, Block};
+
+/// Number of blocks fetched ahead of the walk by default.
+pub(crate) const DEFAULT_READ_AHEAD: usize = 16;
+
+/// Keeps up to `window` blocks in flight, all in the same bitswap session. The fetches not awaited
+/// yet are aborted once dropped, withdrawing their wants.
+pub(crate) struct ReadAhead {
+    repo: Repo,
+    session: Option<u64>,
+    providers: Vec<PeerId>,
+    local_only: bool,
+    timeout: Option<Duration>,
+    window: usize,
+    fetches: HashMap<Cid, JoinHandle<Result<Block, Error>>>,
+}
+
+impl ReadAhead {
+    pub(crate) fn new(
+        repo: Repo,
+        session: Option<u64>,
+        providers: Vec<PeerId>,
+        local_only: bool,
+        timeout: Option<Duration>,
+        window: usize,
+    ) -> Self {
+        Self {
+            repo,
+            session,
+            providers,
+            local_only,
+            timeout,
+            window: window.max(1),
+            fetches: HashMap::new(),
+        }
+    }
+
+    /// Returns the block of `next`, after starting the fetches of the blocks following it in the
+    /// walk, as far as the window allows.
+    pub(crate) async fn get<'a>(
+        &mut self,
+        next: &Cid,
+        following: impl Iterator<Item = &'a Cid>,
+    ) -> Result<Block, Error> {
+        for cid in std::iter::once(next).chain(following).take(self.window) {
+            if self.fetches.contains_key(cid) {
+                continue;
+            }
+
+            let repo = self.repo.clone();
+            let providers = self.providers.clone();
+            let (session, local_only, timeout, cid) =
+                (self.session, self.local_only, self.timeout, *cid);
+
+            let fetch = tokio::spawn(async move {
+                repo.get_block_with_session(session, &cid, &providers, local_only, timeout)
+                    .await
+            });
+            self.fetches.insert(cid, fetch);
+        }
+
+        let fetch = self.fetches.remove(next).expect("fetch was started above");
+        fetch.await?
+    }
+}
+
+impl Drop for ReadAhead {
+    fn drop(&mut self) {
+        for fetch in self.fetches.values() {
+            fetch.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use libipld::{
+        multihash::{Code, MultihashDigest},
+        Cid, IpldCodec,
+    };
+
+    use super::ReadAhead;
+    use crate::{repo::Repo, Block};
+
+    fn block(data: &[u8]) -> Block {
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(data));
+        Block::new(cid, data.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn window_limits_the_fetches_started() {
+        let repo = Repo::new_memory();
+        let blocks = (0..8u8).map(|i| block(&[i])).collect::<Vec<_>>();
+        for block in &blocks {
+            repo.put_block(block.clone()).await.unwrap();
+        }
+        let cids = blocks.iter().map(|block| *block.cid()).collect::<Vec<_>>();
+
+        let mut read_ahead =
+            ReadAhead::new(repo, None, vec![], true, Some(Duration::from_secs(1)), 3);
+
+        let fetched = read_ahead.get(&cids[0], cids[1..].iter()).await.unwrap();
+        assert_eq!(fetched, blocks[0]);
+        assert_eq!(read_ahead.fetches.len(), 2);
+        assert!(read_ahead.fetches.contains_key(&cids[1]));
+        assert!(read_ahead.fetches.contains_key(&cids[2]));
+
+        // blocks already in flight are not fetched again
+        let fetched = read_ahead.get(&cids[1], cids[2..].iter()).await.unwrap();
+        assert_eq!(fetched, blocks[1]);
+        assert_eq!(read_ahead.fetches.len(), 2);
+        assert!(read_ahead.fetches.contains_key(&cids[3]));
+    }
+}
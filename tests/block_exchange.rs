@@ -19,7 +19,7 @@ fn create_block() -> Block {
 // verify that a put block can be received via get_block and the data matches
 #[tokio::test]
 async fn two_node_put_get() {
-    let nodes = spawn_nodes::<2>(Topology::Line).await;
+    let nodes = rust_ipfs::testing::memory_nodes(2).await;
     let block = create_block();
 
     nodes[0].put_block(block.clone()).await.unwrap();
@@ -47,3 +47,323 @@ async fn long_get_block() {
     nodes[0].put_block(block.clone()).await.unwrap();
     nodes[N - 1].get_block(block.cid()).await.unwrap();
 }
+
+// without kademlia, the block can only be found through the provider hint
+#[tokio::test]
+#[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+async fn get_block_from_provider_hint() {
+    use rust_ipfs::UninitializedIpfsNoop;
+
+    let start = || async {
+        let ipfs = UninitializedIpfsNoop::new()
+            .with_bitswap()
+            .start()
+            .await
+            .unwrap();
+        let addr = ipfs
+            .add_listening_address("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .await
+            .unwrap();
+        (ipfs, addr)
+    };
+    let (a, _) = start().await;
+    let (b, b_addr) = start().await;
+
+    let block = create_block();
+    b.put_block(block.clone()).await.unwrap();
+
+    let b_id = b.keypair().public().to_peer_id();
+    a.add_peer(b_id, b_addr).await.unwrap();
+
+    timeout(Duration::from_secs(2), a.get_block(block.cid()))
+        .await
+        .expect_err("no peer to fetch from without the hint");
+
+    let found_block = timeout(
+        Duration::from_secs(10),
+        a.get_block_from(block.cid(), &[b_id]),
+    )
+    .await
+    .expect("get_block_from did not complete in time")
+    .unwrap();
+
+    assert_eq!(block.data(), found_block.data());
+}
+
+// a node attached to the repo of another one serves the blocks the other one fetched, and has the
+// blocks it wants fetched by the other one
+#[tokio::test]
+#[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+async fn attached_node_shares_the_blockstore() {
+    use rust_ipfs::{repo::Repo, Ipfs, UninitializedIpfsNoop};
+
+    let start = |repo: Option<Repo>| async move {
+        let mut builder = UninitializedIpfsNoop::new().with_bitswap();
+        if let Some(repo) = &repo {
+            builder = builder.attach_repo(repo);
+        }
+        let ipfs = builder.start().await.unwrap();
+        let addr = ipfs
+            .add_listening_address("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .await
+            .unwrap();
+        let id = ipfs.keypair().public().to_peer_id();
+        (ipfs, id, addr)
+    };
+    let fetch = |ipfs: Ipfs, cid: Cid, from: libp2p::PeerId| async move {
+        timeout(Duration::from_secs(10), ipfs.get_block_from(&cid, &[from]))
+            .await
+            .expect("get_block_from did not complete in time")
+            .unwrap()
+    };
+
+    let (provider, provider_id, provider_addr) = start(None).await;
+    let (owner, _, _) = start(None).await;
+    let (attached, attached_id, attached_addr) = start(Some(owner.repo().clone())).await;
+    let (client, _, _) = start(None).await;
+
+    let block = create_block();
+    provider.put_block(block.clone()).await.unwrap();
+    owner.add_peer(provider_id, provider_addr).await.unwrap();
+    fetch(owner.clone(), *block.cid(), provider_id).await;
+
+    client.add_peer(attached_id, attached_addr).await.unwrap();
+    let found = fetch(client, *block.cid(), attached_id).await;
+    assert_eq!(block.data(), found.data());
+
+    // wanted through the attached node, fetched by the owner which knows the provider
+    let data = b"wanted through the attached node\n".to_vec();
+    let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+    provider
+        .put_block(Block::new_unchecked(cid, data))
+        .await
+        .unwrap();
+    fetch(attached.clone(), cid, provider_id).await;
+    assert!(owner.repo().contains(&cid).await.unwrap());
+
+    // the repo stays with the owner once the attached node exits
+    attached.exit_daemon().await;
+    assert!(owner.repo().is_online());
+}
+
+// the peers waiting on the attached node for a block get it once it is written to the repo
+#[tokio::test]
+#[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+async fn attached_node_sends_the_blocks_written_later() {
+    use rust_ipfs::UninitializedIpfsNoop;
+
+    let owner = UninitializedIpfsNoop::new()
+        .with_bitswap()
+        .start()
+        .await
+        .unwrap();
+    let attached = UninitializedIpfsNoop::new()
+        .with_bitswap()
+        .attach_repo(owner.repo())
+        .start()
+        .await
+        .unwrap();
+    let attached_addr = attached
+        .add_listening_address("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+    let attached_id = attached.keypair().public().to_peer_id();
+    let client = UninitializedIpfsNoop::new()
+        .with_bitswap()
+        .start()
+        .await
+        .unwrap();
+    client.add_peer(attached_id, attached_addr).await.unwrap();
+
+    let block = create_block();
+    let want = tokio::spawn({
+        let client = client.clone();
+        let cid = *block.cid();
+        async move { client.get_block_from(&cid, &[attached_id]).await }
+    });
+    // the want reaches the attached node before the block is written
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    owner.put_block(block.clone()).await.unwrap();
+
+    let found = timeout(Duration::from_secs(10), want)
+        .await
+        .expect("the block was not sent by the attached node")
+        .unwrap()
+        .unwrap();
+    assert_eq!(block.data(), found.data());
+}
+
+#[tokio::test]
+async fn attached_node_rejects_repo_options() {
+    use rust_ipfs::UninitializedIpfsNoop;
+
+    let owner = UninitializedIpfsNoop::new().start().await.unwrap();
+    let e = UninitializedIpfsNoop::new()
+        .set_storage_max(1024)
+        .attach_repo(owner.repo())
+        .start()
+        .await
+        .err()
+        .expect("the storage limit of the repo cannot be set on an attached node");
+    assert!(e.to_string().contains("storage limit"), "{e}");
+    assert!(owner.repo().is_online());
+}
+
+// the blocks fetched ahead of the walk are yielded in the order of the file
+#[tokio::test]
+async fn cat_with_read_ahead_preserves_order() {
+    let nodes = rust_ipfs::testing::memory_nodes(2).await;
+
+    // spans several leaves of the default 256 KiB chunker
+    let data = (0..1_500_000u32)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+    let path = nodes[0].add_unixfs(data.clone()).await.unwrap();
+
+    let cat = timeout(Duration::from_secs(30), async {
+        nodes[1].cat_unixfs(path).window(3).await
+    })
+    .await
+    .expect("cat did not complete in time")
+    .unwrap();
+
+    assert_eq!(cat, data);
+}
+
+#[tokio::test]
+async fn get_block_fails_promptly_without_bitswap() {
+    use rust_ipfs::error::{ProtocolDisabled, ProtocolKind};
+    use rust_ipfs::{p2p::KadConfig, Components, Node, UninitializedIpfsNoop};
+
+    let ipfs = UninitializedIpfsNoop::new()
+        .with_kademlia(KadConfig::default(), Default::default())
+        .start()
+        .await
+        .unwrap();
+
+    let components = ipfs.enabled_components().await.unwrap();
+    assert_eq!(
+        components,
+        Components {
+            kad: true,
+            ..Default::default()
+        }
+    );
+
+    let block = create_block();
+    let e = timeout(Duration::from_secs(5), ipfs.get_block(block.cid()))
+        .await
+        .expect("failed promptly")
+        .unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<ProtocolDisabled>(),
+        Some(&ProtocolDisabled(ProtocolKind::Bitswap))
+    );
+
+    // local blocks are still served
+    ipfs.put_block(block.clone()).await.unwrap();
+    assert_eq!(
+        ipfs.get_block(block.cid()).await.unwrap().data(),
+        block.data()
+    );
+
+    let node = Node::new("a").await;
+    assert!(node.enabled_components().await.unwrap().bitswap);
+}
+
+// a fetch interrupted by a restart completes once the block is available, without being asked for
+// again
+#[tokio::test]
+#[cfg_attr(any(feature = "libp2p_bitswap", feature = "beetle_bitswap"), ignore)]
+async fn interrupted_fetch_resumes_after_restart() {
+    use rust_ipfs::{repo::WantPersistence, Node, UninitializedIpfsNoop};
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let start = || {
+        UninitializedIpfsNoop::new()
+            .set_path(dir.path())
+            .set_want_persistence(WantPersistence::default())
+            .start()
+    };
+    let block = create_block();
+    let cid = *block.cid();
+
+    let b = Node::new("b").await;
+    let a = start().await.unwrap();
+    a.connect(b.addrs[0].clone()).await.unwrap();
+
+    // the block is wanted, but no peer has it yet
+    let fetch = tokio::spawn({
+        let a = a.clone();
+        async move { a.get_block(&cid).await }
+    });
+    timeout(Duration::from_secs(10), async {
+        while !a.bitswap_wantlist(None).await.unwrap().contains(&cid) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("block was not wanted");
+
+    a.exit_daemon().await;
+    fetch.abort();
+    let _ = fetch.await;
+
+    // the repo lock is released once the previous task is gone
+    let a = timeout(Duration::from_secs(10), async {
+        loop {
+            match start().await {
+                Ok(ipfs) => break ipfs,
+                Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        }
+    })
+    .await
+    .expect("restart timed out");
+
+    // the want cancelled by the shutdown was kept
+    let key = format!("/local/wants/{cid}/none");
+    assert!(a
+        .repo()
+        .data_store()
+        .contains(key.as_bytes())
+        .await
+        .unwrap());
+
+    b.put_block(block).await.unwrap();
+    a.connect(b.addrs[0].clone()).await.unwrap();
+
+    timeout(Duration::from_secs(10), async {
+        while !a.repo().contains(&cid).await.unwrap() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("fetch was not resumed");
+}
+
+#[tokio::test]
+#[cfg_attr(any(feature = "libp2p_bitswap", feature = "beetle_bitswap"), ignore)]
+async fn fetched_blocks_are_notified_with_the_sender() {
+    use futures::StreamExt;
+    use rust_ipfs::repo::{BlockSource, RepoNotification};
+
+    let nodes = rust_ipfs::testing::memory_nodes(2).await;
+    let block = create_block();
+
+    let mut notifications = nodes[1].repo().subscribe();
+    nodes[0].put_block(block.clone()).await.unwrap();
+    timeout(Duration::from_secs(10), nodes[1].get_block(block.cid()))
+        .await
+        .expect("get_block did not complete in time")
+        .unwrap();
+
+    assert_eq!(
+        notifications.next().await,
+        Some(RepoNotification::BlockAdded {
+            cid: *block.cid(),
+            size: block.data().len(),
+            source: BlockSource::Bitswap(Some(nodes[0].id)),
+        })
+    );
+}
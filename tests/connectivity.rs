@@ -1,4 +1,5 @@
 use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
 use rust_ipfs::Node;
 use std::time::Duration;
 use tokio::time::timeout;
@@ -125,3 +126,1154 @@ async fn connect_to_wrong_peer() {
         .expect("connect timed out")
         .expect_err("connection should had failed (wrong peer id)");
 }
+
+#[tokio::test]
+async fn gate_denies_connections_to_denied_peers() {
+    let a = Node::new("a").await;
+    let b = Node::new("b").await;
+
+    a.gate().deny_peer(b.id);
+
+    timeout(TIMEOUT, a.connect(b.addrs[0].clone()))
+        .await
+        .expect("connect timed out")
+        .expect_err("connection should have been denied");
+    assert_eq!(a.gate().stats().denied_outbound, 1);
+    assert!(a.connected().await.unwrap().is_empty());
+
+    assert!(a.gate().remove_denied_peer(&b.id));
+
+    timeout(TIMEOUT, a.connect(b.addrs[0].clone()))
+        .await
+        .expect("connect timed out")
+        .expect("should have connected");
+}
+
+#[tokio::test]
+async fn connection_events_are_streamed() {
+    use futures::StreamExt;
+    use libp2p::core::Endpoint;
+    use rust_ipfs::ConnectionEvent;
+
+    let a = Node::new("a").await;
+    let b = Node::new("b").await;
+
+    let mut events = a.connection_events();
+    // every subscriber receives the events
+    let mut other_events = a.connection_events();
+
+    a.connect(b.addrs[0].clone()).await.unwrap();
+    let event = timeout(TIMEOUT, events.next()).await.unwrap().unwrap();
+    assert!(matches!(
+        event,
+        ConnectionEvent::Connected { peer, direction: Endpoint::Dialer, .. } if peer == b.id
+    ));
+    let event = timeout(TIMEOUT, other_events.next()).await.unwrap();
+    assert!(matches!(event, Some(ConnectionEvent::Connected { .. })));
+
+    a.disconnect(b.id).await.unwrap();
+    let event = timeout(TIMEOUT, events.next()).await.unwrap().unwrap();
+    assert!(matches!(
+        event,
+        ConnectionEvent::Disconnected { peer, remaining_connections: 0, .. } if peer == b.id
+    ));
+
+    let unreachable: Multiaddr = "/ip4/127.0.0.1/tcp/1".parse().unwrap();
+    timeout(TIMEOUT, a.connect(unreachable.clone()))
+        .await
+        .unwrap()
+        .unwrap_err();
+
+    loop {
+        let event = timeout(TIMEOUT, events.next()).await.unwrap().unwrap();
+        if let ConnectionEvent::DialFailure { addr, .. } = event {
+            assert_eq!(addr, Some(unreachable));
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn peers_info_and_observed_addresses() {
+    let a = Node::new("a").await;
+    let b = Node::new("b").await;
+
+    a.connect(b.addrs[0].clone()).await.unwrap();
+
+    // the peers are known once identified
+    let peers = timeout(TIMEOUT, async {
+        loop {
+            let peers = a.peers_info().await.unwrap();
+            if !peers.is_empty() {
+                break peers;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("b was identified");
+
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].peer_id, b.id);
+    assert!(!peers[0].agent_version.is_empty());
+    assert!(!peers[0].protocols.is_empty());
+    assert_eq!(peers[0].connection_addrs.len(), 1);
+
+    let observed = a.observed_addresses().await.unwrap();
+    assert_eq!(observed.len(), 1);
+    assert_eq!(observed[0].1, 1);
+}
+
+#[tokio::test]
+async fn peer_meta_is_reported_with_peers_info() {
+    use rust_ipfs::p2p::PeerMetaError;
+
+    let a = Node::new("a").await;
+    let b = Node::new("b").await;
+
+    a.peer_set_meta(b.id, "role", b"leader".to_vec())
+        .await
+        .unwrap();
+    assert_eq!(
+        a.peer_get_meta(b.id, "role").await.unwrap().as_deref(),
+        Some(&b"leader"[..])
+    );
+
+    let e = a
+        .peer_set_meta(b.id, "blob", vec![0; 1024 * 1024])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        e.downcast_ref::<PeerMetaError>(),
+        Some(PeerMetaError::ValueTooLarge { .. })
+    ));
+
+    a.connect(b.addrs[0].clone()).await.unwrap();
+
+    let peers = timeout(TIMEOUT, async {
+        loop {
+            let peers = a.peers_info().await.unwrap();
+            if !peers.is_empty() {
+                break peers;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("b was identified");
+
+    assert_eq!(peers[0].meta.get("role"), Some(&b"leader".to_vec()));
+
+    a.peer_del_meta(b.id, "role").await.unwrap();
+    assert!(a.peer_get_meta(b.id, "role").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn connect_addr_reports_typed_errors() {
+    use rust_ipfs::p2p::DialError;
+
+    let a = Node::new("a").await;
+    let b = Node::new("b").await;
+    let c = Node::new("c").await;
+
+    // take b's address but with c's peerid
+    let mut wrong_addr = b.addrs[0].clone();
+    assert!(matches!(wrong_addr.pop(), Some(Protocol::P2p(_))));
+    wrong_addr.push(Protocol::P2p(c.id));
+
+    let e = timeout(TIMEOUT, a.connect_addr(wrong_addr))
+        .await
+        .expect("connect timed out")
+        .unwrap_err();
+    assert!(matches!(
+        e,
+        DialError::WrongPeerId { expected, obtained, .. } if expected == c.id && obtained == b.id
+    ));
+
+    // without a peer id, the peer connected to is reported
+    let mut addr = b.addrs[0].clone();
+    addr.pop();
+    let peer_id = timeout(TIMEOUT, a.connect_addr(addr))
+        .await
+        .expect("connect timed out")
+        .unwrap();
+    assert_eq!(peer_id, b.id);
+
+    let peer_id = timeout(TIMEOUT, a.connect_addr(c.addrs[0].clone()))
+        .await
+        .expect("connect timed out")
+        .unwrap();
+    assert_eq!(peer_id, c.id);
+
+    let unsupported: Multiaddr = "/unix/tmp%2Fipfs.sock".parse().unwrap();
+    let e = timeout(TIMEOUT, a.connect_addr(unsupported.clone()))
+        .await
+        .expect("connect timed out")
+        .unwrap_err();
+    assert!(matches!(e, DialError::UnsupportedAddress(addr) if addr == unsupported));
+}
+
+#[tokio::test]
+async fn connect_reports_typed_errors() {
+    use rust_ipfs::p2p::DialError;
+    use rust_ipfs::UninitializedIpfsNoop;
+
+    let a = Node::new("a").await;
+    let b = Node::new("b").await;
+
+    let refused: Multiaddr = "/ip4/127.0.0.1/tcp/1".parse().unwrap();
+    let e = a.ipfs.connect(refused.clone()).await.unwrap_err();
+    assert!(matches!(e, DialError::Refused(addr) if addr == refused));
+
+    a.ipfs.connect(b.addrs[0].clone()).await.unwrap();
+    // being connected already is fine by default
+    a.ipfs.connect(b.id).await.unwrap();
+
+    let strict = UninitializedIpfsNoop::new()
+        .with_default()
+        .set_fail_if_connected(true)
+        .set_dial_timeout(Duration::from_millis(500));
+    let c = Node::with_builder(strict, vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()]).await;
+
+    c.ipfs.connect(b.addrs[0].clone()).await.unwrap();
+    let e = c.ipfs.connect(b.id).await.unwrap_err();
+    assert!(matches!(e, DialError::AlreadyConnected));
+
+    // accepts the tcp connection but never completes the handshake
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let silent: Multiaddr = format!(
+        "/ip4/127.0.0.1/tcp/{}",
+        listener.local_addr().unwrap().port()
+    )
+    .parse()
+    .unwrap();
+    let e = timeout(TIMEOUT, c.ipfs.connect(silent))
+        .await
+        .expect("the dial timeout to elapse first")
+        .unwrap_err();
+    assert!(matches!(e, DialError::Timeout(None)));
+}
+
+#[tokio::test]
+async fn addressbook_is_restored_after_restart() {
+    use rust_ipfs::p2p::AddressBookConfig;
+    use rust_ipfs::UninitializedIpfsNoop;
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let file = dir.path().join("addressbook.json");
+    let start = || {
+        UninitializedIpfsNoop::new()
+            .set_path(dir.path())
+            .set_addrbook_configuration(AddressBookConfig {
+                store_on_connection: true,
+                persist: true,
+                ..Default::default()
+            })
+            .start()
+    };
+
+    let b = Node::new("b").await;
+
+    let a = start().await.unwrap();
+    timeout(TIMEOUT, a.connect(b.addrs[0].clone()))
+        .await
+        .expect("connect timed out")
+        .unwrap();
+    a.exit_daemon().await;
+
+    timeout(TIMEOUT, async {
+        while !file.exists() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("addressbook was not saved");
+
+    // the repo lock is released once the previous task is gone
+    let a = timeout(TIMEOUT, async {
+        loop {
+            match start().await {
+                Ok(ipfs) => break ipfs,
+                Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        }
+    })
+    .await
+    .expect("restart timed out");
+
+    // no address or bootstrap node is given
+    timeout(TIMEOUT, a.connect(b.id))
+        .await
+        .expect("connect timed out")
+        .unwrap();
+    assert!(a.is_connected(b.id).await.unwrap());
+}
+
+#[tokio::test]
+async fn identified_addresses_are_saved_with_the_addressbook() {
+    use rust_ipfs::p2p::AddressBookConfig;
+    use rust_ipfs::UninitializedIpfsNoop;
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let file = dir.path().join("addressbook.json");
+    let start = || {
+        // the addresses of the connections are not added to the addressbook
+        UninitializedIpfsNoop::new()
+            .set_path(dir.path())
+            .with_identify(Default::default())
+            .set_addrbook_configuration(AddressBookConfig {
+                persist: true,
+                ..Default::default()
+            })
+            .start()
+    };
+
+    let b = Node::new("b").await;
+
+    let a = start().await.unwrap();
+    timeout(TIMEOUT, a.connect(b.addrs[0].clone()))
+        .await
+        .expect("connect timed out")
+        .unwrap();
+    timeout(TIMEOUT, async {
+        while !a
+            .peers_info()
+            .await
+            .unwrap()
+            .iter()
+            .any(|info| info.peer_id == b.id)
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("b was not identified");
+    a.exit_daemon().await;
+
+    timeout(TIMEOUT, async {
+        while !file.exists() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("addressbook was not saved");
+
+    let a = timeout(TIMEOUT, async {
+        loop {
+            match start().await {
+                Ok(ipfs) => break ipfs,
+                Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        }
+    })
+    .await
+    .expect("restart timed out");
+
+    // the listen addresses b identified with were saved
+    timeout(TIMEOUT, a.connect(b.id))
+        .await
+        .expect("connect timed out")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn bans_of_addresses_and_peers_expire() {
+    use rust_ipfs::p2p::BanTarget;
+
+    let a = Node::new("a").await;
+    let b = Node::new("b").await;
+    let c = Node::new("c").await;
+
+    // the peer id of the address is not part of the ban
+    a.ban_address(b.addrs[0].clone(), None).await.unwrap();
+    timeout(TIMEOUT, a.connect(b.addrs[0].clone()))
+        .await
+        .expect("connect timed out")
+        .expect_err("dial to a banned address should be refused");
+
+    // inbound connections are refused on the remote address, whatever the peer
+    let loopback: Multiaddr = "/ip4/127.0.0.1".parse().unwrap();
+    a.ban_address(loopback.clone(), Some(Duration::from_millis(500)))
+        .await
+        .unwrap();
+    timeout(TIMEOUT, c.connect(a.addrs[0].clone()))
+        .await
+        .expect("connect timed out")
+        .expect_err("connection from a banned address should be refused");
+
+    let bans = a.list_bans().await.unwrap();
+    assert_eq!(bans.len(), 2);
+    let timed = bans
+        .iter()
+        .find(|ban| ban.target == BanTarget::Address(loopback.clone()))
+        .unwrap();
+    assert!(timed.remaining.unwrap() <= Duration::from_millis(500));
+
+    let mut b_addr = b.addrs[0].clone();
+    b_addr.pop();
+    a.unban_address(b_addr).await.unwrap();
+    a.ban_peer_with_duration(b.id, Duration::from_millis(500))
+        .await
+        .unwrap();
+    // unbanning a peer which is not banned does nothing
+    a.unban_peer(c.id).await.unwrap();
+
+    let bans = a.list_bans().await.unwrap();
+    assert_eq!(bans.len(), 2);
+    assert!(bans
+        .iter()
+        .any(|ban| ban.target == BanTarget::Peer(b.id) && ban.remaining.is_some()));
+
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert!(a.list_bans().await.unwrap().is_empty());
+
+    timeout(TIMEOUT, c.connect(a.addrs[0].clone()))
+        .await
+        .expect("connect timed out")
+        .expect("should connect once the ban expired");
+    timeout(TIMEOUT, a.connect(b.addrs[0].clone()))
+        .await
+        .expect("connect timed out")
+        .expect("should connect once the ban expired");
+}
+
+#[tokio::test]
+async fn banning_an_address_closes_its_connections_and_keeps_denies() {
+    let a = Node::new("a").await;
+    let b = Node::new("b").await;
+
+    timeout(TIMEOUT, a.connect(b.addrs[0].clone()))
+        .await
+        .expect("connect timed out")
+        .unwrap();
+    assert!(a.is_connected(b.id).await.unwrap());
+
+    let mut b_addr = b.addrs[0].clone();
+    b_addr.pop();
+    a.gate().deny_addr(b_addr.clone());
+    a.ban_address(b_addr.clone(), Some(Duration::from_millis(300)))
+        .await
+        .unwrap();
+
+    timeout(TIMEOUT, async {
+        while a.is_connected(b.id).await.unwrap() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("the connection on the banned address should be closed");
+
+    // the ban expiring does not lift the deny
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert!(a.list_bans().await.unwrap().is_empty());
+    timeout(TIMEOUT, a.connect(b.addrs[0].clone()))
+        .await
+        .expect("connect timed out")
+        .expect_err("dial to a denied address should be refused");
+
+    assert!(a.gate().remove_denied_addr(&b_addr));
+    timeout(TIMEOUT, a.connect(b.addrs[0].clone()))
+        .await
+        .expect("connect timed out")
+        .expect("should connect once the deny is removed");
+}
+
+#[tokio::test]
+async fn bandwidth_is_counted_per_peer_and_protocol() {
+    let a = Node::new("a").await;
+    let b = Node::new("b").await;
+
+    assert_eq!(a.bandwidth_stats().total_outbound, 0);
+
+    timeout(TIMEOUT, a.connect(b.addrs[0].clone()))
+        .await
+        .expect("connect timed out")
+        .unwrap();
+
+    // identify is exchanged on connection
+    let stats = timeout(TIMEOUT, async {
+        loop {
+            let stats = a.bandwidth_stats();
+            if stats.total_inbound > 0 && stats.total_outbound > 0 {
+                break stats;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("no bytes were counted");
+
+    let by_peer = a.bandwidth_by_peer();
+    let peer = by_peer[&b.id];
+    assert!(peer.total_inbound > 0 && peer.total_inbound <= stats.total_inbound);
+
+    let by_protocol = a.bandwidth_by_protocol();
+    assert!(by_protocol["/ip4/tcp"].total_outbound > 0);
+}
+
+#[tokio::test]
+async fn relay_reservation_is_advertised_as_external_address() {
+    use rust_ipfs::p2p::{MultiaddrExt, RelayClientConfig};
+    use rust_ipfs::UninitializedIpfsNoop;
+
+    let relay = UninitializedIpfsNoop::new()
+        .with_default()
+        .with_relay_server(Default::default())
+        .listen_as_external_addr()
+        .start()
+        .await
+        .unwrap();
+    let relay_id = relay.keypair().public().to_peer_id();
+    let relay_addr = relay
+        .add_listening_address("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap()
+        .with(Protocol::P2p(relay_id));
+
+    let a = UninitializedIpfsNoop::new()
+        .with_default()
+        .set_relay_configuration(RelayClientConfig {
+            enabled: true,
+            static_relays: vec![relay_addr],
+            auto: true,
+        })
+        .start()
+        .await
+        .unwrap();
+
+    // AutoNAT has not reported the node as private yet
+    let status = a.relay_status().await.unwrap();
+    assert!(!status.private);
+    assert!(status.reservations.is_empty());
+    assert_eq!(status.pending, None);
+
+    let relays = a.list_relays(false).await.unwrap();
+    assert_eq!(relays[0].0, relay_id);
+
+    timeout(Duration::from_secs(10), a.enable_relay(relay_id))
+        .await
+        .expect("reservation timed out")
+        .unwrap();
+
+    let status = a.relay_status().await.unwrap();
+    assert_eq!(status.reservations.len(), 1);
+    let reservation = &status.reservations[0];
+    assert_eq!(reservation.relay, relay_id);
+    assert!(reservation.addrs[0].is_relay());
+
+    let external = a.external_addresses().await.unwrap();
+    assert!(external.contains(&reservation.addrs[0]));
+}
+
+#[tokio::test]
+async fn relay_server_reports_reservations_and_circuits() {
+    use futures::StreamExt;
+    use rust_ipfs::p2p::{MultiaddrExt, RelayClientConfig};
+    use rust_ipfs::{ConnectionEvent, UninitializedIpfsNoop};
+
+    let relay = UninitializedIpfsNoop::new()
+        .with_default()
+        .with_relay_server(Default::default())
+        .listen_as_external_addr()
+        .start()
+        .await
+        .unwrap();
+    let relay_id = relay.keypair().public().to_peer_id();
+    let relay_addr = relay
+        .add_listening_address("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap()
+        .with(Protocol::P2p(relay_id));
+
+    let stats = relay.relay_server_stats().await.unwrap();
+    assert_eq!(stats.active_reservations, 0);
+    assert_eq!(stats.bytes_relayed, 0);
+
+    let a = UninitializedIpfsNoop::new()
+        .with_default()
+        .set_relay_configuration(RelayClientConfig {
+            enabled: true,
+            static_relays: vec![relay_addr],
+            auto: false,
+        })
+        .start()
+        .await
+        .unwrap();
+    let a_id = a.keypair().public().to_peer_id();
+
+    timeout(Duration::from_secs(10), a.enable_relay(relay_id))
+        .await
+        .expect("reservation timed out")
+        .unwrap();
+    assert_eq!(
+        relay
+            .relay_server_stats()
+            .await
+            .unwrap()
+            .active_reservations,
+        1
+    );
+
+    let circuit = a.relay_status().await.unwrap().reservations[0].addrs[0].clone();
+    assert!(circuit.is_relay());
+
+    let mut events = relay.connection_events();
+    // dialing a circuit needs the relay client transport
+    let b = UninitializedIpfsNoop::new()
+        .with_default()
+        .with_relay(false)
+        .start()
+        .await
+        .unwrap();
+    let b_id = b.keypair().public().to_peer_id();
+    timeout(TIMEOUT, b.connect(circuit))
+        .await
+        .expect("timeout")
+        .expect("should have connected through the relay");
+
+    loop {
+        let event = timeout(TIMEOUT, events.next()).await.unwrap().unwrap();
+        if let ConnectionEvent::RelayCircuitOpened { src, dst } = event {
+            assert_eq!(src, b_id);
+            assert_eq!(dst, a_id);
+            break;
+        }
+    }
+
+    let stats = relay.relay_server_stats().await.unwrap();
+    assert_eq!(stats.active_circuits, 1);
+    assert!(stats.bytes_relayed > 0);
+
+    // a node without the server enabled has no stats
+    assert!(b.relay_server_stats().await.is_err());
+}
+
+#[cfg(feature = "metrics")]
+#[tokio::test]
+async fn connected_peers_are_reported_in_metrics() {
+    let a = Node::new("a").await;
+    let b = Node::new("b").await;
+
+    a.connect(b.addrs[0].clone()).await.unwrap();
+
+    let encoded = a.metrics_encoded();
+    assert!(encoded.contains("ipfs_p2p_peers_total 1"), "{encoded}");
+    assert!(encoded.contains("# TYPE ipfs_p2p_sent_bytes counter"));
+}
+
+#[tokio::test]
+async fn swarm_observers_see_every_event() {
+    use libp2p::swarm::{behaviour::toggle::Toggle, dummy, SwarmEvent};
+    use rust_ipfs::p2p::BehaviourEvent;
+    use rust_ipfs::UninitializedIpfsNoop;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    type Event = SwarmEvent<BehaviourEvent<dummy::Behaviour>>;
+
+    let counter = |count: &Arc<AtomicUsize>| {
+        let count = count.clone();
+        move |event: &Event| {
+            if matches!(event, SwarmEvent::ConnectionEstablished { .. }) {
+                count.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    };
+
+    let first = Arc::new(AtomicUsize::new(0));
+    let second = Arc::new(AtomicUsize::new(0));
+    let at_runtime = Arc::new(AtomicUsize::new(0));
+
+    let a = UninitializedIpfsNoop::new()
+        .with_default()
+        .register_swarm_observer(counter(&first))
+        .register_swarm_observer(|_: &Event| panic!("faulty observer"))
+        .register_swarm_observer(counter(&second))
+        .start()
+        .await
+        .unwrap();
+    a.add_listening_address("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+    a.add_swarm_observer(counter(&at_runtime)).await.unwrap();
+
+    // an observer of another behaviour is refused
+    a.add_swarm_observer(|_: &SwarmEvent<BehaviourEvent<Toggle<dummy::Behaviour>>>| {})
+        .await
+        .unwrap_err();
+
+    let b = Node::new("b").await;
+    a.connect(b.addrs[0].clone()).await.unwrap();
+
+    // the panicking observer was removed without affecting the others nor the node
+    assert_eq!(first.load(Ordering::SeqCst), 1);
+    assert_eq!(second.load(Ordering::SeqCst), 1);
+    assert_eq!(at_runtime.load(Ordering::SeqCst), 1);
+    assert!(a.is_connected(b.id).await.unwrap());
+}
+
+#[tokio::test]
+async fn nat_status_is_unknown_before_probing() {
+    use libp2p::autonat::NatStatus;
+    use rust_ipfs::UninitializedIpfsNoop;
+
+    let a = Node::new("a").await;
+    let b = Node::new("b").await;
+
+    let status = a.nat_status().await.unwrap();
+    assert_eq!(status.status, NatStatus::Unknown);
+    assert_eq!(status.confidence, 0);
+    assert_eq!(status.public_address, None);
+
+    let mut addr = b.addrs[0].clone();
+    addr.pop();
+    a.add_autonat_server(b.id, Some(addr)).await.unwrap();
+
+    let without_autonat = UninitializedIpfsNoop::new().start().await.unwrap();
+    without_autonat.nat_status().await.unwrap_err();
+    without_autonat
+        .add_autonat_server(b.id, None)
+        .await
+        .unwrap_err();
+}
+
+#[tokio::test]
+async fn mdns_can_be_toggled_at_runtime() {
+    use rust_ipfs::UninitializedIpfsNoop;
+
+    let ipfs = UninitializedIpfsNoop::new().start().await.unwrap();
+    ipfs.mdns_peers().await.unwrap_err();
+
+    ipfs.set_mdns_enabled(true).await.unwrap();
+    // enabling twice keeps the behaviour running
+    ipfs.set_mdns_enabled(true).await.unwrap();
+    ipfs.mdns_peers().await.unwrap();
+
+    ipfs.set_mdns_enabled(false).await.unwrap();
+    ipfs.mdns_peers().await.unwrap_err();
+}
+
+#[tokio::test]
+async fn rendezvous_registers_and_discovers_peers() {
+    use rust_ipfs::p2p::RendezvousConfig;
+    use rust_ipfs::UninitializedIpfsNoop;
+
+    let server = UninitializedIpfsNoop::new()
+        .with_default()
+        .with_rendezvous_server()
+        .start()
+        .await
+        .unwrap();
+    let server_id = server.keypair().public().to_peer_id();
+    let server_addr = server
+        .add_listening_address("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap()
+        .with(Protocol::P2p(server_id));
+
+    let config = RendezvousConfig {
+        servers: vec![server_addr],
+        dial_discovered: true,
+        ..Default::default()
+    };
+
+    let a = UninitializedIpfsNoop::new()
+        .with_default()
+        .set_rendezvous_configuration(config.clone())
+        .listen_as_external_addr()
+        .start()
+        .await
+        .unwrap();
+    let a_id = a.keypair().public().to_peer_id();
+    a.add_listening_address("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+    timeout(TIMEOUT, a.rendezvous_register("private-swarm"))
+        .await
+        .expect("registration timed out")
+        .unwrap();
+
+    let b = UninitializedIpfsNoop::new()
+        .with_default()
+        .set_rendezvous_configuration(config)
+        .start()
+        .await
+        .unwrap();
+
+    let peers = timeout(TIMEOUT, b.rendezvous_discover("private-swarm"))
+        .await
+        .expect("discovery timed out")
+        .unwrap();
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].0, a_id);
+
+    // the discovered peer is dialed
+    timeout(TIMEOUT, async {
+        while !b.is_connected(a_id).await.unwrap() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("discovered peer was not dialed");
+
+    // the rendezvous client is disabled by default
+    let node = Node::new("no-rendezvous").await;
+    node.rendezvous_discover("private-swarm").await.unwrap_err();
+}
+
+#[tokio::test]
+async fn external_addresses_are_pushed_to_connected_peers() {
+    use rust_ipfs::p2p::{ExternalAddressInfo, ExternalAddressSource};
+
+    let a = Node::new("a").await;
+    let b = Node::new("b").await;
+    let a_id = a.id;
+
+    b.connect(a.addrs[0].clone()).await.unwrap();
+
+    let external: Multiaddr = "/ip4/198.51.100.1/tcp/4001".parse().unwrap();
+    a.add_external_address(external.clone()).await.unwrap();
+    assert!(a
+        .external_address_info()
+        .await
+        .unwrap()
+        .contains(&ExternalAddressInfo {
+            address: external.clone(),
+            source: ExternalAddressSource::Manual,
+        }));
+
+    // learned from the push rather than the next periodic identify
+    timeout(TIMEOUT, async {
+        loop {
+            if let Ok(info) = b.identity(Some(a_id)).await {
+                if info.listen_addrs.contains(&external) {
+                    break;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("external address was not pushed");
+
+    a.remove_external_address(external.clone()).await.unwrap();
+    assert!(!a
+        .external_address_info()
+        .await
+        .unwrap()
+        .iter()
+        .any(|info| info.address == external));
+}
+
+#[tokio::test]
+async fn public_only_filter_hides_the_local_addresses() {
+    use rust_ipfs::p2p::{AddressFilter, IdentifyConfiguration, MultiaddrExt};
+    use rust_ipfs::UninitializedIpfsNoop;
+
+    let uninit = UninitializedIpfsNoop::new()
+        .with_default()
+        .with_identify(IdentifyConfiguration {
+            address_filter: AddressFilter::PublicOnly,
+            ..Default::default()
+        });
+    let a = Node::with_builder(uninit, vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()]).await;
+    let b = Node::new("b").await;
+
+    let private: Multiaddr = "/ip4/192.168.1.10/tcp/4001".parse().unwrap();
+    let public: Multiaddr = "/ip4/198.51.100.1/tcp/4001".parse().unwrap();
+    a.add_external_address(private.clone()).await.unwrap();
+    a.add_external_address(public.clone()).await.unwrap();
+
+    b.connect(a.addrs[0].clone()).await.unwrap();
+
+    let info = timeout(TIMEOUT, async {
+        loop {
+            if let Ok(info) = b.identity(Some(a.id)).await {
+                if info.listen_addrs.contains(&public) {
+                    break info;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("identify to complete");
+
+    assert!(
+        !info
+            .listen_addrs
+            .iter()
+            .any(|addr| addr.is_loopback() || *addr == private),
+        "{:?}",
+        info.listen_addrs
+    );
+}
+
+#[tokio::test]
+async fn connections_are_pruned_when_the_interval_ticks() {
+    use rust_ipfs::p2p::ConnectionLimits;
+    use rust_ipfs::testing::{memory_nodes, memory_transport, ManualIntervals};
+    use rust_ipfs::UninitializedIpfsNoop;
+
+    let peers = memory_nodes(2).await;
+
+    let intervals = ManualIntervals::default();
+    let uninit = UninitializedIpfsNoop::new()
+        .with_default()
+        .with_custom_transport(memory_transport())
+        .with_interval_factory(intervals.factory())
+        .set_connection_limits(ConnectionLimits {
+            high_water: Some(1),
+            ..Default::default()
+        });
+    let node = Node::with_builder(uninit, vec!["/memory/0".parse().unwrap()]).await;
+
+    for peer in &peers {
+        node.connect(peer.addrs[0].clone()).await.unwrap();
+    }
+    // nothing is pruned until the cleanup interval ticks
+    assert_eq!(node.connected().await.unwrap().len(), 2);
+
+    assert_eq!(intervals.tick(Duration::from_secs(60)), 1);
+    timeout(TIMEOUT, async {
+        while node.connected().await.unwrap().len() > 1 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("connections were not pruned");
+}
+
+#[tokio::test]
+async fn identity_reports_the_agent_version() {
+    use rust_ipfs::UninitializedIpfsNoop;
+
+    let uninit = UninitializedIpfsNoop::new()
+        .with_default()
+        .set_agent_version("myapp/1.2.3");
+    let a = Node::with_builder(uninit, vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()]).await;
+    let b = Node::new("b").await;
+
+    let local = a.identity(None).await.unwrap();
+    assert_eq!(local.peer_id, a.id);
+    assert_eq!(local.agent_version, "myapp/1.2.3");
+    assert!(!local.listen_addrs.is_empty());
+    assert!(local
+        .listen_addrs
+        .iter()
+        .all(|addr| matches!(addr.iter().last(), Some(Protocol::P2p(id)) if id == a.id)));
+
+    b.connect(a.addrs[0].clone()).await.unwrap();
+
+    // the agent version is announced through identify
+    timeout(TIMEOUT, async {
+        loop {
+            if let Ok(info) = b.identity(Some(a.id)).await {
+                assert_eq!(info.agent_version, "myapp/1.2.3");
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("identify to complete");
+}
+
+#[tokio::test]
+async fn wait_ready_resolves_once_the_criteria_are_met() {
+    use rust_ipfs::{error::NotReady, ReadyCriteria};
+
+    let node_a = Node::new("a").await;
+    let node_b = Node::new("b").await;
+
+    let criteria = ReadyCriteria {
+        listeners: 1,
+        connected_peers: 1,
+        dht_routing_peers: 0,
+        timeout: Duration::from_millis(200),
+    };
+
+    let err = node_a.wait_ready(criteria).await.unwrap_err();
+    let state = err.downcast_ref::<NotReady>().expect("not ready").0;
+    assert_eq!(state.listeners, 1);
+    assert_eq!(state.connected_peers, 0);
+
+    let waiting = node_a.wait_ready(ReadyCriteria {
+        timeout: TIMEOUT,
+        ..criteria
+    });
+    let connecting = async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        node_b.connect(node_a.addrs[0].clone()).await.unwrap();
+    };
+    let (state, _) = tokio::join!(waiting, connecting);
+    assert_eq!(state.unwrap().connected_peers, 1);
+}
+
+#[tokio::test]
+async fn pending_dials_are_reported_and_aborted() {
+    use libp2p::PeerId;
+    use rust_ipfs::{p2p::SwarmConfig, UninitializedIpfsNoop};
+
+    // accepts the connections but never negotiates them
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let mut sockets = vec![];
+        while let Ok((socket, _)) = listener.accept().await {
+            sockets.push(socket);
+        }
+    });
+    let peer_id = PeerId::random();
+    let addr: Multiaddr = format!("/ip4/127.0.0.1/tcp/{port}/p2p/{peer_id}")
+        .parse()
+        .unwrap();
+
+    let a = Node::new("a").await;
+    let dial = tokio::spawn({
+        let ipfs = a.ipfs.clone();
+        let addr = addr.clone();
+        async move { ipfs.connect_addr(addr).await }
+    });
+
+    let pending = async {
+        loop {
+            let dials = a.pending_dials().await.unwrap();
+            if let Some((peer, addrs, _)) = dials.first() {
+                break (*peer, addrs.clone());
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    };
+    let (peer, addrs) = timeout(TIMEOUT, pending).await.expect("dial is pending");
+    assert_eq!(peer, Some(peer_id));
+    assert_eq!(addrs, vec![addr.clone()]);
+
+    assert!(a.abort_dial(peer_id).await.unwrap());
+    timeout(TIMEOUT, dial)
+        .await
+        .expect("dial was aborted")
+        .unwrap()
+        .unwrap_err();
+    assert!(a.pending_dials().await.unwrap().is_empty());
+    assert!(!a.abort_dial(peer_id).await.unwrap());
+
+    // the dial timeout gives up on the connection by itself
+    let uninit = UninitializedIpfsNoop::new()
+        .with_default()
+        .set_swarm_configuration(SwarmConfig {
+            dial_timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        });
+    let b = Node::with_builder(uninit, vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()]).await;
+    timeout(TIMEOUT, b.connect_addr(addr))
+        .await
+        .expect("dial timed out")
+        .unwrap_err();
+}
+
+#[tokio::test]
+async fn disconnections_are_recorded_with_their_cause() {
+    use rust_ipfs::{DisconnectCause, Disconnection};
+
+    let a = Node::new("a").await;
+    let b = Node::new("b").await;
+    let c = Node::new("c").await;
+
+    a.connect(b.addrs[0].clone()).await.unwrap();
+    a.connect(c.addrs[0].clone()).await.unwrap();
+
+    a.disconnect(b.id).await.unwrap();
+    a.ban_peer(c.id).await.unwrap();
+
+    let closed = async {
+        loop {
+            let disconnections = a.recent_disconnections().await.unwrap();
+            if disconnections.len() == 2 {
+                break disconnections;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    };
+    let disconnections = timeout(TIMEOUT, closed).await.expect("peers disconnected");
+    let cause_of = |peer| {
+        disconnections
+            .iter()
+            .find(|Disconnection { peer: p, .. }| *p == peer)
+            .map(|d| d.cause.clone())
+    };
+    assert_eq!(cause_of(b.id), Some(DisconnectCause::Requested));
+    assert_eq!(cause_of(c.id), Some(DisconnectCause::Banned));
+}
+
+// the negotiation failures of bitswap are only reported by the default implementation
+#[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+#[tokio::test]
+async fn peer_protocols_and_unsupported_protocols_are_reported() {
+    use futures::StreamExt;
+    use libipld::multihash::{Code, MultihashDigest};
+    use libipld::{Cid, IpldCodec};
+    use rust_ipfs::{ConnectionEvent, UninitializedIpfsNoop};
+
+    let a = Node::new("a").await;
+    let b = Node::new("b").await;
+    // speaks neither kad nor bitswap
+    let c = UninitializedIpfsNoop::new()
+        .with_identify(Default::default())
+        .with_ping(Default::default())
+        .start()
+        .await
+        .unwrap();
+    let c_id = c.keypair().public().to_peer_id();
+    let c_addrs = c
+        .add_listening_address("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    a.connect(b.addrs[0].clone()).await.unwrap();
+    let protocols = timeout(TIMEOUT, async {
+        loop {
+            let protocols = a.peer_protocols(b.id).await.unwrap();
+            if !protocols.is_empty() {
+                break protocols;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("b to be identified");
+    assert!(protocols.iter().any(|p| p == "/ipfs/kad/1.0.0"));
+    assert!(protocols.iter().any(|p| p.starts_with("/ipfs/bitswap")));
+
+    let mut events = a.connection_events();
+    let c_addr = c_addrs[0].clone().with(Protocol::P2p(c_id));
+    a.connect(c_addr).await.unwrap();
+
+    // wanting a block from c tries to negotiate bitswap with it
+    let data = b"never provided".to_vec();
+    let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+    let fetch = tokio::spawn({
+        let ipfs = a.ipfs.clone();
+        async move { ipfs.get_block_from(&cid, &[c_id]).await }
+    });
+
+    let mut unsupported = vec![];
+    timeout(Duration::from_secs(10), async {
+        while unsupported.len() < 2 {
+            if let Some(ConnectionEvent::ProtocolUnsupported { peer, protocol }) =
+                events.next().await
+            {
+                assert_eq!(peer, c_id);
+                if !unsupported.contains(&protocol) {
+                    unsupported.push(protocol);
+                }
+            }
+        }
+    })
+    .await
+    .expect("kad and bitswap to be reported as unsupported");
+    unsupported.sort();
+    assert_eq!(unsupported, ["/ipfs/bitswap/1.2.0", "/ipfs/kad/1.0.0"]);
+
+    fetch.abort();
+}
@@ -0,0 +1,101 @@
+use futures::StreamExt;
+use libp2p::request_response::{self, json, ProtocolSupport};
+use libp2p::StreamProtocol;
+use rust_ipfs::testing::memory_transport;
+use rust_ipfs::{Ipfs, UninitializedIpfs, UninitializedIpfsNoop};
+
+type SyncBehaviour = json::Behaviour<String, String>;
+
+async fn sync_node() -> Ipfs {
+    let behaviour = SyncBehaviour::new(
+        [(
+            StreamProtocol::new("/myapp/sync/1.0.0"),
+            ProtocolSupport::Full,
+        )],
+        request_response::Config::default(),
+    );
+
+    let ipfs = UninitializedIpfs::new()
+        .with_default()
+        .with_custom_transport(memory_transport())
+        .with_custom_behaviour(behaviour)
+        .start()
+        .await
+        .unwrap();
+    ipfs.add_listening_address("/memory/0".parse().unwrap())
+        .await
+        .unwrap();
+    ipfs
+}
+
+#[tokio::test]
+async fn custom_request_response_protocol() {
+    let a = sync_node().await;
+    let b = sync_node().await;
+
+    let mut a_events = a.custom_events::<SyncBehaviour>().await.unwrap();
+    let mut b_events = b.custom_events::<SyncBehaviour>().await.unwrap();
+
+    let addr = a.listening_addresses().await.unwrap()[0].clone();
+    let a_id = b.connect_addr(addr).await.unwrap();
+
+    b.with_custom_behaviour(move |sync: &mut SyncBehaviour| {
+        sync.send_request(&a_id, "ping".into())
+    })
+    .await
+    .unwrap();
+
+    let channel = loop {
+        if let request_response::Event::Message {
+            message:
+                request_response::Message::Request {
+                    request, channel, ..
+                },
+            ..
+        } = a_events
+            .next()
+            .await
+            .expect("events of the custom behaviour")
+        {
+            assert_eq!(request, "ping");
+            break channel;
+        }
+    };
+
+    a.with_custom_behaviour(move |sync: &mut SyncBehaviour| {
+        sync.send_response(channel, "pong".into())
+    })
+    .await
+    .unwrap()
+    .unwrap();
+
+    loop {
+        if let request_response::Event::Message {
+            message: request_response::Message::Response { response, .. },
+            ..
+        } = b_events
+            .next()
+            .await
+            .expect("events of the custom behaviour")
+        {
+            assert_eq!(response, "pong");
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn custom_behaviour_type_is_checked() {
+    let a = sync_node().await;
+    a.custom_events::<libp2p::ping::Behaviour>()
+        .await
+        .unwrap_err();
+    a.with_custom_behaviour(|_: &mut libp2p::ping::Behaviour| {})
+        .await
+        .unwrap_err();
+
+    let noop = UninitializedIpfsNoop::new().start().await.unwrap();
+    noop.with_custom_behaviour(|_: &mut libp2p::swarm::dummy::Behaviour| {})
+        .await
+        .unwrap_err();
+}
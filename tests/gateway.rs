@@ -0,0 +1,194 @@
+#![cfg(feature = "gateway_fallback")]
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use libipld::{
+    cbor::DagCborCodec,
+    codec::Codec,
+    ipld,
+    multihash::{Code, MultihashDigest},
+    Cid, IpldCodec,
+};
+use reqwest::Url;
+use rust_ipfs::{Block, Ipfs, UninitializedIpfsNoop};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+fn create_block(data: &[u8]) -> Block {
+    let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(data));
+    Block::new(cid, data.to_vec()).unwrap()
+}
+
+fn create_cbor_block(data: libipld::Ipld) -> Block {
+    let data = DagCborCodec.encode(&data).unwrap();
+    let cid = Cid::new_v1(IpldCodec::DagCbor.into(), Code::Sha2_256.digest(&data));
+    Block::new(cid, data).unwrap()
+}
+
+/// Writes a CARv1 with the root and blocks.
+fn create_car(root: &Cid, blocks: &[&Block]) -> Vec<u8> {
+    fn frame(car: &mut Vec<u8>, parts: &[&[u8]]) {
+        let len = parts.iter().map(|part| part.len()).sum::<usize>();
+        let mut buf = unsigned_varint::encode::usize_buffer();
+        car.extend_from_slice(unsigned_varint::encode::usize(len, &mut buf));
+        for part in parts {
+            car.extend_from_slice(part);
+        }
+    }
+
+    let mut car = vec![];
+    let header = DagCborCodec
+        .encode(&ipld!({ "roots": [*root], "version": 1 }))
+        .unwrap();
+    frame(&mut car, &[&header]);
+    for block in blocks {
+        frame(&mut car, &[&block.cid().to_bytes(), block.data()]);
+    }
+    car
+}
+
+/// Serves the data for `GET /ipfs/{cid}` requests, counting them.
+async fn gateway(content: HashMap<Cid, Vec<u8>>) -> (Url, Arc<AtomicUsize>) {
+    delayed_gateway(content, Duration::ZERO).await
+}
+
+/// Serves the data as [`gateway`] does, once the delay elapsed.
+async fn delayed_gateway(
+    content: HashMap<Cid, Vec<u8>>,
+    delay: Duration,
+) -> (Url, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    let requests = Arc::new(AtomicUsize::new(0));
+
+    tokio::spawn({
+        let requests = requests.clone();
+        async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                requests.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(delay).await;
+
+                let mut buf = vec![0; 4096];
+                let read = stream.read(&mut buf).await.unwrap();
+                let request = String::from_utf8_lossy(&buf[..read]);
+                let cid = request
+                    .split_whitespace()
+                    .nth(1)
+                    .and_then(|path| path.strip_prefix("/ipfs/"))
+                    .and_then(|path| path.split('?').next())
+                    .and_then(|cid| cid.parse::<Cid>().ok());
+
+                let response = match cid.and_then(|cid| content.get(&cid)) {
+                    Some(data) => [
+                        format!(
+                            "HTTP/1.1 200 OK\r\ncontent-type: application/vnd.ipld.raw\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                            data.len()
+                        )
+                        .into_bytes(),
+                        data.clone(),
+                    ]
+                    .concat(),
+                    None => b"HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                        .to_vec(),
+                };
+                let _ = stream.write_all(&response).await;
+                let _ = stream.shutdown().await;
+            }
+        }
+    });
+
+    (url, requests)
+}
+
+async fn node(gateways: Vec<Url>) -> Ipfs {
+    UninitializedIpfsNoop::new()
+        .with_default()
+        .set_gateway_fallback(gateways, Duration::from_millis(100))
+        .start()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn missing_block_is_fetched_from_gateway() {
+    let block = create_block(b"served by the gateway");
+    let (url, _) = gateway(HashMap::from([(*block.cid(), block.data().to_vec())])).await;
+    let ipfs = node(vec![url]).await;
+
+    let fetched = timeout(Duration::from_secs(10), ipfs.get_block(block.cid()))
+        .await
+        .expect("the gateway was fallen back to")
+        .unwrap();
+
+    assert_eq!(fetched, block);
+    assert!(ipfs.get_block_local(block.cid()).await.is_ok());
+}
+
+#[tokio::test]
+async fn corrupt_gateway_is_skipped() {
+    let first = create_block(b"first");
+    let second = create_block(b"second");
+
+    let corrupt = HashMap::from([
+        (*first.cid(), b"not first".to_vec()),
+        (*second.cid(), b"not second".to_vec()),
+    ]);
+    let honest = HashMap::from([
+        (*first.cid(), first.data().to_vec()),
+        (*second.cid(), second.data().to_vec()),
+    ]);
+    let (corrupt_url, corrupt_requests) = gateway(corrupt).await;
+    // the gateways are requested at once, the honest one answering last
+    let (honest_url, _) = delayed_gateway(honest, Duration::from_millis(500)).await;
+    let ipfs = node(vec![corrupt_url, honest_url]).await;
+
+    for block in [&first, &second] {
+        let fetched = timeout(Duration::from_secs(10), ipfs.get_block(block.cid()))
+            .await
+            .expect("the gateway was fallen back to")
+            .unwrap();
+        assert_eq!(&fetched, block);
+    }
+
+    // the corrupt gateway is cooling down after the first block
+    assert_eq!(corrupt_requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn oversized_block_is_not_read() {
+    let block = create_block(&vec![1; 4 * 1024 * 1024 + 1]);
+    let (url, requests) = gateway(HashMap::from([(*block.cid(), block.data().to_vec())])).await;
+    let ipfs = node(vec![url]).await;
+
+    let res = ipfs
+        .get_block(block.cid())
+        .deadline(Duration::from_secs(2))
+        .await;
+
+    assert!(res.is_err());
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+    assert!(ipfs.get_block_local(block.cid()).await.is_err());
+}
+
+#[tokio::test]
+async fn only_blocks_reachable_from_the_root_are_imported() {
+    let child = create_block(b"child");
+    let unrelated = create_block(b"unrelated");
+    let root = create_cbor_block(ipld!({ "child": *child.cid() }));
+
+    let car = create_car(root.cid(), &[&root, &child, &unrelated]);
+    let (url, _) = gateway(HashMap::from([(*root.cid(), car)])).await;
+    let ipfs = node(vec![url]).await;
+
+    ipfs.repo().fetch_from_gateways(root.cid()).await.unwrap();
+
+    assert!(ipfs.get_block_local(root.cid()).await.is_ok());
+    assert!(ipfs.get_block_local(child.cid()).await.is_ok());
+    assert!(ipfs.get_block_local(unrelated.cid()).await.is_err());
+}
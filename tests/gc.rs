@@ -1,25 +1,33 @@
+use std::time::Duration;
+
 use libipld::{
     multihash::{Code, MultihashDigest},
     Cid, IpldCodec,
 };
+use rust_ipfs::repo::{Eviction, StorageFull};
+use rust_ipfs::unixfs::WriteOptions;
 use rust_ipfs::{Block, Node};
 
 fn create_block() -> Block {
-    let data = b"hello block\n".to_vec();
-    let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+    create_block_with(b"hello block\n")
+}
+
+fn create_block_with(data: &[u8]) -> Block {
+    let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(data));
 
-    Block::new_unchecked(cid, data)
+    Block::new_unchecked(cid, data.to_vec())
 }
 
 #[tokio::test]
 async fn cleanup_unpinned_blocks() -> anyhow::Result<()> {
     let node = Node::new("gc_test_node").await;
     let block = create_block();
-    let cid = node.put_block(block).await?;
+    let (cid, _) = node.put_block(block).await?;
 
-    let removed = node.gc().await?;
+    let result = node.gc().await?;
 
-    assert_eq!(removed[0], cid);
+    assert_eq!(result.removed[0], cid);
+    assert_eq!(result.reclaimed, b"hello block\n".len());
 
     Ok(())
 }
@@ -28,10 +36,214 @@ async fn cleanup_unpinned_blocks() -> anyhow::Result<()> {
 async fn gc_cleanup_attempt_of_pinned_blocks() -> anyhow::Result<()> {
     let node = Node::new("gc_test_node").await;
     let block = create_block();
-    let cid = node.put_block(block).await?;
+    let (cid, _) = node.put_block(block).await?;
+    node.insert_pin(&cid).await?;
+    let result = node.gc().await?;
+    assert!(result.removed.is_empty());
+    assert_eq!(result.reclaimed, 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn gc_spares_blocks_within_grace_period() -> anyhow::Result<()> {
+    let node = Node::new("gc_test_node").await;
+    node.repo().set_gc_grace_period(Duration::from_secs(60));
+
+    let (cid, _) = node.put_block(create_block()).await?;
+    let result = node.gc().await?;
+    assert!(result.removed.is_empty());
+    assert!(node.repo().contains(&cid).await?);
+
+    node.repo().set_gc_grace_period(Duration::ZERO);
+    let result = node.gc().await?;
+    assert_eq!(result.removed, vec![cid]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn gc_keeps_blocks_reachable_from_files_root() -> anyhow::Result<()> {
+    let node = Node::new("gc_test_node").await;
+    let options = WriteOptions {
+        create: true,
+        ..Default::default()
+    };
+
+    node.files().mkdir("/docs", false).await?;
+    node.files().write("/docs/a", b"kept", options).await?;
+    let (unreferenced, _) = node.put_block(create_block()).await?;
+
+    let result = node.gc().await?;
+    assert!(result.removed.contains(&unreferenced));
+    assert_eq!(&node.files().read("/docs/a").await?[..], b"kept");
+
+    // the previous versions of the files root are no longer reachable
+    node.files().write("/docs/a", b"replaced", options).await?;
+    let result = node.gc().await?;
+    assert!(!result.removed.is_empty());
+    assert_eq!(&node.files().read("/docs/a").await?[..], b"replaced");
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_pin_unpin_and_gc_never_remove_pinned_blocks() -> anyhow::Result<()> {
+    let node = Node::new("gc_test_node").await;
+
+    let (kept, _) = node.put_block(create_block()).await?;
+    node.insert_pin(&kept).await?;
+
+    let collector = tokio::spawn({
+        let node = node.ipfs.clone();
+        async move {
+            for _ in 0..100 {
+                node.gc().await?;
+                tokio::task::yield_now().await;
+            }
+            Ok::<_, anyhow::Error>(())
+        }
+    });
+
+    let writers = (0..4)
+        .map(|writer| {
+            let node = node.ipfs.clone();
+            tokio::spawn(async move {
+                for round in 0..25 {
+                    let data = format!("block {writer} {round}");
+                    let cid = {
+                        // without a grace period, the block could be collected before pinned
+                        let _guard = node.repo().gc_guard().await;
+                        let (cid, _) = node.put_block(create_block_with(data.as_bytes())).await?;
+                        let pin = node.insert_pin(&cid);
+                        match round % 2 {
+                            0 => pin.await?,
+                            _ => pin.recursive().await?,
+                        }
+                        cid
+                    };
+
+                    tokio::task::yield_now().await;
+                    assert!(node.repo().contains(&cid).await?, "{cid} was removed");
+
+                    node.remove_pin(&cid).recursive().await?;
+                }
+                Ok::<_, anyhow::Error>(())
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for writer in writers {
+        writer.await??;
+    }
+    collector.await??;
+
+    assert!(node.repo().contains(&kept).await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn repo_stat_tracks_puts_and_removals() -> anyhow::Result<()> {
+    let node = Node::new("gc_test_node").await;
+    let stat = node.repo_stat().await?;
+    assert_eq!((stat.num_blocks, stat.size_bytes), (0, 0));
+    assert_eq!(stat.storage_max, None);
+
+    let (cid, _) = node.put_block(create_block()).await?;
+    node.put_block(create_block()).await?;
+    node.put_block(create_block_with(b"other")).await?;
+    let stat = node.repo_stat().await?;
+    assert_eq!(stat.num_blocks, 2);
+    assert_eq!(
+        stat.size_bytes,
+        (b"hello block\n".len() + b"other".len()) as u64
+    );
+
+    node.repo().remove_block(&cid, false).await?;
+    let stat = node.repo_stat().await?;
+    assert_eq!(stat.num_blocks, 1);
+    assert_eq!(stat.size_bytes, b"other".len() as u64);
+
+    node.gc().await?;
+    let stat = node.repo_stat().await?;
+    assert_eq!((stat.num_blocks, stat.size_bytes), (0, 0));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn put_beyond_storage_max_fails() -> anyhow::Result<()> {
+    let node = Node::new("gc_test_node").await;
+    node.repo().set_max_storage_size(16);
+
+    let (cid, _) = node.put_block(create_block()).await?;
+    // writing an existing block takes no room
+    node.put_block(create_block()).await?;
+
+    let e = node
+        .put_block(create_block_with(b"does not fit"))
+        .await
+        .unwrap_err();
+    let full = e.downcast_ref::<StorageFull>().expect("storage full error");
+    assert_eq!(full.storage_max, 16);
+    assert_eq!(full.needed, b"does not fit".len() as u64);
+
+    assert!(node.repo().contains(&cid).await?);
+    assert_eq!(node.repo_stat().await?.storage_max, Some(16));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn put_beyond_storage_max_collects_garbage_with_gc_auto() -> anyhow::Result<()> {
+    let node = Node::new("gc_test_node").await;
+    node.repo().set_max_storage_size(16);
+    node.repo().set_gc_auto(true);
+
+    let (unpinned, _) = node.put_block(create_block()).await?;
+    let (cid, _) = node.put_block(create_block_with(b"fits now")).await?;
+    assert!(!node.repo().contains(&unpinned).await?);
     node.insert_pin(&cid).await?;
-    let removed = node.gc().await?;
-    assert!(removed.is_empty());
+
+    // nothing left to collect
+    let e = node
+        .put_block(create_block_with(b"does not fit"))
+        .await
+        .unwrap_err();
+    assert!(e.is::<StorageFull>());
+    assert!(node.repo().contains(&cid).await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lru_eviction_keeps_recently_accessed_and_pinned_blocks() -> anyhow::Result<()> {
+    let node = Node::new("gc_test_node").await;
+    node.repo().set_max_storage_size(40);
+    node.repo().set_eviction(Eviction::Lru);
+
+    let (pinned, _) = node.put_block(create_block_with(b"pinned block")).await?;
+    node.insert_pin(&pinned).await?;
+    let (old, _) = node.put_block(create_block_with(b"old block!")).await?;
+    let (recent, _) = node.put_block(create_block_with(b"recent one")).await?;
+
+    // accessing the older block makes it the most recently used
+    node.get_block(&old).await?;
+
+    // 32 bytes are stored, the new block needs evicting
+    let (new, _) = node.put_block(create_block_with(b"new block!")).await?;
+
+    assert!(node.repo().contains(&pinned).await?);
+    assert!(node.repo().contains(&old).await?);
+    assert!(!node.repo().contains(&recent).await?);
+    assert!(node.repo().contains(&new).await?);
+
+    let stat = node.repo_stat().await?;
+    assert_eq!(stat.evictions, 1);
+    assert_eq!(stat.hits, 1);
+    assert_eq!(stat.hit_rate(), 1.0);
+    assert_eq!(stat.size_bytes, 32);
 
     Ok(())
 }
@@ -3,10 +3,18 @@ use libipld::{
     multihash::{Code, MultihashDigest},
     Cid, IpldCodec,
 };
-use libp2p::{kad::Quorum, multiaddr::Protocol, Multiaddr};
-use rust_ipfs::{p2p::MultiaddrExt, Block, Node};
+use libp2p::{kad::Quorum, multiaddr::Protocol, Multiaddr, PeerId};
+use rust_ipfs::error::{ProtocolDisabled, ProtocolKind};
+use rust_ipfs::{p2p::MultiaddrExt, Block, DhtEvent, DhtMode, Node, UninitializedIpfsNoop};
 use tokio::time::timeout;
-
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 mod common;
@@ -33,12 +41,108 @@ async fn find_peer_local() {
     }
 }
 
+#[tokio::test]
+async fn dht_events_report_the_peers_added() {
+    let nodes = spawn_nodes::<2>(Topology::None).await;
+
+    let mut first = nodes[0].dht_events().await.unwrap();
+    let mut second = nodes[0].dht_events().await.unwrap();
+    // a dropped subscriber doesn't get in the way of the others
+    drop(nodes[0].dht_events().await.unwrap());
+
+    nodes[0].connect(nodes[1].addrs[0].clone()).await.unwrap();
+
+    for events in [&mut first, &mut second] {
+        let event = timeout(Duration::from_secs(10), events.next())
+            .await
+            .expect("peer was added")
+            .unwrap();
+        let DhtEvent::PeerAdded {
+            peer, addresses, ..
+        } = event
+        else {
+            panic!("unexpected event {event:?}");
+        };
+        assert_eq!(peer, nodes[1].id);
+        assert!(!addresses.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn bootstrap_policy_bootstraps_on_start() {
+    use rust_ipfs::{p2p::BootstrapPolicy, testing::memory_transport};
+
+    let start = |bootstrap: Option<Multiaddr>, policy: BootstrapPolicy| {
+        let mut uninit = UninitializedIpfsNoop::new()
+            .with_default()
+            .with_custom_transport(memory_transport())
+            .set_bootstrap_policy(policy);
+        if let Some(addr) = bootstrap {
+            uninit = uninit.add_bootstrap(addr);
+        }
+        Node::with_builder(uninit, vec!["/memory/0".parse().unwrap()])
+    };
+    let bootstrapper = start(None, Default::default()).await;
+
+    let policy = BootstrapPolicy {
+        on_start: true,
+        interval: Some(Duration::from_secs(60)),
+        min_peers_trigger: Some(1),
+    };
+    let node = start(Some(bootstrapper.addrs[0].clone()), policy).await;
+    assert_eq!(node.bootstrap_policy().await.unwrap(), policy);
+
+    // the bootstrap dials the bootstrapper without being asked to
+    let connected = async {
+        while !node.is_connected(bootstrapper.id).await.unwrap() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    };
+    timeout(Duration::from_secs(10), connected)
+        .await
+        .expect("bootstrapped on start");
+
+    node.set_bootstrap_policy(Default::default()).await.unwrap();
+    assert_eq!(
+        node.bootstrap_policy().await.unwrap(),
+        BootstrapPolicy::default()
+    );
+}
+
+#[tokio::test]
+async fn bootstrap_reports_the_unreachable_bootstrappers() {
+    use rust_ipfs::testing::memory_transport;
+
+    let start = || {
+        let uninit = UninitializedIpfsNoop::new()
+            .with_default()
+            .with_custom_transport(memory_transport());
+        Node::with_builder(uninit, vec!["/memory/0".parse().unwrap()])
+    };
+    let bootstrapper = start().await;
+    let node = start().await;
+
+    let unreachable: Multiaddr = format!("/memory/1/p2p/{}", PeerId::random())
+        .parse()
+        .unwrap();
+    node.add_bootstrap(bootstrapper.addrs[0].clone())
+        .await
+        .unwrap();
+    node.add_bootstrap(unreachable.clone()).await.unwrap();
+
+    let report = timeout(Duration::from_secs(30), node.bootstrap_report())
+        .await
+        .expect("bootstrap completed")
+        .unwrap();
+    assert_eq!(report.contacted, vec![bootstrapper.id]);
+    assert_eq!(report.failed, vec![unreachable]);
+}
+
 // starts the specified number of rust IPFS nodes connected in a chain.
 #[cfg(all(not(feature = "test_go_interop"), not(feature = "test_js_interop")))]
 async fn spawn_bootstrapped_nodes<const N: usize>() -> (Vec<Node>, Option<ForeignNode>) {
     // fire up `n` nodes
 
-    use rust_ipfs::DhtMode;
     let nodes = spawn_nodes::<N>(Topology::None).await;
 
     // register the nodes' addresses so they can bootstrap against
@@ -184,6 +288,27 @@ async fn dht_providing() {
         .any(|x| *x == nodes[last_index].id));
 }
 
+/// Lazily providing does not wait for the query, which cannot complete without peers.
+#[tokio::test]
+async fn lazy_providing_returns_once_started() {
+    let node = Node::new("a").await;
+
+    let data = b"provided lazily".to_vec();
+    let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+    let missing = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(b"missing"));
+    node.put_block(Block::new(cid, data).unwrap())
+        .await
+        .unwrap();
+
+    timeout(Duration::from_secs(1), node.provide_lazy(cid))
+        .await
+        .expect("returns without waiting for the query")
+        .unwrap();
+    node.provide_lazy(missing).await.unwrap_err();
+
+    assert_eq!(node.provided().await.unwrap(), vec![cid]);
+}
+
 /// Check if Ipfs::{get, put} does its job.
 #[tokio::test]
 async fn dht_get_put() {
@@ -213,3 +338,258 @@ async fn dht_get_put() {
         .iter()
         .any(|x| x.value == value));
 }
+
+/// Spans closed while the recorder is the default subscriber, with their recorded fields.
+#[derive(Clone, Default)]
+struct SpanRecorder(Arc<Mutex<Vec<(&'static str, HashMap<&'static str, String>)>>>);
+
+#[derive(Default)]
+struct SpanFields(HashMap<&'static str, String>);
+
+impl Visit for SpanFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0.insert(field.name(), format!("{value:?}"));
+    }
+}
+
+impl<S> Layer<S> for SpanRecorder
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = SpanFields::default();
+        attrs.record(&mut fields);
+        ctx.span(id).unwrap().extensions_mut().insert(fields);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).unwrap();
+        if let Some(fields) = span.extensions_mut().get_mut::<SpanFields>() {
+            values.record(fields);
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = ctx.span(&id).unwrap();
+        let fields = span
+            .extensions_mut()
+            .remove::<SpanFields>()
+            .unwrap_or_default();
+        self.0.lock().unwrap().push((span.name(), fields.0));
+    }
+}
+
+impl SpanRecorder {
+    fn closed(&self, name: &str) -> Vec<HashMap<&'static str, String>> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(span, _)| *span == name)
+            .map(|(_, fields)| fields.clone())
+            .collect()
+    }
+}
+
+/// Check that the kad queries and the bitswap fetches are traced until completed.
+#[cfg(all(not(feature = "test_go_interop"), not(feature = "test_js_interop")))]
+#[tokio::test]
+async fn dht_queries_and_fetches_are_traced() {
+    use libipld::multibase::{self, Base};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let recorder = SpanRecorder::default();
+    // the nodes run on the current thread, within the default subscriber
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(recorder.clone()));
+
+    let (nodes, _) = spawn_bootstrapped_nodes::<2>().await;
+
+    nodes[0].get_closest_peers(nodes[1].id).await.unwrap();
+
+    let key = multibase::encode(Base::Base32Lower, nodes[1].id.to_bytes());
+    let queries = recorder.closed("kad.query");
+    assert!(
+        queries
+            .iter()
+            .any(|fields| fields["kind"] == "get_closest_peers"
+                && fields["key"] == key
+                && fields.contains_key("query_id")
+                && fields.contains_key("elapsed_ms")),
+        "{queries:?}"
+    );
+
+    let data = b"traced block\n".to_vec();
+    let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+    nodes[1]
+        .put_block(Block::new(cid, data).unwrap())
+        .await
+        .unwrap();
+    timeout(Duration::from_secs(10), nodes[0].get_block(&cid))
+        .await
+        .expect("block was not fetched")
+        .unwrap();
+
+    let fetches = recorder.closed("bitswap.fetch");
+    let fetch = fetches
+        .iter()
+        .find(|fields| fields["cid"] == cid.to_string())
+        .expect("fetch span closed");
+    assert!(["retrieved", "stored"].contains(&fetch["outcome"].as_str()));
+    assert!(fetch.contains_key("elapsed_ms"));
+}
+
+/// Check that concurrent provider lookups of the same key share a single kad query.
+#[cfg(all(not(feature = "test_go_interop"), not(feature = "test_js_interop")))]
+#[tokio::test]
+async fn concurrent_provider_lookups_share_a_query() {
+    use libipld::multibase::{self, Base};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let recorder = SpanRecorder::default();
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(recorder.clone()));
+
+    let (nodes, _) = spawn_bootstrapped_nodes::<2>().await;
+
+    let data = b"looked up twice\n".to_vec();
+    let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+    let key = multibase::encode(Base::Base32Lower, cid.hash().to_bytes());
+    let provider_queries = || {
+        recorder
+            .closed("kad.query")
+            .into_iter()
+            .filter(|fields| fields["kind"] == "get_providers" && fields["key"] == key)
+            .map(|fields| fields["query_id"].clone())
+            .collect::<Vec<_>>()
+    };
+
+    let (first, second) = tokio::join!(nodes[0].get_providers(cid), nodes[0].get_providers(cid));
+    let lookups = futures::future::join(
+        first.unwrap().collect::<Vec<_>>(),
+        second.unwrap().collect::<Vec<_>>(),
+    );
+    timeout(Duration::from_secs(30), lookups)
+        .await
+        .expect("the lookups completed");
+
+    assert_eq!(provider_queries().len(), 1);
+
+    // the query finished, a later lookup starts a new one
+    let lookup = nodes[0].get_providers(cid).await.unwrap();
+    timeout(Duration::from_secs(30), lookup.collect::<Vec<_>>())
+        .await
+        .expect("the lookup completed");
+
+    let queries = provider_queries();
+    assert_eq!(queries.len(), 2);
+    assert_ne!(queries[0], queries[1]);
+}
+
+/// Check that the DHT writes of a peer over the limit are dropped and lower its score.
+#[tokio::test]
+async fn dht_writes_over_the_limit_are_dropped() {
+    use rust_ipfs::{p2p::DhtWriteLimit, testing::memory_transport, UninitializedIpfsNoop};
+
+    let start = |limit: Option<DhtWriteLimit>| {
+        let uninit = UninitializedIpfsNoop::new()
+            .with_default()
+            .with_custom_transport(memory_transport())
+            .set_dht_write_limit(limit);
+        Node::with_builder(uninit, vec!["/memory/0".parse().unwrap()])
+    };
+    let limited = start(Some(DhtWriteLimit {
+        max_writes: 2,
+        interval: Duration::from_secs(60),
+    }))
+    .await;
+    let writer = start(None).await;
+
+    writer
+        .add_peer(limited.id, limited.addrs[0].clone())
+        .await
+        .unwrap();
+    writer.connect(limited.addrs[0].clone()).await.unwrap();
+
+    // the score is reported with the identified peers
+    let identified = async {
+        while !limited
+            .peers_info()
+            .await
+            .unwrap()
+            .iter()
+            .any(|info| info.peer_id == writer.id)
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    };
+    timeout(Duration::from_secs(10), identified)
+        .await
+        .expect("writer was identified");
+
+    for i in 0..4 {
+        writer
+            .dht_put(format!("key{i}"), b"value".to_vec(), Quorum::One)
+            .await
+            .unwrap();
+    }
+
+    let stats = limited.dht_stats().await.unwrap();
+    assert_eq!(stats.stored_records, 2);
+    assert_eq!(stats.rejected_records, 2);
+
+    let peers = limited.peers_info().await.unwrap();
+    let peer = peers.iter().find(|info| info.peer_id == writer.id).unwrap();
+    assert_eq!(peer.score, -2);
+}
+
+#[tokio::test]
+async fn dht_requests_fail_when_kad_is_disabled() {
+    fn assert_kad_disabled<T>(result: Result<T, rust_ipfs::Error>) {
+        let Err(e) = result else {
+            panic!("kad is disabled");
+        };
+        assert_eq!(
+            e.downcast_ref(),
+            Some(&ProtocolDisabled(ProtocolKind::Kademlia))
+        );
+        assert_eq!(e.to_string(), "kad protocol is disabled");
+    }
+
+    let ipfs = UninitializedIpfsNoop::new().start().await.unwrap();
+    let peer_id = PeerId::random();
+    let bootstrapper: Multiaddr = format!("/ip4/127.0.0.1/tcp/4001/p2p/{peer_id}")
+        .parse()
+        .unwrap();
+
+    let data = b"hello kad".to_vec();
+    let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+    ipfs.put_block(Block::new(cid, data).unwrap())
+        .await
+        .unwrap();
+
+    assert_kad_disabled(ipfs.bootstrap().await);
+    assert_kad_disabled(ipfs.bootstrap_report().await);
+    assert_kad_disabled(ipfs.provide(cid).await);
+    assert_kad_disabled(ipfs.provide_lazy(cid).await);
+    assert_kad_disabled(ipfs.provided().await);
+    assert_kad_disabled(ipfs.get_providers(cid).await);
+    assert_kad_disabled(ipfs.dht_get("key").await);
+    assert_kad_disabled(ipfs.dht_put("key", b"value".to_vec(), Quorum::One).await);
+    assert_kad_disabled(ipfs.get_closest_peers(peer_id).await);
+    assert_kad_disabled(ipfs.find_peer(peer_id).await);
+    assert_kad_disabled(ipfs.identity(Some(peer_id)).await);
+    assert_kad_disabled(ipfs.dht_mode(DhtMode::Server).await);
+    assert_kad_disabled(ipfs.dht_stats().await);
+    assert_kad_disabled(ipfs.dht_events().await);
+    assert_kad_disabled(ipfs.add_bootstrap(bootstrapper.clone()).await);
+    assert_kad_disabled(ipfs.remove_bootstrap(bootstrapper).await);
+    assert_kad_disabled(ipfs.clear_bootstrap().await);
+    assert_kad_disabled(ipfs.default_bootstrap().await);
+    assert_kad_disabled(ipfs.bootstrap_policy().await);
+    assert_kad_disabled(ipfs.set_bootstrap_policy(Default::default()).await);
+}
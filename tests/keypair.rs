@@ -0,0 +1,98 @@
+use std::path::Path;
+
+use rust_ipfs::testing::memory_transport;
+use rust_ipfs::{Node, PeerId, UninitializedIpfsNoop};
+
+/// Private keys in the protobuf encoding of kubo's `Identity.PrivKey` with the peer identifiers
+/// kubo derives from them.
+const FIXTURES: &[(&str, &str)] = &[
+    (
+        "ed25519.key",
+        "12D3KooWGSs6EpzQdpy22pBgwePxX1TLgDZAyZQAzfehxkDpkbvt",
+    ),
+    (
+        "secp256k1.key",
+        "16Uiu2HAmGjZDAVNUaQ9NzdsvHL8PAXQqseahWzJTYnGWuYt9k6sj",
+    ),
+    ("rsa.key", "QmNzvTuriufA9kae5D1cC7uM9LRwTcMvCxq81kTYZjsKGU"),
+];
+
+fn fixture(name: &str) -> impl AsRef<Path> {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/keys")
+        .join(name)
+}
+
+async fn node(key: &str) -> Node {
+    let uninit = UninitializedIpfsNoop::new()
+        .with_default()
+        .with_custom_transport(memory_transport())
+        .load_or_create_keypair(fixture(key));
+    Node::with_builder(uninit, vec!["/memory/0".parse().unwrap()]).await
+}
+
+#[tokio::test]
+async fn peer_ids_match_kubo() {
+    for (key, expected) in FIXTURES {
+        let node = node(key).await;
+        assert_eq!(node.id, expected.parse::<PeerId>().unwrap(), "{key}");
+    }
+}
+
+#[tokio::test]
+async fn nodes_with_different_key_types_connect() {
+    let ed25519 = node("ed25519.key").await;
+    let secp256k1 = node("secp256k1.key").await;
+    let rsa = node("rsa.key").await;
+
+    for (a, b) in [(&ed25519, &secp256k1), (&secp256k1, &rsa), (&rsa, &ed25519)] {
+        let peer_id = a.connect_addr(b.addrs[0].clone()).await.unwrap();
+        assert_eq!(peer_id, b.id);
+    }
+}
+
+#[tokio::test]
+async fn created_keypair_is_persisted() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.key");
+
+    let first = UninitializedIpfsNoop::new()
+        .load_or_create_keypair(&path)
+        .start()
+        .await
+        .unwrap();
+    let peer_id = first.keypair().public().to_peer_id();
+    first.exit_daemon().await;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    let second = UninitializedIpfsNoop::new()
+        .load_or_create_keypair(&path)
+        .start()
+        .await
+        .unwrap();
+    assert_eq!(second.keypair().public().to_peer_id(), peer_id);
+}
+
+#[tokio::test]
+async fn rsa_keypair_cannot_be_created() {
+    let dir = tempfile::tempdir().unwrap();
+    let rsa = UninitializedIpfsNoop::new()
+        .load_or_create_keypair(fixture("rsa.key"))
+        .start()
+        .await
+        .unwrap();
+
+    UninitializedIpfsNoop::new()
+        .set_keypair(rsa.keypair())
+        .load_or_create_keypair(dir.path().join("identity.key"))
+        .start()
+        .await
+        .unwrap_err();
+    assert!(!dir.path().join("identity.key").exists());
+}
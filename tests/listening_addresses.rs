@@ -91,6 +91,32 @@ async fn listening_for_multiple_unspecified_addresses() {
     );
 }
 
+#[tokio::test]
+async fn unspecified_addr_resolves_with_every_interface() {
+    let node = rust_ipfs::Node::new("test_node").await;
+
+    let target = libp2p::build_multiaddr!(Ip4([0, 0, 0, 0]), Tcp(0u16));
+    let addrs = node.add_listening_addresses(target.clone()).await.unwrap();
+
+    // at least the loopback interface, all on the same assigned port
+    assert!(addrs.iter().any(|addr| addr.is_loopback()), "{addrs:?}");
+    assert!(addrs.iter().all(|addr| !addr.is_unspecified()));
+    let port = |addr: &libp2p::Multiaddr| addr.iter().last();
+    assert!(addrs.iter().all(|addr| port(addr) == port(&addrs[0])));
+
+    let listener = node
+        .listeners()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|listener| listener.requested == target)
+        .expect("requested address is listed");
+    assert_eq!(listener.confirmed, addrs);
+
+    let listening = node.listening_addresses().await.unwrap();
+    assert!(addrs.iter().all(|addr| listening.contains(addr)));
+}
+
 #[tokio::test]
 async fn remove_listening_address() {
     let node = rust_ipfs::Node::new("test_node").await;
@@ -106,6 +132,23 @@ async fn remove_listening_address() {
     node.remove_listening_address(first).await.unwrap();
 }
 
+#[tokio::test]
+async fn removed_listening_address_is_not_listened_on_again() {
+    let node = rust_ipfs::UninitializedIpfs::new()
+        .enable_relisten()
+        .start()
+        .await
+        .unwrap();
+
+    let unbound = libp2p::build_multiaddr!(Ip4([127, 0, 0, 1]), Tcp(0u16));
+    let first = node.add_listening_address(unbound).await.unwrap();
+    node.remove_listening_address(first).await.unwrap();
+
+    // past the first backoff
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    assert!(node.listening_addresses().await.unwrap().is_empty());
+}
+
 #[tokio::test]
 async fn pre_configured_listening_addrs() {
     use libp2p::Multiaddr;
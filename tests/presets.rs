@@ -0,0 +1,44 @@
+use rust_ipfs::UninitializedIpfsNoop;
+
+#[tokio::test]
+async fn minimal_preset() {
+    let ipfs = UninitializedIpfsNoop::minimal().start().await.unwrap();
+
+    ipfs.mdns_peers().await.unwrap_err();
+    ipfs.nat_status().await.unwrap_err();
+    ipfs.relay_status().await.unwrap_err();
+    ipfs.pubsub_subscribed().await.unwrap_err();
+}
+
+#[tokio::test]
+async fn desktop_preset() {
+    let ipfs = UninitializedIpfsNoop::desktop().start().await.unwrap();
+
+    ipfs.mdns_peers().await.unwrap();
+    ipfs.nat_status().await.unwrap();
+    let status = ipfs.relay_status().await.unwrap();
+    assert!(status.reservations.is_empty());
+}
+
+#[tokio::test]
+async fn server_preset() {
+    let ipfs = UninitializedIpfsNoop::server().start().await.unwrap();
+
+    ipfs.nat_status().await.unwrap();
+    ipfs.mdns_peers().await.unwrap_err();
+    ipfs.relay_status().await.unwrap_err();
+    ipfs.relay_server_stats().await.unwrap_err();
+}
+
+#[tokio::test]
+async fn presets_are_overridden_by_the_builder() {
+    let ipfs = UninitializedIpfsNoop::minimal()
+        .with_mdns()
+        .with_relay_server(Default::default())
+        .start()
+        .await
+        .unwrap();
+
+    ipfs.mdns_peers().await.unwrap();
+    ipfs.relay_server_stats().await.unwrap();
+}
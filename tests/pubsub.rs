@@ -346,3 +346,105 @@ async fn pubsub_interop() {
         "timed out before both nodes appeared as pubsub peers"
     );
 }
+
+#[tokio::test]
+async fn new_pins_are_announced_to_the_peers() {
+    use libipld::multihash::{Code, MultihashDigest};
+    use libipld::{Cid, IpldCodec};
+    use rust_ipfs::p2p::ContentAnnouncerConfig;
+    use rust_ipfs::{Block, UninitializedIpfsNoop};
+
+    let config = ContentAnnouncerConfig {
+        topic: "announce".into(),
+        ..Default::default()
+    };
+    let announcer = || {
+        UninitializedIpfsNoop::new()
+            .with_default()
+            .with_content_announcer(config.clone())
+    };
+    let a = Node::with_builder(announcer(), vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()]).await;
+    let b = Node::with_builder(announcer(), vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()]).await;
+    a.connect(b.addrs[0].clone()).await.unwrap();
+
+    // the announcement is only published to the peers known to be subscribed
+    timeout(Duration::from_secs(10), async {
+        while !a
+            .pubsub_peers(Some(config.topic.clone()))
+            .await
+            .unwrap()
+            .contains(&b.id)
+        {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("b to subscribe to the announcements");
+
+    let data = b"announced block".to_vec();
+    let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+    a.put_block(Block::new(cid, data).unwrap()).await.unwrap();
+    a.insert_pin(&cid).await.unwrap();
+
+    let providers = timeout(Duration::from_secs(10), async {
+        loop {
+            let providers = b.announced_providers(&cid).await.unwrap();
+            if !providers.is_empty() {
+                break providers;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("the pin to be announced");
+    assert_eq!(providers, vec![a.id]);
+
+    // the block is fetched from the announcer
+    let block = timeout(Duration::from_secs(10), b.get_block(&cid))
+        .await
+        .expect("block to be fetched")
+        .unwrap();
+    assert_eq!(block.cid(), &cid);
+
+    // the announcements of the node itself are not recorded
+    assert!(a.announced_providers(&cid).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn mesh_of_the_subscribed_topics_is_exposed() {
+    use rust_ipfs::{MeshEvent, MeshInfo};
+
+    let nodes = spawn_nodes::<2>(Topology::Line).await;
+    let topic = "meshed".to_owned();
+
+    let mut events = nodes[0]
+        .pubsub_mesh_events(Some(topic.clone()))
+        .await
+        .unwrap();
+    let _a_msgs = nodes[0].pubsub_subscribe(topic.clone()).await.unwrap();
+    let _b_msgs = nodes[1].pubsub_subscribe(topic.clone()).await.unwrap();
+
+    // the peers are grafted on the heartbeats once known to be subscribed
+    let event = timeout(Duration::from_secs(10), events.next())
+        .await
+        .expect("the peer to be grafted")
+        .unwrap();
+    assert_eq!(
+        event,
+        MeshEvent::Graft {
+            topic: topic.clone(),
+            peer_id: nodes[1].id
+        }
+    );
+
+    let mesh = nodes[0].pubsub_mesh(topic.clone()).await.unwrap();
+    assert_eq!(mesh.mesh_peers, vec![nodes[1].id]);
+    assert!(mesh.fanout_peers.is_empty());
+    assert!(mesh.gossip_peers.is_empty());
+
+    // not an error for the topics not subscribed to
+    assert_eq!(
+        nodes[0].pubsub_mesh("unsubscribed").await.unwrap(),
+        MeshInfo::default()
+    );
+}
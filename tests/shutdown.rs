@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use rust_ipfs::{FacadeError, Node, ShutdownReason, UninitializedIpfs};
+use tokio::time::timeout;
+
+#[tokio::test]
+async fn graceful_shutdown_stops_the_task_and_its_connections() {
+    let a = Node::new("a").await;
+    let b = Node::new("b").await;
+    a.connect(b.addrs[0].clone()).await.unwrap();
+
+    let handle = a.ipfs.task_handle();
+    let a = a.ipfs.clone();
+    a.clone()
+        .shutdown_graceful(Duration::from_secs(5))
+        .await
+        .unwrap();
+
+    // the background task exited by the time the shutdown completes
+    let reason = timeout(Duration::from_secs(1), handle.join())
+        .await
+        .expect("task of the node is still running");
+    assert_eq!(reason, ShutdownReason::Exited);
+
+    // the node no longer accepts any request
+    assert!(a.listening_addresses().await.is_err());
+
+    // the connections close as the swarm is dropped
+    timeout(Duration::from_secs(5), async {
+        while !b.connected().await.unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("connection of the node is still open");
+}
+
+#[tokio::test]
+async fn requests_to_a_shut_down_node_fail() {
+    let node = Node::new("a").await;
+    let ipfs = node.ipfs.clone();
+    node.ipfs
+        .clone()
+        .shutdown_graceful(Duration::from_secs(5))
+        .await
+        .unwrap();
+
+    assert_eq!(ipfs.queue_depth(), 0);
+
+    let error = ipfs.listening_addresses().await.unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<FacadeError>(),
+        Some(&FacadeError::NodeShutdown {
+            reason: ShutdownReason::Exited
+        })
+    );
+
+    let error = ipfs.try_dht_get("/key").await.err().unwrap();
+    assert_eq!(
+        error.downcast_ref::<FacadeError>(),
+        Some(&FacadeError::NodeShutdown {
+            reason: ShutdownReason::Exited
+        })
+    );
+}
+
+#[tokio::test]
+async fn requests_to_a_panicked_node_fail_with_the_panic() {
+    let ipfs = UninitializedIpfs::new()
+        .with_custom_behaviour(libp2p::swarm::dummy::Behaviour)
+        .start()
+        .await
+        .unwrap();
+    let handle = ipfs.task_handle();
+    assert_eq!(handle.reason(), None);
+
+    // the closure runs within the background task
+    ipfs.with_custom_behaviour(|_: &mut libp2p::swarm::dummy::Behaviour| {
+        panic!("poisoned handler")
+    })
+    .await
+    .unwrap_err();
+
+    let reason = timeout(Duration::from_secs(5), handle.join())
+        .await
+        .expect("task exited");
+    assert_eq!(reason, ShutdownReason::Panicked("poisoned handler".into()));
+
+    let error = ipfs.listening_addresses().await.unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<FacadeError>(),
+        Some(&FacadeError::NodeShutdown { reason })
+    );
+}
+
+#[tokio::test]
+async fn aborted_node_reports_the_abort() {
+    let node = Node::new("a").await;
+    let handle = node.ipfs.task_handle();
+    handle.abort();
+    assert_eq!(handle.join().await, ShutdownReason::Aborted);
+
+    let error = node.ipfs.listening_addresses().await.unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<FacadeError>(),
+        Some(&FacadeError::NodeShutdown {
+            reason: ShutdownReason::Aborted
+        })
+    );
+}
+
+#[tokio::test]
+async fn pending_try_get_block_fails_with_the_shutdown() {
+    use libipld::multihash::{Code, MultihashDigest};
+    use libipld::{Cid, IpldCodec};
+
+    let node = Node::new("a").await;
+    let ipfs = node.ipfs.clone();
+    let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(b"never found"));
+
+    let fetch = tokio::spawn({
+        let ipfs = ipfs.clone();
+        async move { ipfs.try_get_block(&cid).await }
+    });
+    timeout(Duration::from_secs(5), async {
+        while !ipfs.bitswap_wantlist(None).await.unwrap().contains(&cid) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("block was not wanted");
+
+    node.ipfs
+        .clone()
+        .shutdown_graceful(Duration::from_secs(5))
+        .await
+        .unwrap();
+
+    let error = fetch.await.unwrap().unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<FacadeError>(),
+        Some(&FacadeError::NodeShutdown {
+            reason: ShutdownReason::Exited
+        })
+    );
+}
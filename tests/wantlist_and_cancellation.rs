@@ -165,3 +165,90 @@ async fn wantlist_cancellation() {
     // ensure that there are no related subscriptions
     check_cid_subscriptions(&ipfs, &cid, 0).await;
 }
+
+/// Check that the fetches timing out no longer want the block nor look for its providers.
+#[tokio::test]
+#[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
+async fn timed_out_operations_are_cancelled() {
+    use futures::StreamExt;
+    use libp2p::{Multiaddr, PeerId};
+    use rust_ipfs::{dag::ResolveError, error::Timeout, unixfs::TraversalFailed};
+
+    let ipfs = Node::new("test_node").await;
+
+    // a DHT peer accepting the connections but never negotiating them keeps the provider
+    // lookups in progress
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    task::spawn(async move {
+        let mut sockets = vec![];
+        while let Ok((socket, _)) = listener.accept().await {
+            sockets.push(socket);
+        }
+    });
+    let addr: Multiaddr = format!("/ip4/127.0.0.1/tcp/{port}").parse().unwrap();
+    ipfs.add_peer(PeerId::random(), addr).await.unwrap();
+
+    let cid = Cid::try_from("QmSoLPppuBtQSGwKDZT2M73ULpjvfd3aZ6ha4oFGL1KaGa").unwrap();
+    let deadline = Duration::from_millis(500);
+
+    let nothing_left = || async {
+        check_cid_subscriptions(&ipfs, &cid, 0).await;
+        let wantlist_cleared = bounded_retry(
+            Duration::from_secs(5),
+            || ipfs.bitswap_wantlist(None),
+            |ret| ret.unwrap().is_empty(),
+        )
+        .await;
+        assert!(wantlist_cleared.is_ok(), "the block is still wanted");
+        let queries_finished = bounded_retry(
+            Duration::from_secs(5),
+            || ipfs.dht_queries(),
+            |ret| ret.unwrap() == 0,
+        )
+        .await;
+        assert!(
+            queries_finished.is_ok(),
+            "the provider lookup is still in progress"
+        );
+    };
+
+    let e = ipfs.get_block(&cid).deadline(deadline).await.unwrap_err();
+    let e = e.downcast_ref::<Timeout>().unwrap();
+    assert_eq!(e.operation, "get_block");
+    assert_eq!(e.elapsed, deadline);
+    nothing_left().await;
+
+    let e = ipfs.get_dag(cid).deadline(deadline).await.unwrap_err();
+    assert!(matches!(
+        e,
+        ResolveError::Deadline(Timeout {
+            operation: "dag_get",
+            ..
+        })
+    ));
+    nothing_left().await;
+
+    let e = ipfs
+        .resolve(cid.into())
+        .deadline(deadline)
+        .await
+        .unwrap_err();
+    assert!(matches!(e, ResolveError::Deadline(_)));
+    nothing_left().await;
+
+    let mut cat = ipfs.cat_unixfs(cid).deadline(deadline);
+    assert!(matches!(
+        cat.next().await,
+        Some(Err(TraversalFailed::Deadline(_)))
+    ));
+    assert!(cat.next().await.is_none());
+    nothing_left().await;
+
+    let providers = ipfs.get_providers(cid).deadline(deadline).await.unwrap();
+    let found = timeout(Duration::from_secs(5), providers.collect::<Vec<_>>())
+        .await
+        .expect("the providers stream to end");
+    assert!(found.is_empty());
+    nothing_left().await;
+}
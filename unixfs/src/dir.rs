@@ -77,6 +77,66 @@ pub fn resolve<'needle>(
     }
 }
 
+/// Lists the links of a single dag-pb or UnixFS directory block (normal, sharded).
+///
+/// For HAMT sharded directories the bucket prefixes are removed from the entry names, and the
+/// links to the nested buckets are returned as [`ListedLink::Bucket`]. These need to be loaded and
+/// listed in turn to find all of the entries of the directory.
+#[allow(clippy::result_large_err)]
+pub fn list(block: &[u8]) -> Result<Vec<ListedLink>, ResolveError> {
+    let (links, sharded) = match FlatUnixFs::try_parse(block) {
+        Ok(mut hamt) if hamt.data.Type == UnixFsType::HAMTShard => {
+            ShardedLookup::check_supported(&mut hamt)?;
+            (hamt.links, true)
+        }
+        Ok(flat) if flat.data.Type == UnixFsType::Directory => {
+            (check_directory_supported(flat)?.links, false)
+        }
+        Err(ParsingFailed::InvalidUnixFs(_, PBNode { Links: links, .. }))
+        | Err(ParsingFailed::NoData(PBNode { Links: links, .. })) => (links, false),
+        Ok(other) => return Err(ResolveError::UnexpectedType(other.data.Type.into())),
+        Err(ParsingFailed::InvalidDagPb(e)) => return Err(ResolveError::Read(e)),
+    };
+
+    let mut listed = Vec::with_capacity(links.len());
+
+    for (i, link) in links.into_iter().enumerate() {
+        let name = link.Name.as_deref().unwrap_or_default().to_owned();
+        let size = link.Tsize.unwrap_or_default();
+        let cid = try_convert_cid(i, link)?;
+
+        listed.push(match name.len() {
+            // the magic number of two comes from the fanout (256)
+            2 if sharded => ListedLink::Bucket(cid),
+            len if sharded && len > 2 => ListedLink::Entry {
+                name: name[2..].to_owned(),
+                cid,
+                size,
+            },
+            _ if sharded => continue,
+            _ => ListedLink::Entry { name, cid, size },
+        });
+    }
+
+    Ok(listed)
+}
+
+/// Link of a directory listed by [`list`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListedLink {
+    /// An entry of the directory.
+    Entry {
+        /// Name of the entry
+        name: String,
+        /// Target of the link
+        cid: Cid,
+        /// Cumulative size of the linked DAG, as recorded in the link
+        size: u64,
+    },
+    /// A nested bucket of a HAMT sharded directory.
+    Bucket(Cid),
+}
+
 fn try_convert_cid(nth: usize, link: PBLink<'_>) -> Result<Cid, InvalidCidInLink> {
     let hash = link.Hash.as_deref().unwrap_or_default();
     Cid::try_from(hash).map_err(|e| InvalidCidInLink::from((nth, link, e)))
@@ -213,7 +273,7 @@ impl MultipleMatchingLinks {
 #[cfg(test)]
 mod tests {
 
-    use super::{resolve, MaybeResolved};
+    use super::{list, resolve, ListedLink, MaybeResolved};
     use crate::test_support::FakeBlockstore;
     use core::convert::TryFrom;
     use hex_literal::hex;
@@ -297,4 +357,65 @@ mod tests {
             "QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL"
         );
     }
+
+    #[test]
+    fn list_plain_directory() {
+        let blocks = FakeBlockstore::with_fixtures();
+
+        let block = blocks.get_by_str("QmVkvLsSEm2uJx1h5Fqukje8mMPYg393o5C2kMCkF2bBTA");
+
+        let names = list(block)
+            .unwrap()
+            .into_iter()
+            .map(|link| match link {
+                ListedLink::Entry { name, size, .. } => (name, size),
+                x => unreachable!("{:?}", x),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            names,
+            [
+                ("foobar.balanced".to_owned(), 221),
+                ("foobar.trickle".to_owned(), 221)
+            ]
+        );
+    }
+
+    #[test]
+    fn list_sharded_directory() {
+        let blocks = FakeBlockstore::with_fixtures();
+
+        let block = blocks.get_by_str("QmZbFPTnDBMWbQ6iBxQAhuhLz8Nu9XptYS96e7cuf5wvbk");
+
+        let buckets = list(block).unwrap();
+        assert_eq!(buckets.len(), 8);
+
+        let mut names = Vec::new();
+
+        for bucket in buckets {
+            let cid = match bucket {
+                ListedLink::Bucket(cid) => cid,
+                x => unreachable!("{:?}", x),
+            };
+
+            for link in list(blocks.get_by_cid(&cid)).unwrap() {
+                match link {
+                    ListedLink::Entry { name, .. } => names.push(name),
+                    x => unreachable!("{:?}", x),
+                }
+            }
+        }
+
+        assert_eq!(names.len(), 16);
+        assert!(names
+            .iter()
+            .all(|name| name.starts_with("long-named-file-")));
+    }
+
+    #[test]
+    fn list_errors_with_file() {
+        let payload = hex!("0a130802120d666f6f6261720a666f6f626172180d");
+        list(&payload[..]).unwrap_err();
+    }
 }
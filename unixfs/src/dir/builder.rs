@@ -13,6 +13,9 @@ pub use buffered::BufferingTreeBuilder;
 mod custom_pb;
 use custom_pb::CustomFlatUnixFs;
 
+mod sharded;
+pub use sharded::{render_directory, DirectoryNode, HAMT_SHARDING_SIZE};
+
 enum Entry {
     Leaf(Leaf),
     Directory(DirBuilder),
@@ -130,6 +133,8 @@ pub enum TreeConstructionFailed {
     /// The resulting directory would be too large and HAMT sharding is yet to be implemented or
     /// denied.
     TooLargeBlock(u64),
+    /// The names hash to the same value, which cannot be told apart in a HAMT shard.
+    CollidingNames(String, String),
 }
 
 impl fmt::Display for TreeConstructionFailed {
@@ -139,6 +144,7 @@ impl fmt::Display for TreeConstructionFailed {
         match self {
             Protobuf(e) => write!(fmt, "serialization failed: {e}"),
             TooLargeBlock(size) => write!(fmt, "attempted to create block of {size} bytes"),
+            CollidingNames(a, b) => write!(fmt, "names {a:?} and {b:?} have the same hash"),
         }
     }
 }
@@ -0,0 +1,297 @@
+//! Rendering of a single directory level, as a HAMT sharded directory once too large in the same
+//! way as go-ipfs.
+
+use super::TreeConstructionFailed;
+use crate::pb::{FlatUnixFs, PBLink, UnixFs, UnixFsType};
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use libipld::multihash::{Code, Multihash};
+use libipld::Cid;
+use quick_protobuf::{MessageWrite, Writer};
+
+/// Estimated size of a directory at which go-ipfs shards it, see [`render_directory`].
+pub const HAMT_SHARDING_SIZE: usize = 256 * 1024;
+
+/// Fanout of the shards, the only one supported when reading them.
+const FANOUT: u64 = 256;
+
+/// Multicodec of the murmur3-x64-64 hash of the names in the shards.
+const MURMUR3_X64_64: u64 = 0x22;
+
+/// A dag-pb node of a directory rendered with [`render_directory`].
+#[derive(Debug)]
+pub struct DirectoryNode {
+    /// The Cid of the document.
+    pub cid: Cid,
+    /// Cumulative total size of the subtree in bytes.
+    pub total_size: u64,
+    /// Raw dag-pb document.
+    pub block: Box<[u8]>,
+}
+
+/// Renders the directory with the given links, keyed by name with the target and its cumulative
+/// size, into its dag-pb nodes in post order, the root being the last one.
+///
+/// The directory is sharded once its size estimated as go-ipfs does, the length of the names and
+/// the targets, reaches `sharding_size`, so that the Cids are the same as go-ipfs for the same
+/// links. `None` never shards the directory.
+pub fn render_directory(
+    links: &BTreeMap<String, (Cid, u64)>,
+    sharding_size: Option<usize>,
+) -> Result<Vec<DirectoryNode>, TreeConstructionFailed> {
+    let estimated_size = links
+        .iter()
+        .map(|(name, (cid, _))| name.len() + cid.encoded_len())
+        .sum::<usize>();
+
+    let mut nodes = Vec::new();
+    match sharding_size {
+        Some(limit) if estimated_size >= limit => {
+            let entries = links
+                .iter()
+                .map(|(name, (cid, size))| {
+                    (murmur3_x64_64(name.as_bytes()), name.as_str(), cid, *size)
+                })
+                .collect();
+            render_shard(entries, 0, &mut nodes)?;
+        }
+        _ => {
+            let links = links
+                .iter()
+                .map(|(name, (cid, size))| pb_link(name.clone(), cid, *size))
+                .collect();
+            let data = UnixFs {
+                Type: UnixFsType::Directory,
+                ..Default::default()
+            };
+            nodes.push(render_node(links, data)?);
+        }
+    }
+    Ok(nodes)
+}
+
+type ShardEntry<'a> = (u64, &'a str, &'a Cid, u64);
+
+/// Renders the shard of the entries at the depth, after its nested shards.
+fn render_shard(
+    entries: Vec<ShardEntry<'_>>,
+    depth: u32,
+    nodes: &mut Vec<DirectoryNode>,
+) -> Result<(Cid, u64), TreeConstructionFailed> {
+    // each level consumes the next byte of the hash, starting from the most significant one
+    let mut buckets: BTreeMap<u8, Vec<ShardEntry<'_>>> = BTreeMap::new();
+    for entry in entries {
+        let index = (entry.0 >> (56 - 8 * depth)) as u8;
+        buckets.entry(index).or_default().push(entry);
+    }
+
+    let mut bitfield = [0u8; FANOUT as usize / 8];
+    let mut links = Vec::with_capacity(buckets.len());
+    for (index, mut entries) in buckets {
+        bitfield[bitfield.len() - 1 - index as usize / 8] |= 1 << (index % 8);
+
+        if entries.len() == 1 {
+            let (_, name, cid, size) = entries.pop().expect("single entry");
+            links.push(pb_link(format!("{index:02X}{name}"), cid, size));
+            continue;
+        }
+
+        if depth == 7 {
+            return Err(TreeConstructionFailed::CollidingNames(
+                entries[0].1.to_owned(),
+                entries[1].1.to_owned(),
+            ));
+        }
+        let (cid, total_size) = render_shard(entries, depth + 1, nodes)?;
+        links.push(pb_link(format!("{index:02X}"), &cid, total_size));
+    }
+
+    // the bitfield is written as a big-endian big integer, without its leading zeroes
+    let start = bitfield
+        .iter()
+        .position(|byte| *byte != 0)
+        .unwrap_or(bitfield.len());
+    let data = UnixFs {
+        Type: UnixFsType::HAMTShard,
+        Data: Some(Cow::Owned(bitfield[start..].to_vec())),
+        hashType: Some(MURMUR3_X64_64),
+        fanout: Some(FANOUT),
+        ..Default::default()
+    };
+
+    let node = render_node(links, data)?;
+    let rendered = (node.cid, node.total_size);
+    nodes.push(node);
+    Ok(rendered)
+}
+
+fn pb_link(name: String, cid: &Cid, total_size: u64) -> PBLink<'static> {
+    PBLink {
+        Hash: Some(Cow::Owned(cid.to_bytes())),
+        Name: Some(Cow::Owned(name)),
+        Tsize: Some(total_size),
+    }
+}
+
+fn render_node(
+    links: Vec<PBLink<'_>>,
+    data: UnixFs<'_>,
+) -> Result<DirectoryNode, TreeConstructionFailed> {
+    use sha2::{Digest, Sha256};
+
+    let combined_from_links = links.iter().filter_map(|link| link.Tsize).sum::<u64>();
+    let node = FlatUnixFs { links, data };
+
+    let mut block = Vec::with_capacity(node.get_size());
+    node.write_message(&mut Writer::new(&mut block))
+        .map_err(TreeConstructionFailed::Protobuf)?;
+
+    let mh = Multihash::wrap(Code::Sha2_256.into(), &Sha256::digest(&block)).unwrap();
+    let cid = Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0");
+
+    Ok(DirectoryNode {
+        cid,
+        total_size: block.len() as u64 + combined_from_links,
+        block: block.into_boxed_slice(),
+    })
+}
+
+/// The first half of the 128-bit x64 murmur3 hash with a zero seed, as used by go-ipfs for the
+/// names in the shards.
+fn murmur3_x64_64(data: &[u8]) -> u64 {
+    const C1: u64 = 0x87c3_7b91_1142_53d5;
+    const C2: u64 = 0x4cf5_ad43_2745_937f;
+
+    let mix_k1 = |k1: u64| k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+    let mix_k2 = |k2: u64| k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+
+    let (mut h1, mut h2) = (0u64, 0u64);
+    let mut chunks = data.chunks_exact(16);
+    for chunk in &mut chunks {
+        let k1 = u64::from_le_bytes(chunk[..8].try_into().expect("8 bytes"));
+        let k2 = u64::from_le_bytes(chunk[8..].try_into().expect("8 bytes"));
+
+        h1 ^= mix_k1(k1);
+        h1 = h1
+            .rotate_left(27)
+            .wrapping_add(h2)
+            .wrapping_mul(5)
+            .wrapping_add(0x52dc_e729);
+        h2 ^= mix_k2(k2);
+        h2 = h2
+            .rotate_left(31)
+            .wrapping_add(h1)
+            .wrapping_mul(5)
+            .wrapping_add(0x3849_5ab5);
+    }
+
+    let tail = chunks.remainder();
+    let (mut k1, mut k2) = (0u64, 0u64);
+    for (i, byte) in tail.iter().enumerate() {
+        match i {
+            0..=7 => k1 ^= (*byte as u64) << (8 * i),
+            _ => k2 ^= (*byte as u64) << (8 * (i - 8)),
+        }
+    }
+    if tail.len() > 8 {
+        h2 ^= mix_k2(k2);
+    }
+    if !tail.is_empty() {
+        h1 ^= mix_k1(k1);
+    }
+
+    h1 ^= data.len() as u64;
+    h2 ^= data.len() as u64;
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    h1 = fmix64(h1);
+    h2 = fmix64(h2);
+    h1.wrapping_add(h2)
+}
+
+fn fmix64(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    k ^ (k >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{murmur3_x64_64, render_directory, HAMT_SHARDING_SIZE};
+    use crate::dir::{list, ListedLink};
+    use alloc::collections::BTreeMap;
+    use core::convert::TryFrom;
+    use libipld::Cid;
+
+    #[test]
+    fn murmur3_matches_the_reference() {
+        assert_eq!(murmur3_x64_64(b""), 0);
+        assert_eq!(murmur3_x64_64(b"foo"), 0xe271_8657_01f5_4561);
+        assert_eq!(
+            murmur3_x64_64(b"The quick brown fox jumps over the lazy dog"),
+            0xe34b_bc7b_bc07_1b6c
+        );
+    }
+
+    #[test]
+    fn flat_directories_match_go_ipfs() {
+        // foobar\n
+        let five_block_foobar =
+            Cid::try_from("QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6").unwrap();
+
+        let empty = render_directory(&BTreeMap::new(), Some(HAMT_SHARDING_SIZE)).unwrap();
+        assert_eq!(
+            empty[0].cid.to_string(),
+            "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"
+        );
+
+        // the same as BufferingTreeBuilder in `multiple_roots`
+        let links = BTreeMap::from([
+            ("b".to_owned(), (five_block_foobar, 221)),
+            ("a".to_owned(), (five_block_foobar, 221)),
+        ]);
+        let nodes = render_directory(&links, Some(HAMT_SHARDING_SIZE)).unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(
+            nodes[0].cid.to_string(),
+            "QmdbWuhpVCX9weVMMqvVTMeGwKMqCNJDbx7ZK1zG36sea7"
+        );
+    }
+
+    #[test]
+    fn large_directories_are_sharded() {
+        let five_block_foobar =
+            Cid::try_from("QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6").unwrap();
+        let links = (0..1000)
+            .map(|i| (format!("file-{i}"), (five_block_foobar, 221)))
+            .collect::<BTreeMap<_, _>>();
+
+        assert_eq!(render_directory(&links, None).unwrap().len(), 1);
+
+        let nodes = render_directory(&links, Some(1024)).unwrap();
+        assert!(nodes.len() > 1);
+
+        // every name is found once walking the buckets from the root
+        let by_cid = nodes
+            .iter()
+            .map(|node| (node.cid, &node.block))
+            .collect::<BTreeMap<_, _>>();
+        let mut pending = vec![nodes.last().unwrap().cid];
+        let mut names = Vec::new();
+        while let Some(cid) = pending.pop() {
+            for link in list(by_cid[&cid]).unwrap() {
+                match link {
+                    ListedLink::Entry { name, cid, size } => {
+                        assert_eq!((cid, size), (five_block_foobar, 221));
+                        names.push(name);
+                    }
+                    ListedLink::Bucket(cid) => pending.push(cid),
+                }
+            }
+        }
+        names.sort();
+        assert_eq!(names, links.into_keys().collect::<Vec<_>>());
+    }
+}
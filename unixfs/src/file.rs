@@ -2,10 +2,12 @@
 //!
 //! The module provides low-level File tree visitor support and file importing support. Note: The
 //! [`ipfs_unixfs::walk::Walker`] should typically be used for accessing file content.
-use crate::pb::ParsingFailed;
+use crate::pb::{FlatUnixFs, ParsingFailed, UnixFsType};
 use crate::{InvalidCidInLink, Metadata, UnexpectedNodeType};
 use alloc::borrow::Cow;
+use core::convert::TryFrom;
 use core::fmt;
+use libipld::Cid;
 
 /// Low level UnixFS file descriptor reader support.
 mod reader;
@@ -16,6 +18,63 @@ pub mod visit;
 /// File adder capable of constructing UnixFs v1 trees
 pub mod adder;
 
+/// Multicodec of the raw leaf blocks, which contain only file content.
+pub(crate) const RAW_CODEC: u64 = 0x55;
+
+/// Link of a UnixFs file block to a part of the file, as returned by [`links`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileLink {
+    /// The linked leaf or subtree.
+    pub cid: Cid,
+    /// Cumulative size of the linked blocks.
+    pub total_size: u64,
+    /// Length of the file content under the link.
+    pub file_size: u64,
+}
+
+/// Returns the inline content and the links of a single UnixFs file block, without following
+/// the links. Leaves only have content, and the nodes above them usually only have links; the
+/// content comes before the linked parts of the file.
+pub fn links(block: &[u8]) -> Result<(&[u8], Vec<FileLink>), FileReadFailed> {
+    let inner = FlatUnixFs::try_from(block)?;
+
+    if inner.data.Type != UnixFsType::File && inner.data.Type != UnixFsType::Raw {
+        return Err(FileReadFailed::UnexpectedType(inner.data.Type.into()));
+    }
+
+    if inner.links.len() != inner.data.blocksizes.len() {
+        return Err(FileError::LinksAndBlocksizesMismatch.into());
+    }
+
+    let content = inner.data.Data.unwrap_borrowed_or_empty();
+    let mut links = Vec::with_capacity(inner.links.len());
+
+    for (nth, (link, file_size)) in inner
+        .links
+        .into_iter()
+        .zip(inner.data.blocksizes)
+        .enumerate()
+    {
+        let total_size = link.Tsize.unwrap_or_default();
+        let cid = match Cid::try_from(link.Hash.as_deref().unwrap_or_default()) {
+            Ok(cid) => cid,
+            Err(e) => {
+                return Err(FileReadFailed::InvalidCid(InvalidCidInLink::from((
+                    nth, link, e,
+                ))))
+            }
+        };
+
+        links.push(FileLink {
+            cid,
+            total_size,
+            file_size,
+        });
+    }
+
+    Ok((content, links))
+}
+
 /// Describes the errors which can happen during a visit or lower level block-by-block walking of
 /// the DAG.
 #[derive(Debug)]
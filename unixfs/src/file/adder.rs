@@ -1,6 +1,8 @@
-use libipld::multihash::{self, Multihash};
+use libipld::cid::Version;
+use libipld::multihash::{self, Code, Multihash, MultihashDigest};
 use libipld::Cid;
 
+use super::{FileLink, RAW_CODEC};
 use crate::pb::{FlatUnixFs, PBLink, UnixFs, UnixFsType};
 use alloc::borrow::Cow;
 use core::fmt;
@@ -11,15 +13,15 @@ use sha2::{Digest, Sha256};
 /// File tree builder. Implements [`core::default::Default`] which tracks the recent defaults.
 ///
 /// Custom file tree builder can be created with [`FileAdder::builder()`] and configuring the
-/// chunker and collector.
+/// chunker, collector, leaf format, Cid version and hash function.
 ///
-/// Current implementation maintains an internal buffer for the block creation and uses a
-/// non-customizable hash function to produce Cid version 0 links. Currently does not support
-/// inline links.
+/// Current implementation maintains an internal buffer for the block creation. By default, Cid
+/// version 0 links are produced with sha2-256. Currently does not support inline links.
 #[derive(Default)]
 pub struct FileAdder {
     chunker: Chunker,
     collector: Collector,
+    format: BlockFormat,
     block_buffer: Vec<u8>,
     // all unflushed links as a flat vec; this is compacted as we grow and need to create a link
     // block for the last N blocks, as decided by the collector.
@@ -33,8 +35,9 @@ impl fmt::Debug for FileAdder {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             fmt,
-            "FileAdder {{ chunker: {:?}, block_buffer: {}/{}, unflushed_links: {} }}",
+            "FileAdder {{ chunker: {:?}, format: {:?}, block_buffer: {}/{}, unflushed_links: {} }}",
             self.chunker,
+            self.format,
             self.block_buffer.len(),
             self.block_buffer.capacity(),
             LinkFormatter(&self.unflushed_links),
@@ -110,11 +113,53 @@ impl fmt::Debug for Link {
     }
 }
 
+/// How the blocks produced by [`FileAdder`] are encoded and addressed.
+#[derive(Debug, Clone, Copy)]
+struct BlockFormat {
+    /// Leaves are stored as raw blocks instead of UnixFs nodes.
+    raw_leaves: bool,
+    version: Version,
+    hash: Code,
+}
+
+impl Default for BlockFormat {
+    fn default() -> Self {
+        BlockFormat {
+            raw_leaves: false,
+            version: Version::V0,
+            hash: Code::Sha2_256,
+        }
+    }
+}
+
+impl BlockFormat {
+    /// Cid version 0 is only possible for sha2-256 hashed dag-pb blocks; in other cases, version 1
+    /// is used.
+    fn cid(&self, codec: u64, block: &[u8]) -> Cid {
+        let mh = match self.hash {
+            Code::Sha2_256 => {
+                Multihash::wrap(multihash::Code::Sha2_256.into(), &Sha256::digest(block)).unwrap()
+            }
+            code => code.digest(block),
+        };
+
+        match self.version {
+            Version::V0 if codec == DAG_PB && self.hash == Code::Sha2_256 => {
+                Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0")
+            }
+            _ => Cid::new_v1(codec, mh),
+        }
+    }
+}
+
+const DAG_PB: u64 = 0x70;
+
 /// Convenience type to facilitate configuring [`FileAdder`]s.
 #[derive(Default)]
 pub struct FileAdderBuilder {
     chunker: Chunker,
     collector: Collector,
+    format: BlockFormat,
 }
 
 impl FileAdderBuilder {
@@ -131,13 +176,38 @@ impl FileAdderBuilder {
         }
     }
 
+    /// Configures the builder to store the file contents as raw blocks, instead of wrapping them
+    /// in UnixFs nodes. Raw blocks are always addressed with Cid version 1.
+    pub fn with_raw_leaves(mut self, raw_leaves: bool) -> Self {
+        self.format.raw_leaves = raw_leaves;
+        self
+    }
+
+    /// Configures the builder to use the given Cid version for the UnixFs nodes.
+    pub fn with_cid_version(mut self, version: Version) -> Self {
+        self.format.version = version;
+        self
+    }
+
+    /// Configures the builder to use the given hash function for all blocks. Cid version 0 can
+    /// only be used with sha2-256, so other hash functions always produce Cid version 1 links.
+    pub fn with_hash(mut self, hash: Code) -> Self {
+        self.format.hash = hash;
+        self
+    }
+
     /// Returns a new FileAdder
     pub fn build(self) -> FileAdder {
-        let FileAdderBuilder { chunker, collector } = self;
+        let FileAdderBuilder {
+            chunker,
+            collector,
+            format,
+        } = self;
 
         FileAdder {
             chunker,
             collector,
+            format,
             ..Default::default()
         }
     }
@@ -172,7 +242,7 @@ impl FileAdder {
             // blocks and user takes care of chunking (and buffering)?
             //
             // cat file | my_awesome_chunker | my_brilliant_collector
-            let leaf = Self::flush_buffered_leaf(accepted, &mut self.unflushed_links, false);
+            let leaf = self.flush_buffered_leaf(accepted, false);
             assert!(leaf.is_some(), "chunk completed, must produce a new block");
             self.block_buffer.clear();
             let links = self.flush_buffered_links(false);
@@ -194,11 +264,9 @@ impl FileAdder {
                 (None, Vec::new())
             } else {
                 // a new leaf must be output, as well as possibly a new link block
-                let leaf = Self::flush_buffered_leaf(
-                    self.block_buffer.as_slice(),
-                    &mut self.unflushed_links,
-                    false,
-                );
+                let block_buffer = core::mem::take(&mut self.block_buffer);
+                let leaf = self.flush_buffered_leaf(block_buffer.as_slice(), false);
+                self.block_buffer = block_buffer;
                 assert!(leaf.is_some(), "chunk completed, must produce a new block");
                 self.block_buffer.clear();
                 let links = self.flush_buffered_links(false);
@@ -209,6 +277,47 @@ impl FileAdder {
         }
     }
 
+    /// Called to link an already stored part of a file, such as an unchanged leaf of a modified
+    /// file, without pushing its bytes again. `depth` is the height of the linked subtree, zero for
+    /// a leaf. Any bytes buffered from earlier pushes are first stored as a leaf of their own.
+    ///
+    /// Returns the newly created blocks and their respective Cids.
+    ///
+    /// # Panics
+    ///
+    /// When a subtree is linked after a shallower one; the subtrees must be linked from the
+    /// deepest one, as they would be compacted by the collector.
+    pub fn push_link(
+        &mut self,
+        link: FileLink,
+        depth: usize,
+    ) -> impl Iterator<Item = (Cid, Vec<u8>)> {
+        let block_buffer = core::mem::take(&mut self.block_buffer);
+        let leaf = self.flush_buffered_leaf(&block_buffer, false);
+        self.block_buffer = block_buffer;
+        self.block_buffer.clear();
+
+        assert!(
+            self.unflushed_links
+                .last()
+                .map(|last| last.depth >= depth)
+                .unwrap_or(true),
+            "subtree of depth {} linked after {}",
+            depth,
+            LinkFormatter(&self.unflushed_links)
+        );
+
+        self.unflushed_links.push(Link {
+            depth,
+            target: link.cid,
+            total_size: link.total_size,
+            file_size: link.file_size,
+        });
+
+        let links = self.flush_buffered_links(false);
+        leaf.into_iter().chain(links)
+    }
+
     /// Called after the last [`FileAdder::push`] to finish the tree construction.
     ///
     /// Returns a list of Cids and their respective blocks.
@@ -216,8 +325,8 @@ impl FileAdder {
     /// Note: the API will hopefully evolve in a direction which will not allocate a new Vec for
     /// every block in the near-ish future.
     pub fn finish(mut self) -> impl Iterator<Item = (Cid, Vec<u8>)> {
-        let last_leaf =
-            Self::flush_buffered_leaf(&self.block_buffer, &mut self.unflushed_links, true);
+        let block_buffer = core::mem::take(&mut self.block_buffer);
+        let last_leaf = self.flush_buffered_leaf(&block_buffer, true);
         let root_links = self.flush_buffered_links(true);
         // should probably error if there is neither?
         last_leaf.into_iter().chain(root_links)
@@ -225,37 +334,42 @@ impl FileAdder {
 
     /// Returns `None` when the input is empty but there are links, otherwise a new Cid and a
     /// block.
-    fn flush_buffered_leaf(
-        input: &[u8],
-        unflushed_links: &mut Vec<Link>,
-        finishing: bool,
-    ) -> Option<(Cid, Vec<u8>)> {
-        if input.is_empty() && (!finishing || !unflushed_links.is_empty()) {
+    fn flush_buffered_leaf(&mut self, input: &[u8], finishing: bool) -> Option<(Cid, Vec<u8>)> {
+        if input.is_empty() && (!finishing || !self.unflushed_links.is_empty()) {
             return None;
         }
 
-        // for empty unixfs file the bytes is missing but filesize is present.
+        if input.is_empty() && !self.collector.wants_empty_leaf() {
+            // the collector will create the root block without any links
+            return None;
+        }
 
-        let data = if !input.is_empty() {
-            Some(Cow::Borrowed(input))
+        let (cid, vec) = if self.format.raw_leaves {
+            (self.format.cid(RAW_CODEC, input), input.to_vec())
         } else {
-            None
-        };
+            // for empty unixfs file the bytes is missing but filesize is present.
 
-        let filesize = Some(input.len() as u64);
+            let data = if !input.is_empty() {
+                Some(Cow::Borrowed(input))
+            } else {
+                None
+            };
 
-        let inner = FlatUnixFs {
-            links: Vec::new(),
-            data: UnixFs {
-                Type: UnixFsType::File,
-                Data: data,
-                filesize,
-                // no blocksizes as there are no links
-                ..Default::default()
-            },
-        };
+            let filesize = Some(input.len() as u64);
+
+            let inner = FlatUnixFs {
+                links: Vec::new(),
+                data: UnixFs {
+                    Type: self.collector.leaf_type(),
+                    Data: data,
+                    filesize,
+                    // no blocksizes as there are no links
+                    ..Default::default()
+                },
+            };
 
-        let (cid, vec) = render_and_hash(&inner);
+            render_and_hash(&inner, &self.format)
+        };
 
         let total_size = vec.len();
 
@@ -266,14 +380,14 @@ impl FileAdder {
             file_size: input.len() as u64,
         };
 
-        unflushed_links.push(link);
+        self.unflushed_links.push(link);
 
         Some((cid, vec))
     }
 
     fn flush_buffered_links(&mut self, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
         self.collector
-            .flush_links(&mut self.unflushed_links, finishing)
+            .flush_links(&mut self.unflushed_links, finishing, &self.format)
     }
 
     /// Test helper for collecting all of the produced blocks; probably not a good idea outside
@@ -305,7 +419,7 @@ impl FileAdder {
     }
 }
 
-fn render_and_hash(flat: &FlatUnixFs<'_>) -> (Cid, Vec<u8>) {
+fn render_and_hash(flat: &FlatUnixFs<'_>, format: &BlockFormat) -> (Cid, Vec<u8>) {
     // TODO: as shown in later dagger we don't really need to render the FlatUnixFs fully; we could
     // either just render a fixed header and continue with the body OR links, though the links are
     // a bit more complicated.
@@ -313,8 +427,7 @@ fn render_and_hash(flat: &FlatUnixFs<'_>) -> (Cid, Vec<u8>) {
     let mut writer = Writer::new(&mut out);
     flat.write_message(&mut writer)
         .expect("unsure how this could fail");
-    let mh = Multihash::wrap(multihash::Code::Sha2_256.into(), &Sha256::digest(&out)).unwrap();
-    let cid = Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0");
+    let cid = format.cid(DAG_PB, &out);
     (cid, out)
 }
 
@@ -323,6 +436,17 @@ fn render_and_hash(flat: &FlatUnixFs<'_>) -> (Cid, Vec<u8>) {
 pub enum Chunker {
     /// Size based chunking
     Size(usize),
+    /// Content defined chunking with a rabin fingerprint over a 64 byte window, compatible with
+    /// the go-ipfs `rabin-{min}-{avg}-{max}` chunker. `min` should be at least the window size,
+    /// and `min <= avg <= max`.
+    Rabin {
+        /// Minimum chunk size
+        min: usize,
+        /// Targeted average chunk size, rounded down to a power of two
+        avg: usize,
+        /// Maximum chunk size
+        max: usize,
+    },
 }
 
 impl Default for Chunker {
@@ -343,6 +467,33 @@ impl Chunker {
                 let ready = buffered.len() + l >= *max;
                 (accepted, ready)
             }
+            Rabin { min, avg, max } => {
+                let mask = (1u64 << avg.ilog2()) - 1;
+
+                // the first bytes of a chunk are never hashed as the cut can only happen after
+                // `min` bytes
+                let hashed_from = min.saturating_sub(RABIN_WINDOW);
+
+                // the fingerprint depends only on the bytes in the window, so it can be restored
+                // from the tail of the buffered bytes.
+                let mut window = RabinWindow::default();
+                let primed_from = buffered.len().saturating_sub(RABIN_WINDOW).max(hashed_from);
+                for &b in buffered.get(primed_from..).unwrap_or_default() {
+                    window.slide(b);
+                }
+
+                for (i, &b) in input.iter().enumerate() {
+                    let count = buffered.len() + i + 1;
+                    if count > hashed_from {
+                        window.slide(b);
+                    }
+                    if count >= *max || count >= *min && (window.digest & mask) == 0 {
+                        return (&input[..=i], true);
+                    }
+                }
+
+                (input, false)
+            }
         }
     }
 
@@ -351,18 +502,102 @@ impl Chunker {
 
         match self {
             Size(max) => *max,
+            Rabin { max, .. } => *max,
         }
     }
 }
 
+/// The irreducible polynomial used by go-ipfs for the rabin fingerprints.
+const RABIN_POLYNOMIAL: u64 = 17437180132763653;
+const RABIN_WINDOW: usize = 64;
+
+/// Lookup tables for sliding a byte out of the window and reducing modulo the polynomial.
+struct RabinTables {
+    out: [u64; 256],
+    modulo: [u64; 256],
+}
+
+static RABIN_TABLES: RabinTables = RabinTables::new();
+
+impl RabinTables {
+    const fn new() -> Self {
+        const fn deg(x: u64) -> i32 {
+            63 - x.leading_zeros() as i32
+        }
+
+        const fn modulo(mut x: u64, d: u64) -> u64 {
+            while deg(x) >= deg(d) {
+                x ^= d << (deg(x) - deg(d));
+            }
+            x
+        }
+
+        let k = deg(RABIN_POLYNOMIAL);
+        let mut out = [0u64; 256];
+        let mut modulo_table = [0u64; 256];
+
+        let mut b = 0;
+        while b < 256 {
+            // out[b] = hash(b || 0 || ... || 0), so xoring it removes b from the window
+            let mut h = modulo(b as u64, RABIN_POLYNOMIAL);
+            let mut i = 0;
+            while i < RABIN_WINDOW - 1 {
+                h = modulo(h << 8, RABIN_POLYNOMIAL);
+                i += 1;
+            }
+            out[b] = h;
+
+            // the top 8 bits select both the reduction and the bits which cancel themselves out
+            modulo_table[b] = modulo((b as u64) << k, RABIN_POLYNOMIAL) | ((b as u64) << k);
+            b += 1;
+        }
+
+        RabinTables {
+            out,
+            modulo: modulo_table,
+        }
+    }
+}
+
+/// Rolling rabin fingerprint over the last [`RABIN_WINDOW`] bytes.
+struct RabinWindow {
+    window: [u8; RABIN_WINDOW],
+    position: usize,
+    digest: u64,
+}
+
+impl Default for RabinWindow {
+    fn default() -> Self {
+        RabinWindow {
+            window: [0; RABIN_WINDOW],
+            position: 0,
+            digest: 0,
+        }
+    }
+}
+
+impl RabinWindow {
+    fn slide(&mut self, b: u8) {
+        const SHIFT: u32 = 63 - RABIN_POLYNOMIAL.leading_zeros() - 8;
+
+        let out = core::mem::replace(&mut self.window[self.position], b);
+        self.digest ^= RABIN_TABLES.out[out as usize];
+        self.position = (self.position + 1) % RABIN_WINDOW;
+
+        let index = (self.digest >> SHIFT) as usize;
+        self.digest = ((self.digest << 8) | b as u64) ^ RABIN_TABLES.modulo[index];
+    }
+}
+
 /// Collector or layout strategy. For more information, see the [Layout section of the spec].
-/// Currently only the default balanced collector/layout has been implemented.
 ///
 /// [Layout section of the spec]: https://github.com/ipfs/specs/blob/master/UNIXFS.md#layout
 #[derive(Debug, Clone)]
 pub enum Collector {
     /// Balanced trees.
     Balanced(BalancedCollector),
+    /// Trickle trees.
+    Trickle(TrickleCollector),
 }
 
 impl Default for Collector {
@@ -372,13 +607,33 @@ impl Default for Collector {
 }
 
 impl Collector {
-    fn flush_links(&mut self, pending: &mut Vec<Link>, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        finishing: bool,
+        format: &BlockFormat,
+    ) -> Vec<(Cid, Vec<u8>)> {
         use Collector::*;
 
         match self {
-            Balanced(bc) => bc.flush_links(pending, finishing),
+            Balanced(bc) => bc.flush_links(pending, finishing, format),
+            Trickle(tc) => tc.flush_links(pending, finishing, format),
         }
     }
+
+    /// The UnixFs type of the non-raw leaves, following go-ipfs.
+    fn leaf_type(&self) -> UnixFsType {
+        match self {
+            Collector::Balanced(_) => UnixFsType::File,
+            Collector::Trickle(_) => UnixFsType::Raw,
+        }
+    }
+
+    /// Returns true if an empty file is represented by an empty leaf, instead of a root block
+    /// without any links.
+    fn wants_empty_leaf(&self) -> bool {
+        matches!(self, Collector::Balanced(_))
+    }
 }
 
 /// BalancedCollector creates balanced UnixFs trees, most optimized for random access to different
@@ -436,7 +691,12 @@ impl BalancedCollector {
     /// In-place compression of the `pending` links to a balanced hierarchy. When `finishing`, the
     /// links will be compressed iteratively from the lowest level to produce a single root link
     /// block.
-    fn flush_links(&mut self, pending: &mut Vec<Link>, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        finishing: bool,
+        format: &BlockFormat,
+    ) -> Vec<(Cid, Vec<u8>)> {
         /*
 
         file    |- - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -|
@@ -576,7 +836,7 @@ impl BalancedCollector {
                     },
                 };
 
-                let (cid, vec) = render_and_hash(&inner);
+                let (cid, vec) = render_and_hash(&inner, format);
 
                 // start overwriting at the first index of this level, then continue forward on
                 // next iterations.
@@ -638,13 +898,133 @@ impl BalancedCollector {
     }
 }
 
+/// TrickleCollector creates trickle UnixFs trees, most optimized for streaming the file from the
+/// start. The root links to up to `max_links` leaves followed by `layer_repeat` subtrees of each
+/// increasing depth, where each subtree has the same shape recursively. As with go-ipfs, the tree
+/// is only created once all of the leaves are known.
+#[derive(Debug, Clone)]
+pub struct TrickleCollector {
+    max_links: usize,
+    layer_repeat: usize,
+}
+
+impl Default for TrickleCollector {
+    /// Returns a default collector which matches go-ipfs.
+    fn default() -> Self {
+        Self::with_limits(174, 4)
+    }
+}
+
+impl From<TrickleCollector> for Collector {
+    fn from(t: TrickleCollector) -> Self {
+        Collector::Trickle(t)
+    }
+}
+
+impl TrickleCollector {
+    /// Configure Trickle collector with the given maximum amount of links per block and the
+    /// amount of subtrees of each depth.
+    pub fn with_limits(max_links: usize, layer_repeat: usize) -> Self {
+        assert!(max_links > 0);
+        assert!(layer_repeat > 0);
+
+        Self {
+            max_links,
+            layer_repeat,
+        }
+    }
+
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        finishing: bool,
+        format: &BlockFormat,
+    ) -> Vec<(Cid, Vec<u8>)> {
+        if !finishing {
+            return Vec::new();
+        }
+
+        let mut ret = Vec::new();
+        let mut leaves = core::mem::take(pending).into_iter().peekable();
+
+        let root = self.fill(&mut leaves, None, &mut ret, format);
+        debug_assert!(leaves.next().is_none());
+
+        pending.push(root);
+        ret
+    }
+
+    /// Creates a link block for the next leaves, and the subtrees of depths up to `max_depth`
+    /// (exclusive), or unlimited for the root.
+    fn fill(
+        &self,
+        leaves: &mut core::iter::Peekable<alloc::vec::IntoIter<Link>>,
+        max_depth: Option<usize>,
+        ret: &mut Vec<(Cid, Vec<u8>)>,
+        format: &BlockFormat,
+    ) -> Link {
+        let mut children = leaves.by_ref().take(self.max_links).collect::<Vec<_>>();
+
+        let mut depth = 1;
+        while max_depth.map(|max| depth < max).unwrap_or(true) && leaves.peek().is_some() {
+            for _ in 0..self.layer_repeat {
+                if leaves.peek().is_none() {
+                    break;
+                }
+                children.push(self.fill(leaves, Some(depth), ret, format));
+            }
+            depth += 1;
+        }
+
+        let mut links = Vec::with_capacity(children.len());
+        let mut blocksizes = Vec::with_capacity(children.len());
+        let mut nested_size = 0;
+        let mut nested_total_size = 0;
+
+        for link in &children {
+            BalancedCollector::partition_link(
+                link,
+                &mut links,
+                &mut blocksizes,
+                &mut nested_size,
+                &mut nested_total_size,
+            );
+        }
+
+        let inner = FlatUnixFs {
+            links,
+            data: UnixFs {
+                Type: UnixFsType::File,
+                filesize: Some(nested_size),
+                blocksizes,
+                ..Default::default()
+            },
+        };
+
+        let (cid, vec) = render_and_hash(&inner, format);
+
+        let link = Link {
+            depth,
+            target: cid,
+            total_size: nested_total_size + vec.len() as u64,
+            file_size: nested_size,
+        };
+
+        ret.push((cid, vec));
+
+        link
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
-    use super::{BalancedCollector, Chunker, FileAdder};
+    use super::{BalancedCollector, Chunker, FileAdder, TrickleCollector};
+    use crate::file::{links, visit::IdleFileVisit, RAW_CODEC};
     use crate::test_support::FakeBlockstore;
     use core::convert::TryFrom;
     use hex_literal::hex;
+    use libipld::cid::Version;
     use libipld::Cid;
 
     #[test]
@@ -866,4 +1246,280 @@ mod tests {
 
         assert_eq!(blocks_count, 175);
     }
+
+    #[test]
+    fn raw_leaves_cidv1_fixtures() {
+        // go-ipfs: ipfs add --cid-version=1 --raw-leaves --chunker=size-262144
+        let fixtures: [(&[u8], &str); 2] = [
+            (
+                b"hello world",
+                "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e",
+            ),
+            (
+                b"",
+                "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku",
+            ),
+        ];
+
+        for (content, expected) in fixtures {
+            let blocks = FileAdder::builder()
+                .with_chunker(Chunker::Size(262144))
+                .with_raw_leaves(true)
+                .with_cid_version(Version::V1)
+                .build()
+                .collect_blocks(content, 0);
+
+            // single chunk files are the raw leaf itself
+            assert_eq!(blocks.len(), 1);
+            assert_eq!(blocks[0].0.to_string(), expected);
+            assert_eq!(blocks[0].1, content);
+        }
+    }
+
+    #[test]
+    fn raw_leaves_multiple_chunks() {
+        let content = (0..262144 * 2 + 1000)
+            .map(|i: usize| ((i * 7 + i / 256) % 256) as u8)
+            .collect::<Vec<_>>();
+
+        let blocks = FileAdder::builder()
+            .with_chunker(Chunker::Size(262144))
+            .with_raw_leaves(true)
+            .with_cid_version(Version::V1)
+            .build()
+            .collect_blocks(&content, 0);
+
+        // three raw leaves and a cidv1 dag-pb root linking to them
+        assert_eq!(blocks.len(), 4);
+        assert!(blocks[..3].iter().all(|(cid, _)| cid.codec() == RAW_CODEC));
+        assert_eq!(
+            blocks[3].0.to_string(),
+            "bafybeiemfjkb4odsynb5sp63ccrkhfg7uxoldyfqzop5froio577k2ap7m"
+        );
+        assert_eq!(read_back(&blocks), content);
+    }
+
+    #[test]
+    fn default_options_are_unchanged() {
+        let content = b"foobar\n";
+        let explicit = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_raw_leaves(false)
+            .with_cid_version(Version::V0)
+            .with_hash(libipld::multihash::Code::Sha2_256)
+            .build()
+            .collect_blocks(content, 0);
+
+        assert_eq!(
+            explicit.last().unwrap().0.to_string(),
+            "QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6"
+        );
+    }
+
+    #[test]
+    fn raw_leaves_cidv1_multi_chunk_fixtures() {
+        // ipfs add --cid-version=1 --raw-leaves --chunker=<chunker>; the roots were computed with
+        // an independent implementation of the go-ipfs-chunker chunkers and of the go-unixfs
+        // balanced layout, which gives the root of raw_leaves_multiple_chunks as well.
+        let fixtures = [
+            // size-1024: 201 leaves, two levels of links as at most 174 fit in a block
+            (
+                Chunker::Size(1024),
+                1024 * 200 + 100,
+                "bafybeicwuyhzzit2bwrlswflwglsmo2tbeitk5litft5nx5tgvdilc7gt4",
+            ),
+            // rabin-256-1024-4096: 57 leaves
+            (
+                Chunker::Rabin {
+                    min: 256,
+                    avg: 1024,
+                    max: 4096,
+                },
+                64 * 1024,
+                "bafybeif25bmsx6gpwgl552m2kdaq6splkz5rt4naqxrqlcariwpkbaisdi",
+            ),
+        ];
+
+        for (chunker, len, expected) in fixtures {
+            let content = pseudorandom_content(len);
+            let blocks = FileAdder::builder()
+                .with_chunker(chunker)
+                .with_raw_leaves(true)
+                .with_cid_version(Version::V1)
+                .build()
+                .collect_blocks(&content, 0);
+
+            assert_eq!(
+                blocks.last().unwrap().0.to_string(),
+                expected,
+                "{chunker:?}"
+            );
+            assert_eq!(read_back(&blocks), content);
+        }
+    }
+
+    #[test]
+    fn rabin_chunks_are_independent_of_pushed_slices() {
+        let content = pseudorandom_content(64 * 1024);
+
+        let chunker = Chunker::Rabin {
+            min: 256,
+            avg: 1024,
+            max: 4096,
+        };
+
+        let adder = || {
+            FileAdder::builder()
+                .with_chunker(chunker)
+                .with_raw_leaves(true)
+                .build()
+        };
+
+        let expected = adder().collect_blocks(&content, 0);
+
+        let leaves = expected
+            .iter()
+            .filter(|(cid, _)| cid.codec() == RAW_CODEC)
+            .map(|(_, block)| block.len())
+            .collect::<Vec<_>>();
+
+        assert_eq!(leaves.iter().sum::<usize>(), content.len());
+        assert!(leaves.len() > 1);
+        for len in &leaves[..leaves.len() - 1] {
+            assert!((256..=4096).contains(len), "{len}");
+        }
+
+        for amt in [1, 63, 64, 65, 1000] {
+            assert_eq!(
+                adder().collect_blocks(&content, amt),
+                expected,
+                "amt: {amt}"
+            );
+        }
+
+        assert_eq!(read_back(&expected), content);
+    }
+
+    #[test]
+    fn trickle_round_trip() {
+        let content = (0..1000).map(|i: usize| i as u8).collect::<Vec<_>>();
+
+        for raw_leaves in [false, true] {
+            let blocks = FileAdder::builder()
+                .with_chunker(Chunker::Size(3))
+                .with_collector(TrickleCollector::with_limits(4, 2))
+                .with_raw_leaves(raw_leaves)
+                .build()
+                .collect_blocks(&content, 0);
+
+            assert_eq!(read_back(&blocks), content, "raw_leaves: {raw_leaves}");
+        }
+    }
+
+    #[test]
+    fn trickle_empty_and_single_chunk() {
+        let blocks = FileAdder::builder()
+            .with_collector(TrickleCollector::default())
+            .build()
+            .collect_blocks(b"", 0);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].1.as_slice(), &hex!("0a 04 08 02 18 00"));
+
+        // unlike the balanced layout, the single leaf is wrapped in a root block
+        let blocks = FileAdder::builder()
+            .with_collector(TrickleCollector::default())
+            .with_raw_leaves(true)
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].0.codec(), RAW_CODEC);
+        assert_eq!(read_back(&blocks), b"foobar\n");
+    }
+
+    #[test]
+    fn linking_unchanged_subtrees() {
+        let content = (0..20u8).collect::<Vec<_>>();
+
+        let adder = || {
+            FileAdder::builder()
+                .with_chunker(Chunker::Size(4))
+                .with_collector(BalancedCollector::with_branching_factor(2))
+                .build()
+        };
+
+        let blocks = adder().collect_blocks(&content, 0);
+        let lookup = |cid: &Cid| {
+            blocks
+                .iter()
+                .find(|(c, _)| c == cid)
+                .map(|(_, block)| block.as_slice())
+                .expect("block must have been produced")
+        };
+
+        // the leftmost subtree linking the first two leaves
+        let (_, root_links) = links(lookup(&blocks.last().unwrap().0)).unwrap();
+        let (_, nested) = links(lookup(&root_links[0].cid)).unwrap();
+        let subtree = nested[0];
+        assert_eq!(subtree.file_size, 8);
+
+        let mut modified = content.clone();
+        modified[9] = 0xff;
+
+        let mut patched = adder();
+        let mut created = patched.push_link(subtree, 1).collect::<Vec<_>>();
+        let mut written = 8;
+        while written < modified.len() {
+            let (blocks, pushed) = patched.push(&modified[written..]);
+            created.extend(blocks);
+            written += pushed;
+        }
+        created.extend(patched.finish());
+
+        let expected = adder().collect_blocks(&modified, 0);
+        assert_eq!(created.last(), expected.last());
+        assert!(!created.iter().any(|(cid, _)| cid == &subtree.cid));
+        assert_eq!(created.len(), expected.len() - 3);
+    }
+
+    /// Xorshift generated content, for the rabin chunker to find cut points in.
+    fn pseudorandom_content(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    /// Reads the file from the blocks, the last of which must be the root.
+    fn read_back(blocks: &[(Cid, Vec<u8>)]) -> Vec<u8> {
+        let lookup = |cid: &Cid| {
+            blocks
+                .iter()
+                .find(|(c, _)| c == cid)
+                .map(|(_, block)| block.as_slice())
+                .expect("block must have been produced")
+        };
+
+        let (root, block) = blocks.last().unwrap();
+        let (bytes, _, _, mut visit) = if root.codec() == RAW_CODEC {
+            IdleFileVisit::default().start_raw(block)
+        } else {
+            IdleFileVisit::default().start(block).unwrap()
+        };
+
+        let mut out = bytes.to_vec();
+
+        while let Some(current) = visit {
+            let next = *current.pending_links().0;
+            let (bytes, next_visit) = current.continue_walk(lookup(&next), &mut None).unwrap();
+            out.extend_from_slice(bytes);
+            visit = next_visit;
+        }
+
+        out
+    }
 }
@@ -212,6 +212,23 @@ impl Traversal {
         FileReader::from_continued(self, tree_range.start, next_block)
     }
 
+    /// Continues the walk on the merkle tree with a raw leaf block, which contains only file
+    /// content. As with [`Traversal::continue_walk`], the range is expected to be the next from
+    /// previous call to FileContent::Links iterator.
+    pub fn continue_raw_walk(
+        self,
+        next_block: &[u8],
+        tree_range: &Range<u64>,
+    ) -> Result<Traversal, FileReadFailed> {
+        self.last_ending
+            .check_is_suitable_next(self.last_offset, tree_range)?;
+        Ok(Traversal {
+            last_ending: Ending::Chunk(tree_range.start + next_block.len() as u64),
+            last_offset: tree_range.start,
+            ..self
+        })
+    }
+
     /// Returns the total size of the file.
     pub fn file_size(&self) -> u64 {
         self.file_size
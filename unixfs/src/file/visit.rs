@@ -3,7 +3,7 @@ use core::ops::Range;
 use libipld::Cid;
 
 use crate::file::reader::{FileContent, FileReader, Traversal};
-use crate::file::{FileReadFailed, Metadata};
+use crate::file::{FileReadFailed, Metadata, RAW_CODEC};
 use crate::pb::{merkledag::PBLink, FlatUnixFs};
 use crate::InvalidCidInLink;
 
@@ -34,6 +34,16 @@ impl IdleFileVisit {
         self.start_from_reader(fr, &mut None)
     }
 
+    /// Begins the visitation of a raw block, which is a single block file of just the content
+    /// without any metadata.
+    ///
+    /// Returns the same tuple as [`IdleFileVisit::start`], without the `FileVisit`.
+    pub fn start_raw(self, block: &'_ [u8]) -> FileVisitResult<'_> {
+        let range = 0..block.len() as u64;
+        let content = maybe_target_slice(block, &range, self.range.as_ref());
+        (content, range.end, Metadata::default(), None)
+    }
+
     pub(crate) fn start_from_parsed<'a>(
         self,
         block: FlatUnixFs<'a>,
@@ -155,25 +165,24 @@ impl FileVisit {
         cache: &mut Option<Cache>,
     ) -> Result<(&'a [u8], Option<Self>), FileReadFailed> {
         let traversal = self.state;
-        let (_, range) = self
+        let (cid, range) = self
             .pending
             .pop()
             .expect("User called continue_walk there must have been a next link");
 
+        if cid.codec() == RAW_CODEC {
+            let traversal = traversal.continue_raw_walk(next, &range)?;
+            self.state = traversal;
+            return Ok(self.continue_with_bytes(next, &range, cache));
+        }
+
         // interesting, validation doesn't trigger if the range is the same?
         let fr = traversal.continue_walk(next, &range)?;
         let (content, traversal) = fr.content();
         match content {
             FileContent::Bytes(content) => {
-                let content = maybe_target_slice(content, &range, self.range.as_ref());
-
-                if !self.pending.is_empty() {
-                    self.state = traversal;
-                    Ok((content, Some(self)))
-                } else {
-                    *cache = Some(self.pending.into());
-                    Ok((content, None))
-                }
+                self.state = traversal;
+                Ok(self.continue_with_bytes(content, &range, cache))
             }
             FileContent::Links(iter) => {
                 let before = self.pending.len();
@@ -199,6 +208,22 @@ impl FileVisit {
     pub fn file_size(&self) -> u64 {
         self.state.file_size()
     }
+
+    fn continue_with_bytes<'a>(
+        self,
+        content: &'a [u8],
+        range: &Range<u64>,
+        cache: &mut Option<Cache>,
+    ) -> (&'a [u8], Option<Self>) {
+        let content = maybe_target_slice(content, range, self.range.as_ref());
+
+        if !self.pending.is_empty() {
+            (content, Some(self))
+        } else {
+            *cache = Some(self.pending.into());
+            (content, None)
+        }
+    }
 }
 
 impl AsRef<Metadata> for FileVisit {
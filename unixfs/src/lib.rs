@@ -21,7 +21,7 @@ pub mod symlink;
 
 /// Directory and directory tree support
 pub mod dir;
-pub use dir::{resolve, LookupError, MaybeResolved, ResolveError};
+pub use dir::{list, resolve, ListedLink, LookupError, MaybeResolved, ResolveError};
 
 mod pb;
 use pb::{UnixFs, UnixFsType};
@@ -1,6 +1,6 @@
 use crate::dir::{ShardError, UnexpectedDirectoryProperties};
 use crate::file::visit::{Cache, FileVisit, IdleFileVisit};
-use crate::file::{FileError, FileReadFailed};
+use crate::file::{FileError, FileReadFailed, RAW_CODEC};
 use crate::pb::{FlatUnixFs, PBLink, ParsingFailed, UnixFsType};
 use crate::{InvalidCidInLink, Metadata, UnexpectedNodeType};
 use alloc::borrow::Cow;
@@ -150,6 +150,18 @@ impl Walker {
             return Ok(ContinuedWalk::File(segment, cid, path, metadata, *sz));
         }
 
+        if matches!(next, Some((cid, ..)) if cid.codec() == RAW_CODEC) {
+            // raw blocks are single block files without any metadata
+            let visited = IdleFileVisit::default().start_raw(bytes);
+            return Ok(Self::continue_file(
+                current,
+                next,
+                pending,
+                should_continue,
+                visited,
+            ));
+        }
+
         let flat = FlatUnixFs::try_from(bytes)?;
         let metadata = Metadata::from(&flat.data);
 
@@ -236,37 +248,13 @@ impl Walker {
                 })
             }
             UnixFsType::Raw | UnixFsType::File => {
-                let (bytes, file_size, metadata, step) =
-                    IdleFileVisit::default().start_from_parsed(flat, cache)?;
-                let (cid, name, depth) = next.take().expect("validated at new and earlier");
-                let file_continues = step.is_some();
-
-                match current {
-                    None => {
-                        let ie =
-                            InnerEntry::new_root_file(cid, metadata, &name, step, file_size, depth);
-                        *current = Some(ie);
-                    }
-                    Some(ie) => {
-                        ie.as_file(cid, &name, depth, metadata, step, file_size);
-                    }
-                };
-
-                let next_local = pending.pop();
-                if file_continues || next_local.is_some() {
-                    *next = next_local;
-                    *should_continue = true;
-                }
-
-                let segment = FileSegment::first(bytes, !file_continues);
-
-                let ie = current.as_ref().unwrap();
-                Ok(ContinuedWalk::File(
-                    segment,
-                    &ie.cid,
-                    &ie.path,
-                    &ie.metadata,
-                    file_size,
+                let visited = IdleFileVisit::default().start_from_parsed(flat, cache)?;
+                Ok(Self::continue_file(
+                    current,
+                    next,
+                    pending,
+                    should_continue,
+                    visited,
                 ))
             }
             UnixFsType::Metadata => Err(Error::UnsupportedType(flat.data.Type.into())),
@@ -304,6 +292,39 @@ impl Walker {
         }
     }
 
+    /// Continues the walk after visiting the first block of a file.
+    fn continue_file<'c>(
+        current: &'c mut Option<InnerEntry>,
+        next: &mut Option<(Cid, String, usize)>,
+        pending: &mut Vec<(Cid, String, usize)>,
+        should_continue: &mut bool,
+        (bytes, file_size, metadata, step): (&'c [u8], u64, Metadata, Option<FileVisit>),
+    ) -> ContinuedWalk<'c> {
+        let (cid, name, depth) = next.take().expect("validated at new and earlier");
+        let file_continues = step.is_some();
+
+        match current {
+            None => {
+                let ie = InnerEntry::new_root_file(cid, metadata, &name, step, file_size, depth);
+                *current = Some(ie);
+            }
+            Some(ie) => {
+                ie.as_file(cid, &name, depth, metadata, step, file_size);
+            }
+        };
+
+        let next_local = pending.pop();
+        if file_continues || next_local.is_some() {
+            *next = next_local;
+            *should_continue = true;
+        }
+
+        let segment = FileSegment::first(bytes, !file_continues);
+
+        let ie = current.as_ref().unwrap();
+        ContinuedWalk::File(segment, &ie.cid, &ie.path, &ie.metadata, file_size)
+    }
+
     /// Returns `true` if there are more links to walk over.
     pub fn should_continue(&self) -> bool {
         self.should_continue